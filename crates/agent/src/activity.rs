@@ -0,0 +1,246 @@
+//! Idle detection for the capture loop.
+//!
+//! A session is idle only when neither the user nor the screen has done
+//! anything for a while. User activity is the most recent of interactive
+//! browser input and the X server's own input idle counter (MIT-SCREEN-SAVER,
+//! which also sees input from other X clients). Screen activity comes from
+//! XDamage, or from a sparse frame fingerprint when XDamage is missing, so a
+//! playing video or a scrolling build log keeps the full framerate.
+//!
+//! Longer inactivity walks the session's idle policy (`IdleLadder`): lock,
+//! then disconnect the browser, then destroy the session.
+
+use crate::capture::DirtyRect;
+use beam_protocol::{IdleAction, IdlePolicy};
+use std::time::{Duration, Instant};
+
+/// Damage smaller than this share of the screen (blinking carets, panel
+/// clocks) doesn't count as activity; otherwise no desktop would ever idle.
+const MIN_ACTIVE_COVERAGE: f64 = 0.005;
+
+/// Fingerprint every Nth pixel. Dense enough to see video or scrolling,
+/// sparse enough to stay far below a frame's encode cost.
+const FINGERPRINT_PIXEL_STRIDE: usize = 61;
+
+pub struct ActivityMonitor {
+    timeout: Duration,
+    last_screen_change: Instant,
+    last_fingerprint: Option<u64>,
+}
+
+impl ActivityMonitor {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_screen_change: now,
+            last_fingerprint: None,
+        }
+    }
+
+    /// XDamage reported `dirty` for a `width` x `height` screen.
+    pub fn record_damage(&mut self, dirty: &DirtyRect, width: u32, height: u32, now: Instant) {
+        if dirty.coverage(width, height) >= MIN_ACTIVE_COVERAGE {
+            self.last_screen_change = now;
+        }
+    }
+
+    /// Without XDamage: compare a sparse fingerprint of the captured BGRx frame.
+    pub fn observe_frame(&mut self, frame: &[u8], now: Instant) {
+        let fingerprint = fingerprint(frame);
+        if self
+            .last_fingerprint
+            .is_some_and(|prev| prev != fingerprint)
+        {
+            self.last_screen_change = now;
+        }
+        self.last_fingerprint = Some(fingerprint);
+    }
+
+    /// `since_input` is the time since the most recent user input from any
+    /// source, or None if there has been none.
+    pub fn is_idle(&self, since_input: Option<Duration>, now: Instant) -> bool {
+        self.inactive_for(since_input, now) > self.timeout
+    }
+
+    /// Time since the last user input or screen change, whichever is later.
+    pub fn inactive_for(&self, since_input: Option<Duration>, now: Instant) -> Duration {
+        let since_screen = now.duration_since(self.last_screen_change);
+        since_input.map_or(since_screen, |since| since.min(since_screen))
+    }
+}
+
+/// Inactivity going backwards by more than this is new activity. Smaller
+/// steps are jitter from extrapolating the X idle counter between samples.
+const IDLE_RESET_SLACK: Duration = Duration::from_secs(1);
+
+/// The lock/disconnect/destroy stages of an idle policy. Each fires once per
+/// idle period; any activity starts a new period.
+pub struct IdleLadder {
+    /// Enabled stages, earliest first
+    stages: Vec<(Duration, IdleAction)>,
+    /// How many stages have fired this period
+    fired: usize,
+    locked: bool,
+    last_idle: Duration,
+}
+
+impl IdleLadder {
+    pub fn new(policy: &IdlePolicy) -> Self {
+        let mut stages: Vec<_> = [
+            (policy.lock_after, IdleAction::Lock),
+            (policy.disconnect_after, IdleAction::Disconnect),
+            (policy.destroy_after, IdleAction::Destroy),
+        ]
+        .into_iter()
+        .filter(|&(secs, _)| secs > 0)
+        .map(|(secs, action)| (Duration::from_secs(secs), action))
+        .collect();
+        stages.sort_by_key(|&(after, _)| after);
+        Self {
+            stages,
+            fired: 0,
+            locked: false,
+            last_idle: Duration::ZERO,
+        }
+    }
+
+    /// `inactive` is `ActivityMonitor::inactive_for`; `since_input` as for
+    /// `ActivityMonitor::is_idle`. Returns the stage that just became due.
+    pub fn update(
+        &mut self,
+        inactive: Duration,
+        since_input: Option<Duration>,
+    ) -> Option<IdleAction> {
+        // A screen locker draws (clock, animation) while locked, so only
+        // input ends an idle period after the lock stage
+        let idle = if self.locked {
+            since_input.unwrap_or(inactive)
+        } else {
+            inactive
+        };
+        if idle + IDLE_RESET_SLACK < self.last_idle {
+            self.fired = 0;
+            self.locked = false;
+        }
+        self.last_idle = idle;
+
+        let &(after, action) = self.stages.get(self.fired)?;
+        if idle < after {
+            return None;
+        }
+        self.fired += 1;
+        if action == IdleAction::Lock {
+            self.locked = true;
+        }
+        Some(action)
+    }
+}
+
+/// FNV-1a over every `FINGERPRINT_PIXEL_STRIDE`th pixel's colour bytes
+/// (the 4th byte is X padding).
+fn fingerprint(frame: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for pixel in frame.chunks_exact(4).step_by(FINGERPRINT_PIXEL_STRIDE) {
+        for &byte in &pixel[..3] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn idle_needs_quiet_input_and_screen() {
+        let start = Instant::now();
+        let mut monitor = ActivityMonitor::new(TIMEOUT, start);
+        let later = start + TIMEOUT + Duration::from_secs(1);
+
+        assert!(!monitor.is_idle(None, start));
+        assert!(monitor.is_idle(None, later));
+        assert!(monitor.is_idle(Some(TIMEOUT * 2), later));
+        assert!(!monitor.is_idle(Some(Duration::from_secs(10)), later));
+
+        // Video playing: large damage keeps the session active
+        let video = DirtyRect {
+            x: 0,
+            y: 0,
+            width: 640,
+            height: 360,
+        };
+        monitor.record_damage(&video, 1920, 1080, later);
+        assert!(!monitor.is_idle(None, later + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn tiny_damage_is_not_activity() {
+        let start = Instant::now();
+        let mut monitor = ActivityMonitor::new(TIMEOUT, start);
+        let later = start + TIMEOUT + Duration::from_secs(1);
+        let caret = DirtyRect {
+            x: 100,
+            y: 100,
+            width: 8,
+            height: 16,
+        };
+        monitor.record_damage(&caret, 1920, 1080, later);
+        assert!(monitor.is_idle(None, later));
+    }
+
+    #[test]
+    fn frame_fingerprint_detects_change() {
+        let start = Instant::now();
+        let mut monitor = ActivityMonitor::new(TIMEOUT, start);
+        let later = start + TIMEOUT + Duration::from_secs(1);
+        let mut frame = vec![0u8; 4 * 1000];
+
+        monitor.observe_frame(&frame, later);
+        monitor.observe_frame(&frame, later);
+        assert!(monitor.is_idle(None, later), "identical frames stay idle");
+
+        // Padding byte changes are not content changes
+        frame[3] = 0xFF;
+        monitor.observe_frame(&frame, later);
+        assert!(monitor.is_idle(None, later));
+
+        frame[0] = 0x80;
+        monitor.observe_frame(&frame, later);
+        assert!(!monitor.is_idle(None, later));
+    }
+
+    #[test]
+    fn ladder_fires_each_stage_once_per_idle_period() {
+        let policy = IdlePolicy {
+            lock_after: 600,
+            disconnect_after: 300,
+            destroy_after: 0,
+            ..IdlePolicy::default()
+        };
+        let mut ladder = IdleLadder::new(&policy);
+        let mins = |m: u64| Duration::from_secs(m * 60);
+
+        assert_eq!(ladder.update(mins(4), None), None);
+        assert_eq!(ladder.update(mins(5), None), Some(IdleAction::Disconnect));
+        assert_eq!(ladder.update(mins(6), None), None);
+        assert_eq!(ladder.update(mins(10), None), Some(IdleAction::Lock));
+        assert_eq!(
+            ladder.update(mins(60), Some(mins(60))),
+            None,
+            "no more stages"
+        );
+
+        // The locker redrawing the screen doesn't end the idle period...
+        assert_eq!(ladder.update(Duration::ZERO, Some(mins(61))), None);
+        // ...but input does, and the stages fire again
+        assert_eq!(ladder.update(Duration::ZERO, Some(Duration::ZERO)), None);
+        assert_eq!(
+            ladder.update(mins(5), Some(mins(5))),
+            Some(IdleAction::Disconnect)
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Remote assistance on someone's physical display.
+//!
+//! Nothing is streamed until the person at the display accepts a prompt
+//! naming the operator. For as long as the session lasts a banner across
+//! the top of their screen says who is connected, and lets them pause the
+//! operator's keyboard and mouse or end the session outright.
+
+use beam_protocol::{ASSIST_ENDED_EXIT, AssistNotice, InputEvent};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{
+    ConfigureWindowAux, ConnectionExt, CreateGCAux, CreateWindowAux, EventMask, Rectangle,
+    StackMode, WindowClass,
+};
+use x11rb::rust_connection::RustConnection;
+
+/// xmessage exit status for the Allow button
+const XMESSAGE_ALLOW: i32 = 101;
+
+/// How often the banner is raised back above other windows
+const RAISE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Ask the person at `display` whether `operator` may connect. Declining,
+/// closing the prompt, letting it time out, or having no way to show it
+/// all count as no.
+pub fn ask_consent(display: &str, operator: &str, timeout_secs: u64) -> bool {
+    let text = format!(
+        "{operator} is asking to connect to this screen to help you.\n\
+         They will see everything on it and can use your keyboard and mouse \
+         until you end the session."
+    );
+    let timeout = timeout_secs.to_string();
+    let zenity = Command::new("zenity")
+        .args([
+            "--question",
+            "--title=Remote assistance",
+            "--ok-label=Allow",
+            "--cancel-label=Decline",
+            "--timeout",
+            &timeout,
+            "--text",
+            &text,
+        ])
+        .env("DISPLAY", display)
+        .stdin(Stdio::null())
+        .status();
+    let allowed = match zenity {
+        Ok(status) => status.success(),
+        Err(_) => {
+            let xmessage = Command::new("xmessage")
+                .args([
+                    "-center",
+                    "-buttons",
+                    "Decline:1,Allow:101",
+                    "-default",
+                    "Decline",
+                    "-timeout",
+                    &timeout,
+                    &text,
+                ])
+                .env("DISPLAY", display)
+                .stdin(Stdio::null())
+                .status();
+            match xmessage {
+                Ok(status) => status.code() == Some(XMESSAGE_ALLOW),
+                Err(e) => {
+                    warn!("Neither zenity nor xmessage could ask for consent: {e}");
+                    false
+                }
+            }
+        }
+    };
+    info!(operator, allowed, "Remote assistance consent answered");
+    allowed
+}
+
+/// Whether an assisting operator's event may reach the display. `control`
+/// is off while the person at the display has paused the operator.
+pub fn allowed(event: &InputEvent, control: bool) -> bool {
+    match event {
+        // The display and its settings belong to the person being helped
+        InputEvent::Resize { .. }
+        | InputEvent::Layout { .. }
+        | InputEvent::DisplaySettings { .. }
+        | InputEvent::ScreenAdd { .. }
+        | InputEvent::ScreenRemove
+        | InputEvent::WebcamStart { .. } => false,
+        InputEvent::Key { .. }
+        | InputEvent::MouseMove { .. }
+        | InputEvent::ScreenMouseMove { .. }
+        | InputEvent::RelativeMouseMove { .. }
+        | InputEvent::Button { .. }
+        | InputEvent::Scroll { .. }
+        | InputEvent::InputBatch { .. }
+        | InputEvent::KeyCombo { .. }
+        | InputEvent::PointerLock { .. }
+        | InputEvent::Clipboard { .. }
+        | InputEvent::ClipboardPrimary { .. }
+        | InputEvent::ClipboardHistoryRequest
+        | InputEvent::ClipboardHistoryPaste { .. }
+        | InputEvent::ClipboardHistoryClear
+        | InputEvent::OpenUrl { .. }
+        | InputEvent::FileStart { .. }
+        | InputEvent::FileChunk { .. }
+        | InputEvent::FileDone { .. }
+        | InputEvent::FileDownloadRequest { .. } => control,
+        _ => true,
+    }
+}
+
+/// Tell the operator's browser whether their keyboard and mouse are live.
+pub fn send_notice(ws_tx: &mpsc::Sender<Message>, control: bool) {
+    let Ok(msg) = serde_json::to_string(&AssistNotice { control }) else {
+        return;
+    };
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        warn!(control, "Failed to queue assist notice: {e}");
+    }
+}
+
+/// Show the banner on `display` from a thread of its own. Its buttons flip
+/// `control` (and tell the browser) or exit the agent with
+/// `ASSIST_ENDED_EXIT`.
+pub fn spawn_banner(
+    display: String,
+    operator: String,
+    control: Arc<AtomicBool>,
+    ws_tx: mpsc::Sender<Message>,
+) {
+    send_notice(&ws_tx, control.load(Ordering::Relaxed));
+    std::thread::Builder::new()
+        .name("assist-banner".into())
+        .spawn(move || {
+            if let Err(e) = run_banner(&display, &operator, &control, &ws_tx) {
+                warn!("Assist banner failed: {e:#}");
+            }
+        })
+        .expect("failed to spawn assist banner thread");
+}
+
+struct Layout {
+    char_width: i16,
+    ascent: i16,
+    height: u16,
+    status_width: i16,
+    button_width: i16,
+}
+
+impl Layout {
+    const PAD: i16 = 8;
+
+    fn width(&self) -> u16 {
+        (self.status_width + 2 * self.button_width + 4 * Self::PAD) as u16
+    }
+
+    fn toggle_x(&self) -> i16 {
+        self.status_width + 2 * Self::PAD
+    }
+
+    fn end_x(&self) -> i16 {
+        self.toggle_x() + self.button_width + Self::PAD
+    }
+}
+
+fn status_text(operator: &str, control: bool) -> String {
+    if control {
+        format!("Remote assistance: {operator} can see and control this screen")
+    } else {
+        format!("Remote assistance: {operator} can see this screen")
+    }
+}
+
+fn toggle_text(control: bool) -> &'static str {
+    if control {
+        "Pause control"
+    } else {
+        "Allow control"
+    }
+}
+
+const END_TEXT: &str = "End session";
+
+fn run_banner(
+    x_display: &str,
+    operator: &str,
+    control: &AtomicBool,
+    ws_tx: &mpsc::Sender<Message>,
+) -> anyhow::Result<()> {
+    let (conn, screen_num) = RustConnection::connect(Some(x_display))?;
+    let screen = &conn.setup().roots[screen_num];
+
+    let font = conn.generate_id()?;
+    conn.open_font(font, b"fixed")?;
+    let metrics = conn.query_font(font)?.reply()?;
+    let char_width = metrics.max_bounds.character_width.max(1);
+    let longest = |texts: &[&str]| texts.iter().map(|t| t.len()).max().unwrap_or(0) as i16;
+    let layout = Layout {
+        char_width,
+        ascent: metrics.font_ascent,
+        height: (metrics.font_ascent + metrics.font_descent + 2 * Layout::PAD) as u16,
+        status_width: char_width
+            * longest(&[&status_text(operator, true), &status_text(operator, false)]),
+        button_width: char_width * longest(&[toggle_text(true), toggle_text(false), END_TEXT])
+            + 2 * Layout::PAD,
+    };
+
+    let red = conn
+        .alloc_color(screen.default_colormap, 0xc600, 0x2800, 0x2800)?
+        .reply()?
+        .pixel;
+    let window = conn.generate_id()?;
+    let x = (screen.width_in_pixels.saturating_sub(layout.width()) / 2) as i16;
+    conn.create_window(
+        screen.root_depth,
+        window,
+        screen.root,
+        x,
+        0,
+        layout.width(),
+        layout.height,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        screen.root_visual,
+        &CreateWindowAux::new()
+            .background_pixel(red)
+            .override_redirect(1)
+            .event_mask(EventMask::EXPOSURE | EventMask::BUTTON_PRESS),
+    )?;
+    let gc = conn.generate_id()?;
+    conn.create_gc(
+        gc,
+        window,
+        &CreateGCAux::new()
+            .foreground(screen.white_pixel)
+            .background(red)
+            .font(font),
+    )?;
+    conn.map_window(window)?;
+    conn.flush()?;
+    info!(x_display, "Assist banner shown");
+
+    let mut last_raise = Instant::now();
+    loop {
+        while let Some(event) = conn.poll_for_event()? {
+            match event {
+                Event::Expose(e) if e.count == 0 => {
+                    draw(
+                        &conn,
+                        window,
+                        gc,
+                        &layout,
+                        operator,
+                        control.load(Ordering::Relaxed),
+                    )?;
+                }
+                Event::ButtonPress(e) => {
+                    let x = e.event_x;
+                    if (layout.end_x()..layout.end_x() + layout.button_width).contains(&x) {
+                        info!("Session ended from the assist banner");
+                        std::process::exit(ASSIST_ENDED_EXIT);
+                    }
+                    if (layout.toggle_x()..layout.toggle_x() + layout.button_width).contains(&x) {
+                        let now = !control.load(Ordering::Relaxed);
+                        control.store(now, Ordering::Relaxed);
+                        info!(
+                            control = now,
+                            "Operator control toggled from the assist banner"
+                        );
+                        send_notice(ws_tx, now);
+                        conn.clear_area(false, window, 0, 0, 0, 0)?;
+                        draw(&conn, window, gc, &layout, operator, now)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Keep it above whatever the person (or the operator) opens next
+        if last_raise.elapsed() >= RAISE_INTERVAL {
+            conn.configure_window(
+                window,
+                &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+            )?;
+            last_raise = Instant::now();
+        }
+        conn.flush()?;
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn draw(
+    conn: &RustConnection,
+    window: u32,
+    gc: u32,
+    layout: &Layout,
+    operator: &str,
+    control: bool,
+) -> anyhow::Result<()> {
+    let baseline = Layout::PAD + layout.ascent;
+    conn.image_text8(
+        window,
+        gc,
+        Layout::PAD,
+        baseline,
+        status_text(operator, control).as_bytes(),
+    )?;
+    for (x, label) in [
+        (layout.toggle_x(), toggle_text(control)),
+        (layout.end_x(), END_TEXT),
+    ] {
+        conn.poly_rectangle(
+            window,
+            gc,
+            &[Rectangle {
+                x,
+                y: 2,
+                width: layout.button_width as u16,
+                height: layout.height - 5,
+            }],
+        )?;
+        let inset = (layout.button_width - label.len() as i16 * layout.char_width) / 2;
+        conn.image_text8(window, gc, x + inset, baseline, label.as_bytes())?;
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use beam_protocol::QualityPreset;
+
+    #[test]
+    fn paused_operator_only_watches() {
+        let key = InputEvent::Key { c: 30, d: true };
+        let paste = InputEvent::Clipboard { text: "hi".into() };
+        let quality = InputEvent::Quality {
+            mode: QualityPreset::Sharp,
+        };
+        assert!(allowed(&key, true));
+        assert!(!allowed(&key, false));
+        assert!(!allowed(&paste, false));
+        assert!(allowed(&quality, false));
+        assert!(allowed(&InputEvent::FrameAck { ts: 1, dd: 0.0 }, false));
+    }
+
+    #[test]
+    fn assisted_display_keeps_its_settings() {
+        let resize = InputEvent::Resize {
+            w: 800,
+            h: 600,
+            s: None,
+        };
+        let layout = InputEvent::Layout {
+            layout: "de".into(),
+            variant: None,
+            options: vec![],
+        };
+        assert!(!allowed(&resize, true));
+        assert!(!allowed(&layout, true));
+    }
+}
@@ -1,12 +1,129 @@
+use anyhow::Context;
 use audiopus::coder::Encoder as OpusEncoder;
-use audiopus::{Application, Bitrate, Channels, SampleRate};
+use audiopus::{Application, Bandwidth, Bitrate, Channels, SampleRate, Signal};
+use beam_protocol::{AudioSinkInfo, AudioSources, DEFAULT_OPUS_FRAME_MS};
+use gstreamer::prelude::*;
+use gstreamer::{self as gst, ClockTime, ElementFactory};
+use gstreamer_app::AppSink;
 use libpulse_binding as pulse;
 use libpulse_simple_binding::Simple;
-use tracing::info;
+use std::collections::{HashMap, VecDeque};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Packet loss Opus is told to expect with FEC on. It spends no bits on
+/// FEC when it expects none.
+const FEC_LOSS_PERC: u8 = 10;
+
+/// Capture source used until the browser picks another one.
+const DEFAULT_SOURCE: &str = "@DEFAULT_MONITOR@";
+
+/// Null sink that single-application capture moves the chosen stream onto,
+/// so its monitor carries that application and nothing else.
+const APP_CAPTURE_SINK: &str = "beam_app_capture";
+
+/// Buffers pipewiresrc may queue in the appsink while capture is paused,
+/// oldest dropped first (about a second of frames)
+const PIPEWIRE_MAX_BUFFERS: u32 = 50;
+
+/// Opus encoding profile, switched live by `InputEvent::AudioMode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioMode {
+    /// Configured `audio.bitrate`, automatic signal detection.
+    #[default]
+    Standard,
+    /// Speech: 32 kbps, wideband, DTX so silence costs next to nothing.
+    Voice,
+    /// 192 kbps fullband for music and game audio.
+    Music,
+}
+
+impl AudioMode {
+    /// Parse the `mode` field of `InputEvent::AudioMode`.
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode {
+            "standard" => Some(Self::Standard),
+            "voice" => Some(Self::Voice),
+            "music" => Some(Self::Music),
+            _ => None,
+        }
+    }
+
+    /// Bitrate in bits per second; `Standard` uses the configured one.
+    fn bitrate_bps(self, configured_kbps: u32) -> i32 {
+        match self {
+            Self::Standard => configured_kbps as i32 * 1000,
+            Self::Voice => 32_000,
+            Self::Music => 192_000,
+        }
+    }
+}
+
+/// Opus settings that trade latency for robustness, set from config and
+/// changed by `InputEvent::AudioTuning`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusTuning {
+    /// Frame length, one of `OPUS_FRAME_MS`
+    pub frame_ms: u32,
+    /// Inband forward error correction
+    pub fec: bool,
+    /// Discontinuous transmission, also on whenever the mode is `Voice`
+    pub dtx: bool,
+}
+
+impl Default for OpusTuning {
+    fn default() -> Self {
+        Self {
+            frame_ms: DEFAULT_OPUS_FRAME_MS,
+            fec: false,
+            dtx: false,
+        }
+    }
+}
+
+impl OpusTuning {
+    /// This tuning with the fields an `AudioTuning` event sets replaced.
+    pub fn with(self, frame_ms: Option<u32>, fec: Option<bool>, dtx: Option<bool>) -> Self {
+        Self {
+            frame_ms: frame_ms.unwrap_or(self.frame_ms),
+            fec: fec.unwrap_or(self.fec),
+            dtx: dtx.unwrap_or(self.dtx),
+        }
+    }
+
+    pub fn frame_duration(self) -> Duration {
+        Duration::from_millis(u64::from(self.frame_ms))
+    }
+}
+
+/// Audio browsers played and the part they concealed, summed from their
+/// `AudioReport`s for the watchdog's health reports (ms).
+#[derive(Default)]
+pub struct PlayoutStats {
+    pub played_ms: AtomicU64,
+    pub concealed_ms: AtomicU64,
+}
 
 pub struct AudioCapture {
-    simple: Simple,
+    recorder: Recorder,
+    pulse_server: Option<String>,
+    /// The session's PipeWire socket, recorded from with pipewiresrc
+    pipewire: Option<PathBuf>,
+    /// Source the record stream is open on, reopened when the frame length
+    /// changes
+    source: String,
+    spec: pulse::sample::Spec,
+    buf_attr: pulse::def::BufferAttr,
     opus_encoder: OpusEncoder,
+    /// `audio.bitrate` in kbps, restored by `AudioMode::Standard`
+    bitrate_kbps: u32,
+    mode: AudioMode,
+    tuning: OpusTuning,
     pcm_buffer: Vec<u8>,
     opus_buffer: Vec<u8>,
     /// Pre-allocated buffer for s16le→i16 conversion (avoids 50 allocs/sec)
@@ -17,7 +134,10 @@ impl AudioCapture {
     pub fn new(
         sample_rate: u32,
         channels: u16,
+        bitrate_kbps: u32,
+        tuning: OpusTuning,
         pulse_server: Option<&str>,
+        pipewire: Option<&Path>,
     ) -> anyhow::Result<Self> {
         let spec = pulse::sample::Spec {
             format: pulse::sample::Format::S16le,
@@ -25,12 +145,11 @@ impl AudioCapture {
             rate: sample_rate,
         };
 
-        // 20ms frame: samples_per_channel = sample_rate * 20 / 1000
-        let samples_per_frame = (sample_rate * 20 / 1000) as usize;
+        let samples_per_frame = samples_per_frame(&spec, tuning.frame_ms);
         let frame_bytes_val = samples_per_frame * channels as usize * 2; // s16le
 
         // Set PulseAudio buffer attributes for low-latency capture.
-        // fragsize = one Opus frame (20ms) to minimize audio latency.
+        // fragsize = one Opus frame to minimize audio latency.
         let buf_attr = pulse::def::BufferAttr {
             maxlength: u32::MAX,
             tlength: u32::MAX,
@@ -39,17 +158,18 @@ impl AudioCapture {
             fragsize: frame_bytes_val as u32,
         };
 
-        let simple = Simple::new(
-            pulse_server, // Explicit server path (avoids unsafe set_var)
-            "beam-agent", // Application name
-            pulse::stream::Direction::Record,
-            Some("@DEFAULT_MONITOR@"), // Capture system audio output
-            "audio-capture",           // Stream description
-            &spec,
-            None,            // Default channel map
-            Some(&buf_attr), // Low-latency buffer (20ms fragments)
-        )
-        .map_err(|e| anyhow::anyhow!("PulseAudio connection failed: {e}"))?;
+        // Without the GStreamer plugin, PipeWire is recorded through
+        // pipewire-pulse like PulseAudio
+        let pipewire = pipewire.filter(|_| {
+            let found = ElementFactory::find("pipewiresrc").is_some();
+            if !found {
+                warn!(
+                    "pipewiresrc not available, recording PipeWire through its PulseAudio server"
+                );
+            }
+            found
+        });
+        let recorder = open_recorder(pulse_server, pipewire, DEFAULT_SOURCE, &spec, &buf_attr)?;
 
         let opus_channels = match channels {
             1 => Channels::Mono,
@@ -66,36 +186,150 @@ impl AudioCapture {
             _ => anyhow::bail!("Unsupported sample rate for Opus: {sample_rate}"),
         };
 
-        let mut opus_encoder =
-            OpusEncoder::new(opus_sample_rate, opus_channels, Application::LowDelay)
-                .map_err(|e| anyhow::anyhow!("Failed to create Opus encoder: {e:?}"))?;
-
-        opus_encoder
-            .set_bitrate(Bitrate::BitsPerSecond(256_000))
-            .map_err(|e| anyhow::anyhow!("Failed to set Opus bitrate: {e:?}"))?;
+        let opus_encoder = OpusEncoder::new(opus_sample_rate, opus_channels, Application::LowDelay)
+            .map_err(|e| anyhow::anyhow!("Failed to create Opus encoder: {e:?}"))?;
 
         info!(
             sample_rate,
             channels,
+            bitrate_kbps,
+            ?tuning,
+            pipewire = pipewire.is_some(),
             frame_bytes = frame_bytes_val,
             samples_per_channel = samples_per_frame,
             "Audio capture initialized"
         );
 
-        Ok(Self {
-            simple,
+        let mut capture = Self {
+            recorder,
+            pulse_server: pulse_server.map(str::to_string),
+            pipewire: pipewire.map(Path::to_path_buf),
+            source: DEFAULT_SOURCE.to_string(),
+            spec,
+            buf_attr,
             opus_encoder,
+            bitrate_kbps,
+            mode: AudioMode::Standard,
+            tuning,
             pcm_buffer: vec![0u8; frame_bytes_val],
             opus_buffer: vec![0u8; 4000], // Max Opus frame size
             samples_buffer: vec![0i16; samples_per_frame * channels as usize],
-        })
+        };
+        capture.configure_encoder()?;
+        Ok(capture)
     }
 
-    /// Read 20ms of PCM audio from PulseAudio and encode to Opus.
+    /// Discard audio the sound server buffered while capture was paused,
+    /// so a resume doesn't replay stale sound.
+    pub fn discard_buffered(&mut self) -> anyhow::Result<()> {
+        match &mut self.recorder {
+            Recorder::Pulse(simple) => simple
+                .flush()
+                .map_err(|e| anyhow::anyhow!("PulseAudio flush failed: {e}")),
+            Recorder::PipeWire(recorder) => {
+                recorder.flush();
+                Ok(())
+            }
+        }
+    }
+
+    /// Reconfigure the Opus encoder in place. Takes effect from the next
+    /// frame; the browser's decoder needs no reset.
+    pub fn set_mode(&mut self, mode: AudioMode) -> anyhow::Result<()> {
+        self.mode = mode;
+        self.configure_encoder()
+    }
+
+    /// Change the frame length, FEC and DTX. A new frame length reopens the
+    /// record stream with fragments of that length; the browser's decoder
+    /// takes any length as it comes.
+    pub fn set_tuning(&mut self, tuning: OpusTuning) -> anyhow::Result<()> {
+        if tuning.frame_ms != self.tuning.frame_ms {
+            let samples =
+                samples_per_frame(&self.spec, tuning.frame_ms) * self.spec.channels as usize;
+            let buf_attr = pulse::def::BufferAttr {
+                fragsize: (samples * 2) as u32,
+                ..self.buf_attr
+            };
+            self.recorder = open_recorder(
+                self.pulse_server.as_deref(),
+                self.pipewire.as_deref(),
+                &self.source,
+                &self.spec,
+                &buf_attr,
+            )?;
+            self.buf_attr = buf_attr;
+            self.pcm_buffer.resize(samples * 2, 0);
+            self.samples_buffer.resize(samples, 0);
+        }
+        self.tuning = tuning;
+        self.configure_encoder()
+    }
+
+    /// Audio in each frame `capture_and_encode` returns.
+    pub fn frame_duration(&self) -> Duration {
+        self.tuning.frame_duration()
+    }
+
+    pub fn tuning(&self) -> OpusTuning {
+        self.tuning
+    }
+
+    fn configure_encoder(&mut self) -> anyhow::Result<()> {
+        let mode = self.mode;
+        let (signal, bandwidth, dtx) = match mode {
+            AudioMode::Standard => (Signal::Auto, Bandwidth::Fullband, self.tuning.dtx),
+            AudioMode::Voice => (Signal::Voice, Bandwidth::Wideband, true),
+            AudioMode::Music => (Signal::Music, Bandwidth::Fullband, self.tuning.dtx),
+        };
+        let fec = self.tuning.fec;
+        let bitrate = mode.bitrate_bps(self.bitrate_kbps);
+        let encoder = &mut self.opus_encoder;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(bitrate))
+            .map_err(|e| anyhow::anyhow!("Failed to set Opus bitrate: {e:?}"))?;
+        encoder
+            .set_signal(signal)
+            .map_err(|e| anyhow::anyhow!("Failed to set Opus signal type: {e:?}"))?;
+        encoder
+            .set_max_bandwidth(bandwidth)
+            .map_err(|e| anyhow::anyhow!("Failed to set Opus bandwidth: {e:?}"))?;
+        encoder
+            .set_dtx(dtx)
+            .map_err(|e| anyhow::anyhow!("Failed to set Opus DTX: {e:?}"))?;
+        encoder
+            .set_inband_fec(fec)
+            .map_err(|e| anyhow::anyhow!("Failed to set Opus FEC: {e:?}"))?;
+        encoder
+            .set_packet_loss_perc(if fec { FEC_LOSS_PERC } else { 0 })
+            .map_err(|e| anyhow::anyhow!("Failed to set Opus expected loss: {e:?}"))?;
+        info!(?mode, bitrate, dtx, fec, "Opus encoder mode set");
+        Ok(())
+    }
+
+    /// Switch the record stream to another PulseAudio source. The current
+    /// stream is kept if the new one can't be opened.
+    pub fn set_source(&mut self, source: &str) -> anyhow::Result<()> {
+        self.recorder = open_recorder(
+            self.pulse_server.as_deref(),
+            self.pipewire.as_deref(),
+            source,
+            &self.spec,
+            &self.buf_attr,
+        )?;
+        self.source = source.to_string();
+        info!(source, "Audio capture source changed");
+        Ok(())
+    }
+
+    /// Read one frame of PCM audio from the sound server and encode to Opus.
     pub fn capture_and_encode(&mut self) -> anyhow::Result<Vec<u8>> {
-        self.simple
-            .read(&mut self.pcm_buffer)
-            .map_err(|e| anyhow::anyhow!("PulseAudio read failed: {e}"))?;
+        match &mut self.recorder {
+            Recorder::Pulse(simple) => simple
+                .read(&mut self.pcm_buffer)
+                .map_err(|e| anyhow::anyhow!("PulseAudio read failed: {e}"))?,
+            Recorder::PipeWire(recorder) => recorder.read(&mut self.pcm_buffer)?,
+        }
 
         // Convert s16le bytes to i16 samples using pre-allocated buffer
         for (i, chunk) in self.pcm_buffer.chunks_exact(2).enumerate() {
@@ -110,3 +344,472 @@ impl AudioCapture {
         Ok(self.opus_buffer[..encoded_len].to_vec())
     }
 }
+
+/// Samples per channel in a `frame_ms` frame.
+fn samples_per_frame(spec: &pulse::sample::Spec, frame_ms: u32) -> usize {
+    (spec.rate * frame_ms / 1000) as usize
+}
+
+/// Where frames are recorded from
+enum Recorder {
+    Pulse(Simple),
+    PipeWire(PipeWireRecorder),
+}
+
+fn open_recorder(
+    pulse_server: Option<&str>,
+    pipewire: Option<&Path>,
+    source: &str,
+    spec: &pulse::sample::Spec,
+    buf_attr: &pulse::def::BufferAttr,
+) -> anyhow::Result<Recorder> {
+    Ok(match pipewire {
+        Some(socket) => Recorder::PipeWire(PipeWireRecorder::open(
+            socket,
+            pipewire_target(source),
+            spec,
+            buf_attr.fragsize as usize,
+        )?),
+        None => Recorder::Pulse(open_record_stream(pulse_server, source, spec, buf_attr)?),
+    })
+}
+
+/// pipewiresrc's target for a PulseAudio source name: the sink whose
+/// monitor it is, or none for the default sink's.
+fn pipewire_target(source: &str) -> Option<&str> {
+    (source != DEFAULT_SOURCE).then(|| source.strip_suffix(".monitor").unwrap_or(source))
+}
+
+/// pipewiresrc recording a sink's monitor, converted to the capture
+/// format. PipeWire hands over whatever its graph's quantum holds, so
+/// samples are cut into frames here.
+struct PipeWireRecorder {
+    pipeline: gst::Pipeline,
+    appsink: AppSink,
+    /// Samples pulled but not yet returned
+    pending: VecDeque<u8>,
+    /// pipewiresrc talks over a dup of this connection
+    _connection: UnixStream,
+}
+
+impl PipeWireRecorder {
+    fn open(
+        socket: &Path,
+        target: Option<&str>,
+        spec: &pulse::sample::Spec,
+        frame_bytes: usize,
+    ) -> anyhow::Result<Self> {
+        let connection = UnixStream::connect(socket)
+            .with_context(|| format!("PipeWire connection to {} failed", socket.display()))?;
+        let frame_samples = frame_bytes / (spec.channels as usize * 2);
+        let stream_properties = gst::Structure::builder("props")
+            .field("stream.capture.sink", "true")
+            .field("node.latency", format!("{frame_samples}/{}", spec.rate))
+            .field("media.name", "audio-capture")
+            .build();
+        let mut src = ElementFactory::make("pipewiresrc")
+            .property("fd", connection.as_raw_fd())
+            .property("client-name", "beam-agent")
+            .property("stream-properties", stream_properties);
+        if let Some(target) = target {
+            src = src.property("target-object", target);
+        }
+        let src = src.build().context("Failed to create pipewiresrc")?;
+        let caps = gst::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("rate", spec.rate as i32)
+            .field("channels", i32::from(spec.channels))
+            .build();
+        let capsfilter = ElementFactory::make("capsfilter")
+            .property("caps", &caps)
+            .build()
+            .context("Failed to create audio capsfilter")?;
+        let appsink = AppSink::builder()
+            .sync(false)
+            .max_buffers(PIPEWIRE_MAX_BUFFERS)
+            .drop(true)
+            .build();
+        let elements = [
+            src,
+            ElementFactory::make("audioconvert")
+                .build()
+                .context("Failed to create audioconvert")?,
+            ElementFactory::make("audioresample")
+                .build()
+                .context("Failed to create audioresample")?,
+            capsfilter,
+            appsink.clone().upcast(),
+        ];
+        let pipeline = gst::Pipeline::new();
+        pipeline
+            .add_many(&elements)
+            .context("Failed to add audio elements to pipeline")?;
+        gst::Element::link_many(&elements).context("Failed to link audio pipeline")?;
+        if let Err(e) = pipeline.set_state(gst::State::Playing) {
+            let _ = pipeline.set_state(gst::State::Null);
+            return Err(e).context(format!(
+                "PipeWire capture of {} failed to start",
+                target.unwrap_or("the default sink")
+            ));
+        }
+        Ok(Self {
+            pipeline,
+            appsink,
+            pending: VecDeque::new(),
+            _connection: connection,
+        })
+    }
+
+    /// Fill `buf` with the next samples, waiting for PipeWire as long as
+    /// it takes, as a PulseAudio read does.
+    fn read(&mut self, buf: &mut [u8]) -> anyhow::Result<()> {
+        while self.pending.len() < buf.len() {
+            match self.appsink.try_pull_sample(ClockTime::from_seconds(1)) {
+                Some(sample) => {
+                    let buffer = sample
+                        .buffer()
+                        .context("PipeWire sample without a buffer")?;
+                    let map = buffer
+                        .map_readable()
+                        .map_err(|_| anyhow::anyhow!("PipeWire buffer not readable"))?;
+                    self.pending.extend(map.as_slice());
+                }
+                None => self.check_bus()?,
+            }
+        }
+        let n = buf.len();
+        for (byte, sample) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *byte = sample;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        self.pending.clear();
+        while self.appsink.try_pull_sample(ClockTime::ZERO).is_some() {}
+    }
+
+    fn check_bus(&self) -> anyhow::Result<()> {
+        if self.appsink.is_eos() {
+            anyhow::bail!("PipeWire capture stream ended");
+        }
+        let error = self
+            .pipeline
+            .bus()
+            .and_then(|bus| bus.pop_filtered(&[gst::MessageType::Error]));
+        if let Some(msg) = error
+            && let gst::MessageView::Error(err) = msg.view()
+        {
+            anyhow::bail!("PipeWire capture failed: {}", err.error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipeWireRecorder {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+fn open_record_stream(
+    pulse_server: Option<&str>,
+    source: &str,
+    spec: &pulse::sample::Spec,
+    buf_attr: &pulse::def::BufferAttr,
+) -> anyhow::Result<Simple> {
+    Simple::new(
+        pulse_server, // Explicit server path (avoids unsafe set_var)
+        "beam-agent", // Application name
+        pulse::stream::Direction::Record,
+        Some(source),    // Monitor of the sink being captured
+        "audio-capture", // Stream description
+        spec,
+        None,           // Default channel map
+        Some(buf_attr), // Low-latency buffer (20ms fragments)
+    )
+    .map_err(|e| anyhow::anyhow!("PulseAudio connection to {source} failed: {e}"))
+}
+
+/// What the browser asked to capture (`InputEvent::AudioSource`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioSelection {
+    /// Monitor of the default sink: everything the session plays
+    Default,
+    /// Monitor of one sink, by name
+    Sink(String),
+    /// One application's stream, by sink-input index
+    App(u32),
+}
+
+impl AudioSelection {
+    /// An application choice wins over a sink; neither means the default.
+    pub fn from_event(sink: Option<String>, app: Option<u32>) -> Self {
+        match (sink, app) {
+            (_, Some(index)) => Self::App(index),
+            (Some(name), None) => Self::Sink(name),
+            (None, None) => Self::Default,
+        }
+    }
+}
+
+/// Routes PulseAudio streams for the current `AudioSelection`. Lives on
+/// the audio thread; all methods shell out to `pactl` and block.
+pub struct AudioRouter {
+    pulse_server: Option<String>,
+    /// Application stream currently parked on `APP_CAPTURE_SINK`
+    moved_app: Option<u32>,
+}
+
+impl AudioRouter {
+    pub fn new(pulse_server: Option<&str>) -> Self {
+        Self {
+            pulse_server: pulse_server.map(str::to_string),
+            moved_app: None,
+        }
+    }
+
+    /// Apply a selection and return the PulseAudio source to record from.
+    pub fn select(&mut self, selection: &AudioSelection) -> anyhow::Result<String> {
+        // An app parked on the capture sink is inaudible to every other
+        // choice, so send it back to the default sink first
+        if let Some(index) = self.moved_app.take()
+            && *selection != AudioSelection::App(index)
+        {
+            let index = index.to_string();
+            if let Err(e) = self.pactl(&["move-sink-input", &index, "@DEFAULT_SINK@"]) {
+                warn!(index, "Failed to restore application stream: {e:#}");
+            }
+        }
+
+        match selection {
+            AudioSelection::Default => Ok(DEFAULT_SOURCE.to_string()),
+            AudioSelection::Sink(name) => {
+                let sinks = parse_pactl_list(&self.pactl(&["list", "sinks"])?, "Sink");
+                if !sinks
+                    .iter()
+                    .any(|(_, f)| f.get("Name").is_some_and(|n| n == name))
+                {
+                    anyhow::bail!("Unknown audio sink: {name}");
+                }
+                Ok(format!("{name}.monitor"))
+            }
+            AudioSelection::App(index) => {
+                self.ensure_app_capture_sink()?;
+                self.pactl(&["move-sink-input", &index.to_string(), APP_CAPTURE_SINK])
+                    .with_context(|| format!("Failed to capture application stream {index}"))?;
+                self.moved_app = Some(*index);
+                Ok(format!("{APP_CAPTURE_SINK}.monitor"))
+            }
+        }
+    }
+
+    fn ensure_app_capture_sink(&self) -> anyhow::Result<()> {
+        let sinks = self.pactl(&["list", "short", "sinks"])?;
+        if sinks
+            .lines()
+            .any(|l| l.split('\t').nth(1) == Some(APP_CAPTURE_SINK))
+        {
+            return Ok(());
+        }
+        self.pactl(&[
+            "load-module",
+            "module-null-sink",
+            &format!("sink_name={APP_CAPTURE_SINK}"),
+            "sink_properties=device.description=Beam-Application-Capture",
+        ])?;
+        Ok(())
+    }
+
+    fn pactl(&self, args: &[&str]) -> anyhow::Result<String> {
+        pactl(self.pulse_server.as_deref(), args)
+    }
+}
+
+/// Give a session's fresh PipeWire the null sink PulseAudio sessions get
+/// from their config, as the default sink.
+pub fn add_session_sink(pulse_server: &str) -> anyhow::Result<()> {
+    pactl(
+        Some(pulse_server),
+        &[
+            "load-module",
+            "module-null-sink",
+            "sink_name=beam",
+            "sink_properties=device.description=Beam",
+        ],
+    )?;
+    pactl(Some(pulse_server), &["set-default-sink", "beam"])?;
+    Ok(())
+}
+
+/// Enumerate capturable sinks and application playback streams, with
+/// the streams' volumes for the browser's mixer.
+pub fn list_sources(pulse_server: Option<&str>) -> anyhow::Result<AudioSources> {
+    let sinks = parse_pactl_list(&pactl(pulse_server, &["list", "sinks"])?, "Sink")
+        .into_iter()
+        .filter_map(|(_, mut fields)| {
+            let name = fields.remove("Name")?;
+            if name == APP_CAPTURE_SINK {
+                return None;
+            }
+            let description = fields.remove("Description").unwrap_or_else(|| name.clone());
+            Some(AudioSinkInfo { name, description })
+        })
+        .collect();
+
+    let apps = crate::mixer::list_apps(pulse_server)?;
+
+    Ok(AudioSources { sinks, apps })
+}
+
+fn pactl(pulse_server: Option<&str>, args: &[&str]) -> anyhow::Result<String> {
+    let mut cmd = Command::new("pactl");
+    cmd.args(args);
+    if let Some(server) = pulse_server {
+        cmd.env("PULSE_SERVER", server);
+    }
+    let output = cmd.output().context("Failed to run pactl")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "pactl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Split `pactl list <kind>` output into `(index, fields)` blocks, one per
+/// `<header> #N` line. Fields hold both `Key: value` lines and quoted
+/// `key = "value"` properties.
+fn parse_pactl_list(output: &str, header: &str) -> Vec<(u32, HashMap<String, String>)> {
+    let prefix = format!("{header} #");
+    let mut blocks = Vec::new();
+    for line in output.lines() {
+        if let Some(index) = line.strip_prefix(&prefix) {
+            if let Ok(index) = index.trim().parse() {
+                blocks.push((index, HashMap::new()));
+            }
+            continue;
+        }
+        let Some((_, fields)) = blocks.last_mut() else {
+            continue;
+        };
+        let line = line.trim();
+        if let Some((key, value)) = line.split_once(" = ") {
+            fields.insert(key.to_string(), value.trim_matches('"').to_string());
+        } else if let Some((key, value)) = line.split_once(": ") {
+            fields
+                .entry(key.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINKS: &str = "Sink #0
+\tState: RUNNING
+\tName: auto_null
+\tDescription: Dummy Output
+\tProperties:
+\t\tdevice.description = \"Dummy Output\"
+
+Sink #4
+\tName: beam_app_capture
+\tDescription: Beam-Application-Capture
+";
+
+    const SINK_INPUTS: &str = "Sink Input #7
+\tDriver: protocol-native.c
+\tSink: 0
+\tProperties:
+\t\tmedia.name = \"Playback\"
+\t\tapplication.name = \"Firefox\"
+Sink Input #9
+\tProperties:
+\t\tmedia.name = \"notification\"
+";
+
+    #[test]
+    fn parses_pactl_blocks() {
+        let sinks = parse_pactl_list(SINKS, "Sink");
+        assert_eq!(sinks.len(), 2);
+        assert_eq!(sinks[0].0, 0);
+        assert_eq!(sinks[0].1["Name"], "auto_null");
+        assert_eq!(sinks[0].1["device.description"], "Dummy Output");
+        assert_eq!(sinks[1].1["Name"], APP_CAPTURE_SINK);
+
+        let inputs = parse_pactl_list(SINK_INPUTS, "Sink Input");
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].0, 7);
+        assert_eq!(inputs[0].1["application.name"], "Firefox");
+        assert_eq!(inputs[1].1["media.name"], "notification");
+    }
+
+    #[test]
+    fn sink_header_does_not_match_sink_inputs() {
+        assert!(parse_pactl_list(SINK_INPUTS, "Sink").is_empty());
+    }
+
+    #[test]
+    fn audio_mode_parsing_and_bitrate() {
+        assert_eq!(AudioMode::from_mode("voice"), Some(AudioMode::Voice));
+        assert_eq!(AudioMode::from_mode("music"), Some(AudioMode::Music));
+        assert_eq!(AudioMode::from_mode("standard"), Some(AudioMode::Standard));
+        assert_eq!(AudioMode::from_mode("loud"), None);
+
+        assert_eq!(AudioMode::Standard.bitrate_bps(128), 128_000);
+        assert_eq!(AudioMode::Voice.bitrate_bps(128), 32_000);
+        assert_eq!(AudioMode::Music.bitrate_bps(128), 192_000);
+    }
+
+    #[test]
+    fn tuning_keeps_what_an_event_leaves_out() {
+        let tuning = OpusTuning::default().with(Some(40), Some(true), None);
+        assert_eq!(
+            tuning,
+            OpusTuning {
+                frame_ms: 40,
+                fec: true,
+                dtx: false,
+            }
+        );
+        assert_eq!(tuning.with(None, None, Some(true)).frame_ms, 40);
+        assert_eq!(tuning.frame_duration(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn selection_prefers_app_over_sink() {
+        assert_eq!(
+            AudioSelection::from_event(Some("auto_null".into()), Some(3)),
+            AudioSelection::App(3)
+        );
+        assert_eq!(
+            AudioSelection::from_event(Some("auto_null".into()), None),
+            AudioSelection::Sink("auto_null".into())
+        );
+        assert_eq!(
+            AudioSelection::from_event(None, None),
+            AudioSelection::Default
+        );
+    }
+
+    #[test]
+    fn pipewire_records_the_monitored_sink() {
+        assert_eq!(pipewire_target(DEFAULT_SOURCE), None);
+        assert_eq!(pipewire_target("beam.monitor"), Some("beam"));
+        assert_eq!(
+            pipewire_target(&format!("{APP_CAPTURE_SINK}.monitor")),
+            Some(APP_CAPTURE_SINK)
+        );
+        assert_eq!(
+            pipewire_target("alsa_input.usb-mic"),
+            Some("alsa_input.usb-mic")
+        );
+    }
+}
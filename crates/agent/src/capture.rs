@@ -1,15 +1,23 @@
 use anyhow::{Context, bail};
 use std::sync::mpsc as std_mpsc;
+use std::time::Duration;
 use tracing::{debug, info};
 use x11rb::connection::Connection;
+use x11rb::errors::{ConnectionError, ReplyError};
+use x11rb::protocol::Event;
+use x11rb::protocol::damage;
+use x11rb::protocol::screensaver;
 use x11rb::protocol::shm;
+use x11rb::protocol::xfixes;
 use x11rb::protocol::xproto::{ImageFormat, Screen};
 use x11rb::rust_connection::RustConnection;
 
 const BYTES_PER_PIXEL: u32 = 4; // BGRA
-/// Number of pre-allocated frame buffers. 3 allows one filling from SHM,
-/// one being encoded by GStreamer, and one spare to absorb timing jitter.
-const POOL_SIZE: usize = 3;
+/// Number of pre-allocated frame buffers. The capture thread doesn't wait
+/// for the encoder, so besides the one filling from SHM, two can be queued
+/// in or encoded by GStreamer while the encoder runs a frame behind, plus
+/// one spare to absorb timing jitter.
+const POOL_SIZE: usize = 4;
 
 /// A frame buffer checked out from the pool. When dropped (e.g. after
 /// GStreamer finishes encoding), the backing Vec is returned to the pool
@@ -40,11 +48,70 @@ impl Drop for PooledFrame {
     }
 }
 
+/// Screen area changed since the previous capture, from XDamage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DirtyRect {
+    /// Smallest rectangle covering both `self` and `other`.
+    pub fn union(self, other: Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Self {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+
+    /// Fraction of a `width` x `height` frame covered by this rectangle.
+    pub fn coverage(&self, width: u32, height: u32) -> f64 {
+        if width == 0 || height == 0 {
+            return 0.0;
+        }
+        (self.width as f64 * self.height as f64) / (width as f64 * height as f64)
+    }
+}
+
+/// Part of the root window a capture covers: the whole screen, or one of
+/// the monitors laid out on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Area {
+    /// `rect`, in root window coordinates, clipped to the area and moved
+    /// into its coordinates. None if the two don't overlap.
+    fn clip(&self, rect: DirtyRect) -> Option<DirtyRect> {
+        let x = rect.x.max(self.x);
+        let y = rect.y.max(self.y);
+        let right = (rect.x + rect.width).min(self.x + self.width);
+        let bottom = (rect.y + rect.height).min(self.y + self.height);
+        (right > x && bottom > y).then(|| DirtyRect {
+            x: x - self.x,
+            y: y - self.y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
 pub struct ScreenCapture {
     conn: RustConnection,
     root: u32,
-    width: u32,
-    height: u32,
+    area: Area,
     _depth: u8,
     shm_seg: shm::Seg,
     _shm_id: i32,
@@ -53,6 +120,14 @@ pub struct ScreenCapture {
     /// Pool of reusable frame buffers to avoid per-frame allocation
     pool_tx: std_mpsc::Sender<Vec<u8>>,
     pool_rx: std_mpsc::Receiver<Vec<u8>>,
+    /// XDamage object on the root window (None if the extension is missing)
+    damage: Option<DamageTracker>,
+    /// Bounding box of damage reported since the last `take_dirty_region`
+    dirty: Option<DirtyRect>,
+    /// XFixes negotiated on this connection (needed for cursor compositing)
+    xfixes_available: bool,
+    /// MIT-SCREEN-SAVER negotiated (X input idle time for idle detection)
+    screensaver_available: bool,
 }
 
 // SAFETY: The SHM pointer is only accessed through &mut self methods,
@@ -60,7 +135,17 @@ pub struct ScreenCapture {
 unsafe impl Send for ScreenCapture {}
 
 impl ScreenCapture {
+    /// Capture the whole screen.
     pub fn new(x_display: &str) -> anyhow::Result<Self> {
+        Self::open(x_display, None)
+    }
+
+    /// Capture `area` of the screen, such as one monitor of two.
+    pub fn with_area(x_display: &str, area: Area) -> anyhow::Result<Self> {
+        Self::open(x_display, Some(area))
+    }
+
+    fn open(x_display: &str, area: Option<Area>) -> anyhow::Result<Self> {
         let (conn, screen_num) =
             RustConnection::connect(Some(x_display)).context("Failed to connect to X display")?;
 
@@ -72,11 +157,30 @@ impl ScreenCapture {
 
         let screen: &Screen = &conn.setup().roots[screen_num];
         let root = screen.root;
-        let width = screen.width_in_pixels as u32;
-        let height = screen.height_in_pixels as u32;
+        let (screen_width, screen_height) = (
+            screen.width_in_pixels as u32,
+            screen.height_in_pixels as u32,
+        );
+        let area = area.unwrap_or(Area {
+            x: 0,
+            y: 0,
+            width: screen_width,
+            height: screen_height,
+        });
+        if area.x + area.width > screen_width || area.y + area.height > screen_height {
+            bail!("Capture area {area:?} is outside the {screen_width}x{screen_height} screen");
+        }
+        let (width, height) = (area.width, area.height);
         let depth = screen.root_depth;
 
-        info!(width, height, depth, x_display, "Connected to X display");
+        info!(
+            width,
+            height,
+            x = area.x,
+            depth,
+            x_display,
+            "Connected to X display"
+        );
 
         let shm_size = (width * height * BYTES_PER_PIXEL) as usize;
 
@@ -125,11 +229,30 @@ impl ScreenCapture {
             "Frame buffer pool initialized"
         );
 
+        // XFixes requires a per-client version handshake before GetCursorImage
+        let xfixes_available = xfixes::query_version(&conn, 4, 0)
+            .ok()
+            .and_then(|c| c.reply().ok())
+            .is_some();
+        let screensaver_available = screensaver::query_version(&conn, 1, 1)
+            .ok()
+            .and_then(|c| c.reply().ok())
+            .is_some();
+
+        // Track changed screen areas so the encoder can spend bits where
+        // content actually moves. Optional: capture works without it.
+        let damage = match DamageTracker::new(x_display, root) {
+            Ok(d) => Some(d),
+            Err(e) => {
+                debug!("XDamage unavailable, no dirty-region hints: {e:#}");
+                None
+            }
+        };
+
         Ok(Self {
             conn,
             root,
-            width,
-            height,
+            area,
             _depth: depth,
             shm_seg,
             _shm_id: shm_id,
@@ -137,21 +260,83 @@ impl ScreenCapture {
             shm_size,
             pool_tx,
             pool_rx,
+            damage,
+            dirty: None,
+            xfixes_available,
+            screensaver_available,
         })
     }
 
+    /// Return the bounding box of screen changes since the previous call
+    /// and reset the accumulated damage. `None` means either nothing changed
+    /// or XDamage is unavailable.
+    pub fn take_dirty_region(&mut self) -> Option<DirtyRect> {
+        let tracker = self.damage.as_ref()?;
+        while let Ok(Some(event)) = tracker.conn.poll_for_event() {
+            if let Event::DamageNotify(ev) = event
+                && let Some(rect) = self.area.clip(DirtyRect {
+                    x: ev.area.x.max(0) as u32,
+                    y: ev.area.y.max(0) as u32,
+                    width: ev.area.width as u32,
+                    height: ev.area.height as u32,
+                })
+            {
+                self.dirty = Some(match self.dirty {
+                    Some(prev) => prev.union(rect),
+                    None => rect,
+                });
+            }
+        }
+        // Clear the server-side region so BOUNDING_BOX reports fire again
+        let _ = damage::subtract(&tracker.conn, tracker.damage, x11rb::NONE, x11rb::NONE);
+        let _ = tracker.conn.flush();
+        self.dirty.take()
+    }
+
+    /// Whether XDamage is active, i.e. `take_dirty_region` returning None
+    /// really means the screen didn't change.
+    pub fn has_damage(&self) -> bool {
+        self.damage.is_some()
+    }
+
+    /// How the screen is captured, for the agent's report to the server.
+    pub fn backend(&self) -> &'static str {
+        if self.has_damage() {
+            "x11-shm+damage"
+        } else {
+            "x11-shm"
+        }
+    }
+
+    /// Time since the last input the X server saw from any client or
+    /// device. None without the MIT-SCREEN-SAVER extension.
+    pub fn input_idle_time(&self) -> Option<Duration> {
+        if !self.screensaver_available {
+            return None;
+        }
+        let info = screensaver::query_info(&self.conn, self.root)
+            .ok()?
+            .reply()
+            .ok()?;
+        Some(Duration::from_millis(info.ms_since_user_input as u64))
+    }
+
     /// Capture a frame into a pooled buffer. The SHM data is copied once
     /// into a pre-allocated buffer from the pool, then passed to the encoder
     /// via `gst::Buffer::from_slice`. When GStreamer finishes encoding, the
     /// buffer is automatically returned to the pool for reuse.
-    pub fn capture_frame(&mut self) -> anyhow::Result<PooledFrame> {
+    ///
+    /// With `with_cursor`, the current X cursor image is alpha-blended into
+    /// the frame. The X server never includes the cursor in GetImage
+    /// results, so this is the only way it ends up in the stream.
+    pub fn capture_frame(&mut self, with_cursor: bool) -> anyhow::Result<PooledFrame> {
         shm::get_image(
             &self.conn,
             self.root,
-            0,
-            0,
-            self.width as u16,
-            self.height as u16,
+            self.area.x as i16,
+            self.area.y as i16,
+            self.area.width as u16,
+            self.area.height as u16,
             !0, // all planes
             ImageFormat::Z_PIXMAP.into(),
             self.shm_seg,
@@ -184,6 +369,24 @@ impl ScreenCapture {
             pixel[3] = 0xFF;
         }
 
+        if with_cursor && self.xfixes_available {
+            match xfixes::get_cursor_image(&self.conn).map(|c| c.reply()) {
+                Ok(Ok(cursor)) => blend_cursor(
+                    &mut data,
+                    self.area.width,
+                    self.area.height,
+                    &CursorSprite {
+                        pixels: &cursor.cursor_image,
+                        width: cursor.width as u32,
+                        height: cursor.height as u32,
+                        left: cursor.x as i32 - cursor.xhot as i32 - self.area.x as i32,
+                        top: cursor.y as i32 - cursor.yhot as i32 - self.area.y as i32,
+                    },
+                ),
+                _ => debug!("GetCursorImage failed, frame sent without cursor"),
+            }
+        }
+
         Ok(PooledFrame {
             data,
             return_tx: self.pool_tx.clone(),
@@ -191,11 +394,97 @@ impl ScreenCapture {
     }
 
     pub fn width(&self) -> u32 {
-        self.width
+        self.area.width
     }
 
     pub fn height(&self) -> u32 {
-        self.height
+        self.area.height
+    }
+}
+
+/// Whether a capture error means the connection to the X server is gone,
+/// as when Xorg has died, rather than one request failing. Nothing on a
+/// lost connection works again.
+pub fn is_connection_lost(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<ConnectionError>()
+            || matches!(
+                cause.downcast_ref::<ReplyError>(),
+                Some(ReplyError::ConnectionError(_))
+            )
+    })
+}
+
+/// Cursor image positioned in frame coordinates.
+struct CursorSprite<'a> {
+    /// Premultiplied ARGB pixels, as returned by XFixesGetCursorImage
+    pixels: &'a [u32],
+    width: u32,
+    height: u32,
+    /// Top-left corner (pointer position minus hotspot); may be negative
+    left: i32,
+    top: i32,
+}
+
+/// Alpha-blend a cursor sprite onto a BGRx frame. Parts of the cursor
+/// outside the frame are clipped.
+fn blend_cursor(frame: &mut [u8], frame_width: u32, frame_height: u32, cursor: &CursorSprite) {
+    for cy in 0..cursor.height as i32 {
+        let fy = cursor.top + cy;
+        if fy < 0 || fy >= frame_height as i32 {
+            continue;
+        }
+        for cx in 0..cursor.width as i32 {
+            let fx = cursor.left + cx;
+            if fx < 0 || fx >= frame_width as i32 {
+                continue;
+            }
+            let Some(&argb) = cursor
+                .pixels
+                .get((cy as u32 * cursor.width + cx as u32) as usize)
+            else {
+                return;
+            };
+            let alpha = argb >> 24;
+            if alpha == 0 {
+                continue;
+            }
+            let offset = ((fy as u32 * frame_width + fx as u32) * BYTES_PER_PIXEL) as usize;
+            let Some(pixel) = frame.get_mut(offset..offset + 3) else {
+                return;
+            };
+            let inv = 255 - alpha;
+            // BGR order; source channels are already premultiplied
+            for (i, shift) in [0u32, 8, 16].into_iter().enumerate() {
+                let src = (argb >> shift) & 0xFF;
+                pixel[i] = (src + pixel[i] as u32 * inv / 255).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// XDamage on a connection of its own, so draining damage events never
+/// swallows events meant for the capture connection. Closing the
+/// connection frees the damage object.
+struct DamageTracker {
+    conn: RustConnection,
+    damage: damage::Damage,
+}
+
+impl DamageTracker {
+    fn new(x_display: &str, root: u32) -> anyhow::Result<Self> {
+        let (conn, _) = RustConnection::connect(Some(x_display))
+            .context("Failed to connect to X display for DAMAGE")?;
+        damage::query_version(&conn, 1, 1)
+            .context("DAMAGE extension not available")?
+            .reply()
+            .context("DAMAGE query_version failed")?;
+        let damage = conn.generate_id().context("Failed to generate damage id")?;
+        damage::create(&conn, damage, root, damage::ReportLevel::BOUNDING_BOX)
+            .context("DAMAGE create request failed")?
+            .check()
+            .context("DAMAGE create failed")?;
+        Ok(Self { conn, damage })
     }
 }
 
@@ -209,3 +498,140 @@ impl Drop for ScreenCapture {
         debug!("SHM segment detached and cleaned up");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirty_rect_union_covers_both() {
+        let a = DirtyRect {
+            x: 10,
+            y: 10,
+            width: 100,
+            height: 50,
+        };
+        let b = DirtyRect {
+            x: 200,
+            y: 5,
+            width: 20,
+            height: 20,
+        };
+        let u = a.union(b);
+        assert_eq!(
+            u,
+            DirtyRect {
+                x: 10,
+                y: 5,
+                width: 210,
+                height: 55
+            }
+        );
+    }
+
+    #[test]
+    fn damage_is_clipped_to_the_captured_area() {
+        // The second of two 1920x1080 monitors
+        let area = Area {
+            x: 1920,
+            y: 0,
+            width: 1280,
+            height: 1024,
+        };
+        let across = DirtyRect {
+            x: 1900,
+            y: 1000,
+            width: 100,
+            height: 100,
+        };
+        assert_eq!(
+            area.clip(across),
+            Some(DirtyRect {
+                x: 0,
+                y: 1000,
+                width: 80,
+                height: 24
+            })
+        );
+        let first_only = DirtyRect {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(area.clip(first_only), None);
+    }
+
+    #[test]
+    fn blend_cursor_opaque_and_transparent_pixels() {
+        // 2x1 frame, grey
+        let mut frame = vec![0x80, 0x80, 0x80, 0xFF, 0x80, 0x80, 0x80, 0xFF];
+        // 2x1 cursor: opaque red, fully transparent
+        let cursor = [0xFFFF0000, 0x00000000];
+        let sprite = CursorSprite {
+            pixels: &cursor,
+            width: 2,
+            height: 1,
+            left: 0,
+            top: 0,
+        };
+        blend_cursor(&mut frame, 2, 1, &sprite);
+        assert_eq!(
+            &frame[0..3],
+            &[0x00, 0x00, 0xFF],
+            "opaque red replaces pixel"
+        );
+        assert_eq!(
+            &frame[4..7],
+            &[0x80, 0x80, 0x80],
+            "transparent leaves pixel"
+        );
+    }
+
+    #[test]
+    fn blend_cursor_clips_at_frame_edges() {
+        let mut frame = vec![0u8; 2 * 2 * 4];
+        let cursor = [0xFFFFFFFF; 9];
+        // 3x3 white cursor hanging off the top-left corner
+        let sprite = CursorSprite {
+            pixels: &cursor,
+            width: 3,
+            height: 3,
+            left: -2,
+            top: -2,
+        };
+        blend_cursor(&mut frame, 2, 2, &sprite);
+        assert_eq!(&frame[0..3], &[0xFF, 0xFF, 0xFF]);
+        assert_eq!(&frame[4..7], &[0, 0, 0]);
+    }
+
+    #[test]
+    fn lost_connections_are_told_from_failed_requests() {
+        let lost = anyhow::Error::from(ReplyError::ConnectionError(ConnectionError::UnknownError))
+            .context("SHM GetImage reply failed");
+        assert!(is_connection_lost(&lost));
+        let lost = anyhow::Error::from(ConnectionError::UnknownError)
+            .context("SHM GetImage request failed");
+        assert!(is_connection_lost(&lost));
+        assert!(!is_connection_lost(&anyhow::anyhow!("shmat failed")));
+    }
+
+    #[test]
+    fn dirty_rect_coverage() {
+        let full = DirtyRect {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(full.coverage(1920, 1080), 1.0);
+        let quarter = DirtyRect {
+            x: 0,
+            y: 0,
+            width: 960,
+            height: 540,
+        };
+        assert_eq!(quarter.coverage(1920, 1080), 0.25);
+        assert_eq!(quarter.coverage(0, 0), 0.0);
+    }
+}
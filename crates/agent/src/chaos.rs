@@ -0,0 +1,193 @@
+//! Fault injection for reconnection testing, in debug builds only.
+//!
+//! When `BEAM_CHAOS_DIR` is set, the agent listens on
+//! `<dir>/<session-id>.sock` for one command per connection:
+//!
+//! - `encoder-error`: the next frame sees a failed encoder, as after a
+//!   GStreamer pipeline error; repeat it to walk the fallback chain
+//! - `drop-signaling`: cut the server WebSocket without a close frame;
+//!   the agent reconnects with backoff
+//! - `stall-capture <secs>`: block the capture thread, like a hung X server
+//!
+//! e.g. `echo drop-signaling | nc -U /tmp/beam-chaos/<id>.sock`. The server
+//! passes its environment on to agents, so setting the variable for a
+//! `make dev` server covers every session. Release builds ignore it.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const DIR_ENV: &str = "BEAM_CHAOS_DIR";
+
+/// Longest capture stall one command can ask for
+const MAX_STALL: Duration = Duration::from_secs(120);
+
+/// A client that connects but never sends a command is dropped after this.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq)]
+enum Fault {
+    EncoderError,
+    DropSignaling,
+    StallCapture(Duration),
+}
+
+fn parse_fault(line: &str) -> Option<Fault> {
+    let mut words = line.split_whitespace();
+    let fault = match words.next()? {
+        "encoder-error" => Fault::EncoderError,
+        "drop-signaling" => Fault::DropSignaling,
+        "stall-capture" => {
+            let secs: f64 = words.next()?.parse().ok()?;
+            if !secs.is_finite() || secs <= 0.0 {
+                return None;
+            }
+            Fault::StallCapture(Duration::from_secs_f64(secs).min(MAX_STALL))
+        }
+        _ => return None,
+    };
+    words.next().is_none().then_some(fault)
+}
+
+/// Faults waiting to be picked up by the capture thread and the signaling
+/// task. Each one fires once.
+#[derive(Default)]
+pub(crate) struct Chaos {
+    encoder_error: AtomicBool,
+    stall_capture_ms: AtomicU64,
+    drop_signaling: Notify,
+}
+
+impl Chaos {
+    fn inject(&self, fault: Fault) {
+        warn!(?fault, "Chaos: injecting fault");
+        match fault {
+            Fault::EncoderError => self.encoder_error.store(true, Ordering::Relaxed),
+            Fault::DropSignaling => self.drop_signaling.notify_one(),
+            Fault::StallCapture(stall) => self
+                .stall_capture_ms
+                .store(stall.as_millis() as u64, Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn take_encoder_error(&self) -> bool {
+        self.encoder_error.swap(false, Ordering::Relaxed)
+    }
+
+    pub(crate) fn take_capture_stall(&self) -> Option<Duration> {
+        match self.stall_capture_ms.swap(0, Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Resolves when the signaling connection should be dropped. A request
+    /// made while disconnected applies to the next connection.
+    pub(crate) async fn signaling_dropped(&self) {
+        self.drop_signaling.notified().await;
+    }
+}
+
+/// Control socket for this session, or None when fault injection is off.
+pub(crate) fn socket_from_env(session_id: Uuid) -> Option<PathBuf> {
+    if !cfg!(debug_assertions) {
+        return None;
+    }
+    let dir = std::env::var_os(DIR_ENV)?;
+    Some(PathBuf::from(dir).join(format!("{session_id}.sock")))
+}
+
+/// Accept fault commands on `socket` and answer each with `ok` or an error.
+/// Returns only if the socket can't be set up.
+pub(crate) async fn run_chaos_listener(socket: &Path, chaos: &Chaos) -> Result<()> {
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let _ = fs::remove_file(socket);
+    let listener = tokio::net::UnixListener::bind(socket)
+        .with_context(|| format!("Failed to bind {}", socket.display()))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+        .context("Failed to restrict chaos socket permissions")?;
+    warn!(socket = %socket.display(), "Chaos: fault injection enabled");
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Chaos socket accept failed: {e}");
+                continue;
+            }
+        };
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+        let mut reader = BufReader::new(reader.take(256));
+        match tokio::time::timeout(READ_TIMEOUT, reader.read_line(&mut line)).await {
+            Ok(Ok(_)) => {}
+            _ => continue,
+        }
+        let reply = match parse_fault(&line) {
+            Some(fault) => {
+                chaos.inject(fault);
+                "ok\n"
+            }
+            None => {
+                info!(command = line.trim(), "Chaos: unknown command");
+                "unknown command (encoder-error, drop-signaling, stall-capture <secs>)\n"
+            }
+        };
+        let _ = writer.write_all(reply.as_bytes()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fault_commands() {
+        assert_eq!(parse_fault("encoder-error\n"), Some(Fault::EncoderError));
+        assert_eq!(parse_fault(" drop-signaling "), Some(Fault::DropSignaling));
+        assert_eq!(
+            parse_fault("stall-capture 1.5"),
+            Some(Fault::StallCapture(Duration::from_millis(1500)))
+        );
+        assert_eq!(
+            parse_fault("stall-capture 3600"),
+            Some(Fault::StallCapture(MAX_STALL))
+        );
+        assert_eq!(parse_fault("stall-capture"), None);
+        assert_eq!(parse_fault("stall-capture -1"), None);
+        assert_eq!(parse_fault("stall-capture NaN"), None);
+        assert_eq!(parse_fault("encoder-error now"), None);
+        assert_eq!(parse_fault("reboot"), None);
+        assert_eq!(parse_fault(""), None);
+    }
+
+    #[tokio::test]
+    async fn faults_fire_once() {
+        let chaos = Chaos::default();
+        assert!(!chaos.take_encoder_error());
+        assert_eq!(chaos.take_capture_stall(), None);
+
+        chaos.inject(Fault::EncoderError);
+        chaos.inject(Fault::StallCapture(Duration::from_secs(2)));
+        assert!(chaos.take_encoder_error());
+        assert!(!chaos.take_encoder_error());
+        assert_eq!(chaos.take_capture_stall(), Some(Duration::from_secs(2)));
+        assert_eq!(chaos.take_capture_stall(), None);
+
+        // A drop requested between connections waits for the next one
+        chaos.inject(Fault::DropSignaling);
+        tokio::time::timeout(Duration::from_secs(1), chaos.signaling_dropped())
+            .await
+            .expect("pending drop should fire");
+    }
+}
@@ -1,8 +1,18 @@
+use crate::audio::OpusTuning;
+use crate::display::DisplayHandover;
+use crate::encoder::Chroma;
+use crate::sched::parse_cpu_list;
 use anyhow::Context;
+use beam_protocol::{
+    AgentConfig, AudioBackend, ClipboardHistoryConfig, ClipboardPolicy, CpuAffinity, Desktop,
+    DisplayMode, EncoderTuning, IdlePolicy, MAX_DISPLAY_MODES, OPUS_FRAME_MS, RealtimeConfig,
+};
+use std::path::PathBuf;
 use uuid::Uuid;
 
 pub(crate) const DEFAULT_BITRATE: u32 = 50_000; // 50 Mbps -- LAN default
 pub(crate) const DEFAULT_FRAMERATE: u32 = 120; // 120fps
+pub(crate) const DEFAULT_AUDIO_BITRATE: u32 = 128; // kbps, Opus
 
 pub(crate) struct Args {
     pub display: String,
@@ -14,9 +24,65 @@ pub(crate) struct Args {
     pub height: u32,
     pub framerate: u32,
     pub bitrate: u32,
+    /// Bandwidth cap the bitrate never exceeds (0 = none)
+    pub max_bitrate: u32,
+    /// Bitrate Sharp and Lossless may not stay over (0 = none)
+    pub quality_ceiling: u32,
     pub encoder: Option<String>,
     pub max_width: u32,
     pub max_height: u32,
+    pub scale: f64,
+    pub chroma: Chroma,
+    pub tuning: EncoderTuning,
+    pub audio_bitrate: u32,
+    pub audio_channels: u16,
+    pub audio_tuning: OpusTuning,
+    /// Sound server started with a virtual display
+    pub audio_backend: AudioBackend,
+    pub forward_urls: bool,
+    /// Keep the desktop's settings in the home directory across sessions
+    pub persist_desktop: bool,
+    /// Desktop environment to start on the virtual display
+    pub desktop: Desktop,
+    /// Started again in place after the X server died (see
+    /// `restart_args`), so the browser is told the display is back
+    pub display_restarted: bool,
+    /// The display an agent executing this one on upgrade handed over
+    /// (see `upgrade_args`), to stop on exit as if started here
+    pub adopt_display: Option<DisplayHandover>,
+    /// Keep the display's size whatever the browser window's
+    pub letterbox: bool,
+    pub pace_input: bool,
+    pub idle: IdlePolicy,
+    pub stage_metrics_file: Option<PathBuf>,
+    pub realtime: RealtimeConfig,
+    pub cpu_affinity: CpuAffinity,
+    pub max_rss_mb: u32,
+    /// Work on video that stops reaching the browser (see
+    /// `watchdog::RecoveryLadder`)
+    pub freeze_recovery: bool,
+    pub sandbox: bool,
+    pub clipboard: ClipboardPolicy,
+    pub clipboard_history: ClipboardHistoryConfig,
+    pub primary_selection: bool,
+    /// Seconds between admin panel thumbnails (0 = none)
+    pub thumbnail_secs: u64,
+    /// Preferred modes of the virtual display, added to it at startup
+    pub display_modes: Vec<DisplayMode>,
+    /// Set to attach to someone's physical display for remote assistance
+    pub assist: Option<AssistArgs>,
+}
+
+/// Remote assistance: who is asking and what the person at the display
+/// agrees to.
+#[derive(Debug, Clone)]
+pub(crate) struct AssistArgs {
+    /// Name shown in the consent prompt and the on-screen banner
+    pub operator: String,
+    /// Seconds the consent prompt waits for an answer
+    pub consent_secs: u64,
+    /// Whether the operator's keyboard and mouse start out enabled
+    pub control: bool,
 }
 
 pub(crate) fn parse_args() -> anyhow::Result<Args> {
@@ -29,9 +95,40 @@ pub(crate) fn parse_args() -> anyhow::Result<Args> {
     let mut height: u32 = 1080;
     let mut framerate: u32 = DEFAULT_FRAMERATE;
     let mut bitrate: u32 = DEFAULT_BITRATE;
+    let mut max_bitrate: u32 = 0;
+    let mut quality_ceiling: u32 = 0;
+    let mut thumbnail_secs: u64 = 0;
+    let mut display_modes = Vec::new();
     let mut encoder: Option<String> = None;
     let mut max_width: u32 = 3840;
     let mut max_height: u32 = 2160;
+    let mut scale: f64 = 1.0;
+    let mut chroma = Chroma::Yuv420;
+    let mut tuning = EncoderTuning::default();
+    let mut audio_bitrate: u32 = DEFAULT_AUDIO_BITRATE;
+    let mut audio_channels: u16 = 2;
+    let mut audio_tuning = OpusTuning::default();
+    let mut audio_backend = AudioBackend::default();
+    let mut forward_urls = false;
+    let mut persist_desktop = false;
+    let mut desktop = Desktop::default();
+    let mut display_restarted = false;
+    let mut adopt_display = None;
+    let mut letterbox = false;
+    let mut pace_input = false;
+    let mut idle = IdlePolicy::default();
+    let mut stage_metrics_file = None;
+    let mut realtime = RealtimeConfig::default();
+    let mut cpu_affinity = CpuAffinity::default();
+    let mut max_rss_mb = AgentConfig::default().max_rss_mb;
+    let mut freeze_recovery = false;
+    let mut sandbox = false;
+    let mut clipboard = ClipboardPolicy::default();
+    let mut clipboard_history = ClipboardHistoryConfig::default();
+    let mut primary_selection = false;
+    let mut assist_operator: Option<String> = None;
+    let mut assist_consent_secs: u64 = 30;
+    let mut assist_control = true;
 
     let args: Vec<String> = std::env::args().collect();
     let mut i = 1;
@@ -63,11 +160,119 @@ pub(crate) fn parse_args() -> anyhow::Result<Args> {
                 println!(
                     "    --bitrate <KBPS>             Initial video bitrate [default: 100000]"
                 );
+                println!(
+                    "    --max-bitrate <KBPS>         Bandwidth cap on the video bitrate [default: 0 = none]"
+                );
+                println!(
+                    "    --quality-ceiling <KBPS>     Bitrate over which sharp/lossless fall back [default: 0 = none]"
+                );
                 println!(
                     "    --encoder <NAME>             Force encoder (nvh264enc, vah264enc, x264enc)"
                 );
                 println!("    --max-width <PIXELS>         Maximum resize width [default: 3840]");
                 println!("    --max-height <PIXELS>        Maximum resize height [default: 2160]");
+                println!(
+                    "    --scale <FACTOR>             Initial desktop HiDPI scale [default: 1.0]"
+                );
+                println!("    --chroma <420|444>           Chroma subsampling [default: 420]");
+                println!("    --preset <NAME>              Encoder speed/quality preset");
+                println!("    --tune <NAME>                Encoder tuning (nvh264enc/x264enc)");
+                println!("    --rc-mode <MODE>             Encoder rate control mode");
+                println!("    --gop-size <FRAMES>          Keyframe interval");
+                println!("    --lookahead <FRAMES>         Rate-control lookahead [default: 0]");
+                println!(
+                    "    --intra-refresh              Periodic intra refresh instead of IDR frames"
+                );
+                println!("    --audio-bitrate <KBPS>       Opus bitrate [default: 128]");
+                println!("    --audio-channels <1|2>       Captured audio channels [default: 2]");
+                println!("    --audio-frame-ms <10|20|40>  Opus frame length [default: 20]");
+                println!("    --audio-fec                  Opus inband forward error correction");
+                println!("    --audio-dtx                  Opus discontinuous transmission");
+                println!(
+                    "    --audio-backend <NAME>       auto, pulseaudio or pipewire [default: auto]"
+                );
+                println!(
+                    "    --forward-urls               Open session http(s) links in the client browser"
+                );
+                println!(
+                    "    --persist-desktop            Keep desktop settings in ~/.config/beam across sessions"
+                );
+                println!(
+                    "    --desktop <NAME|COMMAND>     xfce, kde, gnome, i3, openbox or a command [default: auto]"
+                );
+                println!(
+                    "    --letterbox                  Keep the display's size when the browser window changes"
+                );
+                println!(
+                    "    --pace-input                 Replay input with the browser's event spacing"
+                );
+                println!(
+                    "    --thumbnail-secs <SECS>      Send a screenshot for the admin panel this often [default: 0 = never]"
+                );
+                println!("    --display-mode <WxH[@HZ]>    Preferred display mode (repeatable)");
+                println!(
+                    "    --idle-throttle-after <SECS> Drop to the idle framerate after this long [default: 300]"
+                );
+                println!("    --idle-throttle-framerate <FPS> Framerate while idle [default: 5]");
+                println!("    --idle-lock-after <SECS>     Blank and lock the session (0 = never)");
+                println!("    --idle-disconnect-after <SECS> Disconnect the browser (0 = never)");
+                println!("    --idle-destroy-after <SECS>  End the session (0 = never)");
+                println!(
+                    "    --stage-metrics-file <PATH>  Write pipeline stage histograms as a Prometheus textfile"
+                );
+                println!(
+                    "    --realtime-policy <fifo|rr|off> Scheduler class for the priorities below [default: fifo]"
+                );
+                println!(
+                    "    --capture-priority <1-99>    Capture thread real-time priority, 0 = normal [default: 50]"
+                );
+                println!(
+                    "    --audio-priority <1-99>      Audio thread real-time priority [default: 0]"
+                );
+                println!(
+                    "    --worker-priority <1-99>     Tokio thread real-time priority [default: 0]"
+                );
+                println!(
+                    "    --capture-cpus <LIST>        Pin the capture thread, e.g. 2,3 or 2-5"
+                );
+                println!("    --audio-cpus <LIST>          Pin the audio thread");
+                println!("    --worker-cpus <LIST>         Pin the tokio threads");
+                println!(
+                    "    --max-rss-mb <MIB>           Exit for a restart past this memory use, 0 = no limit [default: 4096]"
+                );
+                println!(
+                    "    --freeze-recovery            Recover video that stops reaching the browser: keyframe, new encoder, reconnect"
+                );
+                println!(
+                    "    --clipboard <POLICY>         bidirectional, to-remote, to-local or off [default: bidirectional]"
+                );
+                println!(
+                    "    --primary-selection          Also sync the PRIMARY selection (middle-click paste)"
+                );
+                println!(
+                    "    --clipboard-history <N>      Recent clipboard texts kept for re-pasting, 0 = none [default: 20]"
+                );
+                println!(
+                    "    --clipboard-history-entry-bytes <BYTES>  Longest text kept [default: 262144]"
+                );
+                println!(
+                    "    --clipboard-history-total-bytes <BYTES>  Bytes kept across entries [default: 1048576]"
+                );
+                println!(
+                    "    --sandbox                    Drop capabilities and filter syscalls once the session is up"
+                );
+                println!(
+                    "    --assist-operator <NAME>     Attach to a physical display for remote assistance, asking its user first"
+                );
+                println!(
+                    "    --assist-consent-secs <N>    How long the consent prompt waits [default: 30]"
+                );
+                println!(
+                    "    --assist-view-only           Start with the operator's keyboard and mouse paused"
+                );
+                println!(
+                    "    --open-url <URL>             Hand a URL to the running agent (session link handler)"
+                );
                 println!("    -V, --version                Print version and exit");
                 println!("    -h, --help                   Print this help and exit");
                 std::process::exit(0);
@@ -130,6 +335,22 @@ pub(crate) fn parse_args() -> anyhow::Result<Args> {
                     .parse()
                     .context("Invalid --bitrate value")?;
             }
+            "--max-bitrate" => {
+                i += 1;
+                max_bitrate = args
+                    .get(i)
+                    .context("Missing --max-bitrate value")?
+                    .parse()
+                    .context("Invalid --max-bitrate value")?;
+            }
+            "--quality-ceiling" => {
+                i += 1;
+                quality_ceiling = args
+                    .get(i)
+                    .context("Missing --quality-ceiling value")?
+                    .parse()
+                    .context("Invalid --quality-ceiling value")?;
+            }
             "--encoder" => {
                 i += 1;
                 encoder = Some(args.get(i).context("Missing --encoder value")?.clone());
@@ -150,11 +371,320 @@ pub(crate) fn parse_args() -> anyhow::Result<Args> {
                     .parse()
                     .context("Invalid --max-height value")?;
             }
+            "--scale" => {
+                i += 1;
+                scale = args
+                    .get(i)
+                    .context("Missing --scale value")?
+                    .parse()
+                    .context("Invalid --scale value")?;
+            }
+            "--chroma" => {
+                i += 1;
+                let value = args.get(i).context("Missing --chroma value")?;
+                chroma = Chroma::from_config(value)
+                    .with_context(|| format!("Invalid --chroma value: {value} (use 420 or 444)"))?;
+            }
+            "--preset" => {
+                i += 1;
+                tuning.preset = Some(args.get(i).context("Missing --preset value")?.clone());
+            }
+            "--tune" => {
+                i += 1;
+                tuning.tune = Some(args.get(i).context("Missing --tune value")?.clone());
+            }
+            "--rc-mode" => {
+                i += 1;
+                tuning.rc_mode = Some(args.get(i).context("Missing --rc-mode value")?.clone());
+            }
+            "--gop-size" => {
+                i += 1;
+                tuning.gop_size = Some(
+                    args.get(i)
+                        .context("Missing --gop-size value")?
+                        .parse()
+                        .context("Invalid --gop-size value")?,
+                );
+            }
+            "--lookahead" => {
+                i += 1;
+                tuning.lookahead = Some(
+                    args.get(i)
+                        .context("Missing --lookahead value")?
+                        .parse()
+                        .context("Invalid --lookahead value")?,
+                );
+            }
+            "--intra-refresh" => {
+                tuning.intra_refresh = true;
+            }
+            "--audio-bitrate" => {
+                i += 1;
+                audio_bitrate = args
+                    .get(i)
+                    .context("Missing --audio-bitrate value")?
+                    .parse()
+                    .context("Invalid --audio-bitrate value")?;
+            }
+            "--audio-channels" => {
+                i += 1;
+                audio_channels = args
+                    .get(i)
+                    .context("Missing --audio-channels value")?
+                    .parse()
+                    .context("Invalid --audio-channels value")?;
+            }
+            "--audio-frame-ms" => {
+                i += 1;
+                let value = args.get(i).context("Missing --audio-frame-ms value")?;
+                audio_tuning.frame_ms = value
+                    .parse()
+                    .ok()
+                    .filter(|ms| OPUS_FRAME_MS.contains(ms))
+                    .with_context(|| {
+                        format!("Invalid --audio-frame-ms value: {value} (use 10, 20 or 40)")
+                    })?;
+            }
+            "--audio-fec" => {
+                audio_tuning.fec = true;
+            }
+            "--audio-dtx" => {
+                audio_tuning.dtx = true;
+            }
+            "--audio-backend" => {
+                i += 1;
+                let value = args.get(i).context("Missing --audio-backend value")?;
+                audio_backend = AudioBackend::from_name(value).with_context(|| {
+                    format!(
+                        "Invalid --audio-backend value: {value} (use auto, pulseaudio or pipewire)"
+                    )
+                })?;
+            }
+            "--forward-urls" => {
+                forward_urls = true;
+            }
+            "--persist-desktop" => {
+                persist_desktop = true;
+            }
+            "--desktop" => {
+                i += 1;
+                let value = args.get(i).context("Missing --desktop value")?;
+                desktop = Desktop::parse(value);
+            }
+            "--letterbox" => {
+                letterbox = true;
+            }
+            "--display-restarted" => {
+                display_restarted = true;
+            }
+            "--adopt-display" => {
+                i += 1;
+                let value = args.get(i).context("Missing --adopt-display value")?;
+                adopt_display =
+                    Some(serde_json::from_str(value).context("Invalid --adopt-display value")?);
+            }
+            "--pace-input" => {
+                pace_input = true;
+            }
+            "--thumbnail-secs" => {
+                i += 1;
+                thumbnail_secs = args
+                    .get(i)
+                    .context("Missing --thumbnail-secs value")?
+                    .parse()
+                    .context("Invalid --thumbnail-secs value")?;
+            }
+            "--display-mode" => {
+                i += 1;
+                let value = args.get(i).context("Missing --display-mode value")?;
+                let mode = DisplayMode::parse(value)
+                    .with_context(|| format!("Invalid --display-mode value: {value}"))?;
+                anyhow::ensure!(
+                    display_modes.len() < MAX_DISPLAY_MODES,
+                    "At most {MAX_DISPLAY_MODES} --display-mode values"
+                );
+                display_modes.push(mode);
+            }
+            "--idle-throttle-after" => {
+                i += 1;
+                idle.throttle_after = args
+                    .get(i)
+                    .context("Missing --idle-throttle-after value")?
+                    .parse()
+                    .context("Invalid --idle-throttle-after value")?;
+            }
+            "--idle-throttle-framerate" => {
+                i += 1;
+                idle.throttle_framerate = args
+                    .get(i)
+                    .context("Missing --idle-throttle-framerate value")?
+                    .parse()
+                    .context("Invalid --idle-throttle-framerate value")?;
+            }
+            "--idle-lock-after" => {
+                i += 1;
+                idle.lock_after = args
+                    .get(i)
+                    .context("Missing --idle-lock-after value")?
+                    .parse()
+                    .context("Invalid --idle-lock-after value")?;
+            }
+            "--idle-disconnect-after" => {
+                i += 1;
+                idle.disconnect_after = args
+                    .get(i)
+                    .context("Missing --idle-disconnect-after value")?
+                    .parse()
+                    .context("Invalid --idle-disconnect-after value")?;
+            }
+            "--idle-destroy-after" => {
+                i += 1;
+                idle.destroy_after = args
+                    .get(i)
+                    .context("Missing --idle-destroy-after value")?
+                    .parse()
+                    .context("Invalid --idle-destroy-after value")?;
+            }
+            "--stage-metrics-file" => {
+                i += 1;
+                stage_metrics_file = Some(PathBuf::from(
+                    args.get(i).context("Missing --stage-metrics-file value")?,
+                ));
+            }
+            "--realtime-policy" => {
+                i += 1;
+                let value = args.get(i).context("Missing --realtime-policy value")?;
+                anyhow::ensure!(
+                    matches!(value.as_str(), "fifo" | "rr" | "off"),
+                    "Invalid --realtime-policy value: {value} (use fifo, rr or off)"
+                );
+                realtime.policy = value.clone();
+            }
+            "--capture-priority" => {
+                i += 1;
+                realtime.capture = parse_priority(args.get(i), "--capture-priority")?;
+            }
+            "--audio-priority" => {
+                i += 1;
+                realtime.audio = parse_priority(args.get(i), "--audio-priority")?;
+            }
+            "--worker-priority" => {
+                i += 1;
+                realtime.workers = parse_priority(args.get(i), "--worker-priority")?;
+            }
+            "--capture-cpus" => {
+                i += 1;
+                cpu_affinity.capture =
+                    parse_cpu_list(args.get(i).context("Missing --capture-cpus value")?)
+                        .context("Invalid --capture-cpus value")?;
+            }
+            "--audio-cpus" => {
+                i += 1;
+                cpu_affinity.audio =
+                    parse_cpu_list(args.get(i).context("Missing --audio-cpus value")?)
+                        .context("Invalid --audio-cpus value")?;
+            }
+            "--worker-cpus" => {
+                i += 1;
+                cpu_affinity.workers =
+                    parse_cpu_list(args.get(i).context("Missing --worker-cpus value")?)
+                        .context("Invalid --worker-cpus value")?;
+            }
+            "--max-rss-mb" => {
+                i += 1;
+                max_rss_mb = args
+                    .get(i)
+                    .context("Missing --max-rss-mb value")?
+                    .parse()
+                    .context("Invalid --max-rss-mb value")?;
+            }
+            "--freeze-recovery" => {
+                freeze_recovery = true;
+            }
+            "--sandbox" => {
+                sandbox = true;
+            }
+            "--clipboard" => {
+                i += 1;
+                let value = args.get(i).context("Missing --clipboard value")?;
+                clipboard = ClipboardPolicy::from_name(value).with_context(|| {
+                    format!(
+                        "Invalid --clipboard value: {value} (use {})",
+                        ClipboardPolicy::NAMES.join(", ")
+                    )
+                })?;
+            }
+            "--primary-selection" => {
+                primary_selection = true;
+            }
+            "--clipboard-history" => {
+                i += 1;
+                clipboard_history.entries = args
+                    .get(i)
+                    .context("Missing --clipboard-history value")?
+                    .parse()
+                    .context("Invalid --clipboard-history value")?;
+            }
+            "--clipboard-history-entry-bytes" => {
+                i += 1;
+                clipboard_history.max_entry_bytes = args
+                    .get(i)
+                    .context("Missing --clipboard-history-entry-bytes value")?
+                    .parse()
+                    .context("Invalid --clipboard-history-entry-bytes value")?;
+            }
+            "--clipboard-history-total-bytes" => {
+                i += 1;
+                clipboard_history.max_total_bytes = args
+                    .get(i)
+                    .context("Missing --clipboard-history-total-bytes value")?
+                    .parse()
+                    .context("Invalid --clipboard-history-total-bytes value")?;
+            }
+            "--assist-operator" => {
+                i += 1;
+                assist_operator = Some(
+                    args.get(i)
+                        .context("Missing --assist-operator value")?
+                        .clone(),
+                );
+            }
+            "--assist-consent-secs" => {
+                i += 1;
+                assist_consent_secs = args
+                    .get(i)
+                    .context("Missing --assist-consent-secs value")?
+                    .parse()
+                    .context("Invalid --assist-consent-secs value")?;
+            }
+            "--assist-view-only" => {
+                assist_control = false;
+            }
+            "--open-url" => {
+                // Link handler shim inside the session, not an agent run
+                i += 1;
+                let url = args.get(i).context("Missing --open-url value")?;
+                if let Err(e) = crate::url_open::forward_to_agent(url) {
+                    eprintln!("beam-agent: {e:#}");
+                    std::process::exit(1);
+                }
+                std::process::exit(0);
+            }
             other => anyhow::bail!("Unknown argument: {other}"),
         }
         i += 1;
     }
 
+    // Locking the screen would lock the person being helped out of it
+    let assist = assist_operator.map(|operator| AssistArgs {
+        operator,
+        consent_secs: assist_consent_secs,
+        control: assist_control,
+    });
+    if assist.is_some() {
+        idle.lock_after = 0;
+    }
+
     // Prefer env var for agent token (CLI args are visible in /proc)
     if agent_token.is_none() {
         agent_token = std::env::var("BEAM_AGENT_TOKEN").ok();
@@ -170,8 +700,169 @@ pub(crate) fn parse_args() -> anyhow::Result<Args> {
         height,
         framerate,
         bitrate,
+        max_bitrate,
+        quality_ceiling,
         encoder,
         max_width,
         max_height,
+        scale,
+        chroma,
+        tuning,
+        audio_bitrate,
+        audio_channels,
+        audio_tuning,
+        audio_backend,
+        forward_urls,
+        persist_desktop,
+        desktop,
+        display_restarted,
+        adopt_display,
+        letterbox,
+        pace_input,
+        idle,
+        stage_metrics_file,
+        realtime,
+        cpu_affinity,
+        max_rss_mb,
+        freeze_recovery,
+        sandbox,
+        clipboard,
+        clipboard_history,
+        primary_selection,
+        thumbnail_secs,
+        display_modes,
+        assist,
     })
 }
+
+fn parse_priority(value: Option<&String>, flag: &str) -> anyhow::Result<u8> {
+    let priority: u8 = value
+        .with_context(|| format!("Missing {flag} value"))?
+        .parse()
+        .with_context(|| format!("Invalid {flag} value"))?;
+    anyhow::ensure!(
+        priority <= 99,
+        "Invalid {flag} value: {priority} (use 0-99)"
+    );
+    Ok(priority)
+}
+
+/// Arguments for starting the agent again on a fresh display, from the
+/// ones it was started with: the display's current size in place of the
+/// initial one, and `--display-restarted`.
+pub(crate) fn restart_args(
+    args: impl IntoIterator<Item = String>,
+    width: u32,
+    height: u32,
+) -> Vec<String> {
+    let mut restarted = respawn_args(args, width, height);
+    restarted.push("--display-restarted".to_string());
+    restarted
+}
+
+/// Arguments for the agent binary an upgrade executes, from the ones this
+/// one was started with: the display's current size in place of the
+/// initial one, and the display to adopt (JSON), if this agent started it.
+pub(crate) fn upgrade_args(
+    args: impl IntoIterator<Item = String>,
+    width: u32,
+    height: u32,
+    handover: Option<&str>,
+) -> Vec<String> {
+    let mut upgraded = respawn_args(args, width, height);
+    if let Some(handover) = handover {
+        upgraded.extend(["--adopt-display".to_string(), handover.to_string()]);
+    }
+    upgraded
+}
+
+/// The arguments without those describing how this image came to be,
+/// and the display at `width` x `height`
+fn respawn_args(args: impl IntoIterator<Item = String>, width: u32, height: u32) -> Vec<String> {
+    let mut respawned = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" | "--height" | "--adopt-display" => {
+                args.next();
+            }
+            "--display-restarted" => {}
+            _ => respawned.push(arg),
+        }
+    }
+    respawned.extend([
+        "--width".to_string(),
+        width.to_string(),
+        "--height".to_string(),
+        height.to_string(),
+    ]);
+    respawned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_keep_the_session_at_its_current_size() {
+        let args = [
+            "--display",
+            ":12",
+            "--width",
+            "1280",
+            "--height",
+            "720",
+            "--session-id",
+            "abc",
+            "--display-restarted",
+        ]
+        .map(String::from);
+        assert_eq!(
+            restart_args(args, 2560, 1440),
+            [
+                "--display",
+                ":12",
+                "--session-id",
+                "abc",
+                "--width",
+                "2560",
+                "--height",
+                "1440",
+                "--display-restarted",
+            ]
+        );
+    }
+
+    #[test]
+    fn upgrades_adopt_the_display_once() {
+        let args = [
+            "--session-id",
+            "abc",
+            "--adopt-display",
+            r#"{"old":true}"#,
+            "--width",
+            "1280",
+            "--height",
+            "720",
+        ]
+        .map(String::from);
+        assert_eq!(
+            upgrade_args(args.clone(), 1920, 1080, Some(r#"{"new":true}"#)),
+            [
+                "--session-id",
+                "abc",
+                "--width",
+                "1920",
+                "--height",
+                "1080",
+                "--adopt-display",
+                r#"{"new":true}"#,
+            ]
+        );
+        // A display the agent didn't start is left to whoever did
+        assert_eq!(
+            upgrade_args(args, 1920, 1080, None),
+            ["--session-id", "abc", "--width", "1920", "--height", "1080"]
+        );
+    }
+}
@@ -26,7 +26,7 @@ impl ClipboardBridge {
 
     /// Strip terminal control characters that could execute commands
     /// when pasted into a terminal emulator. Keep \t (0x09), \n (0x0A), \r (0x0D).
-    fn sanitize(text: &str) -> String {
+    pub(crate) fn sanitize(text: &str) -> String {
         text.chars()
             .filter(|&c| c == '\t' || c == '\n' || c == '\r' || (c >= ' ' && c != '\x7f'))
             .collect()
@@ -72,14 +72,23 @@ impl ClipboardBridge {
     }
 
     pub fn get_text(&self) -> anyhow::Result<Option<String>> {
+        self.get_selection("clipboard")
+    }
+
+    /// Read the X11 PRIMARY selection (the most recently selected text).
+    pub fn get_primary_text(&self) -> anyhow::Result<Option<String>> {
+        self.get_selection("primary")
+    }
+
+    fn get_selection(&self, selection: &str) -> anyhow::Result<Option<String>> {
         let output = Command::new("xclip")
-            .args(["-selection", "clipboard", "-o"])
+            .args(["-selection", selection, "-o"])
             .env("DISPLAY", &self.x_display)
             .output()
             .context("Failed to run xclip -o")?;
 
         if !output.status.success() {
-            // No clipboard content or xclip error - not fatal
+            // No selection content or xclip error - not fatal
             return Ok(None);
         }
 
@@ -0,0 +1,168 @@
+//! Recent clipboard texts, so an earlier copy can be pasted again after the
+//! session's clipboard was overwritten. Holds what crossed the clipboard
+//! bridge in either direction, bounded by the server's
+//! `[session.clipboard_history]` limits.
+
+use beam_protocol::{ClipboardHistory, ClipboardHistoryConfig, ClipboardHistoryEntry};
+use std::collections::VecDeque;
+
+/// Replies past this lose their oldest entries. JSON escaping can grow
+/// text beyond `max_total_bytes`, and a WebSocket message tops out at 2 MiB.
+const MAX_REPLY_BYTES: usize = 1_572_864;
+
+pub(crate) struct History {
+    limits: ClipboardHistoryConfig,
+    /// Newest first
+    entries: VecDeque<ClipboardHistoryEntry>,
+    total_bytes: usize,
+    next_id: u64,
+}
+
+impl History {
+    pub(crate) fn new(limits: ClipboardHistoryConfig) -> Self {
+        Self {
+            limits,
+            entries: VecDeque::new(),
+            total_bytes: 0,
+            next_id: 1,
+        }
+    }
+
+    /// Keeps nothing; for sessions whose policy doesn't let text out.
+    pub(crate) fn disabled() -> Self {
+        Self::new(ClipboardHistoryConfig {
+            entries: 0,
+            ..ClipboardHistoryConfig::default()
+        })
+    }
+
+    /// Remember `text` as the newest entry. A text already in the history
+    /// moves to the top instead of being stored twice.
+    pub(crate) fn record(&mut self, text: &str, at: u64) {
+        let max_bytes = self.limits.max_entry_bytes.min(self.limits.max_total_bytes) as usize;
+        if self.limits.entries == 0 || text.is_empty() || text.len() > max_bytes {
+            return;
+        }
+        if let Some(pos) = self.entries.iter().position(|e| e.text == text)
+            && let Some(mut entry) = self.entries.remove(pos)
+        {
+            entry.at = at;
+            self.entries.push_front(entry);
+            return;
+        }
+        self.entries.push_front(ClipboardHistoryEntry {
+            id: self.next_id,
+            text: text.to_owned(),
+            at,
+        });
+        self.next_id += 1;
+        self.total_bytes += text.len();
+        while self.entries.len() > self.limits.entries as usize
+            || self.total_bytes > self.limits.max_total_bytes as usize
+        {
+            let Some(oldest) = self.entries.pop_back() else {
+                break;
+            };
+            self.total_bytes -= oldest.text.len();
+        }
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| e.text.as_str())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.total_bytes = 0;
+    }
+
+    /// The `ClipboardHistory` reply, trimmed to fit one message.
+    pub(crate) fn message(&self) -> Option<String> {
+        let mut reply = ClipboardHistory {
+            entries: self.entries.iter().cloned().collect(),
+        };
+        loop {
+            let json = serde_json::to_string(&reply).ok()?;
+            if json.len() <= MAX_REPLY_BYTES || reply.entries.pop().is_none() {
+                return Some(json);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(entries: u32, max_entry_bytes: u32, max_total_bytes: u32) -> ClipboardHistoryConfig {
+        ClipboardHistoryConfig {
+            entries,
+            max_entry_bytes,
+            max_total_bytes,
+        }
+    }
+
+    fn texts(history: &History) -> Vec<&str> {
+        history.entries.iter().map(|e| e.text.as_str()).collect()
+    }
+
+    #[test]
+    fn newest_first_without_duplicates() {
+        let mut history = History::new(limits(3, 100, 1000));
+        history.record("one", 1);
+        history.record("two", 2);
+        history.record("one", 3);
+        assert_eq!(texts(&history), ["one", "two"]);
+        // The moved entry keeps its id for a browser holding the old list
+        assert_eq!(history.get(1), Some("one"));
+        assert_eq!(history.entries[0].at, 3);
+
+        history.record("three", 4);
+        history.record("four", 5);
+        assert_eq!(texts(&history), ["four", "three", "one"]);
+        assert_eq!(history.get(2), None);
+    }
+
+    #[test]
+    fn byte_limits_bound_entries_and_total() {
+        let mut history = History::new(limits(10, 4, 8));
+        history.record("toolong", 1);
+        assert!(history.entries.is_empty());
+
+        history.record("aaaa", 2);
+        history.record("bbbb", 3);
+        history.record("cc", 4);
+        // 10 bytes would exceed the total; the oldest goes
+        assert_eq!(texts(&history), ["cc", "bbbb"]);
+        assert_eq!(history.total_bytes, 6);
+
+        history.clear();
+        assert_eq!(history.total_bytes, 0);
+        assert_eq!(
+            history.message().as_deref(),
+            Some(r#"{"t":"chl","entries":[]}"#)
+        );
+    }
+
+    #[test]
+    fn disabled_history_keeps_nothing() {
+        let mut history = History::disabled();
+        history.record("secret", 1);
+        assert!(history.entries.is_empty());
+    }
+
+    #[test]
+    fn reply_drops_oldest_to_fit_a_message() {
+        let big = 1_048_576;
+        let mut history = History::new(limits(10, big, big));
+        // Control characters escape to six bytes each
+        history.record(&"\u{1}".repeat(300_000), 1);
+        history.record("recent", 2);
+        let reply: ClipboardHistory = serde_json::from_str(&history.message().unwrap()).unwrap();
+        assert_eq!(reply.entries.len(), 1);
+        assert_eq!(reply.entries[0].text, "recent");
+    }
+}
@@ -1,43 +1,206 @@
 use crate::clipboard::ClipboardBridge;
+use crate::clipboard_history::History;
 use crate::signaling::WsSender;
+use beam_protocol::{AgentMessage, ClipboardHistoryConfig, ClipboardPolicy, MAX_CLIPBOARD_BYTES};
 
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::Message;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
+
+/// Work for the clipboard sync task, queued from the input callback.
+pub(crate) enum ClipboardRequest {
+    /// Ctrl+C/X was pressed: read the X11 clipboard and send it to the browser
+    ReadRemote,
+    /// The browser set the session clipboard to this text
+    FromBrowser(String),
+    /// A selection gesture ended: send PRIMARY to the browser if it changed
+    ReadPrimary,
+    /// The browser set PRIMARY to this text for a middle-click paste
+    FromBrowserPrimary(String),
+    ListHistory,
+    PasteHistory(u64),
+    ClearHistory,
+}
 
 /// Clipboard sync: after Ctrl+C/X, read X11 clipboard and send to browser via WS text.
+/// Nothing is read or sent, and no history kept, unless `policy` allows
+/// copying out of the session.
 pub(crate) async fn run_clipboard_sync(
-    clipboard_read_rx: &mut mpsc::Receiver<()>,
+    clipboard_rx: &mut mpsc::Receiver<ClipboardRequest>,
     clipboard: &Arc<Mutex<ClipboardBridge>>,
+    policy: ClipboardPolicy,
+    history_limits: ClipboardHistoryConfig,
     ws_tx: &WsSender,
 ) {
-    while let Some(()) = clipboard_read_rx.recv().await {
-        // Brief delay so the X11 app has time to write to the clipboard
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        let text = {
-            let cb = clipboard.lock().unwrap_or_else(|e| e.into_inner());
-            cb.get_text()
-        };
-        match text {
-            Ok(Some(ref text)) if !text.is_empty() => {
-                const MAX_CLIPBOARD_BYTES: usize = 1_048_576;
-                if text.len() <= MAX_CLIPBOARD_BYTES {
-                    let msg = serde_json::json!({ "t": "c", "text": text }).to_string();
-                    if let Err(e) = ws_tx.send(Message::Text(msg.into())).await {
-                        warn!("Failed to send clipboard to browser: {e}");
-                    } else {
-                        info!(len = text.len(), "Clipboard text sent to browser");
-                    }
+    let mut history = if policy.allows_to_local() {
+        History::new(history_limits)
+    } else {
+        History::disabled()
+    };
+    let mut primary = PrimaryEcho::default();
+    while let Some(request) = clipboard_rx.recv().await {
+        match request {
+            ClipboardRequest::ReadRemote => {
+                if !policy.allows_to_local() {
+                    continue;
+                }
+                if let Some(text) = read_and_send(clipboard, ws_tx).await {
+                    history.record(&text, now_ms());
                 }
             }
-            Ok(_) => {
-                info!("Clipboard read returned empty/none");
+            ClipboardRequest::FromBrowser(text) => history.record(&text, now_ms()),
+            ClipboardRequest::ReadPrimary => {
+                if policy.allows_to_local() {
+                    send_primary(clipboard, &mut primary, ws_tx).await;
+                }
+            }
+            ClipboardRequest::FromBrowserPrimary(text) => {
+                primary.seen(ClipboardBridge::sanitize(&text));
+            }
+            ClipboardRequest::ListHistory => send_history(&history, ws_tx).await,
+            ClipboardRequest::PasteHistory(id) => {
+                let Some(text) = history.get(id).map(str::to_owned) else {
+                    debug!(id, "Clipboard history entry no longer exists");
+                    send_history(&history, ws_tx).await;
+                    continue;
+                };
+                let result = {
+                    let cb = clipboard.lock().unwrap_or_else(|e| e.into_inner());
+                    cb.set_text(&text)
+                };
+                match result {
+                    Ok(()) => history.record(&text, now_ms()),
+                    Err(e) => warn!("Failed to paste clipboard history entry: {e:#}"),
+                }
+                send_history(&history, ws_tx).await;
+            }
+            ClipboardRequest::ClearHistory => {
+                history.clear();
+                send_history(&history, ws_tx).await;
+            }
+        }
+    }
+}
+
+/// Read the X11 clipboard and send it to the browser. Returns what was sent.
+async fn read_and_send(
+    clipboard: &Arc<Mutex<ClipboardBridge>>,
+    ws_tx: &WsSender,
+) -> Option<String> {
+    // Brief delay so the X11 app has time to write to the clipboard
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let text = {
+        let cb = clipboard.lock().unwrap_or_else(|e| e.into_inner());
+        cb.get_text()
+    };
+    match text {
+        Ok(Some(text)) if !text.is_empty() => {
+            if text.len() > MAX_CLIPBOARD_BYTES {
+                return None;
             }
-            Err(e) => {
-                warn!("Failed to read clipboard: {e:#}");
+            let msg = AgentMessage::Clipboard { text: text.clone() }.to_json();
+            if let Err(e) = ws_tx.send(Message::Text(msg.into())).await {
+                warn!("Failed to send clipboard to browser: {e}");
+                None
+            } else {
+                info!(len = text.len(), "Clipboard text sent to browser");
+                Some(text)
             }
         }
+        Ok(_) => {
+            info!("Clipboard read returned empty/none");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read clipboard: {e:#}");
+            None
+        }
+    }
+}
+
+/// Send PRIMARY to the browser unless it is what the browser last set or
+/// was last sent, so a selection doesn't bounce between the two.
+async fn send_primary(
+    clipboard: &Arc<Mutex<ClipboardBridge>>,
+    primary: &mut PrimaryEcho,
+    ws_tx: &WsSender,
+) {
+    // Same delay as for Ctrl+C: the app claims PRIMARY after the release
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let text = {
+        let cb = clipboard.lock().unwrap_or_else(|e| e.into_inner());
+        cb.get_primary_text()
+    };
+    let text = match text {
+        Ok(Some(text)) if !text.is_empty() && text.len() <= MAX_CLIPBOARD_BYTES => text,
+        Ok(_) => return,
+        Err(e) => {
+            debug!("Failed to read PRIMARY selection: {e:#}");
+            return;
+        }
+    };
+    if !primary.is_new(&text) {
+        return;
+    }
+    let msg = AgentMessage::ClipboardPrimary { text: text.clone() }.to_json();
+    if let Err(e) = ws_tx.send(Message::Text(msg.into())).await {
+        warn!("Failed to send PRIMARY selection to browser: {e}");
+        return;
+    }
+    debug!(len = text.len(), "PRIMARY selection sent to browser");
+    primary.seen(text);
+}
+
+/// The PRIMARY text both sides already have
+#[derive(Default)]
+struct PrimaryEcho {
+    last: Option<String>,
+}
+
+impl PrimaryEcho {
+    fn seen(&mut self, text: String) {
+        self.last = Some(text);
+    }
+
+    fn is_new(&self, text: &str) -> bool {
+        self.last.as_deref() != Some(text)
+    }
+}
+
+async fn send_history(history: &History, ws_tx: &WsSender) {
+    let Some(msg) = history.message() else {
+        return;
+    };
+    if let Err(e) = ws_tx.send(Message::Text(msg.into())).await {
+        warn!("Failed to send clipboard history to browser: {e}");
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primary_echo_suppresses_round_trips() {
+        let mut primary = PrimaryEcho::default();
+        assert!(primary.is_new("selected"));
+
+        // Set from the browser, then read back after a click in the session
+        primary.seen(ClipboardBridge::sanitize("from\x1b browser"));
+        assert!(!primary.is_new("from browser"));
+
+        // A new selection in the session goes out once
+        assert!(primary.is_new("remote"));
+        primary.seen("remote".to_string());
+        assert!(!primary.is_new("remote"));
     }
 }
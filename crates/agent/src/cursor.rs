@@ -1,3 +1,5 @@
+use base64::Engine;
+use beam_protocol::AgentMessage;
 use std::collections::HashMap;
 use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
@@ -6,14 +8,50 @@ use x11rb::protocol::Event;
 use x11rb::protocol::xfixes;
 use x11rb::rust_connection::RustConnection;
 
+/// Largest cursor bitmap forwarded to the browser. Browsers ignore CSS
+/// cursor images above 128x128, so bigger ones fall back to the CSS name.
+const MAX_CURSOR_IMAGE_SIZE: u16 = 128;
+
+/// Cursor shape as forwarded to the browser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorShape {
+    /// CSS cursor name; also the fallback when `image` can't be displayed
+    pub css: &'static str,
+    /// Actual bitmap, set when the X cursor name has no CSS equivalent
+    pub image: Option<CursorImage>,
+}
+
+/// Cursor bitmap encoded for use in a CSS `url()` cursor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorImage {
+    /// Base64-encoded PNG
+    pub png: String,
+    pub xhot: u16,
+    pub yhot: u16,
+}
+
+impl CursorShape {
+    /// JSON message for the browser (`{"t":"cur",...}`).
+    pub fn to_message(&self) -> String {
+        let image = self.image.as_ref();
+        AgentMessage::Cursor {
+            css: self.css.to_string(),
+            png: image.map(|img| img.png.clone()),
+            hx: image.map(|img| img.xhot),
+            hy: image.map(|img| img.yhot),
+        }
+        .to_json()
+    }
+}
+
 /// Spawn a thread that monitors X11 cursor shape changes via XFixes
-/// and sends CSS cursor names over the returned channel.
+/// and sends cursor shapes over the returned channel.
 ///
 /// Also hides the X11 cursor on the display so it doesn't appear
-/// in the screen capture — the browser renders its own CSS cursor instead.
-pub fn spawn_cursor_monitor(display: &str) -> Option<mpsc::Receiver<String>> {
+/// in the screen capture — the browser renders its own cursor instead.
+pub fn spawn_cursor_monitor(display: &str) -> Option<mpsc::Receiver<CursorShape>> {
     let display = display.to_string();
-    let (tx, rx) = mpsc::channel::<String>(8);
+    let (tx, rx) = mpsc::channel::<CursorShape>(8);
 
     std::thread::Builder::new()
         .name("cursor-monitor".into())
@@ -27,7 +65,7 @@ pub fn spawn_cursor_monitor(display: &str) -> Option<mpsc::Receiver<String>> {
     Some(rx)
 }
 
-fn cursor_monitor_loop(display: &str, tx: mpsc::Sender<String>) -> anyhow::Result<()> {
+fn cursor_monitor_loop(display: &str, tx: mpsc::Sender<CursorShape>) -> anyhow::Result<()> {
     let (conn, screen_num) =
         RustConnection::connect(Some(display)).map_err(|e| anyhow::anyhow!("X11 connect: {e}"))?;
 
@@ -55,7 +93,7 @@ fn cursor_monitor_loop(display: &str, tx: mpsc::Sender<String>) -> anyhow::Resul
     xfixes::select_cursor_input(&conn, root, xfixes::CursorNotifyMask::DISPLAY_CURSOR)?;
     conn.flush()?;
 
-    let mut last_css = String::new();
+    let mut last_shape: Option<CursorShape> = None;
     let mut last_serial: u32 = 0;
 
     // Build name→CSS mapping table
@@ -63,15 +101,10 @@ fn cursor_monitor_loop(display: &str, tx: mpsc::Sender<String>) -> anyhow::Resul
 
     // Send initial cursor state
     if let Ok(reply) = xfixes::get_cursor_image_and_name(&conn)?.reply() {
-        let name = String::from_utf8_lossy(&reply.name).to_string();
-        let css = map
-            .get(name.as_str())
-            .copied()
-            .unwrap_or("default")
-            .to_string();
-        last_css = css.clone();
+        let shape = cursor_shape(&map, &reply);
         last_serial = reply.cursor_serial;
-        let _ = tx.blocking_send(css);
+        last_shape = Some(shape.clone());
+        let _ = tx.blocking_send(shape);
     }
 
     loop {
@@ -84,23 +117,25 @@ fn cursor_monitor_loop(display: &str, tx: mpsc::Sender<String>) -> anyhow::Resul
             }
             last_serial = notify.cursor_serial;
 
-            // Get cursor name
-            let css = match xfixes::get_cursor_image_and_name(&conn)?.reply() {
+            // Get cursor name (and bitmap, for app-specific cursors)
+            let shape = match xfixes::get_cursor_image_and_name(&conn)?.reply() {
                 Ok(reply) => {
-                    let name = String::from_utf8_lossy(&reply.name).to_string();
-                    debug!(cursor_name = %name, "Cursor changed");
-                    map.get(name.as_str())
-                        .copied()
-                        .unwrap_or("default")
-                        .to_string()
+                    debug!(
+                        cursor_name = %String::from_utf8_lossy(&reply.name),
+                        "Cursor changed"
+                    );
+                    cursor_shape(&map, &reply)
                 }
-                Err(_) => "default".to_string(),
+                Err(_) => CursorShape {
+                    css: "default",
+                    image: None,
+                },
             };
 
             // Only send if changed
-            if css != last_css {
-                last_css = css.clone();
-                if tx.blocking_send(css).is_err() {
+            if last_shape.as_ref() != Some(&shape) {
+                last_shape = Some(shape.clone());
+                if tx.blocking_send(shape).is_err() {
                     break; // receiver dropped, agent shutting down
                 }
             }
@@ -110,6 +145,124 @@ fn cursor_monitor_loop(display: &str, tx: mpsc::Sender<String>) -> anyhow::Resul
     Ok(())
 }
 
+/// Resolve a cursor to its CSS name, attaching the bitmap when the name
+/// is unknown (or empty, as with most application-drawn cursors).
+fn cursor_shape(
+    map: &HashMap<&'static str, &'static str>,
+    reply: &xfixes::GetCursorImageAndNameReply,
+) -> CursorShape {
+    let name = String::from_utf8_lossy(&reply.name);
+    if let Some(&css) = map.get(name.as_ref()) {
+        return CursorShape { css, image: None };
+    }
+
+    let image = (reply.width > 0
+        && reply.height > 0
+        && reply.width <= MAX_CURSOR_IMAGE_SIZE
+        && reply.height <= MAX_CURSOR_IMAGE_SIZE)
+        .then(|| {
+            let rgba = argb_to_rgba(&reply.cursor_image);
+            let png = encode_png(reply.width.into(), reply.height.into(), &rgba);
+            CursorImage {
+                png: base64::engine::general_purpose::STANDARD.encode(png),
+                xhot: reply.xhot,
+                yhot: reply.yhot,
+            }
+        });
+
+    CursorShape {
+        css: "default",
+        image,
+    }
+}
+
+/// Convert XFixes premultiplied ARGB pixels to straight-alpha RGBA bytes.
+fn argb_to_rgba(pixels: &[u32]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() * 4);
+    for &argb in pixels {
+        let a = argb >> 24;
+        let unpremultiply = |c: u32| (c * 255).checked_div(a).map_or(0, |v| v.min(255) as u8);
+        out.push(unpremultiply((argb >> 16) & 0xFF));
+        out.push(unpremultiply((argb >> 8) & 0xFF));
+        out.push(unpremultiply(argb & 0xFF));
+        out.push(a as u8);
+    }
+    out
+}
+
+/// Encode RGBA pixels as a PNG using stored (uncompressed) deflate blocks.
+///
+/// Cursors are at most 128x128, so skipping compression keeps this tiny
+/// without pulling in a zlib dependency.
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    // Raw scanlines, each prefixed with filter type 0 (None)
+    let stride = width as usize * 4;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in rgba.chunks(stride).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib stream: header, stored blocks of up to 65535 bytes, adler32
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(u8::from(blocks.peek().is_none()));
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8-bit depth, color type 6 (RGBA), default compression/filter, no interlace
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_png_chunk(&mut png, b"IHDR", &ihdr);
+    write_png_chunk(&mut png, b"IDAT", &zlib);
+    write_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = crc32(0xFFFF_FFFF, kind);
+    crc = crc32(crc, data);
+    out.extend_from_slice(&(!crc).to_be_bytes());
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
 fn build_cursor_map() -> HashMap<&'static str, &'static str> {
     let mut m = HashMap::new();
 
@@ -249,4 +402,59 @@ mod tests {
         let map = build_cursor_map();
         assert_eq!(map.get("some_custom_cursor_xyz"), None);
     }
+
+    #[test]
+    fn argb_to_rgba_unpremultiplies() {
+        // Half-transparent premultiplied red, opaque blue, fully transparent
+        let rgba = argb_to_rgba(&[0x8080_0000, 0xFF00_00FF, 0x0000_0000]);
+        assert_eq!(&rgba[0..4], &[255, 0, 0, 0x80]);
+        assert_eq!(&rgba[4..8], &[0, 0, 255, 255]);
+        assert_eq!(&rgba[8..12], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn crc32_and_adler32_known_values() {
+        assert_eq!(!crc32(0xFFFF_FFFF, b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn encode_png_layout() {
+        let png = encode_png(2, 1, &[255, 0, 0, 255, 0, 255, 0, 128]);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..20], &2u32.to_be_bytes());
+        assert_eq!(&png[20..24], &1u32.to_be_bytes());
+        // IDAT holds one filter byte plus 8 pixel bytes in a single stored block
+        assert_eq!(&png[37..41], b"IDAT");
+        assert_eq!(&png[41..43], &[0x78, 0x01]);
+        assert_eq!(&png[43..48], &[1, 9, 0, 0xF6, 0xFF]);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn cursor_message_includes_image_only_when_present() {
+        let named = CursorShape {
+            css: "text",
+            image: None,
+        };
+        let v: serde_json::Value = serde_json::from_str(&named.to_message()).unwrap();
+        assert_eq!(v["t"], "cur");
+        assert_eq!(v["css"], "text");
+        assert!(v.get("png").is_none());
+
+        let custom = CursorShape {
+            css: "default",
+            image: Some(CursorImage {
+                png: "AAAA".into(),
+                xhot: 3,
+                yhot: 4,
+            }),
+        };
+        let v: serde_json::Value = serde_json::from_str(&custom.to_message()).unwrap();
+        assert_eq!(v["css"], "default");
+        assert_eq!(v["png"], "AAAA");
+        assert_eq!(v["hx"], 3);
+        assert_eq!(v["hy"], 4);
+    }
 }
@@ -1,8 +1,16 @@
+use crate::{skel, url_open};
+
 use anyhow::{Context, Result, bail};
+use beam_protocol::{AudioBackend, DEFAULT_REFRESH_HZ, Desktop, DisplayMode, Rotation};
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, Stdio};
 use tracing::{debug, info, warn};
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{ChangeWindowAttributesAux, ConnectionExt as _, Window};
+use x11rb::rust_connection::RustConnection;
 
 /// Minimal PulseAudio config for virtual desktop sessions.
 /// Creates a null sink (virtual audio output) with a monitor source
@@ -17,14 +25,67 @@ load-module module-always-sink
 /// Manages a virtual X display using the dummy video driver.
 pub struct VirtualDisplay {
     display_num: u32,
-    xorg_child: Option<Child>,
-    desktop_child: Option<Child>,
-    pulse_child: Option<Child>,
-    cursor_child: Option<Child>,
+    xorg_child: Option<Process>,
+    desktop_child: Option<Process>,
+    pulse_child: Option<Process>,
+    /// pipewire, wireplumber and pipewire-pulse, in start order
+    pipewire_children: Vec<Process>,
+    cursor_child: Option<Process>,
     /// Temp config path to clean up on drop (None for package-installed static config)
     cleanup_config: Option<String>,
 }
 
+/// A process of the display: started by this agent, or by the agent it
+/// replaced on upgrade. Exec keeps the pid, so both are our children.
+enum Process {
+    Spawned(Child),
+    Adopted(u32),
+}
+
+impl Process {
+    fn id(&self) -> u32 {
+        match self {
+            Self::Spawned(child) => child.id(),
+            Self::Adopted(pid) => *pid,
+        }
+    }
+
+    /// Whether the process exited; one that can't be waited for counts
+    /// as exited, so its pid (maybe recycled) is never signalled.
+    fn has_exited(&mut self) -> bool {
+        match self {
+            Self::Spawned(child) => !matches!(child.try_wait(), Ok(None)),
+            Self::Adopted(pid) => unsafe {
+                libc::waitpid(*pid as i32, std::ptr::null_mut(), libc::WNOHANG) != 0
+            },
+        }
+    }
+
+    fn wait(&mut self) {
+        match self {
+            Self::Spawned(child) => {
+                let _ = child.wait();
+            }
+            Self::Adopted(pid) => unsafe {
+                libc::waitpid(*pid as i32, std::ptr::null_mut(), 0);
+            },
+        }
+    }
+}
+
+/// What the next image of the agent needs to take over a running display
+/// (see `VirtualDisplay::hand_over`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayHandover {
+    display_num: u32,
+    xorg: Option<u32>,
+    desktop: Option<u32>,
+    pulse: Option<u32>,
+    pipewire: Vec<u32>,
+    cursor: Option<u32>,
+    cleanup_config: Option<String>,
+}
+
 impl VirtualDisplay {
     /// Create and start a new virtual X display on the given display number.
     pub fn start(display_num: u32, width: u32, height: u32) -> Result<Self> {
@@ -121,7 +182,7 @@ impl VirtualDisplay {
         }
 
         // When using the static package config (no per-session modeline),
-        // set the requested resolution via xrandr after Xorg starts.
+        // set the requested resolution through RandR after Xorg starts.
         if config_path == "/etc/X11/beam-xorg.conf"
             && let Err(e) = set_display_resolution(&display_str, width, height)
         {
@@ -137,432 +198,432 @@ impl VirtualDisplay {
 
         Ok(Self {
             display_num,
-            xorg_child: Some(child),
+            xorg_child: Some(Process::Spawned(child)),
             desktop_child: None,
             pulse_child: None,
+            pipewire_children: Vec::new(),
             cursor_child: None,
             cleanup_config,
         })
     }
 
-    /// Change the resolution of the virtual display using xrandr.
+    /// Change the resolution of the virtual display through RandR.
     #[allow(dead_code)]
     pub fn set_resolution(&self, width: u32, height: u32) -> Result<()> {
         set_display_resolution(&format!(":{}", self.display_num), width, height)
     }
 
-    /// Start a desktop environment on this display.
-    /// Prefers XFCE4 for a full desktop experience. Disables the xfwm4
-    /// compositor to minimize latency for remote desktop streaming.
+    /// Start a desktop environment on this display: `desktop` if it is
+    /// installed, else XFCE4 for a full desktop experience. Disables the
+    /// xfwm4 compositor to minimize latency for remote desktop streaming.
     /// Falls back to openbox (lightweight WM) if XFCE4 is unavailable.
-    pub fn start_desktop(&mut self) -> Result<()> {
+    ///
+    /// `scale` is the initial desktop scale factor (see `normalize_scale`);
+    /// it seeds Xft.dpi and the GTK window scale so HiDPI clients get
+    /// readable text from the first frame.
+    ///
+    /// With `forward_urls`, the default web browser is Beam's link handler
+    /// (see `url_open`), so http(s) links open in the client's browser.
+    ///
+    /// With `persist`, the desktop's settings live in the user's home and
+    /// carry over to the next session (see `skel`).
+    pub fn start_desktop(
+        &mut self,
+        desktop: &Desktop,
+        scale: f64,
+        forward_urls: bool,
+        persist: bool,
+    ) -> Result<()> {
         let display = format!(":{}", self.display_num);
+        let (window_scale, xft_dpi) = dpi_settings(scale);
 
-        // Prefer XFCE4: full desktop with panels, file manager, app menu.
-        if which_exists("xfce4-session") {
-            // Pre-seed XFCE/GTK config to disable animations, compositor,
-            // and menu delays — critical for responsive remote desktop.
-            let xfce_config_dir = format!("/tmp/beam-xfce-{}", self.display_num);
-            let _ = fs::create_dir_all(&xfce_config_dir);
+        // Xft.dpi in the resource database covers Xlib/Qt apps and the
+        // openbox fallback; GTK reads the xsettings values below instead.
+        if let Err(e) = merge_xft_dpi(&display, (96.0 * scale).round() as u32) {
+            warn!("Failed to set initial Xft.dpi: {e:#}");
+        }
 
-            let xfconf_dir = format!("{xfce_config_dir}/xfce4/xfconf/xfce-perchannel-xml");
-            let _ = fs::create_dir_all(&xfconf_dir);
+        // The desktop asked for if it is installed. Otherwise prefer XFCE4:
+        // full desktop with panels, file manager, app menu.
+        let desktop = match desktop.program() {
+            Some(program) if which_exists(program) => desktop.clone(),
+            Some(program) => {
+                warn!(%desktop, program, "Desktop not installed, falling back");
+                Desktop::Auto
+            }
+            None => Desktop::Auto,
+        };
+        let desktop = match desktop {
+            Desktop::Auto if which_exists("xfce4-session") => Desktop::Xfce,
+            Desktop::Auto | Desktop::Openbox => return self.start_openbox(&display),
+            desktop => desktop,
+        };
 
-            // xfwm4: disable compositor and workspace zoom animation
-            let _ = fs::write(
-                format!("{xfconf_dir}/xfwm4.xml"),
-                r#"<?xml version="1.0" encoding="UTF-8"?>
-<channel name="xfwm4" version="1.0">
-  <property name="general" type="empty">
-    <property name="use_compositing" type="bool" value="false"/>
-    <property name="zoom_desktop" type="bool" value="false"/>
-    <property name="popup_opacity" type="int" value="100"/>
-    <property name="move_opacity" type="int" value="100"/>
-    <property name="resize_opacity" type="int" value="100"/>
-  </property>
-</channel>
-"#,
-            );
+        // Create XDG_RUNTIME_DIR for this session. Without it, D-Bus services,
+        // GVFS, and PulseAudio can't find proper socket paths. Normally created
+        // by logind for interactive sessions, but beam-agent is spawned by the
+        // beam-server systemd service (not a PAM login session).
+        let runtime_dir = format!("/tmp/beam-run-{}", self.display_num);
+        let _ = fs::remove_dir_all(&runtime_dir);
+        fs::create_dir_all(&runtime_dir)
+            .with_context(|| format!("Failed to create runtime dir: {runtime_dir}"))?;
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&runtime_dir, fs::Permissions::from_mode(0o700));
+        }
 
-            // xsettings: disable GTK animations via xsettings daemon
-            let _ = fs::write(
-                format!("{xfconf_dir}/xsettings.xml"),
-                r#"<?xml version="1.0" encoding="UTF-8"?>
-<channel name="xsettings" version="1.0">
-  <property name="Gtk" type="empty">
-    <property name="MenuPopupDelay" type="int" value="0"/>
-    <property name="MenuPopdownDelay" type="int" value="0"/>
-  </property>
-  <property name="Net" type="empty">
-    <property name="EnableAnimations" type="bool" value="false"/>
-  </property>
-</channel>
-"#,
+        // Configure default applications (browser + terminal).
+        // Three layers to cover all lookup mechanisms:
+        // 1. XFCE helpers.rc — exo-open --launch WebBrowser / TerminalEmulator
+        // 2. XDG mimeapps.list — xdg-open for http/https MIME types
+        // 3. BROWSER / TERMINAL env vars — universal fallback
+        // The first two go in a config dir of the session's own, after the
+        // config home in XDG_CONFIG_DIRS order, so applications the user
+        // picks in a persistent config home win over these.
+        let session_config_dir = format!("{runtime_dir}/xdg");
+        let helpers_dir = format!("{session_config_dir}/xfce4");
+        let _ = fs::create_dir_all(&helpers_dir);
+
+        let detected_browser = detect_browser();
+        let detected_terminal = find_non_snap_app(&["xfce4-terminal", "gnome-terminal", "xterm"]);
+
+        // helpers.rc: XFCE helper IDs (NOT binary names)
+        let mut helpers_rc = String::from("[Default]\n");
+        if let Some(term) = detected_terminal {
+            // Terminal binary names match XFCE helper IDs directly
+            helpers_rc.push_str(&format!("TerminalEmulator={term}\n"));
+            info!(term, "Default terminal");
+        }
+        if let Some(browser) = detected_browser {
+            let helper_id = match browser {
+                "firefox-esr" => "firefox-esr",
+                "firefox" => "firefox",
+                "google-chrome-stable" | "google-chrome" => "google-chrome",
+                "chromium-browser" | "chromium" => "chromium",
+                "epiphany-browser" => "epiphany",
+                _ => browser,
+            };
+            helpers_rc.push_str(&format!("WebBrowser={helper_id}\n"));
+            info!(browser, "Default browser");
+        } else {
+            warn!(
+                "No non-snap browser found. Install a .deb browser: \
+                 sudo apt install epiphany-browser"
             );
+        }
+        let _ = fs::write(format!("{helpers_dir}/helpers.rc"), &helpers_rc);
+
+        // mimeapps.list: XDG MIME type associations
+        if let Some(browser) = detected_browser {
+            let desktop_file = match browser {
+                "firefox-esr" => "firefox-esr.desktop",
+                "firefox" => "firefox.desktop",
+                "google-chrome-stable" | "google-chrome" => "google-chrome.desktop",
+                "chromium-browser" | "chromium" => "chromium-browser.desktop",
+                "epiphany-browser" => "org.gnome.Epiphany.desktop",
+                _ => "",
+            };
+            if !desktop_file.is_empty() {
+                let content = format!(
+                    "[Default Applications]\n\
+                     x-scheme-handler/http={d}\n\
+                     x-scheme-handler/https={d}\n\
+                     text/html={d}\n\
+                     application/xhtml+xml={d}\n",
+                    d = desktop_file,
+                );
+                let _ = fs::write(format!("{session_config_dir}/mimeapps.list"), content);
+            }
+        }
 
-            // xfce4-session: no splash screen
+        // Link forwarding overrides the browser picked above in all
+        // three layers; the handler's entries live in the runtime dir
+        let url_handler = if forward_urls {
+            let agent_exe = std::env::current_exe().context("Cannot locate beam-agent")?;
+            match url_open::install_handler(std::path::Path::new(&runtime_dir), &agent_exe) {
+                Ok(script) => Some(script),
+                Err(e) => {
+                    warn!("Failed to install URL forwarding handler: {e:#}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if url_handler.is_some() {
+            let handler = url_open::HANDLER_ID;
+            let mut helpers_rc: String = helpers_rc
+                .lines()
+                .filter(|line| !line.starts_with("WebBrowser="))
+                .map(|line| format!("{line}\n"))
+                .collect();
+            helpers_rc.push_str(&format!("WebBrowser={handler}\n"));
+            let _ = fs::write(format!("{helpers_dir}/helpers.rc"), &helpers_rc);
             let _ = fs::write(
-                format!("{xfconf_dir}/xfce4-session.xml"),
-                r#"<?xml version="1.0" encoding="UTF-8"?>
-<channel name="xfce4-session" version="1.0">
-  <property name="splash" type="empty">
-    <property name="Engine" type="string" value=""/>
-  </property>
-</channel>
-"#,
+                format!("{session_config_dir}/mimeapps.list"),
+                format!(
+                    "[Default Applications]\n\
+                     x-scheme-handler/http={handler}.desktop\n\
+                     x-scheme-handler/https={handler}.desktop\n"
+                ),
             );
+            info!("Session links open in the client browser");
+        }
 
-            // Keyboard shortcuts: Alt+F2 for app finder search
-            let _ = fs::write(
-                format!("{xfconf_dir}/xfce4-keyboard-shortcuts.xml"),
-                r#"<?xml version="1.0" encoding="UTF-8"?>
-<channel name="xfce4-keyboard-shortcuts" version="1.0">
-  <property name="commands" type="empty">
-    <property name="custom" type="empty">
-      <property name="&lt;Alt&gt;F2" type="string" value="xfce4-appfinder --collapsed"/>
-    </property>
-  </property>
-</channel>
-"#,
-            );
+        // XFCE gets Beam's tuned settings as its config home. Other desktops
+        // keep theirs in ~/.config when the home persists, else in the
+        // runtime dir, with Beam's defaults behind them in XDG_CONFIG_DIRS.
+        let config_home = if desktop == Desktop::Xfce {
+            let (dir, persistent) = skel::config_home(self.display_num, persist);
+            skel::seed(&dir, xft_dpi, window_scale, persistent);
+            if persistent {
+                info!(dir = %dir.display(), "Desktop settings kept across sessions");
+            }
+            Some(dir)
+        } else {
+            skel::seed_defaults(std::path::Path::new(&session_config_dir), &desktop);
+            (!persist).then(|| std::path::PathBuf::from(format!("{runtime_dir}/config")))
+        };
+        let launch = launch_for(&desktop);
+
+        let mut cmd = Command::new("/usr/bin/dbus-launch");
+        cmd.arg("--exit-with-session")
+            .args(&launch.argv)
+            .env("DISPLAY", &display)
+            .envs(self.audio_env())
+            .env(
+                "XDG_CONFIG_DIRS",
+                format!(
+                    "{session_config_dir}:{}",
+                    std::env::var("XDG_CONFIG_DIRS").unwrap_or_else(|_| "/etc/xdg".to_string())
+                ),
+            )
+            .env("XDG_RUNTIME_DIR", &runtime_dir)
+            .env("GVFS_DISABLE_FUSE", "1")
+            .envs(launch.env.iter().copied());
+        if let Some(dir) = &config_home {
+            cmd.env("XDG_CONFIG_HOME", dir);
+        }
+        if let Some((current, session)) = launch.names {
+            cmd.env("XDG_CURRENT_DESKTOP", current)
+                .env("XDG_SESSION_DESKTOP", session);
+        }
 
-            // GTK3 settings: disable animations, menu delays, cursor blink
-            let gtk3_dir = format!("{xfce_config_dir}/gtk-3.0");
-            let _ = fs::create_dir_all(&gtk3_dir);
-            let _ = fs::write(
-                format!("{gtk3_dir}/settings.ini"),
-                "[Settings]\n\
-                 gtk-enable-animations=false\n\
-                 gtk-menu-popup-delay=0\n\
-                 gtk-menu-popdown-delay=0\n\
-                 gtk-cursor-blink=false\n",
-            );
+        // Set env vars as universal fallback for apps that check directly.
+        if let Some(ref script) = url_handler {
+            cmd.env("BROWSER", script);
+            let data_dirs = std::env::var("XDG_DATA_DIRS")
+                .unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
+            cmd.env("XDG_DATA_DIRS", format!("{runtime_dir}/share:{data_dirs}"));
+        } else if let Some(browser) = detected_browser {
+            cmd.env("BROWSER", browser);
+        }
+        if let Some(term) = detected_terminal {
+            cmd.env("TERMINAL", term);
+        }
 
-            // GTK3 CSS: kill ALL CSS transitions (Greybird theme has 46 × 200ms
-            // transitions on buttons, menus, entries, hover states etc.).
-            // gtk-enable-animations only affects GtkAnimation objects, NOT CSS
-            // transitions — this override is required for instant menu hover.
-            let _ = fs::write(
-                format!("{gtk3_dir}/gtk.css"),
-                "* { transition-duration: 0s !important; animation-duration: 0s !important; }\n",
-            );
+        let child = unsafe {
+            cmd.stdout(Stdio::null())
+                .stderr(Stdio::null())
+                // Create a new session (process group) so we can kill all
+                // grandchildren (xfwm4, xfce4-panel, etc.) on cleanup.
+                .pre_exec(|| {
+                    if libc::setsid() == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                })
+                .spawn()
+                .with_context(|| format!("Failed to start {desktop} desktop via dbus-launch"))?
+        };
 
-            // Mask autostart entries that fail or are useless in a virtual session.
-            // XDG spec: user-level .desktop files in $XDG_CONFIG_HOME/autostart/
-            // override system-level files in /etc/xdg/autostart/ by filename.
-            let autostart_dir = format!("{xfce_config_dir}/autostart");
-            let _ = fs::create_dir_all(&autostart_dir);
-            for entry in [
-                "update-notifier.desktop",                     // pkexec error dialogs
-                "polkit-gnome-authentication-agent-1.desktop", // pkexec auth prompts
-                "pulseaudio.desktop",                          // conflicts with our PulseAudio
-                "tracker-miner-fs-3.desktop",                  // file indexer wastes CPU
-                "snap-userd-autostart.desktop",                // snap UI daemon
-                "spice-vdagent.desktop",                       // SPICE agent, not used
-                "ubuntu-advantage-notification.desktop",       // Ubuntu Pro nag
-                "ubuntu-report-on-upgrade.desktop",            // upgrade reporter
-                "gnome-initial-setup-copy-worker.desktop",     // GNOME first-run
-                "gnome-initial-setup-first-login.desktop",     // GNOME first-run
-                "org.gnome.DejaDup.Monitor.desktop",           // backup monitor
-                "org.gnome.Evolution-alarm-notify.desktop",    // calendar alarms
-            ] {
-                let _ = fs::write(
-                    format!("{autostart_dir}/{entry}"),
-                    "[Desktop Entry]\nHidden=true\n",
-                );
-            }
+        info!(
+            display = self.display_num,
+            pid = child.id(),
+            %desktop,
+            "Desktop started"
+        );
 
-            // Configure default applications (browser + terminal).
-            // Three layers to cover all lookup mechanisms:
-            // 1. XFCE helpers.rc — exo-open --launch WebBrowser / TerminalEmulator
-            // 2. XDG mimeapps.list — xdg-open for http/https MIME types
-            // 3. BROWSER / TERMINAL env vars — universal fallback
-            let helpers_dir = format!("{xfce_config_dir}/xfce4");
-            let _ = fs::create_dir_all(&helpers_dir);
-
-            let detected_browser = find_non_snap_app(&[
-                "firefox-esr",
-                "google-chrome-stable",
-                "google-chrome",
-                "chromium-browser",
-                "firefox",
-                "chromium",
-                "epiphany-browser",
-            ]);
-            let detected_terminal =
-                find_non_snap_app(&["xfce4-terminal", "gnome-terminal", "xterm"]);
-
-            // helpers.rc: XFCE helper IDs (NOT binary names)
-            let mut helpers_rc = String::from("[Default]\n");
-            if let Some(term) = detected_terminal {
-                // Terminal binary names match XFCE helper IDs directly
-                helpers_rc.push_str(&format!("TerminalEmulator={term}\n"));
-                info!(term, "Default terminal");
-            }
-            if let Some(browser) = detected_browser {
-                let helper_id = match browser {
-                    "firefox-esr" => "firefox-esr",
-                    "firefox" => "firefox",
-                    "google-chrome-stable" | "google-chrome" => "google-chrome",
-                    "chromium-browser" | "chromium" => "chromium",
-                    "epiphany-browser" => "epiphany",
-                    _ => browser,
-                };
-                helpers_rc.push_str(&format!("WebBrowser={helper_id}\n"));
-                info!(browser, "Default browser");
-            } else {
-                warn!(
-                    "No non-snap browser found. Install a .deb browser: \
-                     sudo apt install epiphany-browser"
-                );
-            }
-            let _ = fs::write(format!("{helpers_dir}/helpers.rc"), &helpers_rc);
+        self.desktop_child = Some(Process::Spawned(child));
 
-            // mimeapps.list: XDG MIME type associations
-            if let Some(browser) = detected_browser {
-                let desktop_file = match browser {
-                    "firefox-esr" => "firefox-esr.desktop",
-                    "firefox" => "firefox.desktop",
-                    "google-chrome-stable" | "google-chrome" => "google-chrome.desktop",
-                    "chromium-browser" | "chromium" => "chromium-browser.desktop",
-                    "epiphany-browser" => "org.gnome.Epiphany.desktop",
-                    _ => "",
-                };
-                if !desktop_file.is_empty() {
-                    let content = format!(
-                        "[Default Applications]\n\
-                         x-scheme-handler/http={d}\n\
-                         x-scheme-handler/https={d}\n\
-                         text/html={d}\n\
-                         application/xhtml+xml={d}\n",
-                        d = desktop_file,
-                    );
-                    let _ = fs::write(format!("{xfce_config_dir}/mimeapps.list"), content);
-                }
-            }
+        if desktop != Desktop::Xfce {
+            return Ok(());
+        }
 
-            // Create XDG_RUNTIME_DIR for this session. Without it, D-Bus services,
-            // GVFS, and PulseAudio can't find proper socket paths. Normally created
-            // by logind for interactive sessions, but beam-agent is spawned by the
-            // beam-server systemd service (not a PAM login session).
-            let runtime_dir = format!("/tmp/beam-run-{}", self.display_num);
-            let _ = fs::remove_dir_all(&runtime_dir);
-            fs::create_dir_all(&runtime_dir)
-                .with_context(|| format!("Failed to create runtime dir: {runtime_dir}"))?;
-            {
-                use std::os::unix::fs::PermissionsExt;
-                let _ = fs::set_permissions(&runtime_dir, fs::Permissions::from_mode(0o700));
+        // Apply settings via xfconf-query AFTER the session starts.
+        // Pre-seeded XML files get overridden by xfconfd on startup,
+        // so we must set properties after the daemon is running.
+        let display_for_xfconf = display.clone();
+        std::thread::spawn(move || {
+            // Wait for xfconfd and xfce4-panel to initialize
+            std::thread::sleep(std::time::Duration::from_secs(3));
+
+            // Discover DBUS_SESSION_BUS_ADDRESS from the running panel.
+            // Without this, xfconf-query silently connects to a different
+            // (auto-launched) bus instead of the XFCE session's bus,
+            // making settings appear to succeed but have no effect.
+            let dbus_addr = find_dbus_address_for_display(&display_for_xfconf);
+            if dbus_addr.is_none() {
+                warn!("Could not find DBUS session bus, xfconf settings may not apply");
             }
 
-            let pulse_server = format!("unix:/tmp/beam-pulse-{}/native", self.display_num);
-            let mut cmd = Command::new("/usr/bin/dbus-launch");
-            cmd.arg("--exit-with-session")
-                .arg("xfce4-session")
-                .env("DISPLAY", &display)
-                .env("PULSE_SERVER", &pulse_server)
-                .env("XDG_CONFIG_HOME", &xfce_config_dir)
-                .env("XDG_RUNTIME_DIR", &runtime_dir)
-                .env("XDG_CURRENT_DESKTOP", "XFCE")
-                .env("XDG_SESSION_DESKTOP", "xfce")
-                .env("GVFS_DISABLE_FUSE", "1");
-
-            // Set env vars as universal fallback for apps that check directly.
-            if let Some(browser) = detected_browser {
-                cmd.env("BROWSER", browser);
-            }
-            if let Some(term) = detected_terminal {
-                cmd.env("TERMINAL", term);
-            }
-
-            let child = unsafe {
-                cmd.stdout(Stdio::null())
+            // Start gnome-keyring-daemon inside the D-Bus session so it
+            // registers as org.freedesktop.secrets on the session bus.
+            // VS Code and other apps use libsecret to talk to this service.
+            //
+            // Must use --foreground + separate --control-directory because
+            // --start discovers the HOST's existing daemon via the shared
+            // /run/user/ control socket and reuses it (which is on a
+            // different D-Bus). A fresh daemon with its own control dir
+            // registers on THIS session's bus.
+            if let Some(ref addr) = dbus_addr {
+                let display_num = display_for_xfconf.trim_start_matches(':');
+                let keyring_dir = format!("/tmp/beam-keyring-{display_num}");
+                let keyring_data_dir = format!("/tmp/beam-keyring-{display_num}/data");
+                let keyrings_dir = format!("{keyring_data_dir}/keyrings");
+
+                // Clean up stale keyring data from previous sessions.
+                // Different users may have used this display number, leaving
+                // directories owned by another user (mode 700) that we can't
+                // write into. Fresh start ensures correct ownership.
+                let _ = fs::remove_dir_all(&keyring_dir);
+                let _ = fs::create_dir_all(&keyrings_dir);
+
+                // Set the default keyring name so Chrome/apps use "login".
+                // Do NOT pre-create login.keyring: gnome-keyring uses a binary
+                // format and an empty file causes "invalid or unrecognized
+                // format" errors. The --unlock flag with empty stdin creates
+                // the keyring file in the correct format automatically.
+                let _ = fs::write(format!("{keyrings_dir}/default"), "login");
+
+                // Use a shell pipe to reliably deliver the empty password
+                // to --unlock via stdin. Direct Stdio::piped() + drop has
+                // a race condition with --foreground (daemon may not have
+                // started reading stdin when we close the pipe).
+                let keyring_cmd = format!(
+                    "echo '' | gnome-keyring-daemon --foreground --unlock \
+                     --components=secrets --control-directory={}",
+                    keyring_dir
+                );
+                match Command::new("sh")
+                    .args(["-c", &keyring_cmd])
+                    .env("DISPLAY", &display_for_xfconf)
+                    .env("DBUS_SESSION_BUS_ADDRESS", addr)
+                    .env("XDG_DATA_HOME", &keyring_data_dir)
+                    .stdout(Stdio::null())
                     .stderr(Stdio::null())
-                    // Create a new session (process group) so we can kill all
-                    // grandchildren (xfwm4, xfce4-panel, etc.) on cleanup.
-                    .pre_exec(|| {
-                        if libc::setsid() == -1 {
-                            return Err(std::io::Error::last_os_error());
-                        }
-                        Ok(())
-                    })
                     .spawn()
-                    .context("Failed to start XFCE4 desktop via dbus-launch")?
-            };
-
-            info!(
-                display = self.display_num,
-                pid = child.id(),
-                "XFCE4 desktop started"
-            );
-
-            self.desktop_child = Some(child);
-
-            // Apply settings via xfconf-query AFTER the session starts.
-            // Pre-seeded XML files get overridden by xfconfd on startup,
-            // so we must set properties after the daemon is running.
-            let display_for_xfconf = display.clone();
-            std::thread::spawn(move || {
-                // Wait for xfconfd and xfce4-panel to initialize
-                std::thread::sleep(std::time::Duration::from_secs(3));
-
-                // Discover DBUS_SESSION_BUS_ADDRESS from the running panel.
-                // Without this, xfconf-query silently connects to a different
-                // (auto-launched) bus instead of the XFCE session's bus,
-                // making settings appear to succeed but have no effect.
-                let dbus_addr = find_dbus_address_for_display(&display_for_xfconf);
-                if dbus_addr.is_none() {
-                    warn!("Could not find DBUS session bus, xfconf settings may not apply");
-                }
-
-                // Start gnome-keyring-daemon inside the D-Bus session so it
-                // registers as org.freedesktop.secrets on the session bus.
-                // VS Code and other apps use libsecret to talk to this service.
-                //
-                // Must use --foreground + separate --control-directory because
-                // --start discovers the HOST's existing daemon via the shared
-                // /run/user/ control socket and reuses it (which is on a
-                // different D-Bus). A fresh daemon with its own control dir
-                // registers on THIS session's bus.
-                if let Some(ref addr) = dbus_addr {
-                    let display_num = display_for_xfconf.trim_start_matches(':');
-                    let keyring_dir = format!("/tmp/beam-keyring-{display_num}");
-                    let keyring_data_dir = format!("/tmp/beam-keyring-{display_num}/data");
-                    let keyrings_dir = format!("{keyring_data_dir}/keyrings");
-
-                    // Clean up stale keyring data from previous sessions.
-                    // Different users may have used this display number, leaving
-                    // directories owned by another user (mode 700) that we can't
-                    // write into. Fresh start ensures correct ownership.
-                    let _ = fs::remove_dir_all(&keyring_dir);
-                    let _ = fs::create_dir_all(&keyrings_dir);
-
-                    // Set the default keyring name so Chrome/apps use "login".
-                    // Do NOT pre-create login.keyring: gnome-keyring uses a binary
-                    // format and an empty file causes "invalid or unrecognized
-                    // format" errors. The --unlock flag with empty stdin creates
-                    // the keyring file in the correct format automatically.
-                    let _ = fs::write(format!("{keyrings_dir}/default"), "login");
-
-                    // Use a shell pipe to reliably deliver the empty password
-                    // to --unlock via stdin. Direct Stdio::piped() + drop has
-                    // a race condition with --foreground (daemon may not have
-                    // started reading stdin when we close the pipe).
-                    let keyring_cmd = format!(
-                        "echo '' | gnome-keyring-daemon --foreground --unlock \
-                         --components=secrets --control-directory={}",
-                        keyring_dir
-                    );
-                    match Command::new("sh")
-                        .args(["-c", &keyring_cmd])
-                        .env("DISPLAY", &display_for_xfconf)
-                        .env("DBUS_SESSION_BUS_ADDRESS", addr)
-                        .env("XDG_DATA_HOME", &keyring_data_dir)
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::null())
-                        .spawn()
-                    {
-                        Ok(child) => {
-                            info!(
-                                pid = child.id(),
-                                "gnome-keyring-daemon started (secrets) on session bus"
-                            );
-                        }
-                        Err(e) => {
-                            warn!("Failed to start gnome-keyring-daemon: {e}");
-                        }
+                {
+                    Ok(child) => {
+                        info!(
+                            pid = child.id(),
+                            "gnome-keyring-daemon started (secrets) on session bus"
+                        );
+                    }
+                    Err(e) => {
+                        warn!("Failed to start gnome-keyring-daemon: {e}");
                     }
                 }
+            }
 
-                let has_whiskermenu = which_exists("xfce4-popup-whiskermenu");
-
-                let mut settings: Vec<(&str, &str, &str, &str)> = vec![
-                    // Disable compositor (biggest latency offender)
-                    ("xfwm4", "/general/use_compositing", "bool", "false"),
-                    // Disable workspace zoom animation
-                    ("xfwm4", "/general/zoom_desktop", "bool", "false"),
-                    // Full opacity during move/resize (no transparency)
-                    ("xfwm4", "/general/popup_opacity", "int", "100"),
-                    ("xfwm4", "/general/move_opacity", "int", "100"),
-                    ("xfwm4", "/general/resize_opacity", "int", "100"),
-                    // Disable GTK animations (menu fade-in/out ~200ms)
-                    ("xsettings", "/Net/EnableAnimations", "bool", "false"),
-                    // Zero delay on submenu popup/popdown (~225ms each)
-                    ("xsettings", "/Gtk/MenuPopupDelay", "int", "0"),
-                    ("xsettings", "/Gtk/MenuPopdownDelay", "int", "0"),
-                    // Disable cursor blink (saves encode bandwidth)
-                    ("xsettings", "/Gtk/CursorBlink", "bool", "false"),
-                    // Force Greybird theme (consistent, well-tested with our CSS override)
-                    ("xsettings", "/Net/ThemeName", "string", "Greybird"),
-                ];
-
-                // Replace default Applications Menu with Whisker Menu if installed.
-                // Whisker Menu uses a two-pane layout (categories + apps)
-                // instead of cascading GtkMenu submenus, completely
-                // bypassing the 225ms hardcoded MENU_POPUP_DELAY in GTK3.
-                if has_whiskermenu {
-                    settings.push(("xfce4-panel", "/plugins/plugin-1", "string", "whiskermenu"));
-                } else {
-                    info!("Whisker Menu not installed, keeping default applicationsmenu");
-                }
+            let has_whiskermenu = which_exists("xfce4-popup-whiskermenu");
+            let xft_dpi = xft_dpi.to_string();
+            let window_scale = window_scale.to_string();
+
+            let mut settings: Vec<(&str, &str, &str, &str)> = vec![
+                // Disable compositor (biggest latency offender)
+                ("xfwm4", "/general/use_compositing", "bool", "false"),
+                // Disable workspace zoom animation
+                ("xfwm4", "/general/zoom_desktop", "bool", "false"),
+                // Full opacity during move/resize (no transparency)
+                ("xfwm4", "/general/popup_opacity", "int", "100"),
+                ("xfwm4", "/general/move_opacity", "int", "100"),
+                ("xfwm4", "/general/resize_opacity", "int", "100"),
+                // Disable GTK animations (menu fade-in/out ~200ms)
+                ("xsettings", "/Net/EnableAnimations", "bool", "false"),
+                // Zero delay on submenu popup/popdown (~225ms each)
+                ("xsettings", "/Gtk/MenuPopupDelay", "int", "0"),
+                ("xsettings", "/Gtk/MenuPopdownDelay", "int", "0"),
+                // Disable cursor blink (saves encode bandwidth)
+                ("xsettings", "/Gtk/CursorBlink", "bool", "false"),
+                // Force Greybird theme (consistent, well-tested with our CSS override)
+                ("xsettings", "/Net/ThemeName", "string", "Greybird"),
+                // HiDPI: integer GTK scale plus fractional remainder as DPI
+                ("xsettings", "/Xft/DPI", "int", &xft_dpi),
+                (
+                    "xsettings",
+                    "/Gdk/WindowScalingFactor",
+                    "int",
+                    &window_scale,
+                ),
+            ];
+
+            // Replace default Applications Menu with Whisker Menu if installed.
+            // Whisker Menu uses a two-pane layout (categories + apps)
+            // instead of cascading GtkMenu submenus, completely
+            // bypassing the 225ms hardcoded MENU_POPUP_DELAY in GTK3.
+            if has_whiskermenu {
+                settings.push(("xfce4-panel", "/plugins/plugin-1", "string", "whiskermenu"));
+            } else {
+                info!("Whisker Menu not installed, keeping default applicationsmenu");
+            }
 
-                for (channel, prop, typ, value) in settings {
-                    let mut cmd = Command::new("xfconf-query");
-                    cmd.env("DISPLAY", &display_for_xfconf)
-                        .args(["-c", channel, "-p", prop, "-n", "-t", typ, "-s", value]);
-                    if let Some(ref addr) = dbus_addr {
-                        cmd.env("DBUS_SESSION_BUS_ADDRESS", addr);
+            for (channel, prop, typ, value) in settings {
+                let mut cmd = Command::new("xfconf-query");
+                cmd.env("DISPLAY", &display_for_xfconf)
+                    .args(["-c", channel, "-p", prop, "-n", "-t", typ, "-s", value]);
+                if let Some(ref addr) = dbus_addr {
+                    cmd.env("DBUS_SESSION_BUS_ADDRESS", addr);
+                }
+                match cmd.output() {
+                    Ok(output) if output.status.success() => {
+                        debug!(channel, prop, value, "xfconf setting applied");
+                    }
+                    Ok(output) => {
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        warn!(channel, prop, "xfconf-query failed: {stderr}");
                     }
-                    match cmd.output() {
-                        Ok(output) if output.status.success() => {
-                            debug!(channel, prop, value, "xfconf setting applied");
-                        }
-                        Ok(output) => {
-                            let stderr = String::from_utf8_lossy(&output.stderr);
-                            warn!(channel, prop, "xfconf-query failed: {stderr}");
-                        }
-                        Err(e) => {
-                            warn!(channel, prop, "Failed to run xfconf-query: {e}");
-                        }
+                    Err(e) => {
+                        warn!(channel, prop, "Failed to run xfconf-query: {e}");
                     }
                 }
+            }
 
-                // Restart the panel only if we swapped to Whisker Menu.
-                // The plugin type change via xfconf only takes effect after
-                // the panel reloads its plugin instances.
-                if has_whiskermenu {
-                    let mut cmd = Command::new("xfce4-panel");
-                    cmd.env("DISPLAY", &display_for_xfconf).arg("--restart");
-                    if let Some(ref addr) = dbus_addr {
-                        cmd.env("DBUS_SESSION_BUS_ADDRESS", addr);
+            // Restart the panel only if we swapped to Whisker Menu.
+            // The plugin type change via xfconf only takes effect after
+            // the panel reloads its plugin instances.
+            if has_whiskermenu {
+                let mut cmd = Command::new("xfce4-panel");
+                cmd.env("DISPLAY", &display_for_xfconf).arg("--restart");
+                if let Some(ref addr) = dbus_addr {
+                    cmd.env("DBUS_SESSION_BUS_ADDRESS", addr);
+                }
+                match cmd.output() {
+                    Ok(output) if output.status.success() => {
+                        info!("Panel restarted with Whisker Menu");
                     }
-                    match cmd.output() {
-                        Ok(output) if output.status.success() => {
-                            info!("Panel restarted with Whisker Menu");
-                        }
-                        Ok(output) => {
-                            let stderr = String::from_utf8_lossy(&output.stderr);
-                            warn!("Panel restart failed: {stderr}");
-                        }
-                        Err(e) => {
-                            warn!("Failed to restart panel: {e}");
-                        }
+                    Ok(output) => {
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        warn!("Panel restart failed: {stderr}");
+                    }
+                    Err(e) => {
+                        warn!("Failed to restart panel: {e}");
                     }
                 }
+            }
 
-                info!("XFCE settings applied (compositor off, animations off, whisker menu)");
-            });
+            info!("XFCE settings applied (compositor off, animations off, whisker menu)");
+        });
 
-            return Ok(());
-        }
+        Ok(())
+    }
 
-        // Fallback: openbox minimal WM
+    /// Openbox with a terminal: the fallback when no desktop is installed.
+    fn start_openbox(&mut self, display: &str) -> Result<()> {
         if which_exists("openbox") {
             let child = Command::new("openbox")
-                .env("DISPLAY", &display)
-                .env(
-                    "PULSE_SERVER",
-                    format!("unix:/tmp/beam-pulse-{}/native", self.display_num),
-                )
+                .env("DISPLAY", display)
+                .envs(self.audio_env())
                 .stdout(Stdio::null())
                 .stderr(Stdio::null())
                 .spawn()
@@ -574,23 +635,22 @@ impl VirtualDisplay {
                 "Openbox window manager started (XFCE4 not available)"
             );
 
-            self.desktop_child = Some(child);
+            self.desktop_child = Some(Process::Spawned(child));
 
-            let _ = Command::new("xsetroot")
-                .env("DISPLAY", &display)
-                .args(["-solid", "#2d3436"])
-                .output();
+            if let Err(e) = set_root_color(display, 0x2d3436) {
+                debug!("Failed to set root window color: {e:#}");
+            }
 
             // Launch a terminal so the user has something to interact with
             if which_exists("xfce4-terminal") {
                 let _ = Command::new("xfce4-terminal")
-                    .env("DISPLAY", &display)
+                    .env("DISPLAY", display)
                     .stdout(Stdio::null())
                     .stderr(Stdio::null())
                     .spawn();
             } else if which_exists("xterm") {
                 let _ = Command::new("xterm")
-                    .env("DISPLAY", &display)
+                    .env("DISPLAY", display)
                     .args([
                         "-geometry",
                         "100x35+100+100",
@@ -617,6 +677,10 @@ impl VirtualDisplay {
     ///
     /// Uses `unclutter` if available (best-effort, degrades gracefully).
     pub fn hide_cursor(&mut self) {
+        // Already hidden, by the agent this one upgraded
+        if self.cursor_child.is_some() {
+            return;
+        }
         let display = format!(":{}", self.display_num);
 
         // Prefer unclutter-xfixes: uses XFixes extension to set a transparent
@@ -638,7 +702,7 @@ impl VirtualDisplay {
                         pid = child.id(),
                         "Cursor hidden via unclutter-xfixes"
                     );
-                    self.cursor_child = Some(child);
+                    self.cursor_child = Some(Process::Spawned(child));
                     return;
                 }
                 Err(e) => {
@@ -664,7 +728,7 @@ impl VirtualDisplay {
                         pid = child.id(),
                         "Cursor hidden via unclutter (classic fallback)"
                     );
-                    self.cursor_child = Some(child);
+                    self.cursor_child = Some(Process::Spawned(child));
                 }
                 Err(e) => {
                     warn!("Failed to start unclutter: {e}");
@@ -705,39 +769,155 @@ impl VirtualDisplay {
             "PulseAudio started"
         );
 
-        self.pulse_child = Some(child);
+        self.pulse_child = Some(Process::Spawned(child));
         Ok(())
     }
-}
 
-impl Drop for VirtualDisplay {
-    fn drop(&mut self) {
-        /// Gracefully stop a child process: check if still running before
-        /// sending SIGTERM to avoid killing an unrelated process if the
-        /// PID has been recycled.
-        fn stop_child(child: &mut Child, name: &str, display_num: u32) {
-            match child.try_wait() {
-                Ok(Some(_)) => return, // already exited
-                Ok(None) => {}         // still running
-                Err(_) => return,
+    /// Start PipeWire, WirePlumber and pipewire-pulse for this display's
+    /// user session. pipewire-pulse listens where PulseAudio would, so
+    /// applications and `pactl` find either the same way; the session has
+    /// no sink until one is added through it.
+    pub fn start_pipewire(&mut self) -> Result<()> {
+        let runtime_dir = pipewire_runtime_dir(self.display_num);
+        let pulse_dir = format!("/tmp/beam-pulse-{}", self.display_num);
+        for dir in [&runtime_dir, &pulse_dir] {
+            // Stale directories may be owned by a previous session's user
+            let _ = fs::remove_dir_all(dir);
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create PipeWire dir: {dir}"))?;
+        }
+
+        let mut children = Vec::new();
+        let started = (|| -> Result<()> {
+            for program in AudioBackend::PIPEWIRE_PROGRAMS {
+                let child = Command::new(program)
+                    .env("PIPEWIRE_RUNTIME_DIR", &runtime_dir)
+                    .env("XDG_RUNTIME_DIR", &runtime_dir)
+                    .env("PULSE_RUNTIME_PATH", &pulse_dir)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .with_context(|| format!("Failed to start {program}"))?;
+                children.push(child);
+                // The others connect to pipewire's socket as they start
+                if program == "pipewire" {
+                    let socket = format!("{runtime_dir}/pipewire-0");
+                    if !wait_for_path(&socket, std::time::Duration::from_secs(2)) {
+                        bail!("PipeWire did not create {socket}");
+                    }
+                }
             }
-            let pid = child.id();
-            debug!(display = display_num, pid, name, "Stopping process");
-            unsafe {
-                libc::kill(pid as i32, libc::SIGTERM);
+            Ok(())
+        })();
+        if let Err(e) = started {
+            for child in children {
+                stop_child(&mut Process::Spawned(child), "pipewire", self.display_num);
             }
-            let _ = child.wait();
+            return Err(e);
+        }
+
+        info!(
+            display = self.display_num,
+            pids = ?children.iter().map(Child::id).collect::<Vec<_>>(),
+            "PipeWire started"
+        );
+        self.pipewire_children = children.into_iter().map(Process::Spawned).collect();
+        Ok(())
+    }
+
+    /// Where programs in the session find its sound server
+    fn audio_env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![(
+            "PULSE_SERVER",
+            format!("unix:/tmp/beam-pulse-{}/native", self.display_num),
+        )];
+        if !self.pipewire_children.is_empty() {
+            env.push((
+                "PIPEWIRE_RUNTIME_DIR",
+                pipewire_runtime_dir(self.display_num),
+            ));
         }
+        env
+    }
+
+    /// Let go of the display without stopping it, for the agent binary
+    /// this process is about to exec to `adopt`.
+    pub fn hand_over(self) -> DisplayHandover {
+        let handover = DisplayHandover {
+            display_num: self.display_num,
+            xorg: self.xorg_child.as_ref().map(Process::id),
+            desktop: self.desktop_child.as_ref().map(Process::id),
+            pulse: self.pulse_child.as_ref().map(Process::id),
+            pipewire: self.pipewire_children.iter().map(Process::id).collect(),
+            cursor: self.cursor_child.as_ref().map(Process::id),
+            cleanup_config: self.cleanup_config.clone(),
+        };
+        // Dropping it would stop the processes and remove their directories
+        std::mem::forget(self);
+        handover
+    }
 
+    /// Take over the display the agent before an upgrade handed over. It
+    /// is stopped on drop as if this agent had started it.
+    pub fn adopt(handover: DisplayHandover) -> Self {
+        info!(display = handover.display_num, "Adopting the display");
+        Self {
+            display_num: handover.display_num,
+            xorg_child: handover.xorg.map(Process::Adopted),
+            desktop_child: handover.desktop.map(Process::Adopted),
+            pulse_child: handover.pulse.map(Process::Adopted),
+            pipewire_children: handover
+                .pipewire
+                .into_iter()
+                .map(Process::Adopted)
+                .collect(),
+            cursor_child: handover.cursor.map(Process::Adopted),
+            cleanup_config: handover.cleanup_config,
+        }
+    }
+}
+
+/// Runtime directory of a display's PipeWire, holding its `pipewire-0`
+/// socket
+pub fn pipewire_runtime_dir(display_num: u32) -> String {
+    format!("/tmp/beam-pipewire-{display_num}")
+}
+
+fn wait_for_path(path: &str, timeout: std::time::Duration) -> bool {
+    let deadline = std::time::Instant::now() + timeout;
+    while !std::path::Path::new(path).exists() {
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    true
+}
+
+/// Gracefully stop a child process: check if still running before
+/// sending SIGTERM to avoid killing an unrelated process if the
+/// PID has been recycled.
+fn stop_child(child: &mut Process, name: &str, display_num: u32) {
+    if child.has_exited() {
+        return;
+    }
+    let pid = child.id();
+    debug!(display = display_num, pid, name, "Stopping process");
+    unsafe {
+        libc::kill(pid as i32, libc::SIGTERM);
+    }
+    child.wait();
+}
+
+impl Drop for VirtualDisplay {
+    fn drop(&mut self) {
         /// Stop a desktop process group: sends SIGTERM to the entire process
         /// group (negative PID) to reach grandchildren (xfwm4, xfce4-panel,
         /// etc.) spawned by dbus-launch -> xfce4-session. Falls back to
         /// SIGKILL after a brief wait if processes are still alive.
-        fn stop_desktop_group(child: &mut Child, display_num: u32) {
-            match child.try_wait() {
-                Ok(Some(_)) => return, // already exited
-                Ok(None) => {}         // still running
-                Err(_) => return,
+        fn stop_desktop_group(child: &mut Process, display_num: u32) {
+            if child.has_exited() {
+                return;
             }
             let pid = child.id() as i32;
             debug!(display = display_num, pid, "Stopping desktop process group");
@@ -748,20 +928,16 @@ impl Drop for VirtualDisplay {
             // Brief wait for graceful shutdown
             std::thread::sleep(std::time::Duration::from_millis(500));
             // Check if the lead process exited
-            match child.try_wait() {
-                Ok(Some(_)) => (),
-                Ok(None) => {
-                    // Still alive — escalate to SIGKILL on the group
-                    debug!(
-                        display = display_num,
-                        pid, "Desktop group still alive, sending SIGKILL"
-                    );
-                    unsafe {
-                        libc::kill(-pid, libc::SIGKILL);
-                    }
-                    let _ = child.wait();
+            if !child.has_exited() {
+                // Still alive — escalate to SIGKILL on the group
+                debug!(
+                    display = display_num,
+                    pid, "Desktop group still alive, sending SIGKILL"
+                );
+                unsafe {
+                    libc::kill(-pid, libc::SIGKILL);
                 }
-                Err(_) => {}
+                child.wait();
             }
         }
 
@@ -769,10 +945,13 @@ impl Drop for VirtualDisplay {
         if let Some(ref mut child) = self.cursor_child {
             stop_child(child, "unclutter", self.display_num);
         }
-        // Stop PulseAudio first
+        // Stop the sound server first
         if let Some(ref mut child) = self.pulse_child {
             stop_child(child, "pulseaudio", self.display_num);
         }
+        for child in self.pipewire_children.iter_mut().rev() {
+            stop_child(child, "pipewire", self.display_num);
+        }
         // Stop desktop environment (kill entire process group)
         if let Some(ref mut child) = self.desktop_child {
             stop_desktop_group(child, self.display_num);
@@ -784,15 +963,16 @@ impl Drop for VirtualDisplay {
         if let Some(ref path) = self.cleanup_config {
             let _ = fs::remove_file(path);
         }
-        // Clean up PA, XFCE config, and runtime directories
+        // Clean up PA, PipeWire, XFCE config, and runtime directories
         let _ = fs::remove_dir_all(format!("/tmp/beam-pulse-{}", self.display_num));
+        let _ = fs::remove_dir_all(pipewire_runtime_dir(self.display_num));
         let _ = fs::remove_file(format!("/tmp/beam-pulse-{}.pa", self.display_num));
         let _ = fs::remove_dir_all(format!("/tmp/beam-xfce-{}", self.display_num));
         let _ = fs::remove_dir_all(format!("/tmp/beam-run-{}", self.display_num));
     }
 }
 
-/// Clamp and normalize resize dimensions for safe use with xrandr and H.264.
+/// Clamp and normalize resize dimensions for safe use with RandR and H.264.
 /// Returns `None` if the dimensions are out of the valid range (320..=7680, 240..=4320).
 /// Otherwise clamps to `max_width`/`max_height` (0 = unlimited, default 3840x2160),
 /// enforces minimum 640x480, and rounds down to even numbers (H.264 requirement).
@@ -822,68 +1002,634 @@ pub fn clamp_resize_dimensions(
     Some((cw, ch))
 }
 
-/// Change display resolution using xrandr. Standalone function that only needs
-/// the X display string (e.g. ":10"), so it can be called from the capture thread
-/// without owning a VirtualDisplay reference.
-pub fn set_display_resolution(x_display: &str, width: u32, height: u32) -> Result<()> {
-    let mode_name = format!("{width}x{height}");
-    let modeline = generate_modeline(width, height, 60);
+/// The largest size with `width` x `height`'s aspect ratio that fits in
+/// `max_width` x `max_height`, rounded down to even. Never larger than
+/// `width` x `height`: a letterboxed display is scaled down to a smaller
+/// viewport, but the browser scales it up itself.
+pub fn fit_within(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+    let (w, h) = (u64::from(width), u64::from(height));
+    let (fit_w, fit_h) = if w * u64::from(max_height) > h * u64::from(max_width) {
+        (u64::from(max_width), h * u64::from(max_width) / w)
+    } else {
+        (w * u64::from(max_height) / h, u64::from(max_height))
+    };
+    (fit_w as u32 & !1, fit_h as u32 & !1)
+}
 
-    // Try to add the mode (may already exist from a previous resize).
-    // Log failures — these help diagnose xrandr issues.
-    let newmode_output = Command::new("xrandr")
-        .env("DISPLAY", x_display)
-        .args(["--newmode", &mode_name])
-        .args(modeline.split_whitespace())
-        .output()
-        .context("Failed to run xrandr --newmode")?;
-    if !newmode_output.status.success() {
-        let stderr = String::from_utf8_lossy(&newmode_output.stderr);
-        // "already exists" is expected for repeated resizes
-        if !stderr.contains("already exists") {
-            warn!("xrandr --newmode {mode_name} failed: {stderr}");
+/// Normalize a browser devicePixelRatio into a desktop scale factor.
+/// Non-finite or sub-1.0 values fall back to 1.0, the result is capped at 4.0,
+/// and it's snapped to 0.25 steps so browser zoom noise (e.g. 1.4999) doesn't
+/// trigger a DPI change on every resize.
+pub fn normalize_scale(scale: f64) -> f64 {
+    if !scale.is_finite() {
+        return 1.0;
+    }
+    ((scale.clamp(1.0, 4.0) * 4.0).round()) / 4.0
+}
+
+/// Convert CSS-pixel dimensions to physical pixels for the given scale.
+/// The scale is reduced if needed so the result stays within the 7680x4320
+/// range accepted by `clamp_resize_dimensions`, preserving aspect ratio.
+pub fn scale_dimensions(w: u32, h: u32, scale: f64) -> (u32, u32) {
+    if w == 0 || h == 0 {
+        return (w, h);
+    }
+    let scale = scale.min(7680.0 / w as f64).min(4320.0 / h as f64).max(1.0);
+    (
+        (w as f64 * scale).round() as u32,
+        (h as f64 * scale).round() as u32,
+    )
+}
+
+/// Split a scale factor into the integer GTK window scale and the Xft DPI
+/// that makes up the fractional remainder. GTK multiplies the two, so a
+/// 1.5x client gets window scale 1 at 144 DPI and a 2x client gets window
+/// scale 2 at 96 DPI.
+fn dpi_settings(scale: f64) -> (u32, u32) {
+    let window_scale = (scale.floor() as u32).max(1);
+    let xft_dpi = (96.0 * scale / window_scale as f64).round() as u32;
+    (window_scale, xft_dpi)
+}
+
+/// How a desktop's session is started under dbus-launch.
+struct Launch {
+    argv: Vec<String>,
+    /// XDG_CURRENT_DESKTOP and XDG_SESSION_DESKTOP
+    names: Option<(&'static str, &'static str)>,
+    /// Environment that tunes the desktop for a remote display
+    env: &'static [(&'static str, &'static str)],
+}
+
+fn launch_for(desktop: &Desktop) -> Launch {
+    let launch = |program: &str, names, env| Launch {
+        argv: vec![program.to_string()],
+        names: Some(names),
+        env,
+    };
+    match desktop {
+        // Compositing is the biggest latency offender; KWin's is forced off
+        Desktop::Kde => launch(
+            "startplasma-x11",
+            ("KDE", "plasma"),
+            &[("KWIN_COMPOSE", "N")],
+        ),
+        // Without a seat from logind, gnome-session would guess Wayland
+        Desktop::Gnome => launch(
+            "gnome-session",
+            ("GNOME", "gnome"),
+            &[("XDG_SESSION_TYPE", "x11")],
+        ),
+        Desktop::I3 => launch("i3", ("i3", "i3"), &[]),
+        Desktop::Custom(command) => Launch {
+            argv: vec!["sh".to_string(), "-c".to_string(), command.clone()],
+            names: None,
+            env: &[],
+        },
+        Desktop::Xfce | Desktop::Auto | Desktop::Openbox => {
+            launch("xfce4-session", ("XFCE", "xfce"), &[])
         }
     }
+}
 
-    // Add mode to the output (may already be added)
-    let addmode_output = Command::new("xrandr")
-        .env("DISPLAY", x_display)
-        .args(["--addmode", "DUMMY0", &mode_name])
-        .output()
-        .context("Failed to run xrandr --addmode")?;
-    if !addmode_output.status.success() {
-        let stderr = String::from_utf8_lossy(&addmode_output.stderr);
-        if !stderr.contains("already exists") {
-            warn!("xrandr --addmode DUMMY0 {mode_name} failed: {stderr}");
+/// Apply a desktop scale factor to a running display: `Xft.dpi` in the X
+/// resource database (for Xlib/Qt apps and new GTK processes) and the
+/// xsettings `Xft/DPI` and `Gdk/WindowScalingFactor`, which xfsettingsd
+/// pushes live to running GTK apps.
+pub fn set_display_scale(x_display: &str, scale: f64) -> Result<()> {
+    let (window_scale, xft_dpi) = dpi_settings(scale);
+    merge_xft_dpi(x_display, (96.0 * scale).round() as u32)?;
+
+    let dbus_addr = find_dbus_address_for_display(x_display);
+    for (prop, value) in [
+        ("/Xft/DPI", xft_dpi),
+        ("/Gdk/WindowScalingFactor", window_scale),
+    ] {
+        let mut cmd = Command::new("xfconf-query");
+        cmd.env("DISPLAY", x_display).args([
+            "-c",
+            "xsettings",
+            "-p",
+            prop,
+            "-n",
+            "-t",
+            "int",
+            "-s",
+            &value.to_string(),
+        ]);
+        if let Some(ref addr) = dbus_addr {
+            cmd.env("DBUS_SESSION_BUS_ADDRESS", addr);
+        }
+        match cmd.output() {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                warn!(prop, "xfconf-query failed: {stderr}");
+            }
+            Err(e) => warn!(prop, "Failed to run xfconf-query: {e}"),
         }
     }
 
-    // Switch to the new mode
-    let output = Command::new("xrandr")
+    info!(
+        x_display,
+        scale, window_scale, xft_dpi, "Display scale applied"
+    );
+    Ok(())
+}
+
+/// Merge `Xft.dpi` into the display's X resource database via `xrdb`.
+fn merge_xft_dpi(x_display: &str, dpi: u32) -> Result<()> {
+    use std::io::Write;
+
+    let mut child = Command::new("xrdb")
+        .env("DISPLAY", x_display)
+        .args(["-merge", "-nocpp"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to run xrdb")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "Xft.dpi: {dpi}");
+    }
+    let status = child.wait().context("Failed to wait for xrdb")?;
+    if !status.success() {
+        bail!("xrdb -merge exited with {status}");
+    }
+    Ok(())
+}
+
+/// Screen lockers in order of preference. xflock4 itself picks whichever
+/// locker the XFCE session is configured for.
+const SCREEN_LOCKERS: &[&[&str]] = &[
+    &["xflock4"],
+    &["xfce4-screensaver-command", "--lock"],
+    &["xscreensaver-command", "-lock"],
+];
+
+/// Lockers that can end a lock by command. xscreensaver can't: its
+/// `-deactivate` only brings up the password dialog.
+const SCREEN_UNLOCKERS: &[&[&str]] = &[&["xfce4-screensaver-command", "--deactivate"]];
+
+/// Run `argv` on the session's display and bus; true if it succeeded.
+fn run_in_session(x_display: &str, dbus_addr: Option<&str>, argv: &[&str]) -> bool {
+    let mut cmd = Command::new(argv[0]);
+    cmd.args(&argv[1..])
         .env("DISPLAY", x_display)
-        .args(["--output", "DUMMY0", "--mode", &mode_name])
-        .output()
-        .context("Failed to run xrandr --output")?;
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Some(addr) = dbus_addr {
+        cmd.env("DBUS_SESSION_BUS_ADDRESS", addr);
+    }
+    cmd.status().is_ok_and(|s| s.success())
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("Failed to set resolution to {mode_name}: {stderr}");
+/// Blank the display and lock the session with the first locker that
+/// accepts the request. Blanking alone still happens without a locker.
+pub fn lock_screen(x_display: &str) -> Result<()> {
+    let dbus_addr = find_dbus_address_for_display(x_display);
+    if !run_in_session(x_display, dbus_addr.as_deref(), &["xset", "s", "activate"]) {
+        warn!(x_display, "xset s activate failed, display not blanked");
+    }
+    for locker in SCREEN_LOCKERS {
+        if which_exists(locker[0]) && run_in_session(x_display, dbus_addr.as_deref(), locker) {
+            info!(x_display, locker = locker[0], "Session locked");
+            return Ok(());
+        }
+    }
+    bail!("No screen locker accepted the lock request (install xfce4-screensaver or xscreensaver)")
+}
+
+/// Pop up a desktop notification, e.g. a message from an admin.
+pub fn notify(x_display: &str, summary: &str, body: &str) -> Result<()> {
+    let dbus_addr = find_dbus_address_for_display(x_display);
+    let argv = [
+        "notify-send",
+        "--app-name=Beam",
+        "--urgency=critical",
+        "--",
+        summary,
+        body,
+    ];
+    if !run_in_session(x_display, dbus_addr.as_deref(), &argv) {
+        bail!("notify-send failed (install libnotify-bin)");
+    }
+    Ok(())
+}
+
+/// End a screen lock without the password prompt and wake the display.
+pub fn unlock_screen(x_display: &str) -> Result<()> {
+    let dbus_addr = find_dbus_address_for_display(x_display);
+    let unlocked = SCREEN_UNLOCKERS.iter().any(|unlocker| {
+        which_exists(unlocker[0]) && run_in_session(x_display, dbus_addr.as_deref(), unlocker)
+    });
+    run_in_session(x_display, dbus_addr.as_deref(), &["xset", "s", "reset"]);
+    if !unlocked {
+        bail!("No screen locker that supports unlocking by command (install xfce4-screensaver)");
+    }
+    info!(x_display, "Session unlocked");
+    Ok(())
+}
+
+/// RandR output of the dummy driver's virtual monitor
+const OUTPUT_NAME: &[u8] = b"DUMMY0";
+
+/// RandR monitor of the second screen. The dummy driver has one output,
+/// so the second screen is a monitor without one, as `xrandr
+/// --setmonitor` makes: a region of the screen right of the first, which
+/// desktops lay panels and maximized windows out on like any monitor.
+const SECOND_MONITOR_NAME: &[u8] = b"BEAM-2";
+
+/// What the virtual monitor is set to besides its size: the session's
+/// preferred modes and the browser's `DisplaySettings`.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayLayout {
+    /// Preferred modes; a resize to one of their sizes uses its rate
+    pub modes: Vec<DisplayMode>,
+    /// Refresh rate the browser asked for, over the preferred modes' own
+    pub refresh: Option<u32>,
+    pub rotation: Rotation,
+    /// Size of the second monitor (`InputEvent::ScreenAdd`), if attached
+    pub second_screen: Option<(u32, u32)>,
+}
+
+impl DisplayLayout {
+    /// The mode behind a `width` x `height` screen: the screen's size,
+    /// turned back if the monitor is on its side, at the rate asked for.
+    pub fn mode_for(&self, width: u32, height: u32) -> DisplayMode {
+        let (width, height) = if self.rotation.is_portrait() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let refresh = self.refresh.unwrap_or_else(|| {
+            self.modes
+                .iter()
+                .find(|m| (m.width, m.height) == (width, height))
+                .map_or(DEFAULT_REFRESH_HZ, |m| m.refresh)
+        });
+        DisplayMode {
+            width,
+            height,
+            refresh,
+        }
+    }
+}
+
+/// RandR name of `mode`: `WxH` at the default rate, as the xorg.conf
+/// Modeline is named, otherwise `WxH_HZ` as cvt names them.
+fn mode_name(mode: &DisplayMode) -> String {
+    match mode.refresh {
+        DEFAULT_REFRESH_HZ => format!("{}x{}", mode.width, mode.height),
+        refresh => format!("{}x{}_{refresh}", mode.width, mode.height),
+    }
+}
+
+/// Change display resolution through RandR. Standalone function that only
+/// needs the X display string (e.g. ":10"), so it can be called from the
+/// capture thread without owning a VirtualDisplay reference.
+pub fn set_display_resolution(x_display: &str, width: u32, height: u32) -> Result<()> {
+    set_display_layout(x_display, width, height, &DisplayLayout::default())
+}
+
+/// Make the screen `width` x `height` with the mode, refresh rate and
+/// rotation `layout` gives it, and place its second monitor right of it.
+///
+/// Does what `xrandr --newmode`, `--addmode` and `--output DUMMY0 --mode
+/// --rate --rotate` do: creates the mode unless the server has it already,
+/// adds it to the output, and resizes the screen around the CRTC. The
+/// screen is made wide enough for the second monitor as well.
+pub fn set_display_layout(
+    x_display: &str,
+    width: u32,
+    height: u32,
+    layout: &DisplayLayout,
+) -> Result<()> {
+    let display_mode = layout.mode_for(width, height);
+    let modeline = Modeline::new(
+        display_mode.width,
+        display_mode.height,
+        display_mode.refresh,
+    )?;
+    let mode_name = mode_name(&display_mode);
+    let rotation = match layout.rotation {
+        Rotation::Normal => randr::Rotation::ROTATE0,
+        Rotation::Left => randr::Rotation::ROTATE90,
+        Rotation::Inverted => randr::Rotation::ROTATE180,
+        Rotation::Right => randr::Rotation::ROTATE270,
+    };
+    let (conn, screen_num) =
+        RustConnection::connect(Some(x_display)).context("Failed to connect to X display")?;
+    let screen = &conn.setup().roots[screen_num];
+    let root = screen.root;
+    let (screen_width, screen_height) = (screen.width_in_pixels, screen.height_in_pixels);
+
+    let resources = conn
+        .randr_get_screen_resources_current(root)?
+        .reply()
+        .context("RandR GetScreenResourcesCurrent failed")?;
+    let config_timestamp = resources.config_timestamp;
+    let (output, output_info) = find_output(&conn, &resources)?;
+    let mode = add_mode(&conn, root, &resources, output, &output_info, &display_mode)?;
+
+    let crtc = match output_info.crtc {
+        x11rb::NONE => *output_info
+            .crtcs
+            .first()
+            .context("Display output has no CRTC")?,
+        crtc => crtc,
+    };
+    let crtc_info = conn
+        .randr_get_crtc_info(crtc, config_timestamp)?
+        .reply()
+        .context("RandR GetCrtcInfo failed")?;
+    if !crtc_info.rotations.contains(rotation) {
+        bail!("The display driver can't rotate to {:?}", layout.rotation);
+    }
+
+    // The screen can't shrink under an enabled CRTC: switch it off first if
+    // it won't fit, as xrandr does
+    let (new_width, new_height) = if layout.rotation.is_portrait() {
+        (modeline.height, modeline.width)
+    } else {
+        (modeline.width, modeline.height)
+    };
+    let crtc_right = i32::from(crtc_info.x) + i32::from(crtc_info.width);
+    let crtc_bottom = i32::from(crtc_info.y) + i32::from(crtc_info.height);
+    if crtc_info.mode != x11rb::NONE
+        && (crtc_right > i32::from(new_width) || crtc_bottom > i32::from(new_height))
+    {
+        set_crtc(
+            &conn,
+            crtc,
+            &crtc_info,
+            config_timestamp,
+            x11rb::NONE,
+            randr::Rotation::ROTATE0,
+            &[],
+        )
+        .context("Failed to disable CRTC for resize")?;
+    }
+    let (total_width, total_height) = match layout.second_screen {
+        Some((second_width, second_height)) => {
+            let max = conn
+                .randr_get_screen_size_range(root)?
+                .reply()
+                .context("RandR GetScreenSizeRange failed")?;
+            let total = (
+                u32::from(new_width) + second_width,
+                u32::from(new_height).max(second_height),
+            );
+            if total.0 > u32::from(max.max_width) || total.1 > u32::from(max.max_height) {
+                bail!(
+                    "Both screens need {}x{}, over the display's {}x{}",
+                    total.0,
+                    total.1,
+                    max.max_width,
+                    max.max_height
+                );
+            }
+            (total.0 as u16, total.1 as u16)
+        }
+        None => {
+            // Gone before the screen shrinks away from under it
+            set_second_monitor(&conn, root, None)?;
+            (new_width, new_height)
+        }
+    };
+    if (total_width, total_height) != (screen_width, screen_height) {
+        conn.randr_set_screen_size(
+            root,
+            total_width,
+            total_height,
+            millimeters(total_width.into()),
+            millimeters(total_height.into()),
+        )?
+        .check()
+        .with_context(|| format!("RandR SetScreenSize {total_width}x{total_height} failed"))?;
+    }
+    set_crtc(
+        &conn,
+        crtc,
+        &crtc_info,
+        config_timestamp,
+        mode,
+        rotation,
+        &[output],
+    )
+    .with_context(|| format!("Failed to set display mode {mode_name}"))?;
+    if let Some((second_width, second_height)) = layout.second_screen {
+        set_second_monitor(&conn, root, Some((new_width, second_width, second_height)))?;
     }
 
     info!(
         x_display,
-        width, height, "Display resolution changed via xrandr"
+        width,
+        height,
+        refresh = display_mode.refresh,
+        rotation = ?layout.rotation,
+        second_screen = ?layout.second_screen,
+        "Display mode changed"
     );
     Ok(())
 }
 
+/// Physical size of `px` pixels at 96 DPI; the desktop's scale is set
+/// through Xft.dpi
+fn millimeters(px: u32) -> u32 {
+    px * 254 / 960
+}
+
+/// Define the second monitor, `(x, width, height)`, as `xrandr
+/// --setmonitor` does, or remove it as `--delmonitor` does.
+fn set_second_monitor(
+    conn: &RustConnection,
+    root: Window,
+    monitor: Option<(u16, u32, u32)>,
+) -> Result<()> {
+    let name = conn
+        .intern_atom(false, SECOND_MONITOR_NAME)?
+        .reply()
+        .context("InternAtom failed")?
+        .atom;
+    match monitor {
+        Some((x, width, height)) => {
+            let info = randr::MonitorInfo {
+                name,
+                primary: false,
+                automatic: false,
+                x: x as i16,
+                y: 0,
+                width: width as u16,
+                height: height as u16,
+                width_in_millimeters: millimeters(width),
+                height_in_millimeters: millimeters(height),
+                outputs: Vec::new(),
+            };
+            conn.randr_set_monitor(root, info)?
+                .check()
+                .context("RandR SetMonitor failed")?;
+        }
+        None => {
+            let monitors = conn
+                .randr_get_monitors(root, false)?
+                .reply()
+                .context("RandR GetMonitors failed")?;
+            if monitors.monitors.iter().any(|m| m.name == name) {
+                conn.randr_delete_monitor(root, name)?
+                    .check()
+                    .context("RandR DeleteMonitor failed")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Offer `modes` on the display's output, as `xrandr --newmode` and
+/// `--addmode` do, so the desktop's display settings list them.
+pub fn add_display_modes(x_display: &str, modes: &[DisplayMode]) -> Result<()> {
+    let (conn, screen_num) =
+        RustConnection::connect(Some(x_display)).context("Failed to connect to X display")?;
+    let root = conn.setup().roots[screen_num].root;
+    for (i, mode) in modes.iter().enumerate() {
+        if modes[..i].contains(mode) {
+            continue;
+        }
+        // Fresh resources each time, so the last mode created is found
+        let resources = conn
+            .randr_get_screen_resources_current(root)?
+            .reply()
+            .context("RandR GetScreenResourcesCurrent failed")?;
+        let (output, output_info) = find_output(&conn, &resources)?;
+        add_mode(&conn, root, &resources, output, &output_info, mode)?;
+    }
+    Ok(())
+}
+
+/// The server's id for `mode`, created and added to `output` if needed.
+fn add_mode(
+    conn: &RustConnection,
+    root: Window,
+    resources: &randr::GetScreenResourcesCurrentReply,
+    output: randr::Output,
+    output_info: &randr::GetOutputInfoReply,
+    mode: &DisplayMode,
+) -> Result<randr::Mode> {
+    let name = mode_name(mode);
+    let id = match find_mode(resources, name.as_bytes()) {
+        Some(id) => id,
+        None => {
+            let modeline = Modeline::new(mode.width, mode.height, mode.refresh)?;
+            conn.randr_create_mode(root, modeline.mode_info(name.len() as u16), name.as_bytes())?
+                .reply()
+                .with_context(|| format!("RandR CreateMode {name} failed"))?
+                .mode
+        }
+    };
+    if !output_info.modes.contains(&id) {
+        conn.randr_add_output_mode(output, id)?
+            .check()
+            .with_context(|| format!("RandR AddOutputMode {name} failed"))?;
+    }
+    Ok(id)
+}
+
+/// The dummy driver's output, or failing that the first connected one.
+fn find_output(
+    conn: &RustConnection,
+    resources: &randr::GetScreenResourcesCurrentReply,
+) -> Result<(randr::Output, randr::GetOutputInfoReply)> {
+    let mut connected = None;
+    for &output in &resources.outputs {
+        let info = conn
+            .randr_get_output_info(output, resources.config_timestamp)?
+            .reply()
+            .context("RandR GetOutputInfo failed")?;
+        if info.name == OUTPUT_NAME {
+            return Ok((output, info));
+        }
+        if connected.is_none() && info.connection == randr::Connection::CONNECTED {
+            connected = Some((output, info));
+        }
+    }
+    connected.context("No connected RandR output")
+}
+
+/// The id of the server's mode named `name`. Mode names are packed into
+/// one buffer, in the order of the modes.
+fn find_mode(
+    resources: &randr::GetScreenResourcesCurrentReply,
+    name: &[u8],
+) -> Option<randr::Mode> {
+    let mut names = resources.names.as_slice();
+    for mode in &resources.modes {
+        let (mode_name, rest) = names.split_at_checked(usize::from(mode.name_len))?;
+        if mode_name == name {
+            return Some(mode.id);
+        }
+        names = rest;
+    }
+    None
+}
+
+fn set_crtc(
+    conn: &RustConnection,
+    crtc: randr::Crtc,
+    info: &randr::GetCrtcInfoReply,
+    config_timestamp: u32,
+    mode: randr::Mode,
+    rotation: randr::Rotation,
+    outputs: &[randr::Output],
+) -> Result<()> {
+    let reply = conn
+        .randr_set_crtc_config(
+            crtc,
+            info.timestamp,
+            config_timestamp,
+            0,
+            0,
+            mode,
+            rotation,
+            outputs,
+        )?
+        .reply()
+        .context("RandR SetCrtcConfig failed")?;
+    if reply.status != randr::SetConfig::SUCCESS {
+        bail!("RandR SetCrtcConfig refused: {:?}", reply.status);
+    }
+    Ok(())
+}
+
+/// Paint the root window a solid color, as `xsetroot -solid` does.
+fn set_root_color(x_display: &str, rgb: u32) -> Result<()> {
+    let (conn, screen_num) =
+        RustConnection::connect(Some(x_display)).context("Failed to connect to X display")?;
+    let screen = &conn.setup().roots[screen_num];
+    let channel = |shift: u32| ((rgb >> shift) & 0xff) as u16 * 0x101;
+    let pixel = conn
+        .alloc_color(screen.default_colormap, channel(16), channel(8), channel(0))?
+        .reply()
+        .context("Failed to allocate root window color")?
+        .pixel;
+    conn.change_window_attributes(
+        screen.root,
+        &ChangeWindowAttributesAux::new().background_pixel(pixel),
+    )?;
+    conn.clear_area(false, screen.root, 0, 0, 0, 0)?;
+    conn.flush()?;
+    Ok(())
+}
+
 fn generate_xorg_config(width: u32, height: u32) -> String {
     // The dummy driver needs a Modeline for non-standard resolutions.
     // Without it, Xorg falls back to a default mode (e.g. 2048x1536)
     // when the requested resolution isn't a recognized standard mode.
     let modeline = generate_modeline(width, height, 60);
     // Allocate enough VRAM for up to 4K (3840x2160) so dynamic resolution
-    // changes via xrandr don't fail with BadMatch. The dummy driver needs
+    // changes don't fail with BadMatch. The dummy driver needs
     // VideoRam >= width*height*4/1024 for the LARGEST resolution, not just
     // the initial one. 256MB covers up to 8K.
     let vram: u32 = 262_144; // 256 MB in KB
@@ -928,21 +1674,85 @@ EndSection
     )
 }
 
+/// Display timings for one mode, as in an xorg.conf Modeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Modeline {
+    /// Pixel clock (Hz)
+    dot_clock: u32,
+    width: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    height: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+}
+
+impl Modeline {
+    /// Simplified CVT timings for `width` x `height` at `refresh` Hz.
+    fn new(width: u32, height: u32, refresh: u32) -> Result<Self> {
+        let (Ok(w), Ok(h)) = (u16::try_from(width), u16::try_from(height)) else {
+            bail!("Resolution {width}x{height} is out of range");
+        };
+        if w > u16::MAX - 160 || h > u16::MAX - 33 {
+            bail!("Resolution {width}x{height} is out of range");
+        }
+        let pixel_clock = width as f64 * height as f64 * refresh as f64 * 1.2;
+        Ok(Self {
+            dot_clock: pixel_clock as u32,
+            width: w,
+            hsync_start: w + 48,
+            hsync_end: w + 48 + 32,
+            htotal: w + 48 + 32 + 80,
+            height: h,
+            vsync_start: h + 3,
+            vsync_end: h + 3 + 5,
+            vtotal: h + 3 + 5 + 25,
+        })
+    }
+
+    fn mode_info(&self, name_len: u16) -> randr::ModeInfo {
+        randr::ModeInfo {
+            id: 0,
+            width: self.width,
+            height: self.height,
+            dot_clock: self.dot_clock,
+            hsync_start: self.hsync_start,
+            hsync_end: self.hsync_end,
+            htotal: self.htotal,
+            hskew: 0,
+            vsync_start: self.vsync_start,
+            vsync_end: self.vsync_end,
+            vtotal: self.vtotal,
+            name_len,
+            mode_flags: randr::ModeFlag::HSYNC_POSITIVE | randr::ModeFlag::VSYNC_POSITIVE,
+        }
+    }
+}
+
+impl std::fmt::Display for Modeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2} {} {} {} {} {} {} {} {} +hsync +vsync",
+            f64::from(self.dot_clock) / 1_000_000.0,
+            self.width,
+            self.hsync_start,
+            self.hsync_end,
+            self.htotal,
+            self.height,
+            self.vsync_start,
+            self.vsync_end,
+            self.vtotal,
+        )
+    }
+}
+
 fn generate_modeline(width: u32, height: u32, refresh: u32) -> String {
-    // Simplified CVT modeline calculation
-    let pixel_clock = (width as f64 * height as f64 * refresh as f64) / 1_000_000.0 * 1.2;
-    format!(
-        "{:.2} {} {} {} {} {} {} {} {} +hsync +vsync",
-        pixel_clock,
-        width,
-        width + 48,
-        width + 48 + 32,
-        width + 48 + 32 + 80,
-        height,
-        height + 3,
-        height + 3 + 5,
-        height + 3 + 5 + 25,
-    )
+    Modeline::new(width, height, refresh)
+        .map(|m| m.to_string())
+        .unwrap_or_default()
 }
 
 fn is_display_running(display_num: u32) -> bool {
@@ -998,6 +1808,22 @@ fn is_snap_binary(program: &str) -> bool {
 
 /// Find the first non-snap binary from a list of candidates.
 /// Snap apps fail in Beam sessions (no logind session, no snap env vars).
+/// Web browsers in order of preference for the session.
+const BROWSER_CANDIDATES: &[&str] = &[
+    "firefox-esr",
+    "google-chrome-stable",
+    "google-chrome",
+    "chromium-browser",
+    "firefox",
+    "chromium",
+    "epiphany-browser",
+];
+
+/// The session's web browser: the first installed non-snap candidate.
+pub(crate) fn detect_browser() -> Option<&'static str> {
+    find_non_snap_app(BROWSER_CANDIDATES)
+}
+
 fn find_non_snap_app(candidates: &[&'static str]) -> Option<&'static str> {
     candidates
         .iter()
@@ -1009,7 +1835,7 @@ fn find_non_snap_app(candidates: &[&'static str]) -> Option<&'static str> {
 /// the given display. dbus-launch sets this in child process environments,
 /// but doesn't always export it as an X11 root window property. We read it
 /// from /proc/<pid>/environ of the panel process.
-fn find_dbus_address_for_display(x_display: &str) -> Option<String> {
+pub(crate) fn find_dbus_address_for_display(x_display: &str) -> Option<String> {
     let output = Command::new("pgrep")
         .arg("-x")
         .arg("xfce4-panel")
@@ -1059,7 +1885,7 @@ mod tests {
     #[test]
     fn xorg_config_has_generous_vram_for_dynamic_resize() {
         // Even at a small initial resolution, VRAM must be large enough
-        // for fullscreen (e.g. 4K). Otherwise xrandr --output fails with
+        // for fullscreen (e.g. 4K). Otherwise switching modes fails with
         // BadMatch when the user enters fullscreen.
         let config = generate_xorg_config(800, 600);
         assert!(
@@ -1100,6 +1926,64 @@ mod tests {
         assert_eq!(parts[10], "+vsync");
     }
 
+    #[test]
+    fn layout_gives_the_mode_behind_the_screen() {
+        let mode = |s| DisplayMode::parse(s).unwrap();
+        let mut layout = DisplayLayout {
+            modes: vec![mode("2560x1440@144"), mode("1920x1080@75")],
+            ..Default::default()
+        };
+        assert_eq!(layout.mode_for(1920, 1080), mode("1920x1080@75"));
+        assert_eq!(layout.mode_for(1280, 720), mode("1280x720@60"));
+        assert_eq!(mode_name(&layout.mode_for(1920, 1080)), "1920x1080_75");
+        assert_eq!(mode_name(&layout.mode_for(1280, 720)), "1280x720");
+
+        // A portrait screen is a landscape mode turned on its side
+        layout.rotation = Rotation::Left;
+        assert_eq!(layout.mode_for(1440, 2560), mode("2560x1440@144"));
+
+        // The browser's rate wins over the preferred modes'
+        layout.refresh = Some(30);
+        assert_eq!(layout.mode_for(1440, 2560), mode("2560x1440@30"));
+    }
+
+    #[test]
+    fn modeline_mode_info_matches_the_xorg_modeline() {
+        let modeline = Modeline::new(1920, 1080, 60).unwrap();
+        let info = modeline.mode_info(9);
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.htotal, 2080);
+        assert_eq!(info.vtotal, 1113);
+        assert_eq!(info.dot_clock, 149_299_200);
+        assert_eq!(
+            modeline.to_string(),
+            "149.30 1920 1968 2000 2080 1080 1083 1088 1113 +hsync +vsync"
+        );
+        assert!(Modeline::new(70_000, 1080, 60).is_err());
+    }
+
+    #[test]
+    fn find_mode_walks_packed_names() {
+        let mode = |id, name: &str| randr::ModeInfo {
+            id,
+            name_len: name.len() as u16,
+            ..Modeline::new(800, 600, 60).unwrap().mode_info(0)
+        };
+        let resources = randr::GetScreenResourcesCurrentReply {
+            sequence: 0,
+            length: 0,
+            timestamp: 0,
+            config_timestamp: 0,
+            crtcs: vec![],
+            outputs: vec![],
+            modes: vec![mode(7, "800x600"), mode(9, "1920x1080")],
+            names: b"800x6001920x1080".to_vec(),
+        };
+        assert_eq!(find_mode(&resources, b"1920x1080"), Some(9));
+        assert_eq!(find_mode(&resources, b"800x600"), Some(7));
+        assert_eq!(find_mode(&resources, b"1920x108"), None);
+    }
+
     #[test]
     fn modeline_dimensions_are_correct() {
         let ml = generate_modeline(1800, 1168, 60);
@@ -1159,6 +2043,63 @@ mod tests {
         assert_eq!(h, 1080);
     }
 
+    #[test]
+    fn fit_within_keeps_the_aspect_ratio() {
+        assert_eq!(fit_within(1920, 1080, 1280, 1024), (1280, 720));
+        assert_eq!(fit_within(1920, 1080, 2560, 900), (1600, 900));
+        // Odd results round down to even
+        assert_eq!(fit_within(1920, 1080, 1002, 1000), (1002, 562));
+        // A larger viewport is left to the browser
+        assert_eq!(fit_within(1920, 1080, 3840, 2160), (1920, 1080));
+    }
+
+    #[test]
+    fn normalize_scale_snaps_and_clamps() {
+        assert_eq!(normalize_scale(1.0), 1.0);
+        assert_eq!(normalize_scale(1.4999), 1.5);
+        assert_eq!(normalize_scale(2.0), 2.0);
+        assert_eq!(normalize_scale(0.5), 1.0);
+        assert_eq!(normalize_scale(9.0), 4.0);
+        assert_eq!(normalize_scale(f64::NAN), 1.0);
+        assert_eq!(normalize_scale(f64::INFINITY), 1.0);
+    }
+
+    #[test]
+    fn scale_dimensions_converts_to_physical() {
+        assert_eq!(scale_dimensions(1280, 720, 1.0), (1280, 720));
+        assert_eq!(scale_dimensions(1280, 720, 1.5), (1920, 1080));
+        assert_eq!(scale_dimensions(1920, 1080, 2.0), (3840, 2160));
+    }
+
+    #[test]
+    fn scale_dimensions_stays_within_8k() {
+        // 3840x2400 at 2x would be 7680x4800; reduce scale to fit 4320 height
+        let (w, h) = scale_dimensions(3840, 2400, 2.0);
+        assert!(w <= 7680 && h <= 4320, "{w}x{h} exceeds 8K");
+        assert_eq!(h, 4320);
+    }
+
+    #[test]
+    fn dpi_settings_split_integer_and_fraction() {
+        assert_eq!(dpi_settings(1.0), (1, 96));
+        assert_eq!(dpi_settings(1.5), (1, 144));
+        assert_eq!(dpi_settings(2.0), (2, 96));
+        assert_eq!(dpi_settings(2.5), (2, 120));
+    }
+
+    #[test]
+    fn desktops_launch_their_session() {
+        let kde = launch_for(&Desktop::Kde);
+        assert_eq!(kde.argv, ["startplasma-x11"]);
+        assert_eq!(kde.names, Some(("KDE", "plasma")));
+        assert!(kde.env.contains(&("KWIN_COMPOSE", "N")));
+
+        // A custom command runs as written, naming no desktop
+        let custom = launch_for(&Desktop::parse("exec lxqt-session --debug"));
+        assert_eq!(custom.argv, ["sh", "-c", "exec lxqt-session --debug"]);
+        assert_eq!(custom.names, None);
+    }
+
     #[test]
     fn clamp_resize_even_after_max_clamp() {
         // If max bound produces an odd number, still round to even
@@ -1166,4 +2107,29 @@ mod tests {
         assert_eq!(w, 1920);
         assert_eq!(h, 1080);
     }
+
+    #[test]
+    fn handed_over_display_is_stopped_by_whoever_adopts_it() {
+        let sleep = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = sleep.id();
+        let display = VirtualDisplay {
+            display_num: 9_999,
+            xorg_child: Some(Process::Spawned(sleep)),
+            desktop_child: None,
+            pulse_child: None,
+            pipewire_children: Vec::new(),
+            cursor_child: None,
+            cleanup_config: None,
+        };
+
+        let handover = display.hand_over();
+        assert!(std::path::Path::new(&format!("/proc/{pid}")).exists());
+        let json = serde_json::to_string(&handover).unwrap();
+        let handover: DisplayHandover = serde_json::from_str(&json).unwrap();
+        assert_eq!(handover.xorg, Some(pid));
+
+        drop(VirtualDisplay::adopt(handover));
+        // Stopped and reaped
+        assert!(!std::path::Path::new(&format!("/proc/{pid}")).exists());
+    }
 }
@@ -0,0 +1,191 @@
+//! Consumer half of the capture pipeline. The capture thread grabs a frame
+//! into the buffer pool and hands it to the encoder without waiting for
+//! the result; this thread waits for the encoder's output instead and
+//! queues it for the video send loop. A slow encode of one frame then no
+//! longer delays the capture of the next.
+
+use crate::encoder::EncodedOutput;
+use crate::frame_queue::{EncodedFrame, FrameSender, Pushed};
+use crate::h264;
+use crate::pipeline_stats::{PipelineTimer, Stage};
+use crate::quality::LinkStats;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Frames remembered between submission and output. Encoders drop frames
+/// under load, so entries that never come out are evicted by later ones.
+const MAX_IN_FLIGHT: usize = 16;
+
+/// How often to look for a recreated encoder's output while the current
+/// one is quiet
+const OUTPUT_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Submitted {
+    pts: u64,
+    captured_at: Instant,
+    submitted_at: Instant,
+}
+
+/// Frames handed to the encoder, matched to its output by PTS.
+#[derive(Default)]
+pub(crate) struct SubmitLog {
+    frames: Mutex<VecDeque<Submitted>>,
+}
+
+impl SubmitLog {
+    pub(crate) fn record(&self, pts: u64, captured_at: Instant, submitted_at: Instant) {
+        let mut frames = self.frames.lock().unwrap_or_else(|e| e.into_inner());
+        if frames.len() == MAX_IN_FLIGHT {
+            frames.pop_front();
+        }
+        frames.push_back(Submitted {
+            pts,
+            captured_at,
+            submitted_at,
+        });
+    }
+
+    /// The submission `pts` was encoded from. Earlier frames the encoder
+    /// dropped are forgotten on the way.
+    fn take(&self, pts: u64) -> Option<Submitted> {
+        let mut frames = self.frames.lock().unwrap_or_else(|e| e.into_inner());
+        while frames.front()?.pts < pts {
+            frames.pop_front();
+        }
+        if frames.front()?.pts == pts {
+            frames.pop_front()
+        } else {
+            None
+        }
+    }
+}
+
+/// Encoder output totals, read by the capture thread for its heartbeat and
+/// the bandwidth guard of the sharp and lossless presets.
+#[derive(Default)]
+pub(crate) struct OutputCounters {
+    pub frames: AtomicU64,
+    pub bytes: AtomicU64,
+}
+
+pub(crate) struct OutputCtx {
+    /// Output of each encoder the capture thread creates, in order
+    pub outputs: mpsc::Receiver<mpsc::Receiver<EncodedOutput>>,
+    pub frames: FrameSender,
+    pub submitted: Arc<SubmitLog>,
+    pub counters: Arc<OutputCounters>,
+    pub timer: Arc<Mutex<PipelineTimer>>,
+    pub link_stats: Arc<LinkStats>,
+}
+
+/// Forward encoded frames until the capture thread or the send loop is gone.
+pub(crate) fn run_encoded_output(ctx: OutputCtx) {
+    let Ok(mut output) = ctx.outputs.recv() else {
+        return;
+    };
+    let mut first_logged = false;
+    loop {
+        // A recreated encoder replaces the one before it
+        if let Ok(next) = ctx.outputs.try_recv() {
+            output = next;
+            first_logged = false;
+        }
+        let encoded = match output.recv_timeout(OUTPUT_POLL) {
+            Ok(encoded) => encoded,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => match ctx.outputs.recv() {
+                Ok(next) => {
+                    output = next;
+                    first_logged = false;
+                    continue;
+                }
+                Err(_) => return,
+            },
+        };
+        let output_at = Instant::now();
+        ctx.counters.frames.fetch_add(1, Ordering::Relaxed);
+        ctx.counters
+            .bytes
+            .fetch_add(encoded.data.len() as u64, Ordering::Relaxed);
+        if !first_logged {
+            info!(size = encoded.data.len(), "First H.264 frame from encoder");
+            first_logged = true;
+        }
+
+        let submitted = encoded.pts.and_then(|pts| ctx.submitted.take(pts));
+        let frame = EncodedFrame {
+            keyframe: h264::h264_contains_idr(&encoded.data),
+            data: encoded.data,
+            captured_at: submitted.map_or(output_at, |s| s.captured_at),
+            queued_at: Instant::now(),
+        };
+        let pushed = ctx.frames.push(frame);
+        {
+            let mut timer = ctx.timer.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(s) = submitted {
+                timer.record(Stage::Drain, output_at.duration_since(s.submitted_at));
+            }
+            if matches!(pushed, Pushed::DroppedOldest | Pushed::DroppedNew) {
+                timer.record_queue_drop();
+            }
+        }
+        match pushed {
+            Pushed::Queued => {}
+            Pushed::DroppedOldest | Pushed::DroppedNew => {
+                ctx.link_stats.record_dropped();
+                debug!("Dropped a P-frame (send loop behind, prioritizing latency)");
+            }
+            Pushed::Closed => {
+                info!("Encoded frame channel closed, stopping encoded output");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn submissions_match_output_by_pts() {
+        let log = SubmitLog::default();
+        let t0 = Instant::now();
+        for pts in [10, 20, 30] {
+            log.record(pts, t0, t0 + Duration::from_nanos(pts));
+        }
+        assert_eq!(log.take(10).map(|s| s.pts), Some(10));
+        // The encoder dropped 20
+        assert_eq!(
+            log.take(30).map(|s| s.submitted_at),
+            Some(t0 + Duration::from_nanos(30))
+        );
+        assert_eq!(log.take(40), None);
+    }
+
+    #[test]
+    fn unmatched_output_leaves_later_submissions() {
+        let log = SubmitLog::default();
+        let t0 = Instant::now();
+        log.record(20, t0, t0);
+        // Output from before the log started (e.g. a recreated encoder)
+        assert_eq!(log.take(15), None);
+        assert_eq!(log.take(20).map(|s| s.pts), Some(20));
+    }
+
+    #[test]
+    fn log_is_bounded() {
+        let log = SubmitLog::default();
+        let t0 = Instant::now();
+        for pts in 0..(MAX_IN_FLIGHT as u64 + 4) {
+            log.record(pts, t0, t0);
+        }
+        assert_eq!(log.take(3), None);
+        assert_eq!(log.take(4).map(|s| s.pts), Some(4));
+    }
+}
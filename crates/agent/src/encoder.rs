@@ -1,10 +1,13 @@
-use crate::capture::PooledFrame;
+use crate::capture::{DirtyRect, PooledFrame};
 use anyhow::{Context, bail};
+use beam_protocol::{EncoderTuning, QualityPreset};
 use gstreamer::prelude::*;
 use gstreamer::{self as gst, ClockTime, ElementFactory, FlowError};
 use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 
 /// Detected encoder type, exposed so peer.rs can register the matching H.264 profile.
@@ -15,28 +18,279 @@ pub enum EncoderType {
     Software,
 }
 
+impl EncoderType {
+    /// Whether a ForceKeyUnit event reliably yields an IDR on the next
+    /// frame. nvh264enc with an infinite GOP doesn't always honour it, so
+    /// a reconnect recreates that pipeline instead.
+    pub fn honors_force_key_unit(self) -> bool {
+        self != EncoderType::Nvidia
+    }
+}
+
+/// How the encoder is set up for a `QualityPreset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PresetParams {
+    /// Share of the bitrate rate control aims for, in percent
+    bitrate_percent: u32,
+    /// Highest quantizer rate control may pick, instead of the encoder's
+    qp_max: Option<u32>,
+    /// Constant quantizer, replacing rate control
+    constant_qp: Option<u32>,
+}
+
+impl PresetParams {
+    fn of(preset: QualityPreset) -> Self {
+        let params = Self {
+            bitrate_percent: 100,
+            qp_max: None,
+            constant_qp: None,
+        };
+        match preset {
+            QualityPreset::Smooth => Self {
+                bitrate_percent: SMOOTH_BITRATE_PERCENT,
+                qp_max: Some(H264_MAX_QP),
+                ..params
+            },
+            QualityPreset::Balanced => params,
+            QualityPreset::Sharp => Self {
+                qp_max: Some(SHARP_QP_MAX),
+                ..params
+            },
+            QualityPreset::Lossless => Self {
+                constant_qp: Some(LOSSLESS_QP),
+                ..params
+            },
+        }
+    }
+}
+
+/// Whether `preset` lets the encoder go over its bitrate, so the capture
+/// thread has to watch the output rate.
+pub fn may_exceed_bitrate(preset: QualityPreset) -> bool {
+    matches!(preset, QualityPreset::Sharp | QualityPreset::Lossless)
+}
+
+/// The rate such a preset may not stay over: the configured quality
+/// ceiling, held to the bandwidth cap if that is lower (either 0 = none).
+pub fn quality_ceiling(ceiling: u32, cap: u32) -> Option<u32> {
+    match (ceiling, cap) {
+        (0, 0) => None,
+        (0, limit) | (limit, 0) => Some(limit),
+        (ceiling, cap) => Some(ceiling.min(cap)),
+    }
+}
+
+/// Chroma subsampling of the encoded stream (`video.chroma` in config).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Chroma {
+    /// 4:2:0, Main profile. Universally decodable.
+    #[default]
+    Yuv420,
+    /// 4:4:4, High 4:4:4 Predictive profile. Removes color fringing on
+    /// red/blue text. Supported by nvh264enc and x264enc.
+    Yuv444,
+}
+
+impl Chroma {
+    pub fn from_config(value: &str) -> Option<Self> {
+        match value {
+            "420" => Some(Self::Yuv420),
+            "444" => Some(Self::Yuv444),
+            _ => None,
+        }
+    }
+}
+
+/// Encoder selection and tuning fixed for the agent's lifetime (from CLI).
+#[derive(Debug, Clone)]
+pub struct EncoderSettings {
+    /// Forced encoder element name; None = auto-detect
+    pub preferred: Option<String>,
+    pub chroma: Chroma,
+    pub tuning: EncoderTuning,
+    /// Encoders that failed repeatedly this session and are skipped by
+    /// detection, even if `preferred`
+    pub failed: Vec<EncoderType>,
+}
+
+/// Pipeline errors within `FALLBACK_WINDOW` that trigger a downgrade to
+/// the next encoder in the chain instead of recreating the same one.
+const FALLBACK_ERROR_THRESHOLD: usize = 3;
+const FALLBACK_WINDOW: Duration = Duration::from_secs(60);
+
+/// Tracks pipeline errors of the current encoder to decide when recreating
+/// it is pointless (e.g. NVENC after a driver reset keeps failing).
+#[derive(Debug, Default)]
+pub struct EncoderHealth {
+    errors: VecDeque<Instant>,
+}
+
+impl EncoderHealth {
+    /// Record a pipeline error. Returns true if the encoder has now failed
+    /// often enough that the caller should fall back to another one.
+    pub fn record_error(&mut self, now: Instant) -> bool {
+        while let Some(&first) = self.errors.front() {
+            if now.duration_since(first) > FALLBACK_WINDOW {
+                self.errors.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.errors.push_back(now);
+        self.errors.len() >= FALLBACK_ERROR_THRESHOLD
+    }
+
+    /// Forget past errors (after switching encoders).
+    pub fn reset(&mut self) {
+        self.errors.clear();
+    }
+}
+
+/// `EncoderTuning` with the per-encoder defaults filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ResolvedTuning {
+    preset: String,
+    tune: Option<String>,
+    rc_mode: String,
+    /// None = infinite GOP; keyframes only when the browser asks for one
+    gop_size: Option<u32>,
+    lookahead: u32,
+    intra_refresh: bool,
+}
+
+impl ResolvedTuning {
+    fn new(encoder_type: EncoderType, tuning: &EncoderTuning) -> Self {
+        let (preset, tune, rc_mode, gop_size) = match encoder_type {
+            EncoderType::Nvidia => ("low-latency-hq", None, "cbr-ld-hq", None),
+            // vah264enc has no presets; target-usage 7 is its fastest mode
+            EncoderType::VaApi => ("7", None, "cbr", Some(60)),
+            EncoderType::Software => ("ultrafast", Some("zerolatency"), "cbr", Some(30)),
+        };
+        Self {
+            preset: tuning.preset.clone().unwrap_or_else(|| preset.into()),
+            tune: tuning.tune.clone().or_else(|| tune.map(Into::into)),
+            rc_mode: tuning.rc_mode.clone().unwrap_or_else(|| rc_mode.into()),
+            gop_size: tuning.gop_size.or(gop_size),
+            lookahead: tuning.lookahead.unwrap_or(0),
+            intra_refresh: tuning.intra_refresh,
+        }
+    }
+}
+
+/// QP offset applied to the dirty region via `GstVideoRegionOfInterestMeta`.
+/// Negative = more bits for the area that changed.
+const ROI_DELTA_QP: i32 = -6;
+
+/// Skip ROI hints when the dirty region covers most of the frame (full-screen
+/// video, scrolling): there is no static background to take bits from.
+const ROI_MAX_COVERAGE: f64 = 0.5;
+
+/// Quantizer used in lossless mode. QP 0 is only truly lossless in the
+/// High 4:4:4 Predictive profile, which the main-profile capsfilter rules
+/// out; QP 8 is visually indistinguishable for text and UI content.
+const LOSSLESS_QP: u32 = 8;
+
+/// Quantizer ceiling of the sharp preset: text edges survive, at the cost
+/// of bitrate bursts when much of the screen changes.
+const SHARP_QP_MAX: u32 = 18;
+
+/// The smooth preset aims below the bitrate, leaving headroom on the link.
+const SMOOTH_BITRATE_PERCENT: u32 = 75;
+
+/// Highest quantizer H.264 has: no ceiling at all.
+const H264_MAX_QP: u32 = 51;
+
+/// Where frames are scaled down to the browser's viewport when the
+/// session's display keeps a size of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scaler {
+    /// cudascale, handing nvh264enc frames already in GPU memory
+    Cuda,
+    /// vapostproc, which converts and scales for vah264enc in one pass
+    Va,
+    /// videoscale on the CPU
+    Software,
+}
+
+impl Scaler {
+    /// The GPU scaler that feeds `encoder_type`, if its elements are
+    /// `available`, else videoscale. 4:4:4 on NVIDIA goes through
+    /// videoconvert on the CPU anyway.
+    fn pick(encoder_type: EncoderType, chroma: Chroma, available: impl Fn(&str) -> bool) -> Self {
+        match encoder_type {
+            EncoderType::Nvidia
+                if chroma == Chroma::Yuv420
+                    && available("cudaupload")
+                    && available("cudascale") =>
+            {
+                Self::Cuda
+            }
+            EncoderType::VaApi if available("vapostproc") => Self::Va,
+            _ => Self::Software,
+        }
+    }
+}
+
+/// One encoded access unit from the appsink.
+pub struct EncodedOutput {
+    /// PTS given to the raw frame in `encode_frame` (ns)
+    pub pts: Option<u64>,
+    pub data: Vec<u8>,
+}
+
 pub struct Encoder {
     pipeline: gst::Pipeline,
     appsrc: AppSrc,
-    encoded_rx: std::sync::Mutex<mpsc::Receiver<Vec<u8>>>,
+    /// Encoded access units with their PTS, until `take_output` hands the
+    /// receiver to the encoded output thread
+    encoded_rx: Option<mpsc::Receiver<EncodedOutput>>,
     _bus_watch: gst::bus::BusWatchGuard,
     /// Set by the GStreamer bus watch on pipeline error. The capture thread
     /// checks this each iteration and recreates the encoder if set.
     pipeline_error: Arc<AtomicBool>,
+    encoder_type: EncoderType,
+    encoder_name: String,
+    width: u32,
+    height: u32,
+    /// Size of the encoded frames, when scaled down from `width` x `height`
+    scaled_to: Option<(u32, u32)>,
 }
 
 impl Encoder {
+    /// Encode `width` x `height` frames, scaled to `scaled_to` first if set.
     pub fn with_encoder_preference(
         width: u32,
         height: u32,
+        scaled_to: Option<(u32, u32)>,
         framerate: u32,
         bitrate: u32,
-        preferred_encoder: Option<&str>,
+        settings: &EncoderSettings,
+        quality: QualityPreset,
     ) -> anyhow::Result<Self> {
-        let (encoder_type, encoder_name) = detect_encoder(preferred_encoder)?;
+        let (encoder_type, encoder_name) =
+            detect_encoder(settings.preferred.as_deref(), &settings.failed)?;
+        let chroma = if settings.chroma == Chroma::Yuv444 && encoder_type == EncoderType::VaApi {
+            warn!("vah264enc does not support 4:4:4 chroma, falling back to 4:2:0");
+            Chroma::Yuv420
+        } else {
+            settings.chroma
+        };
+        let tuning = ResolvedTuning::new(encoder_type, &settings.tuning);
+        let scaled_to = scaled_to.filter(|&size| size != (width, height));
+        let scaler = scaled_to.map(|_| Scaler::pick(encoder_type, chroma, can_instantiate));
         info!(
             ?encoder_type,
-            encoder_name, width, height, framerate, bitrate, "Creating H.264 encoder pipeline"
+            ?quality,
+            ?chroma,
+            encoder_name,
+            width,
+            height,
+            ?scaled_to,
+            ?scaler,
+            framerate,
+            bitrate,
+            ?tuning,
+            "Creating H.264 encoder pipeline"
         );
 
         let pipeline = gst::Pipeline::new();
@@ -84,12 +338,24 @@ impl Encoder {
         appsrc.set_property("max-latency", 0i64);
 
         // encoder element
-        let encoder = build_encoder_element(encoder_type, &encoder_name, bitrate, framerate)?;
-
-        // capsfilter: force main profile for best quality/compression ratio.
+        let encoder = build_encoder_element(
+            encoder_type,
+            &encoder_name,
+            bitrate,
+            framerate,
+            quality,
+            &tuning,
+        )?;
+
+        // capsfilter: force main profile for best quality/compression ratio,
+        // or High 4:4:4 Predictive when full chroma is requested.
         // WebCodecs VideoDecoder handles all H.264 profiles natively.
+        let profile = match chroma {
+            Chroma::Yuv420 => "main",
+            Chroma::Yuv444 => "high-4:4:4",
+        };
         let profile_caps = gst::Caps::builder("video/x-h264")
-            .field("profile", "main")
+            .field("profile", profile)
             .build();
         let capsfilter = ElementFactory::make("capsfilter")
             .property("caps", &profile_caps)
@@ -116,8 +382,8 @@ impl Encoder {
 
         // appsink: pull encoded H.264 NAL units.
         // max-buffers=1 + drop=true: absolute minimum buffering for lowest latency.
-        // Combined with channel capacity 2 in main.rs, total pipeline depth is
-        // at most 3 frames (~25ms at 120fps).
+        // Combined with frame queue capacity 2 in main.rs, total pipeline depth
+        // is at most 3 frames (~25ms at 120fps).
         // async=false: don't wait for clock sync on state changes.
         let appsink_elem = ElementFactory::make("appsink")
             .name("sink")
@@ -134,7 +400,7 @@ impl Encoder {
             .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
 
         // Channel to collect encoded frames
-        let (encoded_tx, encoded_rx) = mpsc::channel::<Vec<u8>>();
+        let (encoded_tx, encoded_rx) = mpsc::channel::<EncodedOutput>();
 
         appsink.set_callbacks(
             AppSinkCallbacks::builder()
@@ -142,7 +408,10 @@ impl Encoder {
                     let sample = sink.pull_sample().map_err(|_| FlowError::Eos)?;
                     let buffer = sample.buffer().ok_or(FlowError::Error)?;
                     let map = buffer.map_readable().map_err(|_| FlowError::Error)?;
-                    let _ = encoded_tx.send(map.to_vec());
+                    let _ = encoded_tx.send(EncodedOutput {
+                        pts: buffer.pts().map(|pts| pts.nseconds()),
+                        data: map.to_vec(),
+                    });
                     Ok(gst::FlowSuccess::Ok)
                 })
                 .build(),
@@ -158,59 +427,77 @@ impl Encoder {
         //   VideoDecoder codec string (avc1.4d0033). Without it, nvh264enc may
         //   output high profile, causing VideoDecoder decode errors.
         // Other:  appsrc(BGRx) → videoconvert → encoder → capsfilter → h264parse → appsink
-        match encoder_type {
-            EncoderType::Nvidia => {
-                pipeline
-                    .add_many([
-                        appsrc.upcast_ref(),
-                        &encoder,
-                        &capsfilter,
-                        &parser,
-                        &parse_capsfilter,
-                        appsink.upcast_ref(),
-                    ])
-                    .context("Failed to add elements to NVIDIA pipeline")?;
-                gst::Element::link_many([
-                    appsrc.upcast_ref(),
-                    &encoder,
-                    &capsfilter,
-                    &parser,
-                    &parse_capsfilter,
-                    appsink.upcast_ref(),
-                ])
-                .context("Failed to link NVIDIA pipeline")?;
-                info!(
-                    "NVIDIA pipeline: appsrc(BGRA) → nvh264enc → capsfilter(main) → h264parse → appsink"
-                );
+        // 4:4:4:  appsrc → videoconvert → capsfilter(Y444) → encoder → capsfilter(high-4:4:4) → ...
+        //   Both nvh264enc and x264enc pick their 4:4:4 path from Y444 input.
+        // Scaled: cudaupload → cudascale or vapostproc in place of
+        //   videoconvert, else videoscale after it, then a capsfilter with
+        //   the output size.
+        let mut elements: Vec<gst::Element> = vec![appsrc.clone().upcast()];
+        let make = |name: &str| {
+            ElementFactory::make(name)
+                .build()
+                .with_context(|| format!("Failed to create {name}"))
+        };
+        let mut raw_caps = gst::Caps::builder("video/x-raw");
+        let mut memory = None;
+        match scaler {
+            Some(Scaler::Cuda) => {
+                elements.extend([make("cudaupload")?, make("cudascale")?]);
+                memory = Some("memory:CUDAMemory");
             }
-            _ => {
-                let convert = ElementFactory::make("videoconvert")
-                    .build()
-                    .context("Failed to create videoconvert")?;
-                pipeline
-                    .add_many([
-                        appsrc.upcast_ref(),
-                        &convert,
-                        &encoder,
-                        &capsfilter,
-                        &parser,
-                        &parse_capsfilter,
-                        appsink.upcast_ref(),
-                    ])
-                    .context("Failed to add elements to pipeline")?;
-                gst::Element::link_many([
-                    appsrc.upcast_ref(),
-                    &convert,
-                    &encoder,
-                    &capsfilter,
-                    &parser,
-                    &parse_capsfilter,
-                    appsink.upcast_ref(),
-                ])
-                .context("Failed to link pipeline elements")?;
-                info!("Pipeline: appsrc(BGRx) → videoconvert → encoder → h264parse → appsink");
+            Some(Scaler::Va) => {
+                elements.push(make("vapostproc")?);
+                memory = Some("memory:VAMemory");
+            }
+            Some(Scaler::Software) | None => {
+                if encoder_type != EncoderType::Nvidia || chroma == Chroma::Yuv444 {
+                    elements.push(make("videoconvert")?);
+                }
+                if scaler.is_some() {
+                    elements.push(make("videoscale")?);
+                }
+                if chroma == Chroma::Yuv444 {
+                    raw_caps = raw_caps.field("format", "Y444");
+                }
             }
         }
+        if let Some((scaled_width, scaled_height)) = scaled_to {
+            raw_caps = raw_caps
+                .field("width", scaled_width as i32)
+                .field("height", scaled_height as i32);
+        }
+        if scaled_to.is_some() || chroma == Chroma::Yuv444 {
+            let mut raw_caps = raw_caps.build();
+            if let Some(memory) = memory {
+                raw_caps
+                    .get_mut()
+                    .expect("freshly-built caps should have unique ownership")
+                    .set_features_simple(Some(gst::CapsFeatures::new([memory])));
+            }
+            elements.push(
+                ElementFactory::make("capsfilter")
+                    .property("caps", &raw_caps)
+                    .build()
+                    .context("Failed to create raw video capsfilter")?,
+            );
+        }
+        elements.extend([
+            encoder,
+            capsfilter,
+            parser,
+            parse_capsfilter,
+            appsink.clone().upcast(),
+        ]);
+        pipeline
+            .add_many(&elements)
+            .context("Failed to add elements to pipeline")?;
+        gst::Element::link_many(&elements).context("Failed to link pipeline elements")?;
+        let description = elements
+            .iter()
+            .filter_map(|e| e.factory().map(|f| f.name().to_string()))
+            .collect::<Vec<_>>()
+            .join(" → ");
+        info!(pipeline = %description, profile, "Encoder pipeline built");
 
         // Set up bus watch for error monitoring.
         // The guard must be kept alive or the watch is removed.
@@ -268,22 +555,55 @@ impl Encoder {
         Ok(Self {
             pipeline,
             appsrc,
-            encoded_rx: std::sync::Mutex::new(encoded_rx),
+            encoded_rx: Some(encoded_rx),
             _bus_watch,
             pipeline_error,
+            encoder_type,
+            encoder_name,
+            width,
+            height,
+            scaled_to,
         })
     }
 
     /// Push a raw frame into the encoder. Takes ownership of a pooled frame
     /// buffer. When GStreamer finishes encoding, the PooledFrame is dropped
     /// and the backing memory is returned to the capture pool for reuse.
-    pub fn encode_frame(&self, frame: PooledFrame, pts: u64) -> anyhow::Result<()> {
+    ///
+    /// `dirty` is the XDamage region for this frame. With VA-API it is
+    /// attached as a region-of-interest meta with a negative delta-QP, so
+    /// the encoder spends more bits on changed content. Only the VA-API
+    /// encoders read `roi/vaapi` params; nvh264enc and x264enc get no ROI
+    /// hints.
+    pub fn encode_frame(
+        &self,
+        frame: PooledFrame,
+        pts: u64,
+        dirty: Option<DirtyRect>,
+    ) -> anyhow::Result<()> {
         let mut buffer = gst::Buffer::from_slice(frame);
         {
             let buffer_mut = buffer
                 .get_mut()
                 .expect("freshly-created GstBuffer should have unique ownership");
             buffer_mut.set_pts(ClockTime::from_nseconds(pts));
+            // Dirty rectangles are in captured pixels, not scaled ones
+            if let Some(rect) = dirty
+                && self.encoder_type == EncoderType::VaApi
+                && self.scaled_to.is_none()
+                && rect.coverage(self.width, self.height) < ROI_MAX_COVERAGE
+            {
+                let mut meta = gstreamer_video::VideoRegionOfInterestMeta::add(
+                    buffer_mut,
+                    "dirty",
+                    (rect.x, rect.y, rect.width, rect.height),
+                );
+                meta.add_param(
+                    gst::Structure::builder("roi/vaapi")
+                        .field("delta-qp", ROI_DELTA_QP)
+                        .build(),
+                );
+            }
         }
         self.appsrc
             .push_buffer(buffer)
@@ -297,6 +617,20 @@ impl Encoder {
         self.pipeline_error.load(Ordering::Relaxed)
     }
 
+    /// Size of the encoded frames.
+    pub fn output_size(&self) -> (u32, u32) {
+        self.scaled_to.unwrap_or((self.width, self.height))
+    }
+
+    pub fn encoder_type(&self) -> EncoderType {
+        self.encoder_type
+    }
+
+    /// GStreamer element name of the running encoder, e.g. "nvh264enc".
+    pub fn name(&self) -> &str {
+        &self.encoder_name
+    }
+
     /// Force the encoder to emit an IDR keyframe on the next frame.
     /// Call this on connection so the browser's VideoDecoder
     /// can start decoding immediately.
@@ -308,15 +642,10 @@ impl Encoder {
         info!("Forced IDR keyframe from encoder");
     }
 
-    pub fn pull_encoded(&self) -> anyhow::Result<Option<Vec<u8>>> {
-        let rx = self.encoded_rx.lock().unwrap_or_else(|e| e.into_inner());
-        match rx.try_recv() {
-            Ok(data) => Ok(Some(data)),
-            Err(mpsc::TryRecvError::Empty) => Ok(None),
-            Err(mpsc::TryRecvError::Disconnected) => {
-                bail!("Encoder pipeline disconnected")
-            }
-        }
+    /// The encoder's output, once. It disconnects when the encoder is
+    /// dropped.
+    pub fn take_output(&mut self) -> Option<mpsc::Receiver<EncodedOutput>> {
+        self.encoded_rx.take()
     }
 }
 
@@ -346,10 +675,15 @@ fn can_instantiate(name: &str) -> bool {
 /// Detect which encoder type is available without creating a full pipeline.
 /// Returns (encoder_type, encoder_element_name).
 pub fn detect_encoder_type(preferred: Option<&str>) -> anyhow::Result<(EncoderType, String)> {
-    detect_encoder(preferred)
+    detect_encoder(preferred, &[])
 }
 
-fn detect_encoder(preferred: Option<&str>) -> anyhow::Result<(EncoderType, String)> {
+/// Pick an encoder: the preferred one if usable, else the first working
+/// entry of the nvenc → vaapi → x264 chain. Types in `failed` are skipped.
+fn detect_encoder(
+    preferred: Option<&str>,
+    failed: &[EncoderType],
+) -> anyhow::Result<(EncoderType, String)> {
     // If user specified a preferred encoder, try it first
     if let Some(pref) = preferred {
         let enc_type = match pref {
@@ -358,7 +692,12 @@ fn detect_encoder(preferred: Option<&str>) -> anyhow::Result<(EncoderType, Strin
             "x264enc" => EncoderType::Software,
             _ => bail!("Unknown encoder: {pref}. Use nvh264enc, vah264enc, or x264enc."),
         };
-        if can_instantiate(pref) {
+        if failed.contains(&enc_type) {
+            warn!(
+                encoder = pref,
+                "Preferred encoder failed repeatedly, using fallback chain"
+            );
+        } else if can_instantiate(pref) {
             info!(encoder = pref, "Using preferred encoder from config");
             return Ok((enc_type, pref.to_string()));
         }
@@ -375,6 +714,9 @@ fn detect_encoder(preferred: Option<&str>) -> anyhow::Result<(EncoderType, Strin
     ];
 
     for (enc_type, name) in &candidates {
+        if failed.contains(enc_type) {
+            continue;
+        }
         if can_instantiate(name) {
             info!(encoder = name, "Found working encoder");
             return Ok((*enc_type, name.to_string()));
@@ -385,20 +727,57 @@ fn detect_encoder(preferred: Option<&str>) -> anyhow::Result<(EncoderType, Strin
     bail!("No H.264 encoder found. Install gstreamer plugins (good/bad/ugly).")
 }
 
+/// Set a tuning property from a config string. Unknown properties or enum
+/// values are logged and skipped rather than passed to
+/// `set_property_from_str`, which panics on them.
+fn set_tuning_property(elem: &gst::Element, property: &str, value: &str) {
+    let Some(pspec) = elem.find_property(property) else {
+        warn!(
+            property,
+            value, "Encoder has no such property, ignoring tuning"
+        );
+        return;
+    };
+    let valid = match pspec.downcast_ref::<gst::glib::ParamSpecEnum>() {
+        Some(spec) => {
+            let class = spec.enum_class();
+            class.value_by_nick(value).is_some() || class.value_by_name(value).is_some()
+        }
+        None => pspec.value_type() == gst::glib::Type::STRING || value.parse::<i64>().is_ok(),
+    };
+    if valid {
+        elem.set_property_from_str(property, value);
+    } else {
+        warn!(
+            property,
+            value, "Invalid encoder tuning value, keeping default"
+        );
+    }
+}
+
 fn build_encoder_element(
     encoder_type: EncoderType,
     name: &str,
     bitrate: u32,
     framerate: u32,
+    quality: QualityPreset,
+    tuning: &ResolvedTuning,
 ) -> anyhow::Result<gst::Element> {
+    let params = PresetParams::of(quality);
+    let bitrate = bitrate * params.bitrate_percent / 100;
+    // B-frames are never configurable: frame reordering adds at least one
+    // frame of latency and the browser decodes in display order.
     let elem = match encoder_type {
         EncoderType::Nvidia => ElementFactory::make(name)
-            .property_from_str("preset", "low-latency-hq")
-            .property_from_str("rc-mode", "cbr-ld-hq")
             .property("bitrate", bitrate)
-            .property("gop-size", i32::MAX)
+            .property(
+                "gop-size",
+                tuning
+                    .gop_size
+                    .map_or(i32::MAX, |g| g.min(i32::MAX as u32) as i32),
+            )
             .property("zerolatency", true)
-            .property("rc-lookahead", 0u32)
+            .property("rc-lookahead", tuning.lookahead)
             .property("bframes", 0u32)
             .property("strict-gop", true)
             .property("qp-max-i", 20i32)
@@ -407,22 +786,77 @@ fn build_encoder_element(
             .build()
             .context("Failed to create nvh264enc")?,
         EncoderType::VaApi => ElementFactory::make(name)
-            .property_from_str("rate-control", "cbr")
             .property("bitrate", bitrate)
-            .property("target-usage", 7u32)
-            .property("key-int-max", 60u32)
+            .property("key-int-max", tuning.gop_size.unwrap_or(60))
+            .property("b-frames", 0u32)
             .build()
             .context("Failed to create vah264enc")?,
         EncoderType::Software => ElementFactory::make(name)
-            .property_from_str("tune", "zerolatency")
-            .property_from_str("speed-preset", "ultrafast")
             .property("bitrate", bitrate)
-            .property("key-int-max", 30u32)
+            .property("key-int-max", tuning.gop_size.unwrap_or(30))
             .property("bframes", 0u32)
+            .property("rc-lookahead", tuning.lookahead as i32)
             .build()
             .context("Failed to create x264enc")?,
     };
 
+    // String knobs go through validation so a typo in the config degrades
+    // to the default instead of aborting the agent
+    let (preset_prop, rc_prop) = match encoder_type {
+        EncoderType::Nvidia => ("preset", "rc-mode"),
+        EncoderType::VaApi => ("target-usage", "rate-control"),
+        EncoderType::Software => ("speed-preset", "pass"),
+    };
+    set_tuning_property(&elem, preset_prop, &tuning.preset);
+    set_tuning_property(&elem, rc_prop, &tuning.rc_mode);
+    if let Some(ref tune) = tuning.tune {
+        set_tuning_property(&elem, "tune", tune);
+    }
+
+    // Intra refresh spreads the GOP's intra coding over its frames; the
+    // encoder still emits an IDR when one is forced
+    if tuning.intra_refresh {
+        match elem.find_property("intra-refresh") {
+            Some(pspec) if pspec.value_type() == gst::glib::Type::BOOL => {
+                elem.set_property("intra-refresh", true);
+            }
+            _ => warn!(
+                encoder = name,
+                "Encoder has no intra refresh, keeping periodic keyframes"
+            ),
+        }
+    }
+
+    if let Some(qp_max) = params.qp_max {
+        match encoder_type {
+            EncoderType::Nvidia => {
+                elem.set_property("qp-max-i", qp_max as i32);
+                elem.set_property("qp-max-p", qp_max as i32);
+            }
+            EncoderType::VaApi => elem.set_property("max-qp", qp_max),
+            EncoderType::Software => elem.set_property("qp-max", qp_max),
+        }
+    }
+
+    // Constant QP: the bitrate set above is ignored by the encoder
+    if let Some(qp) = params.constant_qp {
+        match encoder_type {
+            EncoderType::Nvidia => {
+                elem.set_property_from_str("rc-mode", "constqp");
+                elem.set_property("qp-const", qp as i32);
+            }
+            EncoderType::VaApi => {
+                elem.set_property_from_str("rate-control", "cqp");
+                elem.set_property("qpi", qp);
+                elem.set_property("qpp", qp);
+            }
+            EncoderType::Software => {
+                elem.set_property_from_str("pass", "quant");
+                elem.set_property("quantizer", qp);
+            }
+        }
+    }
+
     Ok(elem)
 }
 
@@ -451,4 +885,137 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn health_requests_fallback_after_repeated_errors() {
+        let mut health = EncoderHealth::default();
+        let t0 = Instant::now();
+        assert!(!health.record_error(t0));
+        assert!(!health.record_error(t0 + Duration::from_secs(10)));
+        assert!(health.record_error(t0 + Duration::from_secs(20)));
+
+        health.reset();
+        assert!(!health.record_error(t0 + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn health_forgets_errors_outside_window() {
+        let mut health = EncoderHealth::default();
+        let t0 = Instant::now();
+        assert!(!health.record_error(t0));
+        assert!(!health.record_error(t0 + Duration::from_secs(1)));
+        // First two errors have aged out: one-off glitches don't add up
+        assert!(!health.record_error(t0 + FALLBACK_WINDOW + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn tuning_defaults_per_encoder() {
+        let defaults = EncoderTuning::default();
+
+        let nv = ResolvedTuning::new(EncoderType::Nvidia, &defaults);
+        assert_eq!(nv.preset, "low-latency-hq");
+        assert_eq!(nv.rc_mode, "cbr-ld-hq");
+        assert_eq!(nv.gop_size, None);
+        assert_eq!(nv.lookahead, 0);
+        assert!(!nv.intra_refresh);
+
+        let va = ResolvedTuning::new(EncoderType::VaApi, &defaults);
+        assert_eq!(va.preset, "7");
+        assert_eq!(va.gop_size, Some(60));
+
+        let sw = ResolvedTuning::new(EncoderType::Software, &defaults);
+        assert_eq!(sw.preset, "ultrafast");
+        assert_eq!(sw.tune.as_deref(), Some("zerolatency"));
+        assert_eq!(sw.gop_size, Some(30));
+    }
+
+    #[test]
+    fn tuning_overrides_replace_defaults() {
+        let tuning = EncoderTuning {
+            preset: Some("veryfast".into()),
+            tune: None,
+            rc_mode: Some("qual".into()),
+            gop_size: Some(120),
+            lookahead: Some(4),
+            intra_refresh: true,
+        };
+        let sw = ResolvedTuning::new(EncoderType::Software, &tuning);
+        assert_eq!(sw.preset, "veryfast");
+        // Unset knobs keep the encoder's default
+        assert_eq!(sw.tune.as_deref(), Some("zerolatency"));
+        assert_eq!(sw.rc_mode, "qual");
+        assert_eq!(sw.gop_size, Some(120));
+        assert_eq!(sw.lookahead, 4);
+        assert!(sw.intra_refresh);
+    }
+
+    #[test]
+    fn scaler_follows_the_encoder() {
+        let all = |_: &str| true;
+        let none = |_: &str| false;
+        let no_cudascale = |name: &str| name != "cudascale";
+        assert_eq!(
+            Scaler::pick(EncoderType::Nvidia, Chroma::Yuv420, all),
+            Scaler::Cuda
+        );
+        assert_eq!(
+            Scaler::pick(EncoderType::Nvidia, Chroma::Yuv444, all),
+            Scaler::Software
+        );
+        assert_eq!(
+            Scaler::pick(EncoderType::Nvidia, Chroma::Yuv420, no_cudascale),
+            Scaler::Software
+        );
+        assert_eq!(
+            Scaler::pick(EncoderType::VaApi, Chroma::Yuv420, all),
+            Scaler::Va
+        );
+        assert_eq!(
+            Scaler::pick(EncoderType::VaApi, Chroma::Yuv420, none),
+            Scaler::Software
+        );
+        assert_eq!(
+            Scaler::pick(EncoderType::Software, Chroma::Yuv420, all),
+            Scaler::Software
+        );
+    }
+
+    #[test]
+    fn chroma_parses_config_values() {
+        assert_eq!(Chroma::from_config("420"), Some(Chroma::Yuv420));
+        assert_eq!(Chroma::from_config("444"), Some(Chroma::Yuv444));
+        assert_eq!(Chroma::from_config("422"), None);
+    }
+
+    #[test]
+    fn presets_map_to_encoder_parameters() {
+        let balanced = PresetParams::of(QualityPreset::Balanced);
+        assert_eq!(
+            (
+                balanced.bitrate_percent,
+                balanced.qp_max,
+                balanced.constant_qp
+            ),
+            (100, None, None)
+        );
+        assert!(PresetParams::of(QualityPreset::Smooth).bitrate_percent < 100);
+        assert!(PresetParams::of(QualityPreset::Sharp).qp_max < Some(H264_MAX_QP));
+        assert_eq!(
+            PresetParams::of(QualityPreset::Lossless).constant_qp,
+            Some(LOSSLESS_QP)
+        );
+        assert!(may_exceed_bitrate(QualityPreset::Sharp));
+        assert!(may_exceed_bitrate(QualityPreset::Lossless));
+        assert!(!may_exceed_bitrate(QualityPreset::Smooth));
+        assert!(!may_exceed_bitrate(QualityPreset::Balanced));
+    }
+
+    #[test]
+    fn quality_ceiling_is_held_to_the_bandwidth_cap() {
+        assert_eq!(quality_ceiling(0, 0), None);
+        assert_eq!(quality_ceiling(100_000, 0), Some(100_000));
+        assert_eq!(quality_ceiling(0, 8_000), Some(8_000));
+        assert_eq!(quality_ceiling(100_000, 8_000), Some(8_000));
+        assert_eq!(quality_ceiling(20_000, 50_000), Some(20_000));
+    }
 }
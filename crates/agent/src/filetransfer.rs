@@ -1,5 +1,6 @@
 use anyhow::{Context, Result, bail};
 use base64::Engine;
+use beam_protocol::{AgentMessage, FILE_CHUNK_BYTES};
 use std::collections::HashMap;
 use std::fs;
 use std::io::{Read, Write};
@@ -201,12 +202,11 @@ impl FileTransferManager {
         let canonical = match self.validate_download_path(path) {
             Ok(p) => p,
             Err(e) => {
-                let error_msg = serde_json::json!({
-                    "t": "fde",
-                    "id": id,
-                    "error": e.to_string(),
-                });
-                send_fn(error_msg.to_string());
+                let error_msg = AgentMessage::DownloadError {
+                    id,
+                    error: e.to_string(),
+                };
+                send_fn(error_msg.to_json());
                 return Err(e);
             }
         };
@@ -220,19 +220,17 @@ impl FileTransferManager {
         let file_size = metadata.len();
 
         // Send download start
-        let start_msg = serde_json::json!({
-            "t": "fds",
-            "id": id,
-            "name": filename,
-            "size": file_size,
-        });
-        send_fn(start_msg.to_string());
+        let start_msg = AgentMessage::DownloadStart {
+            id: id.clone(),
+            name: filename.to_string(),
+            size: file_size,
+        };
+        send_fn(start_msg.to_json());
 
         // Read and send chunks
-        const DOWNLOAD_CHUNK_SIZE: usize = 16 * 1024;
         let mut file = fs::File::open(&canonical)
             .with_context(|| format!("Failed to open: {}", canonical.display()))?;
-        let mut buf = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+        let mut buf = vec![0u8; FILE_CHUNK_BYTES];
 
         loop {
             let n = file.read(&mut buf).context("Failed to read file")?;
@@ -240,20 +238,16 @@ impl FileTransferManager {
                 break;
             }
             let b64 = base64::engine::general_purpose::STANDARD.encode(&buf[..n]);
-            let chunk_msg = serde_json::json!({
-                "t": "fdc",
-                "id": id,
-                "data": b64,
-            });
-            send_fn(chunk_msg.to_string());
+            let chunk_msg = AgentMessage::DownloadChunk {
+                id: id.clone(),
+                data: b64,
+            };
+            send_fn(chunk_msg.to_json());
         }
 
         // Send download done
-        let done_msg = serde_json::json!({
-            "t": "fdd",
-            "id": id,
-        });
-        send_fn(done_msg.to_string());
+        let done_msg = AgentMessage::DownloadDone { id: id.clone() };
+        send_fn(done_msg.to_json());
 
         info!(
             id,
@@ -0,0 +1,192 @@
+//! Encoded frames from the capture thread to the video send loop.
+//!
+//! A bounded queue like the mpsc channel it replaces, but with a drop
+//! policy that knows about H.264: when it is full the oldest P-frame goes,
+//! and IDR frames are never dropped. Losing the keyframe a freshly
+//! connected browser is waiting for costs a whole IDR round trip, while a
+//! lost P-frame only smears until the next one.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+pub(crate) struct EncodedFrame {
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+    pub captured_at: Instant,
+    /// When the capture thread handed the frame over
+    pub queued_at: Instant,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Pushed {
+    Queued,
+    /// Queued after dropping the oldest queued P-frame
+    DroppedOldest,
+    /// Queue full of IDR frames, so the new P-frame was dropped
+    DroppedNew,
+    /// The send loop is gone
+    Closed,
+}
+
+struct State {
+    frames: VecDeque<EncodedFrame>,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ready: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub(crate) fn frame_queue(capacity: usize) -> (FrameSender, FrameReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            frames: VecDeque::with_capacity(capacity + 1),
+            sender_alive: true,
+            receiver_alive: true,
+        }),
+        ready: Notify::new(),
+    });
+    (
+        FrameSender {
+            shared: Arc::clone(&shared),
+            capacity,
+        },
+        FrameReceiver { shared },
+    )
+}
+
+pub(crate) struct FrameSender {
+    shared: Arc<Shared>,
+    capacity: usize,
+}
+
+impl FrameSender {
+    /// Queue a frame without blocking. An IDR frame is always queued, even
+    /// past capacity when only IDR frames are waiting.
+    pub(crate) fn push(&self, frame: EncodedFrame) -> Pushed {
+        let mut state = self.shared.lock();
+        if !state.receiver_alive {
+            return Pushed::Closed;
+        }
+        let pushed = if state.frames.len() < self.capacity {
+            Pushed::Queued
+        } else if let Some(oldest_p) = state.frames.iter().position(|f| !f.keyframe) {
+            state.frames.remove(oldest_p);
+            Pushed::DroppedOldest
+        } else if frame.keyframe {
+            Pushed::Queued
+        } else {
+            return Pushed::DroppedNew;
+        };
+        state.frames.push_back(frame);
+        drop(state);
+        self.shared.ready.notify_one();
+        pushed
+    }
+}
+
+impl Drop for FrameSender {
+    fn drop(&mut self) {
+        self.shared.lock().sender_alive = false;
+        self.shared.ready.notify_one();
+    }
+}
+
+pub(crate) struct FrameReceiver {
+    shared: Arc<Shared>,
+}
+
+impl FrameReceiver {
+    /// Next frame in capture order, or None once the capture thread is gone
+    /// and the queue is empty.
+    pub(crate) async fn recv(&mut self) -> Option<EncodedFrame> {
+        loop {
+            {
+                let mut state = self.shared.lock();
+                if let Some(frame) = state.frames.pop_front() {
+                    return Some(frame);
+                }
+                if !state.sender_alive {
+                    return None;
+                }
+            }
+            self.shared.ready.notified().await;
+        }
+    }
+}
+
+impl Drop for FrameReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(tag: u8, keyframe: bool) -> EncodedFrame {
+        let now = Instant::now();
+        EncodedFrame {
+            data: vec![tag],
+            keyframe,
+            captured_at: now,
+            queued_at: now,
+        }
+    }
+
+    async fn drain(rx: &mut FrameReceiver) -> Vec<u8> {
+        let mut tags = Vec::new();
+        while let Ok(Some(f)) =
+            tokio::time::timeout(std::time::Duration::from_millis(10), rx.recv()).await
+        {
+            tags.push(f.data[0]);
+        }
+        tags
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_oldest_p_frame() {
+        let (tx, mut rx) = frame_queue(2);
+        assert_eq!(tx.push(frame(1, false)), Pushed::Queued);
+        assert_eq!(tx.push(frame(2, true)), Pushed::Queued);
+        assert_eq!(tx.push(frame(3, false)), Pushed::DroppedOldest);
+        assert_eq!(drain(&mut rx).await, [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn idr_frames_are_never_dropped() {
+        let (tx, mut rx) = frame_queue(2);
+        assert_eq!(tx.push(frame(1, true)), Pushed::Queued);
+        assert_eq!(tx.push(frame(2, true)), Pushed::Queued);
+        assert_eq!(tx.push(frame(3, false)), Pushed::DroppedNew);
+        assert_eq!(tx.push(frame(4, true)), Pushed::Queued);
+        assert_eq!(drain(&mut rx).await, [1, 2, 4]);
+    }
+
+    #[tokio::test]
+    async fn either_side_closing_ends_the_queue() {
+        let (tx, mut rx) = frame_queue(2);
+        tx.push(frame(1, false));
+        drop(tx);
+        // Frames already queued are still delivered
+        assert_eq!(rx.recv().await.map(|f| f.data), Some(vec![1]));
+        assert!(rx.recv().await.is_none());
+
+        let (tx, rx) = frame_queue(2);
+        drop(rx);
+        assert_eq!(tx.push(frame(1, true)), Pushed::Closed);
+    }
+}
@@ -33,7 +33,6 @@ pub fn h264_contains_idr(data: &[u8]) -> bool {
 
 /// Extract NAL units from an Annex B byte stream.
 /// Returns a Vec of (nal_type, payload_bytes) tuples.
-#[allow(dead_code)]
 pub fn extract_nals(data: &[u8]) -> Vec<(u8, Vec<u8>)> {
     let mut nals = Vec::new();
     let mut nal_starts = Vec::new();
@@ -98,6 +97,16 @@ pub struct SpsInfo {
     pub colour_description_present: bool,
 }
 
+/// Check whether an Annex B access unit carries an SPS for the High 4:4:4
+/// Predictive profile (profile_idc 244). Returns `None` if there is no SPS
+/// (non-IDR frames), so callers can keep the last known state.
+pub fn sps_is_high_444(data: &[u8]) -> Option<bool> {
+    extract_nals(data)
+        .iter()
+        .find(|(nal_type, nal)| *nal_type == 7 && nal.len() > 1)
+        .map(|(_, nal)| nal[1] == 244)
+}
+
 /// Exp-Golomb bit reader for H.264 SPS parsing.
 #[allow(dead_code)]
 struct BitReader<'a> {
@@ -419,6 +428,16 @@ mod tests {
 
     // --- SPS parsing tests ---
 
+    #[test]
+    fn sps_profile_444_detection() {
+        let main_au = [0, 0, 0, 1, 0x67, 0x4d, 0x00, 0x28, 0, 0, 0, 1, 0x65, 0x88];
+        assert_eq!(sps_is_high_444(&main_au), Some(false));
+        let high444_au = [0, 0, 0, 1, 0x67, 0xf4, 0x00, 0x33, 0, 0, 0, 1, 0x65, 0x88];
+        assert_eq!(sps_is_high_444(&high444_au), Some(true));
+        let p_frame = [0, 0, 0, 1, 0x41, 0x9a];
+        assert_eq!(sps_is_high_444(&p_frame), None);
+    }
+
     #[test]
     fn parse_sps_main_profile() {
         // Minimal Main profile SPS (profile_idc=77/0x4d, level=4.0/0x28)
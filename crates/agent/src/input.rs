@@ -1,6 +1,8 @@
+use crate::capture::Area;
 use anyhow::Context;
-use std::sync::Arc;
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use tracing::info;
 use x11rb::connection::{Connection, RequestConnection};
 use x11rb::protocol::xproto;
@@ -17,16 +19,43 @@ pub struct InputInjector {
     root: xproto::Window,
     width: Arc<AtomicU32>,
     height: Arc<AtomicU32>,
+    /// Where the second monitor is on the screen, while one is attached
+    second_screen: Arc<Mutex<Option<Area>>>,
     /// Accumulated fractional scroll for smooth trackpad support
     scroll_accum_x: f64,
     scroll_accum_y: f64,
+    /// Sub-pixel remainder of relative motion, so slow high-DPI mouse
+    /// movement isn't rounded away
+    motion_accum_x: f64,
+    motion_accum_y: f64,
+    /// Set while the browser holds pointer lock
+    pointer_lock: Option<PointerLockState>,
+    /// Evdev keycodes currently pressed, so they can be released if the
+    /// browser loses the keyboard grab mid-combo
+    held_keys: HashSet<u16>,
 }
 
+/// Server pointer settings saved when entering pointer lock, and where the
+/// locked pointer is.
+struct PointerLockState {
+    accel_numerator: u16,
+    accel_denominator: u16,
+    threshold: u16,
+    /// Pointer position tracked from the relative moves injected since the
+    /// last warp; acceleration is off, so they land 1:1
+    position: (i32, i32),
+}
+
+/// How close to a screen edge the locked pointer may get before it is
+/// warped back to the center
+const LOCK_EDGE_MARGIN: i32 = 64;
+
 impl InputInjector {
     pub fn new(
         x_display: &str,
         width: Arc<AtomicU32>,
         height: Arc<AtomicU32>,
+        second_screen: Arc<Mutex<Option<Area>>>,
     ) -> anyhow::Result<Self> {
         let (conn, screen_num) =
             RustConnection::connect(Some(x_display)).context("Failed to connect to X display")?;
@@ -44,14 +73,24 @@ impl InputInjector {
             root,
             width,
             height,
+            second_screen,
             scroll_accum_x: 0.0,
             scroll_accum_y: 0.0,
+            motion_accum_x: 0.0,
+            motion_accum_y: 0.0,
+            pointer_lock: None,
+            held_keys: HashSet::new(),
         })
     }
 
     /// Inject a keyboard event. `code` is a Linux evdev keycode.
     /// X11 keycode = evdev keycode + 8.
     pub fn inject_key(&mut self, code: u16, pressed: bool) -> anyhow::Result<()> {
+        if pressed {
+            self.held_keys.insert(code);
+        } else {
+            self.held_keys.remove(&code);
+        }
         let x_keycode = (code + 8) as u8;
         let event_type = if pressed {
             xproto::KEY_PRESS_EVENT
@@ -63,12 +102,57 @@ impl InputInjector {
         Ok(())
     }
 
+    /// Release every key still held down.
+    pub fn release_all_keys(&mut self) -> anyhow::Result<()> {
+        let held: Vec<u16> = self.held_keys.iter().copied().collect();
+        for code in held {
+            self.inject_key(code, false)?;
+        }
+        Ok(())
+    }
+
+    /// Press the keys of a combo in order, then release them in reverse.
+    pub fn inject_combo(&mut self, keys: &[u16]) -> anyhow::Result<()> {
+        for &code in keys {
+            self.inject_key(code, true)?;
+        }
+        for &code in keys.iter().rev() {
+            self.inject_key(code, false)?;
+        }
+        Ok(())
+    }
+
     /// Inject absolute mouse movement from normalized [0.0, 1.0] coordinates.
+    /// Ignored while pointer lock is active.
     pub fn inject_mouse_move_abs(&mut self, x: f64, y: f64) -> anyhow::Result<()> {
+        if self.pointer_lock.is_some() {
+            return Ok(());
+        }
         let w = self.width.load(Ordering::Relaxed);
         let h = self.height.load(Ordering::Relaxed);
-        let px = (x.clamp(0.0, 1.0) * w as f64) as i16;
-        let py = (y.clamp(0.0, 1.0) * h as f64) as i16;
+        self.move_pointer_to(
+            (x.clamp(0.0, 1.0) * w as f64) as i16,
+            (y.clamp(0.0, 1.0) * h as f64) as i16,
+        )
+    }
+
+    /// Inject absolute mouse movement on the second monitor, from
+    /// coordinates normalized to it. Ignored while pointer lock is active
+    /// or no second monitor is attached.
+    pub fn inject_screen_mouse_move(&mut self, x: f64, y: f64) -> anyhow::Result<()> {
+        if self.pointer_lock.is_some() {
+            return Ok(());
+        }
+        let Some(area) = *self.second_screen.lock().unwrap_or_else(|e| e.into_inner()) else {
+            return Ok(());
+        };
+        self.move_pointer_to(
+            (area.x as f64 + x.clamp(0.0, 1.0) * area.width as f64) as i16,
+            (area.y as f64 + y.clamp(0.0, 1.0) * area.height as f64) as i16,
+        )
+    }
+
+    fn move_pointer_to(&mut self, px: i16, py: i16) -> anyhow::Result<()> {
         // detail=0 for absolute motion, root=target window
         xtest::fake_input(
             &self.conn,
@@ -85,12 +169,30 @@ impl InputInjector {
     }
 
     /// Inject relative mouse movement (pointer lock mode).
+    ///
+    /// While pointer lock is active the pointer is confined to the screen:
+    /// motion past an edge is clamped, and once the pointer comes within
+    /// `LOCK_EDGE_MARGIN` of an edge it is warped back to the center, so
+    /// the browser's unbounded deltas never pin it against a border.
     pub fn inject_mouse_move_rel(&mut self, dx: f64, dy: f64) -> anyhow::Result<()> {
-        let dx_i = dx.round() as i16;
-        let dy_i = dy.round() as i16;
-        if dx_i == 0 && dy_i == 0 {
-            return Ok(());
+        let mut dx_i = Self::accumulate_scroll(&mut self.motion_accum_x, dx);
+        let mut dy_i = Self::accumulate_scroll(&mut self.motion_accum_y, dy);
+        let mut near_edge = false;
+        if let Some(lock) = &mut self.pointer_lock {
+            let w = self.width.load(Ordering::Relaxed) as i32;
+            let h = self.height.load(Ordering::Relaxed) as i32;
+            (dx_i, dy_i, near_edge) = confine_motion(&mut lock.position, dx_i, dy_i, w, h);
+        }
+        if dx_i != 0 || dy_i != 0 {
+            self.fake_relative_motion(dx_i as i16, dy_i as i16)?;
         }
+        if near_edge {
+            self.recenter_locked_pointer()?;
+        }
+        Ok(())
+    }
+
+    fn fake_relative_motion(&mut self, dx: i16, dy: i16) -> anyhow::Result<()> {
         // detail=1 for relative motion
         xtest::fake_input(
             &self.conn,
@@ -98,14 +200,114 @@ impl InputInjector {
             1, // true = relative
             0,
             x11rb::NONE, // no root for relative
-            dx_i,
-            dy_i,
+            dx,
+            dy,
             0,
         )?;
         self.conn.flush()?;
         Ok(())
     }
 
+    /// Warp the locked pointer back to the screen center once it nears an
+    /// edge. The tracked position goes stale when an application warps the
+    /// pointer itself (games re-center it too), so the real position is
+    /// checked first and adopted if it isn't near an edge after all.
+    fn recenter_locked_pointer(&mut self) -> anyhow::Result<()> {
+        let w = self.width.load(Ordering::Relaxed) as i32;
+        let h = self.height.load(Ordering::Relaxed) as i32;
+        let actual = xproto::query_pointer(&self.conn, self.root)?
+            .reply()
+            .context("Failed to query pointer")?;
+        let actual = (actual.root_x as i32, actual.root_y as i32);
+        let position = if near_screen_edge(actual, w, h) {
+            self.warp_pointer_to_center()?
+        } else {
+            actual
+        };
+        if let Some(lock) = &mut self.pointer_lock {
+            lock.position = position;
+        }
+        Ok(())
+    }
+
+    fn warp_pointer_to_center(&mut self) -> anyhow::Result<(i32, i32)> {
+        let w = self.width.load(Ordering::Relaxed);
+        let h = self.height.load(Ordering::Relaxed);
+        let center = ((w / 2) as i32, (h / 2) as i32);
+        xproto::warp_pointer(
+            &self.conn,
+            x11rb::NONE,
+            self.root,
+            0,
+            0,
+            0,
+            0,
+            center.0 as i16,
+            center.1 as i16,
+        )?;
+        self.conn.flush()?;
+        Ok(center)
+    }
+
+    /// Whether pointer lock mode is active.
+    pub fn pointer_locked(&self) -> bool {
+        self.pointer_lock.is_some()
+    }
+
+    /// Enter or leave pointer lock mode.
+    ///
+    /// Entering saves and then disables server pointer acceleration so
+    /// browser deltas map 1:1 to X motion, and warps the pointer to the
+    /// screen center so relative motion has full travel before hitting an
+    /// edge; from then on the pointer is confined to the screen (see
+    /// `inject_mouse_move_rel`). Leaving restores the saved acceleration.
+    pub fn set_pointer_lock(&mut self, locked: bool) -> anyhow::Result<()> {
+        match (locked, self.pointer_lock.is_some()) {
+            (true, false) => {
+                let ctrl = xproto::get_pointer_control(&self.conn)?
+                    .reply()
+                    .context("Failed to query pointer control")?;
+                xproto::change_pointer_control(&self.conn, 1, 1, 0, true, true)?;
+                let position = self.warp_pointer_to_center()?;
+
+                self.motion_accum_x = 0.0;
+                self.motion_accum_y = 0.0;
+                self.pointer_lock = Some(PointerLockState {
+                    accel_numerator: ctrl.acceleration_numerator,
+                    accel_denominator: ctrl.acceleration_denominator,
+                    threshold: ctrl.threshold,
+                    position,
+                });
+                info!(
+                    saved_numerator = ctrl.acceleration_numerator,
+                    saved_denominator = ctrl.acceleration_denominator,
+                    "Pointer lock enabled, acceleration disabled"
+                );
+            }
+            (false, true) => {
+                if let Some(saved) = self.pointer_lock.take() {
+                    self.restore_pointer_control(&saved)?;
+                }
+                info!("Pointer lock released, acceleration restored");
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn restore_pointer_control(&self, saved: &PointerLockState) -> anyhow::Result<()> {
+        xproto::change_pointer_control(
+            &self.conn,
+            saved.accel_numerator as i16,
+            saved.accel_denominator as i16,
+            saved.threshold as i16,
+            true,
+            true,
+        )?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
     /// Map browser button index to X11 button number.
     /// Browser: 0=left, 1=middle, 2=right → X11: 1=left, 2=middle, 3=right
     fn map_button(button: u8) -> anyhow::Result<u8> {
@@ -129,7 +331,8 @@ impl InputInjector {
         Ok(())
     }
 
-    /// Accumulate fractional scroll and return discrete notch count.
+    /// Accumulate fractional scroll (or sub-pixel motion) and return the
+    /// discrete step count, keeping the remainder in `accum`.
     fn accumulate_scroll(accum: &mut f64, pixels_per_notch: f64) -> i32 {
         *accum += pixels_per_notch;
         let discrete = *accum as i32;
@@ -216,6 +419,50 @@ impl InputInjector {
     }
 }
 
+/// Evdev keycodes for a named system combo, as sent in `KeyCombo` events.
+pub fn combo_keys(name: &str) -> Option<&'static [u16]> {
+    const LEFTCTRL: u16 = 29;
+    const LEFTALT: u16 = 56;
+    const LEFTMETA: u16 = 125;
+    match name {
+        "ctrl-alt-del" => Some(&[LEFTCTRL, LEFTALT, 111]),
+        "ctrl-alt-backspace" => Some(&[LEFTCTRL, LEFTALT, 14]),
+        "alt-tab" => Some(&[LEFTALT, 15]),
+        "alt-f4" => Some(&[LEFTALT, 62]),
+        "super" => Some(&[LEFTMETA]),
+        "print" => Some(&[99]),
+        _ => None,
+    }
+}
+
+/// Clamp a relative move so the pointer stays on a `w`×`h` screen, and
+/// advance the tracked `position` by it. Returns the clamped move and
+/// whether the pointer has come near an edge.
+fn confine_motion(position: &mut (i32, i32), dx: i32, dy: i32, w: i32, h: i32) -> (i32, i32, bool) {
+    let x = (position.0 + dx).clamp(0, (w - 1).max(0));
+    let y = (position.1 + dy).clamp(0, (h - 1).max(0));
+    let moved = (x - position.0, y - position.1);
+    *position = (x, y);
+    (moved.0, moved.1, near_screen_edge(*position, w, h))
+}
+
+/// Whether `(x, y)` is within `LOCK_EDGE_MARGIN` of an edge of a `w`×`h`
+/// screen. The margin shrinks on tiny screens so the center never counts.
+fn near_screen_edge((x, y): (i32, i32), w: i32, h: i32) -> bool {
+    let mx = LOCK_EDGE_MARGIN.min(w / 4);
+    let my = LOCK_EDGE_MARGIN.min(h / 4);
+    x < mx || y < my || x >= w - mx || y >= h - my
+}
+
+impl Drop for InputInjector {
+    fn drop(&mut self) {
+        // Acceleration is server-global; don't leave it disabled
+        if let Some(saved) = self.pointer_lock.take() {
+            let _ = self.restore_pointer_control(&saved);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -243,6 +490,21 @@ mod tests {
         assert!(InputInjector::map_button(255).is_err());
     }
 
+    // --- System combos ---
+
+    #[test]
+    fn combo_keys_known_names() {
+        assert_eq!(combo_keys("ctrl-alt-del"), Some(&[29u16, 56, 111][..]));
+        assert_eq!(combo_keys("alt-tab"), Some(&[56u16, 15][..]));
+        assert_eq!(combo_keys("super"), Some(&[125u16][..]));
+    }
+
+    #[test]
+    fn combo_keys_unknown_rejected() {
+        assert_eq!(combo_keys("ctrl-alt-f1"), None);
+        assert_eq!(combo_keys(""), None);
+    }
+
     // --- Scroll accumulation ---
 
     #[test]
@@ -295,4 +557,39 @@ mod tests {
         InputInjector::accumulate_scroll(&mut accum, -0.5);
         assert!(accum.abs() < 0.001);
     }
+
+    // --- Pointer lock confinement ---
+
+    #[test]
+    fn confine_motion_clamps_at_screen_edges() {
+        let mut position = (10, 1070);
+        assert_eq!(
+            confine_motion(&mut position, -50, 30, 1920, 1080),
+            (-10, 9, true)
+        );
+        assert_eq!(position, (0, 1079));
+    }
+
+    #[test]
+    fn confine_motion_passes_moves_through_in_the_middle() {
+        let mut position = (960, 540);
+        assert_eq!(
+            confine_motion(&mut position, 25, -40, 1920, 1080),
+            (25, -40, false)
+        );
+        assert_eq!(position, (985, 500));
+    }
+
+    #[test]
+    fn near_screen_edge_margin() {
+        assert!(near_screen_edge((LOCK_EDGE_MARGIN - 1, 540), 1920, 1080));
+        assert!(near_screen_edge((960, 1080 - LOCK_EDGE_MARGIN), 1920, 1080));
+        assert!(!near_screen_edge(
+            (LOCK_EDGE_MARGIN, LOCK_EDGE_MARGIN),
+            1920,
+            1080
+        ));
+        // A tiny screen still has a center that isn't near an edge
+        assert!(!near_screen_edge((50, 50), 100, 100));
+    }
 }
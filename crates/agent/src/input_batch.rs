@@ -0,0 +1,176 @@
+//! Coalescing of `InputEvent::InputBatch` pointer history.
+//!
+//! A 1000 Hz mouse produces a dozen or more moves per browser frame. The
+//! batch keeps their timestamps, so moves closer together than
+//! `MOVE_INTERVAL_MS` can be merged: absolute moves keep the latest
+//! position, relative moves add up. The path keeps its shape at a few
+//! hundred updates per second instead of one XTEST call per hardware report.
+//! Buttons and scrolls are never merged and always see the pointer where
+//! the browser saw it.
+
+use beam_protocol::{InputEvent, TimedInput};
+
+/// Shortest time between two injected moves of a batch.
+pub const MOVE_INTERVAL_MS: f64 = 4.0;
+
+/// A run of mergeable moves: the timestamp it started at and the one of
+/// its latest move.
+enum PendingMove {
+    Abs {
+        ts: f64,
+        last: f64,
+        x: f64,
+        y: f64,
+    },
+    Rel {
+        ts: f64,
+        last: f64,
+        dx: f64,
+        dy: f64,
+    },
+}
+
+impl PendingMove {
+    fn into_timed(self) -> TimedInput {
+        match self {
+            Self::Abs { last, x, y, .. } => TimedInput {
+                ts: last,
+                ev: InputEvent::MouseMove { x, y },
+            },
+            Self::Rel { last, dx, dy, .. } => TimedInput {
+                ts: last,
+                ev: InputEvent::RelativeMouseMove { dx, dy },
+            },
+        }
+    }
+}
+
+/// Merge the moves of a batch, returning the events to inject in order.
+/// A merged move carries the timestamp of the last move it absorbed.
+pub fn coalesce(batch: Vec<TimedInput>, interval_ms: f64) -> Vec<TimedInput> {
+    let mut out = Vec::with_capacity(batch.len());
+    let mut pending: Option<PendingMove> = None;
+
+    for TimedInput { ts, ev } in batch {
+        // A NaN timestamp fails the comparison and starts a new run
+        pending = match (pending.take(), ev) {
+            (Some(PendingMove::Abs { ts: start, .. }), InputEvent::MouseMove { x, y })
+                if ts - start < interval_ms =>
+            {
+                Some(PendingMove::Abs {
+                    ts: start,
+                    last: ts,
+                    x,
+                    y,
+                })
+            }
+            (
+                Some(PendingMove::Rel {
+                    ts: start, dx, dy, ..
+                }),
+                InputEvent::RelativeMouseMove { dx: ddx, dy: ddy },
+            ) if ts - start < interval_ms => Some(PendingMove::Rel {
+                ts: start,
+                last: ts,
+                dx: dx + ddx,
+                dy: dy + ddy,
+            }),
+            (prev, ev) => {
+                if let Some(prev) = prev {
+                    out.push(prev.into_timed());
+                }
+                match ev {
+                    InputEvent::MouseMove { x, y } => Some(PendingMove::Abs { ts, last: ts, x, y }),
+                    InputEvent::RelativeMouseMove { dx, dy } => Some(PendingMove::Rel {
+                        ts,
+                        last: ts,
+                        dx,
+                        dy,
+                    }),
+                    ev => {
+                        out.push(TimedInput { ts, ev });
+                        None
+                    }
+                }
+            }
+        };
+    }
+    if let Some(prev) = pending {
+        out.push(prev.into_timed());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn abs(ts: f64, x: f64) -> TimedInput {
+        TimedInput {
+            ts,
+            ev: InputEvent::MouseMove { x, y: 0.5 },
+        }
+    }
+
+    fn rel(ts: f64, dx: f64) -> TimedInput {
+        TimedInput {
+            ts,
+            ev: InputEvent::RelativeMouseMove { dx, dy: 1.0 },
+        }
+    }
+
+    fn xs(events: &[TimedInput]) -> Vec<String> {
+        events
+            .iter()
+            .map(|t| match &t.ev {
+                InputEvent::MouseMove { x, .. } => format!("m{x}"),
+                InputEvent::RelativeMouseMove { dx, dy } => format!("rm{dx},{dy}"),
+                InputEvent::Button { b, d } => format!("b{b}{d}"),
+                other => format!("{other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_moves_within_the_interval() {
+        // 1 kHz mouse: twelve moves over 12 ms become three
+        let batch: Vec<_> = (0..12).map(|i| abs(i as f64, i as f64)).collect();
+        let out = coalesce(batch, MOVE_INTERVAL_MS);
+        assert_eq!(xs(&out), ["m3", "m7", "m11"]);
+        // Each merged move keeps the time the pointer reached it
+        let ts: Vec<_> = out.iter().map(|t| t.ts).collect();
+        assert_eq!(ts, [3.0, 7.0, 11.0]);
+    }
+
+    #[test]
+    fn relative_moves_add_up() {
+        let batch = vec![rel(0.0, 1.0), rel(1.0, 2.0), rel(2.0, 3.0), rel(5.0, 4.0)];
+        assert_eq!(xs(&coalesce(batch, MOVE_INTERVAL_MS)), ["rm6,3", "rm4,1"]);
+    }
+
+    #[test]
+    fn buttons_split_runs_and_keep_their_position() {
+        let press = TimedInput {
+            ts: 1.5,
+            ev: InputEvent::Button { b: 0, d: true },
+        };
+        let batch = vec![
+            abs(0.0, 1.0),
+            abs(1.0, 2.0),
+            press,
+            abs(2.0, 3.0),
+            rel(2.5, 1.0),
+        ];
+        assert_eq!(
+            xs(&coalesce(batch, MOVE_INTERVAL_MS)),
+            ["m2", "b0true", "m3", "rm1,1"]
+        );
+    }
+
+    #[test]
+    fn zero_interval_keeps_every_event() {
+        let batch: Vec<_> = (0..5).map(|i| abs(i as f64, i as f64)).collect();
+        assert_eq!(coalesce(batch, 0.0).len(), 5);
+        assert!(coalesce(Vec::new(), MOVE_INTERVAL_MS).is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+//! Rate caps on browser input.
+//!
+//! Nothing stops a buggy or hostile client from sending input far faster
+//! than anyone types or moves a mouse. Every event takes the injector lock,
+//! and some cost much more: a clipboard set claims an X11 selection, a
+//! layout change has the X server compile a keymap. Each category of input
+//! gets a budget per one-second window; events over it are dropped and
+//! counted, and the counts go to the server in the watchdog's health
+//! reports.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use beam_protocol::{InputDrops, InputEvent};
+use tracing::warn;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Key,
+    Pointer,
+    Clipboard,
+    Layout,
+    Control,
+}
+
+const CATEGORIES: usize = 5;
+
+impl Category {
+    /// The category `event` is capped under. None for events that are
+    /// cheap or bounded elsewhere: frame acks, visibility and audio
+    /// playout reports, and file uploads, whose size the transfer manager
+    /// limits.
+    pub fn of(event: &InputEvent) -> Option<Self> {
+        Some(match event {
+            InputEvent::Key { .. } | InputEvent::KeyCombo { .. } => Self::Key,
+            InputEvent::MouseMove { .. }
+            | InputEvent::ScreenMouseMove { .. }
+            | InputEvent::RelativeMouseMove { .. }
+            | InputEvent::Button { .. }
+            | InputEvent::Scroll { .. }
+            | InputEvent::InputBatch { .. } => Self::Pointer,
+            InputEvent::Clipboard { .. }
+            | InputEvent::ClipboardPrimary { .. }
+            | InputEvent::ClipboardHistoryRequest
+            | InputEvent::ClipboardHistoryPaste { .. }
+            | InputEvent::ClipboardHistoryClear => Self::Clipboard,
+            InputEvent::Layout { .. } => Self::Layout,
+            InputEvent::Resize { .. }
+            | InputEvent::DisplaySettings { .. }
+            | InputEvent::ScreenAdd { .. }
+            | InputEvent::ScreenRemove
+            | InputEvent::Quality { .. }
+            | InputEvent::CursorMode { .. }
+            | InputEvent::PointerLock { .. }
+            | InputEvent::KeyboardGrab { .. }
+            | InputEvent::OpenUrl { .. }
+            | InputEvent::WebcamStart { .. }
+            | InputEvent::WebcamStop
+            | InputEvent::AudioEnabled { .. }
+            | InputEvent::AudioMode { .. }
+            | InputEvent::AudioTuning { .. }
+            | InputEvent::AudioSourcesRequest
+            | InputEvent::AudioSource { .. }
+            | InputEvent::AudioAppVolume { .. }
+            | InputEvent::FileDownloadRequest { .. } => Self::Control,
+            InputEvent::FrameAck { .. }
+            | InputEvent::VisibilityState { .. }
+            | InputEvent::AudioReport { .. }
+            | InputEvent::FileStart { .. }
+            | InputEvent::FileChunk { .. }
+            | InputEvent::FileDone { .. } => return None,
+        })
+    }
+
+    /// Events allowed per window. Well above real use: key repeat plus
+    /// fast typing, 1000 Hz mice sending unbatched, a window being dragged
+    /// to a new size.
+    pub fn cap(self) -> u32 {
+        match self {
+            Self::Key => 200,
+            Self::Pointer => 1000,
+            Self::Clipboard => 10,
+            Self::Layout => 2,
+            Self::Control => 50,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Key => "key",
+            Self::Pointer => "pointer",
+            Self::Clipboard => "clipboard",
+            Self::Layout => "layout",
+            Self::Control => "control",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+pub struct InputLimiter {
+    windows: Mutex<[Option<Window>; CATEGORIES]>,
+    dropped: [AtomicU64; CATEGORIES],
+}
+
+impl Default for InputLimiter {
+    fn default() -> Self {
+        Self {
+            windows: Mutex::new([None; CATEGORIES]),
+            dropped: Default::default(),
+        }
+    }
+}
+
+impl InputLimiter {
+    /// Whether `event`, arriving at `now`, is within its category's cap.
+    /// Dropped events are counted.
+    pub fn allow(&self, event: &InputEvent, now: Instant) -> bool {
+        let Some(category) = Category::of(event) else {
+            return true;
+        };
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        let window = match &mut windows[category as usize] {
+            Some(w) if now.duration_since(w.start) < WINDOW => w,
+            slot => slot.insert(Window {
+                start: now,
+                count: 0,
+            }),
+        };
+        if window.count < category.cap() {
+            window.count += 1;
+            return true;
+        }
+        if window.count == category.cap() {
+            // Once per window, not once per dropped event
+            window.count += 1;
+            warn!(
+                category = category.name(),
+                cap = category.cap(),
+                "Browser input over its rate cap, dropping the rest of this second"
+            );
+        }
+        self.dropped[category as usize].fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    /// Events dropped so far, per category.
+    pub fn dropped(&self) -> InputDrops {
+        let count = |category: Category| self.dropped[category as usize].load(Ordering::Relaxed);
+        InputDrops {
+            key: count(Category::Key),
+            pointer: count(Category::Pointer),
+            clipboard: count(Category::Clipboard),
+            layout: count(Category::Layout),
+            control: count(Category::Control),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> InputEvent {
+        InputEvent::Layout {
+            layout: "us".into(),
+            variant: None,
+            options: vec![],
+        }
+    }
+
+    #[test]
+    fn drops_over_the_cap_until_the_window_ends() {
+        let limiter = InputLimiter::default();
+        let start = Instant::now();
+        assert!(limiter.allow(&layout(), start));
+        assert!(limiter.allow(&layout(), start));
+        assert!(!limiter.allow(&layout(), start + Duration::from_millis(500)));
+        assert!(!limiter.allow(&layout(), start + Duration::from_millis(999)));
+        assert!(limiter.allow(&layout(), start + WINDOW));
+        assert_eq!(
+            limiter.dropped(),
+            InputDrops {
+                layout: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn categories_have_separate_budgets() {
+        let limiter = InputLimiter::default();
+        let now = Instant::now();
+        let text = || InputEvent::Clipboard { text: "x".into() };
+        for _ in 0..Category::Clipboard.cap() {
+            assert!(limiter.allow(&text(), now));
+        }
+        assert!(!limiter.allow(&text(), now));
+        // Typing carries on while clipboard sets are refused
+        assert!(limiter.allow(&InputEvent::Key { c: 30, d: true }, now));
+        assert_eq!(limiter.dropped().clipboard, 1);
+        assert_eq!(limiter.dropped().key, 0);
+    }
+
+    #[test]
+    fn acks_and_uploads_are_never_capped() {
+        let limiter = InputLimiter::default();
+        let now = Instant::now();
+        let ack = InputEvent::FrameAck { ts: 1, dd: 0.5 };
+        let chunk = InputEvent::FileChunk {
+            id: "1".into(),
+            data: String::new(),
+        };
+        for _ in 0..5000 {
+            assert!(limiter.allow(&ack, now));
+            assert!(limiter.allow(&chunk, now));
+        }
+        assert_eq!(limiter.dropped(), InputDrops::default());
+    }
+}
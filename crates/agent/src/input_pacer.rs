@@ -0,0 +1,178 @@
+//! Pacing of timestamped input (`--pace-input`).
+//!
+//! Jitter on the browser's connection can hold back a few input messages
+//! and then deliver them in one go. Injected on arrival, two clicks 400 ms
+//! apart can land 5 ms apart and become a double click, and a swipe
+//! arrives as a jump. With pacing on, events carrying the browser's
+//! timestamp are replayed with their original spacing, each held back by
+//! at most `MAX_PACE_DELAY`. A pause in input starts the clock mapping
+//! afresh, so delay picked up during one gesture is gone by the next.
+
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use beam_protocol::{InputEvent, TimedInput, is_valid_input_batch};
+use tracing::warn;
+
+use crate::input_batch;
+
+/// Longest an event is held back to restore its spacing.
+pub const MAX_PACE_DELAY: Duration = Duration::from_millis(50);
+
+/// Gap between browser timestamps after which pacing starts afresh.
+const RESYNC_GAP_MS: f64 = 250.0;
+
+type InputCallback = Arc<dyn Fn(InputEvent) + Send + Sync>;
+
+/// Maps browser event timestamps onto the local clock.
+#[derive(Default)]
+pub struct Pacer {
+    /// Browser timestamp and local instant the mapping is anchored at
+    anchor: Option<(f64, Instant)>,
+    last_ts: f64,
+}
+
+impl Pacer {
+    /// How long to hold an event stamped `ts` that is ready at `now`.
+    pub fn delay(&mut self, ts: f64, now: Instant) -> Duration {
+        if !ts.is_finite() {
+            return Duration::ZERO;
+        }
+        let last = std::mem::replace(&mut self.last_ts, ts);
+        let Some((anchor_ts, anchor_at)) = self.anchor else {
+            self.anchor = Some((ts, now));
+            return Duration::ZERO;
+        };
+        // A reloaded page restarts its clock; a pause ends the gesture
+        if ts < last || ts - last > RESYNC_GAP_MS {
+            self.anchor = Some((ts, now));
+            return Duration::ZERO;
+        }
+        let target = Duration::try_from_secs_f64((ts - anchor_ts) / 1000.0)
+            .ok()
+            .and_then(|offset| anchor_at.checked_add(offset));
+        match target.and_then(|t| t.checked_duration_since(now)) {
+            Some(wait) if !wait.is_zero() && wait <= MAX_PACE_DELAY => wait,
+            // Late, or further out than we are willing to wait: pace the
+            // rest of the run from here
+            _ => {
+                self.anchor = Some((ts, now));
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+/// Where the signaling loop hands input: straight to the input callback,
+/// or through the pacing thread.
+#[derive(Clone)]
+pub enum InputSink {
+    Direct(InputCallback),
+    Paced(mpsc::Sender<(Option<f64>, InputEvent)>),
+}
+
+impl InputSink {
+    pub fn new(callback: InputCallback, pace: bool) -> Self {
+        if !pace {
+            return Self::Direct(callback);
+        }
+        let (tx, rx) = mpsc::channel();
+        let fallback = Arc::clone(&callback);
+        match std::thread::Builder::new()
+            .name("input-pacer".into())
+            .spawn(move || run_pacer(rx, callback))
+        {
+            Ok(_) => Self::Paced(tx),
+            Err(e) => {
+                warn!("Failed to start input pacing, injecting on arrival: {e}");
+                Self::Direct(fallback)
+            }
+        }
+    }
+
+    /// Hand over an event, with the browser's timestamp if it has one.
+    pub fn send(&self, ts: Option<f64>, event: InputEvent) {
+        match self {
+            Self::Direct(callback) => callback(event),
+            Self::Paced(tx) => {
+                // The thread only exits once every sender is gone
+                let _ = tx.send((ts, event));
+            }
+        }
+    }
+}
+
+/// Pacing thread: events run through in arrival order, so an unstamped
+/// event never overtakes one that is being held back.
+fn run_pacer(rx: mpsc::Receiver<(Option<f64>, InputEvent)>, callback: InputCallback) {
+    let mut pacer = Pacer::default();
+    let mut pace = |ts: f64, event: InputEvent| {
+        let wait = pacer.delay(ts, Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        callback(event);
+    };
+    for (ts, event) in rx {
+        match (ts, event) {
+            (Some(ts), event) => pace(ts, event),
+            // Batch entries carry their own timestamps
+            (None, InputEvent::InputBatch { e }) if is_valid_input_batch(&e) => {
+                for TimedInput { ts, ev } in input_batch::coalesce(e, input_batch::MOVE_INTERVAL_MS)
+                {
+                    pace(ts, ev);
+                }
+            }
+            (None, event) => callback(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn burst_is_spread_out_again() {
+        let mut pacer = Pacer::default();
+        let now = Instant::now();
+        // Three events 10 ms apart, delivered together
+        assert_eq!(pacer.delay(100.0, now), Duration::ZERO);
+        assert_eq!(pacer.delay(110.0, now), 10 * MS);
+        assert_eq!(pacer.delay(120.0, now), 20 * MS);
+    }
+
+    #[test]
+    fn late_event_moves_the_anchor() {
+        let mut pacer = Pacer::default();
+        let start = Instant::now();
+        assert_eq!(pacer.delay(0.0, start), Duration::ZERO);
+        // Held up by 20 ms, then the next one arrives with it
+        assert_eq!(pacer.delay(10.0, start + 30 * MS), Duration::ZERO);
+        assert_eq!(pacer.delay(20.0, start + 30 * MS), 10 * MS);
+    }
+
+    #[test]
+    fn never_waits_longer_than_the_cap() {
+        let mut pacer = Pacer::default();
+        let now = Instant::now();
+        assert_eq!(pacer.delay(0.0, now), Duration::ZERO);
+        assert_eq!(pacer.delay(200.0, now), Duration::ZERO);
+        // Paced from the new anchor
+        assert_eq!(pacer.delay(210.0, now), 10 * MS);
+    }
+
+    #[test]
+    fn pauses_and_clock_resets_resync() {
+        let mut pacer = Pacer::default();
+        let start = Instant::now();
+        assert_eq!(pacer.delay(1000.0, start), Duration::ZERO);
+        assert_eq!(pacer.delay(5000.0, start + 100 * MS), Duration::ZERO);
+        assert_eq!(pacer.delay(10.0, start + 200 * MS), Duration::ZERO);
+        assert_eq!(pacer.delay(f64::NAN, start + 200 * MS), Duration::ZERO);
+        assert_eq!(pacer.delay(20.0, start + 200 * MS), 10 * MS);
+    }
+}
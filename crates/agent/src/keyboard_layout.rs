@@ -0,0 +1,536 @@
+//! Keyboard layouts loaded through the XKB extension.
+//!
+//! The browser names a layout the way XKB does: a layout, optionally a
+//! variant, and options. The names are checked against the catalog in the
+//! X server's XKB data (`rules/evdev.lst`, read once), turned into keymap
+//! components the way the `evdev` rules do, and handed to the X server in
+//! an XKB GetKbdByName request, which compiles and loads the keymap. That
+//! is what `setxkbmap` does, minus a process per change. Requests go to one
+//! thread that keeps its X connection; a burst of changes collapses into
+//! the last one.
+
+use std::collections::{HashMap, HashSet};
+use std::io::IoSlice;
+use std::sync::OnceLock;
+use std::sync::mpsc;
+
+use anyhow::{Context, Result, bail};
+use tracing::{debug, info, warn};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::ParseError;
+use x11rb::protocol::xkb::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, PropMode, Window};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::x11_utils::TryParse;
+
+const XKB_RULES_DIR: &str = "/usr/share/X11/xkb/rules";
+
+/// The rules `setxkbmap` uses on an evdev X server, and its default model
+const RULES: &str = "evdev";
+const MODEL: &str = "pc105";
+
+/// A keyboard layout by XKB names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyboardLayout {
+    pub layout: String,
+    pub variant: Option<String>,
+    pub options: Vec<String>,
+}
+
+/// Keymap component names, the form the X server compiles a keymap from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Components {
+    pub keycodes: String,
+    pub types: String,
+    pub compat: String,
+    pub symbols: String,
+    pub geometry: String,
+}
+
+/// What the agent knows of the XKB data: the catalog of layouts, variants
+/// and options, and the parts of the rules it resolves names with.
+#[derive(Debug, Default)]
+pub struct XkbData {
+    layouts: HashSet<String>,
+    /// (layout, variant)
+    variants: HashSet<(String, String)>,
+    options: HashSet<String>,
+    /// Layout groups the rules define (`$azerty`, `$qwertz`, ...)
+    groups: HashMap<String, Vec<String>>,
+    option_symbols: HashMap<String, String>,
+    option_compat: HashMap<String, String>,
+    option_types: HashMap<String, String>,
+}
+
+impl XkbData {
+    /// The system's XKB data, read on first use.
+    pub fn system() -> Result<&'static XkbData> {
+        static DATA: OnceLock<Option<XkbData>> = OnceLock::new();
+        DATA.get_or_init(|| {
+            let read = |name: &str| std::fs::read_to_string(format!("{XKB_RULES_DIR}/{name}"));
+            match (read(&format!("{RULES}.lst")), read(RULES)) {
+                (Ok(lst), Ok(rules)) => Some(XkbData::parse(&lst, &rules)),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Failed to read XKB rules from {XKB_RULES_DIR}: {e}");
+                    None
+                }
+            }
+        })
+        .as_ref()
+        .context("XKB rules not available")
+    }
+
+    /// Parse a rules catalog (`evdev.lst`) and the rules file (`evdev`).
+    pub fn parse(lst: &str, rules: &str) -> Self {
+        let mut data = XkbData::default();
+
+        let mut section = "";
+        for line in lst.lines() {
+            if let Some(name) = line.strip_prefix("! ") {
+                section = name.trim();
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let Some(name) = words.next() else {
+                continue;
+            };
+            match section {
+                "layout" => {
+                    data.layouts.insert(name.to_string());
+                }
+                // "  nodeadkeys      de: German (no dead keys)"
+                "variant" => {
+                    if let Some(layout) = words.next().and_then(|w| w.strip_suffix(':')) {
+                        data.variants.insert((layout.to_string(), name.to_string()));
+                    }
+                }
+                // Group headers ("grp") carry no colon and set nothing
+                "option" if name.contains(':') => {
+                    data.options.insert(name.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        // A trailing backslash continues a line
+        let joined = rules.replace("\\\n", " ");
+        let mut table: Option<&mut HashMap<String, String>> = None;
+        for line in joined.lines() {
+            let line = line.split("//").next().unwrap_or_default();
+            if let Some(header) = line.strip_prefix('!') {
+                let (lhs, rhs) = header.split_once('=').unwrap_or((header, ""));
+                let lhs: Vec<&str> = lhs.split_whitespace().collect();
+                let rhs = rhs.trim();
+                table = None;
+                match (lhs.as_slice(), rhs) {
+                    ([group], members) if group.starts_with('$') => {
+                        let members = members.split_whitespace().map(str::to_string).collect();
+                        data.groups.insert(group.to_string(), members);
+                    }
+                    (["option"], "symbols") => table = Some(&mut data.option_symbols),
+                    (["option"], "compat") => table = Some(&mut data.option_compat),
+                    (["option"], "types") => table = Some(&mut data.option_types),
+                    _ => {}
+                }
+                continue;
+            }
+            if let Some(table) = table.as_deref_mut()
+                && let Some((option, value)) = line.split_once('=')
+            {
+                let option = option.trim();
+                if !option.is_empty() && !option.contains(['*', '$']) {
+                    table.insert(option.to_string(), value.trim().to_string());
+                }
+            }
+        }
+        data
+    }
+
+    /// Check `layout` against the catalog and resolve it to components.
+    pub fn resolve(&self, layout: &KeyboardLayout) -> Result<Components> {
+        let name = &layout.layout;
+        if !is_xkb_name(name) || !self.layouts.contains(name) {
+            bail!("unknown keyboard layout {name:?}");
+        }
+        let mut symbols = format!("pc+{name}");
+        if let Some(variant) = &layout.variant {
+            if !is_xkb_name(variant) || !self.variants.contains(&(name.clone(), variant.clone())) {
+                bail!("layout {name:?} has no variant {variant:?}");
+            }
+            symbols.push_str(&format!("({variant})"));
+        }
+        symbols.push_str("+inet(evdev)");
+
+        let in_group = |group: &str| {
+            self.groups
+                .get(group)
+                .is_some_and(|members| members.contains(name))
+        };
+        let aliases = if in_group("$azerty") {
+            "azerty"
+        } else if in_group("$qwertz") {
+            "qwertz"
+        } else {
+            "qwerty"
+        };
+        let mut components = Components {
+            keycodes: format!("evdev+aliases({aliases})"),
+            types: "complete".into(),
+            compat: "complete".into(),
+            symbols,
+            geometry: format!("pc({MODEL})"),
+        };
+        for option in &layout.options {
+            if !is_xkb_name(option) || !self.options.contains(option) {
+                bail!("unknown XKB option {option:?}");
+            }
+            // Options the rules map to nothing (they act through the model
+            // or a layout) add no components
+            for (table, component) in [
+                (&self.option_symbols, &mut components.symbols),
+                (&self.option_compat, &mut components.compat),
+                (&self.option_types, &mut components.types),
+            ] {
+                if let Some(value) = table.get(option) {
+                    component.push_str(value);
+                }
+            }
+        }
+        Ok(components)
+    }
+}
+
+/// XKB layout, variant and option names: ASCII letters, digits, `-`, `_`,
+/// and the `:` between an option's group and name.
+fn is_xkb_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b':'))
+}
+
+/// Start the layout thread for `display`. Layouts sent on the returned
+/// channel are applied in the background.
+pub fn spawn(display: &str) -> mpsc::Sender<KeyboardLayout> {
+    let (tx, rx) = mpsc::channel::<KeyboardLayout>();
+    let display = display.to_string();
+    if let Err(e) = std::thread::Builder::new()
+        .name("keyboard-layout".into())
+        .spawn(move || run(&display, rx))
+    {
+        warn!("Failed to spawn keyboard layout thread: {e}");
+    }
+    tx
+}
+
+fn run(display: &str, rx: mpsc::Receiver<KeyboardLayout>) {
+    let mut keyboard: Option<XkbKeyboard> = None;
+    let mut current = KeyboardLayout::default();
+    while let Ok(mut layout) = rx.recv() {
+        // Only the latest of a burst matters
+        while let Ok(newer) = rx.try_recv() {
+            layout = newer;
+        }
+        if layout == current {
+            continue;
+        }
+        let components = match XkbData::system().and_then(|data| data.resolve(&layout)) {
+            Ok(components) => components,
+            Err(e) => {
+                warn!(layout = %layout.layout, "Ignoring keyboard layout: {e:#}");
+                continue;
+            }
+        };
+        if keyboard.is_none() {
+            match XkbKeyboard::connect(display) {
+                Ok(k) => keyboard = Some(k),
+                Err(e) => {
+                    warn!("Failed to set keyboard layout: {e:#}");
+                    continue;
+                }
+            }
+        }
+        let Some(k) = &keyboard else {
+            continue;
+        };
+        match k.load(&components, &layout) {
+            Ok(()) => {
+                info!(
+                    layout = %layout.layout,
+                    variant = layout.variant.as_deref().unwrap_or_default(),
+                    options = %layout.options.join(","),
+                    "Keyboard layout set"
+                );
+                current = layout;
+            }
+            Err(e) => {
+                warn!(layout = %layout.layout, "Failed to set keyboard layout: {e:#}");
+                // Reconnect next time, in case the connection broke
+                keyboard = None;
+            }
+        }
+    }
+    debug!("Keyboard layout thread exiting");
+}
+
+struct XkbKeyboard {
+    conn: RustConnection,
+    root: Window,
+    opcode: u8,
+}
+
+impl XkbKeyboard {
+    fn connect(display: &str) -> Result<Self> {
+        let (conn, screen_num) =
+            RustConnection::connect(Some(display)).context("Failed to connect to X display")?;
+        let root = conn.setup().roots[screen_num].root;
+        let reply = conn
+            .xkb_use_extension(1, 0)
+            .context("XKB UseExtension failed")?
+            .reply()
+            .context("XKB UseExtension failed")?;
+        if !reply.supported {
+            bail!("X server does not support XKB 1.0");
+        }
+        let opcode = conn
+            .extension_information(xkb::X11_EXTENSION_NAME)?
+            .context("X server has no XKB extension")?
+            .major_opcode;
+        Ok(Self { conn, root, opcode })
+    }
+
+    /// Have the X server compile and load `components` as the core
+    /// keyboard's keymap, then record the names the way `setxkbmap` does
+    /// for anything that asks which layout is active.
+    fn load(&self, components: &Components, layout: &KeyboardLayout) -> Result<()> {
+        let request = get_kbd_by_name_request(self.opcode, components)?;
+        let reply: LoadReply = self
+            .conn
+            .send_request_with_reply(&[IoSlice::new(&request)], Vec::new())?
+            .reply()
+            .context("XKB GetKbdByName failed")?;
+        if !reply.loaded {
+            bail!("X server could not compile the keymap");
+        }
+
+        let names = [
+            RULES,
+            MODEL,
+            &layout.layout,
+            layout.variant.as_deref().unwrap_or_default(),
+            &layout.options.join(","),
+        ]
+        .iter()
+        .flat_map(|name| name.bytes().chain([0]))
+        .collect::<Vec<u8>>();
+        let atom = self
+            .conn
+            .intern_atom(false, b"_XKB_RULES_NAMES")?
+            .reply()?
+            .atom;
+        self.conn
+            .change_property8(PropMode::REPLACE, self.root, atom, AtomEnum::STRING, &names)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+/// An XKB GetKbdByName request loading `components`. x11rb's binding of
+/// it leaves out the component names, so it is put together here: the
+/// fixed part, then each name as a length byte and the bytes, in the order
+/// keymap (unused), keycodes, types, compat, symbols, geometry.
+fn get_kbd_by_name_request(opcode: u8, components: &Components) -> Result<Vec<u8>> {
+    // Everything but the geometry must compile; the reply lists it all,
+    // as for setxkbmap
+    let want = xkb::GBNDetail::TYPES
+        | xkb::GBNDetail::COMPAT_MAP
+        | xkb::GBNDetail::CLIENT_SYMBOLS
+        | xkb::GBNDetail::SERVER_SYMBOLS
+        | xkb::GBNDetail::INDICATOR_MAPS
+        | xkb::GBNDetail::KEY_NAMES
+        | xkb::GBNDetail::OTHER_NAMES;
+    let need = u16::from(want);
+    let want = u16::from(want | xkb::GBNDetail::GEOMETRY);
+
+    let mut request = vec![opcode, xkb::GET_KBD_BY_NAME_REQUEST, 0, 0];
+    request.extend_from_slice(&u16::from(xkb::ID::USE_CORE_KBD).to_ne_bytes());
+    request.extend_from_slice(&need.to_ne_bytes());
+    request.extend_from_slice(&want.to_ne_bytes());
+    request.extend_from_slice(&[1, 0]);
+    for name in [
+        "",
+        &components.keycodes,
+        &components.types,
+        &components.compat,
+        &components.symbols,
+        &components.geometry,
+    ] {
+        let len = u8::try_from(name.len()).context("keymap component name too long")?;
+        request.push(len);
+        request.extend_from_slice(name.as_bytes());
+    }
+    request.resize(request.len().next_multiple_of(4), 0);
+    let words = u16::try_from(request.len() / 4).context("keymap request too long")?;
+    request[2..4].copy_from_slice(&words.to_ne_bytes());
+    Ok(request)
+}
+
+/// The fixed part of a GetKbdByName reply; the keymap it describes is not
+/// needed.
+struct LoadReply {
+    loaded: bool,
+}
+
+impl TryParse for LoadReply {
+    fn try_parse(value: &[u8]) -> Result<(Self, &[u8]), ParseError> {
+        // Type, device, sequence, length, min and max keycode, then loaded
+        let loaded = *value.get(10).ok_or(ParseError::InsufficientData)?;
+        Ok((
+            Self {
+                loaded: loaded != 0,
+            },
+            &[],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LST: &str = "\
+! model
+  pc105           Generic 105-key PC
+
+! layout
+  us              English (US)
+  de              German
+  fr              French
+
+! variant
+  intl            us: English (US, intl., with dead keys)
+  nodeadkeys      de: German (no dead keys)
+
+! option
+  ctrl                 Ctrl position
+  ctrl:nocaps          Caps Lock as Ctrl
+  grp:alt_shift_toggle Alt+Shift
+  caps:shift           Caps Lock acts as Shift with locking
+  grp_led:scroll       Scroll Lock
+";
+
+    const RULES_FILE: &str = "\
+// comment
+! $azerty = be fr
+! $qwertz = al ch cz de hr hu ro si sk \\
+            sr
+
+! model\t\t=\tkeycodes
+  *\t\t=\tevdev
+
+! option\t=\tsymbols
+  ctrl:nocaps\t\t=\t+ctrl(nocaps)
+  grp:alt_shift_toggle\t=\t+group(alt_shift_toggle)
+
+! option\t=\tcompat
+  grp_led:scroll\t=\t+ledscroll(group_lock)
+
+! option\t=\ttypes
+  caps:shift\t\t\t=\t+caps(shift)
+";
+
+    fn layout(layout: &str, variant: Option<&str>, options: &[&str]) -> KeyboardLayout {
+        KeyboardLayout {
+            layout: layout.into(),
+            variant: variant.map(Into::into),
+            options: options.iter().map(|o| o.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn resolves_like_the_evdev_rules() {
+        let data = XkbData::parse(LST, RULES_FILE);
+        assert_eq!(
+            data.resolve(&layout("us", None, &[])).unwrap(),
+            Components {
+                keycodes: "evdev+aliases(qwerty)".into(),
+                types: "complete".into(),
+                compat: "complete".into(),
+                symbols: "pc+us+inet(evdev)".into(),
+                geometry: "pc(pc105)".into(),
+            }
+        );
+
+        let de = data
+            .resolve(&layout(
+                "de",
+                Some("nodeadkeys"),
+                &["ctrl:nocaps", "caps:shift", "grp_led:scroll"],
+            ))
+            .unwrap();
+        assert_eq!(de.keycodes, "evdev+aliases(qwertz)");
+        assert_eq!(de.symbols, "pc+de(nodeadkeys)+inet(evdev)+ctrl(nocaps)");
+        assert_eq!(de.types, "complete+caps(shift)");
+        assert_eq!(de.compat, "complete+ledscroll(group_lock)");
+
+        let fr = data.resolve(&layout("fr", None, &[])).unwrap();
+        assert_eq!(fr.keycodes, "evdev+aliases(azerty)");
+    }
+
+    #[test]
+    fn rejects_names_not_in_the_catalog() {
+        let data = XkbData::parse(LST, RULES_FILE);
+        assert!(data.resolve(&layout("xx", None, &[])).is_err());
+        // A variant of another layout
+        assert!(data.resolve(&layout("de", Some("intl"), &[])).is_err());
+        // Group headers aren't options
+        assert!(data.resolve(&layout("us", None, &["ctrl"])).is_err());
+        assert!(
+            data.resolve(&layout("us", None, &["ctrl:nocaps+evil"]))
+                .is_err()
+        );
+        assert!(data.resolve(&layout("us(intl)", None, &[])).is_err());
+    }
+
+    #[test]
+    fn request_carries_the_component_names() {
+        let components = Components {
+            keycodes: "evdev".into(),
+            types: "complete".into(),
+            compat: "complete".into(),
+            symbols: "pc+us".into(),
+            geometry: "pc(pc105)".into(),
+        };
+        let request = get_kbd_by_name_request(135, &components).unwrap();
+        assert_eq!(request[..2], [135, xkb::GET_KBD_BY_NAME_REQUEST]);
+        assert_eq!(request.len() % 4, 0);
+        assert_eq!(
+            usize::from(u16::from_ne_bytes([request[2], request[3]])) * 4,
+            request.len()
+        );
+        // load = true
+        assert_eq!(request[10], 1);
+        let names = &request[12..];
+        assert_eq!(names[0], 0);
+        assert_eq!(names[1], 5);
+        assert_eq!(&names[2..7], b"evdev");
+
+        let long = Components {
+            symbols: "x".repeat(256),
+            ..components
+        };
+        assert!(get_kbd_by_name_request(135, &long).is_err());
+    }
+
+    #[test]
+    fn reads_the_system_catalog() {
+        // Only where XKB data is installed
+        let Ok(data) = XkbData::system() else {
+            return;
+        };
+        assert!(data.resolve(&layout("us", None, &[])).is_ok());
+        assert!(data.resolve(&layout("no", None, &["ctrl:nocaps"])).is_ok());
+    }
+}
@@ -0,0 +1,225 @@
+use beam_protocol::LatencyStats;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Sent frames remembered while waiting for a browser ack (~4s at 120fps).
+const MAX_PENDING: usize = 512;
+/// Upper bound on samples held between reports.
+const MAX_SAMPLES: usize = 4096;
+/// Acks claiming longer browser-side delays than this are discarded.
+const MAX_DISPLAY_DELAY_MS: f64 = 10_000.0;
+/// Weight of the newest sample in the smoothed round-trip time.
+const RTT_SMOOTHING: f64 = 0.125;
+
+struct SentFrame {
+    timestamp_us: u64,
+    /// Capture → handed to the WebSocket outbox
+    pipeline: Duration,
+    sent_at: Instant,
+}
+
+struct Sample {
+    total_ms: f64,
+    pipeline_ms: f64,
+    network_ms: f64,
+}
+
+/// Glass-to-glass latency estimator.
+///
+/// The agent and browser clocks aren't synchronized, so latency is
+/// estimated from the agent's side: agent pipeline time (exact) + browser
+/// receive-to-display time (reported in the ack) + one-way network time,
+/// taken as half of what remains of the ack round trip.
+#[derive(Default)]
+pub struct LatencyTracker {
+    pending: VecDeque<SentFrame>,
+    samples: Vec<Sample>,
+    /// Smoothed network round trip (ack round trip minus browser display time)
+    rtt_ms: Option<f64>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember a frame handed to the transport.
+    pub fn record_sent(&mut self, timestamp_us: u64, pipeline: Duration, sent_at: Instant) {
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(SentFrame {
+            timestamp_us,
+            pipeline,
+            sent_at,
+        });
+    }
+
+    /// Match a browser display ack to its frame. Returns false if the frame
+    /// is unknown (too old, or never sent) or the ack is malformed.
+    pub fn record_ack(
+        &mut self,
+        timestamp_us: u64,
+        display_delay_ms: f64,
+        acked_at: Instant,
+    ) -> bool {
+        if !display_delay_ms.is_finite()
+            || !(0.0..=MAX_DISPLAY_DELAY_MS).contains(&display_delay_ms)
+        {
+            return false;
+        }
+        let Some(idx) = self
+            .pending
+            .iter()
+            .position(|f| f.timestamp_us == timestamp_us)
+        else {
+            return false;
+        };
+        // Frames before the acked one will never be acked (browser samples)
+        self.pending.drain(..idx);
+        let Some(frame) = self.pending.pop_front() else {
+            return false;
+        };
+
+        let round_trip_ms = acked_at
+            .saturating_duration_since(frame.sent_at)
+            .as_secs_f64()
+            * 1000.0;
+        let network_rtt_ms = (round_trip_ms - display_delay_ms).max(0.0);
+        let network_ms = network_rtt_ms / 2.0;
+        self.rtt_ms = Some(match self.rtt_ms {
+            Some(rtt) => rtt + RTT_SMOOTHING * (network_rtt_ms - rtt),
+            None => network_rtt_ms,
+        });
+        let pipeline_ms = frame.pipeline.as_secs_f64() * 1000.0;
+
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(Sample {
+                total_ms: pipeline_ms + network_ms + display_delay_ms,
+                pipeline_ms,
+                network_ms,
+            });
+        }
+        true
+    }
+
+    /// Smoothed network round-trip time, once at least one ack has arrived.
+    pub fn rtt_ms(&self) -> Option<f64> {
+        self.rtt_ms
+    }
+
+    /// Aggregate and clear the samples gathered since the last report.
+    pub fn take_report(&mut self) -> Option<LatencyStats> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let samples = std::mem::take(&mut self.samples);
+        let sorted = |f: fn(&Sample) -> f64| {
+            let mut v: Vec<f64> = samples.iter().map(f).collect();
+            v.sort_by(f64::total_cmp);
+            v
+        };
+        let total = sorted(|s| s.total_ms);
+        let pipeline = sorted(|s| s.pipeline_ms);
+        let network = sorted(|s| s.network_ms);
+
+        Some(LatencyStats {
+            samples: samples.len() as u32,
+            p50_ms: percentile(&total, 50.0),
+            p95_ms: percentile(&total, 95.0),
+            p99_ms: percentile(&total, 99.0),
+            pipeline_p50_ms: percentile(&pipeline, 50.0),
+            network_p50_ms: percentile(&network, 50.0),
+        })
+    }
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+pub(crate) fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_nearest_rank() {
+        let v: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&v, 50.0), 50.0);
+        assert_eq!(percentile(&v, 95.0), 95.0);
+        assert_eq!(percentile(&v, 99.0), 99.0);
+        assert_eq!(percentile(&[7.0], 99.0), 7.0);
+    }
+
+    #[test]
+    fn ack_estimates_glass_to_glass() {
+        let mut t = LatencyTracker::new();
+        let sent = Instant::now();
+        t.record_sent(1000, Duration::from_millis(6), sent);
+
+        // 30ms round trip, 10ms of which was browser decode/display
+        assert!(t.record_ack(1000, 10.0, sent + Duration::from_millis(30)));
+
+        let report = t.take_report().unwrap();
+        assert_eq!(report.samples, 1);
+        assert!((report.pipeline_p50_ms - 6.0).abs() < 0.01);
+        assert!((report.network_p50_ms - 10.0).abs() < 0.01);
+        assert!((report.p50_ms - 26.0).abs() < 0.01);
+
+        // Samples are cleared after reporting
+        assert!(t.take_report().is_none());
+    }
+
+    #[test]
+    fn rtt_is_smoothed() {
+        let mut t = LatencyTracker::new();
+        let sent = Instant::now();
+        assert!(t.rtt_ms().is_none());
+
+        t.record_sent(1, Duration::ZERO, sent);
+        assert!(t.record_ack(1, 0.0, sent + Duration::from_millis(40)));
+        assert!((t.rtt_ms().unwrap() - 40.0).abs() < 0.01);
+
+        // A single spike moves the estimate by 1/8 of the difference
+        t.record_sent(2, Duration::ZERO, sent);
+        assert!(t.record_ack(2, 0.0, sent + Duration::from_millis(120)));
+        assert!((t.rtt_ms().unwrap() - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn ack_drops_older_unacked_frames() {
+        let mut t = LatencyTracker::new();
+        let now = Instant::now();
+        for ts in [1, 2, 3] {
+            t.record_sent(ts, Duration::ZERO, now);
+        }
+        assert!(t.record_ack(2, 0.0, now));
+        // Frame 1 was skipped by sampling and is gone; frame 3 still pending
+        assert!(!t.record_ack(1, 0.0, now));
+        assert!(t.record_ack(3, 0.0, now));
+    }
+
+    #[test]
+    fn ack_rejects_unknown_or_bogus() {
+        let mut t = LatencyTracker::new();
+        let now = Instant::now();
+        t.record_sent(5, Duration::ZERO, now);
+        assert!(!t.record_ack(6, 1.0, now));
+        assert!(!t.record_ack(5, f64::NAN, now));
+        assert!(!t.record_ack(5, -1.0, now));
+        assert!(t.take_report().is_none());
+    }
+
+    #[test]
+    fn pending_is_bounded() {
+        let mut t = LatencyTracker::new();
+        let now = Instant::now();
+        for ts in 0..(MAX_PENDING as u64 + 10) {
+            t.record_sent(ts, Duration::ZERO, now);
+        }
+        assert_eq!(t.pending.len(), MAX_PENDING);
+        assert!(!t.record_ack(0, 0.0, now));
+    }
+}
@@ -1,33 +1,70 @@
+mod activity;
+mod assist;
 mod audio;
 mod capture;
+mod chaos;
 mod cli;
 mod clipboard;
+mod clipboard_history;
 mod clipboard_sync;
 mod cursor;
 mod display;
+mod encoded_output;
 mod encoder;
 mod file_transfer_task;
 mod filetransfer;
+mod frame_queue;
 mod h264;
 mod input;
+mod input_batch;
+mod input_limit;
+mod input_pacer;
+mod keyboard_layout;
+mod latency;
+mod media_clock;
+mod mixer;
+mod pipeline_stats;
+mod printing;
+mod quality;
+mod sandbox;
+mod sched;
+mod second_screen;
 mod signaling;
+mod skel;
+mod thumbnail;
+mod url_open;
 mod video;
+mod watchdog;
+mod webcam;
 
+use activity::{ActivityMonitor, IdleLadder};
 use anyhow::Context;
-use audio::AudioCapture;
-use beam_protocol::InputEvent;
+use audio::{AudioCapture, AudioMode, AudioRouter, AudioSelection, PlayoutStats};
+use beam_protocol::{
+    AgentMessage, AudioBackend, ClipboardPolicy, DEFAULT_REFRESH_HZ, DISPLAY_REFRESH_HZ,
+    DisplayMode, IdleAction, InputEvent, MAX_CLIPBOARD_BYTES, OPUS_FRAME_MS, QualityPreset,
+    Rotation, TimedInput,
+};
 use capture::ScreenCapture;
 use cli::DEFAULT_FRAMERATE;
 use clipboard::ClipboardBridge;
-use encoder::Encoder;
+use clipboard_sync::ClipboardRequest;
+use encoder::{Encoder, EncoderHealth, EncoderSettings, EncoderType};
 use input::InputInjector;
+use keyboard_layout::KeyboardLayout;
+use latency::LatencyTracker;
+use media_clock::{AudioStamp, AudioTimeline, MediaClock};
+use pipeline_stats::PipelineTimer;
+use quality::{LinkStats, QualityMonitor};
+use sched::ThreadGroup;
 use signaling::SignalingCtx;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info, warn};
+use webcam::WebcamCommand;
 
 /// Commands sent from async tasks to the capture thread.
 /// Using a command channel lets the capture thread exclusively own (and recreate)
@@ -39,17 +76,69 @@ pub(crate) enum CaptureCommand {
     },
     /// Recreate the encoder pipeline to guarantee a fresh IDR frame.
     ResetEncoder,
+    /// The server connection was re-established and the browser needs an
+    /// IDR: forced where the encoder honours ForceKeyUnit, otherwise the
+    /// same as `ResetEncoder`.
+    Reconnected,
+    /// Switch encoder rate control (recreates the pipeline).
+    SetQuality(QualityPreset),
+    /// Apply the server's bandwidth cap in kbps, 0 = none (recreates the
+    /// pipeline if the bitrate changes).
+    SetBitrateCap(u32),
+    /// Toggle burning the X cursor image into captured frames.
+    SetCursorComposite(bool),
+    /// Reopen the X display connection and shared memory capture (watchdog
+    /// recovery).
+    ReinitCapture,
+    /// Apply a browser's `DisplaySettings` to the virtual display and
+    /// report the result.
+    SetDisplayLayout {
+        rotation: Option<Rotation>,
+        refresh: Option<u32>,
+        modes: Option<Vec<DisplayMode>>,
+    },
+    /// Attach, resize (Some) or detach (None) the second monitor and
+    /// report the result.
+    SetSecondScreen(Option<(u32, u32)>),
+}
+
+/// Commands sent from the input callback to the audio thread, which owns
+/// the PulseAudio stream and the Opus encoder.
+pub(crate) enum AudioCommand {
+    /// Capture a different sink or application.
+    Select(AudioSelection),
+    /// Switch the Opus encoding profile.
+    SetMode(AudioMode),
+    /// Change the Opus frame length, FEC or DTX; None keeps the setting.
+    SetTuning {
+        frame_ms: Option<u32>,
+        fec: Option<bool>,
+        dtx: Option<bool>,
+    },
+    /// Pause (false) or resume (true) capture without closing the stream.
+    SetEnabled(bool),
 }
 
 /// Shared context for building the input event callback.
 struct InputCallbackCtx {
     injector: Arc<Mutex<InputInjector>>,
     clipboard: Arc<Mutex<ClipboardBridge>>,
+    clipboard_policy: ClipboardPolicy,
+    /// Send PRIMARY to the browser after selection gestures
+    primary_selection: bool,
     file_transfer: Arc<Mutex<filetransfer::FileTransferManager>>,
-    resize_tx: mpsc::Sender<(u32, u32)>,
+    resize_tx: mpsc::Sender<(u32, u32, f64)>,
     last_input_time: Arc<AtomicU64>,
-    clipboard_read_tx: mpsc::Sender<()>,
+    clipboard_tx: mpsc::Sender<ClipboardRequest>,
     download_request_tx: mpsc::Sender<String>,
+    pointer_lock_tx: mpsc::Sender<bool>,
+    /// Listing requests, each after an optional mixer change
+    audio_sources_tx: mpsc::Sender<Option<mixer::AppVolume>>,
+    audio_cmd_tx: std::sync::mpsc::Sender<AudioCommand>,
+    audio_playout: Arc<PlayoutStats>,
+    webcam_tx: std::sync::mpsc::SyncSender<WebcamCommand>,
+    latency: Arc<Mutex<LatencyTracker>>,
+    link_stats: Arc<LinkStats>,
     capture_wake: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
     capture_cmd_tx: std::sync::mpsc::Sender<CaptureCommand>,
     tab_backgrounded: Arc<AtomicBool>,
@@ -57,6 +146,10 @@ struct InputCallbackCtx {
     display: String,
     max_width: u32,
     max_height: u32,
+    /// Remote assistance: whether the operator may currently use the display
+    assist_control: Option<Arc<AtomicBool>>,
+    limiter: Arc<input_limit::InputLimiter>,
+    layout_tx: std::sync::mpsc::Sender<KeyboardLayout>,
 }
 
 /// Build the reusable input event callback that dispatches input events
@@ -65,29 +158,44 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
     let InputCallbackCtx {
         injector,
         clipboard,
+        clipboard_policy,
+        primary_selection,
         file_transfer,
         resize_tx,
         last_input_time,
-        clipboard_read_tx,
+        clipboard_tx,
         download_request_tx,
+        pointer_lock_tx,
+        audio_sources_tx,
+        audio_cmd_tx,
+        audio_playout,
+        webcam_tx,
+        latency,
+        link_stats,
         capture_wake,
-        capture_cmd_tx: _capture_cmd_tx,
+        capture_cmd_tx,
         tab_backgrounded,
         force_keyframe,
         display,
         max_width,
         max_height,
+        assist_control,
+        limiter,
+        layout_tx,
     } = ctx;
     let ctrl_down = Arc::new(AtomicBool::new(false));
-    let last_layout = Arc::new(std::sync::Mutex::new(String::new()));
+    let sync_primary = primary_selection && clipboard_policy.allows_to_local();
 
     Arc::new(move |event: InputEvent| {
-        // Update last input timestamp for idle detection
-        let now_ms = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64;
-        last_input_time.store(now_ms, Ordering::Relaxed);
+        if let Some(control) = &assist_control
+            && !assist::allowed(&event, control.load(Ordering::Relaxed))
+        {
+            debug!("Dropping input the assisted user has not allowed");
+            return;
+        }
+        if !limiter.allow(&event, Instant::now()) {
+            return;
+        }
 
         // Wake capture thread if it's sleeping in idle mode
         {
@@ -97,13 +205,22 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
             cvar.notify_one();
         }
 
-        // Clear backgrounded flag on user-interactive input events
+        // Only user-interactive input counts for idle detection and clears
+        // the backgrounded flag; frame acks and other telemetry keep
+        // arriving from an unattended tab
         match &event {
             InputEvent::Key { .. }
             | InputEvent::MouseMove { .. }
+            | InputEvent::ScreenMouseMove { .. }
             | InputEvent::RelativeMouseMove { .. }
             | InputEvent::Button { .. }
-            | InputEvent::Scroll { .. } => {
+            | InputEvent::Scroll { .. }
+            | InputEvent::InputBatch { .. } => {
+                let now_ms = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                last_input_time.store(now_ms, Ordering::Relaxed);
                 if tab_backgrounded.swap(false, Ordering::Relaxed) {
                     debug!("Input received while backgrounded, clearing flag");
                 }
@@ -116,8 +233,16 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
                 if c == 29 || c == 97 {
                     ctrl_down.store(d, Ordering::Relaxed);
                 }
-                if !d && (c == 46 || c == 45) && ctrl_down.load(Ordering::Relaxed) {
-                    let _ = clipboard_read_tx.try_send(());
+                if !d
+                    && (c == 46 || c == 45)
+                    && ctrl_down.load(Ordering::Relaxed)
+                    && clipboard_policy.allows_to_local()
+                {
+                    let _ = clipboard_tx.try_send(ClipboardRequest::ReadRemote);
+                }
+                // Shift+arrows, Shift+click: keyboard selections end on Shift
+                if !d && (c == 42 || c == 54) && sync_primary {
+                    let _ = clipboard_tx.try_send(ClipboardRequest::ReadPrimary);
                 }
                 if let Err(e) = injector
                     .lock()
@@ -136,11 +261,17 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
                     warn!("Mouse move inject error: {e:#}");
                 }
             }
+            InputEvent::ScreenMouseMove { x, y } => {
+                if let Err(e) = injector
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .inject_screen_mouse_move(x, y)
+                {
+                    warn!("Mouse move inject error: {e:#}");
+                }
+            }
             InputEvent::RelativeMouseMove { dx, dy } => {
-                if dx.is_finite()
-                    && dy.is_finite()
-                    && (-10000.0..=10000.0).contains(&dx)
-                    && (-10000.0..=10000.0).contains(&dy)
+                if is_sane_delta(dx, dy)
                     && let Err(e) = injector
                         .lock()
                         .unwrap_or_else(|e| e.into_inner())
@@ -157,12 +288,13 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
                 {
                     warn!("Button inject error: {e:#}");
                 }
+                // Drag, double- and triple-click selections end on a left release
+                if b == 0 && !d && sync_primary {
+                    let _ = clipboard_tx.try_send(ClipboardRequest::ReadPrimary);
+                }
             }
             InputEvent::Scroll { dx, dy } => {
-                if dx.is_finite()
-                    && dy.is_finite()
-                    && (-10000.0..=10000.0).contains(&dx)
-                    && (-10000.0..=10000.0).contains(&dy)
+                if is_sane_delta(dx, dy)
                     && let Err(e) = injector
                         .lock()
                         .unwrap_or_else(|e| e.into_inner())
@@ -171,8 +303,15 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
                     warn!("Scroll inject error: {e:#}");
                 }
             }
+            InputEvent::Clipboard { .. } | InputEvent::ClipboardPrimary { .. }
+                if !clipboard_policy.allows_to_remote() =>
+            {
+                debug!(
+                    policy = clipboard_policy.name(),
+                    "Ignoring browser clipboard (policy)"
+                );
+            }
             InputEvent::Clipboard { ref text } => {
-                const MAX_CLIPBOARD_BYTES: usize = 1_048_576;
                 if text.len() > MAX_CLIPBOARD_BYTES {
                     warn!(
                         len = text.len(),
@@ -185,10 +324,11 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
                     .set_text(text)
                 {
                     warn!("Clipboard set error: {e:#}");
+                } else {
+                    let _ = clipboard_tx.try_send(ClipboardRequest::FromBrowser(text.clone()));
                 }
             }
             InputEvent::ClipboardPrimary { ref text } => {
-                const MAX_CLIPBOARD_BYTES: usize = 1_048_576;
                 if text.len() > MAX_CLIPBOARD_BYTES {
                     warn!(
                         len = text.len(),
@@ -201,57 +341,193 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
                     .set_primary_text(text)
                 {
                     warn!("Primary clipboard set error: {e:#}");
+                } else if primary_selection {
+                    let _ =
+                        clipboard_tx.try_send(ClipboardRequest::FromBrowserPrimary(text.clone()));
                 }
             }
-            InputEvent::Resize { w, h } => {
+            InputEvent::Resize { w, h, s } => {
+                // Render at physical resolution so HiDPI clients get a 1:1 image
+                let scale = display::normalize_scale(s.unwrap_or(1.0));
+                let (pw, ph) = display::scale_dimensions(w, h, scale);
                 if let Some((cw, ch)) =
-                    display::clamp_resize_dimensions(w, h, max_width, max_height)
+                    display::clamp_resize_dimensions(pw, ph, max_width, max_height)
                 {
-                    let _ = resize_tx.try_send((cw, ch));
+                    let _ = resize_tx.try_send((cw, ch, scale));
                 } else {
                     warn!(w, h, "Ignoring invalid resize dimensions");
                 }
             }
-            InputEvent::Layout { ref layout } => {
-                if layout.len() <= 20
-                    && !layout.is_empty()
-                    && layout
-                        .chars()
-                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
-                {
-                    let mut prev = last_layout.lock().unwrap_or_else(|e| e.into_inner());
-                    if *prev == *layout {
-                        return;
+            InputEvent::ScreenAdd { w, h } => {
+                match display::clamp_resize_dimensions(w, h, max_width, max_height) {
+                    Some(size) => {
+                        info!(w, h, "Second screen requested");
+                        let _ = capture_cmd_tx.send(CaptureCommand::SetSecondScreen(Some(size)));
                     }
-                    *prev = layout.clone();
-                    drop(prev);
-
-                    let display_str = display.clone();
-                    let layout = layout.clone();
-                    std::thread::spawn(move || {
-                        match std::process::Command::new("setxkbmap")
-                            .arg(&layout)
-                            .env("DISPLAY", &display_str)
-                            .output()
-                        {
-                            Ok(output) if output.status.success() => {
-                                info!(layout = %layout, "Keyboard layout set via setxkbmap");
-                            }
-                            Ok(output) => {
-                                let stderr = String::from_utf8_lossy(&output.stderr);
-                                warn!(layout = %layout, "setxkbmap failed: {stderr}");
-                            }
-                            Err(e) => {
-                                warn!(layout = %layout, "Failed to run setxkbmap: {e}");
-                            }
-                        }
-                    });
+                    None => warn!(w, h, "Ignoring invalid second screen dimensions"),
+                }
+            }
+            InputEvent::ScreenRemove => {
+                let _ = capture_cmd_tx.send(CaptureCommand::SetSecondScreen(None));
+            }
+            InputEvent::Layout {
+                layout,
+                variant,
+                options,
+            } => {
+                // Checked and applied on the layout thread
+                let _ = layout_tx.send(KeyboardLayout {
+                    layout,
+                    variant,
+                    options,
+                });
+            }
+            InputEvent::DisplaySettings {
+                rotation,
+                refresh,
+                modes,
+            } => {
+                if let Some(hz) = refresh
+                    && hz != 0
+                    && !DISPLAY_REFRESH_HZ.contains(&hz)
+                {
+                    warn!(hz, "Ignoring display settings with an invalid refresh rate");
                 } else {
-                    warn!(layout = %layout, "Invalid keyboard layout name, ignoring");
+                    let _ = capture_cmd_tx.send(CaptureCommand::SetDisplayLayout {
+                        rotation,
+                        refresh,
+                        modes,
+                    });
+                }
+            }
+            InputEvent::Quality { mode } => {
+                info!(preset = mode.name(), "Quality preset requested");
+                let _ = capture_cmd_tx.send(CaptureCommand::SetQuality(mode));
+            }
+            InputEvent::PointerLock { locked } => {
+                let mut inj = injector.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = inj.set_pointer_lock(locked) {
+                    warn!(locked, "Pointer lock change failed: {e:#}");
+                }
+                // Report the actual state so the browser can release its lock on failure
+                let _ = pointer_lock_tx.try_send(inj.pointer_locked());
+            }
+            InputEvent::KeyboardGrab { grab } => {
+                debug!(grab, "Browser keyboard grab changed");
+                if !grab
+                    && let Err(e) = injector
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .release_all_keys()
+                {
+                    warn!("Failed to release held keys: {e:#}");
+                }
+            }
+            InputEvent::KeyCombo { ref combo } => match input::combo_keys(combo) {
+                Some(keys) => {
+                    info!(combo, "Synthesizing system key combo");
+                    if let Err(e) = injector
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .inject_combo(keys)
+                    {
+                        warn!("Key combo inject error: {e:#}");
+                    }
                 }
+                None => warn!(combo, "Ignoring unknown key combo"),
+            },
+            InputEvent::OpenUrl { ref url } => {
+                // Browser startup can block on which/pgrep; keep it off the
+                // signaling task
+                let display_str = display.clone();
+                let url = url.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = url_open::open_in_session(&display_str, &url) {
+                        warn!("Failed to open URL in session: {e:#}");
+                    }
+                });
+            }
+            InputEvent::FrameAck { ts, dd } => {
+                link_stats.record_acked();
+                let now = Instant::now();
+                if !latency
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record_ack(ts, dd, now)
+                {
+                    debug!(ts, "Frame ack for unknown frame");
+                }
+            }
+            InputEvent::ClipboardHistoryRequest => {
+                let _ = clipboard_tx.try_send(ClipboardRequest::ListHistory);
+            }
+            InputEvent::ClipboardHistoryPaste { id } => {
+                let _ = clipboard_tx.try_send(ClipboardRequest::PasteHistory(id));
+            }
+            InputEvent::ClipboardHistoryClear => {
+                let _ = clipboard_tx.try_send(ClipboardRequest::ClearHistory);
             }
-            InputEvent::Quality { .. } => {
-                // Quality selector removed — bitrate/framerate set by config
+            InputEvent::AudioSourcesRequest => {
+                let _ = audio_sources_tx.try_send(None);
+            }
+            InputEvent::AudioAppVolume { app, volume, muted } => {
+                let _ = audio_sources_tx.try_send(Some(mixer::AppVolume {
+                    index: app,
+                    volume,
+                    muted,
+                }));
+            }
+            InputEvent::AudioSource { sink, app } => {
+                let selection = AudioSelection::from_event(sink, app);
+                info!(?selection, "Audio source selection requested");
+                let _ = audio_cmd_tx.send(AudioCommand::Select(selection));
+            }
+            InputEvent::WebcamStart { codec, w, h } => {
+                info!(codec, w, h, "Browser webcam starting");
+                let _ = webcam_tx.send(WebcamCommand::Start {
+                    codec,
+                    width: w,
+                    height: h,
+                });
+            }
+            InputEvent::WebcamStop => {
+                info!("Browser webcam stopped");
+                let _ = webcam_tx.send(WebcamCommand::Stop);
+            }
+            InputEvent::AudioEnabled { enabled } => {
+                info!(enabled, "Audio capture toggle requested");
+                let _ = audio_cmd_tx.send(AudioCommand::SetEnabled(enabled));
+            }
+            InputEvent::AudioMode { ref mode } => match AudioMode::from_mode(mode) {
+                Some(audio_mode) => {
+                    info!(mode, "Audio mode requested");
+                    let _ = audio_cmd_tx.send(AudioCommand::SetMode(audio_mode));
+                }
+                None => warn!(mode, "Ignoring unknown audio mode"),
+            },
+            InputEvent::AudioTuning { frame_ms, fec, dtx } => match frame_ms {
+                Some(ms) if !OPUS_FRAME_MS.contains(&ms) => {
+                    warn!(frame_ms = ms, "Ignoring unsupported Opus frame length");
+                }
+                _ => {
+                    info!(?frame_ms, ?fec, ?dtx, "Audio tuning requested");
+                    let _ = audio_cmd_tx.send(AudioCommand::SetTuning { frame_ms, fec, dtx });
+                }
+            },
+            InputEvent::AudioReport {
+                played_ms,
+                concealed_ms,
+            } => {
+                audio_playout
+                    .played_ms
+                    .fetch_add(u64::from(played_ms), Ordering::Relaxed);
+                audio_playout
+                    .concealed_ms
+                    .fetch_add(u64::from(concealed_ms.min(played_ms)), Ordering::Relaxed);
+            }
+            InputEvent::CursorMode { composite } => {
+                info!(composite, "Cursor compositing mode requested");
+                let _ = capture_cmd_tx.send(CaptureCommand::SetCursorComposite(composite));
             }
             InputEvent::VisibilityState { visible } => {
                 debug!(visible, "Browser tab visibility changed");
@@ -302,12 +578,244 @@ fn build_input_callback(ctx: InputCallbackCtx) -> Arc<dyn Fn(InputEvent) + Send
                     warn!(path, "File download request dropped: {e:#}");
                 }
             }
+            InputEvent::InputBatch { e } => {
+                if !beam_protocol::is_valid_input_batch(&e) {
+                    warn!(len = e.len(), "Ignoring invalid input batch");
+                    return;
+                }
+                let events = input_batch::coalesce(e, input_batch::MOVE_INTERVAL_MS);
+                // One lock for the whole frame of pointer input
+                let mut inj = injector.lock().unwrap_or_else(|e| e.into_inner());
+                let mut left_released = false;
+                for TimedInput { ev, .. } in events {
+                    left_released |= matches!(ev, InputEvent::Button { b: 0, d: false });
+                    if let Err(e) = inject_pointer(&mut inj, ev) {
+                        warn!("Batched input inject error: {e:#}");
+                    }
+                }
+                if left_released && sync_primary {
+                    let _ = clipboard_tx.try_send(ClipboardRequest::ReadPrimary);
+                }
+            }
         }
     })
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+/// Relative motion and scroll deltas outside this range are dropped as bogus.
+fn is_sane_delta(dx: f64, dy: f64) -> bool {
+    dx.is_finite()
+        && dy.is_finite()
+        && (-10000.0..=10000.0).contains(&dx)
+        && (-10000.0..=10000.0).contains(&dy)
+}
+
+/// Inject one event of an `InputBatch` (pointer events only, see
+/// `InputEvent::is_batchable`).
+fn inject_pointer(inj: &mut InputInjector, event: InputEvent) -> anyhow::Result<()> {
+    match event {
+        InputEvent::MouseMove { x, y } => inj.inject_mouse_move_abs(x, y),
+        InputEvent::ScreenMouseMove { x, y } => inj.inject_screen_mouse_move(x, y),
+        InputEvent::RelativeMouseMove { dx, dy } if is_sane_delta(dx, dy) => {
+            inj.inject_mouse_move_rel(dx, dy)
+        }
+        InputEvent::Button { b, d } => inj.inject_button(b, d),
+        InputEvent::Scroll { dx, dy } if is_sane_delta(dx, dy) => inj.inject_scroll(dx, dy),
+        _ => Ok(()),
+    }
+}
+
+/// Queue an `EncoderStatus` report so the server records which encoder is
+/// running. Called from the capture thread, so it never blocks; the report
+/// is dropped if the outbox is full.
+fn send_encoder_status(ws_tx: &mpsc::Sender<Message>, name: &str, fallback: bool) {
+    let status = beam_protocol::EncoderStatus {
+        name: name.to_string(),
+        fallback,
+    };
+    let Ok(msg) = serde_json::to_string(&status) else {
+        return;
+    };
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        warn!(encoder = name, "Failed to queue encoder status: {e}");
+    }
+}
+
+/// Apply `change` to what the agent reports about itself, and queue the
+/// report if that changed anything.
+fn update_agent_info(
+    ws_tx: &mpsc::Sender<Message>,
+    info: &Mutex<beam_protocol::AgentInfo>,
+    change: impl FnOnce(&mut beam_protocol::AgentInfo),
+) {
+    let mut info = info.lock().unwrap_or_else(|e| e.into_inner());
+    let before = info.clone();
+    change(&mut info);
+    if *info == before {
+        return;
+    }
+    let Ok(msg) = serde_json::to_string(&*info) else {
+        return;
+    };
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        debug!("Failed to queue agent info: {e}");
+    }
+}
+
+/// Start over after the X server died mid-session. The agent executes
+/// itself again with the same arguments, so the session keeps its id,
+/// token and process (which the server and a PAM session watch), and the
+/// new image starts Xorg, PulseAudio and the desktop afresh. A display
+/// this agent didn't start, or a sandboxed agent, exits with an error for
+/// the server to restart instead: no_new_privs would outlast the exec and
+/// keep the setuid Xorg wrapper from starting a server. Returns only on
+/// failure.
+fn restart_display(
+    x_display: &str,
+    sandbox: bool,
+    virtual_display: Option<display::VirtualDisplay>,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let Some(virtual_display) = virtual_display else {
+        anyhow::bail!("Lost X display {x_display}, which this agent didn't start");
+    };
+    if sandbox {
+        anyhow::bail!("Lost X display {x_display}; leaving the restart to the server");
+    }
+    // Stops PulseAudio and the desktop, and cleans up after them
+    drop(virtual_display);
+    let display_num = x_display.trim_start_matches(':');
+    let _ = std::fs::remove_file(format!("/tmp/.X{display_num}-lock"));
+
+    warn!(display = x_display, width, height, "Restarting the display");
+    let exe = std::env::current_exe().context("Cannot locate beam-agent")?;
+    let err = std::process::Command::new(exe)
+        .args(cli::restart_args(std::env::args().skip(1), width, height))
+        .exec();
+    Err(err).context("Failed to restart the agent")
+}
+
+/// Execute the agent binary at `path` in place of this one, as the server
+/// asked once the pipeline has stopped. The session keeps its process, id
+/// and token as on a display restart, and the display keeps running: the
+/// new image adopts the processes this one started. Returns only on
+/// failure, after stopping the display as a plain shutdown would.
+fn upgrade_agent(
+    path: &str,
+    virtual_display: Option<display::VirtualDisplay>,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let handover = virtual_display.map(display::VirtualDisplay::hand_over);
+    let err = match handover.as_ref().map(serde_json::to_string).transpose() {
+        Ok(handover_json) => {
+            info!(%path, width, height, "Upgrading the agent");
+            let err = std::process::Command::new(path)
+                .args(cli::upgrade_args(
+                    std::env::args().skip(1),
+                    width,
+                    height,
+                    handover_json.as_deref(),
+                ))
+                .exec();
+            anyhow::Error::new(err).context(format!("Failed to execute {path}"))
+        }
+        Err(e) => anyhow::Error::new(e).context("Failed to describe the display"),
+    };
+    drop(handover.map(display::VirtualDisplay::adopt));
+    Err(err)
+}
+
+/// Tell the browser what the virtual display is set to.
+fn send_display_state(
+    ws_tx: &mpsc::Sender<Message>,
+    width: u32,
+    height: u32,
+    layout: &display::DisplayLayout,
+) {
+    let msg = AgentMessage::DisplayState {
+        w: width,
+        h: height,
+        refresh: layout.mode_for(width, height).refresh,
+        rotation: layout.rotation,
+        modes: layout.modes.clone(),
+    }
+    .to_json();
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        warn!("Failed to queue display state: {e}");
+    }
+}
+
+/// Tell the browser the second monitor's size, 0 x 0 for none.
+fn send_second_screen_state(
+    ws_tx: &mpsc::Sender<Message>,
+    second_screen: Option<&second_screen::SecondScreen>,
+) {
+    let (w, h) = second_screen.map_or((0, 0), |s| (s.area().width, s.area().height));
+    let msg = AgentMessage::SecondScreen { w, h }.to_json();
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        warn!("Failed to queue second screen state: {e}");
+    }
+}
+
+/// Capture of the first monitor: the whole screen, or with a second
+/// monitor beside it, its own `width` x `height` corner.
+fn first_screen_capture(
+    x_display: &str,
+    width: u32,
+    height: u32,
+    layout: &display::DisplayLayout,
+) -> anyhow::Result<ScreenCapture> {
+    if layout.second_screen.is_none() {
+        return ScreenCapture::new(x_display);
+    }
+    ScreenCapture::with_area(
+        x_display,
+        capture::Area {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        },
+    )
+}
+
+/// The configured bitrate held to a bandwidth cap (0 = none).
+fn capped_bitrate(bitrate: u32, cap: u32) -> u32 {
+    if cap == 0 { bitrate } else { bitrate.min(cap) }
+}
+
+/// Tell the server an idle policy stage fired; it disconnects the browser
+/// or ends the session. Called from the capture thread, so it never blocks.
+fn send_idle_notice(ws_tx: &mpsc::Sender<Message>, action: IdleAction) {
+    let notice = beam_protocol::IdleNotice { action };
+    let Ok(msg) = serde_json::to_string(&notice) else {
+        return;
+    };
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        warn!(?action, "Failed to queue idle notice: {e}");
+    }
+}
+
+/// Tell the browser, and through it the server, that `requested` stayed
+/// over the quality ceiling and the encoder now runs at `effective`.
+fn send_quality_fallback(ws_tx: &mpsc::Sender<Message>, fallback: beam_protocol::QualityFallback) {
+    let Ok(msg) = serde_json::to_string(&fallback) else {
+        return;
+    };
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        warn!(
+            preset = fallback.requested.name(),
+            "Failed to queue quality fallback: {e}"
+        );
+    }
+}
+
+fn main() -> anyhow::Result<()> {
     // Install rustls crypto provider (needed for TLS WebSocket to server)
     rustls::crypto::ring::default_provider()
         .install_default()
@@ -320,9 +828,27 @@ async fn main() -> anyhow::Result<()> {
         )
         .init();
 
+    let args = cli::parse_args()?;
+
+    // Runtime threads are scheduled per --worker-priority/--worker-cpus;
+    // the first one reports how that went
+    let realtime = args.realtime.clone();
+    let affinity = args.cpu_affinity.clone();
+    let worker_reported = AtomicBool::new(false);
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .on_thread_start(move || {
+            let report = !worker_reported.swap(true, Ordering::Relaxed);
+            sched::tune_current_thread(ThreadGroup::Workers, &realtime, &affinity, report);
+        })
+        .build()
+        .context("Failed to start the async runtime")?
+        .block_on(run(args))
+}
+
+async fn run(args: cli::Args) -> anyhow::Result<()> {
     gstreamer::init().context("Failed to initialize GStreamer")?;
 
-    let args = cli::parse_args()?;
     info!(
         display = %args.display,
         session_id = %args.session_id,
@@ -332,13 +858,36 @@ async fn main() -> anyhow::Result<()> {
 
     // PulseAudio server path — derived from display number regardless of new/existing display
     let mut pulse_server: Option<String> = None;
+    let mut pipewire_socket: Option<std::path::PathBuf> = None;
     let display_num: u32 = args.display.trim_start_matches(':').parse().unwrap_or(10);
 
+    // Remote assistance attaches to someone's own display, which must
+    // already be there, and only once they have agreed to it
+    if let Some(assist) = &args.assist {
+        ScreenCapture::new(&args.display).context("The assisted display is not available")?;
+        let x_display = args.display.clone();
+        let operator = assist.operator.clone();
+        let consent_secs = assist.consent_secs;
+        let allowed = tokio::task::spawn_blocking(move || {
+            assist::ask_consent(&x_display, &operator, consent_secs)
+        })
+        .await
+        .context("Consent prompt panicked")?;
+        if !allowed {
+            std::process::exit(beam_protocol::ASSIST_DECLINED_EXIT);
+        }
+    }
+
     // Try to connect to the display; if it doesn't exist, start a virtual one
     let mut virtual_display = match ScreenCapture::new(&args.display) {
+        Ok(_) if args.assist.is_some() => {
+            info!(display = %args.display, "Connected to assisted display");
+            None
+        }
         Ok(_) => {
             info!(display = %args.display, "Connected to existing display");
-            // Session reuse: PulseAudio should already be running for this display
+            // Session reuse: PulseAudio or PipeWire should already be running
+            // for this display
             let pulse_path = format!("/tmp/beam-pulse-{display_num}/native");
             if std::path::Path::new(&pulse_path).exists() {
                 pulse_server = Some(format!("unix:{pulse_path}"));
@@ -346,7 +895,16 @@ async fn main() -> anyhow::Result<()> {
             } else {
                 warn!(%pulse_path, "No PulseAudio socket found for reused display, audio may not work");
             }
-            None
+            let socket = std::path::Path::new(&display::pipewire_runtime_dir(display_num))
+                .join("pipewire-0");
+            if socket.exists() {
+                info!(socket = %socket.display(), "Found existing PipeWire for reused display");
+                pipewire_socket = Some(socket);
+            }
+            // Handed over by the agent this one upgraded
+            args.adopt_display
+                .clone()
+                .map(display::VirtualDisplay::adopt)
         }
         Err(e) => {
             warn!(display = %args.display, "Display not available ({e:#}), starting virtual display");
@@ -354,21 +912,52 @@ async fn main() -> anyhow::Result<()> {
                 Ok(mut vd) => {
                     info!(display = %args.display, "Virtual display started");
 
-                    // Start PulseAudio BEFORE desktop so apps inherit PULSE_SERVER
-                    if let Err(e) = vd.start_pulseaudio() {
+                    // Start the sound server BEFORE desktop so apps inherit
+                    // PULSE_SERVER
+                    let mut backend = args.audio_backend.resolve();
+                    if backend == AudioBackend::PipeWire {
+                        match vd.start_pipewire() {
+                            Ok(()) => {
+                                pipewire_socket = Some(
+                                    std::path::Path::new(&display::pipewire_runtime_dir(
+                                        display_num,
+                                    ))
+                                    .join("pipewire-0"),
+                                );
+                            }
+                            Err(e) => {
+                                warn!("Failed to start PipeWire, trying PulseAudio: {e:#}");
+                                backend = AudioBackend::PulseAudio;
+                            }
+                        }
+                    }
+                    if backend == AudioBackend::PulseAudio
+                        && let Err(e) = vd.start_pulseaudio()
+                    {
                         warn!("Failed to start PulseAudio: {e:#}");
                     }
                     let pulse_path = format!("/tmp/beam-pulse-{display_num}/native");
-                    pulse_server = Some(format!("unix:{pulse_path}"));
+                    let server = format!("unix:{pulse_path}");
                     for _ in 0..20 {
                         if std::path::Path::new(&pulse_path).exists() {
                             break;
                         }
                         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
                     }
+                    if pipewire_socket.is_some()
+                        && let Err(e) = audio::add_session_sink(&server)
+                    {
+                        warn!("Failed to add the PipeWire session sink: {e:#}");
+                    }
+                    pulse_server = Some(server);
 
                     // Start desktop AFTER PulseAudio
-                    if let Err(e) = vd.start_desktop() {
+                    if let Err(e) = vd.start_desktop(
+                        &args.desktop,
+                        display::normalize_scale(args.scale),
+                        args.forward_urls,
+                        args.persist_desktop,
+                    ) {
                         warn!("Failed to start desktop: {e:#}");
                     }
                     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
@@ -387,11 +976,34 @@ async fn main() -> anyhow::Result<()> {
     let width = screen_capture.width();
     let height = screen_capture.height();
 
+    // The session's preferred modes, listed in the desktop's display
+    // settings. The initial size may be one of them, at a rate of its own.
+    let display_layout = display::DisplayLayout {
+        modes: args.display_modes.clone(),
+        ..Default::default()
+    };
+    if !display_layout.modes.is_empty() {
+        if let Err(e) = display::add_display_modes(&args.display, &display_layout.modes) {
+            warn!("Failed to add preferred display modes: {e:#}");
+        }
+        if display_layout.mode_for(width, height).refresh != DEFAULT_REFRESH_HZ
+            && let Err(e) =
+                display::set_display_layout(&args.display, width, height, &display_layout)
+        {
+            warn!("Failed to apply preferred display mode: {e:#}");
+        }
+    }
+
     // Detect encoder type first to determine framerate/bitrate caps.
     // Software x264enc ultrafast on ARM64 can only sustain ~60fps at 1080p.
     // Attempting 120fps causes the appsrc queue to grow faster than the
     // encoder drains it, leading to OOM.
-    let encoder_pref = args.encoder.clone();
+    let encoder_settings = EncoderSettings {
+        preferred: args.encoder.clone(),
+        chroma: args.chroma,
+        tuning: args.tuning.clone(),
+        failed: Vec::new(),
+    };
     let (encoder_type, _) = encoder::detect_encoder_type(args.encoder.as_deref())?;
     let config_framerate;
     let config_bitrate;
@@ -408,21 +1020,51 @@ async fn main() -> anyhow::Result<()> {
         config_framerate = args.framerate;
         config_bitrate = args.bitrate;
     }
+    // What the encoder runs at: config_bitrate under the server's
+    // bandwidth cap, which may move while the session runs
+    let video_bitrate = Arc::new(std::sync::atomic::AtomicU32::new(capped_bitrate(
+        config_bitrate,
+        args.max_bitrate,
+    )));
+    let video_bitrate_for_capture = Arc::clone(&video_bitrate);
+    if args.max_bitrate > 0 && args.max_bitrate < config_bitrate {
+        info!(
+            bitrate = config_bitrate,
+            cap = args.max_bitrate,
+            "Video bitrate held to the bandwidth cap"
+        );
+    }
 
     let encoder = Encoder::with_encoder_preference(
         width,
         height,
+        None,
         config_framerate,
-        config_bitrate,
-        args.encoder.as_deref(),
+        video_bitrate.load(Ordering::Relaxed),
+        &encoder_settings,
+        QualityPreset::Balanced,
     )
     .context("Failed to initialize encoder")?;
 
-    // Channel for encoded video frames: capture thread -> async write loop
-    let (encoded_tx, mut encoded_rx) = mpsc::channel::<Vec<u8>>(2);
+    // Encoded video frames: encoded output thread -> async write loop. Keeps
+    // IDR frames when full and drops the oldest P-frame instead.
+    let (encoded_tx, mut encoded_rx) = frame_queue::frame_queue(2);
+
+    // Each encoder's output, from the capture thread to the encoded output
+    // thread, plus what was submitted so output can be matched to capture
+    let (encoder_output_tx, encoder_output_rx) = std::sync::mpsc::channel();
+    let submitted = Arc::new(encoded_output::SubmitLog::default());
+    let submitted_for_capture = Arc::clone(&submitted);
+    let output_counters = Arc::new(encoded_output::OutputCounters::default());
+    let output_counters_for_capture = Arc::clone(&output_counters);
+    let capture_progress = Arc::new(watchdog::CaptureProgress::default());
+    let capture_progress_for_capture = Arc::clone(&capture_progress);
 
     // Channel for encoded audio frames: audio thread -> async write loop
-    let (audio_tx, mut audio_rx) = mpsc::channel::<Vec<u8>>(8);
+    let (audio_tx, mut audio_rx) = mpsc::channel::<(AudioStamp, Vec<u8>)>(8);
+    // Video and audio frame timestamps, so the browser can keep them in sync
+    let media_clock = MediaClock::start();
+    let audio_drift_us = Arc::new(AtomicI64::new(0));
 
     // Shared WebSocket outbox: video, audio, clipboard, cursor, file download all send here.
     // The signaling loop drains this and writes to the actual WS connection.
@@ -430,16 +1072,51 @@ async fn main() -> anyhow::Result<()> {
     // with drop-on-full semantics to avoid backpressure from slow WS.
     let (ws_outbox_tx, mut ws_outbox_rx) = mpsc::channel::<Message>(32);
 
+    // Delivered once signaling connects; the capture thread reports fallbacks
+    let ws_tx_for_encoder_status = ws_outbox_tx.clone();
+    send_encoder_status(&ws_tx_for_encoder_status, encoder.name(), false);
+    // Sent on every signaling connect; the capture thread keeps it current
+    let agent_info = Arc::new(Mutex::new(beam_protocol::AgentInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        encoder: encoder.name().to_string(),
+        fallback: false,
+        capture: screen_capture.backend().to_string(),
+        width: encoder.output_size().0,
+        height: encoder.output_size().1,
+        framerate: config_framerate,
+    }));
+    let agent_info_for_capture = Arc::clone(&agent_info);
+    let upgrade = Mutex::new(None);
+    if args.display_restarted {
+        let msg = AgentMessage::DisplayRestored.to_json();
+        let _ = ws_outbox_tx.try_send(Message::Text(msg.into()));
+    }
+
     let session_id = args.session_id;
 
-    // Create input injector (uses XTEST extension -- no uinput needed)
-    let input_width = Arc::new(std::sync::atomic::AtomicU32::new(args.width));
-    let input_height = Arc::new(std::sync::atomic::AtomicU32::new(args.height));
+    // Create input injector (uses XTEST extension -- no uinput needed). An
+    // assisted display keeps its own size, whatever the browser asked for.
+    let (input_w, input_h) = if args.assist.is_some() {
+        (width, height)
+    } else {
+        (args.width, args.height)
+    };
+    let input_width = Arc::new(std::sync::atomic::AtomicU32::new(input_w));
+    let input_height = Arc::new(std::sync::atomic::AtomicU32::new(input_h));
+    // Size of the encoded frames, which a letterboxed display scales down
+    // from its own
+    let (stream_w, stream_h) = encoder.output_size();
+    let stream_width = Arc::new(std::sync::atomic::AtomicU32::new(stream_w));
+    let stream_height = Arc::new(std::sync::atomic::AtomicU32::new(stream_h));
+    // Where the second monitor is, while the capture thread streams one
+    let second_screen_area = Arc::new(Mutex::new(None));
+    let second_screen_area_for_capture = Arc::clone(&second_screen_area);
     let injector = Arc::new(Mutex::new(
         InputInjector::new(
             &args.display,
             Arc::clone(&input_width),
             Arc::clone(&input_height),
+            second_screen_area,
         )
         .context("Failed to create input injector")?,
     ));
@@ -456,8 +1133,10 @@ async fn main() -> anyhow::Result<()> {
     // Command channel for non-latency-critical capture thread operations
     let (capture_cmd_tx, capture_cmd_rx) = std::sync::mpsc::channel::<CaptureCommand>();
 
-    // Resize request channel
-    let (resize_tx, mut resize_rx) = mpsc::channel::<(u32, u32)>(4);
+    // Resize request channel. A window being dragged sends a burst of
+    // sizes; only the last is applied, once none has come for this long.
+    const RESIZE_SETTLE: Duration = Duration::from_millis(150);
+    let (resize_tx, mut resize_rx) = mpsc::channel::<(u32, u32, f64)>(4);
 
     // Idle detection
     let last_input_time = Arc::new(AtomicU64::new(0));
@@ -467,12 +1146,38 @@ async fn main() -> anyhow::Result<()> {
     let capture_wake = Arc::new((std::sync::Mutex::new(false), std::sync::Condvar::new()));
     let capture_wake_for_input = Arc::clone(&capture_wake);
 
-    // Clipboard read requests
-    let (clipboard_read_tx, mut clipboard_read_rx) = mpsc::channel::<()>(4);
+    // Clipboard reads and history requests
+    let (clipboard_tx, mut clipboard_rx) = mpsc::channel::<ClipboardRequest>(16);
 
     // File download requests
     let (download_request_tx, mut download_request_rx) = mpsc::channel::<String>(4);
 
+    // Glass-to-glass latency estimation from browser frame acks
+    const LATENCY_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+    let latency = Arc::new(Mutex::new(LatencyTracker::new()));
+
+    // Connection quality badge: frame delivery counters sampled every second
+    const QUALITY_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+    let link_stats = Arc::new(LinkStats::default());
+    let link_stats_for_output = Arc::clone(&link_stats);
+
+    // Per-stage frame pipeline timing, reported alongside latency
+    let pipeline_timer = Arc::new(Mutex::new(PipelineTimer::new()));
+    let pipeline_timer_for_capture = Arc::clone(&pipeline_timer);
+
+    // Pointer lock state reports back to the browser
+    let (pointer_lock_tx, mut pointer_lock_rx) = mpsc::channel::<bool>(4);
+
+    // Audio source listing (async, pactl runs on the blocking pool) and
+    // source/mode commands (applied by the audio thread between reads)
+    // Room for a mixer slider's changes while a listing runs
+    let (audio_sources_tx, mut audio_sources_rx) = mpsc::channel::<Option<mixer::AppVolume>>(8);
+    let (audio_cmd_tx, audio_cmd_rx) = std::sync::mpsc::channel::<AudioCommand>();
+
+    // Browser camera frames for the webcam thread. Bounded so a stalled
+    // decoder drops frames instead of queueing them without limit.
+    let (webcam_tx, webcam_rx) = std::sync::mpsc::sync_channel::<WebcamCommand>(8);
+
     // Cursor shape monitor
     let mut cursor_rx = cursor::spawn_cursor_monitor(&args.display);
     if cursor_rx.is_none() {
@@ -490,18 +1195,42 @@ async fn main() -> anyhow::Result<()> {
     let home_dir = std::env::var("HOME")
         .map(std::path::PathBuf::from)
         .unwrap_or_else(|_| std::path::PathBuf::from("/tmp"));
+    let print_dir = home_dir.join(printing::PRINT_OUTPUT_SUBDIR);
     let file_transfer = Arc::new(Mutex::new(filetransfer::FileTransferManager::new(home_dir)));
     let file_transfer_for_download = Arc::clone(&file_transfer);
 
+    // Remote assistance banner, whose buttons pause the operator's input
+    let assist_control = args.assist.as_ref().map(|assist| {
+        let control = Arc::new(AtomicBool::new(assist.control));
+        assist::spawn_banner(
+            args.display.clone(),
+            assist.operator.clone(),
+            Arc::clone(&control),
+            ws_outbox_tx.clone(),
+        );
+        control
+    });
+
     // Build input callback
+    let input_limiter = Arc::new(input_limit::InputLimiter::default());
+    let audio_playout = Arc::new(PlayoutStats::default());
     let input_callback = build_input_callback(InputCallbackCtx {
         injector: Arc::clone(&injector),
         clipboard: Arc::clone(&clipboard),
+        clipboard_policy: args.clipboard,
+        primary_selection: args.primary_selection,
         file_transfer,
         resize_tx: resize_tx.clone(),
         last_input_time: Arc::clone(&last_input_time),
-        clipboard_read_tx: clipboard_read_tx.clone(),
-        download_request_tx,
+        clipboard_tx: clipboard_tx.clone(),
+        download_request_tx: download_request_tx.clone(),
+        pointer_lock_tx,
+        audio_sources_tx,
+        audio_cmd_tx,
+        audio_playout: Arc::clone(&audio_playout),
+        webcam_tx: webcam_tx.clone(),
+        latency: Arc::clone(&latency),
+        link_stats: Arc::clone(&link_stats),
         capture_wake: Arc::clone(&capture_wake_for_input),
         capture_cmd_tx: capture_cmd_tx.clone(),
         tab_backgrounded: Arc::clone(&tab_backgrounded),
@@ -509,7 +1238,11 @@ async fn main() -> anyhow::Result<()> {
         display: args.display.clone(),
         max_width: args.max_width,
         max_height: args.max_height,
+        assist_control,
+        limiter: Arc::clone(&input_limiter),
+        layout_tx: keyboard_layout::spawn(&args.display),
     });
+    let input_sink = input_pacer::InputSink::new(input_callback, args.pace_input);
 
     // Shutdown flag for capture/audio threads
     let shutdown = Arc::new(AtomicBool::new(false));
@@ -517,49 +1250,128 @@ async fn main() -> anyhow::Result<()> {
     let shutdown_for_audio = Arc::clone(&shutdown);
 
     // Capture + encode thread
-    const IDLE_TIMEOUT_MS: u64 = 300_000;
-    const IDLE_FRAMERATE: u32 = 5;
+    // MIT-SCREEN-SAVER idle time is a server round trip; sample it sparingly
+    const X_IDLE_POLL: Duration = Duration::from_secs(1);
     const BACKGROUND_FRAMERATE: u32 = 1;
     const ENCODER_RESET_COOLDOWN: Duration = Duration::from_secs(5);
+    // Presets that may go over the bitrate fall back to balanced after
+    // this many consecutive seconds above the quality ceiling.
+    const OVER_BUDGET_SECS: u32 = 3;
+    let quality_ceiling_kbps = args.quality_ceiling;
+    let max_bitrate_for_capture = args.max_bitrate;
+
+    // Injected faults (debug builds, BEAM_CHAOS_DIR)
+    let chaos = Arc::new(chaos::Chaos::default());
+    let chaos_for_capture = Arc::clone(&chaos);
 
     let display_for_capture = args.display.clone();
+    let letterbox = args.letterbox;
+    let idle_policy = args.idle;
+    let ws_tx_for_idle = ws_outbox_tx.clone();
     let kf_flag_for_capture = Arc::clone(&force_keyframe);
     let capture_wake_for_thread = Arc::clone(&capture_wake);
     let input_width_for_capture = Arc::clone(&input_width);
     let input_height_for_capture = Arc::clone(&input_height);
+    let stream_width_for_capture = Arc::clone(&stream_width);
+    let stream_height_for_capture = Arc::clone(&stream_height);
 
+    let mut thumbnailer = (args.thumbnail_secs > 0).then(|| {
+        thumbnail::Thumbnailer::spawn(
+            Duration::from_secs(args.thumbnail_secs),
+            ws_outbox_tx.clone(),
+        )
+    });
+
+    // Fired by the capture thread when the X server has gone away
+    let (display_lost_tx, display_lost_rx) = tokio::sync::oneshot::channel::<()>();
+
+    let realtime = args.realtime.clone();
+    let cpu_affinity = args.cpu_affinity.clone();
     let capture_handle = std::thread::Builder::new()
         .name("capture-encode".into())
         .spawn(move || {
-            // Elevate to real-time priority for consistent frame pacing
-            #[cfg(target_os = "linux")]
-            {
-                let param = libc::sched_param { sched_priority: 50 };
-                let ret = unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) };
-                if ret != 0 {
-                    warn!("Could not set SCHED_FIFO (need CAP_SYS_NICE): {}",
-                        std::io::Error::last_os_error());
-                } else {
-                    info!("Capture thread elevated to SCHED_FIFO priority 50");
+            // Real-time priority (SCHED_FIFO 50 by default) for consistent
+            // frame pacing
+            sched::tune_current_thread(ThreadGroup::Capture, &realtime, &cpu_affinity, true);
+
+            // The encoded output thread and the frame headers follow each
+            // new encoder
+            let hand_over_output = |encoder: &mut Encoder| {
+                if let Some(output) = encoder.take_output() {
+                    let _ = encoder_output_tx.send(output);
                 }
-            }
-
+                let (width, height) = encoder.output_size();
+                stream_width_for_capture.store(width, Ordering::Relaxed);
+                stream_height_for_capture.store(height, Ordering::Relaxed);
+                update_agent_info(&ws_tx_for_encoder_status, &agent_info_for_capture, |info| {
+                    info.encoder = encoder.name().to_string();
+                    info.width = width;
+                    info.height = height;
+                });
+            };
             let mut encoder = encoder;
-            let current_bitrate = config_bitrate;
-            let current_framerate = config_framerate;
-            let active_frame_duration_ns = 1_000_000_000u64 / config_framerate as u64;
-            let idle_frame_duration_ns = 1_000_000_000u64 / IDLE_FRAMERATE as u64;
+            hand_over_output(&mut encoder);
+            let mut display_layout = display_layout;
+            // Browser viewport a letterboxed display is scaled down to
+            let mut output_size: Option<(u32, u32)> = None;
+            let mut encoder_settings = encoder_settings;
+            let mut encoder_health = EncoderHealth::default();
+            let mut current_bitrate = video_bitrate_for_capture.load(Ordering::Relaxed);
+            let mut current_framerate = config_framerate;
+            let mut active_frame_duration_ns = 1_000_000_000u64 / config_framerate as u64;
+            let idle_framerate = idle_policy.throttle_framerate.max(1);
+            let idle_frame_duration_ns = 1_000_000_000u64 / idle_framerate as u64;
             let background_frame_duration_ns = 1_000_000_000u64 / BACKGROUND_FRAMERATE as u64;
             let mut frame_count: u64 = 0;
-            let mut encoded_count: u64 = 0;
             let start = Instant::now();
             let mut was_idle = false;
+            // throttle_after = 0: never drop the framerate
+            let throttle_after = match idle_policy.throttle_after {
+                0 => Duration::MAX,
+                secs => Duration::from_secs(secs),
+            };
+            let mut activity = ActivityMonitor::new(throttle_after, Instant::now());
+            let mut idle_ladder = IdleLadder::new(&idle_policy);
+            let mut x_input_idle: Option<Duration> = None;
+            let mut x_idle_sampled = Instant::now() - X_IDLE_POLL;
             let mut was_backgrounded = false;
             let mut first_capture_logged = false;
-            let mut first_encode_logged = false;
+            let mut ready_notified = false;
             let mut last_encoder_reset = Instant::now() - ENCODER_RESET_COOLDOWN;
             let mut consecutive_capture_errors: u64 = 0;
             let mut last_capture_heartbeat = Instant::now();
+            let mut current_quality = QualityPreset::Balanced;
+            let mut composite_cursor = false;
+            let mut quality_fallback: Option<beam_protocol::QualityFallback> = None;
+            let mut bandwidth_cap = max_bitrate_for_capture;
+            let mut rate_window_bytes_start: u64 = 0;
+            let mut rate_window_start = Instant::now();
+            let mut over_budget_secs: u32 = 0;
+            let mut second_screen: Option<second_screen::SecondScreen> = None;
+            // Stream the layout's second monitor right of the first, or
+            // none, and point input on it at the new one
+            let lay_out_second_screen =
+                |second_screen: &mut Option<second_screen::SecondScreen>,
+                 size: Option<(u32, u32)>,
+                 first: (u32, u32),
+                 layout: &mut display::DisplayLayout,
+                 settings: &second_screen::StreamSettings<'_>| {
+                    // The old stream's encoder goes before the new one's
+                    *second_screen = None;
+                    *second_screen = second_screen::lay_out(
+                        &display_for_capture,
+                        first,
+                        size,
+                        layout,
+                        settings,
+                        &ws_tx_for_encoder_status,
+                    );
+                    *second_screen_area_for_capture
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner()) = second_screen
+                        .as_ref()
+                        .map(second_screen::SecondScreen::area);
+                };
 
             loop {
                 if shutdown_for_capture.load(Ordering::Relaxed) {
@@ -568,21 +1380,73 @@ async fn main() -> anyhow::Result<()> {
                 }
 
                 // Process commands from async tasks
-                enum EncoderRecreate { None, Reset, Resize }
+                enum EncoderRecreate {
+                    None,
+                    Reset,
+                    Resize,
+                }
                 let mut recreate = EncoderRecreate::None;
+                let mut reinit_capture = false;
+                if let Some(fallback) = quality_fallback.take() {
+                    current_quality = fallback.effective;
+                    recreate = EncoderRecreate::Reset;
+                    send_quality_fallback(&ws_tx_for_encoder_status, fallback);
+                }
                 while let Ok(cmd) = capture_cmd_rx.try_recv() {
                     match cmd {
                         CaptureCommand::Resize { width, height } => {
-                            if width == screen_capture.width() && height == screen_capture.height() {
-                                debug!(width, height, "Resize skipped (same dimensions)");
+                            // Every request is answered with the display's
+                            // size, so the browser knows when it's done
+                            let (current_width, current_height) =
+                                (screen_capture.width(), screen_capture.height());
+                            if letterbox {
+                                let fitted = display::fit_within(
+                                    current_width,
+                                    current_height,
+                                    width,
+                                    height,
+                                );
+                                let scaled =
+                                    (fitted != (current_width, current_height)).then_some(fitted);
+                                send_display_state(
+                                    &ws_tx_for_encoder_status,
+                                    current_width,
+                                    current_height,
+                                    &display_layout,
+                                );
+                                if scaled == output_size {
+                                    continue;
+                                }
+                                info!(?scaled, "Letterboxed viewport changed, rescaling video");
+                                output_size = scaled;
+                                recreate = EncoderRecreate::Reset;
+                                break;
+                            }
+                            if (width, height) == (current_width, current_height) {
+                                debug!(width, height, "Resize skipped");
+                                send_display_state(
+                                    &ws_tx_for_encoder_status,
+                                    current_width,
+                                    current_height,
+                                    &display_layout,
+                                );
                                 continue;
                             }
                             info!(width, height, "Processing resize request");
 
-                            if let Err(e) = display::set_display_resolution(
-                                &display_for_capture, width, height,
+                            if let Err(e) = display::set_display_layout(
+                                &display_for_capture,
+                                width,
+                                height,
+                                &display_layout,
                             ) {
-                                warn!("xrandr resize failed: {e:#}");
+                                warn!("Resize failed: {e:#}");
+                                send_display_state(
+                                    &ws_tx_for_encoder_status,
+                                    current_width,
+                                    current_height,
+                                    &display_layout,
+                                );
                                 continue;
                             }
 
@@ -593,7 +1457,12 @@ async fn main() -> anyhow::Result<()> {
                                 }
                             }
 
-                            let new_capture = match ScreenCapture::new(&display_for_capture) {
+                            let new_capture = match first_screen_capture(
+                                &display_for_capture,
+                                width,
+                                height,
+                                &display_layout,
+                            ) {
                                 Ok(cap) => cap,
                                 Err(e) => {
                                     error!("Failed to recreate capture after resize: {e:#}");
@@ -601,14 +1470,89 @@ async fn main() -> anyhow::Result<()> {
                                 }
                             };
                             screen_capture = new_capture;
+                            // The second monitor moved with the first's edge
+                            if second_screen.is_some() {
+                                lay_out_second_screen(
+                                    &mut second_screen,
+                                    display_layout.second_screen,
+                                    (width, height),
+                                    &mut display_layout,
+                                    &second_screen::StreamSettings {
+                                        framerate: current_framerate,
+                                        bitrate: current_bitrate,
+                                        encoder: &encoder_settings,
+                                        quality: current_quality,
+                                    },
+                                );
+                                send_second_screen_state(
+                                    &ws_tx_for_encoder_status,
+                                    second_screen.as_ref(),
+                                );
+                            }
+                            send_display_state(
+                                &ws_tx_for_encoder_status,
+                                width,
+                                height,
+                                &display_layout,
+                            );
                             recreate = EncoderRecreate::Resize;
                             break;
                         }
-                        CaptureCommand::ResetEncoder => {
+                        CaptureCommand::SetDisplayLayout {
+                            rotation,
+                            refresh,
+                            modes,
+                        } => {
+                            if let Some(modes) = modes {
+                                if let Err(e) =
+                                    display::add_display_modes(&display_for_capture, &modes)
+                                {
+                                    warn!("Failed to add display modes: {e:#}");
+                                }
+                                display_layout.modes = modes;
+                            }
+                            // The screen keeps its size: rotating swaps the
+                            // mode's sides instead
+                            let (width, height) = (screen_capture.width(), screen_capture.height());
+                            if rotation.is_some() || refresh.is_some() {
+                                let layout = display::DisplayLayout {
+                                    rotation: rotation.unwrap_or(display_layout.rotation),
+                                    refresh: match refresh {
+                                        Some(0) => None,
+                                        Some(hz) => Some(hz),
+                                        None => display_layout.refresh,
+                                    },
+                                    modes: display_layout.modes.clone(),
+                                    second_screen: display_layout.second_screen,
+                                };
+                                match display::set_display_layout(
+                                    &display_for_capture,
+                                    width,
+                                    height,
+                                    &layout,
+                                ) {
+                                    Ok(()) => display_layout = layout,
+                                    Err(e) => warn!("Display settings change failed: {e:#}"),
+                                }
+                            }
+                            send_display_state(
+                                &ws_tx_for_encoder_status,
+                                width,
+                                height,
+                                &display_layout,
+                            );
+                        }
+                        CaptureCommand::Reconnected
+                            if encoder.encoder_type().honors_force_key_unit() =>
+                        {
+                            // The force-keyframe flag set alongside is enough
+                        }
+                        CaptureCommand::ResetEncoder | CaptureCommand::Reconnected => {
                             let elapsed = last_encoder_reset.elapsed();
                             if elapsed < ENCODER_RESET_COOLDOWN {
                                 debug!(
-                                    cooldown_remaining_ms = (ENCODER_RESET_COOLDOWN - elapsed).as_millis() as u64,
+                                    cooldown_remaining_ms =
+                                        (ENCODER_RESET_COOLDOWN - elapsed).as_millis() as u64,
                                     "ResetEncoder throttled, sending force_keyframe instead"
                                 );
                                 encoder.force_keyframe();
@@ -617,6 +1561,106 @@ async fn main() -> anyhow::Result<()> {
                                 break;
                             }
                         }
+                        CaptureCommand::SetQuality(quality) => {
+                            if quality == current_quality {
+                                continue;
+                            }
+                            info!(?quality, "Switching encoder quality mode");
+                            current_quality = quality;
+                            over_budget_secs = 0;
+                            recreate = EncoderRecreate::Reset;
+                            break;
+                        }
+                        CaptureCommand::SetBitrateCap(cap) => {
+                            bandwidth_cap = cap;
+                            let bitrate = capped_bitrate(config_bitrate, cap);
+                            if bitrate == current_bitrate {
+                                continue;
+                            }
+                            info!(
+                                bitrate,
+                                previous = current_bitrate,
+                                "Bandwidth cap changed, recreating encoder"
+                            );
+                            current_bitrate = bitrate;
+                            video_bitrate_for_capture.store(bitrate, Ordering::Relaxed);
+                            recreate = EncoderRecreate::Reset;
+                            break;
+                        }
+                        CaptureCommand::SetCursorComposite(enabled) => {
+                            composite_cursor = enabled;
+                        }
+                        CaptureCommand::ReinitCapture => {
+                            reinit_capture = true;
+                        }
+                        CaptureCommand::SetSecondScreen(size) => {
+                            if size.is_some()
+                                && size == display_layout.second_screen
+                                && let Some(screen) = &second_screen
+                            {
+                                // Attached already: a browser (re)opening
+                                // its window needs a keyframe
+                                screen.force_keyframe();
+                            } else if size.is_some() || second_screen.is_some() {
+                                info!(?size, "Changing the second screen");
+                                lay_out_second_screen(
+                                    &mut second_screen,
+                                    size,
+                                    (screen_capture.width(), screen_capture.height()),
+                                    &mut display_layout,
+                                    &second_screen::StreamSettings {
+                                        framerate: current_framerate,
+                                        bitrate: current_bitrate,
+                                        encoder: &encoder_settings,
+                                        quality: current_quality,
+                                    },
+                                );
+                            }
+                            send_second_screen_state(
+                                &ws_tx_for_encoder_status,
+                                second_screen.as_ref(),
+                            );
+                        }
+                    }
+                }
+                if second_screen
+                    .as_ref()
+                    .is_some_and(second_screen::SecondScreen::has_stopped)
+                {
+                    warn!("Second screen stream ended, detaching it");
+                    lay_out_second_screen(
+                        &mut second_screen,
+                        None,
+                        (screen_capture.width(), screen_capture.height()),
+                        &mut display_layout,
+                        &second_screen::StreamSettings {
+                            framerate: current_framerate,
+                            bitrate: current_bitrate,
+                            encoder: &encoder_settings,
+                            quality: current_quality,
+                        },
+                    );
+                    send_second_screen_state(&ws_tx_for_encoder_status, None);
+                }
+                if reinit_capture {
+                    match first_screen_capture(
+                        &display_for_capture,
+                        screen_capture.width(),
+                        screen_capture.height(),
+                        &display_layout,
+                    ) {
+                        Ok(cap) => {
+                            let resized = cap.width() != screen_capture.width()
+                                || cap.height() != screen_capture.height();
+                            screen_capture = cap;
+                            if resized {
+                                recreate = EncoderRecreate::Resize;
+                            } else {
+                                encoder.force_keyframe();
+                            }
+                            info!("Screen capture re-initialized");
+                        }
+                        Err(e) => warn!("Capture re-init failed, keeping the old one: {e:#}"),
                     }
                 }
 
@@ -629,9 +1673,11 @@ async fn main() -> anyhow::Result<()> {
                         encoder = match Encoder::with_encoder_preference(
                             screen_capture.width(),
                             screen_capture.height(),
+                            output_size,
                             current_framerate,
                             current_bitrate,
-                            encoder_pref.as_deref(),
+                            &encoder_settings,
+                            current_quality,
                         ) {
                             Ok(enc) => enc,
                             Err(e) => {
@@ -639,19 +1685,31 @@ async fn main() -> anyhow::Result<()> {
                                 break;
                             }
                         };
-                        first_encode_logged = false;
+                        hand_over_output(&mut encoder);
                         last_encoder_reset = Instant::now();
                         info!("Encoder pipeline recreated (next frame will be IDR)");
                     }
                     EncoderRecreate::Resize => {
                         let new_w = screen_capture.width();
                         let new_h = screen_capture.height();
-                        info!(width = new_w, height = new_h, "Dropping old encoder for resize");
+                        info!(
+                            width = new_w,
+                            height = new_h,
+                            "Dropping old encoder for resize"
+                        );
                         drop(encoder);
                         info!("Old encoder dropped, creating new pipeline for resize");
+                        // A viewport fitted to the old size would be wrong
+                        // for the new one; the browser's next resize fits it
+                        output_size = None;
                         encoder = match Encoder::with_encoder_preference(
-                            new_w, new_h, DEFAULT_FRAMERATE, current_bitrate,
-                            encoder_pref.as_deref(),
+                            new_w,
+                            new_h,
+                            output_size,
+                            DEFAULT_FRAMERATE,
+                            current_bitrate,
+                            &encoder_settings,
+                            current_quality,
                         ) {
                             Ok(enc) => enc,
                             Err(e) => {
@@ -661,14 +1719,15 @@ async fn main() -> anyhow::Result<()> {
                         };
 
                         encoder.force_keyframe();
+                        hand_over_output(&mut encoder);
                         first_capture_logged = false;
-                        first_encode_logged = false;
 
                         input_width_for_capture.store(new_w, Ordering::Relaxed);
                         input_height_for_capture.store(new_h, Ordering::Relaxed);
 
                         info!(
-                            width = new_w, height = new_h,
+                            width = new_w,
+                            height = new_h,
                             "Resize complete, capture and encoder recreated"
                         );
                     }
@@ -677,6 +1736,9 @@ async fn main() -> anyhow::Result<()> {
                 // Check force-keyframe flag
                 if kf_flag_for_capture.swap(false, Ordering::Relaxed) {
                     encoder.force_keyframe();
+                    if let Some(screen) = &second_screen {
+                        screen.force_keyframe();
+                    }
                     if tab_backgrounded_for_capture.swap(false, Ordering::Relaxed) {
                         warn!("Keyframe forced while backgrounded — clearing flag");
                     }
@@ -692,7 +1754,32 @@ async fn main() -> anyhow::Result<()> {
                     .unwrap_or_default()
                     .as_millis() as u64;
                 let last_input_ms = last_input_for_capture.load(Ordering::Relaxed);
-                let is_idle = last_input_ms > 0 && (now_ms - last_input_ms) > IDLE_TIMEOUT_MS;
+                let since_browser_input = (last_input_ms > 0)
+                    .then(|| Duration::from_millis(now_ms.saturating_sub(last_input_ms)));
+                if x_idle_sampled.elapsed() >= X_IDLE_POLL {
+                    x_input_idle = screen_capture.input_idle_time();
+                    x_idle_sampled = Instant::now();
+                }
+                let since_x_input = x_input_idle.map(|idle| idle + x_idle_sampled.elapsed());
+                let since_input = match (since_browser_input, since_x_input) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                let is_idle = activity.is_idle(since_input, frame_start);
+                if let Some(action) =
+                    idle_ladder.update(activity.inactive_for(since_input, frame_start), since_input)
+                {
+                    info!(?action, "Idle policy stage reached");
+                    if action == IdleAction::Lock {
+                        let x_display = display_for_capture.clone();
+                        std::thread::spawn(move || {
+                            if let Err(e) = display::lock_screen(&x_display) {
+                                warn!("Idle lock failed: {e:#}");
+                            }
+                        });
+                    }
+                    send_idle_notice(&ws_tx_for_idle, action);
+                }
 
                 let frame_duration_ns = if is_backgrounded {
                     background_frame_duration_ns
@@ -701,38 +1788,88 @@ async fn main() -> anyhow::Result<()> {
                 } else {
                     active_frame_duration_ns
                 };
+                capture_progress_for_capture.target_fps.store(
+                    (1_000_000_000 / frame_duration_ns) as u32,
+                    Ordering::Relaxed,
+                );
 
                 if is_backgrounded != was_backgrounded {
                     if is_backgrounded {
                         debug!("Tab backgrounded, reducing to {BACKGROUND_FRAMERATE}fps");
                     } else {
-                        debug!(fps = current_framerate, "Tab foregrounded, restoring framerate");
+                        debug!(
+                            fps = current_framerate,
+                            "Tab foregrounded, restoring framerate"
+                        );
                     }
                     was_backgrounded = is_backgrounded;
                 }
 
                 if is_idle != was_idle && !is_backgrounded {
                     if is_idle {
-                        debug!("Entering idle mode ({IDLE_FRAMERATE}fps)");
+                        debug!("Entering idle mode ({idle_framerate}fps)");
                     } else {
                         debug!(fps = current_framerate, "Resuming active mode");
                     }
                     was_idle = is_idle;
                 }
 
-                // Auto-recover from GStreamer pipeline errors
-                if encoder.has_error() {
-                    warn!("GStreamer pipeline error detected, dropping encoder");
+                // Auto-recover from GStreamer pipeline errors. Repeated errors
+                // move down the nvenc → vaapi → x264 chain; x264 is the floor.
+                if encoder.has_error() || chaos_for_capture.take_encoder_error() {
+                    let failed_type = encoder.encoder_type();
+                    let fall_back = encoder_health.record_error(Instant::now())
+                        && failed_type != EncoderType::Software;
+                    if fall_back {
+                        warn!(
+                            encoder = encoder.name(),
+                            "Repeated GStreamer pipeline errors, falling back to next encoder"
+                        );
+                        encoder_settings.failed.push(failed_type);
+                        encoder_health.reset();
+                    } else {
+                        warn!("GStreamer pipeline error detected, dropping encoder");
+                    }
                     drop(encoder);
                     match Encoder::with_encoder_preference(
-                        screen_capture.width(), screen_capture.height(),
-                        current_framerate, current_bitrate,
-                        encoder_pref.as_deref(),
+                        screen_capture.width(),
+                        screen_capture.height(),
+                        output_size,
+                        current_framerate,
+                        current_bitrate,
+                        &encoder_settings,
+                        current_quality,
                     ) {
                         Ok(enc) => {
                             encoder = enc;
-                            first_encode_logged = false;
-                            info!("Encoder auto-recovered from pipeline error");
+                            hand_over_output(&mut encoder);
+                            info!(
+                                encoder = encoder.name(),
+                                "Encoder auto-recovered from pipeline error"
+                            );
+                            if fall_back {
+                                // Same cap as startup: x264 can't sustain >60fps
+                                if encoder.encoder_type() == EncoderType::Software
+                                    && current_framerate > 60
+                                {
+                                    current_framerate = 60;
+                                    active_frame_duration_ns =
+                                        1_000_000_000u64 / current_framerate as u64;
+                                }
+                                send_encoder_status(
+                                    &ws_tx_for_encoder_status,
+                                    encoder.name(),
+                                    true,
+                                );
+                                update_agent_info(
+                                    &ws_tx_for_encoder_status,
+                                    &agent_info_for_capture,
+                                    |info| {
+                                        info.fallback = true;
+                                        info.framerate = current_framerate;
+                                    },
+                                );
+                            }
                         }
                         Err(e) => {
                             error!("Failed to recreate encoder after pipeline error: {e:#}");
@@ -741,89 +1878,148 @@ async fn main() -> anyhow::Result<()> {
                     }
                 }
 
-                match screen_capture.capture_frame() {
-                    Ok(frame) => {
-                        if consecutive_capture_errors > 0 {
-                            info!(
-                                recovered_after = consecutive_capture_errors,
-                                "Capture recovered after consecutive errors"
-                            );
-                            consecutive_capture_errors = 0;
-                        }
-                        if !first_capture_logged {
-                            info!(size = frame.len(), "First frame captured from X display");
-                            first_capture_logged = true;
-                        }
-                        if let Err(e) = encoder.encode_frame(frame, pts) {
-                            error!("Encode error: {e:#}");
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        consecutive_capture_errors += 1;
-                        if consecutive_capture_errors <= 3 || consecutive_capture_errors.is_multiple_of(100) {
-                            warn!(
-                                consecutive_errors = consecutive_capture_errors,
-                                "Capture frame failed: {e:#}"
-                            );
-                        }
-                        if consecutive_capture_errors >= 300 {
-                            error!(
-                                consecutive_errors = consecutive_capture_errors,
-                                "Capture failing persistently, breaking capture loop"
-                            );
-                            break;
-                        }
-                        std::thread::sleep(Duration::from_millis(1));
-                        continue;
-                    }
+                if let Some(stall) = chaos_for_capture.take_capture_stall() {
+                    warn!(secs = stall.as_secs_f64(), "Chaos: stalling capture thread");
+                    std::thread::sleep(stall);
                 }
 
-                // Drain encoded frames
-                let drain_deadline = Instant::now() + Duration::from_millis(2);
-                let mut drained_any = false;
-                loop {
-                    match encoder.pull_encoded() {
-                        Ok(Some(data)) => {
-                            drained_any = true;
-                            encoded_count += 1;
-                            if !first_encode_logged {
-                                info!(size = data.len(), "First H.264 frame from encoder");
-                                first_encode_logged = true;
+                let capture_started = Instant::now();
+                let (capture_time, encode_time) =
+                    match screen_capture.capture_frame(composite_cursor) {
+                        Ok(frame) => {
+                            let capture_time = capture_started.elapsed();
+                            if consecutive_capture_errors > 0 {
+                                info!(
+                                    recovered_after = consecutive_capture_errors,
+                                    "Capture recovered after consecutive errors"
+                                );
+                                consecutive_capture_errors = 0;
                             }
-                            match encoded_tx.try_send(data) {
-                                Ok(()) => {}
-                                Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
-                                    debug!("Dropping encoded frame (channel full, prioritizing latency)");
-                                }
-                                Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
-                                    info!("Encoded frame channel closed, stopping capture");
-                                    return;
-                                }
+                            if !first_capture_logged {
+                                info!(size = frame.len(), "First frame captured from X display");
+                                first_capture_logged = true;
                             }
-                        }
-                        Ok(None) => {
-                            if drained_any || Instant::now() >= drain_deadline {
+                            if !ready_notified {
+                                beam_protocol::sd_notify::ready("Capturing");
+                                ready_notified = true;
+                            }
+                            let dirty = screen_capture.take_dirty_region();
+                            if let Some(ref rect) = dirty {
+                                activity.record_damage(
+                                    rect,
+                                    screen_capture.width(),
+                                    screen_capture.height(),
+                                    frame_start,
+                                );
+                            } else if !screen_capture.has_damage() {
+                                activity.observe_frame(frame.as_ref(), frame_start);
+                            }
+                            if let Some(thumbnailer) = thumbnailer.as_mut()
+                                && thumbnailer.due(frame_start)
+                            {
+                                thumbnailer.offer(
+                                    frame.as_ref(),
+                                    screen_capture.width(),
+                                    screen_capture.height(),
+                                    frame_start,
+                                );
+                            }
+                            // Logged first: a fast encoder can emit before
+                            // encode_frame returns
+                            let encode_started = Instant::now();
+                            submitted_for_capture.record(pts, frame_start, encode_started);
+                            if let Err(e) = encoder.encode_frame(frame, pts, dirty) {
+                                error!("Encode error: {e:#}");
                                 break;
                             }
-                            std::hint::spin_loop();
+                            (capture_time, encode_started.elapsed())
+                        }
+                        Err(e) if capture::is_connection_lost(&e) => {
+                            error!("Lost the X display: {e:#}");
+                            let msg = AgentMessage::DisplayLost.to_json();
+                            let _ = ws_tx_for_encoder_status.try_send(Message::Text(msg.into()));
+                            let _ = display_lost_tx.send(());
+                            break;
                         }
                         Err(e) => {
-                            error!("Pull encoded error: {e:#}");
-                            return;
+                            consecutive_capture_errors += 1;
+                            if consecutive_capture_errors <= 3
+                                || consecutive_capture_errors.is_multiple_of(100)
+                            {
+                                warn!(
+                                    consecutive_errors = consecutive_capture_errors,
+                                    "Capture frame failed: {e:#}"
+                                );
+                            }
+                            if consecutive_capture_errors >= 300 {
+                                error!(
+                                    consecutive_errors = consecutive_capture_errors,
+                                    "Capture failing persistently, breaking capture loop"
+                                );
+                                break;
+                            }
+                            std::thread::sleep(Duration::from_millis(1));
+                            continue;
                         }
-                    }
-                }
+                    };
+
+                // Encoded output is picked up by its own thread, so the
+                // next capture doesn't wait for this frame's encode
+                pipeline_timer_for_capture
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record_frame(capture_time, encode_time);
 
                 frame_count += 1;
+                capture_progress_for_capture
+                    .frames
+                    .fetch_add(1, Ordering::Relaxed);
+
+                // Quality ceiling guard: sharp and lossless output isn't
+                // bounded by the configured bitrate, so measure it and back
+                // off if it stays over `video.quality_ceiling`.
+                let rate_window = rate_window_start.elapsed();
+                if rate_window >= Duration::from_secs(1) {
+                    // bits per millisecond == kilobits per second
+                    let encoded_bytes = output_counters_for_capture.bytes.load(Ordering::Relaxed);
+                    let rate_window_bytes = encoded_bytes - rate_window_bytes_start;
+                    let kbps = rate_window_bytes * 8 / rate_window.as_millis().max(1) as u64;
+                    let ceiling = encoder::quality_ceiling(quality_ceiling_kbps, bandwidth_cap);
+                    if let Some(ceiling) = ceiling
+                        && encoder::may_exceed_bitrate(current_quality)
+                        && kbps > ceiling as u64
+                    {
+                        over_budget_secs += 1;
+                        if over_budget_secs >= OVER_BUDGET_SECS {
+                            warn!(
+                                kbps,
+                                ceiling_kbps = ceiling,
+                                preset = current_quality.name(),
+                                "Quality preset over quality ceiling, falling back to balanced"
+                            );
+                            over_budget_secs = 0;
+                            quality_fallback = Some(beam_protocol::QualityFallback {
+                                requested: current_quality,
+                                effective: QualityPreset::Balanced,
+                                kbps: kbps.min(u32::MAX as u64) as u32,
+                                ceiling_kbps: ceiling,
+                            });
+                        }
+                    } else {
+                        over_budget_secs = 0;
+                    }
+                    rate_window_bytes_start = encoded_bytes;
+                    rate_window_start = Instant::now();
+                }
 
                 if last_capture_heartbeat.elapsed() >= Duration::from_secs(5) {
                     let elapsed = start.elapsed().as_secs_f64();
                     info!(
                         captured = frame_count,
-                        encoded = encoded_count,
+                        encoded = output_counters_for_capture.frames.load(Ordering::Relaxed),
                         fps = format!("{:.1}", frame_count as f64 / elapsed),
-                        is_idle, is_backgrounded,
+                        is_idle,
+                        is_backgrounded,
                         "Capture heartbeat"
                     );
                     last_capture_heartbeat = Instant::now();
@@ -832,13 +2028,19 @@ async fn main() -> anyhow::Result<()> {
                 // Frame pacing
                 let target = Duration::from_nanos(frame_duration_ns);
                 let elapsed = frame_start.elapsed();
-                if elapsed < target {
+                if elapsed >= target {
+                    pipeline_timer_for_capture
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .record_deadline_miss();
+                } else {
                     let remaining = target - elapsed;
                     if is_idle || is_backgrounded {
                         let (lock, cvar) = &*capture_wake_for_thread;
                         let mut woken = lock.lock().unwrap_or_else(|e| e.into_inner());
                         *woken = false;
-                        let result = cvar.wait_timeout(woken, remaining)
+                        let result = cvar
+                            .wait_timeout(woken, remaining)
                             .unwrap_or_else(|e| e.into_inner());
                         if *result.0 {
                             debug!("Capture thread woken by input/visibility change");
@@ -856,21 +2058,127 @@ async fn main() -> anyhow::Result<()> {
         })
         .context("Failed to spawn capture thread")?;
 
+    // Watchdog: reports memory, CPU and frame rates, and recovers or ends
+    // a stalled pipeline
+    let (wedged_tx, mut wedged_rx) = tokio::sync::oneshot::channel();
+    let watchdog_ctx = watchdog::WatchdogCtx {
+        progress: capture_progress,
+        counters: Arc::clone(&output_counters),
+        capture_cmd_tx: capture_cmd_tx.clone(),
+        ws_tx: ws_outbox_tx.clone(),
+        wedged: wedged_tx,
+        shutdown: Arc::clone(&shutdown),
+        max_rss_mb: args.max_rss_mb,
+        input_limiter,
+        audio_drift_us: Arc::clone(&audio_drift_us),
+        audio_playout,
+        link_stats: Arc::clone(&link_stats),
+        tab_backgrounded: Arc::clone(&tab_backgrounded),
+        force_keyframe: Arc::clone(&force_keyframe),
+        freeze_recovery: args.freeze_recovery,
+    };
+    std::thread::Builder::new()
+        .name("watchdog".into())
+        .spawn(move || watchdog::run_watchdog(watchdog_ctx))
+        .context("Failed to spawn watchdog thread")?;
+
+    let output_ctx = encoded_output::OutputCtx {
+        outputs: encoder_output_rx,
+        frames: encoded_tx,
+        submitted,
+        counters: output_counters,
+        timer: Arc::clone(&pipeline_timer),
+        link_stats: link_stats_for_output,
+    };
+    let output_handle = std::thread::Builder::new()
+        .name("encoded-output".into())
+        .spawn(move || encoded_output::run_encoded_output(output_ctx))
+        .context("Failed to spawn encoded output thread")?;
+
     // Audio capture thread
-    let audio_handle = match AudioCapture::new(48000, 2, pulse_server.as_deref()) {
+    const AUDIO_PAUSE_POLL: Duration = Duration::from_millis(250);
+    let audio_handle = match AudioCapture::new(
+        48000,
+        args.audio_channels,
+        args.audio_bitrate,
+        args.audio_tuning,
+        pulse_server.as_deref(),
+        pipewire_socket.as_deref(),
+    ) {
         Ok(mut audio_capture) => {
+            let mut router = AudioRouter::new(pulse_server.as_deref());
+            let ws_tx_for_audio_state = ws_outbox_tx.clone();
+            let realtime = args.realtime.clone();
+            let cpu_affinity = args.cpu_affinity.clone();
+            let mut timeline = AudioTimeline::new(media_clock, audio_drift_us);
             let handle = std::thread::Builder::new()
                 .name("audio-capture".into())
                 .spawn(move || {
                     info!("Audio capture thread started");
+                    sched::tune_current_thread(ThreadGroup::Audio, &realtime, &cpu_affinity, true);
+                    let mut paused = false;
                     loop {
                         if shutdown_for_audio.load(Ordering::Relaxed) {
                             info!("Audio thread shutting down");
                             return;
                         }
-                        match audio_capture.capture_and_encode() {
+                        // Paused: block on commands instead of PulseAudio, waking
+                        // periodically to notice shutdown
+                        let waited = if paused {
+                            audio_cmd_rx.recv_timeout(AUDIO_PAUSE_POLL).ok()
+                        } else {
+                            None
+                        };
+                        for cmd in waited.into_iter().chain(audio_cmd_rx.try_iter()) {
+                            match cmd {
+                                AudioCommand::Select(selection) => {
+                                    let result = router
+                                        .select(&selection)
+                                        .and_then(|source| audio_capture.set_source(&source));
+                                    if let Err(e) = result {
+                                        warn!(?selection, "Audio source change failed: {e:#}");
+                                    }
+                                }
+                                AudioCommand::SetMode(mode) => {
+                                    if let Err(e) = audio_capture.set_mode(mode) {
+                                        warn!(?mode, "Audio mode change failed: {e:#}");
+                                    }
+                                }
+                                AudioCommand::SetTuning { frame_ms, fec, dtx } => {
+                                    let tuning = audio_capture.tuning().with(frame_ms, fec, dtx);
+                                    if let Err(e) = audio_capture.set_tuning(tuning) {
+                                        warn!(?tuning, "Audio tuning change failed: {e:#}");
+                                    }
+                                }
+                                AudioCommand::SetEnabled(enabled) => {
+                                    if enabled && paused {
+                                        if let Err(e) = audio_capture.discard_buffered() {
+                                            warn!("{e:#}");
+                                        }
+                                        timeline.restart();
+                                    }
+                                    paused = !enabled;
+                                    info!(enabled, "Audio capture toggled");
+                                    let ack = InputEvent::AudioEnabled { enabled };
+                                    if let Ok(msg) = serde_json::to_string(&ack) {
+                                        let _ = ws_tx_for_audio_state
+                                            .try_send(Message::Text(msg.into()));
+                                    }
+                                }
+                            }
+                        }
+                        if paused {
+                            continue;
+                        }
+                        let captured = audio_capture.capture_and_encode();
+                        // Silence counts towards the timeline like sound
+                        let stamp = timeline.stamp(Instant::now(), audio_capture.frame_duration());
+                        match captured {
+                            // DTX silence frames (1-2 bytes) carry nothing the
+                            // browser needs; its decoder conceals the gap
+                            Ok(opus_data) if opus_data.len() <= 2 => {}
                             Ok(opus_data) => {
-                                if audio_tx.blocking_send(opus_data).is_err() {
+                                if audio_tx.blocking_send((stamp, opus_data)).is_err() {
                                     info!("Audio channel closed, stopping audio capture");
                                     return;
                                 }
@@ -891,6 +2199,14 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    // Webcam passthrough thread: idle until the browser starts a camera,
+    // exits once the senders are dropped at shutdown
+    let ws_tx_for_webcam = ws_outbox_tx.clone();
+    std::thread::Builder::new()
+        .name("webcam".into())
+        .spawn(move || webcam::run_webcam_loop(webcam_rx, ws_tx_for_webcam))
+        .context("Failed to spawn webcam thread")?;
+
     // Set up SIGTERM handler
     let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
 
@@ -903,6 +2219,12 @@ async fn main() -> anyhow::Result<()> {
 
     // WS sender clones for tasks that need to send messages
     let ws_tx_for_cursor = ws_outbox_tx.clone();
+    let ws_tx_for_pointer_lock = ws_outbox_tx.clone();
+    let ws_tx_for_latency = ws_outbox_tx.clone();
+    let ws_tx_for_quality = ws_outbox_tx.clone();
+    let ws_tx_for_pipeline = ws_outbox_tx.clone();
+    let ws_tx_for_audio_sources = ws_outbox_tx.clone();
+    let pulse_server_for_listing = pulse_server.clone();
 
     let signaling_ctx = SignalingCtx {
         server_url: &server_url,
@@ -910,20 +2232,43 @@ async fn main() -> anyhow::Result<()> {
         agent_token: args.agent_token.as_deref(),
         tls_cert_path: args.tls_cert_path.as_deref(),
         force_keyframe: kf_flag_for_signal,
-        input_callback: Arc::clone(&input_callback),
+        input: input_sink,
         capture_cmd_tx: &cmd_tx_for_signal,
+        webcam_tx: &webcam_tx,
         tab_backgrounded: Arc::clone(&tab_backgrounded),
+        x_display: &args.display,
+        assist: args.assist.is_some(),
+        chaos: &chaos,
+        agent_info: &agent_info,
+        upgrade: &upgrade,
     };
 
+    // Every pipeline thread is running with its scheduling applied; nothing
+    // past this point needs capabilities
+    if args.sandbox {
+        sandbox::enter().context("Failed to sandbox the agent")?;
+        info!("Agent sandboxed: capabilities dropped, seccomp filter installed");
+    }
+
+    let mut wedged = None;
+    let mut display_lost = false;
     tokio::select! {
         // Write encoded video frames as WebSocket binary
         _ = video::run_video_send_loop(
             &mut encoded_rx,
-            &ws_outbox_tx,
-            &force_keyframe,
-            &cmd_tx_for_video,
-            &input_width,
-            &input_height,
+            video::VideoSendCtx {
+                ws_tx: &ws_outbox_tx,
+                force_keyframe: &force_keyframe,
+                capture_cmd_tx: &cmd_tx_for_video,
+                stream_width: &stream_width,
+                stream_height: &stream_height,
+                clock: media_clock,
+            },
+            video::FrameStats {
+                latency: &latency,
+                pipeline: &pipeline_timer,
+                link: &link_stats,
+            },
         ) => {}
 
         // Write encoded audio frames as WebSocket binary
@@ -940,16 +2285,35 @@ async fn main() -> anyhow::Result<()> {
 
         // Forward resize requests to capture thread
         _ = async {
-            while let Some((w, h)) = resize_rx.recv().await {
-                info!(w, h, "Resize requested, forwarding to capture thread");
+            let mut current_scale = display::normalize_scale(args.scale);
+            while let Some(mut request) = resize_rx.recv().await {
+                while let Ok(Some(next)) =
+                    tokio::time::timeout(RESIZE_SETTLE, resize_rx.recv()).await
+                {
+                    request = next;
+                }
+                let (w, h, scale) = request;
+                info!(w, h, scale, "Resize requested, forwarding to capture thread");
                 let _ = cmd_tx_for_resize.send(CaptureCommand::Resize { width: w, height: h });
+                // A letterboxed display keeps its DPI along with its size
+                if !args.letterbox && scale != current_scale {
+                    current_scale = scale;
+                    let x_display = args.display.clone();
+                    tokio::task::spawn_blocking(move || {
+                        if let Err(e) = display::set_display_scale(&x_display, scale) {
+                            warn!("Failed to apply display scale {scale}: {e:#}");
+                        }
+                    });
+                }
             }
         } => {}
 
         // Clipboard sync: after Ctrl+C/X, read X11 clipboard and send to browser
         _ = clipboard_sync::run_clipboard_sync(
-            &mut clipboard_read_rx,
+            &mut clipboard_rx,
             &clipboard_for_sync,
+            args.clipboard,
+            args.clipboard_history,
             &ws_outbox_tx,
         ) => {}
 
@@ -960,20 +2324,184 @@ async fn main() -> anyhow::Result<()> {
             &ws_outbox_tx,
         ) => {}
 
-        // Cursor shape passthrough via WebSocket text
+        // Print redirection: finished cups-pdf jobs go out as downloads.
+        // Not the assisted user's own print jobs.
+        _ = async {
+            if args.assist.is_none() {
+                printing::run_print_watch_loop(print_dir, download_request_tx).await;
+            }
+            std::future::pending::<()>().await;
+        } => {}
+
+        // Session link handler → browser tab (socket lives in the runtime
+        // dir that start_desktop creates for this display)
+        _ = async {
+            if args.forward_urls {
+                let socket = url_open::socket_path(std::path::Path::new(&format!(
+                    "/tmp/beam-run-{display_num}"
+                )));
+                if let Err(e) = url_open::run_url_listener(&socket, &ws_outbox_tx).await {
+                    warn!("URL forwarding unavailable: {e:#}");
+                }
+            }
+            std::future::pending::<()>().await;
+        } => {}
+
+        // Fault injection control socket (debug builds only)
+        _ = async {
+            if let Some(socket) = chaos::socket_from_env(session_id)
+                && let Err(e) = chaos::run_chaos_listener(&socket, &chaos).await
+            {
+                warn!("Fault injection unavailable: {e:#}");
+            }
+            std::future::pending::<()>().await;
+        } => {}
+
+        // Cursor shape passthrough via WebSocket text. The monitor stops
+        // when the X server dies, which the capture thread deals with.
         _ = async {
             if let Some(ref mut rx) = cursor_rx {
-                while let Some(css) = rx.recv().await {
-                    let msg = serde_json::json!({ "t": "cur", "css": css }).to_string();
+                while let Some(shape) = rx.recv().await {
+                    let msg = shape.to_message();
                     if let Err(e) = ws_tx_for_cursor.send(Message::Text(msg.into())).await {
                         debug!("Failed to send cursor shape to browser: {e}");
                     }
                 }
-            } else {
-                std::future::pending::<()>().await;
+            }
+            std::future::pending::<()>().await;
+        } => {}
+
+        // Pointer lock state acknowledgements via WebSocket text
+        _ = async {
+            while let Some(locked) = pointer_lock_rx.recv().await {
+                let msg = AgentMessage::PointerLock { locked }.to_json();
+                if let Err(e) = ws_tx_for_pointer_lock.send(Message::Text(msg.into())).await {
+                    debug!("Failed to send pointer lock state to browser: {e}");
+                }
+            }
+        } => {}
+
+        // Periodic latency percentiles via WebSocket text (cached by the server)
+        _ = async {
+            let mut report_interval = tokio::time::interval(LATENCY_REPORT_INTERVAL);
+            loop {
+                report_interval.tick().await;
+                let report = latency.lock().unwrap_or_else(|e| e.into_inner()).take_report();
+                let Some(stats) = report else { continue };
+                debug!(
+                    p50 = stats.p50_ms,
+                    p95 = stats.p95_ms,
+                    samples = stats.samples,
+                    "Latency report"
+                );
+                let Ok(msg) = serde_json::to_string(&stats) else { continue };
+                if let Err(e) = ws_tx_for_latency.send(Message::Text(msg.into())).await {
+                    debug!("Failed to send latency report: {e}");
+                }
+            }
+        } => {}
+
+        // Periodic pipeline stage timings via WebSocket text (cached by the
+        // server), and the cumulative histograms to the textfile if asked
+        _ = async {
+            let mut report_interval = tokio::time::interval(LATENCY_REPORT_INTERVAL);
+            loop {
+                report_interval.tick().await;
+                let (report, textfile) = {
+                    let mut timer = pipeline_timer.lock().unwrap_or_else(|e| e.into_inner());
+                    let textfile = args
+                        .stage_metrics_file
+                        .as_ref()
+                        .map(|_| timer.prometheus_text(session_id));
+                    (timer.take_report(), textfile)
+                };
+                if let (Some(path), Some(text)) = (&args.stage_metrics_file, textfile)
+                    && let Err(e) = pipeline_stats::write_textfile(path, &text)
+                {
+                    debug!("Failed to write stage metrics: {e:#}");
+                }
+                let Some(stats) = report else { continue };
+                debug!(
+                    frames = stats.frames,
+                    capture_p95 = stats.capture.p95_ms,
+                    encode_p95 = stats.encode.p95_ms,
+                    drain_p95 = stats.drain.p95_ms,
+                    send_p95 = stats.send.p95_ms,
+                    "Pipeline report"
+                );
+                let Ok(msg) = serde_json::to_string(&stats) else { continue };
+                if let Err(e) = ws_tx_for_pipeline.send(Message::Text(msg.into())).await {
+                    debug!("Failed to send pipeline report: {e}");
+                }
+            }
+        } => {}
+
+        // Connection quality score via WebSocket text (transitions logged by the server)
+        _ = async {
+            let mut report_interval = tokio::time::interval(QUALITY_REPORT_INTERVAL);
+            let mut monitor = QualityMonitor::new(Instant::now());
+            loop {
+                report_interval.tick().await;
+                let rtt_ms = latency.lock().unwrap_or_else(|e| e.into_inner()).rtt_ms();
+                let Some(quality) =
+                    monitor.sample(&link_stats, rtt_ms, video_bitrate.load(Ordering::Relaxed), Instant::now())
+                else {
+                    continue;
+                };
+                let Ok(msg) = serde_json::to_string(&quality) else { continue };
+                if let Err(e) = ws_tx_for_quality.send(Message::Text(msg.into())).await {
+                    debug!("Failed to send connection quality: {e}");
+                }
+            }
+        } => {}
+
+        // Audio source listing via WebSocket text
+        _ = async {
+            while let Some(change) = audio_sources_rx.recv().await {
+                let server = pulse_server_for_listing.clone();
+                let listing = tokio::task::spawn_blocking(move || {
+                    if let Some(change) = change
+                        && let Err(e) = mixer::set_app_volume(server.as_deref(), &change)
+                    {
+                        warn!(?change, "Application volume change failed: {e:#}");
+                    }
+                    audio::list_sources(server.as_deref())
+                })
+                .await;
+                let sources = match listing {
+                    Ok(Ok(sources)) => sources,
+                    Ok(Err(e)) => {
+                        warn!("Failed to list audio sources: {e:#}");
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Audio source listing task failed: {e}");
+                        continue;
+                    }
+                };
+                let Ok(msg) = serde_json::to_string(&sources) else { continue };
+                if let Err(e) = ws_tx_for_audio_sources.send(Message::Text(msg.into())).await {
+                    debug!("Failed to send audio sources: {e}");
+                }
             }
         } => {}
 
+        // Stalled beyond recovery: exit with an error so the server restarts us
+        Ok(reason) = &mut wedged_rx => {
+            wedged = Some(reason);
+        }
+
+        // X server gone: start over on a fresh display, once signaling has
+        // had a moment to tell the browser
+        _ = async {
+            if display_lost_rx.await.is_err() {
+                std::future::pending::<()>().await;
+            }
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        } => {
+            display_lost = true;
+        }
+
         // Handle shutdown signals
         _ = tokio::signal::ctrl_c() => {
             info!("Received SIGINT, shutting down");
@@ -987,8 +2515,16 @@ async fn main() -> anyhow::Result<()> {
     shutdown.store(true, Ordering::Relaxed);
     drop(encoded_rx);
     drop(audio_rx);
-    if let Err(e) = capture_handle.join() {
-        warn!("Capture thread panicked: {e:?}");
+    if wedged.is_some() {
+        // A stuck capture or encoder thread would never join; exiting ends it
+        warn!("Not waiting for the capture and encoded output threads");
+    } else {
+        if let Err(e) = capture_handle.join() {
+            warn!("Capture thread panicked: {e:?}");
+        }
+        if let Err(e) = output_handle.join() {
+            warn!("Encoded output thread panicked: {e:?}");
+        }
     }
     if let Some(handle) = audio_handle
         && let Err(e) = handle.join()
@@ -996,6 +2532,29 @@ async fn main() -> anyhow::Result<()> {
         warn!("Audio thread panicked: {e:?}");
     }
 
+    // A stale textfile would keep exporting a session that no longer exists
+    if let Some(path) = &args.stage_metrics_file {
+        let _ = std::fs::remove_file(path);
+    }
+
+    if let Some(reason) = wedged {
+        anyhow::bail!("Watchdog gave up on the pipeline: {reason}");
+    }
+    if display_lost {
+        let (width, height) = (
+            input_width.load(Ordering::Relaxed),
+            input_height.load(Ordering::Relaxed),
+        );
+        return restart_display(&args.display, args.sandbox, virtual_display, width, height);
+    }
+    let upgrade = upgrade.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(path) = upgrade {
+        let (width, height) = (
+            input_width.load(Ordering::Relaxed),
+            input_height.load(Ordering::Relaxed),
+        );
+        return upgrade_agent(&path, virtual_display, width, height);
+    }
     info!("Agent shutdown complete");
     Ok(())
 }
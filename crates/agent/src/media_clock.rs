@@ -0,0 +1,164 @@
+//! One clock for both media tracks.
+//!
+//! Video frames are stamped with the media clock time they were captured
+//! at. Audio frames are stamped by counting samples from the clock time of
+//! the first one, so their timestamps stay evenly spaced however the reads
+//! from PulseAudio bunch up. The sound card's clock isn't the system's, so
+//! over hours the sample count runs ahead of or behind the media clock;
+//! the drift is measured on every frame, reported to the browser in sender
+//! reports and to the server in health reports, and the count is
+//! re-anchored once it's too far off to be jitter.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Drift past which audio timestamps are taken from the clock again. A
+/// capture pause or a stalled read shows up as a jump this size at once;
+/// device clock drift takes hours to get here.
+const RESYNC: Duration = Duration::from_millis(200);
+
+/// Microseconds since the agent started, shared by the video and audio
+/// timestamps in frame headers.
+#[derive(Debug, Clone, Copy)]
+pub struct MediaClock {
+    origin: Instant,
+}
+
+impl MediaClock {
+    /// A clock starting now.
+    pub fn start() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+
+    /// Media clock time of `at` (µs).
+    pub fn micros(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.origin).as_micros() as u64
+    }
+}
+
+/// Timestamps of one audio frame: what it's sent with, and the media
+/// clock time it was captured at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioStamp {
+    pub audio_us: u64,
+    pub clock_us: u64,
+}
+
+impl AudioStamp {
+    /// How far the sample count is ahead of the media clock (µs).
+    pub fn drift_us(&self) -> i64 {
+        self.audio_us as i64 - self.clock_us as i64
+    }
+}
+
+/// Stamps consecutive audio frames.
+pub struct AudioTimeline {
+    clock: MediaClock,
+    next_us: Option<u64>,
+    /// Latest drift, read by the watchdog for health reports
+    drift_us: Arc<AtomicI64>,
+}
+
+impl AudioTimeline {
+    pub fn new(clock: MediaClock, drift_us: Arc<AtomicI64>) -> Self {
+        Self {
+            clock,
+            next_us: None,
+            drift_us,
+        }
+    }
+
+    /// Stamp the `frame` long frame whose read returned at `read_at`.
+    /// Frames skipped as silence must still be stamped, or the count falls
+    /// behind.
+    pub fn stamp(&mut self, read_at: Instant, frame: Duration) -> AudioStamp {
+        let frame_us = frame.as_micros() as u64;
+        // The read returns once the frame's last sample is in
+        let clock_us = self.clock.micros(read_at).saturating_sub(frame_us);
+        let audio_us = match self.next_us {
+            Some(next) if next.abs_diff(clock_us) <= RESYNC.as_micros() as u64 => next,
+            // First frame, or after a pause or a stall
+            _ => clock_us,
+        };
+        self.next_us = Some(audio_us + frame_us);
+        let stamp = AudioStamp { audio_us, clock_us };
+        self.drift_us.store(stamp.drift_us(), Ordering::Relaxed);
+        stamp
+    }
+
+    /// Start counting again from the next frame's clock time, after capture
+    /// was paused.
+    pub fn restart(&mut self) {
+        self.next_us = None;
+        self.drift_us.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(20);
+
+    fn timeline() -> (AudioTimeline, Instant, Arc<AtomicI64>) {
+        let clock = MediaClock::start();
+        let drift = Arc::new(AtomicI64::new(0));
+        let timeline = AudioTimeline::new(clock, Arc::clone(&drift));
+        (timeline, clock.origin, drift)
+    }
+
+    #[test]
+    fn audio_is_stamped_by_sample_count() {
+        let (mut timeline, origin, drift) = timeline();
+        let first = timeline.stamp(origin + Duration::from_millis(1020), FRAME);
+        assert_eq!(first.audio_us, 1_000_000);
+        // Reads bunch up, timestamps don't
+        let second = timeline.stamp(origin + Duration::from_millis(1045), FRAME);
+        let third = timeline.stamp(origin + Duration::from_millis(1046), FRAME);
+        assert_eq!(second.audio_us, 1_020_000);
+        assert_eq!(third.audio_us, 1_040_000);
+        assert_eq!(third.clock_us, 1_026_000);
+        assert_eq!(drift.load(Ordering::Relaxed), 14_000);
+    }
+
+    #[test]
+    fn a_new_frame_length_follows_on() {
+        let (mut timeline, origin, _) = timeline();
+        timeline.stamp(origin + FRAME, FRAME);
+        let long = Duration::from_millis(40);
+        let next = timeline.stamp(origin + Duration::from_millis(60), long);
+        assert_eq!(next.audio_us, 20_000);
+        let after = timeline.stamp(origin + Duration::from_millis(100), long);
+        assert_eq!(after.audio_us, 60_000);
+    }
+
+    #[test]
+    fn drift_past_the_limit_reanchors() {
+        let (mut timeline, origin, drift) = timeline();
+        timeline.stamp(origin + FRAME, FRAME);
+        // A slow sound card: each frame takes 20.1 ms of system time
+        let mut read_at = origin + FRAME;
+        let mut last = None;
+        for _ in 0..1000 {
+            read_at += Duration::from_micros(20_100);
+            last = Some(timeline.stamp(read_at, FRAME));
+        }
+        assert_eq!(last.unwrap().drift_us(), -100_000);
+        // A stall is a jump, taken from the clock at once
+        let after_stall = timeline.stamp(read_at + Duration::from_secs(1), FRAME);
+        assert_eq!(after_stall.audio_us, after_stall.clock_us);
+        assert_eq!(drift.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn restart_takes_the_clock_again() {
+        let (mut timeline, origin, _) = timeline();
+        timeline.stamp(origin + FRAME, FRAME);
+        timeline.restart();
+        let resumed = timeline.stamp(origin + Duration::from_millis(150), FRAME);
+        assert_eq!(resumed.audio_us, 130_000);
+    }
+}
@@ -0,0 +1,206 @@
+//! Per-application volume and mute for the browser's mixer, through
+//! libpulse's introspection API; pipewire-pulse answers it as PulseAudio
+//! does. Each call connects, waits for the server's answer and
+//! disconnects, so it blocks: the agent makes them from `spawn_blocking`.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use anyhow::{Context as _, bail};
+use beam_protocol::{AudioAppInfo, MAX_APP_VOLUME};
+use libpulse_binding as pulse;
+use pulse::callbacks::ListResult;
+use pulse::context::{Context, FlagSet, State};
+use pulse::mainloop::standard::{IterateResult, Mainloop};
+use pulse::operation::{Operation, State as OperationState};
+use pulse::volume::Volume;
+
+/// A browser's change to one application stream (`InputEvent::AudioAppVolume`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppVolume {
+    /// Sink-input index
+    pub index: u32,
+    /// Percent of normal volume
+    pub volume: Option<u16>,
+    pub muted: Option<bool>,
+}
+
+/// Application playback streams with their volume and mute state.
+pub fn list_apps(pulse_server: Option<&str>) -> anyhow::Result<Vec<AudioAppInfo>> {
+    let mut conn = Connection::open(pulse_server)?;
+    let apps = Rc::new(RefCell::new(Vec::new()));
+    let failed = Rc::new(Cell::new(false));
+    let op = conn.context.introspect().get_sink_input_info_list({
+        let apps = Rc::clone(&apps);
+        let failed = Rc::clone(&failed);
+        move |result| match result {
+            ListResult::Item(info) => apps.borrow_mut().push(AudioAppInfo {
+                index: info.index,
+                name: app_name(
+                    info.index,
+                    info.proplist.get_str("application.name"),
+                    info.proplist.get_str("media.name"),
+                ),
+                volume: to_percent(info.volume.max()),
+                muted: info.mute,
+            }),
+            ListResult::End => {}
+            ListResult::Error => failed.set(true),
+        }
+    });
+    conn.wait(&op)?;
+    if failed.get() {
+        bail!(
+            "Listing application streams failed: {}",
+            conn.context.errno()
+        );
+    }
+    Ok(apps.take())
+}
+
+/// Apply a browser's volume and mute change to one application stream.
+pub fn set_app_volume(pulse_server: Option<&str>, change: &AppVolume) -> anyhow::Result<()> {
+    let mut conn = Connection::open(pulse_server)?;
+    let mut introspect = conn.context.introspect();
+    if let Some(percent) = change.volume {
+        let current = Rc::new(RefCell::new(None));
+        let op = introspect.get_sink_input_info(change.index, {
+            let current = Rc::clone(&current);
+            move |result| {
+                if let ListResult::Item(info) = result {
+                    *current.borrow_mut() = Some(info.volume);
+                }
+            }
+        });
+        conn.wait(&op)?;
+        let mut volumes = current
+            .take()
+            .with_context(|| format!("No application stream #{}", change.index))?;
+        // Scaled, so the stream keeps its balance between channels
+        volumes
+            .scale(from_percent(percent))
+            .context("Invalid stream volume")?;
+        let (done, callback) = success();
+        let op = introspect.set_sink_input_volume(change.index, &volumes, Some(callback));
+        conn.wait(&op)?;
+        if !done.get() {
+            bail!(
+                "Setting the volume of stream #{} failed: {}",
+                change.index,
+                conn.context.errno()
+            );
+        }
+    }
+    if let Some(muted) = change.muted {
+        let (done, callback) = success();
+        let op = introspect.set_sink_input_mute(change.index, muted, Some(callback));
+        conn.wait(&op)?;
+        if !done.get() {
+            bail!(
+                "Muting stream #{} failed: {}",
+                change.index,
+                conn.context.errno()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A connected context and the mainloop driving it
+struct Connection {
+    mainloop: Mainloop,
+    context: Context,
+}
+
+impl Connection {
+    fn open(pulse_server: Option<&str>) -> anyhow::Result<Self> {
+        let mut mainloop = Mainloop::new().context("Failed to create PulseAudio mainloop")?;
+        let mut context = Context::new(&mainloop, "beam-agent-mixer")
+            .context("Failed to create PulseAudio context")?;
+        context
+            .connect(pulse_server, FlagSet::NOAUTOSPAWN, None)
+            .context("PulseAudio connection failed")?;
+        loop {
+            iterate(&mut mainloop)?;
+            match context.get_state() {
+                State::Ready => break,
+                State::Failed | State::Terminated => {
+                    bail!("PulseAudio connection failed: {}", context.errno())
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { mainloop, context })
+    }
+
+    /// Run the mainloop until `op` has finished.
+    fn wait<F: ?Sized>(&mut self, op: &Operation<F>) -> anyhow::Result<()> {
+        loop {
+            match op.get_state() {
+                OperationState::Running => iterate(&mut self.mainloop)?,
+                OperationState::Done => return Ok(()),
+                OperationState::Cancelled => {
+                    bail!("PulseAudio request cancelled: {}", self.context.errno())
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.context.disconnect();
+    }
+}
+
+fn iterate(mainloop: &mut Mainloop) -> anyhow::Result<()> {
+    match mainloop.iterate(true) {
+        IterateResult::Success(_) => Ok(()),
+        IterateResult::Quit(_) => bail!("PulseAudio mainloop quit"),
+        IterateResult::Err(e) => Err(e).context("PulseAudio mainloop failed"),
+    }
+}
+
+/// Callback told whether a PulseAudio operation succeeded
+type SuccessCallback = Box<dyn FnMut(bool)>;
+
+/// A success callback for an operation, and the flag it sets
+fn success() -> (Rc<Cell<bool>>, SuccessCallback) {
+    let done = Rc::new(Cell::new(false));
+    let flag = Rc::clone(&done);
+    (done, Box::new(move |ok| flag.set(ok)))
+}
+
+/// What a stream is shown as: its application, else its own name
+fn app_name(index: u32, application: Option<String>, media: Option<String>) -> String {
+    application
+        .or(media)
+        .unwrap_or_else(|| format!("Stream #{index}"))
+}
+
+fn to_percent(volume: Volume) -> u16 {
+    let normal = u64::from(Volume::NORMAL.0);
+    ((u64::from(volume.0) * 100 + normal / 2) / normal).min(u64::from(u16::MAX)) as u16
+}
+
+fn from_percent(percent: u16) -> Volume {
+    let percent = u64::from(percent.min(MAX_APP_VOLUME));
+    Volume((percent * u64::from(Volume::NORMAL.0) / 100) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volumes_convert_to_percent_and_back() {
+        assert_eq!(to_percent(Volume::NORMAL), 100);
+        assert_eq!(to_percent(Volume::MUTED), 0);
+        assert_eq!(from_percent(100), Volume::NORMAL);
+        assert_eq!(to_percent(from_percent(35)), 35);
+        // Boosts stop where the desktop's own mixers do
+        assert_eq!(from_percent(400), from_percent(MAX_APP_VOLUME));
+        assert_eq!(app_name(4, None, Some("Playback".into())), "Playback");
+        assert_eq!(app_name(4, None, None), "Stream #4");
+    }
+}
@@ -0,0 +1,263 @@
+//! Per-stage timing of the frame pipeline: screen capture, handing the
+//! frame to the encoder, waiting for encoded output, and the hop from the
+//! encoded output thread to the WebSocket outbox.
+//!
+//! Every stage keeps the samples of the current reporting window, which
+//! become a `PipelineStats` message, and a cumulative histogram for the
+//! optional Prometheus textfile. A latency regression then shows up in
+//! one stage: XShm, the encoder, or the send path.
+
+use crate::latency::percentile;
+use anyhow::{Context, Result};
+use beam_protocol::{PipelineStats, StageTiming};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Histogram bucket bounds (ms): sub-millisecond grabs up to a stall
+/// several frames long
+const BUCKETS_MS: [f64; 11] = [
+    0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 33.0, 66.0, 133.0, 500.0,
+];
+/// Upper bound on samples held per stage between reports.
+const MAX_SAMPLES: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    Capture,
+    Encode,
+    Drain,
+    Send,
+}
+
+impl Stage {
+    const ALL: [Stage; 4] = [Stage::Capture, Stage::Encode, Stage::Drain, Stage::Send];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Capture => "capture",
+            Stage::Encode => "encode",
+            Stage::Drain => "drain",
+            Stage::Send => "send",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Count per bucket of `BUCKETS_MS`; the last one is +Inf
+    counts: [u64; BUCKETS_MS.len() + 1],
+    sum_ms: f64,
+    window: Vec<f64>,
+}
+
+impl Histogram {
+    fn record(&mut self, ms: f64) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&le| ms <= le)
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.sum_ms += ms;
+        if self.window.len() < MAX_SAMPLES {
+            self.window.push(ms);
+        }
+    }
+
+    fn take_timing(&mut self) -> StageTiming {
+        let mut window = std::mem::take(&mut self.window);
+        if window.is_empty() {
+            return StageTiming::default();
+        }
+        window.sort_by(f64::total_cmp);
+        StageTiming {
+            p50_ms: percentile(&window, 50.0),
+            p95_ms: percentile(&window, 95.0),
+            max_ms: window[window.len() - 1],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PipelineTimer {
+    stages: [Histogram; 4],
+    frames: u32,
+    deadline_missed: u32,
+    deadline_missed_total: u64,
+    queue_dropped: u32,
+    outbox_dropped: u32,
+}
+
+impl PipelineTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time spent in one stage for one frame.
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        self.stages[stage as usize].record(elapsed.as_secs_f64() * 1000.0);
+    }
+
+    /// Stage times of a captured frame, recorded under a single lock by
+    /// the capture thread.
+    pub fn record_frame(&mut self, capture: Duration, encode: Duration) {
+        self.frames = self.frames.saturating_add(1);
+        self.record(Stage::Capture, capture);
+        self.record(Stage::Encode, encode);
+    }
+
+    /// A frame that took longer than the frame interval to capture and
+    /// submit, delaying the next one.
+    pub fn record_deadline_miss(&mut self) {
+        self.deadline_missed = self.deadline_missed.saturating_add(1);
+        self.deadline_missed_total += 1;
+    }
+
+    /// A P-frame the encoded frame queue dropped to make room.
+    pub fn record_queue_drop(&mut self) {
+        self.queue_dropped = self.queue_dropped.saturating_add(1);
+    }
+
+    /// A frame that didn't fit in the WebSocket outbox.
+    pub fn record_outbox_drop(&mut self) {
+        self.outbox_dropped = self.outbox_dropped.saturating_add(1);
+    }
+
+    /// Aggregate and clear the window since the last report.
+    pub fn take_report(&mut self) -> Option<PipelineStats> {
+        if self.frames == 0 {
+            return None;
+        }
+        let [capture, encode, drain, send] = self.stages.each_mut().map(Histogram::take_timing);
+        Some(PipelineStats {
+            frames: std::mem::take(&mut self.frames),
+            deadline_missed: std::mem::take(&mut self.deadline_missed),
+            capture,
+            encode,
+            drain,
+            send,
+            queue_dropped: std::mem::take(&mut self.queue_dropped),
+            outbox_dropped: std::mem::take(&mut self.outbox_dropped),
+        })
+    }
+
+    /// Cumulative histograms in the Prometheus text format.
+    pub fn prometheus_text(&self, session_id: Uuid) -> String {
+        let mut out = String::from(
+            "# HELP beam_agent_stage_duration_ms Time per frame in each agent pipeline stage\n\
+             # TYPE beam_agent_stage_duration_ms histogram\n",
+        );
+        for stage in Stage::ALL {
+            let hist = &self.stages[stage as usize];
+            let labels = format!("session_id=\"{session_id}\",stage=\"{}\"", stage.name());
+            let mut cumulative = 0;
+            for (i, count) in hist.counts.iter().enumerate() {
+                cumulative += count;
+                let le = BUCKETS_MS.get(i).map_or("+Inf".to_string(), f64::to_string);
+                let _ = writeln!(
+                    out,
+                    "beam_agent_stage_duration_ms_bucket{{{labels},le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "beam_agent_stage_duration_ms_sum{{{labels}}} {}",
+                hist.sum_ms
+            );
+            let _ = writeln!(
+                out,
+                "beam_agent_stage_duration_ms_count{{{labels}}} {cumulative}"
+            );
+        }
+        let _ = write!(
+            out,
+            "# HELP beam_agent_deadline_missed_total Frames that overran the frame interval\n\
+             # TYPE beam_agent_deadline_missed_total counter\n\
+             beam_agent_deadline_missed_total{{session_id=\"{session_id}\"}} {}\n",
+            self.deadline_missed_total
+        );
+        out
+    }
+}
+
+/// Replace `path` in one step, so a collector never reads half a file.
+pub fn write_textfile(path: &Path, contents: &str) -> Result<()> {
+    let tmp = path.with_extension("prom.tmp");
+    std::fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn report_summarizes_each_stage() {
+        let mut t = PipelineTimer::new();
+        assert!(t.take_report().is_none());
+
+        for i in 1..=20 {
+            t.record_frame(MS * i, MS);
+            if i % 2 == 0 {
+                t.record(Stage::Drain, MS * 4);
+            }
+            t.record(Stage::Send, MS / 2);
+        }
+        t.record_deadline_miss();
+        t.record_queue_drop();
+        t.record_outbox_drop();
+        t.record_outbox_drop();
+        let report = t.take_report().unwrap();
+        assert_eq!(report.frames, 20);
+        assert_eq!(report.deadline_missed, 1);
+        assert_eq!((report.queue_dropped, report.outbox_dropped), (1, 2));
+        assert_eq!(report.capture.p50_ms, 10.0);
+        assert_eq!(report.capture.p95_ms, 19.0);
+        assert_eq!(report.capture.max_ms, 20.0);
+        assert_eq!(report.encode.max_ms, 1.0);
+        assert_eq!(report.drain.p50_ms, 4.0);
+        assert_eq!(report.send.p95_ms, 0.5);
+
+        // The window starts over; an idle stage reports zeros
+        t.record_frame(MS, MS);
+        let report = t.take_report().unwrap();
+        assert_eq!(report.frames, 1);
+        assert_eq!(report.deadline_missed, 0);
+        assert_eq!(report.drain, StageTiming::default());
+        assert_eq!((report.queue_dropped, report.outbox_dropped), (0, 0));
+    }
+
+    #[test]
+    fn textfile_histograms_are_cumulative() {
+        let mut t = PipelineTimer::new();
+        t.record(Stage::Capture, MS / 10);
+        t.record(Stage::Capture, MS * 3);
+        t.record(Stage::Capture, Duration::from_secs(1));
+        t.record_deadline_miss();
+        // Reports don't reset the histograms
+        t.take_report();
+
+        let id = Uuid::nil();
+        let text = t.prometheus_text(id);
+        let line = |le: &str| {
+            format!(
+                "beam_agent_stage_duration_ms_bucket{{session_id=\"{id}\",stage=\"capture\",le=\"{le}\"}}"
+            )
+        };
+        assert!(text.contains(&format!("{} 1\n", line("0.25"))));
+        assert!(text.contains(&format!("{} 1\n", line("2"))));
+        assert!(text.contains(&format!("{} 2\n", line("4"))));
+        assert!(text.contains(&format!("{} 2\n", line("500"))));
+        assert!(text.contains(&format!("{} 3\n", line("+Inf"))));
+        assert!(text.contains(
+            "beam_agent_stage_duration_ms_count{session_id=\"00000000-0000-0000-0000-000000000000\",stage=\"capture\"} 3\n"
+        ));
+        assert!(text.contains("stage=\"send\",le=\"+Inf\"} 0\n"));
+        assert!(text.contains(&format!(
+            "beam_agent_deadline_missed_total{{session_id=\"{id}\"}} 1\n"
+        )));
+    }
+}
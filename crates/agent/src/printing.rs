@@ -0,0 +1,158 @@
+//! Print redirection: PDFs printed to the "Beam Printer" CUPS queue are
+//! downloaded in the browser.
+//!
+//! The queue uses the cups-pdf backend (set up by the package postinstall),
+//! which writes each job as a PDF into `~/PDF`. This module polls that
+//! directory and hands finished files to the regular file download path.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// cups-pdf's default `Out ${HOME}/PDF` in /etc/cups/cups-pdf.conf.
+pub(crate) const PRINT_OUTPUT_SUBDIR: &str = "PDF";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size and mtime of a PDF as last seen.
+type Stamp = (u64, Option<SystemTime>);
+
+/// Tracks the PDFs in the output directory and reports new ones once they
+/// are complete. cups-pdf writes the file in place, so a job counts as
+/// finished when its size and mtime are unchanged across two polls.
+pub(crate) struct PrintSpool {
+    dir: PathBuf,
+    /// Files already delivered (or present at startup), with their stamp
+    delivered: HashMap<PathBuf, Stamp>,
+    /// New or changed files seen on the previous poll
+    pending: HashMap<PathBuf, Stamp>,
+}
+
+impl PrintSpool {
+    /// Start watching `dir`. PDFs already there are old jobs and are not
+    /// sent again.
+    pub fn new(dir: PathBuf) -> Self {
+        let delivered = scan(&dir);
+        Self {
+            dir,
+            delivered,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Finished print jobs since the last call.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let current = scan(&self.dir);
+        self.delivered.retain(|path, _| current.contains_key(path));
+
+        let mut ready = Vec::new();
+        let mut pending = HashMap::new();
+        for (path, stamp) in current {
+            if self.delivered.get(&path) == Some(&stamp) {
+                continue;
+            }
+            // Empty files are jobs cups-pdf hasn't started writing yet
+            if stamp.0 > 0 && self.pending.get(&path) == Some(&stamp) {
+                self.delivered.insert(path.clone(), stamp);
+                ready.push(path);
+            } else {
+                pending.insert(path, stamp);
+            }
+        }
+        self.pending = pending;
+        ready.sort();
+        ready
+    }
+}
+
+/// All `*.pdf` regular files directly in `dir`. A missing directory (no
+/// job printed yet) is just empty.
+fn scan(dir: &Path) -> HashMap<PathBuf, Stamp> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+        })
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            metadata
+                .is_file()
+                .then(|| (entry.path(), (metadata.len(), metadata.modified().ok())))
+        })
+        .collect()
+}
+
+/// Poll the cups-pdf output directory and queue each finished job as a
+/// file download, which streams it to the browser.
+pub(crate) async fn run_print_watch_loop(dir: PathBuf, download_request_tx: mpsc::Sender<String>) {
+    info!(dir = %dir.display(), "Watching for print jobs");
+    let mut spool = PrintSpool::new(dir);
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        for path in spool.poll() {
+            let Some(path) = path.to_str().map(str::to_string) else {
+                warn!(path = %path.display(), "Skipping print job with non-UTF-8 name");
+                continue;
+            };
+            info!(path, "Print job finished, sending to browser");
+            if download_request_tx.send(path).await.is_err() {
+                debug!("Download channel closed, stopping print watcher");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_new_pdf_once_it_stops_growing() {
+        let dir = std::env::temp_dir().join(format!("beam-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("old.pdf"), b"%PDF-old").unwrap();
+
+        let mut spool = PrintSpool::new(dir.clone());
+        assert!(spool.poll().is_empty(), "existing PDFs are not resent");
+
+        let job = dir.join("job_1-report.pdf");
+        fs::write(&job, b"%PDF-1.4").unwrap();
+        fs::write(dir.join("notes.txt"), b"not a print job").unwrap();
+        assert!(spool.poll().is_empty(), "first sighting is still pending");
+        assert_eq!(spool.poll(), vec![job.clone()]);
+        assert!(spool.poll().is_empty(), "delivered once");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn waits_for_empty_job_and_missing_dir() {
+        let dir = std::env::temp_dir().join(format!("beam-test-{}", uuid::Uuid::new_v4()));
+        let mut spool = PrintSpool::new(dir.clone());
+        assert!(spool.poll().is_empty());
+
+        fs::create_dir_all(&dir).unwrap();
+        let job = dir.join("job_2.pdf");
+        fs::write(&job, b"").unwrap();
+        spool.poll();
+        assert!(spool.poll().is_empty(), "empty file is not a finished job");
+
+        fs::write(&job, b"%PDF-1.4 done").unwrap();
+        spool.poll();
+        assert_eq!(spool.poll(), vec![job]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
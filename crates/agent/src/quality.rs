@@ -0,0 +1,152 @@
+use beam_protocol::{NetworkQuality, QualityLevel};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Counters for the video path, shared by the capture thread (encoder-side
+/// drops), the send loop (outbox drops and delivered bytes) and the input
+/// handler (frame acks).
+#[derive(Default)]
+pub struct LinkStats {
+    frames_sent: AtomicU64,
+    frames_dropped: AtomicU64,
+    bytes_sent: AtomicU64,
+    frames_acked: AtomicU64,
+}
+
+impl LinkStats {
+    pub fn record_sent(&self, bytes: usize) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self) {
+        self.frames_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The browser acked a frame it decoded (one in every few).
+    pub fn record_acked(&self) {
+        self.frames_acked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn frames_sent(&self) -> u64 {
+        self.frames_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_acked(&self) -> u64 {
+        self.frames_acked.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self) -> (u64, u64, u64) {
+        (
+            self.frames_sent.load(Ordering::Relaxed),
+            self.frames_dropped.load(Ordering::Relaxed),
+            self.bytes_sent.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Turns cumulative `LinkStats` into per-interval quality snapshots.
+pub struct QualityMonitor {
+    last: (u64, u64, u64),
+    last_at: Instant,
+}
+
+impl QualityMonitor {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last: (0, 0, 0),
+            last_at: now,
+        }
+    }
+
+    /// Quality over the interval since the previous call. None if no video
+    /// went out (no browser attached, or capture stalled).
+    pub fn sample(
+        &mut self,
+        stats: &LinkStats,
+        rtt_ms: Option<f64>,
+        target_kbps: u32,
+        now: Instant,
+    ) -> Option<NetworkQuality> {
+        let current = stats.snapshot();
+        let sent = current.0 - self.last.0;
+        let dropped = current.1 - self.last.1;
+        let bytes = current.2 - self.last.2;
+        let elapsed_ms = now.duration_since(self.last_at).as_millis().max(1) as u64;
+        self.last = current;
+        self.last_at = now;
+
+        if sent == 0 {
+            return None;
+        }
+        let loss_pct = dropped as f64 * 100.0 / (sent + dropped) as f64;
+        // bits per millisecond == kbps
+        let kbps = (bytes * 8 / elapsed_ms) as u32;
+        Some(NetworkQuality {
+            level: classify(rtt_ms, loss_pct, kbps, target_kbps),
+            rtt_ms,
+            loss_pct,
+            kbps,
+            target_kbps,
+        })
+    }
+}
+
+/// Map link measurements to a badge level.
+///
+/// Loss here is backpressure: frames dropped because the socket couldn't
+/// keep up, so it is the strongest signal. Running at (or above) the
+/// configured bitrate means there is no headroom left for motion bursts.
+fn classify(rtt_ms: Option<f64>, loss_pct: f64, kbps: u32, target_kbps: u32) -> QualityLevel {
+    let rtt = rtt_ms.unwrap_or(0.0);
+    let no_headroom = target_kbps > 0 && kbps as f64 >= target_kbps as f64 * 0.9;
+    if loss_pct >= 5.0 || rtt >= 150.0 {
+        QualityLevel::Poor
+    } else if loss_pct >= 1.0 || rtt >= 60.0 || no_headroom {
+        QualityLevel::Fair
+    } else {
+        QualityLevel::Good
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn classify_thresholds() {
+        assert_eq!(classify(Some(10.0), 0.0, 5000, 20000), QualityLevel::Good);
+        assert_eq!(classify(None, 0.0, 5000, 20000), QualityLevel::Good);
+        assert_eq!(classify(Some(80.0), 0.0, 5000, 20000), QualityLevel::Fair);
+        assert_eq!(classify(Some(10.0), 2.0, 5000, 20000), QualityLevel::Fair);
+        assert_eq!(classify(Some(10.0), 0.0, 19000, 20000), QualityLevel::Fair);
+        assert_eq!(classify(Some(200.0), 0.0, 5000, 20000), QualityLevel::Poor);
+        assert_eq!(classify(Some(10.0), 10.0, 5000, 20000), QualityLevel::Poor);
+    }
+
+    #[test]
+    fn sample_uses_interval_deltas() {
+        let stats = LinkStats::default();
+        let start = Instant::now();
+        let mut monitor = QualityMonitor::new(start);
+
+        for _ in 0..99 {
+            stats.record_sent(1250);
+        }
+        stats.record_dropped();
+        let nq = monitor
+            .sample(&stats, Some(20.0), 10_000, start + Duration::from_secs(1))
+            .unwrap();
+        assert!((nq.loss_pct - 1.0).abs() < 0.001);
+        assert_eq!(nq.kbps, 990); // 99 * 1250 bytes * 8 bits over 1000 ms
+        assert_eq!(nq.level, QualityLevel::Fair);
+
+        // Nothing sent since: no snapshot
+        assert!(
+            monitor
+                .sample(&stats, Some(20.0), 10_000, start + Duration::from_secs(2))
+                .is_none()
+        );
+    }
+}
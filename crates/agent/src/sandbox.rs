@@ -0,0 +1,314 @@
+//! Post-init hardening for `--sandbox`. Once the desktop is running and
+//! the pipeline threads have their scheduling, the agent gives up its
+//! capabilities and installs a seccomp filter denying syscalls that
+//! neither it nor a desktop program needs: tracing other processes,
+//! loading kernel code, BPF, perf events, io_uring and the like.
+//!
+//! The filter and no_new_privs carry over to whatever the agent starts
+//! afterwards (xclip, xrandr, screen lockers, links opened in the session),
+//! so setuid helpers launched from there no longer gain privileges.
+//! Namespaces stay available because browser sandboxes depend on them.
+//! /tmp and the network are shared with the session as before: Xorg,
+//! the sound server and the desktop the agent starts talk through them.
+
+use anyhow::Context;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How long threads get to acknowledge the capability drop
+const DROP_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// x32 syscalls share the x86_64 audit arch and set this bit in the number
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_quotactl,
+    libc::SYS_syslog,
+    libc::SYS_io_uring_setup,
+    libc::SYS_io_uring_enter,
+    libc::SYS_io_uring_register,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_iopl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_ioperm,
+];
+
+/// Offsets into `struct seccomp_data`
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+const DENY: u32 = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+/// Drop capabilities on every thread, then filter syscalls process-wide.
+pub(crate) fn enter() -> anyhow::Result<()> {
+    drop_capabilities()?;
+    install_filter()
+}
+
+/// Threads that have run `drop_on_signal`, and how many of them failed
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static DROP_FAILED: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Empty the calling thread's capability sets (clearing permitted also
+/// clears ambient). Async-signal-safe.
+fn clear_thread_capabilities() -> bool {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [CapData::default(); 2];
+    unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) == 0 }
+}
+
+extern "C" fn drop_on_signal(_: libc::c_int) {
+    let errno = unsafe { *libc::__errno_location() };
+    if !clear_thread_capabilities() {
+        DROP_FAILED.fetch_add(1, Ordering::SeqCst);
+    }
+    DROPPED.fetch_add(1, Ordering::SeqCst);
+    unsafe { *libc::__errno_location() = errno };
+}
+
+/// Capabilities belong to threads, and capset(2) only changes the caller's.
+/// Every other thread is signalled to drop its own, the way glibc applies
+/// setuid() process-wide. Threads started meanwhile are caught by listing
+/// the tasks again until no new ones show up.
+fn drop_capabilities() -> anyhow::Result<()> {
+    let signal = libc::SIGRTMIN();
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = drop_on_signal as extern "C" fn(libc::c_int) as usize;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        anyhow::ensure!(
+            libc::sigaction(signal, &action, std::ptr::null_mut()) == 0,
+            "Failed to install the capability drop handler: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    anyhow::ensure!(
+        clear_thread_capabilities(),
+        "Failed to drop capabilities: {}",
+        std::io::Error::last_os_error()
+    );
+
+    let pid = std::process::id() as libc::c_int;
+    let own = unsafe { libc::gettid() };
+    let mut signalled = HashSet::from([own]);
+    let deadline = Instant::now() + DROP_TIMEOUT;
+    loop {
+        let mut sent = 0;
+        for tid in task_ids().context("Failed to list agent threads")? {
+            if !signalled.contains(&tid)
+                && unsafe { libc::syscall(libc::SYS_tgkill, pid, tid, signal) } == 0
+            {
+                signalled.insert(tid);
+                sent += 1;
+            }
+        }
+        let expected = signalled.len() - 1;
+        while DROPPED.load(Ordering::SeqCst) < expected && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        if sent == 0 || Instant::now() >= deadline {
+            break;
+        }
+    }
+    anyhow::ensure!(
+        DROP_FAILED.load(Ordering::SeqCst) == 0,
+        "Some threads failed to drop their capabilities"
+    );
+    let dropped = DROPPED.load(Ordering::SeqCst);
+    let expected = signalled.len() - 1;
+    if dropped < expected {
+        // Threads that block every signal, e.g. GLib's worker, hold on to
+        // their capabilities; they never run agent code
+        warn!(
+            missing = expected - dropped,
+            "Some threads did not drop their capabilities"
+        );
+    }
+    Ok(())
+}
+
+fn task_ids() -> std::io::Result<Vec<libc::pid_t>> {
+    let mut tids = Vec::new();
+    for entry in std::fs::read_dir("/proc/self/task")? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+            tids.push(tid);
+        }
+    }
+    Ok(tids)
+}
+
+/// no_new_privs plus the deny filter, synchronized to all threads.
+fn install_filter() -> anyhow::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        anyhow::bail!(
+            "Failed to set no_new_privs: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    let mut program = filter_program();
+    let fprog = libc::sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &fprog,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        -1 => Err(std::io::Error::last_os_error()).context("Failed to install the seccomp filter"),
+        tid => anyhow::bail!("Thread {tid} could not take the seccomp filter"),
+    }
+}
+
+/// Deny the listed syscalls and anything from a foreign ABI with EPERM,
+/// allow the rest.
+fn filter_program() -> Vec<libc::sock_filter> {
+    let insn = |code: u32, k: u32, jt: usize| libc::sock_filter {
+        code: code as u16,
+        jt: jt as u8,
+        jf: 0,
+        k,
+    };
+    let jump = |op: u32, k: u32, jt: usize| insn(libc::BPF_JMP | op | libc::BPF_K, k, jt);
+    let load = |offset| insn(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset, 0);
+    let ret = |action| insn(libc::BPF_RET | libc::BPF_K, action, 0);
+
+    let mut checks: Vec<(u32, u32)> = DENIED
+        .iter()
+        .map(|&nr| (libc::BPF_JEQ, nr as u32))
+        .collect();
+    #[cfg(target_arch = "x86_64")]
+    checks.insert(0, (libc::BPF_JGE, X32_SYSCALL_BIT));
+
+    // Arch check and load (4), the checks and allow, then the shared deny
+    let deny_at = 4 + checks.len() + 1;
+    let mut program = vec![
+        load(ARCH_OFFSET),
+        jump(libc::BPF_JEQ, AUDIT_ARCH, 1),
+        ret(DENY),
+        load(NR_OFFSET),
+    ];
+    for (op, k) in checks {
+        let jt = deny_at - program.len() - 1;
+        program.push(jump(op, k, jt));
+    }
+    program.push(ret(libc::SECCOMP_RET_ALLOW));
+    program.push(ret(DENY));
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the classic BPF subset the filter uses against one syscall
+    fn evaluate(program: &[libc::sock_filter], arch: u32, nr: u32) -> u32 {
+        let mut acc = 0;
+        let mut pc = 0;
+        loop {
+            let insn = program[pc];
+            let code = u32::from(insn.code);
+            pc += 1;
+            match code {
+                c if c == libc::BPF_LD | libc::BPF_W | libc::BPF_ABS => {
+                    acc = if insn.k == ARCH_OFFSET { arch } else { nr };
+                }
+                c if c == libc::BPF_RET | libc::BPF_K => return insn.k,
+                c => {
+                    let taken = match c & !libc::BPF_JMP {
+                        op if op == libc::BPF_JEQ => acc == insn.k,
+                        op if op == libc::BPF_JGE => acc >= insn.k,
+                        op => panic!("unexpected BPF op {op:#x}"),
+                    };
+                    pc += usize::from(if taken { insn.jt } else { insn.jf });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn filter_denies_listed_syscalls_only() {
+        let program = filter_program();
+        for &nr in DENIED {
+            assert_eq!(evaluate(&program, AUDIT_ARCH, nr as u32), DENY, "{nr}");
+        }
+        for nr in [
+            libc::SYS_read,
+            libc::SYS_write,
+            libc::SYS_clone,
+            libc::SYS_unshare,
+            libc::SYS_mount,
+            libc::SYS_sched_setscheduler,
+        ] {
+            assert_eq!(
+                evaluate(&program, AUDIT_ARCH, nr as u32),
+                libc::SECCOMP_RET_ALLOW,
+                "{nr}"
+            );
+        }
+    }
+
+    #[test]
+    fn foreign_abis_are_denied() {
+        let program = filter_program();
+        // i386 via int 0x80 / arm32 compat
+        assert_eq!(evaluate(&program, 0x4000_0003, 0), DENY);
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(evaluate(&program, AUDIT_ARCH, X32_SYSCALL_BIT | 1), DENY);
+    }
+}
@@ -0,0 +1,198 @@
+//! Scheduler class, priority and CPU pinning for the agent's
+//! latency-critical threads, as set in the server's `[agent]` config.
+//!
+//! Real-time scheduling needs CAP_SYS_NICE. Without it a thread still gets
+//! the highest real-time priority RLIMIT_RTPRIO grants (limits.conf), and
+//! with neither it stays on the normal scheduler; streaming works either
+//! way, frame pacing just suffers more under load.
+
+use anyhow::Context;
+use beam_protocol::{CpuAffinity, RealtimeConfig};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ThreadGroup {
+    Capture,
+    Audio,
+    /// Tokio runtime threads
+    Workers,
+}
+
+impl ThreadGroup {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Capture => "capture",
+            Self::Audio => "audio",
+            Self::Workers => "worker",
+        }
+    }
+}
+
+/// Apply the configured priority and CPU set for `group` to the calling
+/// thread. Failures leave the thread as it was. Outcomes are logged only
+/// with `report`, so threads that start in numbers log once.
+pub(crate) fn tune_current_thread(
+    group: ThreadGroup,
+    realtime: &RealtimeConfig,
+    affinity: &CpuAffinity,
+    report: bool,
+) {
+    let (priority, cpus) = match group {
+        ThreadGroup::Capture => (realtime.capture, &affinity.capture),
+        ThreadGroup::Audio => (realtime.audio, &affinity.audio),
+        ThreadGroup::Workers => (realtime.workers, &affinity.workers),
+    };
+    #[cfg(target_os = "linux")]
+    {
+        use tracing::{info, warn};
+        let name = group.name();
+        if let Some(policy) = sched_policy(&realtime.policy)
+            && priority > 0
+        {
+            let class = if policy == libc::SCHED_RR {
+                "SCHED_RR"
+            } else {
+                "SCHED_FIFO"
+            };
+            let result = set_realtime(policy, priority);
+            match result {
+                _ if !report => {}
+                Ok(granted) if granted == priority => {
+                    info!("{name} thread elevated to {class} priority {granted}");
+                }
+                Ok(granted) => {
+                    warn!(
+                        "{name} thread elevated to {class} priority {granted} instead of \
+                         {priority} (capped by RLIMIT_RTPRIO)"
+                    );
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not set {class} for the {name} thread (need CAP_SYS_NICE or \
+                         RLIMIT_RTPRIO), keeping normal scheduling: {e}"
+                    );
+                }
+            }
+        }
+        if !cpus.is_empty() {
+            let result = pin(cpus);
+            match result {
+                _ if !report => {}
+                Ok(()) => info!(?cpus, "{name} thread pinned"),
+                Err(e) => warn!(
+                    ?cpus,
+                    "Could not pin the {name} thread, leaving it unpinned: {e}"
+                ),
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (group, priority, cpus, report);
+}
+
+/// Scheduler class for a `policy` name; None for "off".
+#[cfg(target_os = "linux")]
+fn sched_policy(name: &str) -> Option<libc::c_int> {
+    match name {
+        "fifo" => Some(libc::SCHED_FIFO),
+        "rr" => Some(libc::SCHED_RR),
+        _ => None,
+    }
+}
+
+/// Switch the calling thread to `policy` at `priority`, or at the
+/// RLIMIT_RTPRIO cap if that is all an unprivileged agent may have.
+/// Returns the priority granted.
+#[cfg(target_os = "linux")]
+fn set_realtime(policy: libc::c_int, priority: u8) -> std::io::Result<u8> {
+    let set = |priority: u8| {
+        let param = libc::sched_param {
+            sched_priority: priority.into(),
+        };
+        // 0 = the calling thread
+        if unsafe { libc::sched_setscheduler(0, policy, &param) } == 0 {
+            Ok(priority)
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    };
+    match set(priority) {
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if unsafe { libc::getrlimit(libc::RLIMIT_RTPRIO, &mut limit) } != 0 {
+                return Err(e);
+            }
+            match fallback_priority(priority, limit.rlim_cur) {
+                Some(capped) => set(capped),
+                None => Err(e),
+            }
+        }
+        result => result,
+    }
+}
+
+/// Priority to retry with after EPERM: the RLIMIT_RTPRIO soft limit when
+/// it allows real-time at all but less than requested.
+fn fallback_priority(requested: u8, rtprio_limit: u64) -> Option<u8> {
+    (rtprio_limit > 0 && rtprio_limit < u64::from(requested)).then_some(rtprio_limit as u8)
+}
+
+/// Restrict the calling thread to `cpus`. CPUs that don't exist are
+/// ignored by the kernel as long as one of them does.
+#[cfg(target_os = "linux")]
+fn pin(cpus: &[usize]) -> std::io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus.iter().filter(|&&cpu| cpu < libc::CPU_SETSIZE as usize) {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // 0 = the calling thread
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Parse a CPU list like taskset's: "2,3,8-11".
+pub(crate) fn parse_cpu_list(list: &str) -> anyhow::Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.trim().parse().context("Invalid CPU number")?;
+                let last: usize = last.trim().parse().context("Invalid CPU number")?;
+                anyhow::ensure!(first <= last, "Invalid CPU range {part}");
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(part.parse().context("Invalid CPU number")?),
+        }
+    }
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_lists_take_ranges() {
+        assert_eq!(parse_cpu_list("2,3").unwrap(), [2, 3]);
+        assert_eq!(parse_cpu_list("0, 4-6").unwrap(), [0, 4, 5, 6]);
+        assert!(parse_cpu_list("").unwrap().is_empty());
+        assert!(parse_cpu_list("6-4").is_err());
+        assert!(parse_cpu_list("two").is_err());
+    }
+
+    #[test]
+    fn rtprio_limit_caps_the_fallback() {
+        // Unprivileged with a limits.conf rtprio grant
+        assert_eq!(fallback_priority(50, 20), Some(20));
+        // No grant: normal scheduling
+        assert_eq!(fallback_priority(50, 0), None);
+        // The limit was not what refused it
+        assert_eq!(fallback_priority(50, 50), None);
+        assert_eq!(fallback_priority(50, 99), None);
+    }
+}
@@ -0,0 +1,244 @@
+//! The second virtual monitor, for browsers on clients with two monitors.
+//! It is a region of the screen right of the first monitor (see
+//! `display::set_display_layout`), streamed apart from it: a thread of its
+//! own captures the region, encodes it with an encoder of its own and
+//! sends the frames flagged `FLAG_SECOND_SCREEN`, which the browser shows
+//! in a window of their own.
+
+use crate::capture::{self, Area, ScreenCapture};
+use crate::display::{self, DisplayLayout};
+use crate::encoder::{EncodedOutput, Encoder, EncoderSettings};
+use crate::h264;
+use crate::signaling::WsSender;
+
+use anyhow::Context;
+use beam_protocol::{QualityPreset, VideoFrameHeader};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+/// How the second monitor is encoded: as the first one is
+pub struct StreamSettings<'a> {
+    pub framerate: u32,
+    pub bitrate: u32,
+    pub encoder: &'a EncoderSettings,
+    pub quality: QualityPreset,
+}
+
+/// The second monitor's stream. Dropping it stops the stream; the monitor
+/// stays in the layout until `lay_out` takes it away.
+pub struct SecondScreen {
+    area: Area,
+    stop: Arc<AtomicBool>,
+    force_keyframe: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SecondScreen {
+    /// Stream `area` of the screen. The capture and the encoder are set up
+    /// before this returns, so a monitor that can't be streamed is an error.
+    fn start(
+        x_display: &str,
+        area: Area,
+        settings: &StreamSettings<'_>,
+        ws_tx: WsSender,
+    ) -> anyhow::Result<Self> {
+        let capture = ScreenCapture::with_area(x_display, area)
+            .context("Failed to capture the second screen")?;
+        let mut encoder = Encoder::with_encoder_preference(
+            area.width,
+            area.height,
+            None,
+            settings.framerate,
+            settings.bitrate,
+            settings.encoder,
+            settings.quality,
+        )
+        .context("Failed to create the second screen's encoder")?;
+        let output = encoder
+            .take_output()
+            .context("Second screen encoder has no output")?;
+        info!(?area, encoder = encoder.name(), "Second screen attached");
+
+        let stop = Arc::new(AtomicBool::new(false));
+        // The browser's decoder starts at an IDR
+        let force_keyframe = Arc::new(AtomicBool::new(true));
+        let stream = Stream {
+            capture,
+            encoder,
+            output,
+            ws_tx,
+            frame_interval: Duration::from_secs(1) / settings.framerate.max(1),
+        };
+        let thread = std::thread::Builder::new()
+            .name("second-screen".into())
+            .spawn({
+                let stop = Arc::clone(&stop);
+                let force_keyframe = Arc::clone(&force_keyframe);
+                move || stream.run(&stop, &force_keyframe)
+            })
+            .context("Failed to spawn the second screen thread")?;
+        Ok(Self {
+            area,
+            stop,
+            force_keyframe,
+            thread: Some(thread),
+        })
+    }
+
+    /// Where the monitor is on the screen.
+    pub fn area(&self) -> Area {
+        self.area
+    }
+
+    /// Make the next frame an IDR, for a browser (re)starting its decoder.
+    pub fn force_keyframe(&self) {
+        self.force_keyframe.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the stream stopped on its own, after an encoder error.
+    pub fn has_stopped(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+}
+
+impl Drop for SecondScreen {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        info!(area = ?self.area, "Second screen stream stopped");
+    }
+}
+
+/// Lay the screen out with a `size` second monitor right of the first,
+/// `first` (None detaches it), and start streaming it. `layout` is left
+/// as the display was: without a second monitor if it couldn't be added
+/// or streamed.
+pub fn lay_out(
+    x_display: &str,
+    first: (u32, u32),
+    size: Option<(u32, u32)>,
+    layout: &mut DisplayLayout,
+    settings: &StreamSettings<'_>,
+    ws_tx: &WsSender,
+) -> Option<SecondScreen> {
+    let wanted = DisplayLayout {
+        second_screen: size,
+        ..layout.clone()
+    };
+    let result = display::set_display_layout(x_display, first.0, first.1, &wanted).and_then(|()| {
+        *layout = wanted;
+        let Some((width, height)) = size else {
+            return Ok(None);
+        };
+        let area = Area {
+            x: first.0,
+            y: 0,
+            width,
+            height,
+        };
+        SecondScreen::start(x_display, area, settings, ws_tx.clone()).map(Some)
+    });
+    match result {
+        Ok(screen) => screen,
+        Err(e) => {
+            warn!("Failed to attach the second screen: {e:#}");
+            if layout.second_screen.is_some() {
+                layout.second_screen = None;
+                if let Err(e) = display::set_display_layout(x_display, first.0, first.1, layout) {
+                    warn!("Failed to detach the second screen: {e:#}");
+                }
+            }
+            None
+        }
+    }
+}
+
+/// What the stream's thread owns
+struct Stream {
+    capture: ScreenCapture,
+    encoder: Encoder,
+    output: mpsc::Receiver<EncodedOutput>,
+    ws_tx: WsSender,
+    frame_interval: Duration,
+}
+
+impl Stream {
+    fn run(mut self, stop: &AtomicBool, force_keyframe: &AtomicBool) {
+        let start = Instant::now();
+        let (width, height) = (self.capture.width() as u16, self.capture.height() as u16);
+        // Frames are sent from an IDR on: the first, and the next after one
+        // was dropped
+        let mut waiting_for_idr = true;
+        let mut chroma_444 = false;
+        let mut next_frame = start;
+        while !stop.load(Ordering::Relaxed) {
+            if force_keyframe.swap(false, Ordering::Relaxed) {
+                self.encoder.force_keyframe();
+            }
+            if self.encoder.has_error() {
+                warn!("Second screen encoder failed, stopping its stream");
+                return;
+            }
+            // With the cursor drawn in: the browser draws its own over the
+            // first monitor only
+            match self.capture.capture_frame(true) {
+                Ok(frame) => {
+                    let dirty = self.capture.take_dirty_region();
+                    let pts = start.elapsed().as_nanos() as u64;
+                    if let Err(e) = self.encoder.encode_frame(frame, pts, dirty) {
+                        warn!("Second screen encode error: {e:#}");
+                        return;
+                    }
+                }
+                // The first monitor's capture reports the display lost
+                Err(e) if capture::is_connection_lost(&e) => return,
+                Err(e) => debug!("Second screen capture failed: {e:#}"),
+            }
+
+            while let Ok(encoded) = self.output.try_recv() {
+                let keyframe = h264::h264_contains_idr(&encoded.data);
+                if keyframe && let Some(high_444) = h264::sps_is_high_444(&encoded.data) {
+                    chroma_444 = high_444;
+                }
+                if waiting_for_idr && !keyframe {
+                    continue;
+                }
+                waiting_for_idr = false;
+                let header = VideoFrameHeader::video(
+                    width,
+                    height,
+                    encoded.pts.unwrap_or_default() / 1000,
+                    encoded.data.len() as u32,
+                    keyframe,
+                )
+                .with_chroma_444(chroma_444)
+                .on_second_screen();
+                let frame = header.serialize_with_payload(&encoded.data);
+                match self.ws_tx.try_send(Message::Binary(frame.into())) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        debug!("Dropping a second screen frame (WS outbox full)");
+                        waiting_for_idr = true;
+                        self.encoder.force_keyframe();
+                    }
+                    Err(TrySendError::Closed(_)) => return,
+                }
+            }
+
+            next_frame += self.frame_interval;
+            let now = Instant::now();
+            match next_frame.checked_duration_since(now) {
+                Some(wait) => std::thread::sleep(wait),
+                None => next_frame = now,
+            }
+        }
+    }
+}
@@ -1,18 +1,40 @@
 use crate::CaptureCommand;
+use crate::chaos::Chaos;
+use crate::input_pacer::InputSink;
+use crate::webcam::WebcamCommand;
 
+use std::future::Future;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use anyhow::Context;
-use beam_protocol::{AgentCommand, InputEvent};
+use beam_cli::http::{Client, ServerUrl};
+use beam_protocol::{
+    AgentCommand, AgentHandshake, AgentInfo, AgentTicket, CAP_INPUT_TIMESTAMPS, CAP_LOCK_SCREEN,
+    CAP_NOTIFY, CAP_UPGRADE, Hello, InputEvent, PROTOCOL_VERSION, RELEASE, SignalingMessage,
+    TimedInput,
+};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
 use tokio::sync::mpsc;
-use tracing::{info, warn};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+/// Wait before the first reconnect
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+/// Longest wait between reconnects
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How long the binary of an upgrade gets to print its version
+const UPGRADE_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Type alias for the shared WebSocket sender.
 /// Both signaling (text JSON) and video/audio (binary frames) use this.
-pub(crate) type WsSender = mpsc::Sender<tokio_tungstenite::tungstenite::Message>;
+pub(crate) type WsSender = mpsc::Sender<Message>;
+
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
 
 /// Shared context for signaling WebSocket connection.
 pub(crate) struct SignalingCtx<'a> {
@@ -21,41 +43,102 @@ pub(crate) struct SignalingCtx<'a> {
     pub agent_token: Option<&'a str>,
     pub tls_cert_path: Option<&'a str>,
     pub force_keyframe: Arc<AtomicBool>,
-    pub input_callback: Arc<dyn Fn(InputEvent) + Send + Sync>,
+    pub input: InputSink,
     pub capture_cmd_tx: &'a std::sync::mpsc::Sender<CaptureCommand>,
+    pub webcam_tx: &'a std::sync::mpsc::SyncSender<WebcamCommand>,
     pub tab_backgrounded: Arc<AtomicBool>,
+    /// X display, for locking and unlocking the desktop
+    pub x_display: &'a str,
+    /// Attached to someone's own display, which is never locked from here
+    pub assist: bool,
+    /// Injected faults; `drop-signaling` ends the connection
+    pub chaos: &'a Chaos,
+    /// Encoder, capture and stream, reported to the server on connect
+    pub agent_info: &'a std::sync::Mutex<AgentInfo>,
+    /// Agent binary the server upgraded to, executed in place of this
+    /// one once the agent has shut down
+    pub upgrade: &'a std::sync::Mutex<Option<String>>,
 }
 
 /// Run the signaling WebSocket connection with reconnect.
 ///
 /// `ws_outbox_rx` receives outgoing WS messages from video/audio/clipboard/cursor tasks.
-/// Incoming WS text messages (AgentCommand) are dispatched to the input callback.
+/// Incoming WS text messages (AgentCommand) are dispatched to the input sink.
 pub(crate) async fn run_signaling(
     ctx: &SignalingCtx<'_>,
-    ws_outbox_rx: &mut mpsc::Receiver<tokio_tungstenite::tungstenite::Message>,
+    ws_outbox_rx: &mut mpsc::Receiver<Message>,
 ) {
     if ctx.server_url.is_empty() {
         info!("No server URL provided, sleeping forever");
         std::future::pending::<()>().await;
         return;
     }
+    reconnect_loop(ctx, ws_outbox_rx, || connect(ctx)).await;
+}
+
+/// Exponential backoff between reconnects, back to the start once a
+/// connection is made.
+struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            next: INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl Backoff {
+    /// How long to wait before the next attempt.
+    fn next(&mut self) -> Duration {
+        let wait = self.next;
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+        wait
+    }
 
-    // Connect to WebSocket with exponential backoff retry
-    let mut backoff = Duration::from_secs(2);
-    let max_backoff = Duration::from_secs(60);
+    fn reset(&mut self) {
+        self.next = INITIAL_BACKOFF;
+    }
+}
+
+/// Connect with `connect` and handle the connection until the server shuts
+/// the agent down or closes cleanly, reconnecting with backoff after errors.
+async fn reconnect_loop<F, Fut, Tx, Rx>(
+    ctx: &SignalingCtx<'_>,
+    ws_outbox_rx: &mut mpsc::Receiver<Message>,
+    mut connect: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<(Tx, Rx)>>,
+    Tx: Sink<Message, Error = WsError> + Unpin,
+    Rx: Stream<Item = Result<Message, WsError>> + Unpin,
+{
+    let mut backoff = Backoff::default();
     loop {
         info!(url = ctx.server_url, "Connecting to signaling server");
 
-        match connect_and_handle(ctx, ws_outbox_rx).await {
+        let result = match connect().await {
+            Ok((ws_tx, ws_rx)) => {
+                info!("Connected to signaling server");
+                // A connection that was up and then failed starts over: a
+                // server restart shouldn't inherit the wait of an earlier outage
+                backoff.reset();
+                handle_connection(ctx, ws_tx, ws_rx, ws_outbox_rx).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
             Ok(()) => {
                 info!("Signaling connection closed cleanly");
                 break;
             }
             Err(e) => {
                 warn!("Signaling connection error: {e:#}");
-                info!("Reconnecting in {} seconds...", backoff.as_secs());
-                tokio::time::sleep(backoff).await;
-                backoff = (backoff * 2).min(max_backoff);
+                let wait = backoff.next();
+                info!("Reconnecting in {} seconds...", wait.as_secs());
+                tokio::time::sleep(wait).await;
             }
         }
     }
@@ -98,20 +181,45 @@ fn build_tls_connector(tls_cert_path: Option<&str>) -> tokio_tungstenite::Connec
     tokio_tungstenite::Connector::Rustls(Arc::new(tls_config))
 }
 
-async fn connect_and_handle(
-    ctx: &SignalingCtx<'_>,
-    ws_outbox_rx: &mut mpsc::Receiver<tokio_tungstenite::tungstenite::Message>,
-) -> anyhow::Result<()> {
-    use futures_util::{SinkExt, StreamExt};
-    use tokio_tungstenite::tungstenite::Message;
+/// Trade the long-lived agent token for a ticket good for one WebSocket
+/// connection. Done again before every reconnect.
+async fn handshake(ctx: &SignalingCtx<'_>, token: &str) -> anyhow::Result<AgentTicket> {
+    let server = ServerUrl::from_ws(ctx.server_url)?;
+    let tls = beam_cli::http::tls_config(ctx.tls_cert_path.map(std::path::Path::new), false)?;
+    let client = Client::new(server, tls, Some(token.to_string()));
+    let body = serde_json::to_value(AgentHandshake {
+        session_id: ctx.session_id,
+    })?;
+    let response =
+        tokio::task::spawn_blocking(move || client.post("/api/agent/handshake", Some(&body)))
+            .await
+            .context("Agent handshake task failed")??;
+    if !response.is_success() {
+        anyhow::bail!("Agent handshake refused: {}", response.error_message());
+    }
+    let ticket: AgentTicket =
+        serde_json::from_slice(&response.body).context("Invalid agent ticket")?;
+    debug!(expires_in = ticket.expires_in, "Got agent ticket");
+    Ok(ticket)
+}
 
+/// Open the WebSocket to the server, split into its sending and receiving
+/// halves.
+async fn connect(
+    ctx: &SignalingCtx<'_>,
+) -> anyhow::Result<(SplitSink<WsStream, Message>, SplitStream<WsStream>)> {
+    // The long-lived token stays in a header; the URL, which proxies log,
+    // only ever carries a single-use ticket
     let url = match ctx.agent_token {
-        Some(token) => format!(
-            "{}/ws/agent/{}?token={}",
-            ctx.server_url,
-            ctx.session_id,
-            urlencoding::encode(token)
-        ),
+        Some(token) => {
+            let ticket = handshake(ctx, token).await?;
+            format!(
+                "{}/ws/agent/{}?token={}",
+                ctx.server_url,
+                ctx.session_id,
+                urlencoding::encode(&ticket.token)
+            )
+        }
         None => format!("{}/ws/agent/{}", ctx.server_url, ctx.session_id),
     };
 
@@ -126,12 +234,37 @@ async fn connect_and_handle(
     )
     .await
     .context("WebSocket connection failed")?;
+    Ok(ws_stream.split())
+}
 
-    info!("Connected to signaling server");
-    let (mut ws_tx, mut ws_rx) = ws_stream.split();
+/// Announce the agent, then dispatch server commands and forward the
+/// outbox until the server shuts the agent down or the connection ends.
+async fn handle_connection(
+    ctx: &SignalingCtx<'_>,
+    mut ws_tx: impl Sink<Message, Error = WsError> + Unpin,
+    mut ws_rx: impl Stream<Item = Result<Message, WsError>> + Unpin,
+    ws_outbox_rx: &mut mpsc::Receiver<Message>,
+) -> anyhow::Result<()> {
+    // Announce our protocol version; the server answers with its own. An
+    // assisted session can't upgrade: the new binary would ask for consent.
+    let capabilities: &[&str] = if ctx.assist {
+        &[CAP_INPUT_TIMESTAMPS, CAP_NOTIFY]
+    } else {
+        &[
+            CAP_LOCK_SCREEN,
+            CAP_INPUT_TIMESTAMPS,
+            CAP_NOTIFY,
+            CAP_UPGRADE,
+        ]
+    };
+    let hello = serde_json::to_string(&Hello::new(capabilities))?;
+    ws_tx.send(Message::Text(hello.into())).await?;
+    let info = serde_json::to_string(&*ctx.agent_info.lock().unwrap_or_else(|e| e.into_inner()))?;
+    ws_tx.send(Message::Text(info.into())).await?;
+    let mut server_version: Option<u32> = None;
 
-    // On reconnect: reset encoder for fresh IDR, clear backgrounded state
-    let _ = ctx.capture_cmd_tx.send(CaptureCommand::ResetEncoder);
+    // On reconnect: ask for a fresh IDR, clear backgrounded state
+    let _ = ctx.capture_cmd_tx.send(CaptureCommand::Reconnected);
     ctx.force_keyframe.store(true, Ordering::Relaxed);
     ctx.tab_backgrounded.store(false, Ordering::Relaxed);
 
@@ -141,19 +274,99 @@ async fn connect_and_handle(
             msg = ws_rx.next() => {
                 match msg {
                     Some(Ok(Message::Text(text))) => {
+                        if text.starts_with(r#"{"t":"hello""#) {
+                            match serde_json::from_str::<Hello>(&text) {
+                                Ok(hello) => {
+                                    if hello.version != PROTOCOL_VERSION {
+                                        info!(server = hello.version, agent = PROTOCOL_VERSION, "Server speaks a different protocol version");
+                                    }
+                                    if let Some(release) = hello.release.as_deref().filter(|r| *r != RELEASE) {
+                                        info!(server = release, agent = RELEASE, "Server runs a different release");
+                                    }
+                                    server_version = Some(hello.version);
+                                }
+                                Err(e) => warn!("Invalid hello from server: {e}"),
+                            }
+                            continue;
+                        }
+                        // Sent before the server closes on an agent it won't
+                        // work with, such as one of an incompatible release
+                        if text.starts_with(r#"{"type":"error""#) {
+                            let message = match serde_json::from_str::<SignalingMessage>(&text) {
+                                Ok(SignalingMessage::Error { message }) => message,
+                                _ => text.to_string(),
+                            };
+                            anyhow::bail!("Server refused the agent: {message}");
+                        }
                         match serde_json::from_str::<AgentCommand>(&text) {
                             Ok(AgentCommand::Input(event)) => {
-                                (ctx.input_callback)(event);
+                                ctx.input.send(None, event);
+                            }
+                            Ok(AgentCommand::TimedInput(TimedInput { ts, ev })) => {
+                                ctx.input.send(Some(ts), ev);
+                            }
+                            Ok(AgentCommand::SetAudio { enabled }) => {
+                                // Same path as the browser's own toggle
+                                ctx.input.send(None, InputEvent::AudioEnabled { enabled });
+                            }
+                            Ok(AgentCommand::SetBitrateCap { kbps }) => {
+                                let _ = ctx.capture_cmd_tx.send(CaptureCommand::SetBitrateCap(kbps));
+                            }
+                            Ok(AgentCommand::LockScreen | AgentCommand::UnlockScreen) if ctx.assist => {
+                                warn!("Ignoring screen lock command on an assisted display");
                             }
+                            Ok(AgentCommand::LockScreen) => {
+                                let x_display = ctx.x_display.to_string();
+                                std::thread::spawn(move || {
+                                    if let Err(e) = crate::display::lock_screen(&x_display) {
+                                        warn!("Failed to lock desktop: {e:#}");
+                                    }
+                                });
+                            }
+                            Ok(AgentCommand::UnlockScreen) => {
+                                let x_display = ctx.x_display.to_string();
+                                std::thread::spawn(move || {
+                                    if let Err(e) = crate::display::unlock_screen(&x_display) {
+                                        warn!("Failed to unlock desktop: {e:#}");
+                                    }
+                                });
+                            }
+                            Ok(AgentCommand::Notify { summary, body }) => {
+                                let x_display = ctx.x_display.to_string();
+                                std::thread::spawn(move || {
+                                    if let Err(e) = crate::display::notify(&x_display, &summary, &body) {
+                                        warn!("Failed to show notification: {e:#}");
+                                    }
+                                });
+                            }
+                            Ok(AgentCommand::Upgrade { path }) => match check_agent_binary(&path).await {
+                                Ok(release) => {
+                                    info!(%path, release, "Received upgrade command");
+                                    *ctx.upgrade.lock().unwrap_or_else(|e| e.into_inner()) = Some(path);
+                                    return Ok(());
+                                }
+                                Err(e) => warn!("Not upgrading: {e:#}"),
+                            },
                             Ok(AgentCommand::Shutdown) => {
                                 info!("Received shutdown command");
                                 return Ok(());
                             }
+                            // A newer server may send commands this agent
+                            // predates; they are optional by design
+                            Err(e) if server_version.is_some_and(|v| v > PROTOCOL_VERSION) => {
+                                debug!("Ignoring unknown command from newer server: {e}");
+                            }
                             Err(e) => {
                                 warn!("Invalid message from server: {e}");
                             }
                         }
                     }
+                    Some(Ok(Message::Binary(data))) => {
+                        // Browser webcam frames (the server only relays those).
+                        // Dropped when the decoder is behind; the next
+                        // keyframe repairs the picture.
+                        let _ = ctx.webcam_tx.try_send(WebcamCommand::Frame(data.to_vec()));
+                    }
                     Some(Ok(Message::Close(_))) | None => {
                         return Ok(());
                     }
@@ -167,6 +380,336 @@ async fn connect_and_handle(
             Some(msg) = ws_outbox_rx.recv() => {
                 ws_tx.send(msg).await?;
             }
+            // Dropped without a close frame, as a network failure would
+            _ = ctx.chaos.signaling_dropped() => {
+                anyhow::bail!("Chaos: signaling connection dropped");
+            }
+        }
+    }
+}
+
+/// The release of the agent binary at `path`, once it has shown it runs
+/// here: the session is left to it, and one that can't start would end it.
+async fn check_agent_binary(path: &str) -> anyhow::Result<String> {
+    let output = tokio::time::timeout(
+        UPGRADE_CHECK_TIMEOUT,
+        tokio::process::Command::new(path)
+            .arg("--version")
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .with_context(|| format!("{path} --version did not finish"))?
+    .with_context(|| format!("Failed to run {path}"))?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    match version.trim().strip_prefix("beam-agent ") {
+        Some(release) if output.status.success() => Ok(release.to_string()),
+        _ => anyhow::bail!("{path} is not a beam-agent binary"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use tokio::time::Instant;
+
+    type MockTx = Pin<Box<dyn Sink<Message, Error = WsError> + Send>>;
+    type MockRx = futures_util::stream::Iter<std::vec::IntoIter<Result<Message, WsError>>>;
+
+    /// What a test session owns and a `SignalingCtx` borrows.
+    struct Agent {
+        inputs: Arc<Mutex<Vec<InputEvent>>>,
+        capture_tx: std::sync::mpsc::Sender<CaptureCommand>,
+        capture_rx: std::sync::mpsc::Receiver<CaptureCommand>,
+        webcam_tx: std::sync::mpsc::SyncSender<WebcamCommand>,
+        _webcam_rx: std::sync::mpsc::Receiver<WebcamCommand>,
+        force_keyframe: Arc<AtomicBool>,
+        tab_backgrounded: Arc<AtomicBool>,
+        chaos: Chaos,
+        agent_info: Mutex<AgentInfo>,
+        upgrade: Mutex<Option<String>>,
+    }
+
+    impl Agent {
+        fn new() -> Self {
+            let (capture_tx, capture_rx) = std::sync::mpsc::channel();
+            let (webcam_tx, _webcam_rx) = std::sync::mpsc::sync_channel(4);
+            Self {
+                inputs: Arc::default(),
+                capture_tx,
+                capture_rx,
+                webcam_tx,
+                _webcam_rx,
+                force_keyframe: Arc::default(),
+                tab_backgrounded: Arc::default(),
+                chaos: Chaos::default(),
+                agent_info: Mutex::new(AgentInfo {
+                    version: "0.0.0".into(),
+                    encoder: "x264enc".into(),
+                    fallback: false,
+                    capture: "x11-shm".into(),
+                    width: 1280,
+                    height: 720,
+                    framerate: 60,
+                }),
+                upgrade: Mutex::default(),
+            }
+        }
+
+        fn ctx(&self) -> SignalingCtx<'_> {
+            let inputs = Arc::clone(&self.inputs);
+            SignalingCtx {
+                server_url: "ws://beam.test",
+                session_id: Uuid::nil(),
+                agent_token: None,
+                tls_cert_path: None,
+                force_keyframe: Arc::clone(&self.force_keyframe),
+                input: InputSink::Direct(Arc::new(move |event| inputs.lock().unwrap().push(event))),
+                capture_cmd_tx: &self.capture_tx,
+                webcam_tx: &self.webcam_tx,
+                tab_backgrounded: Arc::clone(&self.tab_backgrounded),
+                x_display: ":99",
+                assist: true,
+                chaos: &self.chaos,
+                agent_info: &self.agent_info,
+                upgrade: &self.upgrade,
+            }
+        }
+
+        fn inputs(&self) -> Vec<InputEvent> {
+            self.inputs.lock().unwrap().clone()
         }
+
+        /// Connections the agent announced to the capture thread.
+        fn reconnects(&self) -> usize {
+            self.capture_rx
+                .try_iter()
+                .filter(|cmd| matches!(cmd, CaptureCommand::Reconnected))
+                .count()
+        }
+    }
+
+    fn command(cmd: &AgentCommand) -> Message {
+        Message::Text(serde_json::to_string(cmd).unwrap().into())
+    }
+
+    fn key(c: u16) -> AgentCommand {
+        AgentCommand::Input(InputEvent::Key { c, d: true })
+    }
+
+    /// A connection where the server sends `server`, then closes; what
+    /// the agent sends lands in the returned log.
+    fn scripted(
+        server: Vec<Result<Message, WsError>>,
+    ) -> (MockTx, MockRx, Arc<Mutex<Vec<Message>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let tx = futures_util::sink::unfold(Arc::clone(&sent), |sent, msg: Message| async move {
+            sent.lock().unwrap().push(msg);
+            Ok::<_, WsError>(sent)
+        });
+        (Box::pin(tx), futures_util::stream::iter(server), sent)
+    }
+
+    fn outbox() -> (mpsc::Sender<Message>, mpsc::Receiver<Message>) {
+        mpsc::channel(8)
+    }
+
+    #[tokio::test]
+    async fn announces_itself_before_forwarding_media() {
+        let agent = Agent::new();
+        agent.tab_backgrounded.store(true, Ordering::Relaxed);
+        let (outbox_tx, mut outbox_rx) = outbox();
+        // Queued while disconnected
+        outbox_tx
+            .send(Message::Binary(vec![1, 2, 3].into()))
+            .await
+            .unwrap();
+        let (tx, rx, sent) = scripted(vec![]);
+        handle_connection(&agent.ctx(), tx, rx, &mut outbox_rx)
+            .await
+            .unwrap();
+
+        let sent = sent.lock().unwrap();
+        let Some(Message::Text(hello)) = sent.first() else {
+            panic!("expected hello first, got {sent:?}");
+        };
+        let hello: Hello = serde_json::from_str(hello).unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        // Assisted displays are never locked from here
+        assert!(!hello.supports(CAP_LOCK_SCREEN));
+        let Some(Message::Text(info)) = sent.get(1) else {
+            panic!("expected agent info after hello, got {sent:?}");
+        };
+        let info: AgentInfo = serde_json::from_str(info).unwrap();
+        assert_eq!(info, *agent.agent_info.lock().unwrap());
+        assert!(agent.force_keyframe.load(Ordering::Relaxed));
+        assert!(!agent.tab_backgrounded.load(Ordering::Relaxed));
+        assert_eq!(agent.reconnects(), 1);
+    }
+
+    #[tokio::test]
+    async fn input_keeps_server_order() {
+        let agent = Agent::new();
+        let (_outbox_tx, mut outbox_rx) = outbox();
+        let (tx, rx, _) = scripted(vec![
+            Ok(Message::Text(
+                serde_json::to_string(&Hello::new(&[])).unwrap().into(),
+            )),
+            Ok(command(&key(1))),
+            Ok(command(&AgentCommand::TimedInput(TimedInput {
+                ts: 10.0,
+                ev: InputEvent::Key { c: 2, d: true },
+            }))),
+            Ok(Message::Text("{\"cmd\":\"not_a_command\"}".into())),
+            Ok(command(&AgentCommand::SetAudio { enabled: false })),
+            Ok(command(&key(3))),
+        ]);
+        handle_connection(&agent.ctx(), tx, rx, &mut outbox_rx)
+            .await
+            .unwrap();
+
+        let keys: Vec<_> = agent
+            .inputs()
+            .into_iter()
+            .map(|event| match event {
+                InputEvent::Key { c, .. } => c.to_string(),
+                InputEvent::AudioEnabled { enabled } => format!("audio {enabled}"),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        // A bad message is skipped, not the end of the connection
+        assert_eq!(keys, ["1", "2", "audio false", "3"]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_handling_at_once() {
+        let agent = Agent::new();
+        let (_outbox_tx, mut outbox_rx) = outbox();
+        let (tx, rx, _) = scripted(vec![
+            Ok(command(&key(1))),
+            Ok(command(&AgentCommand::Shutdown)),
+            Ok(command(&key(2))),
+            Err(WsError::ConnectionClosed),
+        ]);
+        handle_connection(&agent.ctx(), tx, rx, &mut outbox_rx)
+            .await
+            .unwrap();
+        assert!(matches!(agent.inputs()[..], [InputEvent::Key { c: 1, .. }]));
+    }
+
+    #[tokio::test]
+    async fn a_broken_connection_is_an_error() {
+        let agent = Agent::new();
+        let (_outbox_tx, mut outbox_rx) = outbox();
+        let (tx, rx, _) = scripted(vec![Ok(command(&key(1))), Err(WsError::ConnectionClosed)]);
+        assert!(
+            handle_connection(&agent.ctx(), tx, rx, &mut outbox_rx)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn a_refusal_ends_the_connection_with_its_reason() {
+        let agent = Agent::new();
+        let (_outbox_tx, mut outbox_rx) = outbox();
+        let refusal = SignalingMessage::Error {
+            message: "Agent 1.0.0 is incompatible with server 0.2.9".into(),
+        };
+        let (tx, rx, _) = scripted(vec![
+            Ok(Message::Text(
+                serde_json::to_string(&refusal).unwrap().into(),
+            )),
+            Ok(command(&key(1))),
+        ]);
+        let err = handle_connection(&agent.ctx(), tx, rx, &mut outbox_rx)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("incompatible with server 0.2.9"));
+        assert!(agent.inputs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn upgrades_only_to_an_agent_binary() {
+        let dir = std::env::temp_dir().join(format!("beam-upgrade-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write_script = |name: &str, version: &str| {
+            use std::os::unix::fs::PermissionsExt;
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\necho '{version}'\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let agent_binary = write_script("beam-agent", "beam-agent 9.9.9");
+        let other_binary = write_script("other", "other 1.0");
+        let upgrade = |path: &str| {
+            command(&AgentCommand::Upgrade {
+                path: path.to_string(),
+            })
+        };
+
+        let agent = Agent::new();
+        let (_outbox_tx, mut outbox_rx) = outbox();
+        let (tx, rx, _) = scripted(vec![
+            Ok(upgrade("/nonexistent/beam-agent")),
+            Ok(upgrade(&other_binary)),
+            Ok(command(&key(1))),
+            Ok(upgrade(&agent_binary)),
+            Ok(command(&key(2))),
+        ]);
+        handle_connection(&agent.ctx(), tx, rx, &mut outbox_rx)
+            .await
+            .unwrap();
+        // Binaries that aren't the agent's are refused and the session
+        // goes on; the agent's ends the connection for the upgrade
+        assert!(matches!(agent.inputs()[..], [InputEvent::Key { c: 1, .. }]));
+        assert_eq!(*agent.upgrade.lock().unwrap(), Some(agent_binary));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let mut backoff = Backoff::default();
+        let waits: Vec<_> = (0..7).map(|_| backoff.next().as_secs()).collect();
+        assert_eq!(waits, [2, 4, 8, 16, 32, 60, 60]);
+        backoff.reset();
+        assert_eq!(backoff.next(), INITIAL_BACKOFF);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_starts_over_after_a_connection() {
+        let agent = Agent::new();
+        let (_outbox_tx, mut outbox_rx) = outbox();
+        let refused = || Err(anyhow::anyhow!("connection refused"));
+        let connected = |server| {
+            let (tx, rx, _) = scripted(server);
+            Ok((tx, rx))
+        };
+        let mut script: VecDeque<anyhow::Result<(MockTx, MockRx)>> = VecDeque::from([
+            refused(),
+            refused(),
+            refused(),
+            connected(vec![Ok(command(&key(1))), Err(WsError::ConnectionClosed)]),
+            refused(),
+            connected(vec![Ok(command(&AgentCommand::Shutdown))]),
+        ]);
+        let mut attempts = Vec::new();
+        reconnect_loop(&agent.ctx(), &mut outbox_rx, || {
+            attempts.push(Instant::now());
+            let next = script.pop_front().expect("reconnected after shutdown");
+            async move { next }
+        })
+        .await;
+
+        let waits: Vec<_> = attempts
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).as_secs())
+            .collect();
+        assert_eq!(waits, [2, 4, 8, 2, 4]);
+        assert!(script.is_empty());
+        assert_eq!(agent.reconnects(), 2);
     }
 }
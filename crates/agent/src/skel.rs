@@ -0,0 +1,257 @@
+//! Beam's desktop skeleton: XFCE and GTK settings tuned for a remote
+//! display (no compositor, animations or menu delays), and autostart
+//! entries that fail or waste CPU in a virtual session masked. Other
+//! desktops get the parts that aren't XFCE's as defaults.
+//!
+//! With `--persist-desktop` the desktop's config home is `~/.config/beam`,
+//! which lasts across sessions: files missing there are seeded from the
+//! skeleton, and ones already there are left as the user made them. Kept
+//! apart from `~/.config`, the tuning doesn't reach the user's other
+//! logins. Without it, or when the home can't be written, the config
+//! home is `/tmp/beam-xfce-<display>`, written afresh every session.
+
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+use beam_protocol::Desktop;
+use tracing::warn;
+
+/// Config home under `$HOME` with `--persist-desktop`
+const PERSISTENT_DIR: &str = ".config/beam";
+
+/// Autostart entries hidden in the session, by file name. XDG lets a
+/// user-level .desktop file override /etc/xdg/autostart/ by name.
+const MASKED_AUTOSTART: [&str; 12] = [
+    "update-notifier.desktop",                     // pkexec error dialogs
+    "polkit-gnome-authentication-agent-1.desktop", // pkexec auth prompts
+    "pulseaudio.desktop",                          // conflicts with our PulseAudio
+    "tracker-miner-fs-3.desktop",                  // file indexer wastes CPU
+    "snap-userd-autostart.desktop",                // snap UI daemon
+    "spice-vdagent.desktop",                       // SPICE agent, not used
+    "ubuntu-advantage-notification.desktop",       // Ubuntu Pro nag
+    "ubuntu-report-on-upgrade.desktop",            // upgrade reporter
+    "gnome-initial-setup-copy-worker.desktop",     // GNOME first-run
+    "gnome-initial-setup-first-login.desktop",     // GNOME first-run
+    "org.gnome.DejaDup.Monitor.desktop",           // backup monitor
+    "org.gnome.Evolution-alarm-notify.desktop",    // calendar alarms
+];
+
+/// The desktop's XDG_CONFIG_HOME for `display_num`, and whether it
+/// persists across sessions.
+pub fn config_home(display_num: u32, persist: bool) -> (PathBuf, bool) {
+    if persist && let Some(home) = std::env::var_os("HOME") {
+        let dir = Path::new(&home).join(PERSISTENT_DIR);
+        match fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+        {
+            Ok(()) if writable(&dir) => return (dir, true),
+            Ok(()) => warn!(dir = %dir.display(), "Desktop config dir is not writable"),
+            Err(e) => warn!(dir = %dir.display(), "Failed to create desktop config dir: {e}"),
+        }
+    }
+    (
+        PathBuf::from(format!("/tmp/beam-xfce-{display_num}")),
+        false,
+    )
+}
+
+fn writable(dir: &Path) -> bool {
+    nix::unistd::access(dir, nix::unistd::AccessFlags::W_OK).is_ok()
+}
+
+/// Write the skeleton into `dir`. With `keep_existing`, files already
+/// there are left alone.
+pub fn seed(dir: &Path, xft_dpi: u32, window_scale: u32, keep_existing: bool) {
+    write(dir, files(xft_dpi, window_scale), keep_existing);
+}
+
+/// Write defaults for a desktop other than XFCE into `dir`, a directory
+/// behind the config home in XDG_CONFIG_DIRS, where the user's own
+/// settings win over them.
+pub fn seed_defaults(dir: &Path, desktop: &Desktop) {
+    let mut files = common_files();
+    if *desktop == Desktop::Kde {
+        // Plasma's animations, as the GTK ones
+        files.push((
+            "kdeglobals".to_string(),
+            "[KDE]\nAnimationDurationFactor=0\n".to_string(),
+        ));
+    }
+    write(dir, files, false);
+}
+
+fn write(dir: &Path, files: Vec<(String, String)>, keep_existing: bool) {
+    for (path, contents) in files {
+        let path = dir.join(path);
+        if keep_existing && path.exists() {
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Err(e) = fs::write(&path, contents) {
+            warn!(path = %path.display(), "Failed to write desktop setting: {e}");
+        }
+    }
+}
+
+/// (path relative to the config home, contents) of each skeleton file
+fn files(xft_dpi: u32, window_scale: u32) -> Vec<(String, String)> {
+    const XFCONF: &str = "xfce4/xfconf/xfce-perchannel-xml";
+    let mut files = vec![
+        // xfwm4: disable compositor and workspace zoom animation
+        (
+            format!("{XFCONF}/xfwm4.xml"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<channel name="xfwm4" version="1.0">
+  <property name="general" type="empty">
+    <property name="use_compositing" type="bool" value="false"/>
+    <property name="zoom_desktop" type="bool" value="false"/>
+    <property name="popup_opacity" type="int" value="100"/>
+    <property name="move_opacity" type="int" value="100"/>
+    <property name="resize_opacity" type="int" value="100"/>
+  </property>
+</channel>
+"#
+            .to_string(),
+        ),
+        // xsettings: disable GTK animations and seed the HiDPI scale
+        (
+            format!("{XFCONF}/xsettings.xml"),
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<channel name="xsettings" version="1.0">
+  <property name="Gtk" type="empty">
+    <property name="MenuPopupDelay" type="int" value="0"/>
+    <property name="MenuPopdownDelay" type="int" value="0"/>
+  </property>
+  <property name="Net" type="empty">
+    <property name="EnableAnimations" type="bool" value="false"/>
+  </property>
+  <property name="Xft" type="empty">
+    <property name="DPI" type="int" value="{xft_dpi}"/>
+  </property>
+  <property name="Gdk" type="empty">
+    <property name="WindowScalingFactor" type="int" value="{window_scale}"/>
+  </property>
+</channel>
+"#
+            ),
+        ),
+        // xfce4-session: no splash screen
+        (
+            format!("{XFCONF}/xfce4-session.xml"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<channel name="xfce4-session" version="1.0">
+  <property name="splash" type="empty">
+    <property name="Engine" type="string" value=""/>
+  </property>
+</channel>
+"#
+            .to_string(),
+        ),
+        // Keyboard shortcuts: Alt+F2 for app finder search
+        (
+            format!("{XFCONF}/xfce4-keyboard-shortcuts.xml"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<channel name="xfce4-keyboard-shortcuts" version="1.0">
+  <property name="commands" type="empty">
+    <property name="custom" type="empty">
+      <property name="&lt;Alt&gt;F2" type="string" value="xfce4-appfinder --collapsed"/>
+    </property>
+  </property>
+</channel>
+"#
+            .to_string(),
+        ),
+        // GTK3 CSS: kill ALL CSS transitions (Greybird theme has 46 × 200ms
+        // transitions on buttons, menus, entries, hover states etc.).
+        // gtk-enable-animations only affects GtkAnimation objects, NOT CSS
+        // transitions — this override is required for instant menu hover.
+        (
+            "gtk-3.0/gtk.css".to_string(),
+            "* { transition-duration: 0s !important; animation-duration: 0s !important; }\n"
+                .to_string(),
+        ),
+    ];
+    files.extend(common_files());
+    files
+}
+
+/// Skeleton files any desktop reads from XDG_CONFIG_DIRS as well as from
+/// the config home
+fn common_files() -> Vec<(String, String)> {
+    let mut files = vec![
+        // GTK3 settings: disable animations, menu delays, cursor blink
+        (
+            "gtk-3.0/settings.ini".to_string(),
+            "[Settings]\n\
+             gtk-enable-animations=false\n\
+             gtk-menu-popup-delay=0\n\
+             gtk-menu-popdown-delay=0\n\
+             gtk-cursor-blink=false\n"
+                .to_string(),
+        ),
+    ];
+    files.extend(MASKED_AUTOSTART.iter().map(|entry| {
+        (
+            format!("autostart/{entry}"),
+            "[Desktop Entry]\nHidden=true\n".to_string(),
+        )
+    }));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeding_keeps_what_the_user_changed() {
+        let dir = std::env::temp_dir().join(format!("beam-skel-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        seed(&dir, 144, 1, true);
+        let xsettings = dir.join("xfce4/xfconf/xfce-perchannel-xml/xsettings.xml");
+        assert!(
+            fs::read_to_string(&xsettings)
+                .unwrap()
+                .contains(r#"name="DPI" type="int" value="144""#)
+        );
+        assert!(dir.join("autostart/pulseaudio.desktop").is_file());
+
+        // The user's own settings survive the next session
+        let css = dir.join("gtk-3.0/gtk.css");
+        fs::write(&css, "/* mine */\n").unwrap();
+        fs::remove_file(dir.join("autostart/pulseaudio.desktop")).unwrap();
+        seed(&dir, 96, 2, true);
+        assert_eq!(fs::read_to_string(&css).unwrap(), "/* mine */\n");
+        assert!(dir.join("autostart/pulseaudio.desktop").is_file());
+        assert!(fs::read_to_string(&xsettings).unwrap().contains("144"));
+
+        // A throwaway config home is rewritten
+        seed(&dir, 96, 2, false);
+        assert_ne!(fs::read_to_string(&css).unwrap(), "/* mine */\n");
+        assert!(
+            fs::read_to_string(&xsettings)
+                .unwrap()
+                .contains(r#"name="WindowScalingFactor" type="int" value="2""#)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn other_desktops_get_no_xfce_settings() {
+        let dir = std::env::temp_dir().join(format!("beam-skel-kde-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        seed_defaults(&dir, &Desktop::Kde);
+        assert!(dir.join("gtk-3.0/settings.ini").is_file());
+        assert!(dir.join("autostart/pulseaudio.desktop").is_file());
+        assert!(dir.join("kdeglobals").is_file());
+        assert!(!dir.join("xfce4").exists());
+        assert!(!dir.join("gtk-3.0/gtk.css").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
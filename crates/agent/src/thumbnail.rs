@@ -0,0 +1,142 @@
+//! Session thumbnails for the admin panel.
+//!
+//! Every `--thumbnail-secs` the capture thread hands a copy of the frame it
+//! just captured to a worker thread, which scales it down to
+//! `THUMBNAIL_WIDTH`, encodes it as JPEG and sends it to the server as a
+//! `Thumbnail`. The capture thread never waits: a frame offered while the
+//! previous one is still being encoded is dropped.
+
+use anyhow::Context;
+use base64::Engine;
+use beam_protocol::Thumbnail;
+use gstreamer as gst;
+use std::sync::mpsc as std_mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, warn};
+
+/// Width of a thumbnail; the height follows the screen's aspect ratio
+pub const THUMBNAIL_WIDTH: u32 = 320;
+
+/// The first thumbnail comes no later than this after startup, so a new
+/// session doesn't sit blank in the admin panel for a whole interval
+const FIRST_THUMBNAIL_AFTER: Duration = Duration::from_secs(5);
+
+struct RawFrame {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+pub struct Thumbnailer {
+    interval: Duration,
+    next: Instant,
+    tx: std_mpsc::SyncSender<RawFrame>,
+}
+
+impl Thumbnailer {
+    /// Start the encoding thread. Thumbnails go out through `ws_tx`.
+    pub fn spawn(interval: Duration, ws_tx: mpsc::Sender<Message>) -> Self {
+        let (tx, rx) = std_mpsc::sync_channel::<RawFrame>(1);
+        std::thread::Builder::new()
+            .name("thumbnail".into())
+            .spawn(move || {
+                for frame in rx {
+                    match encode_jpeg(frame) {
+                        Ok(thumb) => send(&ws_tx, &thumb),
+                        Err(e) => warn!("Failed to encode thumbnail: {e:#}"),
+                    }
+                }
+            })
+            .expect("failed to spawn thumbnail thread");
+        Self {
+            interval,
+            next: Instant::now() + interval.min(FIRST_THUMBNAIL_AFTER),
+            tx,
+        }
+    }
+
+    /// Whether the frame captured at `now` should become a thumbnail.
+    pub fn due(&self, now: Instant) -> bool {
+        now >= self.next
+    }
+
+    /// Queue a copy of a captured BGRx frame for encoding.
+    pub fn offer(&mut self, frame: &[u8], width: u32, height: u32, now: Instant) {
+        self.next = now + self.interval;
+        let frame = RawFrame {
+            data: frame.to_vec(),
+            width,
+            height,
+        };
+        if self.tx.try_send(frame).is_err() {
+            debug!("Thumbnail encoder busy, skipping this one");
+        }
+    }
+}
+
+/// Thumbnail dimensions for a `width` x `height` screen: `THUMBNAIL_WIDTH`
+/// wide (never wider than the screen), even, and the same shape.
+pub fn thumbnail_size(width: u32, height: u32) -> (u32, u32) {
+    let thumb_width = THUMBNAIL_WIDTH.min(width).max(2) & !1;
+    let thumb_height =
+        (u64::from(height) * u64::from(thumb_width) / u64::from(width.max(1))).max(2) as u32 & !1;
+    (thumb_width, thumb_height)
+}
+
+fn encode_jpeg(frame: RawFrame) -> anyhow::Result<Thumbnail> {
+    let (width, height) = thumbnail_size(frame.width, frame.height);
+    let raw_caps = gst::Caps::builder("video/x-raw")
+        .field("format", "BGRx")
+        .field("width", frame.width as i32)
+        .field("height", frame.height as i32)
+        .field("framerate", gst::Fraction::new(0, 1))
+        .build();
+    let jpeg_caps = gst::Caps::builder("image/jpeg")
+        .field("width", width as i32)
+        .field("height", height as i32)
+        .build();
+    let sample = gst::Sample::builder()
+        .buffer(&gst::Buffer::from_mut_slice(frame.data))
+        .caps(&raw_caps)
+        .build();
+    let jpeg =
+        gstreamer_video::convert_sample(&sample, &jpeg_caps, gst::ClockTime::from_seconds(5))
+            .context("JPEG conversion failed")?;
+    let buffer = jpeg.buffer().context("Converted thumbnail has no buffer")?;
+    let map = buffer
+        .map_readable()
+        .context("Failed to map thumbnail buffer")?;
+    Ok(Thumbnail {
+        width,
+        height,
+        jpeg: base64::engine::general_purpose::STANDARD.encode(map.as_slice()),
+    })
+}
+
+fn send(ws_tx: &mpsc::Sender<Message>, thumb: &Thumbnail) {
+    let Ok(msg) = serde_json::to_string(thumb) else {
+        return;
+    };
+    // Outbox full or reconnecting: the next thumbnail will do
+    if let Err(e) = ws_tx.try_send(Message::Text(msg.into())) {
+        debug!("Failed to queue thumbnail: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumbnails_keep_the_screen_shape() {
+        assert_eq!(thumbnail_size(1920, 1080), (320, 180));
+        assert_eq!(thumbnail_size(2560, 1600), (320, 200));
+        // Odd heights round down to even, as encoders want
+        assert_eq!(thumbnail_size(1366, 768), (320, 178));
+        // Never upscaled
+        assert_eq!(thumbnail_size(200, 100), (200, 100));
+        assert_eq!(thumbnail_size(0, 0), (2, 2));
+    }
+}
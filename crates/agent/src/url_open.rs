@@ -0,0 +1,216 @@
+//! URL opening across the session boundary.
+//!
+//! Session → client (`--forward-urls`): the desktop's default web browser
+//! is a small script that runs `beam-agent --open-url <URL>`. That process
+//! passes the URL over a Unix socket to the running agent, which sends it
+//! to the browser as `InputEvent::OpenUrl` and the client opens a tab.
+//!
+//! Client → session: an `OpenUrl` from the browser launches the session's
+//! real web browser directly, bypassing the forwarding handler.
+
+use crate::display;
+use crate::signaling::WsSender;
+use anyhow::{Context, Result, bail};
+use beam_protocol::{InputEvent, MAX_OPEN_URL_LEN, is_forwardable_url};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+
+/// Desktop file and XFCE helper ID of the forwarding handler.
+pub(crate) const HANDLER_ID: &str = "beam-open-url";
+
+/// A shim that connects but never sends a line is dropped after this.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Tells `--open-url` which agent socket to use (set by the handler script).
+const SOCKET_ENV: &str = "BEAM_URL_SOCKET";
+
+/// Socket the agent listens on for forwarded URLs, in the session's
+/// runtime dir (0700, so only the session user can reach it).
+pub(crate) fn socket_path(runtime_dir: &Path) -> PathBuf {
+    runtime_dir.join("url.sock")
+}
+
+/// Write the handler script plus the .desktop and XFCE helper entries that
+/// point at it, under `runtime_dir`. Returns the script path; the caller
+/// registers it as the default browser and adds `runtime_dir/share` to
+/// XDG_DATA_DIRS so the entries are found without touching ~/.local.
+pub(crate) fn install_handler(runtime_dir: &Path, agent_exe: &Path) -> Result<PathBuf> {
+    let script = runtime_dir.join("bin").join(HANDLER_ID);
+    let socket = socket_path(runtime_dir);
+    let files = [
+        (
+            script.clone(),
+            format!(
+                "#!/bin/sh\n\
+                 # Opens http(s) links in the Beam client's browser\n\
+                 {SOCKET_ENV}='{}' exec '{}' --open-url \"$1\"\n",
+                socket.display(),
+                agent_exe.display(),
+            ),
+        ),
+        (
+            runtime_dir.join(format!("share/applications/{HANDLER_ID}.desktop")),
+            format!(
+                "[Desktop Entry]\n\
+                 Type=Application\n\
+                 Name=Beam client browser\n\
+                 Exec={} %u\n\
+                 NoDisplay=true\n\
+                 MimeType=x-scheme-handler/http;x-scheme-handler/https;\n",
+                script.display(),
+            ),
+        ),
+        (
+            runtime_dir.join(format!("share/xfce4/helpers/{HANDLER_ID}.desktop")),
+            format!(
+                "[Desktop Entry]\n\
+                 Version=1.0\n\
+                 Type=X-XFCE-Helper\n\
+                 X-XFCE-Category=WebBrowser\n\
+                 X-XFCE-Commands={s}\n\
+                 X-XFCE-CommandsWithParameter={s} \"%s\"\n\
+                 Name=Beam client browser\n\
+                 Icon=web-browser\n\
+                 NoDisplay=true\n",
+                s = script.display(),
+            ),
+        ),
+    ];
+    for (path, content) in &files {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+        .context("Failed to make URL handler executable")?;
+    Ok(script)
+}
+
+/// `beam-agent --open-url`: pass `url` to the agent named by
+/// `BEAM_URL_SOCKET`.
+pub(crate) fn forward_to_agent(url: &str) -> Result<()> {
+    if !is_forwardable_url(url) {
+        bail!("Not an http(s) URL: {url}");
+    }
+    let socket = std::env::var(SOCKET_ENV).with_context(|| format!("{SOCKET_ENV} is not set"))?;
+    let mut stream = std::os::unix::net::UnixStream::connect(&socket)
+        .with_context(|| format!("Failed to connect to Beam agent at {socket}"))?;
+    stream
+        .write_all(format!("{url}\n").as_bytes())
+        .context("Failed to send URL to Beam agent")?;
+    Ok(())
+}
+
+/// Accept URLs from `--open-url` shims and send each to the browser.
+/// Returns only if the socket can't be set up.
+pub(crate) async fn run_url_listener(socket: &Path, ws_tx: &WsSender) -> Result<()> {
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    // Stale socket from a previous agent on this display
+    let _ = fs::remove_file(socket);
+    let listener = tokio::net::UnixListener::bind(socket)
+        .with_context(|| format!("Failed to bind {}", socket.display()))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+        .context("Failed to restrict URL socket permissions")?;
+    info!(socket = %socket.display(), "Forwarding session URLs to the browser");
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("URL socket accept failed: {e}");
+                continue;
+            }
+        };
+        let mut line = String::new();
+        let mut reader = BufReader::new(stream.take(MAX_OPEN_URL_LEN as u64 + 1));
+        match tokio::time::timeout(READ_TIMEOUT, reader.read_line(&mut line)).await {
+            Ok(Ok(_)) => {}
+            _ => continue,
+        }
+        let url = line.trim_end_matches('\n');
+        if !is_forwardable_url(url) {
+            warn!(len = url.len(), "Ignoring URL that can't be forwarded");
+            continue;
+        }
+        info!(url, "Opening session URL in the browser");
+        let event = InputEvent::OpenUrl {
+            url: url.to_string(),
+        };
+        if let Ok(msg) = serde_json::to_string(&event)
+            && ws_tx.send(Message::Text(msg.into())).await.is_err()
+        {
+            warn!("Failed to send URL to browser");
+        }
+    }
+}
+
+/// Open `url` from the client in the session's web browser.
+pub(crate) fn open_in_session(x_display: &str, url: &str) -> Result<()> {
+    if !is_forwardable_url(url) {
+        bail!("Refusing to open non-http(s) URL");
+    }
+    let browser = display::detect_browser().context("No web browser installed in the session")?;
+    let mut cmd = Command::new(browser);
+    cmd.arg(url)
+        .env("DISPLAY", x_display)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // Join the desktop's bus so a running browser opens a tab rather than
+    // starting a second instance
+    if let Some(addr) = display::find_dbus_address_for_display(x_display) {
+        cmd.env("DBUS_SESSION_BUS_ADDRESS", addr);
+    }
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to launch {browser}"))?;
+    info!(browser, url, "Opened URL in session browser");
+    // Reap it; a first launch keeps running as the browser itself
+    std::thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installs_handler_entries() {
+        let dir = std::env::temp_dir().join(format!("beam-test-{}", uuid::Uuid::new_v4()));
+        let script = install_handler(&dir, Path::new("/usr/local/bin/beam-agent")).unwrap();
+
+        assert_eq!(script, dir.join("bin/beam-open-url"));
+        let body = fs::read_to_string(&script).unwrap();
+        assert!(body.starts_with("#!/bin/sh\n"));
+        assert!(body.contains(&format!(
+            "BEAM_URL_SOCKET='{}' exec '/usr/local/bin/beam-agent' --open-url \"$1\"",
+            dir.join("url.sock").display()
+        )));
+        let mode = fs::metadata(&script).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        let desktop =
+            fs::read_to_string(dir.join("share/applications/beam-open-url.desktop")).unwrap();
+        assert!(desktop.contains("MimeType=x-scheme-handler/http;x-scheme-handler/https;"));
+        assert!(desktop.contains(&format!("Exec={} %u", script.display())));
+        let helper =
+            fs::read_to_string(dir.join("share/xfce4/helpers/beam-open-url.desktop")).unwrap();
+        assert!(helper.contains("X-XFCE-Category=WebBrowser"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,35 +1,80 @@
 use crate::CaptureCommand;
+use crate::frame_queue::{EncodedFrame, FrameReceiver};
 use crate::h264;
+use crate::latency::LatencyTracker;
+use crate::media_clock::{AudioStamp, MediaClock};
+use crate::pipeline_stats::{PipelineTimer, Stage};
+use crate::quality::LinkStats;
 use crate::signaling::WsSender;
 
-use beam_protocol::VideoFrameHeader;
-use std::sync::Arc;
+use beam_protocol::{AgentMessage, VideoFrameHeader};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info, warn};
 
+/// What the video send loop sends frames to and steers the encoder with.
+pub(crate) struct VideoSendCtx<'a> {
+    pub ws_tx: &'a WsSender,
+    pub force_keyframe: &'a Arc<AtomicBool>,
+    pub capture_cmd_tx: &'a std::sync::mpsc::Sender<CaptureCommand>,
+    /// Size the frames are stamped with
+    pub stream_width: &'a Arc<std::sync::atomic::AtomicU32>,
+    pub stream_height: &'a Arc<std::sync::atomic::AtomicU32>,
+    pub clock: MediaClock,
+}
+
+/// Where the video send loop accounts for each frame.
+pub(crate) struct FrameStats<'a> {
+    /// Sent frames, so browser acks can be matched by timestamp
+    pub latency: &'a Arc<Mutex<LatencyTracker>>,
+    /// The hop through the channel and the send loop, as the send stage
+    pub pipeline: &'a Arc<Mutex<PipelineTimer>>,
+    /// Delivered and dropped frames
+    pub link: &'a LinkStats,
+}
+
+/// Time between sender reports
+const SENDER_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Write encoded video frames as WebSocket binary messages.
-/// Each frame is prefixed with a 24-byte VideoFrameHeader.
+/// Each frame is prefixed with a 24-byte VideoFrameHeader, stamped with
+/// the media clock time it was captured at.
 pub(crate) async fn run_video_send_loop(
-    encoded_rx: &mut mpsc::Receiver<Vec<u8>>,
-    ws_tx: &WsSender,
-    force_keyframe: &Arc<AtomicBool>,
-    capture_cmd_tx: &std::sync::mpsc::Sender<CaptureCommand>,
-    capture_width: &Arc<std::sync::atomic::AtomicU32>,
-    capture_height: &Arc<std::sync::atomic::AtomicU32>,
+    encoded_rx: &mut FrameReceiver,
+    ctx: VideoSendCtx<'_>,
+    stats: FrameStats<'_>,
 ) {
+    let VideoSendCtx {
+        ws_tx,
+        force_keyframe,
+        capture_cmd_tx,
+        stream_width,
+        stream_height,
+        clock,
+    } = ctx;
     let mut video_frame_count: u64 = 0;
     let mut waiting_for_idr = true; // Start waiting for first IDR
     let mut idr_wait_start = Instant::now();
     let mut idr_wait_attempts: u32 = 0;
     let mut encoder_reset_count: u32 = 0;
     const MAX_ENCODER_RESETS: u32 = 3;
-    let capture_start = Instant::now();
+    let mut chroma_444 = false;
 
-    while let Some(data) = encoded_rx.recv().await {
-        let is_idr = h264::h264_contains_idr(&data);
+    while let Some(frame) = encoded_rx.recv().await {
+        let EncodedFrame {
+            data,
+            keyframe: is_idr,
+            captured_at,
+            queued_at,
+        } = frame;
+        // SPS is inlined with every IDR; its profile tells the browser which
+        // decoder configuration (Main vs High 4:4:4) to use.
+        if is_idr && let Some(high_444) = h264::sps_is_high_444(&data) {
+            chroma_444 = high_444;
+        }
 
         // Gate on first IDR frame — browser decoder needs a keyframe to initialize
         if waiting_for_idr {
@@ -78,15 +123,29 @@ pub(crate) async fn run_video_send_loop(
         }
 
         // Build binary frame: VideoFrameHeader + H.264 payload
-        let width = capture_width.load(Ordering::Relaxed) as u16;
-        let height = capture_height.load(Ordering::Relaxed) as u16;
-        let timestamp_us = capture_start.elapsed().as_micros() as u64;
+        let width = stream_width.load(Ordering::Relaxed) as u16;
+        let height = stream_height.load(Ordering::Relaxed) as u16;
+        let timestamp_us = clock.micros(captured_at);
         let header =
-            VideoFrameHeader::video(width, height, timestamp_us, data.len() as u32, is_idr);
+            VideoFrameHeader::video(width, height, timestamp_us, data.len() as u32, is_idr)
+                .with_chroma_444(chroma_444);
         let frame_bytes = header.serialize_with_payload(&data);
+        let frame_len = frame_bytes.len();
 
         match ws_tx.try_send(Message::Binary(frame_bytes.into())) {
             Ok(()) => {
+                stats.link.record_sent(frame_len);
+                let sent_at = Instant::now();
+                stats
+                    .latency
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record_sent(timestamp_us, sent_at.duration_since(captured_at), sent_at);
+                stats
+                    .pipeline
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record(Stage::Send, sent_at.duration_since(queued_at));
                 video_frame_count += 1;
                 if video_frame_count <= 5 {
                     info!(
@@ -96,11 +155,14 @@ pub(crate) async fn run_video_send_loop(
                         "Video frame sent via WebSocket"
                     );
                 }
-                if video_frame_count.is_multiple_of(300) {
-                    info!(video_frame_count, "Video frames sent");
-                }
             }
             Err(mpsc::error::TrySendError::Full(_)) => {
+                stats.link.record_dropped();
+                stats
+                    .pipeline
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record_outbox_drop();
                 debug!("Dropping video frame (WS outbox full, prioritizing latency)");
             }
             Err(mpsc::error::TrySendError::Closed(_)) => {
@@ -113,13 +175,16 @@ pub(crate) async fn run_video_send_loop(
 }
 
 /// Write encoded audio frames as WebSocket binary messages.
-/// Uses the same VideoFrameHeader format with the audio flag set.
-pub(crate) async fn run_audio_send_loop(audio_rx: &mut mpsc::Receiver<Vec<u8>>, ws_tx: &WsSender) {
-    let capture_start = Instant::now();
+/// Uses the same VideoFrameHeader format with the audio flag set, followed
+/// by a sender report with the first frame and every few seconds after.
+pub(crate) async fn run_audio_send_loop(
+    audio_rx: &mut mpsc::Receiver<(AudioStamp, Vec<u8>)>,
+    ws_tx: &WsSender,
+) {
     let mut audio_frame_count: u64 = 0;
-    while let Some(data) = audio_rx.recv().await {
-        let timestamp_us = capture_start.elapsed().as_micros() as u64;
-        let header = VideoFrameHeader::audio(timestamp_us, data.len() as u32);
+    let mut last_report_us: Option<u64> = None;
+    while let Some((stamp, data)) = audio_rx.recv().await {
+        let header = VideoFrameHeader::audio(stamp.audio_us, data.len() as u32);
         let frame_bytes = header.serialize_with_payload(&data);
 
         match ws_tx.try_send(Message::Binary(frame_bytes.into())) {
@@ -135,6 +200,16 @@ pub(crate) async fn run_audio_send_loop(audio_rx: &mut mpsc::Receiver<Vec<u8>>,
                 if audio_frame_count.is_multiple_of(500) {
                     info!(audio_frame_count, "Audio frames sent");
                 }
+                if last_report_us.is_none_or(|last| {
+                    stamp.clock_us.saturating_sub(last) >= SENDER_REPORT_INTERVAL.as_micros() as u64
+                }) {
+                    last_report_us = Some(stamp.clock_us);
+                    let report = AgentMessage::SenderReport {
+                        clock_us: stamp.clock_us,
+                        audio_us: stamp.audio_us,
+                    };
+                    let _ = ws_tx.try_send(Message::Text(report.to_json().into()));
+                }
             }
             Err(mpsc::error::TrySendError::Full(_)) => {
                 warn!("Dropping audio frame (WS outbox full)");
@@ -0,0 +1,724 @@
+//! Agent self-monitoring. A thread samples the agent's own memory and CPU
+//! use and the capture and encoder frame counters once a second, reports
+//! them to the server, and steps in when the pipeline stops making
+//! progress: first by recreating the encoder or re-initializing capture,
+//! and if that doesn't help, by ending the agent with an error so the
+//! server's restart loop replaces it. A wedged agent otherwise never exits
+//! and is never restarted. Under a systemd unit with `WatchdogSec=` the
+//! same thread sends the keepalives, and stops once the agent is wedged.
+//!
+//! The same samples tell when the browser's picture has frozen: video sent
+//! that the browser never acks, or capture producing nothing. Those raise
+//! a `StreamAlert` for the server to count and the browser to act on. With
+//! freeze recovery on, video that stays undelivered is worked on a rung at
+//! a time: a keyframe for a restarted decoder, a new encoder, and then a
+//! few requests, further and further apart, for the browser to reconnect.
+
+use crate::CaptureCommand;
+use crate::audio::PlayoutStats;
+use crate::encoded_output::OutputCounters;
+use crate::input_limit::InputLimiter;
+use crate::quality::LinkStats;
+use beam_protocol::{AgentHealth, ReconnectRequest, StreamAlert, StreamAlertKind, sd_notify};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, warn};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Samples per health report
+const REPORT_EVERY: u32 = 5;
+
+/// No progress this long triggers a recovery attempt
+const STALL_RECOVER: Duration = Duration::from_secs(5);
+
+/// Recovery attempts are at least this far apart, longer than the capture
+/// thread's encoder reset cooldown
+const RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// No progress this long despite recovery attempts: exit for a restart
+const STALL_EXIT: Duration = Duration::from_secs(30);
+
+/// Capturing below a quarter of the target framerate this long triggers
+/// one capture re-init
+const SLOW_CAPTURE: Duration = Duration::from_secs(10);
+
+/// Video sent this long without an ack hasn't reached the browser...
+const UNDELIVERED_AFTER: Duration = Duration::from_secs(5);
+
+/// ...if it was at least this many frames: browsers ack one in 30
+const UNDELIVERED_FRAMES: u64 = 90;
+
+/// Video undelivered this long gets a new encoder...
+const LADDER_RESET_ENCODER: Duration = Duration::from_secs(10);
+
+/// ...and this long a request for the browser to reconnect, the wait
+/// doubling before each next one
+const LADDER_FIRST_RECONNECT: Duration = Duration::from_secs(20);
+
+/// Reconnect requests in any `RECONNECT_WINDOW`, so a browser that can't
+/// be helped this way isn't sent around in circles
+const MAX_RECONNECTS: usize = 3;
+const RECONNECT_WINDOW: Duration = Duration::from_secs(600);
+
+/// Updated by the capture thread every frame.
+#[derive(Default)]
+pub(crate) struct CaptureProgress {
+    pub frames: AtomicU64,
+    pub target_fps: AtomicU32,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Verdict {
+    Healthy,
+    ResetEncoder,
+    ReinitCapture,
+    /// Give up; the agent should exit with this reason
+    Wedged(String),
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    at: Instant,
+    captured: u64,
+    encoded: u64,
+}
+
+pub(crate) struct Watchdog {
+    max_rss: u64,
+    last: Option<Sample>,
+    capture_stalled_since: Option<Instant>,
+    encode_stalled_since: Option<Instant>,
+    slow_since: Option<Instant>,
+    slow_recovered: bool,
+    last_recovery: Option<Instant>,
+    recoveries: u32,
+}
+
+impl Watchdog {
+    /// `max_rss_mb` 0 means no memory limit.
+    pub(crate) fn new(max_rss_mb: u32) -> Self {
+        Self {
+            max_rss: u64::from(max_rss_mb) << 20,
+            last: None,
+            capture_stalled_since: None,
+            encode_stalled_since: None,
+            slow_since: None,
+            slow_recovered: false,
+            last_recovery: None,
+            recoveries: 0,
+        }
+    }
+
+    /// Judge one sample of the frame counters and resident memory.
+    pub(crate) fn observe(
+        &mut self,
+        now: Instant,
+        captured: u64,
+        encoded: u64,
+        target_fps: u32,
+        rss_bytes: u64,
+    ) -> Verdict {
+        if self.max_rss > 0 && rss_bytes > self.max_rss {
+            return Verdict::Wedged(format!(
+                "resident memory {} MiB is over the {} MiB limit",
+                rss_bytes >> 20,
+                self.max_rss >> 20
+            ));
+        }
+
+        let sample = Sample {
+            at: now,
+            captured,
+            encoded,
+        };
+        let Some(last) = self.last.replace(sample) else {
+            return Verdict::Healthy;
+        };
+        let capture_advanced = captured > last.captured;
+        if capture_advanced {
+            self.capture_stalled_since = None;
+            if encoded > last.encoded {
+                self.encode_stalled_since = None;
+            } else {
+                self.encode_stalled_since.get_or_insert(last.at);
+            }
+        } else {
+            self.capture_stalled_since.get_or_insert(last.at);
+        }
+
+        let secs = now.duration_since(last.at).as_secs_f64();
+        let capture_fps = (captured - last.captured) as f64 / secs.max(f64::EPSILON);
+        if capture_advanced && capture_fps < f64::from(target_fps) / 4.0 {
+            self.slow_since.get_or_insert(last.at);
+        } else if capture_advanced {
+            self.slow_since = None;
+            self.slow_recovered = false;
+        }
+
+        let stalled_for = |since: Option<Instant>| since.map(|s| now.duration_since(s));
+        let capture_stall = stalled_for(self.capture_stalled_since);
+        let encode_stall = stalled_for(self.encode_stalled_since);
+        if let Some(stall) = capture_stall.filter(|&s| s >= STALL_EXIT) {
+            return Verdict::Wedged(format!("no frame captured for {}s", stall.as_secs()));
+        }
+        if let Some(stall) = encode_stall.filter(|&s| s >= STALL_EXIT) {
+            return Verdict::Wedged(format!("no encoder output for {}s", stall.as_secs()));
+        }
+        if self
+            .last_recovery
+            .is_some_and(|at| now.duration_since(at) < RETRY_AFTER)
+        {
+            return Verdict::Healthy;
+        }
+
+        let verdict = if capture_stall.is_some_and(|s| s >= STALL_RECOVER) {
+            Verdict::ReinitCapture
+        } else if encode_stall.is_some_and(|s| s >= STALL_RECOVER) {
+            Verdict::ResetEncoder
+        } else if !self.slow_recovered
+            && stalled_for(self.slow_since).is_some_and(|s| s >= SLOW_CAPTURE)
+        {
+            self.slow_recovered = true;
+            Verdict::ReinitCapture
+        } else {
+            return Verdict::Healthy;
+        };
+        self.last_recovery = Some(now);
+        self.recoveries += 1;
+        verdict
+    }
+
+    /// How long capture has made no progress, as of the last sample.
+    pub(crate) fn capture_stalled_for(&self, now: Instant) -> Option<Duration> {
+        self.capture_stalled_since.map(|s| now.duration_since(s))
+    }
+}
+
+/// Frames sent and acked as of the browser's latest ack
+#[derive(Clone, Copy)]
+struct AckMark {
+    at: Instant,
+    sent: u64,
+    acked: u64,
+}
+
+/// Tells when video stops reaching the browser, and when it flows again.
+#[derive(Default)]
+pub(crate) struct FreezeDetector {
+    ack_mark: Option<AckMark>,
+    undelivered_since: Option<Instant>,
+    capture_stalled_since: Option<Instant>,
+}
+
+impl FreezeDetector {
+    /// Judge one sample of the link counters and the capture stall the
+    /// watchdog sees; returns the alerts that started or ended with it.
+    pub(crate) fn observe(
+        &mut self,
+        now: Instant,
+        sent: u64,
+        acked: u64,
+        backgrounded: bool,
+        capture_stall: Option<Duration>,
+    ) -> Vec<StreamAlert> {
+        // A backgrounded tab may stop decoding, and so acking
+        let mark = match self.ack_mark {
+            Some(mark) if acked == mark.acked && !backgrounded => mark,
+            _ => *self.ack_mark.insert(AckMark {
+                at: now,
+                sent,
+                acked,
+            }),
+        };
+        let undelivered = now.duration_since(mark.at) >= UNDELIVERED_AFTER
+            && sent - mark.sent >= UNDELIVERED_FRAMES;
+        let stalled = capture_stall.filter(|&s| s >= STALL_RECOVER);
+
+        let mut alerts = Vec::new();
+        alerts.extend(transition(
+            &mut self.undelivered_since,
+            undelivered.then_some(mark.at),
+            StreamAlertKind::NotDelivered,
+            now,
+        ));
+        alerts.extend(transition(
+            &mut self.capture_stalled_since,
+            stalled.map(|s| now - s),
+            StreamAlertKind::CaptureStalled,
+            now,
+        ));
+        alerts
+    }
+
+    /// When video stopped reaching the browser, while it hasn't since.
+    pub(crate) fn undelivered_since(&self) -> Option<Instant> {
+        self.undelivered_since
+    }
+}
+
+/// A step taken against video not reaching the browser
+#[derive(Debug, PartialEq)]
+pub(crate) enum Rung {
+    /// Send a keyframe and have the browser restart its decoder
+    Keyframe,
+    /// Recreate the encoder, in case its stream is what the browser can't
+    /// decode
+    ResetEncoder,
+    /// Ask the browser to reconnect, for the nth time this freeze
+    Reconnect(u32),
+}
+
+/// Progress up the ladder during one freeze
+struct Climb {
+    reset_encoder: bool,
+    reconnects: u32,
+    next_reconnect: Instant,
+    reconnect_wait: Duration,
+}
+
+/// Picks the next `Rung` while video stays undelivered.
+#[derive(Default)]
+pub(crate) struct RecoveryLadder {
+    climb: Option<Climb>,
+    /// When the browser was last asked to reconnect, oldest first
+    reconnects: VecDeque<Instant>,
+}
+
+impl RecoveryLadder {
+    /// The rung to take at `now`, given when video stopped reaching the
+    /// browser (None while it flows).
+    pub(crate) fn step(
+        &mut self,
+        now: Instant,
+        undelivered_since: Option<Instant>,
+    ) -> Option<Rung> {
+        let Some(since) = undelivered_since else {
+            self.climb = None;
+            return None;
+        };
+        let Some(climb) = &mut self.climb else {
+            self.climb = Some(Climb {
+                reset_encoder: false,
+                reconnects: 0,
+                next_reconnect: since + LADDER_FIRST_RECONNECT,
+                reconnect_wait: LADDER_FIRST_RECONNECT,
+            });
+            return Some(Rung::Keyframe);
+        };
+        if !climb.reset_encoder {
+            if now.duration_since(since) < LADDER_RESET_ENCODER {
+                return None;
+            }
+            climb.reset_encoder = true;
+            return Some(Rung::ResetEncoder);
+        }
+        if now < climb.next_reconnect {
+            return None;
+        }
+        while self
+            .reconnects
+            .front()
+            .is_some_and(|&at| now.duration_since(at) >= RECONNECT_WINDOW)
+        {
+            self.reconnects.pop_front();
+        }
+        if self.reconnects.len() >= MAX_RECONNECTS {
+            return None;
+        }
+        self.reconnects.push_back(now);
+        climb.reconnects += 1;
+        climb.reconnect_wait *= 2;
+        climb.next_reconnect = now + climb.reconnect_wait;
+        Some(Rung::Reconnect(climb.reconnects))
+    }
+}
+
+/// An alert of `kind` if it started (`started` is when) or ended
+fn transition(
+    since: &mut Option<Instant>,
+    started: Option<Instant>,
+    kind: StreamAlertKind,
+    now: Instant,
+) -> Option<StreamAlert> {
+    let (active, from) = match (*since, started) {
+        (None, Some(started)) => (true, *since.insert(started)),
+        (Some(from), None) => {
+            *since = None;
+            (false, from)
+        }
+        _ => return None,
+    };
+    Some(StreamAlert {
+        kind,
+        active,
+        secs: now.duration_since(from).as_secs() as u32,
+        recover: false,
+    })
+}
+
+pub(crate) struct WatchdogCtx {
+    pub progress: Arc<CaptureProgress>,
+    pub counters: Arc<OutputCounters>,
+    pub capture_cmd_tx: std::sync::mpsc::Sender<CaptureCommand>,
+    pub ws_tx: mpsc::Sender<Message>,
+    /// Fired with the reason when the agent should exit
+    pub wedged: oneshot::Sender<String>,
+    pub shutdown: Arc<AtomicBool>,
+    pub max_rss_mb: u32,
+    pub input_limiter: Arc<InputLimiter>,
+    /// Audio clock drift the audio thread last measured (µs)
+    pub audio_drift_us: Arc<AtomicI64>,
+    /// Playout totals the browser reported
+    pub audio_playout: Arc<PlayoutStats>,
+    pub link_stats: Arc<LinkStats>,
+    pub tab_backgrounded: Arc<AtomicBool>,
+    pub force_keyframe: Arc<AtomicBool>,
+    /// Climb the `RecoveryLadder` when video stops reaching the browser
+    pub freeze_recovery: bool,
+}
+
+/// Sample, report and recover until shutdown or the agent is wedged.
+pub(crate) fn run_watchdog(ctx: WatchdogCtx) {
+    let mut watchdog = Watchdog::new(ctx.max_rss_mb);
+    let mut freeze = FreezeDetector::default();
+    let mut ladder = RecoveryLadder::default();
+    let page_size = sysconf(libc::_SC_PAGESIZE).unwrap_or(4096);
+    let clock_ticks = sysconf(libc::_SC_CLK_TCK).unwrap_or(100);
+    let mut window_start = (Instant::now(), 0, 0, cpu_ticks().unwrap_or(0));
+    let mut samples = 0;
+    let keepalive = sd_notify::watchdog_interval().is_some();
+    loop {
+        std::thread::sleep(SAMPLE_INTERVAL);
+        if ctx.shutdown.load(Ordering::Relaxed) {
+            return;
+        }
+        let now = Instant::now();
+        let captured = ctx.progress.frames.load(Ordering::Relaxed);
+        let encoded = ctx.counters.frames.load(Ordering::Relaxed);
+        let target_fps = ctx.progress.target_fps.load(Ordering::Relaxed);
+        let rss = rss_pages().unwrap_or(0) * page_size;
+
+        match watchdog.observe(now, captured, encoded, target_fps, rss) {
+            Verdict::Healthy => {}
+            Verdict::ResetEncoder => {
+                warn!("Watchdog: encoder output stalled, recreating the encoder");
+                let _ = ctx.capture_cmd_tx.send(CaptureCommand::ResetEncoder);
+            }
+            Verdict::ReinitCapture => {
+                warn!(
+                    captured,
+                    target_fps, "Watchdog: capture stalled or slow, re-initializing"
+                );
+                let _ = ctx.capture_cmd_tx.send(CaptureCommand::ReinitCapture);
+            }
+            Verdict::Wedged(reason) => {
+                error!("Watchdog: agent wedged ({reason}), exiting for a restart");
+                let _ = ctx.wedged.send(reason);
+                return;
+            }
+        }
+        if keepalive {
+            sd_notify::watchdog();
+        }
+
+        let alerts = freeze.observe(
+            now,
+            ctx.link_stats.frames_sent(),
+            ctx.link_stats.frames_acked(),
+            ctx.tab_backgrounded.load(Ordering::Relaxed),
+            watchdog.capture_stalled_for(now),
+        );
+        let rung = if ctx.freeze_recovery {
+            ladder.step(now, freeze.undelivered_since())
+        } else {
+            None
+        };
+        for mut alert in alerts {
+            if alert.active {
+                warn!(
+                    kind = alert.kind.name(),
+                    "Watchdog: the browser's picture froze"
+                );
+            } else {
+                info!(
+                    kind = alert.kind.name(),
+                    secs = alert.secs,
+                    "Watchdog: video flows again"
+                );
+            }
+            // The ladder's first rung starts with the alert
+            alert.recover =
+                alert.kind == StreamAlertKind::NotDelivered && rung == Some(Rung::Keyframe);
+            let Ok(msg) = serde_json::to_string(&alert) else {
+                continue;
+            };
+            if let Err(e) = ctx.ws_tx.try_send(Message::Text(msg.into())) {
+                debug!("Failed to queue stream alert: {e}");
+            }
+        }
+        match rung {
+            None => {}
+            Some(Rung::Keyframe) => ctx.force_keyframe.store(true, Ordering::Relaxed),
+            Some(Rung::ResetEncoder) => {
+                warn!("Watchdog: video still not reaching the browser, recreating the encoder");
+                let _ = ctx.capture_cmd_tx.send(CaptureCommand::ResetEncoder);
+            }
+            Some(Rung::Reconnect(attempt)) => {
+                warn!(
+                    attempt,
+                    "Watchdog: video still not reaching the browser, asking it to reconnect"
+                );
+                if let Ok(msg) = serde_json::to_string(&ReconnectRequest { attempt })
+                    && let Err(e) = ctx.ws_tx.try_send(Message::Text(msg.into()))
+                {
+                    debug!("Failed to queue reconnect request: {e}");
+                }
+            }
+        }
+
+        samples += 1;
+        if samples < REPORT_EVERY {
+            continue;
+        }
+        samples = 0;
+        let (start, start_captured, start_encoded, start_ticks) = window_start;
+        let ticks = cpu_ticks().unwrap_or(start_ticks);
+        let secs = now.duration_since(start).as_secs_f64();
+        let health = AgentHealth {
+            rss_mb: (rss >> 20) as u32,
+            cpu_pct: (ticks - start_ticks) as f64 / clock_ticks as f64 / secs * 100.0,
+            capture_fps: (captured - start_captured) as f64 / secs,
+            encode_fps: (encoded - start_encoded) as f64 / secs,
+            target_fps,
+            recoveries: watchdog.recoveries,
+            input_dropped: ctx.input_limiter.dropped(),
+            av_drift_ms: ctx.audio_drift_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            audio_played_ms: ctx.audio_playout.played_ms.load(Ordering::Relaxed),
+            audio_concealed_ms: ctx.audio_playout.concealed_ms.load(Ordering::Relaxed),
+        };
+        window_start = (now, captured, encoded, ticks);
+        let Ok(msg) = serde_json::to_string(&health) else {
+            continue;
+        };
+        if let Err(e) = ctx.ws_tx.try_send(Message::Text(msg.into())) {
+            debug!("Failed to queue health report: {e}");
+        }
+    }
+}
+
+fn sysconf(name: libc::c_int) -> Option<u64> {
+    let value = unsafe { libc::sysconf(name) };
+    u64::try_from(value).ok().filter(|&v| v > 0)
+}
+
+/// Resident pages, from /proc/self/statm
+fn rss_pages() -> Option<u64> {
+    parse_statm_resident(&std::fs::read_to_string("/proc/self/statm").ok()?)
+}
+
+/// User plus system CPU time in clock ticks, from /proc/self/stat
+fn cpu_ticks() -> Option<u64> {
+    parse_stat_cpu_ticks(&std::fs::read_to_string("/proc/self/stat").ok()?)
+}
+
+fn parse_statm_resident(statm: &str) -> Option<u64> {
+    statm.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn parse_stat_cpu_ticks(stat: &str) -> Option<u64> {
+    // The command name in parentheses may contain spaces; utime and stime
+    // are the 12th and 13th fields after it
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(utime + stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed one sample a second; returns the verdicts
+    fn run(watchdog: &mut Watchdog, t0: Instant, samples: &[(u64, u64)]) -> Vec<Verdict> {
+        samples
+            .iter()
+            .enumerate()
+            .map(|(i, &(captured, encoded))| {
+                let now = t0 + Duration::from_secs(i as u64);
+                watchdog.observe(now, captured, encoded, 60, 0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steady_pipeline_is_healthy() {
+        let mut watchdog = Watchdog::new(0);
+        let samples: Vec<_> = (0..20).map(|i| (i * 60, i * 60)).collect();
+        let verdicts = run(&mut watchdog, Instant::now(), &samples);
+        assert!(verdicts.iter().all(|v| *v == Verdict::Healthy));
+    }
+
+    #[test]
+    fn stalled_encoder_is_reset_then_given_up_on() {
+        let mut watchdog = Watchdog::new(0);
+        let samples: Vec<_> = (0..40).map(|i| (i * 60, (i * 60).min(60))).collect();
+        let verdicts = run(&mut watchdog, Instant::now(), &samples);
+        // Stalled since the sample at 1s
+        assert_eq!(verdicts[6], Verdict::ResetEncoder);
+        assert_eq!(verdicts[7], Verdict::Healthy);
+        assert_eq!(verdicts[16], Verdict::ResetEncoder);
+        assert!(matches!(verdicts[31], Verdict::Wedged(_)));
+        assert_eq!(watchdog.recoveries, 3);
+    }
+
+    #[test]
+    fn stalled_capture_is_reinitialized() {
+        let mut watchdog = Watchdog::new(0);
+        let mut samples: Vec<_> = (0..3).map(|i| (i * 60, i * 60)).collect();
+        samples.extend([(120, 120); 8]);
+        let verdicts = run(&mut watchdog, Instant::now(), &samples);
+        assert_eq!(verdicts[7], Verdict::ReinitCapture);
+        // An encoder that has nothing to encode isn't stalled
+        assert!(!verdicts.contains(&Verdict::ResetEncoder));
+    }
+
+    #[test]
+    fn slow_capture_is_reinitialized_once() {
+        let mut watchdog = Watchdog::new(0);
+        let samples: Vec<_> = (0..30).map(|i| (i * 10, i * 10)).collect();
+        let verdicts = run(&mut watchdog, Instant::now(), &samples);
+        let reinits = verdicts
+            .iter()
+            .filter(|v| **v == Verdict::ReinitCapture)
+            .count();
+        assert_eq!(reinits, 1);
+        assert_eq!(verdicts[10], Verdict::ReinitCapture);
+    }
+
+    #[test]
+    fn memory_limit_exits() {
+        let mut watchdog = Watchdog::new(1024);
+        let now = Instant::now();
+        assert_eq!(watchdog.observe(now, 0, 0, 60, 900 << 20), Verdict::Healthy);
+        assert!(matches!(
+            watchdog.observe(now, 0, 0, 60, 1100 << 20),
+            Verdict::Wedged(_)
+        ));
+    }
+
+    /// Feed one sample a second of (sent, acked, backgrounded); returns the
+    /// alerts as (second, kind, active, secs)
+    fn freeze_alerts(samples: &[(u64, u64, bool)]) -> Vec<(usize, StreamAlertKind, bool, u32)> {
+        let mut detector = FreezeDetector::default();
+        let t0 = Instant::now();
+        let mut alerts = Vec::new();
+        for (i, &(sent, acked, backgrounded)) in samples.iter().enumerate() {
+            let now = t0 + Duration::from_secs(i as u64);
+            for alert in detector.observe(now, sent, acked, backgrounded, None) {
+                alerts.push((i, alert.kind, alert.active, alert.secs));
+            }
+        }
+        alerts
+    }
+
+    #[test]
+    fn unacked_video_raises_and_clears_an_alert() {
+        use StreamAlertKind::NotDelivered;
+        // 60fps, acked every half second until 3s, then not for 7s
+        let mut samples: Vec<_> = (0..4).map(|i| (i * 60, i * 2, false)).collect();
+        samples.extend((4..11).map(|i| (i * 60, 6, false)));
+        samples.push((660, 7, false));
+        assert_eq!(
+            freeze_alerts(&samples),
+            [(8, NotDelivered, true, 5), (11, NotDelivered, false, 8)]
+        );
+
+        // A trickle of frames, as when idle, isn't enough to tell
+        let samples: Vec<_> = (0..20).map(|i| (i * 2, 0, false)).collect();
+        assert!(freeze_alerts(&samples).is_empty());
+
+        // Nor is a backgrounded tab, which may not be decoding
+        let samples: Vec<_> = (0..20).map(|i| (i * 60, 0, true)).collect();
+        assert!(freeze_alerts(&samples).is_empty());
+    }
+
+    #[test]
+    fn stalled_capture_raises_and_clears_an_alert() {
+        let mut detector = FreezeDetector::default();
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+        let stall = |secs| Some(Duration::from_secs(secs));
+        assert!(detector.observe(at(3), 0, 0, false, stall(3)).is_empty());
+        let raised = detector.observe(at(5), 0, 0, false, stall(5));
+        assert_eq!(raised.len(), 1);
+        assert_eq!(raised[0].kind, StreamAlertKind::CaptureStalled);
+        assert!(raised[0].active);
+        assert!(detector.observe(at(6), 0, 0, false, stall(6)).is_empty());
+        let cleared = detector.observe(at(9), 0, 0, false, None);
+        assert!(!cleared[0].active);
+        assert_eq!(cleared[0].secs, 9);
+    }
+
+    #[test]
+    fn recovery_ladder_climbs_and_caps_reconnects() {
+        let mut ladder = RecoveryLadder::default();
+        let t0 = Instant::now();
+        let mut rungs = Vec::new();
+        // Frozen from 0s to 100s
+        for i in 0..=100 {
+            let now = t0 + Duration::from_secs(i);
+            if let Some(rung) = ladder.step(now, Some(t0)) {
+                rungs.push((i, rung));
+            }
+        }
+        assert_eq!(
+            rungs,
+            [
+                (0, Rung::Keyframe),
+                (10, Rung::ResetEncoder),
+                (20, Rung::Reconnect(1)),
+                (60, Rung::Reconnect(2)),
+            ]
+        );
+
+        // Video flowed again; the next freeze climbs from the bottom, but
+        // shares the reconnect budget with the last
+        assert_eq!(ladder.step(t0 + Duration::from_secs(101), None), None);
+        let t1 = t0 + Duration::from_secs(200);
+        let mut rungs = Vec::new();
+        for i in 0..=430 {
+            let now = t1 + Duration::from_secs(i);
+            if let Some(rung) = ladder.step(now, Some(t1)) {
+                rungs.push((i, rung));
+            }
+        }
+        assert_eq!(
+            rungs,
+            [
+                (0, Rung::Keyframe),
+                (10, Rung::ResetEncoder),
+                (20, Rung::Reconnect(1)),
+                // Out of budget until the first freeze's first request is
+                // ten minutes old
+                (420, Rung::Reconnect(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn proc_files_parse() {
+        assert_eq!(
+            parse_statm_resident("52341 7210 3012 112 0 9012 0\n"),
+            Some(7210)
+        );
+        let stat = "4242 (beam agent) S 1 4242 4242 0 -1 4194304 900 0 0 0 \
+                    1234 567 0 0 20 0 12 0 100 1000000 7210";
+        assert_eq!(parse_stat_cpu_ticks(stat), Some(1234 + 567));
+        assert_eq!(parse_stat_cpu_ticks("garbage"), None);
+    }
+}
@@ -0,0 +1,331 @@
+//! Browser camera → v4l2loopback bridge.
+//!
+//! The browser encodes its camera with WebCodecs and streams it up the
+//! session WebSocket as `FLAG_WEBCAM` frames. This module decodes them with
+//! GStreamer and writes raw video into a v4l2loopback device, so apps in
+//! the remote session (video calls, recorders) see an ordinary /dev/videoN
+//! camera. Needs the `v4l2loopback` kernel module loaded on the host.
+
+use crate::signaling::WsSender;
+use anyhow::Context;
+use beam_protocol::{VideoFrameHeader, WebcamStatus};
+use gstreamer::prelude::*;
+use gstreamer::{self as gst, ClockTime, ElementFactory};
+use gstreamer_app::AppSrc;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+
+/// Where the kernel lists video devices; loopback ones resolve under
+/// /sys/devices/virtual since they have no backing hardware.
+const VIDEO4LINUX_CLASS: &str = "/sys/class/video4linux";
+
+/// H.264 decoders to try, in order (libav first: always in gst-libav).
+const H264_DECODERS: &[&str] = &["avdec_h264", "openh264dec"];
+
+/// Commands for the webcam thread.
+pub enum WebcamCommand {
+    Start {
+        codec: String,
+        width: u32,
+        height: u32,
+    },
+    /// Complete binary frame as received: 24-byte header + payload
+    Frame(Vec<u8>),
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebcamCodec {
+    H264,
+    Vp8,
+}
+
+impl WebcamCodec {
+    /// Parse the `codec` field of `InputEvent::WebcamStart`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "h264" => Some(Self::H264),
+            "vp8" => Some(Self::Vp8),
+            _ => None,
+        }
+    }
+}
+
+/// Decode pipeline feeding one loopback device.
+pub struct WebcamBridge {
+    pipeline: gst::Pipeline,
+    appsrc: AppSrc,
+    device: PathBuf,
+    first_ts_us: Option<u64>,
+}
+
+impl WebcamBridge {
+    /// appsrc → parse/decode → videoconvert → videoscale → YUY2 → v4l2sink.
+    /// YUY2 at a fixed size is the format browsers and Zoom-style apps
+    /// accept most reliably from v4l2loopback.
+    pub fn start(
+        codec: WebcamCodec,
+        width: u32,
+        height: u32,
+        device: &Path,
+    ) -> anyhow::Result<Self> {
+        let pipeline = gst::Pipeline::new();
+
+        let input_caps = match codec {
+            WebcamCodec::H264 => gst::Caps::builder("video/x-h264")
+                .field("stream-format", "byte-stream")
+                .field("alignment", "au")
+                .build(),
+            WebcamCodec::Vp8 => gst::Caps::builder("video/x-vp8").build(),
+        };
+        let appsrc = ElementFactory::make("appsrc")
+            .name("webcam-src")
+            .build()
+            .context("Failed to create appsrc")?
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+        appsrc.set_caps(Some(&input_caps));
+        appsrc.set_is_live(true);
+        appsrc.set_format(gst::Format::Time);
+        appsrc.set_property("block", false);
+
+        let mut elements: Vec<gst::Element> = vec![appsrc.clone().upcast()];
+        match codec {
+            WebcamCodec::H264 => {
+                elements.push(
+                    ElementFactory::make("h264parse")
+                        .build()
+                        .context("Failed to create h264parse")?,
+                );
+                let decoder = H264_DECODERS
+                    .iter()
+                    .find_map(|name| ElementFactory::make(name).build().ok())
+                    .context("No H.264 decoder available (install gst-libav)")?;
+                elements.push(decoder);
+            }
+            WebcamCodec::Vp8 => elements.push(
+                ElementFactory::make("vp8dec")
+                    .build()
+                    .context("Failed to create vp8dec (install gst-plugins-good)")?,
+            ),
+        }
+
+        let output_caps = gst::Caps::builder("video/x-raw")
+            .field("format", "YUY2")
+            .field("width", width as i32)
+            .field("height", height as i32)
+            .build();
+        elements.extend([
+            ElementFactory::make("videoconvert")
+                .build()
+                .context("Failed to create videoconvert")?,
+            ElementFactory::make("videoscale")
+                .build()
+                .context("Failed to create videoscale")?,
+            ElementFactory::make("capsfilter")
+                .property("caps", &output_caps)
+                .build()
+                .context("Failed to create webcam capsfilter")?,
+            ElementFactory::make("v4l2sink")
+                .property("device", device.to_string_lossy().into_owned())
+                .property("sync", false)
+                .build()
+                .context("Failed to create v4l2sink (install gst-plugins-good)")?,
+        ]);
+
+        pipeline
+            .add_many(&elements)
+            .context("Failed to add webcam elements to pipeline")?;
+        gst::Element::link_many(&elements).context("Failed to link webcam pipeline")?;
+        pipeline
+            .set_state(gst::State::Playing)
+            .context("Failed to start webcam pipeline")?;
+
+        info!(?codec, width, height, device = %device.display(), "Webcam bridge started");
+        Ok(Self {
+            pipeline,
+            appsrc,
+            device: device.to_path_buf(),
+            first_ts_us: None,
+        })
+    }
+
+    pub fn device(&self) -> &Path {
+        &self.device
+    }
+
+    /// Push one encoded frame. Timestamps are rebased to the first frame.
+    pub fn push(&mut self, payload: &[u8], timestamp_us: u64) -> anyhow::Result<()> {
+        let first = *self.first_ts_us.get_or_insert(timestamp_us);
+        let mut buffer = gst::Buffer::from_slice(payload.to_vec());
+        buffer
+            .get_mut()
+            .expect("freshly-created GstBuffer should have unique ownership")
+            .set_pts(ClockTime::from_useconds(timestamp_us.saturating_sub(first)));
+        self.appsrc
+            .push_buffer(buffer)
+            .context("Failed to push webcam frame")?;
+        Ok(())
+    }
+
+    /// First pipeline error posted since the last call, if any.
+    pub fn take_error(&self) -> Option<String> {
+        let bus = self.pipeline.bus()?;
+        let msg = bus.pop_filtered(&[gst::MessageType::Error])?;
+        match msg.view() {
+            gst::MessageView::Error(err) => Some(err.error().to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for WebcamBridge {
+    fn drop(&mut self) {
+        let _ = self.appsrc.end_of_stream();
+        let _ = self.pipeline.set_state(gst::State::Null);
+        info!(device = %self.device.display(), "Webcam bridge stopped");
+    }
+}
+
+/// First v4l2loopback device under `class_dir` (normally
+/// /sys/class/video4linux), as a /dev path. Real cameras are skipped:
+/// their sysfs entries resolve to a bus device rather than /devices/virtual.
+pub fn find_loopback_device(class_dir: &Path) -> Option<PathBuf> {
+    let mut names: Vec<String> = std::fs::read_dir(class_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            std::fs::canonicalize(entry.path())
+                .is_ok_and(|target| target.to_string_lossy().contains("/devices/virtual/"))
+        })
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with("video"))
+        .collect();
+    // video2 before video10
+    names.sort_by_key(|name| name[5..].parse::<u32>().unwrap_or(u32::MAX));
+    names.first().map(|name| Path::new("/dev").join(name))
+}
+
+/// Webcam thread body: owns the bridge and applies commands until every
+/// sender is gone. Status goes back to the browser as `WebcamStatus`.
+pub fn run_webcam_loop(rx: mpsc::Receiver<WebcamCommand>, ws_tx: WsSender) {
+    let report = |status: WebcamStatus| {
+        if let Ok(msg) = serde_json::to_string(&status) {
+            let _ = ws_tx.try_send(Message::Text(msg.into()));
+        }
+    };
+    let mut bridge: Option<WebcamBridge> = None;
+    // The decoder can't start mid-GOP; drop deltas until the first keyframe
+    let mut awaiting_keyframe = true;
+
+    while let Ok(cmd) = rx.recv() {
+        match cmd {
+            WebcamCommand::Start {
+                codec,
+                width,
+                height,
+            } => {
+                bridge = None;
+                awaiting_keyframe = true;
+                let result = WebcamCodec::from_name(&codec)
+                    .with_context(|| format!("Unsupported webcam codec: {codec}"))
+                    .and_then(|codec| {
+                        let device = find_loopback_device(Path::new(VIDEO4LINUX_CLASS))
+                            .context("No v4l2loopback device found (modprobe v4l2loopback)")?;
+                        WebcamBridge::start(codec, width, height, &device)
+                    });
+                match result {
+                    Ok(started) => {
+                        report(WebcamStatus {
+                            active: true,
+                            device: Some(started.device().display().to_string()),
+                            error: None,
+                        });
+                        bridge = Some(started);
+                    }
+                    Err(e) => {
+                        warn!("Webcam passthrough unavailable: {e:#}");
+                        report(WebcamStatus {
+                            active: false,
+                            device: None,
+                            error: Some(format!("{e:#}")),
+                        });
+                    }
+                }
+            }
+            WebcamCommand::Frame(data) => {
+                let Some(active) = bridge.as_mut() else {
+                    continue;
+                };
+                let Ok(header) = VideoFrameHeader::deserialize(&data) else {
+                    continue;
+                };
+                if awaiting_keyframe && !header.is_keyframe() {
+                    continue;
+                }
+                awaiting_keyframe = false;
+                let payload = &data[beam_protocol::FRAME_HEADER_SIZE..];
+                let failure = match active.push(payload, header.timestamp_us) {
+                    Err(e) => Some(format!("{e:#}")),
+                    Ok(()) => active.take_error(),
+                };
+                if let Some(error) = failure {
+                    warn!(error, "Webcam pipeline failed, stopping passthrough");
+                    bridge = None;
+                    report(WebcamStatus {
+                        active: false,
+                        device: None,
+                        error: Some(error),
+                    });
+                }
+            }
+            WebcamCommand::Stop => {
+                if bridge.take().is_some() {
+                    report(WebcamStatus {
+                        active: false,
+                        device: None,
+                        error: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn codec_names() {
+        assert_eq!(WebcamCodec::from_name("h264"), Some(WebcamCodec::H264));
+        assert_eq!(WebcamCodec::from_name("vp8"), Some(WebcamCodec::Vp8));
+        assert_eq!(WebcamCodec::from_name("av1"), None);
+    }
+
+    #[test]
+    fn finds_first_virtual_video_device() {
+        let root = std::env::temp_dir().join(format!("beam-test-{}", uuid::Uuid::new_v4()));
+        let class = root.join("class");
+        let real = root.join("devices/pci0000:00/usb1/video4linux");
+        let virt = root.join("devices/virtual/video4linux");
+        for dir in [&class, &real, &virt] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        for (name, parent) in [("video0", &real), ("video10", &virt), ("video2", &virt)] {
+            std::fs::create_dir(parent.join(name)).unwrap();
+            symlink(parent.join(name), class.join(name)).unwrap();
+        }
+
+        assert_eq!(
+            find_loopback_device(&class),
+            Some(PathBuf::from("/dev/video2"))
+        );
+        assert_eq!(find_loopback_device(&root.join("missing")), None);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
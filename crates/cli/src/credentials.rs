@@ -0,0 +1,106 @@
+//! The token saved by `beam login`, kept in
+//! `$XDG_CONFIG_HOME/beam/credentials.json` (default `~/.config/beam`).
+
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    /// Server the token was issued by
+    pub server: String,
+    pub username: String,
+    pub token: String,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("beam").join("credentials.json"))
+}
+
+/// Saved credentials, or None if there aren't any.
+pub fn load(path: &Path) -> Result<Option<Credentials>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let credentials = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid credentials file {}", path.display()))?;
+    Ok(Some(credentials))
+}
+
+/// Write `credentials` readable by the current user only.
+pub fn save(path: &Path, credentials: &Credentials) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    // Replace rather than truncate, so a file that was created with looser
+    // permissions doesn't keep them
+    let tmp = path.with_extension("json.tmp");
+    let _ = std::fs::remove_file(&tmp);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    file.write_all(serde_json::to_string_pretty(credentials)?.as_bytes())
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Delete saved credentials. Returns whether there were any.
+pub fn remove(path: &Path) -> Result<bool> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to remove {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn save_load_remove() {
+        let dir = std::env::temp_dir().join(format!("beam-cli-test-{}", std::process::id()));
+        let path = dir.join("beam").join("credentials.json");
+        assert_eq!(load(&path).unwrap(), None);
+
+        let credentials = Credentials {
+            server: "https://localhost:8444".into(),
+            username: "admin".into(),
+            token: "tok".into(),
+        };
+        save(&path, &credentials).unwrap();
+        assert_eq!(load(&path).unwrap(), Some(credentials.clone()));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Overwriting keeps the file private
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        save(&path, &credentials).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert!(remove(&path).unwrap());
+        assert!(!remove(&path).unwrap());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(load(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
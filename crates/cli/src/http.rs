@@ -0,0 +1,492 @@
+//! A small blocking HTTP/1.1 client for the Beam REST API. One request per
+//! connection, which is all a command-line tool needs.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where the server lives, parsed from `https://host[:port]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerUrl {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+}
+
+impl ServerUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            bail!("Server URL must start with https:// or http://: {url}");
+        };
+        let authority = rest.trim_end_matches('/');
+        if authority.contains('/') {
+            bail!("Server URL must not have a path: {url}");
+        }
+        // IPv6 literals are bracketed: https://[::1]:8444
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => {
+                let (host, after) = v6
+                    .split_once(']')
+                    .with_context(|| format!("Unterminated IPv6 address in {url}"))?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            bail!("Server URL has no host: {url}");
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .with_context(|| format!("Invalid port in {url}"))?,
+            None if tls => 443,
+            None => 80,
+        };
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// The server behind a WebSocket URL (`wss://host[:port]`), as agents
+    /// are given it, for REST calls alongside the socket.
+    pub fn from_ws(url: &str) -> Result<Self> {
+        let http = if let Some(rest) = url.strip_prefix("wss://") {
+            format!("https://{rest}")
+        } else if let Some(rest) = url.strip_prefix("ws://") {
+            format!("http://{rest}")
+        } else {
+            bail!("WebSocket URL must start with wss:// or ws://: {url}");
+        };
+        Self::parse(&http)
+    }
+}
+
+impl std::fmt::Display for ServerUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        if self.host.contains(':') {
+            write!(f, "{scheme}://[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{scheme}://{}:{}", self.host, self.port)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn json(&self) -> Result<serde_json::Value> {
+        serde_json::from_slice(&self.body).context("Server sent invalid JSON")
+    }
+
+    /// The server's explanation of a failed request: the `error` field of a
+    /// JSON body, or the body itself.
+    pub fn error_message(&self) -> String {
+        if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&self.body)
+            && let Some(error) = json["error"].as_str()
+        {
+            return error.to_string();
+        }
+        let text = String::from_utf8_lossy(&self.body).trim().to_string();
+        if text.is_empty() {
+            format!("HTTP {}", self.status)
+        } else {
+            text
+        }
+    }
+}
+
+pub struct Client {
+    server: ServerUrl,
+    tls: Arc<ClientConfig>,
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(server: ServerUrl, tls: Arc<ClientConfig>, token: Option<String>) -> Self {
+        Self { server, tls, token }
+    }
+
+    pub fn get(&self, path: &str) -> Result<Response> {
+        self.request("GET", path, None)
+    }
+
+    pub fn post(&self, path: &str, body: Option<&serde_json::Value>) -> Result<Response> {
+        self.request("POST", path, body)
+    }
+
+    pub fn delete(&self, path: &str) -> Result<Response> {
+        self.request("DELETE", path, None)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<Response> {
+        let body = body.map(serde_json::to_vec).transpose()?;
+        let head = request_head(
+            method,
+            path,
+            &self.server,
+            self.token.as_deref(),
+            body.as_ref().map(Vec::len),
+        );
+
+        let tcp = self.connect()?;
+        let mut raw = Vec::new();
+        if self.server.tls {
+            let name = ServerName::try_from(self.server.host.clone())
+                .with_context(|| format!("Invalid server name {}", self.server.host))?;
+            let conn = rustls::ClientConnection::new(Arc::clone(&self.tls), name)
+                .context("Failed to start TLS")?;
+            let mut stream = rustls::StreamOwned::new(conn, tcp);
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .with_context(|| format!("TLS handshake with {} failed", self.server))?;
+            exchange(&mut stream, &head, body.as_deref(), &mut raw)?;
+        } else {
+            let mut stream = tcp;
+            exchange(&mut stream, &head, body.as_deref(), &mut raw)?;
+        }
+        parse_response(&raw)
+    }
+
+    fn connect(&self) -> Result<TcpStream> {
+        let addrs = (self.server.host.as_str(), self.server.port)
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", self.server.host))?;
+        let mut last_err = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(tcp) => {
+                    tcp.set_read_timeout(Some(IO_TIMEOUT))?;
+                    tcp.set_write_timeout(Some(IO_TIMEOUT))?;
+                    return Ok(tcp);
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e).with_context(|| format!("Failed to connect to {}", self.server)),
+            None => bail!("No addresses found for {}", self.server.host),
+        }
+    }
+}
+
+fn request_head(
+    method: &str,
+    path: &str,
+    server: &ServerUrl,
+    token: Option<&str>,
+    content_length: Option<usize>,
+) -> String {
+    let host = if server.host.contains(':') {
+        format!("[{}]:{}", server.host, server.port)
+    } else {
+        format!("{}:{}", server.host, server.port)
+    };
+    let mut head = format!(
+        "{method} {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         User-Agent: beam-cli/{}\r\n\
+         Accept: application/json\r\n\
+         Connection: close\r\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    if let Some(len) = content_length {
+        head.push_str(&format!(
+            "Content-Type: application/json\r\nContent-Length: {len}\r\n"
+        ));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+fn exchange(
+    stream: &mut (impl Read + Write),
+    head: &str,
+    body: Option<&[u8]>,
+    raw: &mut Vec<u8>,
+) -> Result<()> {
+    stream
+        .write_all(head.as_bytes())
+        .context("Failed to send request")?;
+    if let Some(body) = body {
+        stream.write_all(body).context("Failed to send request")?;
+    }
+    stream.flush().context("Failed to send request")?;
+    match stream.read_to_end(raw) {
+        Ok(_) => Ok(()),
+        // Plenty of servers close without a TLS close_notify; whatever
+        // arrived is checked against the framing headers below
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !raw.is_empty() => Ok(()),
+        Err(e) => Err(e).context("Failed to read response"),
+    }
+}
+
+/// Parse a complete HTTP/1.1 response read up to connection close.
+fn parse_response(raw: &[u8]) -> Result<Response> {
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .context("Truncated response headers")?;
+    let head = std::str::from_utf8(&raw[..split]).context("Invalid response headers")?;
+    let rest = &raw[split + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .context("Invalid HTTP status line")?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(value.parse::<usize>().context("Invalid Content-Length")?);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    let body = if chunked {
+        decode_chunked(rest)?
+    } else if let Some(len) = content_length {
+        if rest.len() < len {
+            bail!("Truncated response body");
+        }
+        rest[..len].to_vec()
+    } else {
+        rest.to_vec()
+    };
+    Ok(Response { status, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .context("Truncated chunked body")?;
+        let size_field = std::str::from_utf8(&data[..line_end]).context("Invalid chunk size")?;
+        // Chunk extensions follow a ';'
+        let size_field = size_field.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_field, 16).context("Invalid chunk size")?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 {
+            bail!("Truncated chunked body");
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+/// TLS settings: the platform's roots plus `ca`, for servers using the
+/// self-signed certificate Beam generates. `insecure` skips verification.
+pub fn tls_config(ca: Option<&Path>, insecure: bool) -> Result<Arc<ClientConfig>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS")?;
+
+    if insecure {
+        let config = builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+            .with_no_client_auth();
+        return Ok(Arc::new(config));
+    }
+
+    let mut roots = rustls::RootCertStore::empty();
+    // Missing system roots only matter if the server has a public cert
+    for cert in rustls_native_certs::load_native_certs().certs {
+        let _ = roots.add(cert);
+    }
+    if let Some(ca) = ca {
+        let pem = std::fs::read(ca)
+            .with_context(|| format!("Failed to read CA certificate {}", ca.display()))?;
+        let mut added = 0;
+        for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+            let cert = cert.with_context(|| format!("Invalid PEM in {}", ca.display()))?;
+            roots
+                .add(cert)
+                .with_context(|| format!("Unusable certificate in {}", ca.display()))?;
+            added += 1;
+        }
+        if added == 0 {
+            bail!("No certificates found in {}", ca.display());
+        }
+    }
+    Ok(Arc::new(
+        builder.with_root_certificates(roots).with_no_client_auth(),
+    ))
+}
+
+/// Verifier for `--insecure`: accepts any certificate, but still checks
+/// that the handshake was signed by it.
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_server_urls() {
+        let url = ServerUrl::parse("https://beam.example.com:8444/").unwrap();
+        assert_eq!(
+            url,
+            ServerUrl {
+                tls: true,
+                host: "beam.example.com".into(),
+                port: 8444
+            }
+        );
+        assert_eq!(url.to_string(), "https://beam.example.com:8444");
+
+        assert_eq!(ServerUrl::parse("https://beam").unwrap().port, 443);
+        assert_eq!(ServerUrl::parse("http://beam").unwrap().port, 80);
+
+        let v6 = ServerUrl::parse("https://[::1]:8444").unwrap();
+        assert_eq!(v6.host, "::1");
+        assert_eq!(v6.port, 8444);
+        assert_eq!(v6.to_string(), "https://[::1]:8444");
+
+        assert!(ServerUrl::parse("beam:8444").is_err());
+        assert!(ServerUrl::parse("https://beam:port").is_err());
+        assert!(ServerUrl::parse("https://beam/api").is_err());
+        assert!(ServerUrl::parse("https://:8444").is_err());
+
+        let ws = ServerUrl::from_ws("wss://127.0.0.1:8444").unwrap();
+        assert_eq!(ws.to_string(), "https://127.0.0.1:8444");
+        assert!(!ServerUrl::from_ws("ws://localhost:9000").unwrap().tls);
+        assert!(ServerUrl::from_ws("https://beam").is_err());
+    }
+
+    #[test]
+    fn request_head_carries_token_and_body_length() {
+        let server = ServerUrl::parse("https://[::1]:8444").unwrap();
+        let head = request_head("POST", "/api/admin/drain", &server, Some("tok"), Some(2));
+        assert!(head.starts_with("POST /api/admin/drain HTTP/1.1\r\nHost: [::1]:8444\r\n"));
+        assert!(head.contains("Authorization: Bearer tok\r\n"));
+        assert!(head.contains("Content-Length: 2\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+
+        let head = request_head("GET", "/api/health", &server, None, None);
+        assert!(!head.contains("Authorization"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn parses_responses() {
+        let raw = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}trailing";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body, b"{}");
+
+        let raw = b"HTTP/1.1 403 Forbidden\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\n{\"err\r\n9;x=1\r\nor\":\"no\"}\r\n0\r\n\r\n";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.status, 403);
+        assert!(!resp.is_success());
+        assert_eq!(resp.error_message(), "no");
+
+        let raw = b"HTTP/1.1 404 Not Found\r\n\r\nSession not found";
+        assert_eq!(
+            parse_response(raw).unwrap().error_message(),
+            "Session not found"
+        );
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n{}").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,4 @@
+//! Blocking REST client for a Beam server, used by the `beam` command-line
+//! tool, `beam-testclient` and agents for their handshake.
+
+pub mod http;
@@ -0,0 +1,708 @@
+mod credentials;
+mod output;
+
+use std::io::{BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use beam_protocol::{AuthRequest, BeamConfig};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use beam_cli::http::{self, Client, Response, ServerUrl};
+use credentials::Credentials;
+use output::Format;
+
+const DEFAULT_SERVER: &str = "https://localhost:8444";
+
+struct Args {
+    server: Option<String>,
+    ca: Option<PathBuf>,
+    insecure: bool,
+    output: Option<Format>,
+    username: Option<String>,
+    password_stdin: bool,
+    command: Vec<String>,
+}
+
+fn print_help() {
+    println!("beam - Beam Remote Desktop command-line client");
+    println!();
+    println!("USAGE:");
+    println!("    beam [OPTIONS] <COMMAND>");
+    println!();
+    println!("COMMANDS:");
+    println!("    login                    Sign in and save a token for later commands");
+    println!("    logout                   Forget the saved token");
+    println!("    sessions list            List all sessions (admin)");
+    println!("    sessions kill <ID> [SECS]");
+    println!("                             End a session, or count its user down to it (admin)");
+    println!("    sessions keep <ID>       Call off a session's scheduled end (admin)");
+    println!("    sessions notify <ID> <MESSAGE>");
+    println!("                             Show the session's user a message (admin)");
+    println!(
+        "    sessions upgrade <ID>    Restart a session's agent on the installed binary (admin)"
+    );
+    println!("    agents upgrade           Restart every agent on the installed binary (admin)");
+    println!(
+        "    stats <ID>               Latency, pipeline and encoder stats of a session (admin)"
+    );
+    println!("    events <ID>              What happened to a session so far (admin)");
+    println!("    drain                    Stop new sessions and exit once idle (admin)");
+    println!("    bans list                Addresses banned for failed logins (admin)");
+    println!("    bans lift <IP>           End an address's ban (admin)");
+    println!("    config validate [PATH]   Check a config file [default: /etc/beam/beam.toml]");
+    println!();
+    println!("OPTIONS:");
+    println!(
+        "    -s, --server <URL>       Server URL [env: BEAM_SERVER, default: {DEFAULT_SERVER}]"
+    );
+    println!(
+        "        --ca <PATH>          Trust this PEM certificate, e.g. /var/lib/beam/server-cert.pem"
+    );
+    println!("    -k, --insecure           Don't verify the server's certificate");
+    println!(
+        "    -o, --output <FORMAT>    json or table [default: table on a terminal, else json]"
+    );
+    println!("    -u, --username <NAME>    User to log in as [default: $USER]");
+    println!("        --password-stdin     Read the login password from stdin");
+    println!("    -V, --version            Print version and exit");
+    println!("    -h, --help               Print this help and exit");
+    println!();
+    println!("A token in BEAM_TOKEN is used instead of the one saved by `beam login`.");
+}
+
+fn parse_args() -> Args {
+    let args: Vec<String> = std::env::args().collect();
+    let mut parsed = Args {
+        server: None,
+        ca: None,
+        insecure: false,
+        output: None,
+        username: None,
+        password_stdin: false,
+        command: Vec::new(),
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-V" | "--version" => {
+                println!("beam {}", env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            "-h" | "--help" => {
+                print_help();
+                std::process::exit(0);
+            }
+            "--server" | "-s" if i + 1 < args.len() => {
+                parsed.server = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "--ca" if i + 1 < args.len() => {
+                parsed.ca = Some(PathBuf::from(&args[i + 1]));
+                i += 1;
+            }
+            "--insecure" | "-k" => parsed.insecure = true,
+            "--output" | "-o" if i + 1 < args.len() => {
+                match Format::parse(&args[i + 1]) {
+                    Some(format) => parsed.output = Some(format),
+                    None => {
+                        eprintln!("beam: unknown output format: {}", args[i + 1]);
+                        std::process::exit(2);
+                    }
+                }
+                i += 1;
+            }
+            "--username" | "-u" if i + 1 < args.len() => {
+                parsed.username = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "--password-stdin" => parsed.password_stdin = true,
+            other if other.starts_with('-') => {
+                eprintln!("beam: unknown argument: {other}");
+                std::process::exit(2);
+            }
+            other => parsed.command.push(other.to_string()),
+        }
+        i += 1;
+    }
+    parsed
+}
+
+/// Server, TLS settings and saved token shared by the commands that talk
+/// to the server.
+struct Api {
+    server: ServerUrl,
+    args: Args,
+    format: Format,
+    credentials_path: Option<PathBuf>,
+    saved: Option<Credentials>,
+}
+
+impl Api {
+    fn new(args: Args) -> Result<Self> {
+        let credentials_path = credentials::default_path();
+        let saved = match &credentials_path {
+            Some(path) => credentials::load(path)?,
+            None => None,
+        };
+        let url = args
+            .server
+            .clone()
+            .or_else(|| std::env::var("BEAM_SERVER").ok().filter(|s| !s.is_empty()))
+            .or_else(|| saved.as_ref().map(|c| c.server.clone()))
+            .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+        let format = args.output.unwrap_or(if std::io::stdout().is_terminal() {
+            Format::Table
+        } else {
+            Format::Json
+        });
+        Ok(Self {
+            server: ServerUrl::parse(&url)?,
+            args,
+            format,
+            credentials_path,
+            saved,
+        })
+    }
+
+    fn client(&self, token: Option<String>) -> Result<Client> {
+        let tls = http::tls_config(self.args.ca.as_deref(), self.args.insecure)?;
+        Ok(Client::new(self.server.clone(), tls, token))
+    }
+
+    /// Client carrying BEAM_TOKEN or the token saved for this server.
+    fn authed_client(&self) -> Result<Client> {
+        let token = std::env::var("BEAM_TOKEN")
+            .ok()
+            .filter(|t| !t.is_empty())
+            .or_else(|| {
+                self.saved
+                    .as_ref()
+                    .filter(|c| ServerUrl::parse(&c.server).ok().as_ref() == Some(&self.server))
+                    .map(|c| c.token.clone())
+            });
+        let Some(token) = token else {
+            bail!("Not logged in to {}; run `beam login`", self.server);
+        };
+        self.client(Some(token))
+    }
+
+    /// Turn an error response into an error that says what to do about it.
+    fn check(&self, response: Response) -> Result<Response> {
+        match response.status {
+            _ if response.is_success() => Ok(response),
+            401 => bail!(
+                "{} rejected the token ({}); run `beam login` again",
+                self.server,
+                response.error_message()
+            ),
+            status => bail!("{} (HTTP {status})", response.error_message()),
+        }
+    }
+}
+
+fn main() {
+    let args = parse_args();
+    if let Err(e) = run(args) {
+        eprintln!("beam: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let command: Vec<&str> = args.command.iter().map(String::as_str).collect();
+    match command.as_slice() {
+        ["config", "validate"] => validate_config(Path::new("/etc/beam/beam.toml"), &args),
+        ["config", "validate", path] => validate_config(Path::new(path), &args),
+        ["login"] => login(&Api::new(args)?),
+        ["logout"] => logout(&Api::new(args)?),
+        ["sessions"] | ["sessions", "list"] => list_sessions(&Api::new(args)?),
+        ["sessions", "kill", id] => {
+            let id = parse_session_id(id)?;
+            kill_session(&Api::new(args)?, id, 0)
+        }
+        ["sessions", "kill", id, secs] => {
+            let id = parse_session_id(id)?;
+            let secs = secs
+                .parse()
+                .with_context(|| format!("Not a number of seconds: {secs}"))?;
+            kill_session(&Api::new(args)?, id, secs)
+        }
+        ["sessions", "keep", id] => {
+            let id = parse_session_id(id)?;
+            keep_session(&Api::new(args)?, id)
+        }
+        ["sessions", "notify", id, message @ ..] if !message.is_empty() => {
+            let id = parse_session_id(id)?;
+            let message = message.join(" ");
+            notify_session(&Api::new(args)?, id, &message)
+        }
+        ["sessions", "upgrade", id] => {
+            let id = parse_session_id(id)?;
+            upgrade_session(&Api::new(args)?, id)
+        }
+        ["agents", "upgrade"] => upgrade_agents(&Api::new(args)?),
+        ["stats", id] => {
+            let id = parse_session_id(id)?;
+            session_stats(&Api::new(args)?, id)
+        }
+        ["events", id] => {
+            let id = parse_session_id(id)?;
+            session_events(&Api::new(args)?, id)
+        }
+        ["drain"] => drain(&Api::new(args)?),
+        ["bans"] | ["bans", "list"] => list_bans(&Api::new(args)?),
+        ["bans", "lift", ip] => {
+            let ip = ip.to_string();
+            lift_ban(&Api::new(args)?, &ip)
+        }
+        [] => {
+            print_help();
+            std::process::exit(2);
+        }
+        _ => {
+            eprintln!(
+                "beam: unknown command: {} (see beam --help)",
+                args.command.join(" ")
+            );
+            std::process::exit(2);
+        }
+    }
+}
+
+fn parse_session_id(id: &str) -> Result<Uuid> {
+    Uuid::parse_str(id).with_context(|| format!("Not a session ID: {id}"))
+}
+
+fn print_json(value: &Value) {
+    println!("{value:#}");
+}
+
+fn print_fields(value: &Value) {
+    let rows: Vec<Vec<String>> = output::flatten(value)
+        .into_iter()
+        .map(|(key, value)| vec![key, value])
+        .collect();
+    print!("{}", output::table(&["FIELD", "VALUE"], &rows));
+}
+
+fn read_password(from_stdin: bool) -> Result<String> {
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    if from_stdin {
+        stdin
+            .lock()
+            .read_line(&mut line)
+            .context("Failed to read password")?;
+    } else {
+        if !stdin.is_terminal() {
+            bail!("No terminal to prompt for a password on; use --password-stdin");
+        }
+        let stty = |arg: &str| {
+            std::process::Command::new("stty")
+                .arg(arg)
+                .stdin(std::process::Stdio::inherit())
+                .status()
+        };
+        eprint!("Password: ");
+        let hidden = stty("-echo").is_ok_and(|s| s.success());
+        let read = stdin.lock().read_line(&mut line);
+        if hidden {
+            let _ = stty("echo");
+            eprintln!();
+        }
+        read.context("Failed to read password")?;
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn login(api: &Api) -> Result<()> {
+    let username = match api.args.username.clone() {
+        Some(name) => name,
+        None => std::env::var("USER").context("No username given; use --username")?,
+    };
+    let password = read_password(api.args.password_stdin)?;
+    let request = AuthRequest {
+        username: username.clone(),
+        password,
+        viewport_width: None,
+        viewport_height: None,
+        idle_timeout: None,
+        system_combos: None,
+        nettest: None,
+        token_only: true,
+        takeover: false,
+        assist: false,
+        desktop: None,
+    };
+
+    let response = api
+        .client(None)?
+        .post("/api/auth/login", Some(&serde_json::to_value(&request)?))?;
+    if !response.is_success() {
+        bail!(
+            "Login failed: {} (HTTP {})",
+            response.error_message(),
+            response.status
+        );
+    }
+    let body = response.json()?;
+    let token = body["token"]
+        .as_str()
+        .context("Login response has no token")?
+        .to_string();
+    if body.get("session_id").is_some() {
+        eprintln!(
+            "beam: warning: {} doesn't support token-only login and started a desktop session",
+            api.server
+        );
+    }
+
+    let path = api
+        .credentials_path
+        .as_deref()
+        .context("Can't tell where to save the token: neither XDG_CONFIG_HOME nor HOME is set")?;
+    credentials::save(
+        path,
+        &Credentials {
+            server: api.server.to_string(),
+            username: username.clone(),
+            token,
+        },
+    )?;
+    eprintln!("Logged in to {} as {username}", api.server);
+    Ok(())
+}
+
+fn logout(api: &Api) -> Result<()> {
+    let removed = match &api.credentials_path {
+        Some(path) => credentials::remove(path)?,
+        None => false,
+    };
+    if removed {
+        eprintln!("Removed the saved token");
+    } else {
+        eprintln!("No saved token");
+    }
+    Ok(())
+}
+
+fn list_sessions(api: &Api) -> Result<()> {
+    let response = api.check(api.authed_client()?.get("/api/admin/sessions")?)?;
+    let sessions = response.json()?;
+    if api.format == Format::Json {
+        print_json(&sessions);
+        return Ok(());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let since = |value: &Value| match value.as_u64() {
+        Some(t) => output::format_duration(now.saturating_sub(t)),
+        None => "-".to_string(),
+    };
+    let link = |value: &Value| {
+        if value["connected"].as_bool() == Some(true) {
+            "connected".to_string()
+        } else {
+            "-".to_string()
+        }
+    };
+    // What the agent last reported; sessions on a fallback encoder stand out
+    let encoder = |info: &Value| match info["encoder"].as_str() {
+        Some(name) if info["fallback"].as_bool() == Some(true) => format!("{name} (fallback)"),
+        Some(name) => name.to_string(),
+        None => "-".to_string(),
+    };
+    let stream = |info: &Value| match (
+        info["width"].as_u64(),
+        info["height"].as_u64(),
+        info["framerate"].as_u64(),
+    ) {
+        (Some(w), Some(h), Some(fps)) => format!("{w}x{h}@{fps}"),
+        _ => "-".to_string(),
+    };
+    let rows: Vec<Vec<String>> = sessions
+        .as_array()
+        .context("Expected a list of sessions")?
+        .iter()
+        .map(|s| {
+            vec![
+                output::scalar(&s["id"]),
+                output::scalar(&s["username"]),
+                format!(":{}", output::scalar(&s["display"])),
+                since(&s["created_at"]),
+                since(&s["last_activity"]),
+                link(&s["browser"]),
+                link(&s["agent"]),
+                encoder(&s["agent_info"]),
+                stream(&s["agent_info"]),
+            ]
+        })
+        .collect();
+    print!(
+        "{}",
+        output::table(
+            &[
+                "ID", "USER", "DISPLAY", "AGE", "IDLE", "BROWSER", "AGENT", "ENCODER", "STREAM"
+            ],
+            &rows
+        )
+    );
+    Ok(())
+}
+
+/// End a session now, or `after` seconds from now with a countdown
+fn kill_session(api: &Api, id: Uuid, after: u64) -> Result<()> {
+    if after == 0 {
+        api.check(
+            api.authed_client()?
+                .delete(&format!("/api/admin/sessions/{id}"))?,
+        )?;
+        match api.format {
+            Format::Json => print_json(&json!({ "id": id, "destroyed": true })),
+            Format::Table => println!("Session {id} destroyed"),
+        }
+        return Ok(());
+    }
+    let response = api.check(
+        api.authed_client()?
+            .delete(&format!("/api/admin/sessions/{id}?after={after}"))?,
+    )?;
+    let scheduled = response.json()?;
+    match api.format {
+        Format::Json => print_json(&scheduled),
+        Format::Table => println!("Session {id} ends in {after} seconds"),
+    }
+    Ok(())
+}
+
+fn keep_session(api: &Api, id: Uuid) -> Result<()> {
+    api.check(
+        api.authed_client()?
+            .delete(&format!("/api/admin/sessions/{id}/termination"))?,
+    )?;
+    match api.format {
+        Format::Json => print_json(&json!({ "id": id, "cancelled": true })),
+        Format::Table => println!("Session {id} will stay open"),
+    }
+    Ok(())
+}
+
+fn notify_session(api: &Api, id: Uuid, message: &str) -> Result<()> {
+    let response = api.check(api.authed_client()?.post(
+        &format!("/api/admin/sessions/{id}/notify"),
+        Some(&json!({ "message": message })),
+    )?)?;
+    let delivered = response.json()?;
+    match api.format {
+        Format::Json => print_json(&delivered),
+        Format::Table => {
+            let shown_in: Vec<&str> = [("browser", "the browser"), ("desktop", "the desktop")]
+                .into_iter()
+                .filter(|(key, _)| delivered[*key] == true)
+                .map(|(_, place)| place)
+                .collect();
+            if shown_in.is_empty() {
+                println!("Nobody is connected to session {id} to see the message");
+            } else {
+                println!("Message shown in {}", shown_in.join(" and "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn upgrade_session(api: &Api, id: Uuid) -> Result<()> {
+    let response = api.check(
+        api.authed_client()?
+            .post(&format!("/api/admin/sessions/{id}/upgrade"), None)?,
+    )?;
+    let upgrade = response.json()?;
+    match api.format {
+        Format::Json => print_json(&upgrade),
+        Format::Table => println!(
+            "Agent of session {id} upgrading to {}",
+            output::scalar(&upgrade["path"])
+        ),
+    }
+    Ok(())
+}
+
+fn upgrade_agents(api: &Api) -> Result<()> {
+    let response = api.check(
+        api.authed_client()?
+            .post("/api/admin/agents/upgrade", None)?,
+    )?;
+    let upgrades = response.json()?;
+    if api.format == Format::Json {
+        print_json(&upgrades);
+        return Ok(());
+    }
+    // Skipped agents are away, or too old to upgrade in place
+    let rows: Vec<Vec<String>> = ["upgrading", "skipped"]
+        .into_iter()
+        .flat_map(|status| {
+            upgrades[status]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(move |id| vec![output::scalar(id), status.to_string()])
+        })
+        .collect();
+    print!("{}", output::table(&["ID", "AGENT"], &rows));
+    Ok(())
+}
+
+fn session_stats(api: &Api, id: Uuid) -> Result<()> {
+    let response = api.check(
+        api.authed_client()?
+            .get(&format!("/api/admin/sessions/{id}/stats"))?,
+    )?;
+    let stats = response.json()?;
+    match api.format {
+        Format::Json => print_json(&stats),
+        Format::Table => print_fields(&stats),
+    }
+    Ok(())
+}
+
+fn session_events(api: &Api, id: Uuid) -> Result<()> {
+    let response = api.check(
+        api.authed_client()?
+            .get(&format!("/api/admin/sessions/{id}/events"))?,
+    )?;
+    let events = response.json()?;
+    if api.format == Format::Json {
+        print_json(&events);
+        return Ok(());
+    }
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let rows: Vec<Vec<String>> = events["events"]
+        .as_array()
+        .context("Expected a list of events")?
+        .iter()
+        .map(|e| {
+            let ago = e["at_ms"].as_u64().map_or_else(
+                || "-".to_string(),
+                |t| output::format_duration(now_ms.saturating_sub(t) / 1000),
+            );
+            // Whatever else the event carries, e.g. "peer=browser reason=timeout"
+            let details = output::flatten(e)
+                .into_iter()
+                .filter(|(key, _)| key != "at_ms" && key != "event")
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            vec![ago, output::scalar(&e["event"]), details]
+        })
+        .collect();
+    print!("{}", output::table(&["AGO", "EVENT", "DETAILS"], &rows));
+    Ok(())
+}
+
+fn drain(api: &Api) -> Result<()> {
+    let response = api.check(api.authed_client()?.post("/api/admin/drain", None)?)?;
+    let status = response.json()?;
+    match api.format {
+        Format::Json => print_json(&status),
+        Format::Table => print_fields(&status),
+    }
+    Ok(())
+}
+
+fn list_bans(api: &Api) -> Result<()> {
+    let response = api.check(api.authed_client()?.get("/api/admin/bans")?)?;
+    let bans = response.json()?;
+    if api.format == Format::Json {
+        print_json(&bans);
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = bans
+        .as_array()
+        .context("Expected a list of bans")?
+        .iter()
+        .map(|b| {
+            vec![
+                output::scalar(&b["ip"]),
+                output::scalar(&b["strikes"]),
+                b["remaining_secs"]
+                    .as_u64()
+                    .map_or_else(|| "-".to_string(), output::format_duration),
+            ]
+        })
+        .collect();
+    print!(
+        "{}",
+        output::table(&["ADDRESS", "BANS", "REMAINING"], &rows)
+    );
+    Ok(())
+}
+
+fn lift_ban(api: &Api, ip: &str) -> Result<()> {
+    // IPv6 bans are listed by /64 prefix
+    let path = format!("/api/admin/bans/{}", ip.replace('/', "%2F"));
+    api.check(api.authed_client()?.delete(&path)?)?;
+    match api.format {
+        Format::Json => print_json(&json!({ "ip": ip, "lifted": true })),
+        Format::Table => println!("Ban on {ip} lifted"),
+    }
+    Ok(())
+}
+
+/// The checks `beam-server` runs at startup, without starting it. Exits 1
+/// if the server would refuse the file.
+fn validate_config(path: &Path, args: &Args) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let (errors, warnings) = match toml::from_str::<BeamConfig>(&text) {
+        Ok(config) => {
+            let issues = config.validate().err().unwrap_or_default();
+            issues
+                .into_iter()
+                .partition(|issue| issue.starts_with("ERROR:"))
+        }
+        Err(e) => (vec![format!("ERROR: {e}")], Vec::new()),
+    };
+
+    let json_output = match args.output {
+        Some(format) => format == Format::Json,
+        None => !std::io::stdout().is_terminal(),
+    };
+    if json_output {
+        print_json(&json!({
+            "path": path,
+            "valid": errors.is_empty(),
+            "errors": errors,
+            "warnings": warnings,
+        }));
+    } else {
+        for issue in errors.iter().chain(&warnings) {
+            println!("{issue}");
+        }
+        if errors.is_empty() && warnings.is_empty() {
+            println!("{}: OK", path.display());
+        } else {
+            println!(
+                "{}: {} errors, {} warnings",
+                path.display(),
+                errors.len(),
+                warnings.len()
+            );
+        }
+    }
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
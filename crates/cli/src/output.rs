@@ -0,0 +1,139 @@
+//! Rendering API responses as JSON or as aligned text tables.
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Table,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "table" => Some(Self::Table),
+            _ => None,
+        }
+    }
+}
+
+/// Left-aligned columns separated by two spaces. The last column isn't
+/// padded, so lines carry no trailing whitespace.
+pub fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    let header_row: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header_row).chain(rows) {
+        let last = row.len().saturating_sub(1);
+        for (i, cell) in row.iter().enumerate() {
+            if i == last {
+                out.push_str(cell);
+            } else {
+                let pad = widths[i] - cell.chars().count();
+                out.push_str(cell);
+                out.push_str(&" ".repeat(pad + 2));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Leaf values of a JSON object as `(dotted.key, value)` pairs. Nulls
+/// show as "-".
+pub fn flatten(value: &Value) -> Vec<(String, String)> {
+    fn walk(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                for (key, value) in map {
+                    let key = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+                    walk(&key, value, out);
+                }
+            }
+            _ => out.push((prefix.to_string(), scalar(value))),
+        }
+    }
+    let mut out = Vec::new();
+    walk("", value, &mut out);
+    out
+}
+
+/// A JSON value as a table cell.
+pub fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(scalar).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+/// A duration in seconds as e.g. "2d3h", "5h12m", "4m10s" or "9s".
+pub fn format_duration(secs: u64) -> String {
+    let (d, h, m, s) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    match (d, h, m) {
+        (0, 0, 0) => format!("{s}s"),
+        (0, 0, _) => format!("{m}m{s}s"),
+        (0, _, _) => format!("{h}h{m}m"),
+        _ => format!("{d}d{h}h"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn table_aligns_columns() {
+        let rows = vec![
+            vec!["alice".to_string(), ":10".to_string(), "yes".to_string()],
+            vec!["bob".to_string(), ":1000".to_string(), "-".to_string()],
+        ];
+        assert_eq!(
+            table(&["USER", "DISPLAY", "BROWSER"], &rows),
+            "USER   DISPLAY  BROWSER\n\
+             alice  :10      yes\n\
+             bob    :1000    -\n"
+        );
+        assert_eq!(table(&["ID"], &[]), "ID\n");
+    }
+
+    #[test]
+    fn flatten_uses_dotted_keys() {
+        let value = json!({
+            "session_id": "abc",
+            "latency": { "p50_ms": 12.5, "samples": 30 },
+            "encoder": null,
+            "caps": ["a", "b"],
+            "empty": {},
+        });
+        let flat = flatten(&value);
+        let get = |key: &str| flat.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        assert_eq!(get("session_id"), Some("abc"));
+        assert_eq!(get("latency.p50_ms"), Some("12.5"));
+        assert_eq!(get("latency.samples"), Some("30"));
+        assert_eq!(get("encoder"), Some("-"));
+        assert_eq!(get("caps"), Some("a,b"));
+        assert_eq!(get("empty"), Some("{}"));
+    }
+
+    #[test]
+    fn durations_are_compact() {
+        assert_eq!(format_duration(9), "9s");
+        assert_eq!(format_duration(250), "4m10s");
+        assert_eq!(format_duration(5 * 3600 + 12 * 60 + 7), "5h12m");
+        assert_eq!(format_duration(2 * 86400 + 3 * 3600), "2d3h");
+    }
+}
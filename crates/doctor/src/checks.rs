@@ -0,0 +1,619 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use beam_protocol::{AudioBackend, AuthConfig, ServerConfig};
+
+use crate::{Report, Status, cert_validity, format_date};
+
+/// Self-signed certificate the server generates without `tls_cert`.
+const SELF_SIGNED_CERT: &str = "/var/lib/beam/server-cert.pem";
+
+/// Certificates closer than this to expiry are a warning.
+const CERT_EXPIRY_WARN_DAYS: i64 = 30;
+
+/// Encoders the agent tries, in order, with the package providing each.
+const ENCODERS: [(&str, &str); 3] = [
+    ("nvh264enc", "gstreamer1.0-plugins-bad"),
+    ("vah264enc", "gstreamer1.0-vaapi"),
+    ("x264enc", "gstreamer1.0-plugins-ugly"),
+];
+
+/// Other elements in the agent's pipelines.
+const ELEMENTS: [(&str, &str); 4] = [
+    ("appsrc", "gstreamer1.0-plugins-base"),
+    ("appsink", "gstreamer1.0-plugins-base"),
+    ("videoconvert", "gstreamer1.0-plugins-base"),
+    ("h264parse", "gstreamer1.0-plugins-bad"),
+];
+
+const XORG_DRIVER_DIRS: [&str; 3] = [
+    "/usr/lib/xorg/modules/drivers",
+    "/usr/lib64/xorg/modules/drivers",
+    "/usr/local/lib/xorg/modules/drivers",
+];
+
+/// Whether an executable named `name` is on `PATH`.
+pub(crate) fn on_path(name: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|dir| {
+            std::fs::metadata(dir.join(name)).is_ok_and(|m| {
+                use std::os::unix::fs::PermissionsExt;
+                m.is_file() && m.permissions().mode() & 0o111 != 0
+            })
+        })
+    })
+}
+
+/// Run a command quietly, returning its stdout if it succeeded.
+pub(crate) fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn gst_has_element(name: &str) -> bool {
+    command_output("gst-inspect-1.0", &[name]).is_some()
+}
+
+/// (major, minor) from `gst-inspect-1.0 --version` output.
+fn parse_gst_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.lines().next()?.split_whitespace().last()?;
+    let mut parts = version.split('.');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+pub(crate) fn gstreamer(report: &mut Report) {
+    const S: &str = "gstreamer";
+    let Some(output) = command_output("gst-inspect-1.0", &["--version"]) else {
+        report.push(
+            S,
+            "gstreamer",
+            Status::Fail,
+            "GStreamer not found — install gstreamer1.0-tools",
+        );
+        return;
+    };
+    match parse_gst_version(&output) {
+        Some((major, minor)) if (major, minor) >= (1, 24) => report.push(
+            S,
+            "gstreamer",
+            Status::Ok,
+            format!("GStreamer {major}.{minor}"),
+        ),
+        Some((major, minor)) if (major, minor) >= (1, 20) => report.push(
+            S,
+            "gstreamer",
+            Status::Warn,
+            format!("GStreamer {major}.{minor} (minimum: 1.20, recommended: 1.24+)"),
+        ),
+        Some((major, minor)) => report.push(
+            S,
+            "gstreamer",
+            Status::Fail,
+            format!("GStreamer {major}.{minor} is too old (need >= 1.24)"),
+        ),
+        None => report.push(
+            S,
+            "gstreamer",
+            Status::Warn,
+            "Could not read the GStreamer version",
+        ),
+    }
+
+    for (element, package) in ELEMENTS {
+        if gst_has_element(element) {
+            report.push(
+                S,
+                format!("element_{element}"),
+                Status::Ok,
+                format!("{element} available"),
+            );
+        } else {
+            report.push(
+                S,
+                format!("element_{element}"),
+                Status::Fail,
+                format!("{element} not found — install {package}"),
+            );
+        }
+    }
+
+    let mut any_encoder = false;
+    for (encoder, package) in ENCODERS {
+        if gst_has_element(encoder) {
+            any_encoder = true;
+            report.push(
+                S,
+                format!("encoder_{encoder}"),
+                Status::Ok,
+                format!("{encoder} encoder available"),
+            );
+        } else {
+            report.push(
+                S,
+                format!("encoder_{encoder}"),
+                Status::Skip,
+                format!("{encoder} not installed ({package})"),
+            );
+        }
+    }
+    if !any_encoder {
+        report.push(
+            S,
+            "h264_encoder",
+            Status::Fail,
+            "No H.264 encoder found — install gstreamer1.0-plugins-bad (NVIDIA), \
+             gstreamer1.0-vaapi (AMD/Intel), or gstreamer1.0-plugins-ugly (software)",
+        );
+    }
+}
+
+pub(crate) fn gpu(report: &mut Report) {
+    const S: &str = "gpu";
+    if on_path("nvidia-smi") {
+        match command_output("nvidia-smi", &["--query-gpu=name", "--format=csv,noheader"]) {
+            Some(name) => report.push(
+                S,
+                "gpu",
+                Status::Ok,
+                format!("NVIDIA GPU: {}", name.lines().next().unwrap_or("").trim()),
+            ),
+            None => report.push(
+                S,
+                "gpu",
+                Status::Warn,
+                "nvidia-smi found but failed — check GPU driver",
+            ),
+        }
+        return;
+    }
+    let vendor = std::fs::read_to_string("/sys/class/drm/card0/device/vendor").unwrap_or_default();
+    match vendor.trim() {
+        "0x1002" => report.push(S, "gpu", Status::Ok, "AMD GPU detected"),
+        "0x8086" => report.push(S, "gpu", Status::Ok, "Intel GPU detected"),
+        _ => report.push(
+            S,
+            "gpu",
+            Status::Warn,
+            "No dedicated GPU detected — will use software encoding (x264)",
+        ),
+    }
+}
+
+pub(crate) fn display(report: &mut Report) {
+    const S: &str = "display";
+    if on_path("Xorg") || Path::new("/usr/lib/xorg/Xorg").exists() {
+        report.push(S, "xorg", Status::Ok, "Xorg found");
+    } else {
+        report.push(
+            S,
+            "xorg",
+            Status::Fail,
+            "Xorg not found — install xserver-xorg-core",
+        );
+    }
+
+    match XORG_DRIVER_DIRS
+        .iter()
+        .map(|dir| Path::new(dir).join("dummy_drv.so"))
+        .find(|p| p.exists())
+    {
+        Some(path) => report.push(
+            S,
+            "xorg_dummy_driver",
+            Status::Ok,
+            format!("Xorg dummy driver: {}", path.display()),
+        ),
+        None => report.push(
+            S,
+            "xorg_dummy_driver",
+            Status::Fail,
+            "Xorg dummy driver missing — install xserver-xorg-video-dummy",
+        ),
+    }
+
+    match std::fs::read_to_string("/etc/X11/Xwrapper.config") {
+        Ok(text) if text.lines().any(|l| l.trim() == "allowed_users=anybody") => report.push(
+            S,
+            "xwrapper",
+            Status::Ok,
+            "Xwrapper allows non-console users",
+        ),
+        Ok(_) => report.push(
+            S,
+            "xwrapper",
+            Status::Fail,
+            "Xwrapper restricts X to console users — run: \
+             echo 'allowed_users=anybody' | sudo tee /etc/X11/Xwrapper.config",
+        ),
+        Err(_) => report.push(
+            S,
+            "xwrapper",
+            Status::Warn,
+            "No /etc/X11/Xwrapper.config — Xorg may reject virtual display creation",
+        ),
+    }
+
+    let tools = [
+        (
+            "xrdb",
+            Status::Warn,
+            "install x11-xserver-utils (HiDPI scaling won't work)",
+        ),
+        (
+            "xclip",
+            Status::Warn,
+            "install xclip (clipboard sync won't work)",
+        ),
+        (
+            "xkbcomp",
+            Status::Warn,
+            "install x11-xkb-utils (Xorg can't compile keyboard layouts)",
+        ),
+    ];
+    for (tool, missing, fix) in tools {
+        if on_path(tool) {
+            report.push(S, tool, Status::Ok, format!("{tool} found"));
+        } else {
+            report.push(S, tool, missing, format!("{tool} not found — {fix}"));
+        }
+    }
+}
+
+pub(crate) fn input(report: &mut Report) {
+    // Input goes through XTEST now; uinput only matters to setups that
+    // still rely on the udev rule from older installs
+    let path = Path::new("/dev/uinput");
+    if !path.exists() {
+        report.push(
+            "input",
+            "uinput",
+            Status::Skip,
+            "/dev/uinput not present (not needed: input uses XTEST)",
+        );
+    } else if std::fs::OpenOptions::new().write(true).open(path).is_ok() {
+        report.push("input", "uinput", Status::Ok, "/dev/uinput is writable");
+    } else {
+        report.push(
+            "input",
+            "uinput",
+            Status::Skip,
+            "/dev/uinput not writable by this user (not needed: input uses XTEST)",
+        );
+    }
+}
+
+pub(crate) fn audio(report: &mut Report) {
+    // The order `audio.backend = "auto"` picks them in
+    if AudioBackend::PIPEWIRE_PROGRAMS.iter().all(|p| on_path(p)) {
+        report.push(
+            "audio",
+            "pulseaudio",
+            Status::Ok,
+            "PipeWire (with WirePlumber and PulseAudio compat) found",
+        );
+    } else if on_path("pulseaudio") {
+        report.push("audio", "pulseaudio", Status::Ok, "PulseAudio found");
+    } else if on_path("pipewire") {
+        report.push(
+            "audio",
+            "pulseaudio",
+            Status::Warn,
+            "PipeWire found without wireplumber or pipewire-pulse — audio streaming won't work",
+        );
+    } else {
+        report.push(
+            "audio",
+            "pulseaudio",
+            Status::Warn,
+            "PulseAudio/PipeWire not found — audio streaming won't work",
+        );
+    }
+}
+
+/// Whether a PAM service file ends in a `pam_deny` rule for auth, as
+/// `/etc/pam.d/other` does on most distributions other than Debian's.
+fn pam_denies_auth(text: &str) -> bool {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with('#'))
+        .any(|l| l.starts_with("auth") && l.contains("pam_deny.so"))
+}
+
+/// The users file when `auth.users_file` is set, the PAM service otherwise.
+pub(crate) fn auth(report: &mut Report, config: &AuthConfig) {
+    let Some(path) = &config.users_file else {
+        pam(report);
+        return;
+    };
+    const S: &str = "auth";
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            report.push(
+                S,
+                "users_file",
+                Status::Fail,
+                format!("Users file {path} is not readable: {e} — add a user with 'beam-server users add <NAME>'"),
+            );
+            return;
+        }
+    };
+    let users = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && l.contains(':'))
+        .count();
+    let world_readable = std::fs::metadata(path)
+        .map(|m| std::os::unix::fs::PermissionsExt::mode(&m.permissions()) & 0o004 != 0)
+        .unwrap_or(false);
+    if users == 0 {
+        report.push(
+            S,
+            "users_file",
+            Status::Fail,
+            format!("Users file {path} has no users — add one with 'beam-server users add <NAME>'"),
+        );
+    } else if world_readable {
+        report.push(
+            S,
+            "users_file",
+            Status::Warn,
+            format!("Users file {path} is readable by every user — chmod 600 it"),
+        );
+    } else {
+        report.push(
+            S,
+            "users_file",
+            Status::Ok,
+            format!("Users file: {path} ({users} users)"),
+        );
+    }
+}
+
+fn pam(report: &mut Report) {
+    const S: &str = "auth";
+    // The server authenticates as PAM service "beam"
+    if Path::new("/etc/pam.d/beam").exists() {
+        report.push(S, "pam", Status::Ok, "PAM service: /etc/pam.d/beam");
+        return;
+    }
+    match std::fs::read_to_string("/etc/pam.d/other") {
+        Ok(text) if pam_denies_auth(&text) => report.push(
+            S,
+            "pam",
+            Status::Fail,
+            "No /etc/pam.d/beam and /etc/pam.d/other denies logins — \
+             create /etc/pam.d/beam (e.g. '@include common-auth')",
+        ),
+        Ok(_) => report.push(
+            S,
+            "pam",
+            Status::Ok,
+            "PAM service \"beam\" uses /etc/pam.d/other",
+        ),
+        Err(_) => report.push(
+            S,
+            "pam",
+            Status::Fail,
+            "No PAM configuration for service \"beam\" — create /etc/pam.d/beam",
+        ),
+    }
+}
+
+/// Whether PEM text holds a private key block: PKCS#8, PKCS#1 or SEC1, the
+/// kinds the server loads.
+fn has_private_key(pem_text: &str) -> bool {
+    pem::parse_many(pem_text)
+        .is_ok_and(|blocks| blocks.iter().any(|b| b.tag().ends_with("PRIVATE KEY")))
+}
+
+pub(crate) fn tls(report: &mut Report, server: &ServerConfig) {
+    const S: &str = "tls";
+    let cert_path = match (&server.tls_cert, &server.tls_key) {
+        (Some(cert), Some(key)) => {
+            match std::fs::read_to_string(key) {
+                Ok(text) if !has_private_key(&text) => report.push(
+                    S,
+                    "tls_key",
+                    Status::Fail,
+                    format!("No PEM private key in {key}"),
+                ),
+                Ok(_) => {}
+                Err(_) => report.push(
+                    S,
+                    "tls_key",
+                    Status::Fail,
+                    format!("TLS key not readable: {key}"),
+                ),
+            }
+            cert.as_str()
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            report.push(
+                S,
+                "tls_cert",
+                Status::Warn,
+                "Only one of tls_cert/tls_key is set — the server ignores both \
+                 and uses a self-signed certificate",
+            );
+            return;
+        }
+        (None, None) if Path::new(SELF_SIGNED_CERT).exists() => SELF_SIGNED_CERT,
+        (None, None) => {
+            report.push(
+                S,
+                "tls_cert",
+                Status::Ok,
+                "Using a self-signed certificate, generated on first start",
+            );
+            return;
+        }
+    };
+
+    let pem_text = match std::fs::read_to_string(cert_path) {
+        Ok(text) => text,
+        Err(e) => {
+            report.push(
+                S,
+                "tls_cert",
+                Status::Fail,
+                format!("TLS cert not readable: {cert_path}: {e}"),
+            );
+            return;
+        }
+    };
+    let validity = match cert_validity(&pem_text) {
+        Ok(v) => v,
+        Err(e) => {
+            report.push(S, "tls_cert", Status::Fail, format!("{cert_path}: {e:#}"));
+            return;
+        }
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let days_left = (validity.not_after - now).div_euclid(86400);
+    let until = format_date(validity.not_after);
+    let (status, detail) = if now < validity.not_before {
+        (
+            Status::Fail,
+            format!(
+                "{cert_path} is not valid before {}",
+                format_date(validity.not_before)
+            ),
+        )
+    } else if now > validity.not_after {
+        (Status::Fail, format!("{cert_path} expired on {until}"))
+    } else if days_left < CERT_EXPIRY_WARN_DAYS {
+        (
+            Status::Warn,
+            format!("{cert_path} expires on {until} ({days_left} days left)"),
+        )
+    } else {
+        (
+            Status::Ok,
+            format!("{cert_path} valid until {until} ({days_left} days left)"),
+        )
+    };
+    report.push(S, "tls_cert", status, detail);
+}
+
+pub(crate) fn port(report: &mut Report, server: &ServerConfig) {
+    let ip = match server.bind.parse::<IpAddr>() {
+        Ok(ip) if ip.is_unspecified() && ip.is_ipv6() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        Ok(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        Ok(ip) => ip,
+        Err(_) => {
+            report.push(
+                "network",
+                "port",
+                Status::Fail,
+                format!("Invalid bind address: {}", server.bind),
+            );
+            return;
+        }
+    };
+    let addr = SocketAddr::new(ip, server.port);
+    if TcpStream::connect_timeout(&addr, Duration::from_secs(1)).is_ok() {
+        report.push(
+            "network",
+            "port",
+            Status::Ok,
+            format!("Port {} is listening", server.port),
+        );
+    } else {
+        report.push(
+            "network",
+            "port",
+            Status::Warn,
+            format!("Nothing is listening on {addr} — is beam-server running?"),
+        );
+    }
+}
+
+pub(crate) fn runtime(report: &mut Report, server: &ServerConfig) {
+    const S: &str = "runtime";
+    if Path::new("/var/lib/beam").is_dir() {
+        report.push(S, "state_dir", Status::Ok, "/var/lib/beam exists");
+    } else {
+        report.push(
+            S,
+            "state_dir",
+            Status::Warn,
+            "/var/lib/beam does not exist (created on first run)",
+        );
+    }
+
+    let index = Path::new(&server.web_root).join("index.html");
+    if index.is_file() {
+        report.push(
+            S,
+            "web_root",
+            Status::Ok,
+            format!("Web root: {}", server.web_root),
+        );
+    } else {
+        report.push(
+            S,
+            "web_root",
+            Status::Fail,
+            format!("Web root missing: {} not found", index.display()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_gst_inspect_version() {
+        let output = "gst-inspect-1.0 version 1.24.2\nGStreamer 1.24.2\n";
+        assert_eq!(parse_gst_version(output), Some((1, 24)));
+        assert_eq!(parse_gst_version(""), None);
+        assert_eq!(parse_gst_version("gst-inspect-1.0 version unknown"), None);
+    }
+
+    #[test]
+    fn spots_a_denying_pam_fallback() {
+        let fedora =
+            "#%PAM-1.0\nauth     required       pam_deny.so\naccount  required       pam_deny.so\n";
+        assert!(pam_denies_auth(fedora));
+        let debian =
+            "#\n# /etc/pam.d/other\n#\n# auth required pam_deny.so\n@include common-auth\n";
+        assert!(!pam_denies_auth(debian));
+    }
+
+    #[test]
+    fn finds_the_private_key_in_pem() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        assert!(has_private_key(&key.serialize_pem()));
+        let params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        // A certificate where the key should be, a common mix-up
+        assert!(!has_private_key(&params.self_signed(&key).unwrap().pem()));
+        assert!(!has_private_key("not pem"));
+    }
+
+    #[test]
+    fn missing_web_root_fails() {
+        let mut report = Report::default();
+        let server = ServerConfig {
+            web_root: "/nonexistent/beam-web".to_string(),
+            ..ServerConfig::default()
+        };
+        runtime(&mut report, &server);
+        let check = report.checks.iter().find(|c| c.name == "web_root").unwrap();
+        assert_eq!(check.status, Status::Fail);
+    }
+}
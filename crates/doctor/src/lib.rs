@@ -0,0 +1,236 @@
+//! System readiness checks for Beam: GStreamer, the virtual display, audio,
+//! PAM or the users file, TLS and the listening port. Used by the `beam-doctor` binary, by
+//! the server's `/api/health/detailed` and, limited to the config and the
+//! files it names, by `beam-server check-config`.
+
+mod cert;
+mod checks;
+
+use std::path::Path;
+
+use beam_protocol::BeamConfig;
+use serde::Serialize;
+use serde_json::json;
+
+pub use cert::{CertValidity, cert_validity, format_date};
+
+/// Outcome of one check, ordered from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Optional component that isn't present; doesn't affect the result
+    Skip,
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    /// Group the check belongs to, e.g. "gstreamer"
+    pub section: &'static str,
+    /// Stable identifier, e.g. "encoder_x264enc"
+    pub name: String,
+    pub status: Status,
+    /// Human-readable result, with a fix for anything not Ok
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn push(
+        &mut self,
+        section: &'static str,
+        name: impl Into<String>,
+        status: Status,
+        detail: impl Into<String>,
+    ) {
+        self.checks.push(Check {
+            section,
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        });
+    }
+
+    /// Most severe status of any check.
+    pub fn status(&self) -> Status {
+        self.checks
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(Status::Ok)
+            .max(Status::Ok)
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+
+    /// The machine-readable report printed by `beam-doctor --json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "status": self.status(),
+            "ok": self.count(Status::Ok),
+            "warn": self.count(Status::Warn),
+            "fail": self.count(Status::Fail),
+            "checks": self.checks,
+        })
+    }
+}
+
+/// Run every check that applies to the host `config` describes. Spawns
+/// `gst-inspect-1.0` and friends, so it blocks for a while.
+pub fn run(config: &BeamConfig) -> Report {
+    let mut report = Report::default();
+    checks::gstreamer(&mut report);
+    checks::gpu(&mut report);
+    checks::display(&mut report);
+    checks::input(&mut report);
+    checks::audio(&mut report);
+    checks::auth(&mut report, &config.auth);
+    checks::tls(&mut report, &config.server);
+    checks::port(&mut report, &config.server);
+    checks::runtime(&mut report, &config.server);
+    report
+}
+
+/// Only the checks a config change can break: the PAM service or users
+/// file, the TLS files and the web root. Quick, and safe to run next to a live server.
+pub fn run_config(config: &BeamConfig) -> Report {
+    let mut report = Report::default();
+    checks::auth(&mut report, &config.auth);
+    checks::tls(&mut report, &config.server);
+    checks::runtime(&mut report, &config.server);
+    report
+}
+
+/// Load the config the server would use, reporting problems with it.
+/// Falls back to defaults so the remaining checks still run.
+pub fn check_config_file(report: &mut Report, path: &Path) -> BeamConfig {
+    const S: &str = "config";
+    let shown = path.display();
+    let text = if !path.exists() {
+        report.push(
+            S,
+            "config_file",
+            Status::Warn,
+            format!("No config file at {shown}, checking against defaults"),
+        );
+        None
+    } else {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                report.push(
+                    S,
+                    "config_file",
+                    Status::Fail,
+                    format!("Config file is not readable: {shown}: {e}"),
+                );
+                return BeamConfig::default();
+            }
+        }
+    };
+    let (config, overrides) = match BeamConfig::from_toml_and_env(text.as_deref().unwrap_or("")) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            report.push(S, "config_file", Status::Fail, format!("{shown}: {e}"));
+            return BeamConfig::default();
+        }
+    };
+    if !overrides.applied.is_empty() {
+        report.push(
+            S,
+            "env_overrides",
+            Status::Ok,
+            format!(
+                "Overridden from the environment: {}",
+                overrides.applied.join(", ")
+            ),
+        );
+    }
+    for name in overrides.unknown {
+        report.push(
+            S,
+            "env_overrides",
+            Status::Warn,
+            format!("{name} matches no config key and is ignored"),
+        );
+    }
+    if text.is_none() {
+        return config;
+    }
+    report.push(
+        S,
+        "config_file",
+        Status::Ok,
+        format!("Config file: {shown}"),
+    );
+    if let Err(issues) = config.validate() {
+        for issue in issues {
+            let status = if issue.starts_with("ERROR:") {
+                Status::Fail
+            } else {
+                Status::Warn
+            };
+            report.push(S, "config_validation", status, issue);
+        }
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_status_is_the_worst_check() {
+        let mut report = Report::default();
+        assert_eq!(report.status(), Status::Ok);
+        report.push(
+            "gstreamer",
+            "encoder_nvh264enc",
+            Status::Skip,
+            "not installed",
+        );
+        assert_eq!(report.status(), Status::Ok);
+        report.push("audio", "pulseaudio", Status::Warn, "not found");
+        report.push("display", "xorg", Status::Ok, "found");
+        assert_eq!(report.status(), Status::Warn);
+        report.push("tls", "tls_cert", Status::Fail, "expired");
+        assert_eq!(report.status(), Status::Fail);
+
+        let json = report.to_json();
+        assert_eq!(json["status"], "fail");
+        assert_eq!(json["ok"], 1);
+        assert_eq!(json["warn"], 1);
+        assert_eq!(json["checks"][0]["status"], "skip");
+        assert_eq!(json["checks"][3]["name"], "tls_cert");
+    }
+
+    #[test]
+    fn config_file_problems_fail_the_check() {
+        let dir = std::env::temp_dir().join(format!("beam-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("beam.toml");
+        let status_of = |text: &str| {
+            std::fs::write(&path, text).unwrap();
+            let mut report = Report::default();
+            check_config_file(&mut report, &path);
+            report.status()
+        };
+        assert_eq!(status_of("[server]\nport = 8444\n"), Status::Ok);
+        assert_eq!(status_of("[server\nport = 8444\n"), Status::Fail);
+        assert_eq!(status_of("[server]\nport = 0\n"), Status::Fail);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut report = Report::default();
+        check_config_file(&mut report, &dir.join("missing.toml"));
+        assert_eq!(report.status(), Status::Warn);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use beam_doctor::{Report, Status};
+
+struct Args {
+    config_path: PathBuf,
+    json: bool,
+}
+
+fn parse_args() -> Args {
+    let args: Vec<String> = std::env::args().collect();
+    let mut parsed = Args {
+        config_path: PathBuf::from("/etc/beam/beam.toml"),
+        json: false,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-V" | "--version" => {
+                println!("beam-doctor {}", env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            "-h" | "--help" => {
+                println!("beam-doctor - Beam Remote Desktop diagnostics");
+                println!();
+                println!("USAGE:");
+                println!("    beam-doctor [OPTIONS]");
+                println!();
+                println!("OPTIONS:");
+                println!(
+                    "    -c, --config <PATH>    Configuration file [default: /etc/beam/beam.toml]"
+                );
+                println!("        --json             Print the report as JSON");
+                println!("    -V, --version          Print version and exit");
+                println!("    -h, --help             Print this help and exit");
+                std::process::exit(0);
+            }
+            "--config" | "-c" if i + 1 < args.len() => {
+                parsed.config_path = PathBuf::from(&args[i + 1]);
+                i += 1;
+            }
+            "--json" => parsed.json = true,
+            other => {
+                eprintln!("beam-doctor: unknown argument: {other}");
+                std::process::exit(2);
+            }
+        }
+        i += 1;
+    }
+    parsed
+}
+
+fn check_service(report: &mut Report) {
+    let systemctl = |verb: &str| {
+        std::process::Command::new("systemctl")
+            .args([verb, "--quiet", "beam"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    };
+    if systemctl("is-active") {
+        report.push("service", "systemd", Status::Ok, "beam.service is running");
+    } else if systemctl("is-enabled") {
+        report.push(
+            "service",
+            "systemd",
+            Status::Warn,
+            "beam.service is enabled but not running",
+        );
+    } else {
+        report.push(
+            "service",
+            "systemd",
+            Status::Warn,
+            "beam.service is not running",
+        );
+    }
+}
+
+fn print_human(report: &Report) {
+    let color = std::io::stdout().is_terminal();
+    let paint = |code: &str, text: &str| {
+        if color {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    };
+
+    let mut section = "";
+    for check in &report.checks {
+        if check.section != section {
+            section = check.section;
+            println!();
+            println!("{}", paint("1", section));
+        }
+        let label = match check.status {
+            Status::Ok => paint("32", "[OK]  "),
+            Status::Warn => paint("33", "[WARN]"),
+            Status::Fail => paint("31", "[FAIL]"),
+            Status::Skip => paint("2", "[--]  "),
+        };
+        println!("{label} {}", check.detail);
+    }
+
+    let fail = report.count(Status::Fail);
+    println!();
+    println!(
+        "{} {}, {}, {}",
+        paint("1", "Doctor summary:"),
+        paint("32", &format!("{} passed", report.count(Status::Ok))),
+        paint("33", &format!("{} warnings", report.count(Status::Warn))),
+        paint(
+            if fail > 0 { "31" } else { "32" },
+            &format!("{fail} failures")
+        ),
+    );
+}
+
+fn main() {
+    let args = parse_args();
+
+    let mut report = Report::default();
+    let config = beam_doctor::check_config_file(&mut report, &args.config_path);
+    check_service(&mut report);
+    report.checks.extend(beam_doctor::run(&config).checks);
+
+    if args.json {
+        println!("{:#}", report.to_json());
+    } else {
+        print_human(&report);
+    }
+    if report.status() == Status::Fail {
+        std::process::exit(1);
+    }
+}
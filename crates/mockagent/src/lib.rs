@@ -0,0 +1,192 @@
+//! Stand-in for `beam-agent` that needs no X11, GStreamer or PulseAudio.
+//! It connects to the server's agent WebSocket after the same handshake,
+//! announces itself like the real agent, streams a synthetic H.264 test
+//! pattern and records the commands the server relays, so server tests can
+//! drive a whole session.
+
+pub mod pattern;
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use beam_cli::http::{Client, ServerUrl};
+use beam_protocol::{
+    AgentCommand, AgentHandshake, AgentTicket, CAP_INPUT_TIMESTAMPS, CAP_LOCK_SCREEN, CAP_UPGRADE,
+    Hello, InputEvent, VideoFrameHeader,
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+use crate::pattern::TestPattern;
+
+pub struct Options {
+    pub width: u16,
+    pub height: u16,
+    pub framerate: u32,
+}
+
+impl Default for Options {
+    /// Small enough that uncompressed keyframes stay around 100 KB
+    fn default() -> Self {
+        Self {
+            width: 320,
+            height: 240,
+            framerate: 30,
+        }
+    }
+}
+
+pub struct MockAgent {
+    commands: mpsc::UnboundedReceiver<AgentCommand>,
+    task: JoinHandle<()>,
+}
+
+impl MockAgent {
+    /// Connect to `server` (`ws://host:port`) as the agent of `session_id`
+    /// and start streaming. Fails if the server refuses the token.
+    pub async fn connect(
+        server: &str,
+        session_id: Uuid,
+        token: &str,
+        options: Options,
+    ) -> Result<Self> {
+        let ticket = handshake(server, session_id, token).await?;
+        let url = format!(
+            "{server}/ws/agent/{session_id}?token={}",
+            urlencoding::encode(&ticket.token)
+        );
+        let (mut ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .context("Agent WebSocket connection failed")?;
+        let hello = serde_json::to_string(&Hello::new(&[
+            CAP_LOCK_SCREEN,
+            CAP_INPUT_TIMESTAMPS,
+            CAP_UPGRADE,
+        ]))?;
+        ws.send(Message::Text(hello.into()))
+            .await
+            .context("Failed to send agent hello")?;
+
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            if let Err(e) = stream(ws, options, commands_tx).await {
+                eprintln!("mock agent {session_id}: {e:#}");
+            }
+        });
+        Ok(Self { commands, task })
+    }
+
+    /// Next command the server relayed, or None after `timeout`.
+    pub async fn recv(&mut self, timeout: Duration) -> Option<AgentCommand> {
+        tokio::time::timeout(timeout, self.commands.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Skip commands until one matches, failing after `timeout`.
+    pub async fn wait_for(
+        &mut self,
+        timeout: Duration,
+        matches: impl Fn(&AgentCommand) -> bool,
+    ) -> Result<AgentCommand> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match tokio::time::timeout_at(deadline, self.commands.recv()).await {
+                Ok(Some(cmd)) if matches(&cmd) => return Ok(cmd),
+                Ok(Some(_)) => {}
+                Ok(None) => bail!("Mock agent connection ended"),
+                Err(_) => bail!("No matching agent command within {timeout:?}"),
+            }
+        }
+    }
+
+    /// Drop the connection without a close frame, like an agent that crashed.
+    pub fn kill(self) {}
+}
+
+impl Drop for MockAgent {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Trade the long-lived agent `token` for a single-use WebSocket ticket,
+/// as the real agent does before every connection.
+pub async fn handshake(server: &str, session_id: Uuid, token: &str) -> Result<AgentTicket> {
+    let client = Client::new(
+        ServerUrl::from_ws(server)?,
+        beam_cli::http::tls_config(None, false)?,
+        Some(token.to_string()),
+    );
+    let body = serde_json::to_value(AgentHandshake { session_id })?;
+    let response =
+        tokio::task::spawn_blocking(move || client.post("/api/agent/handshake", Some(&body)))
+            .await
+            .context("Agent handshake task failed")??;
+    if !response.is_success() {
+        bail!("Agent handshake refused: {}", response.error_message());
+    }
+    serde_json::from_slice(&response.body).context("Invalid agent ticket")
+}
+
+type Socket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Send a frame per tick, starting with a keyframe, and forward commands.
+/// A browser becoming visible asks for a keyframe, as with the real agent.
+async fn stream(
+    ws: Socket,
+    options: Options,
+    commands: mpsc::UnboundedSender<AgentCommand>,
+) -> Result<()> {
+    let (mut tx, mut rx) = ws.split();
+    let mut pattern = TestPattern::new(options.width, options.height);
+    let mut ticks = tokio::time::interval(Duration::from_secs(1) / options.framerate.max(1));
+    let started = Instant::now();
+    let mut want_keyframe = true;
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                let keyframe = std::mem::take(&mut want_keyframe);
+                let payload = if keyframe { pattern.keyframe() } else { pattern.delta() };
+                let header = VideoFrameHeader::video(
+                    pattern.width(),
+                    pattern.height(),
+                    started.elapsed().as_micros() as u64,
+                    payload.len() as u32,
+                    keyframe,
+                );
+                tx.send(Message::Binary(header.serialize_with_payload(&payload).into()))
+                    .await
+                    .context("Failed to send video frame")?;
+            }
+            msg = rx.next() => {
+                let text = match msg {
+                    None | Some(Ok(Message::Close(_))) => return Ok(()),
+                    Some(Err(e)) => return Err(e).context("Agent WebSocket failed"),
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(_)) => continue,
+                };
+                // The server's hello is the only text that isn't a command
+                let Ok(cmd) = serde_json::from_str::<AgentCommand>(&text) else {
+                    continue;
+                };
+                if matches!(cmd, AgentCommand::Input(InputEvent::VisibilityState { visible: true })) {
+                    want_keyframe = true;
+                }
+                let shutdown = matches!(cmd, AgentCommand::Shutdown);
+                let _ = commands.send(cmd);
+                if shutdown {
+                    let _ = tx.close().await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
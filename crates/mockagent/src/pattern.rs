@@ -0,0 +1,374 @@
+//! Synthetic H.264 colour bars, built without an encoder.
+//!
+//! Keyframes are SPS + PPS + an IDR slice in which every macroblock is
+//! I_PCM (raw samples), so they cost about 1.5 bytes per pixel: keep mock
+//! sessions small, the server refuses frames over 2 MB. Delta frames are
+//! a P slice that skips every macroblock. The stream declares Main profile
+//! like the agent's encoders and uses Annex B start codes.
+
+/// 75% colour bars in BT.601 limited-range Y, Cb, Cr
+const BARS: [[u8; 3]; 8] = [
+    [180, 128, 128], // white
+    [162, 44, 142],  // yellow
+    [131, 156, 44],  // cyan
+    [112, 72, 58],   // green
+    [84, 184, 198],  // magenta
+    [65, 100, 212],  // red
+    [35, 212, 114],  // blue
+    [16, 128, 128],  // black
+];
+
+const NAL_SLICE: u8 = 1;
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+
+/// frame_num is 4 bits (log2_max_frame_num_minus4 = 0)
+const MAX_FRAME_NUM: u32 = 16;
+const MB_TYPE_I_PCM: u32 = 25;
+const SLICE_TYPE_P: u32 = 5;
+const SLICE_TYPE_I: u32 = 7;
+
+pub struct TestPattern {
+    width: u16,
+    height: u16,
+    mbs_wide: u32,
+    mbs_high: u32,
+    frame_num: u32,
+    idr_pic_id: u32,
+}
+
+impl TestPattern {
+    pub fn new(width: u16, height: u16) -> Self {
+        let width = width.max(2);
+        let height = height.max(2);
+        Self {
+            width,
+            height,
+            mbs_wide: u32::from(width).div_ceil(16),
+            mbs_high: u32::from(height).div_ceil(16),
+            frame_num: 0,
+            idr_pic_id: 0,
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Parameter sets and an IDR picture of the bars.
+    pub fn keyframe(&mut self) -> Vec<u8> {
+        self.frame_num = 0;
+        // Back-to-back IDR pictures must differ in idr_pic_id
+        self.idr_pic_id ^= 1;
+
+        let mut out = Vec::new();
+        push_nal(&mut out, 3, NAL_SPS, &self.sps());
+        push_nal(&mut out, 3, NAL_PPS, &pps());
+
+        let mut w = self.slice_header(SLICE_TYPE_I);
+        // dec_ref_pic_marking: no_output_of_prior_pics, long_term_reference
+        w.bit(false);
+        w.bit(false);
+        self.slice_header_tail(&mut w);
+        for _ in 0..self.mbs_high {
+            for mb_x in 0..self.mbs_wide {
+                let [y, cb, cr] = BARS[(mb_x * 8 / self.mbs_wide) as usize];
+                w.ue(MB_TYPE_I_PCM);
+                w.align_zero();
+                w.bytes(y, 256);
+                w.bytes(cb, 64);
+                w.bytes(cr, 64);
+            }
+        }
+        w.trailing();
+        push_nal(&mut out, 3, NAL_IDR, &w.finish());
+        out
+    }
+
+    /// A picture identical to the previous one.
+    pub fn delta(&mut self) -> Vec<u8> {
+        self.frame_num = (self.frame_num + 1) % MAX_FRAME_NUM;
+
+        let mut w = self.slice_header(SLICE_TYPE_P);
+        // num_ref_idx_active_override, ref_pic_list_modification_flag_l0,
+        // adaptive_ref_pic_marking_mode
+        w.bit(false);
+        w.bit(false);
+        w.bit(false);
+        self.slice_header_tail(&mut w);
+        w.ue(self.mbs_wide * self.mbs_high); // mb_skip_run
+        w.trailing();
+
+        let mut out = Vec::new();
+        push_nal(&mut out, 2, NAL_SLICE, &w.finish());
+        out
+    }
+
+    fn sps(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(77, 8); // profile_idc: Main
+        w.bits(0x40, 8); // constraint_set1: decodable by Main decoders
+        w.bits(51, 8); // level_idc 5.1, as the web client configures
+        w.ue(0); // seq_parameter_set_id
+        w.ue(0); // log2_max_frame_num_minus4
+        w.ue(2); // pic_order_cnt_type: output order is decode order
+        w.ue(1); // max_num_ref_frames
+        w.bit(false); // gaps_in_frame_num_value_allowed
+        w.ue(self.mbs_wide - 1);
+        w.ue(self.mbs_high - 1);
+        w.bit(true); // frame_mbs_only
+        w.bit(true); // direct_8x8_inference
+
+        // Crop units are two pixels in 4:2:0
+        let crop_right = (self.mbs_wide * 16 - u32::from(self.width)) / 2;
+        let crop_bottom = (self.mbs_high * 16 - u32::from(self.height)) / 2;
+        let cropped = crop_right > 0 || crop_bottom > 0;
+        w.bit(cropped);
+        if cropped {
+            w.ue(0);
+            w.ue(crop_right);
+            w.ue(0);
+            w.ue(crop_bottom);
+        }
+
+        // VUI with only bitstream_restriction, so decoders output each
+        // picture immediately instead of filling a reorder buffer
+        w.bit(true);
+        for _ in 0..8 {
+            // aspect ratio, overscan, signal type, chroma location, timing,
+            // NAL HRD, VCL HRD, pic_struct
+            w.bit(false);
+        }
+        w.bit(true); // bitstream_restriction
+        w.bit(true); // motion_vectors_over_pic_boundaries
+        w.ue(0); // max_bytes_per_pic_denom
+        w.ue(0); // max_bits_per_mb_denom
+        w.ue(16); // log2_max_mv_length_horizontal
+        w.ue(16); // log2_max_mv_length_vertical
+        w.ue(0); // max_num_reorder_frames
+        w.ue(1); // max_dec_frame_buffering
+        w.trailing();
+        w.finish()
+    }
+
+    fn slice_header(&self, slice_type: u32) -> BitWriter {
+        let mut w = BitWriter::default();
+        w.ue(0); // first_mb_in_slice
+        w.ue(slice_type);
+        w.ue(0); // pic_parameter_set_id
+        w.bits(self.frame_num, 4);
+        if slice_type == SLICE_TYPE_I {
+            w.ue(self.idr_pic_id);
+        }
+        w
+    }
+
+    fn slice_header_tail(&self, w: &mut BitWriter) {
+        w.se(0); // slice_qp_delta
+        w.ue(1); // disable_deblocking_filter_idc: nothing to smooth
+    }
+}
+
+fn pps() -> Vec<u8> {
+    let mut w = BitWriter::default();
+    w.ue(0); // pic_parameter_set_id
+    w.ue(0); // seq_parameter_set_id
+    w.bit(false); // entropy_coding_mode: CAVLC
+    w.bit(false); // bottom_field_pic_order_in_frame_present
+    w.ue(0); // num_slice_groups_minus1
+    w.ue(0); // num_ref_idx_l0_default_active_minus1
+    w.ue(0); // num_ref_idx_l1_default_active_minus1
+    w.bit(false); // weighted_pred
+    w.bits(0, 2); // weighted_bipred_idc
+    w.se(0); // pic_init_qp_minus26
+    w.se(0); // pic_init_qs_minus26
+    w.se(0); // chroma_qp_index_offset
+    w.bit(true); // deblocking_filter_control_present
+    w.bit(false); // constrained_intra_pred
+    w.bit(false); // redundant_pic_cnt_present
+    w.trailing();
+    w.finish()
+}
+
+/// Append a NAL unit with a 4-byte start code, escaping the RBSP so no
+/// start code appears inside it.
+fn push_nal(out: &mut Vec<u8>, ref_idc: u8, nal_type: u8, rbsp: &[u8]) {
+    out.extend_from_slice(&[0, 0, 0, 1, (ref_idc << 5) | nal_type]);
+    let mut zeros = 0;
+    for &byte in rbsp {
+        if zeros == 2 && byte <= 3 {
+            out.push(3);
+            zeros = 0;
+        }
+        out.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits used in the last byte; 0 when it is full
+    used: u8,
+}
+
+impl BitWriter {
+    fn bit(&mut self, set: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if set {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+
+    fn bits(&mut self, value: u32, count: u8) {
+        for i in (0..count).rev() {
+            self.bit(value >> i & 1 == 1);
+        }
+    }
+
+    /// Unsigned Exp-Golomb
+    fn ue(&mut self, value: u32) {
+        let coded = value + 1;
+        let len = 32 - coded.leading_zeros() as u8;
+        self.bits(0, len - 1);
+        self.bits(coded, len);
+    }
+
+    /// Signed Exp-Golomb
+    fn se(&mut self, value: i32) {
+        let mapped = if value > 0 {
+            2 * value as u32 - 1
+        } else {
+            2 * value.unsigned_abs()
+        };
+        self.ue(mapped);
+    }
+
+    fn align_zero(&mut self) {
+        self.used = 0;
+    }
+
+    /// `count` copies of a byte; the writer must be aligned
+    fn bytes(&mut self, byte: u8, count: usize) {
+        debug_assert_eq!(self.used, 0);
+        self.bytes.resize(self.bytes.len() + count, byte);
+    }
+
+    /// rbsp_trailing_bits: a stop bit, then zeros to the byte boundary
+    fn trailing(&mut self) {
+        self.bit(true);
+        self.align_zero();
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split Annex B into (nal_type, escaped payload)
+    fn nals(data: &[u8]) -> Vec<(u8, &[u8])> {
+        let starts: Vec<usize> = data
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| *w == [0, 0, 0, 1])
+            .map(|(i, _)| i + 4)
+            .collect();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(n, &start)| {
+                let end = starts.get(n + 1).map_or(data.len(), |next| next - 4);
+                (data[start] & 0x1f, &data[start + 1..end])
+            })
+            .collect()
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: usize) -> u32 {
+            let mut value = 0;
+            for _ in 0..count {
+                let bit = self.data[self.pos / 8] >> (7 - self.pos % 8) & 1;
+                value = value << 1 | u32::from(bit);
+                self.pos += 1;
+            }
+            value
+        }
+
+        fn ue(&mut self) -> u32 {
+            let mut zeros = 0;
+            while self.bits(1) == 0 {
+                zeros += 1;
+            }
+            (1 << zeros) - 1 + self.bits(zeros)
+        }
+    }
+
+    #[test]
+    fn keyframe_is_sps_pps_idr() {
+        let mut pattern = TestPattern::new(320, 240);
+        let key = pattern.keyframe();
+        let types: Vec<u8> = nals(&key).iter().map(|(t, _)| *t).collect();
+        assert_eq!(types, [NAL_SPS, NAL_PPS, NAL_IDR]);
+        // 20x15 macroblocks of 384 raw bytes plus headers
+        assert!(key.len() > 300 * 384);
+        assert!(key.len() < 300 * 390);
+
+        let delta = pattern.delta();
+        let types: Vec<u8> = nals(&delta).iter().map(|(t, _)| *t).collect();
+        assert_eq!(types, [NAL_SLICE]);
+        assert!(delta.len() < 16);
+    }
+
+    #[test]
+    fn sps_describes_cropped_size() {
+        let key = TestPattern::new(1366, 768).keyframe();
+        let (_, sps) = nals(&key)[0];
+        let mut r = BitReader { data: sps, pos: 0 };
+        assert_eq!(r.bits(8), 77);
+        r.bits(16);
+        assert_eq!(r.ue(), 0); // sps id
+        assert_eq!(r.ue(), 0); // log2_max_frame_num_minus4
+        assert_eq!(r.ue(), 2); // poc type
+        assert_eq!(r.ue(), 1); // ref frames
+        r.bits(1);
+        let mbs_wide = r.ue() + 1;
+        let mbs_high = r.ue() + 1;
+        assert_eq!((mbs_wide, mbs_high), (86, 48));
+        assert_eq!(r.bits(3), 0b111); // frame_mbs_only, direct_8x8, cropping
+        let (left, right, top, bottom) = (r.ue(), r.ue(), r.ue(), r.ue());
+        assert_eq!(mbs_wide * 16 - 2 * (left + right), 1366);
+        assert_eq!(mbs_high * 16 - 2 * (top + bottom), 768);
+    }
+
+    #[test]
+    fn payloads_never_contain_start_codes() {
+        let mut pattern = TestPattern::new(64, 64);
+        for frame in [pattern.keyframe(), pattern.delta(), pattern.keyframe()] {
+            for (_, payload) in nals(&frame) {
+                assert!(
+                    !payload
+                        .windows(3)
+                        .any(|w| w[0] == 0 && w[1] == 0 && w[2] <= 2),
+                    "unescaped start code in NAL payload"
+                );
+            }
+        }
+    }
+}
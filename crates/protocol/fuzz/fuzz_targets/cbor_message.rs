@@ -0,0 +1,21 @@
+//! CBOR binary frames from browsers on the `beam-cbor` subprotocol.
+
+#![no_main]
+
+use beam_protocol::{
+    AgentCommand, InputEvent, SignalingMessage, StampedInput, WireFormat, cbor_to_value,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Whatever decodes must encode back to the same value
+    if let Ok(value) = cbor_to_value(data) {
+        let again = WireFormat::Cbor.encode(&value).unwrap();
+        assert_eq!(cbor_to_value(&again).unwrap(), value);
+    }
+    let format = WireFormat::Cbor;
+    let _ = format.decode::<InputEvent>(data);
+    let _ = format.decode::<StampedInput>(data);
+    let _ = format.decode::<AgentCommand>(data);
+    let _ = format.decode::<SignalingMessage>(data);
+});
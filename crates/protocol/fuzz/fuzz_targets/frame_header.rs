@@ -0,0 +1,21 @@
+//! Binary WebSocket frames: the header is parsed before anything checks
+//! where the frame came from.
+
+#![no_main]
+
+use beam_protocol::{FRAME_HEADER_SIZE, VideoFrameHeader};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(header) = VideoFrameHeader::deserialize(data) else {
+        assert!(VideoFrameHeader::validate_complete(data).is_err());
+        return;
+    };
+    assert!(data.len() >= FRAME_HEADER_SIZE);
+    let complete = data.len() >= FRAME_HEADER_SIZE + header.payload_length as usize;
+    assert_eq!(VideoFrameHeader::validate_complete(data).is_ok(), complete);
+
+    let mut buf = [0u8; FRAME_HEADER_SIZE];
+    header.serialize(&mut buf);
+    assert_eq!(VideoFrameHeader::deserialize(&buf).unwrap(), header);
+});
@@ -0,0 +1,22 @@
+//! JSON text frames from browsers, viewers and agents.
+
+#![no_main]
+
+use beam_protocol::{
+    AgentCommand, InputEvent, MAX_INPUT_BATCH, SignalingMessage, StampedInput, TimedInput,
+    WireFormat, is_valid_input_batch,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let format = WireFormat::Json;
+    if let Ok(InputEvent::InputBatch { e }) = format.decode::<InputEvent>(data)
+        && e.len() > MAX_INPUT_BATCH
+    {
+        assert!(!is_valid_input_batch(&e));
+    }
+    let _ = format.decode::<StampedInput>(data);
+    let _ = format.decode::<TimedInput>(data);
+    let _ = format.decode::<AgentCommand>(data);
+    let _ = format.decode::<SignalingMessage>(data);
+});
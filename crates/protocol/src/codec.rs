@@ -0,0 +1,418 @@
+//! Wire encodings for signaling messages.
+//!
+//! JSON text frames are the default. A browser that offers the `beam-cbor`
+//! WebSocket subprotocol gets the same messages as CBOR binary frames
+//! instead: the message types are unchanged, only their framing differs.
+//! A CBOR message is always a map, so its first byte (0xa0..=0xbf) never
+//! collides with the `BEAV` magic of video, audio and webcam frames.
+//!
+//! CBOR goes through `serde_json::Value`, which keeps the internally and
+//! adjacently tagged enums working exactly as they do in JSON.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+
+/// WebSocket subprotocol that selects CBOR framing.
+pub const CBOR_SUBPROTOCOL: &str = "beam-cbor";
+
+/// Nesting limit when decoding, so hostile input can't exhaust the stack.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("invalid CBOR: {0}")]
+    Cbor(&'static str),
+}
+
+/// How signaling messages are framed on one WebSocket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    /// JSON text frames
+    #[default]
+    Json,
+    /// CBOR binary frames (`beam-cbor`)
+    Cbor,
+}
+
+impl WireFormat {
+    /// Format for the subprotocol the server selected during the upgrade.
+    pub fn from_subprotocol(protocol: Option<&str>) -> Self {
+        match protocol {
+            Some(CBOR_SUBPROTOCOL) => Self::Cbor,
+            _ => Self::Json,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(msg)?),
+            Self::Cbor => {
+                let mut out = Vec::new();
+                write_value(&serde_json::to_value(msg)?, &mut out);
+                Ok(out)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Self::Json => Ok(serde_json::from_slice(data)?),
+            Self::Cbor => Ok(serde_json::from_value(cbor_to_value(data)?)?),
+        }
+    }
+}
+
+/// Re-encode a JSON message (as relayed from the agent) as CBOR.
+pub fn json_to_cbor(json: &str) -> Result<Vec<u8>, CodecError> {
+    let value: Value = serde_json::from_str(json)?;
+    let mut out = Vec::new();
+    write_value(&value, &mut out);
+    Ok(out)
+}
+
+/// Decode one CBOR item into a JSON value.
+pub fn cbor_to_value(data: &[u8]) -> Result<Value, CodecError> {
+    let mut reader = Reader { data, pos: 0 };
+    let value = reader.value(0)?;
+    if reader.pos != data.len() {
+        return Err(CodecError::Cbor("trailing bytes"));
+    }
+    Ok(value)
+}
+
+/// Whether a binary WebSocket frame is a CBOR-encoded message rather than
+/// a media frame.
+pub fn is_cbor_message(data: &[u8]) -> bool {
+    data.first().is_some_and(|b| (0xa0..=0xbf).contains(b))
+}
+
+fn write_head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u8::MAX as u64 {
+        out.extend_from_slice(&[major | 24, n as u8]);
+    } else if n <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                write_head(0, u, out);
+            } else if let Some(i) = n.as_i64() {
+                // Only negative values reach here
+                write_head(1, !(i as u64), out);
+            } else {
+                let f = n.as_f64().unwrap_or_default();
+                // Most coordinates and ratios survive single precision
+                if (f as f32) as f64 == f {
+                    out.push(0xfa);
+                    out.extend_from_slice(&(f as f32).to_be_bytes());
+                } else {
+                    out.push(0xfb);
+                    out.extend_from_slice(&f.to_be_bytes());
+                }
+            }
+        }
+        Value::String(s) => {
+            write_head(3, s.len() as u64, out);
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(items) => {
+            write_head(4, items.len() as u64, out);
+            for item in items {
+                write_value(item, out);
+            }
+        }
+        Value::Object(map) => {
+            write_head(5, map.len() as u64, out);
+            for (key, item) in map {
+                write_head(3, key.len() as u64, out);
+                out.extend_from_slice(key.as_bytes());
+                write_value(item, out);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], CodecError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(CodecError::Cbor("truncated"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, CodecError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Argument of an item head; indefinite lengths are not supported.
+    fn argument(&mut self, info: u8) -> Result<u64, CodecError> {
+        Ok(match info {
+            0..=23 => info as u64,
+            24 => self.byte()? as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap_or_default()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap_or_default()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap_or_default()),
+            _ => return Err(CodecError::Cbor("unsupported length encoding")),
+        })
+    }
+
+    /// A collection length, bounded by the bytes left so a bogus length
+    /// can't trigger a huge allocation.
+    fn len(&mut self, info: u8) -> Result<usize, CodecError> {
+        let n = self.argument(info)?;
+        if n > (self.data.len() - self.pos) as u64 {
+            return Err(CodecError::Cbor("length exceeds message"));
+        }
+        Ok(n as usize)
+    }
+
+    fn text(&mut self, info: u8) -> Result<String, CodecError> {
+        let n = self.len(info)?;
+        let bytes = self.take(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| CodecError::Cbor("invalid UTF-8 in text"))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, CodecError> {
+        if depth > MAX_DEPTH {
+            return Err(CodecError::Cbor("nested too deeply"));
+        }
+        let head = self.byte()?;
+        let info = head & 0x1f;
+        Ok(match head >> 5 {
+            0 => Value::from(self.argument(info)?),
+            1 => {
+                let n = self.argument(info)?;
+                let n = i64::try_from(n).map_err(|_| CodecError::Cbor("integer out of range"))?;
+                Value::from(-1 - n)
+            }
+            3 => Value::String(self.text(info)?),
+            4 => {
+                let n = self.len(info)?;
+                let mut items = Vec::with_capacity(n);
+                for _ in 0..n {
+                    items.push(self.value(depth + 1)?);
+                }
+                Value::Array(items)
+            }
+            5 => {
+                let n = self.len(info)?;
+                let mut map = Map::new();
+                for _ in 0..n {
+                    let key_head = self.byte()?;
+                    if key_head >> 5 != 3 {
+                        return Err(CodecError::Cbor("map keys must be text"));
+                    }
+                    let key = self.text(key_head & 0x1f)?;
+                    map.insert(key, self.value(depth + 1)?);
+                }
+                Value::Object(map)
+            }
+            7 => match info {
+                20 => Value::Bool(false),
+                21 => Value::Bool(true),
+                22 | 23 => Value::Null,
+                25 => {
+                    let bits = u16::from_be_bytes(self.take(2)?.try_into().unwrap_or_default());
+                    float(f16_to_f64(bits))?
+                }
+                26 => {
+                    let bits = u32::from_be_bytes(self.take(4)?.try_into().unwrap_or_default());
+                    float(f32::from_bits(bits) as f64)?
+                }
+                27 => {
+                    let bits = u64::from_be_bytes(self.take(8)?.try_into().unwrap_or_default());
+                    float(f64::from_bits(bits))?
+                }
+                _ => return Err(CodecError::Cbor("unsupported simple value")),
+            },
+            // Byte strings and tags have no JSON equivalent in this protocol
+            _ => return Err(CodecError::Cbor("unsupported major type")),
+        })
+    }
+}
+
+fn float(f: f64) -> Result<Value, CodecError> {
+    Number::from_f64(f)
+        .map(Value::Number)
+        .ok_or(CodecError::Cbor("non-finite float"))
+}
+
+/// IEEE 754 half precision, which other CBOR encoders use for small floats.
+fn f16_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((bits >> 10) & 0x1f) as i32;
+    let mant = (bits & 0x3ff) as f64;
+    match exp {
+        0 => sign * mant * 2f64.powi(-24),
+        31 if mant == 0.0 => sign * f64::INFINITY,
+        31 => f64::NAN,
+        _ => sign * (1.0 + mant / 1024.0) * 2f64.powi(exp - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AgentCommand, Hello, InputEvent, SignalingMessage};
+
+    fn roundtrip<T>(format: WireFormat, msg: &T) -> T
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let bytes = format.encode(msg).unwrap();
+        format.decode(&bytes).unwrap()
+    }
+
+    #[test]
+    fn both_formats_roundtrip_messages() {
+        for format in [WireFormat::Json, WireFormat::Cbor] {
+            let key = InputEvent::Key { c: 30, d: true };
+            assert!(matches!(
+                roundtrip(format, &key),
+                InputEvent::Key { c: 30, d: true }
+            ));
+
+            let mouse = InputEvent::MouseMove { x: 0.5, y: 0.1 };
+            match roundtrip(format, &mouse) {
+                InputEvent::MouseMove { x, y } => assert_eq!((x, y), (0.5, 0.1)),
+                other => panic!("expected MouseMove, got {other:?}"),
+            }
+
+            let scroll = InputEvent::Scroll { dx: 0.0, dy: -30.0 };
+            assert!(matches!(
+                roundtrip(format, &scroll),
+                InputEvent::Scroll { dy, .. } if dy == -30.0
+            ));
+
+            let cmd = AgentCommand::Input(InputEvent::Clipboard {
+                text: "héllo ✓".to_string(),
+            });
+            match roundtrip(format, &cmd) {
+                AgentCommand::Input(InputEvent::Clipboard { text }) => assert_eq!(text, "héllo ✓"),
+                other => panic!("expected clipboard command, got {other:?}"),
+            }
+
+            let hello = Hello::new(&["lock_screen"]);
+            assert_eq!(roundtrip(format, &hello), hello);
+
+            let err = SignalingMessage::Error {
+                message: "replaced".to_string(),
+            };
+            assert!(matches!(
+                roundtrip(format, &err),
+                SignalingMessage::Error { message } if message == "replaced"
+            ));
+        }
+    }
+
+    #[test]
+    fn cbor_key_event_bytes() {
+        // Cross-language contract: web/src/cbor.test.ts decodes these bytes
+        let key = InputEvent::Key { c: 30, d: true };
+        assert_eq!(
+            WireFormat::Cbor.encode(&key).unwrap(),
+            [
+                0xa3, 0x61, 0x63, 0x18, 0x1e, 0x61, 0x64, 0xf5, 0x61, 0x74, 0x61, 0x6b
+            ]
+        );
+    }
+
+    #[test]
+    fn cbor_is_smaller_than_json() {
+        let mouse = InputEvent::MouseMove { x: 0.25, y: 0.75 };
+        let json = WireFormat::Json.encode(&mouse).unwrap();
+        let cbor = WireFormat::Cbor.encode(&mouse).unwrap();
+        assert!(cbor.len() < json.len(), "{} >= {}", cbor.len(), json.len());
+        assert!(is_cbor_message(&cbor));
+    }
+
+    #[test]
+    fn cbor_integers_and_floats() {
+        for value in [
+            serde_json::json!(0),
+            serde_json::json!(23),
+            serde_json::json!(24),
+            serde_json::json!(65_536),
+            serde_json::json!(u64::MAX),
+            serde_json::json!(-1),
+            serde_json::json!(i64::MIN),
+            serde_json::json!(0.1),
+            serde_json::json!(-2.5),
+            serde_json::json!(null),
+            serde_json::json!([true, false, "", {}]),
+        ] {
+            let mut bytes = Vec::new();
+            write_value(&value, &mut bytes);
+            assert_eq!(cbor_to_value(&bytes).unwrap(), value, "{bytes:02x?}");
+        }
+        // Half-precision 1.5 from another encoder
+        assert_eq!(
+            cbor_to_value(&[0xf9, 0x3e, 0x00]).unwrap(),
+            serde_json::json!(1.5)
+        );
+    }
+
+    #[test]
+    fn cbor_rejects_malformed_input() {
+        for bad in [
+            &[][..],
+            &[0xa1],                         // map missing its entry
+            &[0x7a, 0xff, 0xff, 0xff],       // text longer than the message
+            &[0xa1, 0x01, 0x01],             // integer map key
+            &[0x5f],                         // indefinite byte string
+            &[0x01, 0x02],                   // trailing bytes
+            &[0xfa, 0x7f, 0xc0, 0x00, 0x00], // NaN
+        ] {
+            assert!(cbor_to_value(bad).is_err(), "{bad:02x?}");
+        }
+        let deep = [0x81; MAX_DEPTH + 2];
+        assert!(cbor_to_value(&deep).is_err());
+    }
+
+    #[test]
+    fn media_frames_are_not_cbor_messages() {
+        assert!(!is_cbor_message(&crate::FRAME_MAGIC.to_le_bytes()));
+        assert!(!is_cbor_message(&[]));
+        assert_eq!(
+            WireFormat::from_subprotocol(Some(CBOR_SUBPROTOCOL)),
+            WireFormat::Cbor
+        );
+        assert_eq!(WireFormat::from_subprotocol(None), WireFormat::Json);
+    }
+
+    #[test]
+    fn json_relay_transcodes_to_cbor() {
+        let json = r#"{"t":"c","text":"copied"}"#;
+        let cbor = json_to_cbor(json).unwrap();
+        let event: InputEvent = WireFormat::Cbor.decode(&cbor).unwrap();
+        assert!(matches!(event, InputEvent::Clipboard { text } if text == "copied"));
+    }
+}
@@ -1,7 +1,11 @@
+use crate::{
+    ClipboardPolicy, DEFAULT_OPUS_FRAME_MS, Desktop, DisplayMode, MAX_DISPLAY_MODES, OPUS_FRAME_MS,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Top-level configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BeamConfig {
     #[serde(default)]
     pub server: ServerConfig,
@@ -11,6 +15,16 @@ pub struct BeamConfig {
     pub audio: AudioConfig,
     #[serde(default)]
     pub session: SessionConfig,
+    #[serde(default)]
+    pub agent: AgentConfig,
+    #[serde(default)]
+    pub assist: AssistConfig,
+    #[serde(default)]
+    pub guest: GuestConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +50,34 @@ pub struct ServerConfig {
     /// Users allowed to access /api/admin/* endpoints (empty = admin panel disabled)
     #[serde(default)]
     pub admin_users: Vec<String>,
+    /// Seconds a draining server waits for its sessions to end before
+    /// shutting down anyway (0 = wait indefinitely)
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+    /// Seconds between WebSocket pings on browser and agent connections
+    #[serde(default = "default_ws_ping_interval")]
+    pub ws_ping_interval: u64,
+    /// Seconds without any frame from a browser or agent before its
+    /// connection is treated as dead and closed
+    #[serde(default = "default_ws_timeout")]
+    pub ws_timeout: u64,
+    /// Reverse proxies whose `X-Forwarded-For` names the client, as
+    /// addresses or CIDR ranges (empty = the TCP peer is the client)
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// MaxMind DB (GeoLite2 Country or City) to record login countries
+    pub geoip_db: Option<String>,
+    /// Seconds an address is banned after exhausting its login attempts,
+    /// doubling with each repeat ban (0 = rate limiting only, no bans)
+    #[serde(default = "default_ip_ban_secs")]
+    pub ip_ban_secs: u64,
+    /// Longest ban the doubling can reach
+    #[serde(default = "default_ip_ban_max_secs")]
+    pub ip_ban_max_secs: u64,
+    /// Minor releases an agent may be behind or ahead of the server before
+    /// it is warned about. Agents of another major release are refused.
+    #[serde(default = "default_agent_version_window")]
+    pub agent_version_window: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +91,11 @@ pub struct VideoConfig {
     /// Maximum bitrate in kbps (for adaptive bitrate)
     #[serde(default = "default_max_bitrate")]
     pub max_bitrate: u32,
+    /// Bitrate in kbps the Sharp and Lossless presets may not stay over;
+    /// the agent falls back to Balanced after a few seconds above it.
+    /// Held to the session's bandwidth cap, if lower (0 = no ceiling).
+    #[serde(default = "default_quality_ceiling")]
+    pub quality_ceiling: u32,
     /// Target framerate
     #[serde(default = "default_framerate")]
     pub framerate: u32,
@@ -60,6 +107,40 @@ pub struct VideoConfig {
     /// Maximum height (0 = unlimited, default: 2160)
     #[serde(default = "default_max_height")]
     pub max_height: u32,
+    /// Chroma subsampling: "420" (default) or "444" (High 4:4:4 profile,
+    /// sharper colored text; nvh264enc and x264enc only)
+    #[serde(default = "default_chroma")]
+    pub chroma: String,
+    /// Encoder tuning overrides (`[video.tuning]`)
+    #[serde(default)]
+    pub tuning: EncoderTuning,
+    /// Directory where each agent writes its pipeline stage histograms as
+    /// `beam-agent-<session>.prom`, for node_exporter's textfile collector.
+    /// Must be writable by session users.
+    pub stage_metrics_dir: Option<String>,
+}
+
+/// Encoder knobs passed through to the agent. Unset fields use the agent's
+/// per-encoder low-latency defaults. Values are encoder-specific: `preset`
+/// is e.g. "low-latency-hq" for nvh264enc, "ultrafast" for x264enc and a
+/// target-usage level (1-7) for vah264enc. B-frames are always disabled.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncoderTuning {
+    /// Speed/quality preset
+    pub preset: Option<String>,
+    /// Tuning profile (nvh264enc/x264enc), e.g. "zerolatency"
+    pub tune: Option<String>,
+    /// Rate control mode, e.g. "cbr-ld-hq" (nvh264enc), "cbr" (vah264enc/x264enc)
+    pub rc_mode: Option<String>,
+    /// Keyframe interval in frames
+    pub gop_size: Option<u32>,
+    /// Rate-control lookahead in frames; each frame adds a frame of latency
+    pub lookahead: Option<u32>,
+    /// Refresh the picture a column at a time across each GOP instead of
+    /// sending periodic IDR frames, so a dropped frame heals without a
+    /// keyframe-sized burst (x264enc; other encoders keep periodic IDRs)
+    #[serde(default)]
+    pub intra_refresh: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,6 +151,72 @@ pub struct AudioConfig {
     /// Opus bitrate in kbps
     #[serde(default = "default_audio_bitrate")]
     pub bitrate: u32,
+    /// Captured channels: 1 (mono) or 2 (stereo)
+    #[serde(default = "default_audio_channels")]
+    pub channels: u16,
+    /// Opus frame length in ms: 10, 20 or 40. Longer frames ride out
+    /// network jitter better and cost less overhead; shorter ones cut
+    /// latency
+    #[serde(default = "default_audio_frame_ms")]
+    pub frame_ms: u32,
+    /// Opus inband forward error correction: each frame carries a coarse
+    /// copy of the one before, at the cost of some bitrate
+    #[serde(default)]
+    pub fec: bool,
+    /// Opus discontinuous transmission: silence is sent as a frame every
+    /// 400 ms instead of a full stream. Always on for the voice profile
+    #[serde(default)]
+    pub dtx: bool,
+    /// Sound server each session gets (`"auto"`, `"pulseaudio"` or
+    /// `"pipewire"`)
+    #[serde(default)]
+    pub backend: AudioBackend,
+}
+
+/// The sound server started for each session. Applications reach either
+/// through the same PulseAudio socket; PipeWire is captured natively.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    /// PipeWire when pipewire, wireplumber and pipewire-pulse are all
+    /// installed, else PulseAudio
+    #[default]
+    Auto,
+    PulseAudio,
+    PipeWire,
+}
+
+impl AudioBackend {
+    /// Programs a PipeWire session runs
+    pub const PIPEWIRE_PROGRAMS: [&str; 3] = ["pipewire", "wireplumber", "pipewire-pulse"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::PulseAudio => "pulseaudio",
+            Self::PipeWire => "pipewire",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "pulseaudio" => Some(Self::PulseAudio),
+            "pipewire" => Some(Self::PipeWire),
+            _ => None,
+        }
+    }
+
+    /// The backend `Auto` stands for on this host; others as they are.
+    pub fn resolve(self) -> Self {
+        match self {
+            Self::Auto if Self::PIPEWIRE_PROGRAMS.iter().all(|p| program_exists(p)) => {
+                Self::PipeWire
+            }
+            Self::Auto => Self::PulseAudio,
+            backend => backend,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -89,6 +236,409 @@ pub struct SessionConfig {
     /// Idle timeout in seconds (0 = disabled)
     #[serde(default = "default_idle_timeout")]
     pub idle_timeout: u64,
+    /// Whether browsers may ask the agent to synthesize system key combos
+    /// like Ctrl+Alt+Del. A login can turn them off for its session, never
+    /// on.
+    #[serde(default)]
+    pub system_combos: bool,
+    /// Make the session's default web browser hand http(s) links back to
+    /// the client, which opens them in a local tab.
+    #[serde(default)]
+    pub forward_urls: bool,
+    /// Have the agent replay input with the spacing the browser saw, at
+    /// the cost of up to 50 ms of added latency while jitter lasts
+    #[serde(default)]
+    pub pace_input: bool,
+    /// Lock the desktop whenever the browser disconnects, so an abandoned
+    /// tab never leaves an unlocked session behind
+    #[serde(default)]
+    pub lock_on_disconnect: bool,
+    /// With `lock_on_disconnect`: a password login unlocks the desktop
+    /// instead of the user unlocking it in the session. Needs a locker
+    /// that can be unlocked by command (xfce4-screensaver).
+    #[serde(default)]
+    pub unlock_on_login: bool,
+    /// What the agent does as a session sits unused (`[session.idle]`)
+    #[serde(default)]
+    pub idle: IdlePolicy,
+    /// Per-user changes to `idle`, keyed by login name
+    /// (`[session.idle_users.<name>]`)
+    #[serde(default)]
+    pub idle_users: HashMap<String, IdlePolicyOverride>,
+    /// Which way clipboard text may cross between browser and desktop
+    #[serde(default)]
+    pub clipboard: ClipboardPolicy,
+    /// Per-user `clipboard`, keyed by login name (`[session.clipboard_users]`)
+    #[serde(default)]
+    pub clipboard_users: HashMap<String, ClipboardPolicy>,
+    /// Also bridge the X11 PRIMARY selection, so text selected in the
+    /// session can be middle-click pasted on a Linux client and back.
+    /// Off by default: clients without a PRIMARY selection get stray
+    /// selections copied around for nothing.
+    #[serde(default)]
+    pub primary_selection: bool,
+    /// Let session owners mint view-only links for someone without an
+    /// account to watch the desktop (`POST /api/sessions/{id}/share`)
+    #[serde(default)]
+    pub share_links: bool,
+    /// Longest lifetime a share link may ask for, in seconds
+    #[serde(default = "default_share_link_max_secs")]
+    pub share_link_max_secs: u64,
+    /// Recent clipboard texts the agent keeps for re-pasting
+    /// (`[session.clipboard_history]`)
+    #[serde(default)]
+    pub clipboard_history: ClipboardHistoryConfig,
+    /// Hard ceiling on one session's video bitrate in kbps, whatever the
+    /// bandwidth probe or `video.bitrate` say (0 = no cap)
+    #[serde(default)]
+    pub max_bandwidth_kbps: u32,
+    /// Per-user `max_bandwidth_kbps`, keyed by login name
+    /// (`[session.bandwidth_users]`)
+    #[serde(default)]
+    pub bandwidth_users: HashMap<String, u32>,
+    /// Ceiling on all sessions' video together in kbps. When their own
+    /// ceilings add up to more, each is lowered by the same proportion
+    /// (0 = no cap).
+    #[serde(default)]
+    pub host_bandwidth_kbps: u32,
+    /// Seconds of per-second stats kept for each session and served by
+    /// `GET /api/sessions/{id}/stats/history` (0 = keep none)
+    #[serde(default = "default_stats_history_secs")]
+    pub stats_history_secs: u32,
+    /// How often agents send a small screenshot of their session for the
+    /// admin panel, in seconds (0 = never). Not taken of remote assistance
+    /// sessions.
+    #[serde(default)]
+    pub thumbnail_secs: u32,
+    /// Keep admins from observing sessions: no thumbnails are taken, and
+    /// attempts are refused and audited. Owners can also turn it on for a
+    /// session of their own (`PUT /api/sessions/{id}/privacy`).
+    #[serde(default)]
+    pub privacy: bool,
+    /// Per-user `privacy`, keyed by login name (`[session.privacy_users]`)
+    #[serde(default)]
+    pub privacy_users: HashMap<String, bool>,
+    /// Modes the virtual monitor offers besides the one the browser's
+    /// window asks for, e.g. `["2560x1440@75", "1080x1920"]`. A resize to
+    /// one of their sizes uses its refresh rate.
+    #[serde(default)]
+    pub display_modes: Vec<DisplayMode>,
+    /// Per-user `display_modes`, keyed by login name
+    /// (`[session.display_modes_users]`)
+    #[serde(default)]
+    pub display_modes_users: HashMap<String, Vec<DisplayMode>>,
+    /// What a browser window of another size does to the session's display
+    #[serde(default)]
+    pub resize_policy: ResizePolicy,
+    /// Create a user's missing home directory from /etc/skel before their
+    /// agent starts, as on a host where accounts come from LDAP
+    #[serde(default = "default_true")]
+    pub create_home: bool,
+    /// Desktop environment sessions run (`"auto"`, `"xfce"`, `"kde"`,
+    /// `"gnome"`, `"i3"`, `"openbox"` or a command). A login may pick
+    /// another of the named ones.
+    #[serde(default)]
+    pub desktop: Desktop,
+}
+
+/// How the session's display follows the browser window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizePolicy {
+    /// The display is resized to the window
+    #[default]
+    Resize,
+    /// The display keeps `default_width` x `default_height`; browsers fit
+    /// the picture in their window with bars on two sides, and the agent
+    /// scales it down for windows smaller than the display
+    Letterbox,
+}
+
+/// Largest `max_total_bytes`: the whole history goes to the browser in one
+/// WebSocket message.
+pub const MAX_CLIPBOARD_HISTORY_BYTES: u32 = 1_048_576;
+
+/// Shortest lifetime a share link can have, in seconds
+pub const MIN_SHARE_LINK_SECS: u64 = 60;
+
+/// Bandwidth caps below this (kbps) leave video barely watchable
+pub const MIN_USABLE_BANDWIDTH_KBPS: u32 = 1000;
+
+/// Longest stats history a session may keep, in seconds
+pub const MAX_STATS_HISTORY_SECS: u32 = 3600;
+
+/// Shortest interval between session thumbnails, in seconds
+pub const MIN_THUMBNAIL_SECS: u32 = 5;
+
+/// Size limits of the agent's clipboard history. Only kept when the
+/// session's clipboard policy lets text leave the session, since the
+/// browser can list it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipboardHistoryConfig {
+    /// Entries kept, newest first (0 = no history)
+    #[serde(default = "default_clipboard_history_entries")]
+    pub entries: u32,
+    /// Longest text recorded; longer copies still work but aren't kept
+    #[serde(default = "default_clipboard_history_entry_bytes")]
+    pub max_entry_bytes: u32,
+    /// Bytes across all entries; the oldest are dropped first
+    #[serde(default = "default_clipboard_history_total_bytes")]
+    pub max_total_bytes: u32,
+}
+
+impl Default for ClipboardHistoryConfig {
+    fn default() -> Self {
+        Self {
+            entries: default_clipboard_history_entries(),
+            max_entry_bytes: default_clipboard_history_entry_bytes(),
+            max_total_bytes: default_clipboard_history_total_bytes(),
+        }
+    }
+}
+
+/// Agent thread scheduling, watchdog limits and sandboxing (`[agent]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentConfig {
+    /// Real-time scheduling (`[agent.realtime]`)
+    #[serde(default)]
+    pub realtime: RealtimeConfig,
+    /// Core pinning (`[agent.cpu_affinity]`)
+    #[serde(default)]
+    pub cpu_affinity: CpuAffinity,
+    /// Resident memory (MiB) past which the agent's watchdog exits so the
+    /// server restarts it (0 = no limit)
+    #[serde(default = "default_max_rss_mb")]
+    pub max_rss_mb: u32,
+    /// When video stops reaching the browser, have it restart its decoder
+    /// from a fresh keyframe instead of leaving a frozen picture; if that
+    /// doesn't help, recreate the encoder, then ask the browser to
+    /// reconnect (at most 3 times in 10 minutes)
+    #[serde(default)]
+    pub freeze_recovery: bool,
+    /// Once the session is up, drop the agent's capabilities and deny it
+    /// (and anything it launches afterwards) kernel-level syscalls such as
+    /// ptrace, bpf and module loading
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Agent executable, or a wrapper that execs it. A bare name is looked
+    /// up in PATH; unset, `beam-agent` next to the server binary is
+    /// preferred over the one in PATH
+    #[serde(default)]
+    pub binary_path: Option<String>,
+    /// Arguments appended after the ones the server passes
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    /// Environment variables set for the agent; `RUST_LOG` here replaces
+    /// the default "info"
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// Remote assistance on the host's physical display (`[assist]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssistConfig {
+    /// Let admins attach to `display`, once the person at it agrees
+    #[serde(default)]
+    pub enabled: bool,
+    /// X display of the physical console
+    #[serde(default = "default_assist_display")]
+    pub display: String,
+    /// Account logged in on that display; by default whoever holds the
+    /// active session on seat0, per logind
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Seconds the local user has to accept before the request is refused
+    #[serde(default = "default_assist_consent_secs")]
+    pub consent_secs: u64,
+    /// Whether the operator may use keyboard and mouse from the start; the
+    /// local user can pause and resume that from the on-screen banner
+    #[serde(default = "default_true")]
+    pub control: bool,
+}
+
+/// Time-limited sessions on throwaway accounts (`[guest]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuestConfig {
+    /// Let admins, and whoever holds `token`, start guest sessions
+    #[serde(default)]
+    pub enabled: bool,
+    /// Bearer token that starts guest sessions without an admin login, for
+    /// kiosks and workshop links (None = admins only)
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Seconds a guest session lasts before it is ended and its account
+    /// deleted
+    #[serde(default = "default_guest_lifetime_secs")]
+    pub lifetime_secs: u64,
+    /// Guest accounts that may exist at once
+    #[serde(default = "default_max_guests")]
+    pub max_guests: u32,
+    /// Start of guest account names, followed by 8 random hex digits
+    #[serde(default = "default_guest_user_prefix")]
+    pub user_prefix: String,
+}
+
+impl GuestConfig {
+    /// Length of the random part of guest account names
+    pub const SUFFIX_LEN: usize = 8;
+}
+
+/// Agent log files (`[logging]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Each session logs to `<agent_dir>/<session id>/agent.log`, readable
+    /// only by the session user
+    #[serde(default = "default_agent_log_dir")]
+    pub agent_dir: String,
+    /// Size past which `agent.log` is rotated to `agent.log.1` (0 = never)
+    #[serde(default = "default_agent_log_max_bytes")]
+    pub agent_max_bytes: u64,
+    /// Rotated files kept per session
+    #[serde(default = "default_agent_log_keep")]
+    pub agent_keep: u32,
+    /// Seconds an ended session's logs are kept (0 = forever)
+    #[serde(default = "default_agent_log_retention_secs")]
+    pub agent_retention_secs: u64,
+}
+
+/// Where logins are checked (`[auth]`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// htpasswd-style file of `name:hash` lines (bcrypt or argon2) checked
+    /// instead of PAM; managed with `beam-server users`
+    pub users_file: Option<String>,
+    /// Run the PAM account and session phases for each agent, as a login
+    /// would: expired accounts are refused, and session modules such as
+    /// pam_mkhomedir and pam_limits apply. Off by default; only with PAM
+    /// logins, as root.
+    #[serde(default)]
+    pub pam_session: bool,
+}
+
+impl AssistConfig {
+    /// Number of `display` (":0" is 0), if it names a local X display
+    pub fn display_number(&self) -> Option<u32> {
+        self.display.strip_prefix(':')?.parse().ok()
+    }
+}
+
+/// Scheduler class and per-thread priorities. Raising a thread to a
+/// real-time class needs CAP_SYS_NICE; without it the agent uses the
+/// highest priority RLIMIT_RTPRIO allows, and failing that leaves the
+/// thread on the normal scheduler.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RealtimeConfig {
+    /// "fifo" (SCHED_FIFO), "rr" (SCHED_RR) or "off" (normal scheduling)
+    #[serde(default = "default_realtime_policy")]
+    pub policy: String,
+    /// Capture/encode thread priority, 1-99 (0 = normal scheduling)
+    #[serde(default = "default_capture_priority")]
+    pub capture: u8,
+    /// Audio capture thread priority (0 = normal scheduling)
+    #[serde(default)]
+    pub audio: u8,
+    /// Tokio runtime thread priority (0 = normal scheduling)
+    #[serde(default)]
+    pub workers: u8,
+}
+
+/// CPUs each thread group may run on; empty leaves it unpinned.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuAffinity {
+    #[serde(default)]
+    pub capture: Vec<usize>,
+    #[serde(default)]
+    pub audio: Vec<usize>,
+    #[serde(default)]
+    pub workers: Vec<usize>,
+}
+
+/// Idle stages, each counted from the later of the last user input and the
+/// last visible screen change. Times are in seconds; 0 disables a stage.
+/// Unlike `idle_timeout` (browser heartbeats stopped), these apply while a
+/// browser is connected but nobody is using it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdlePolicy {
+    /// Drop to `throttle_framerate` after this long
+    #[serde(default = "default_idle_throttle_after")]
+    pub throttle_after: u64,
+    /// Capture framerate while throttled
+    #[serde(default = "default_idle_throttle_framerate")]
+    pub throttle_framerate: u32,
+    /// Blank the display and lock the desktop's screen locker
+    #[serde(default)]
+    pub lock_after: u64,
+    /// Disconnect the browser; the desktop keeps running
+    #[serde(default)]
+    pub disconnect_after: u64,
+    /// End the session
+    #[serde(default)]
+    pub destroy_after: u64,
+}
+
+/// Per-user `IdlePolicy` fields; unset ones keep the global value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdlePolicyOverride {
+    pub throttle_after: Option<u64>,
+    pub throttle_framerate: Option<u32>,
+    pub lock_after: Option<u64>,
+    pub disconnect_after: Option<u64>,
+    pub destroy_after: Option<u64>,
+}
+
+impl IdlePolicy {
+    fn with_override(self, o: &IdlePolicyOverride) -> Self {
+        Self {
+            throttle_after: o.throttle_after.unwrap_or(self.throttle_after),
+            throttle_framerate: o.throttle_framerate.unwrap_or(self.throttle_framerate),
+            lock_after: o.lock_after.unwrap_or(self.lock_after),
+            disconnect_after: o.disconnect_after.unwrap_or(self.disconnect_after),
+            destroy_after: o.destroy_after.unwrap_or(self.destroy_after),
+        }
+    }
+}
+
+impl SessionConfig {
+    /// Idle policy for `username`: the global one with their overrides applied.
+    pub fn idle_policy_for(&self, username: &str) -> IdlePolicy {
+        match self.idle_users.get(username) {
+            Some(o) => self.idle.with_override(o),
+            None => self.idle,
+        }
+    }
+
+    /// Clipboard policy for `username`: their own if set, else the global one.
+    pub fn clipboard_policy_for(&self, username: &str) -> ClipboardPolicy {
+        self.clipboard_users
+            .get(username)
+            .copied()
+            .unwrap_or(self.clipboard)
+    }
+
+    /// Video bitrate cap for `username` in kbps: their own if set, else the
+    /// global one (0 = no cap).
+    pub fn bandwidth_cap_for(&self, username: &str) -> u32 {
+        self.bandwidth_users
+            .get(username)
+            .copied()
+            .unwrap_or(self.max_bandwidth_kbps)
+    }
+
+    /// Whether `username`'s sessions are always private.
+    pub fn privacy_for(&self, username: &str) -> bool {
+        self.privacy_users
+            .get(username)
+            .copied()
+            .unwrap_or(self.privacy)
+    }
+
+    /// Preferred display modes for `username`: their own list if set, else
+    /// the global one.
+    pub fn display_modes_for(&self, username: &str) -> &[DisplayMode] {
+        self.display_modes_users
+            .get(username)
+            .unwrap_or(&self.display_modes)
+    }
 }
 
 impl Default for ServerConfig {
@@ -102,6 +652,14 @@ impl Default for ServerConfig {
             web_root: default_web_root(),
             metrics_require_auth: true,
             admin_users: Vec::new(),
+            drain_timeout: default_drain_timeout(),
+            ws_ping_interval: default_ws_ping_interval(),
+            ws_timeout: default_ws_timeout(),
+            trusted_proxies: Vec::new(),
+            geoip_db: None,
+            ip_ban_secs: default_ip_ban_secs(),
+            ip_ban_max_secs: default_ip_ban_max_secs(),
+            agent_version_window: default_agent_version_window(),
         }
     }
 }
@@ -112,10 +670,14 @@ impl Default for VideoConfig {
             bitrate: default_bitrate(),
             min_bitrate: default_min_bitrate(),
             max_bitrate: default_max_bitrate(),
+            quality_ceiling: default_quality_ceiling(),
             framerate: default_framerate(),
             encoder: None,
             max_width: default_max_width(),
             max_height: default_max_height(),
+            chroma: default_chroma(),
+            tuning: EncoderTuning::default(),
+            stage_metrics_dir: None,
         }
     }
 }
@@ -125,6 +687,11 @@ impl Default for AudioConfig {
         Self {
             enabled: true,
             bitrate: default_audio_bitrate(),
+            channels: default_audio_channels(),
+            frame_ms: default_audio_frame_ms(),
+            fec: false,
+            dtx: false,
+            backend: AudioBackend::default(),
         }
     }
 }
@@ -137,10 +704,137 @@ impl Default for SessionConfig {
             display_start: default_display_start(),
             max_sessions: default_max_sessions(),
             idle_timeout: default_idle_timeout(),
+            system_combos: false,
+            forward_urls: false,
+            pace_input: false,
+            lock_on_disconnect: false,
+            unlock_on_login: false,
+            idle: IdlePolicy::default(),
+            idle_users: HashMap::new(),
+            clipboard: ClipboardPolicy::default(),
+            clipboard_users: HashMap::new(),
+            primary_selection: false,
+            share_links: false,
+            share_link_max_secs: default_share_link_max_secs(),
+            clipboard_history: ClipboardHistoryConfig::default(),
+            max_bandwidth_kbps: 0,
+            bandwidth_users: HashMap::new(),
+            host_bandwidth_kbps: 0,
+            stats_history_secs: default_stats_history_secs(),
+            thumbnail_secs: 0,
+            privacy: false,
+            privacy_users: HashMap::new(),
+            display_modes: Vec::new(),
+            display_modes_users: HashMap::new(),
+            resize_policy: ResizePolicy::default(),
+            create_home: true,
+            desktop: Desktop::default(),
         }
     }
 }
 
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            realtime: RealtimeConfig::default(),
+            cpu_affinity: CpuAffinity::default(),
+            max_rss_mb: default_max_rss_mb(),
+            freeze_recovery: false,
+            sandbox: false,
+            binary_path: None,
+            extra_args: Vec::new(),
+            env: HashMap::new(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            agent_dir: default_agent_log_dir(),
+            agent_max_bytes: default_agent_log_max_bytes(),
+            agent_keep: default_agent_log_keep(),
+            agent_retention_secs: default_agent_log_retention_secs(),
+        }
+    }
+}
+
+impl Default for AssistConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            display: default_assist_display(),
+            user: None,
+            consent_secs: default_assist_consent_secs(),
+            control: true,
+        }
+    }
+}
+
+impl Default for GuestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            token: None,
+            lifetime_secs: default_guest_lifetime_secs(),
+            max_guests: default_max_guests(),
+            user_prefix: default_guest_user_prefix(),
+        }
+    }
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        Self {
+            policy: default_realtime_policy(),
+            capture: default_capture_priority(),
+            audio: 0,
+            workers: 0,
+        }
+    }
+}
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        Self {
+            throttle_after: default_idle_throttle_after(),
+            throttle_framerate: default_idle_throttle_framerate(),
+            lock_after: 0,
+            disconnect_after: 0,
+            destroy_after: 0,
+        }
+    }
+}
+
+/// Bitrate/framerate an agent starts streaming with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamParams {
+    /// Bitrate in kbps
+    pub bitrate: u32,
+    pub framerate: u32,
+}
+
+impl VideoConfig {
+    /// Initial stream parameters for a link measured at `kbps` with `rtt_ms`.
+    ///
+    /// Uses ~70% of the measured throughput (the probe sees an idle link;
+    /// audio, input and bursts need the rest), clamped to
+    /// `min_bitrate..=bitrate`. Slow or distant links also get a lower
+    /// framerate so each frame keeps enough bits to stay legible.
+    pub fn fit_to_link(&self, kbps: u32, rtt_ms: u32) -> StreamParams {
+        let bitrate = (kbps / 10 * 7)
+            .max(self.min_bitrate)
+            .min(self.bitrate.max(self.min_bitrate));
+        let mut framerate = self.framerate;
+        if bitrate < 5_000 || rtt_ms > 150 {
+            framerate = framerate.min(30);
+        } else if bitrate < 15_000 || rtt_ms > 60 {
+            framerate = framerate.min(60);
+        }
+        StreamParams { bitrate, framerate }
+    }
+}
+
 impl BeamConfig {
     /// Validate the configuration, returning a list of issues found.
     ///
@@ -191,6 +885,17 @@ impl BeamConfig {
             issues.push("ERROR: server.port must be between 1 and 65535, got 0.".to_string());
         }
 
+        // --- WebSocket keepalive ---
+        if self.server.ws_ping_interval == 0 {
+            issues.push("ERROR: server.ws_ping_interval must be at least 1 second.".to_string());
+        } else if self.server.ws_timeout <= self.server.ws_ping_interval {
+            issues.push(format!(
+                "ERROR: server.ws_timeout ({}s) must be longer than server.ws_ping_interval ({}s), \
+                 or every connection would time out between pings.",
+                self.server.ws_timeout, self.server.ws_ping_interval
+            ));
+        }
+
         // --- Video bitrate ---
         if self.video.bitrate > 200_000 {
             issues.push(format!(
@@ -223,6 +928,67 @@ impl BeamConfig {
             ));
         }
 
+        // --- Chroma subsampling ---
+        if self.video.chroma != "420" && self.video.chroma != "444" {
+            issues.push(format!(
+                "ERROR: video.chroma must be \"420\" or \"444\", got \"{}\".",
+                self.video.chroma
+            ));
+        } else if self.video.chroma == "444" && self.video.encoder.as_deref() == Some("vah264enc") {
+            issues.push(
+                "WARNING: video.chroma = \"444\" is not supported by vah264enc. \
+                 The agent will fall back to 4:2:0."
+                    .to_string(),
+            );
+        }
+
+        // --- Encoder tuning ---
+        if self.video.tuning.gop_size == Some(0) {
+            issues.push("ERROR: video.tuning.gop_size must be at least 1.".to_string());
+        }
+        if let Some(lookahead) = self.video.tuning.lookahead
+            && lookahead > 0
+        {
+            issues.push(format!(
+                "WARNING: video.tuning.lookahead = {lookahead} delays every frame by \
+                 {lookahead} frames of encoder latency. Use 0 for interactive sessions."
+            ));
+        }
+
+        // --- Audio ---
+        if !(6..=510).contains(&self.audio.bitrate) {
+            issues.push(format!(
+                "ERROR: audio.bitrate must be between 6 and 510 kbps (Opus limits), got {}.",
+                self.audio.bitrate
+            ));
+        }
+        if !matches!(self.audio.channels, 1 | 2) {
+            issues.push(format!(
+                "ERROR: audio.channels must be 1 or 2, got {}.",
+                self.audio.channels
+            ));
+        }
+        if !OPUS_FRAME_MS.contains(&self.audio.frame_ms) {
+            issues.push(format!(
+                "ERROR: audio.frame_ms must be one of {OPUS_FRAME_MS:?}, got {}.",
+                self.audio.frame_ms
+            ));
+        }
+        if self.audio.enabled {
+            let needed: &[&str] = match self.audio.backend {
+                AudioBackend::Auto => &[],
+                AudioBackend::PulseAudio => &["pulseaudio"],
+                AudioBackend::PipeWire => &AudioBackend::PIPEWIRE_PROGRAMS,
+            };
+            if let Some(program) = needed.iter().find(|p| !program_exists(p)) {
+                issues.push(format!(
+                    "WARNING: audio.backend \"{}\" needs {program}, which is not installed. \
+                     Sessions will have no sound.",
+                    self.audio.backend.name()
+                ));
+            }
+        }
+
         // --- Display start ---
         if self.session.display_start == 0 {
             issues.push(
@@ -246,6 +1012,267 @@ impl BeamConfig {
             ));
         }
 
+        // --- Screen lock ---
+        if self.session.unlock_on_login && !self.session.lock_on_disconnect {
+            issues.push(
+                "WARNING: session.unlock_on_login has no effect without session.lock_on_disconnect."
+                    .to_string(),
+            );
+        }
+
+        // --- Idle policy ---
+        let mut policies = vec![("session.idle".to_string(), self.session.idle)];
+        let mut users: Vec<_> = self.session.idle_users.keys().collect();
+        users.sort();
+        for user in users {
+            policies.push((
+                format!("session.idle_users.{user}"),
+                self.session.idle_policy_for(user),
+            ));
+        }
+        for (name, policy) in &policies {
+            if policy.throttle_framerate == 0 {
+                issues.push(format!(
+                    "ERROR: {name}.throttle_framerate must be at least 1."
+                ));
+            }
+            for (stage, secs) in [
+                ("lock_after", policy.lock_after),
+                ("disconnect_after", policy.disconnect_after),
+                ("destroy_after", policy.destroy_after),
+            ] {
+                if secs > 0 && secs < 60 {
+                    issues.push(format!(
+                        "ERROR: {name}.{stage} must be 0 (disabled) or at least 60 seconds, \
+                         got {secs}."
+                    ));
+                }
+            }
+            if policy.destroy_after > 0 && policy.destroy_after <= policy.disconnect_after {
+                issues.push(format!(
+                    "WARNING: {name}.destroy_after ({}) is not later than disconnect_after ({}), \
+                     so idle sessions are ended without a disconnect first.",
+                    policy.destroy_after, policy.disconnect_after
+                ));
+            }
+        }
+
+        // --- Clipboard history ---
+        let history = &self.session.clipboard_history;
+        if history.max_total_bytes > MAX_CLIPBOARD_HISTORY_BYTES {
+            issues.push(format!(
+                "ERROR: session.clipboard_history.max_total_bytes must be at most \
+                 {MAX_CLIPBOARD_HISTORY_BYTES}, got {}.",
+                history.max_total_bytes
+            ));
+        }
+        if history.entries > 0 && history.max_entry_bytes > history.max_total_bytes {
+            issues.push(format!(
+                "WARNING: session.clipboard_history.max_entry_bytes ({}) exceeds \
+                 max_total_bytes ({}), so copies between the two are never kept.",
+                history.max_entry_bytes, history.max_total_bytes
+            ));
+        }
+
+        // --- Share links ---
+        if self.session.share_links && self.session.share_link_max_secs < MIN_SHARE_LINK_SECS {
+            issues.push(format!(
+                "ERROR: session.share_link_max_secs must be at least {MIN_SHARE_LINK_SECS}, got {}.",
+                self.session.share_link_max_secs
+            ));
+        }
+
+        // --- Bandwidth caps ---
+        let mut caps = vec![
+            (
+                "session.max_bandwidth_kbps".to_string(),
+                self.session.max_bandwidth_kbps,
+            ),
+            (
+                "session.host_bandwidth_kbps".to_string(),
+                self.session.host_bandwidth_kbps,
+            ),
+        ];
+        let mut users: Vec<_> = self.session.bandwidth_users.iter().collect();
+        users.sort();
+        caps.extend(
+            users
+                .into_iter()
+                .map(|(user, &kbps)| (format!("session.bandwidth_users.{user}"), kbps)),
+        );
+        for (name, kbps) in caps {
+            if kbps != 0 && kbps < MIN_USABLE_BANDWIDTH_KBPS {
+                issues.push(format!(
+                    "WARNING: {name} is {kbps} kbps; video below \
+                     {MIN_USABLE_BANDWIDTH_KBPS} kbps is barely watchable."
+                ));
+            }
+        }
+        let host = self.session.host_bandwidth_kbps;
+        if host != 0 && host < self.session.max_bandwidth_kbps {
+            issues.push(format!(
+                "WARNING: session.host_bandwidth_kbps ({host}) is below \
+                 session.max_bandwidth_kbps ({}), so no session ever reaches its own cap.",
+                self.session.max_bandwidth_kbps
+            ));
+        }
+
+        // --- Stats history ---
+        if self.session.stats_history_secs > MAX_STATS_HISTORY_SECS {
+            issues.push(format!(
+                "ERROR: session.stats_history_secs must be at most {MAX_STATS_HISTORY_SECS}, got {}.",
+                self.session.stats_history_secs
+            ));
+        }
+
+        // --- Thumbnails ---
+        let thumbnail_secs = self.session.thumbnail_secs;
+        if thumbnail_secs > 0 && thumbnail_secs < MIN_THUMBNAIL_SECS {
+            issues.push(format!(
+                "ERROR: session.thumbnail_secs must be 0 or at least {MIN_THUMBNAIL_SECS}, got {thumbnail_secs}."
+            ));
+        }
+        if thumbnail_secs > 0 && self.server.admin_users.is_empty() {
+            issues.push(
+                "WARNING: session.thumbnail_secs is set but admin_users is empty, so nobody \
+                 can see the thumbnails."
+                    .to_string(),
+            );
+        }
+        if thumbnail_secs > 0
+            && self.session.privacy
+            && !self.session.privacy_users.values().any(|&p| !p)
+        {
+            issues.push(
+                "WARNING: session.thumbnail_secs is set but session.privacy makes every session \
+                 private, so no thumbnails are taken."
+                    .to_string(),
+            );
+        }
+
+        // --- Preferred display modes ---
+        let mode_lists = std::iter::once((
+            "session.display_modes".to_string(),
+            &self.session.display_modes,
+        ))
+        .chain(
+            self.session
+                .display_modes_users
+                .iter()
+                .map(|(user, modes)| (format!("session.display_modes_users.{user}"), modes)),
+        );
+        for (key, modes) in mode_lists {
+            if modes.len() > MAX_DISPLAY_MODES {
+                issues.push(format!(
+                    "ERROR: {key} may list at most {MAX_DISPLAY_MODES} modes, got {}.",
+                    modes.len()
+                ));
+            }
+            let (max_width, max_height) = (self.video.max_width, self.video.max_height);
+            for mode in modes {
+                if (max_width > 0 && mode.width > max_width)
+                    || (max_height > 0 && mode.height > max_height)
+                {
+                    issues.push(format!(
+                        "WARNING: {key} has {mode}, larger than video.max_width x max_height \
+                         ({max_width}x{max_height}), so browsers are never resized to it."
+                    ));
+                }
+            }
+        }
+
+        // --- Desktop environment ---
+        match self.session.desktop.program() {
+            None if self.session.desktop != Desktop::Auto => issues.push(
+                "ERROR: session.desktop must not be empty; use \"auto\" for XFCE or openbox."
+                    .to_string(),
+            ),
+            Some(program) if !program_exists(program) => issues.push(format!(
+                "WARNING: session.desktop \"{}\" needs {program}, which is not installed. \
+                 Sessions fall back to XFCE or openbox.",
+                self.session.desktop
+            )),
+            _ => {}
+        }
+
+        // --- Agent thread scheduling ---
+        let realtime = &self.agent.realtime;
+        if !matches!(realtime.policy.as_str(), "fifo" | "rr" | "off") {
+            issues.push(format!(
+                "ERROR: agent.realtime.policy must be \"fifo\", \"rr\" or \"off\", got \"{}\".",
+                realtime.policy
+            ));
+        }
+        for (thread, priority) in [
+            ("capture", realtime.capture),
+            ("audio", realtime.audio),
+            ("workers", realtime.workers),
+        ] {
+            if priority > 99 {
+                issues.push(format!(
+                    "ERROR: agent.realtime.{thread} must be between 0 (normal scheduling) \
+                     and 99, got {priority}."
+                ));
+            }
+        }
+        let cpus = std::thread::available_parallelism().map_or(usize::MAX, |n| n.get());
+        let affinity = &self.agent.cpu_affinity;
+        for (thread, set) in [
+            ("capture", &affinity.capture),
+            ("audio", &affinity.audio),
+            ("workers", &affinity.workers),
+        ] {
+            if let Some(cpu) = set.iter().find(|&&cpu| cpu >= cpus) {
+                issues.push(format!(
+                    "WARNING: agent.cpu_affinity.{thread} lists CPU {cpu}, but this machine \
+                     has {cpus}. The agent ignores CPUs that do not exist."
+                ));
+            }
+        }
+
+        // --- Agent spawning ---
+        if let Some(path) = &self.agent.binary_path
+            && path.contains('/')
+            && !std::path::Path::new(path).is_file()
+        {
+            issues.push(format!(
+                "ERROR: agent.binary_path '{path}' does not exist, so no session could start."
+            ));
+        }
+        if self.agent.env.contains_key("BEAM_AGENT_TOKEN") {
+            issues.push(
+                "ERROR: agent.env must not set BEAM_AGENT_TOKEN; the server passes each \
+                 agent its own token."
+                    .to_string(),
+            );
+        }
+
+        // --- Logging ---
+        if self.logging.agent_dir.is_empty() {
+            issues.push("ERROR: logging.agent_dir must not be empty.".to_string());
+        }
+        if self.logging.agent_max_bytes > 0 && self.logging.agent_max_bytes < 64 * 1024 {
+            issues.push(format!(
+                "WARNING: logging.agent_max_bytes is {} bytes; agent logs will rotate \
+                 constantly and the kept files hold only moments of history.",
+                self.logging.agent_max_bytes
+            ));
+        }
+
+        // --- Auth ---
+        if let Some(path) = &self.auth.users_file {
+            if path.is_empty() {
+                issues.push(
+                    "ERROR: auth.users_file must not be empty; omit it to use PAM.".to_string(),
+                );
+            } else if !std::path::Path::new(path).exists() {
+                issues.push(format!(
+                    "WARNING: auth.users_file '{path}' does not exist; nobody can log in \
+                     until a user is added with: beam-server users add <NAME>"
+                ));
+            }
+        }
+
         // --- Admin users ---
         for user in &self.server.admin_users {
             let trimmed = user.trim();
@@ -273,6 +1300,94 @@ impl BeamConfig {
             }
         }
 
+        // --- Login audit ---
+        for entry in &self.server.trusted_proxies {
+            if parse_ip_prefix(entry).is_none() {
+                issues.push(format!(
+                    "ERROR: trusted_proxies entry '{entry}' is not an IP address or CIDR range."
+                ));
+            }
+        }
+        if let Some(path) = &self.server.geoip_db
+            && !std::path::Path::new(path).exists()
+        {
+            issues.push(format!(
+                "ERROR: geoip_db '{path}' does not exist. \
+                 Download GeoLite2-Country.mmdb from MaxMind or remove the setting."
+            ));
+        }
+
+        // --- Remote assistance ---
+        if self.assist.enabled {
+            match self.assist.display_number() {
+                None => issues.push(format!(
+                    "ERROR: assist.display '{}' must name a local X display such as \":0\".",
+                    self.assist.display
+                )),
+                Some(num) if num >= self.session.display_start => issues.push(format!(
+                    "ERROR: assist.display '{}' is in the range used for virtual desktops \
+                     (session.display_start = {}).",
+                    self.assist.display, self.session.display_start
+                )),
+                Some(_) => {}
+            }
+            if !(5..=600).contains(&self.assist.consent_secs) {
+                issues.push(format!(
+                    "ERROR: assist.consent_secs must be between 5 and 600, got {}.",
+                    self.assist.consent_secs
+                ));
+            }
+        }
+
+        // --- Guest sessions ---
+        if self.guest.enabled {
+            if !(60..=86_400).contains(&self.guest.lifetime_secs) {
+                issues.push(format!(
+                    "ERROR: guest.lifetime_secs must be between 60 and 86400, got {}.",
+                    self.guest.lifetime_secs
+                ));
+            }
+            if self.guest.max_guests == 0 {
+                issues.push("ERROR: guest.max_guests must be at least 1.".to_string());
+            }
+            // useradd takes names of up to 32 characters, starting with a
+            // lowercase letter or underscore
+            let prefix = &self.guest.user_prefix;
+            let valid_prefix = prefix
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+                && prefix
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+                && prefix.len() + GuestConfig::SUFFIX_LEN <= 32;
+            if !valid_prefix {
+                issues.push(format!(
+                    "ERROR: guest.user_prefix '{prefix}' must start with a lowercase letter, \
+                     hold only lowercase letters, digits, '_' and '-', and be at most {} \
+                     characters long.",
+                    32 - GuestConfig::SUFFIX_LEN
+                ));
+            }
+            if let Some(token) = &self.guest.token
+                && token.len() < 16
+            {
+                issues.push(
+                    "ERROR: guest.token must be at least 16 characters long; \
+                     generate one with: openssl rand -hex 16"
+                        .to_string(),
+                );
+            }
+        }
+
+        // --- Login bans ---
+        if self.server.ip_ban_secs > 0 && self.server.ip_ban_max_secs < self.server.ip_ban_secs {
+            issues.push(format!(
+                "ERROR: server.ip_ban_max_secs ({}s) must be at least server.ip_ban_secs ({}s).",
+                self.server.ip_ban_max_secs, self.server.ip_ban_secs
+            ));
+        }
+
         if issues.is_empty() {
             Ok(())
         } else {
@@ -280,7 +1395,152 @@ impl BeamConfig {
         }
     }
 }
-
+
+/// Environment variables named `BEAM_<SECTION>__<KEY>` override config
+/// keys: `BEAM_SERVER__PORT=9000` sets `port` under `[server]`, and
+/// `BEAM_VIDEO__TUNING__PRESET` a key in `[video.tuning]`. Values are read
+/// as TOML (`9000`, `true`, `["alice", "bob"]`, `"0123"`), or as a plain
+/// string when they aren't valid TOML.
+pub const ENV_OVERRIDE_PREFIX: &str = "BEAM_";
+
+/// Whether `name` is a config override rather than one of the other
+/// `BEAM_` variables (`BEAM_SERVER`, `BEAM_TOKEN`...), which have no `__`.
+pub fn is_env_override(name: &str) -> bool {
+    name.strip_prefix(ENV_OVERRIDE_PREFIX)
+        .is_some_and(|path| path.contains("__") && !path.split("__").any(str::is_empty))
+}
+
+/// The `BEAM_*__*` variables a config was read with.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EnvOverrides {
+    /// Variables that set a config key
+    pub applied: Vec<String>,
+    /// Variables naming no config key, most likely misspelled
+    pub unknown: Vec<String>,
+}
+
+fn env_value(text: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("v = {text}"))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(text.to_string()))
+}
+
+impl BeamConfig {
+    /// Parse a config file with the process environment's overrides on top.
+    pub fn from_toml_and_env(text: &str) -> Result<(Self, EnvOverrides), toml::de::Error> {
+        let vars = std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        });
+        Self::from_toml_with_env(text, vars)
+    }
+
+    /// Parse a config file with the overrides among `vars` on top. They
+    /// apply in name order, so a key overridden twice (`BEAM_SERVER__PORT`
+    /// and `BEAM_SERVER__port`) gets the same value every time.
+    pub fn from_toml_with_env(
+        text: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(Self, EnvOverrides), toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(text)?;
+        let mut overrides: Vec<(String, Vec<String>, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| is_env_override(name))
+            .map(|(name, value)| {
+                let path = name[ENV_OVERRIDE_PREFIX.len()..]
+                    .split("__")
+                    .map(str::to_ascii_lowercase)
+                    .collect();
+                (name, path, value)
+            })
+            .collect();
+        overrides.sort();
+
+        for (_, path, value) in &overrides {
+            let (key, sections) = path.split_last().expect("overrides have two keys or more");
+            let mut at = &mut table;
+            for section in sections {
+                let entry = at
+                    .entry(section.clone())
+                    .or_insert_with(|| toml::Table::new().into());
+                if !entry.is_table() {
+                    *entry = toml::Table::new().into();
+                }
+                at = entry.as_table_mut().expect("made a table above");
+            }
+            at.insert(key.clone(), env_value(value));
+        }
+        let config: Self = toml::Value::Table(table).try_into()?;
+
+        // Keys serde doesn't know are dropped without an error; they're
+        // missing when the config is written back out
+        let resolved = toml::Value::try_from(&config).ok();
+        let mut report = EnvOverrides::default();
+        for (name, path, _) in overrides {
+            let known = resolved.as_ref().is_none_or(|resolved| {
+                path.iter()
+                    .try_fold(resolved, |value, key| value.get(key))
+                    .is_some()
+            });
+            if known {
+                report.applied.push(name);
+            } else {
+                report.unknown.push(name);
+            }
+        }
+        Ok((config, report))
+    }
+}
+
+/// Whether `program` is a file, for a path, or found in PATH.
+fn program_exists(program: &str) -> bool {
+    if program.contains('/') {
+        return std::path::Path::new(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Parse an address or CIDR range such as `10.0.0.0/8` or `2001:db8::/32`
+/// into the address and prefix length.
+pub fn parse_ip_prefix(s: &str) -> Option<(std::net::IpAddr, u8)> {
+    let (addr, len) = match s.trim().split_once('/') {
+        Some((addr, len)) => (addr, Some(len)),
+        None => (s.trim(), None),
+    };
+    let addr: std::net::IpAddr = addr.parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let len = match len {
+        Some(len) => len.parse().ok().filter(|&len| len <= max)?,
+        None => max,
+    };
+    Some((addr, len))
+}
+
+fn default_drain_timeout() -> u64 {
+    3600 // 1 hour
+}
+fn default_ws_ping_interval() -> u64 {
+    15
+}
+fn default_ws_timeout() -> u64 {
+    45 // three missed pings
+}
+fn default_share_link_max_secs() -> u64 {
+    14400 // 4 hours
+}
+fn default_stats_history_secs() -> u32 {
+    600 // 10 minutes
+}
+fn default_ip_ban_secs() -> u64 {
+    300
+}
+fn default_ip_ban_max_secs() -> u64 {
+    86400 // 1 day
+}
+fn default_agent_version_window() -> u32 {
+    1
+}
 fn default_web_root() -> String {
     "web/dist".to_string()
 }
@@ -299,6 +1559,9 @@ fn default_min_bitrate() -> u32 {
 fn default_max_bitrate() -> u32 {
     100000
 }
+fn default_quality_ceiling() -> u32 {
+    100000
+}
 fn default_framerate() -> u32 {
     120
 }
@@ -308,12 +1571,21 @@ fn default_max_width() -> u32 {
 fn default_max_height() -> u32 {
     2160 // 4K
 }
+fn default_chroma() -> String {
+    "420".to_string()
+}
 fn default_true() -> bool {
     true
 }
 fn default_audio_bitrate() -> u32 {
     128
 }
+fn default_audio_channels() -> u16 {
+    2
+}
+fn default_audio_frame_ms() -> u32 {
+    DEFAULT_OPUS_FRAME_MS
+}
 fn default_width() -> u32 {
     1920
 }
@@ -329,6 +1601,60 @@ fn default_max_sessions() -> u32 {
 fn default_idle_timeout() -> u64 {
     3600 // 1 hour
 }
+fn default_max_rss_mb() -> u32 {
+    4096
+}
+fn default_agent_log_dir() -> String {
+    "/var/log/beam".to_string()
+}
+fn default_agent_log_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+fn default_agent_log_keep() -> u32 {
+    3
+}
+fn default_agent_log_retention_secs() -> u64 {
+    86400 // 1 day
+}
+
+fn default_clipboard_history_entries() -> u32 {
+    20
+}
+
+fn default_clipboard_history_entry_bytes() -> u32 {
+    262_144
+}
+
+fn default_clipboard_history_total_bytes() -> u32 {
+    MAX_CLIPBOARD_HISTORY_BYTES
+}
+fn default_assist_display() -> String {
+    ":0".to_string()
+}
+fn default_assist_consent_secs() -> u64 {
+    30
+}
+fn default_guest_lifetime_secs() -> u64 {
+    3600
+}
+fn default_max_guests() -> u32 {
+    10
+}
+fn default_guest_user_prefix() -> String {
+    "beam-guest-".to_string()
+}
+fn default_realtime_policy() -> String {
+    "fifo".to_string()
+}
+fn default_capture_priority() -> u8 {
+    50
+}
+fn default_idle_throttle_after() -> u64 {
+    300 // 5 minutes
+}
+fn default_idle_throttle_framerate() -> u32 {
+    5
+}
 
 #[cfg(test)]
 mod tests {
@@ -352,6 +1678,7 @@ mod tests {
         assert_eq!(config.video.bitrate, 50000);
         assert_eq!(config.video.min_bitrate, 2000);
         assert_eq!(config.video.max_bitrate, 100000);
+        assert_eq!(config.video.quality_ceiling, 100000);
         assert_eq!(config.video.framerate, 120);
         assert!(config.video.encoder.is_none());
         assert_eq!(config.video.max_width, 3840);
@@ -366,6 +1693,13 @@ mod tests {
         assert_eq!(config.session.default_height, 1080);
         assert_eq!(config.session.display_start, 10);
         assert_eq!(config.session.max_sessions, 8);
+        assert!(!config.session.system_combos);
+        assert!(!config.session.forward_urls);
+        assert!(!config.session.pace_input);
+        assert!(!config.session.lock_on_disconnect);
+        assert!(!config.session.primary_selection);
+        assert!(!config.session.share_links);
+        assert_eq!(config.session.share_link_max_secs, 14400);
         assert_eq!(config.session.idle_timeout, 3600);
     }
 
@@ -447,6 +1781,7 @@ metrics_require_auth = false
 bitrate = 10000
 min_bitrate = 1000
 max_bitrate = 30000
+quality_ceiling = 40000
 framerate = 120
 encoder = "nvh264enc"
 max_width = 7680
@@ -455,6 +1790,9 @@ max_height = 4320
 [audio]
 enabled = false
 bitrate = 256
+frame_ms = 40
+fec = true
+backend = "pipewire"
 
 [session]
 default_width = 2560
@@ -462,6 +1800,46 @@ default_height = 1440
 display_start = 20
 max_sessions = 16
 idle_timeout = 7200
+system_combos = true
+forward_urls = true
+pace_input = true
+lock_on_disconnect = true
+unlock_on_login = true
+clipboard = "to-remote"
+primary_selection = true
+share_links = true
+share_link_max_secs = 600
+max_bandwidth_kbps = 20000
+host_bandwidth_kbps = 100000
+stats_history_secs = 1800
+thumbnail_secs = 30
+resize_policy = "letterbox"
+desktop = "i3"
+display_modes = ["1920x1080@75"]
+
+[session.clipboard_users]
+bob = "off"
+
+[session.bandwidth_users]
+carol = 50000
+
+[session.privacy_users]
+dave = true
+
+[session.display_modes_users]
+erin = ["1080x1920", "2560x1440@144"]
+
+[session.clipboard_history]
+entries = 5
+
+[session.idle]
+throttle_after = 120
+lock_after = 900
+disconnect_after = 1800
+
+[session.idle_users.alice]
+lock_after = 0
+destroy_after = 7200
 "#;
         let config: BeamConfig =
             toml::from_str(toml_str).expect("full custom config should deserialize");
@@ -482,6 +1860,7 @@ idle_timeout = 7200
         assert_eq!(config.video.bitrate, 10000);
         assert_eq!(config.video.min_bitrate, 1000);
         assert_eq!(config.video.max_bitrate, 30000);
+        assert_eq!(config.video.quality_ceiling, 40000);
         assert_eq!(config.video.framerate, 120);
         assert_eq!(config.video.encoder.as_deref(), Some("nvh264enc"));
         assert_eq!(config.video.max_width, 7680);
@@ -490,6 +1869,10 @@ idle_timeout = 7200
         // Audio
         assert!(!config.audio.enabled);
         assert_eq!(config.audio.bitrate, 256);
+        assert_eq!(config.audio.channels, 2);
+        assert_eq!(config.audio.frame_ms, 40);
+        assert!(config.audio.fec);
+        assert!(!config.audio.dtx);
 
         // Session
         assert_eq!(config.session.default_width, 2560);
@@ -497,6 +1880,59 @@ idle_timeout = 7200
         assert_eq!(config.session.display_start, 20);
         assert_eq!(config.session.max_sessions, 16);
         assert_eq!(config.session.idle_timeout, 7200);
+        assert!(config.session.system_combos);
+        assert!(config.session.forward_urls);
+        assert!(config.session.pace_input);
+        assert!(config.session.lock_on_disconnect);
+        assert!(config.session.unlock_on_login);
+        assert_eq!(
+            config.session.idle,
+            IdlePolicy {
+                throttle_after: 120,
+                throttle_framerate: 5,
+                lock_after: 900,
+                disconnect_after: 1800,
+                destroy_after: 0,
+            }
+        );
+        let alice = config.session.idle_policy_for("alice");
+        assert_eq!(alice.lock_after, 0);
+        assert_eq!(alice.disconnect_after, 1800);
+        assert_eq!(alice.destroy_after, 7200);
+        assert_eq!(
+            config.session.clipboard_policy_for("alice"),
+            ClipboardPolicy::ToRemote
+        );
+        assert_eq!(
+            config.session.clipboard_policy_for("bob"),
+            ClipboardPolicy::Off
+        );
+        assert!(config.session.primary_selection);
+        assert!(config.session.share_links);
+        assert_eq!(config.session.share_link_max_secs, 600);
+        assert_eq!(config.session.bandwidth_cap_for("alice"), 20_000);
+        assert_eq!(config.session.bandwidth_cap_for("carol"), 50_000);
+        assert_eq!(config.session.host_bandwidth_kbps, 100_000);
+        assert_eq!(config.session.stats_history_secs, 1800);
+        assert_eq!(config.session.thumbnail_secs, 30);
+        assert_eq!(config.session.resize_policy, ResizePolicy::Letterbox);
+        assert_eq!(config.session.desktop, Desktop::I3);
+        assert_eq!(config.audio.backend, AudioBackend::PipeWire);
+        assert!(config.session.privacy_for("dave"));
+        assert!(!config.session.privacy_for("alice"));
+        assert_eq!(
+            config.session.display_modes_for("alice"),
+            [DisplayMode {
+                width: 1920,
+                height: 1080,
+                refresh: 75
+            }]
+        );
+        assert_eq!(config.session.display_modes_for("erin").len(), 2);
+        assert_eq!(config.session.display_modes_for("erin")[1].refresh, 144);
+        assert_eq!(config.session.clipboard_history.entries, 5);
+        assert_eq!(config.session.clipboard_history.max_entry_bytes, 262_144);
+        assert_eq!(config.session.idle_policy_for("bob"), config.session.idle);
     }
 
     #[test]
@@ -515,6 +1951,15 @@ idle_timeout = 7200
             server.metrics_require_auth,
             from_toml.server.metrics_require_auth
         );
+        assert_eq!(server.drain_timeout, from_toml.server.drain_timeout);
+        assert_eq!(server.ws_ping_interval, from_toml.server.ws_ping_interval);
+        assert_eq!(server.ws_timeout, from_toml.server.ws_timeout);
+        assert_eq!(server.ip_ban_secs, from_toml.server.ip_ban_secs);
+        assert_eq!(server.ip_ban_max_secs, from_toml.server.ip_ban_max_secs);
+        assert_eq!(
+            server.agent_version_window,
+            from_toml.server.agent_version_window
+        );
 
         let video = VideoConfig::default();
         assert_eq!(video.bitrate, from_toml.video.bitrate);
@@ -535,6 +1980,43 @@ idle_timeout = 7200
         assert_eq!(session.display_start, from_toml.session.display_start);
         assert_eq!(session.max_sessions, from_toml.session.max_sessions);
         assert_eq!(session.idle_timeout, from_toml.session.idle_timeout);
+        assert_eq!(session.system_combos, from_toml.session.system_combos);
+        assert_eq!(session.forward_urls, from_toml.session.forward_urls);
+        assert_eq!(
+            session.lock_on_disconnect,
+            from_toml.session.lock_on_disconnect
+        );
+        assert_eq!(session.unlock_on_login, from_toml.session.unlock_on_login);
+        assert_eq!(session.idle, from_toml.session.idle);
+        assert!(from_toml.session.idle_users.is_empty());
+        assert_eq!(from_toml.session.clipboard, ClipboardPolicy::Bidirectional);
+        assert_eq!(from_toml.session.resize_policy, ResizePolicy::Resize);
+        assert!(from_toml.session.create_home);
+        assert_eq!(from_toml.session.desktop, Desktop::Auto);
+        assert_eq!(from_toml.audio.backend, AudioBackend::Auto);
+    }
+
+    #[test]
+    fn fit_to_link_scales_down_for_slow_links() {
+        let video = VideoConfig::default();
+
+        // Fast LAN: configured defaults untouched
+        let lan = video.fit_to_link(900_000, 1);
+        assert_eq!(lan.bitrate, video.bitrate);
+        assert_eq!(lan.framerate, video.framerate);
+
+        // 10 Mbps WAN: 7 Mbps, 60fps
+        let wan = video.fit_to_link(10_000, 40);
+        assert_eq!(wan.bitrate, 7_000);
+        assert_eq!(wan.framerate, 60);
+
+        // Very slow link never goes below min_bitrate; high RTT caps fps
+        let slow = video.fit_to_link(500, 200);
+        assert_eq!(slow.bitrate, video.min_bitrate);
+        assert_eq!(slow.framerate, 30);
+
+        // Plenty of bandwidth but far away
+        assert_eq!(video.fit_to_link(900_000, 100).framerate, 60);
     }
 
     // --- Validation tests ---
@@ -587,6 +2069,32 @@ idle_timeout = 7200
         assert!(config.validate().is_ok());
     }
 
+    #[test]
+    fn validate_ws_keepalive() {
+        let mut config = valid_config();
+        config.server.ws_ping_interval = 0;
+        assert!(has_error(&validate_issues(&config), "ws_ping_interval"));
+
+        config.server.ws_ping_interval = 30;
+        config.server.ws_timeout = 30;
+        assert!(has_error(&validate_issues(&config), "ws_timeout"));
+
+        config.server.ws_timeout = 31;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_ip_ban_limits() {
+        let mut config = valid_config();
+        config.server.ip_ban_secs = 600;
+        config.server.ip_ban_max_secs = 300;
+        assert!(has_error(&validate_issues(&config), "ip_ban_max_secs"));
+
+        // Bans off: the ceiling doesn't matter
+        config.server.ip_ban_secs = 0;
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn validate_tls_cert_missing_file_is_error() {
         let mut config = valid_config();
@@ -627,6 +2135,243 @@ idle_timeout = 7200
         );
     }
 
+    #[test]
+    fn encoder_tuning_section_parses() {
+        let toml_str = r#"
+[video.tuning]
+preset = "p1"
+rc_mode = "cbr"
+gop_size = 120
+intra_refresh = true
+"#;
+        let config: BeamConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.video.tuning.preset.as_deref(), Some("p1"));
+        assert_eq!(config.video.tuning.rc_mode.as_deref(), Some("cbr"));
+        assert_eq!(config.video.tuning.gop_size, Some(120));
+        assert!(config.video.tuning.tune.is_none());
+        assert!(config.video.tuning.lookahead.is_none());
+        assert!(config.video.tuning.intra_refresh);
+        // Rest of [video] keeps defaults
+        assert_eq!(config.video.bitrate, 50000);
+    }
+
+    #[test]
+    fn agent_section_parses() {
+        let toml_str = r#"
+[agent]
+sandbox = true
+freeze_recovery = true
+binary_path = "/opt/beam/bin/beam-agent"
+extra_args = ["--verbose"]
+
+[agent.env]
+RUST_LOG = "beam_agent=debug"
+
+[agent.realtime]
+policy = "rr"
+audio = 40
+
+[agent.cpu_affinity]
+capture = [2, 3]
+"#;
+        let config: BeamConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.agent.realtime.policy, "rr");
+        assert_eq!(config.agent.realtime.capture, 50);
+        assert_eq!(config.agent.realtime.audio, 40);
+        assert_eq!(config.agent.realtime.workers, 0);
+        assert_eq!(config.agent.cpu_affinity.capture, [2, 3]);
+        assert!(config.agent.cpu_affinity.workers.is_empty());
+        assert_eq!(config.agent.max_rss_mb, 4096);
+        assert!(config.agent.sandbox);
+        assert!(config.agent.freeze_recovery);
+        assert_eq!(
+            config.agent.binary_path.as_deref(),
+            Some("/opt/beam/bin/beam-agent")
+        );
+        assert_eq!(config.agent.extra_args, ["--verbose"]);
+        assert_eq!(config.agent.env["RUST_LOG"], "beam_agent=debug");
+
+        // Without [agent], only the capture thread goes real-time
+        let config: BeamConfig = toml::from_str("").unwrap();
+        assert_eq!(config.agent, AgentConfig::default());
+        assert_eq!(config.agent.realtime.policy, "fifo");
+        assert!(!config.agent.sandbox);
+        assert!(config.agent.binary_path.is_none());
+    }
+
+    #[test]
+    fn logging_section_parses() {
+        let config: BeamConfig = toml::from_str("").unwrap();
+        assert_eq!(config.logging, LoggingConfig::default());
+        assert_eq!(config.logging.agent_dir, "/var/log/beam");
+
+        let toml_str = r#"
+[logging]
+agent_dir = "/srv/beam/logs"
+agent_max_bytes = 1048576
+agent_retention_secs = 0
+"#;
+        let config: BeamConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.logging.agent_dir, "/srv/beam/logs");
+        assert_eq!(config.logging.agent_max_bytes, 1 << 20);
+        assert_eq!(config.logging.agent_keep, 3);
+        assert_eq!(config.logging.agent_retention_secs, 0);
+    }
+
+    #[test]
+    fn validate_logging() {
+        let mut config = valid_config();
+        config.logging.agent_dir = String::new();
+        config.logging.agent_max_bytes = 1000;
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "logging.agent_dir"));
+        assert!(has_warning(&issues, "logging.agent_max_bytes"));
+
+        // 0 turns rotation off rather than rotating on every write
+        config.logging.agent_dir = "/srv/beam/logs".to_string();
+        config.logging.agent_max_bytes = 0;
+        assert!(validate_issues(&config).is_empty());
+    }
+
+    #[test]
+    fn auth_users_file() {
+        let config: BeamConfig = toml::from_str("").unwrap();
+        assert!(config.auth.users_file.is_none());
+        assert!(!config.auth.pam_session);
+        let config: BeamConfig = toml::from_str("[auth]\npam_session = true").unwrap();
+        assert!(config.auth.pam_session);
+
+        let mut config = valid_config();
+        config.auth.users_file = Some("/nonexistent/beam-users".to_string());
+        assert!(has_warning(&validate_issues(&config), "auth.users_file"));
+        config.auth.users_file = Some(String::new());
+        assert!(has_error(&validate_issues(&config), "auth.users_file"));
+    }
+
+    #[test]
+    fn assist_section_parses() {
+        let config: BeamConfig = toml::from_str("").unwrap();
+        assert_eq!(config.assist, AssistConfig::default());
+        assert!(!config.assist.enabled);
+        assert_eq!(config.assist.display_number(), Some(0));
+
+        let toml_str = r#"
+[assist]
+enabled = true
+display = ":1"
+user = "alice"
+control = false
+"#;
+        let config: BeamConfig = toml::from_str(toml_str).unwrap();
+        assert!(config.assist.enabled);
+        assert_eq!(config.assist.display_number(), Some(1));
+        assert_eq!(config.assist.user.as_deref(), Some("alice"));
+        assert_eq!(config.assist.consent_secs, 30);
+        assert!(!config.assist.control);
+    }
+
+    #[test]
+    fn validate_assist() {
+        let mut config = valid_config();
+        config.assist.display = "localhost:0".to_string();
+        // Not checked while assistance is off
+        assert!(validate_issues(&config).is_empty());
+
+        config.assist.enabled = true;
+        assert!(has_error(&validate_issues(&config), "assist.display"));
+        config.assist.display = ":10".to_string();
+        assert!(has_error(&validate_issues(&config), "virtual desktops"));
+        config.assist.display = ":0".to_string();
+        config.assist.consent_secs = 0;
+        assert!(has_error(&validate_issues(&config), "assist.consent_secs"));
+        config.assist.consent_secs = 60;
+        assert!(validate_issues(&config).is_empty());
+    }
+
+    #[test]
+    fn guest_section_parses() {
+        let config: BeamConfig = toml::from_str("").unwrap();
+        assert_eq!(config.guest, GuestConfig::default());
+        assert!(!config.guest.enabled);
+
+        let toml_str = r#"
+[guest]
+enabled = true
+token = "0123456789abcdef0123"
+lifetime_secs = 1800
+"#;
+        let config: BeamConfig = toml::from_str(toml_str).unwrap();
+        assert!(config.guest.enabled);
+        assert_eq!(config.guest.token.as_deref(), Some("0123456789abcdef0123"));
+        assert_eq!(config.guest.lifetime_secs, 1800);
+        assert_eq!(config.guest.max_guests, 10);
+        assert_eq!(config.guest.user_prefix, "beam-guest-");
+    }
+
+    #[test]
+    fn validate_guest() {
+        let mut config = valid_config();
+        config.guest.lifetime_secs = 10;
+        // Not checked while guests are off
+        assert!(validate_issues(&config).is_empty());
+
+        config.guest.enabled = true;
+        assert!(has_error(&validate_issues(&config), "guest.lifetime_secs"));
+        config.guest.lifetime_secs = 600;
+        config.guest.max_guests = 0;
+        assert!(has_error(&validate_issues(&config), "guest.max_guests"));
+        config.guest.max_guests = 5;
+        for prefix in [
+            "",
+            "Guest-",
+            "-guest",
+            "guest/",
+            "a-very-long-guest-prefix-",
+        ] {
+            config.guest.user_prefix = prefix.to_string();
+            assert!(
+                has_error(&validate_issues(&config), "guest.user_prefix"),
+                "{prefix:?}"
+            );
+        }
+        config.guest.user_prefix = "kiosk_".to_string();
+        config.guest.token = Some("short".to_string());
+        assert!(has_error(&validate_issues(&config), "guest.token"));
+        config.guest.token = Some("0123456789abcdef".to_string());
+        assert!(validate_issues(&config).is_empty());
+    }
+
+    #[test]
+    fn validate_encoder_tuning() {
+        let mut config = valid_config();
+        config.video.tuning.gop_size = Some(0);
+        config.video.tuning.lookahead = Some(8);
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "gop_size"));
+        assert!(has_warning(&issues, "lookahead"));
+
+        config.video.tuning.gop_size = Some(60);
+        config.video.tuning.lookahead = Some(0);
+        assert!(validate_issues(&config).is_empty());
+    }
+
+    #[test]
+    fn validate_audio_limits() {
+        let mut config = valid_config();
+        config.audio.bitrate = 1000;
+        config.audio.channels = 6;
+        config.audio.frame_ms = 30;
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "audio.bitrate"));
+        assert!(has_error(&issues, "audio.channels"));
+        assert!(has_error(&issues, "audio.frame_ms"));
+
+        config.audio.bitrate = 32;
+        config.audio.channels = 1;
+        config.audio.frame_ms = 40;
+        assert!(validate_issues(&config).is_empty());
+    }
+
     #[test]
     fn validate_bitrate_over_200k_is_warning() {
         let mut config = valid_config();
@@ -735,6 +2480,103 @@ idle_timeout = 7200
         assert!(config.validate().is_ok());
     }
 
+    #[test]
+    fn validate_chroma_values() {
+        let mut config = valid_config();
+        assert_eq!(config.video.chroma, "420");
+        config.video.chroma = "444".to_string();
+        assert!(config.validate().is_ok());
+
+        config.video.chroma = "422".to_string();
+        let issues = validate_issues(&config);
+        assert!(
+            has_error(&issues, "video.chroma"),
+            "chroma=422 should produce error"
+        );
+    }
+
+    #[test]
+    fn validate_chroma_444_with_vaapi_warns() {
+        let mut config = valid_config();
+        config.video.chroma = "444".to_string();
+        config.video.encoder = Some("vah264enc".to_string());
+        let issues = validate_issues(&config);
+        assert!(
+            has_warning(&issues, "vah264enc"),
+            "chroma=444 with vah264enc should warn"
+        );
+    }
+
+    #[test]
+    fn validate_agent_scheduling() {
+        let mut config = valid_config();
+        config.agent.realtime.policy = "batch".to_string();
+        config.agent.realtime.audio = 100;
+        config.agent.cpu_affinity.workers = vec![0, 1 << 20];
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "agent.realtime.policy"));
+        assert!(has_error(&issues, "agent.realtime.audio"));
+        assert!(has_warning(&issues, "agent.cpu_affinity.workers"));
+
+        config.agent.realtime.policy = "rr".to_string();
+        config.agent.realtime.audio = 99;
+        config.agent.cpu_affinity.workers = vec![0];
+        assert!(validate_issues(&config).is_empty());
+    }
+
+    #[test]
+    fn validate_desktop() {
+        let mut config = valid_config();
+        config.session.desktop = Desktop::parse("sh");
+        assert!(validate_issues(&config).is_empty());
+
+        config.session.desktop = Desktop::parse("  ");
+        assert!(has_error(&validate_issues(&config), "session.desktop"));
+
+        config.session.desktop = Desktop::parse("/nonexistent/bin/startdesktop --full");
+        assert!(has_warning(
+            &validate_issues(&config),
+            "needs /nonexistent/bin/startdesktop"
+        ));
+    }
+
+    #[test]
+    fn validate_audio_backend() {
+        let mut config = valid_config();
+        config.audio.backend = AudioBackend::PipeWire;
+        let issues = validate_issues(&config);
+        let missing = AudioBackend::PIPEWIRE_PROGRAMS
+            .iter()
+            .any(|p| !program_exists(p));
+        assert_eq!(has_warning(&issues, "audio.backend"), missing);
+
+        // No sound server is started without audio
+        config.audio.enabled = false;
+        assert!(!has_warning(&validate_issues(&config), "audio.backend"));
+
+        assert_eq!(
+            AudioBackend::from_name("pulseaudio"),
+            Some(AudioBackend::PulseAudio)
+        );
+        assert_eq!(AudioBackend::from_name("pulse"), None);
+        assert_ne!(AudioBackend::Auto.resolve(), AudioBackend::Auto);
+    }
+
+    #[test]
+    fn validate_agent_spawning() {
+        let mut config = valid_config();
+        config.agent.binary_path = Some("/nonexistent/beam-agent".to_string());
+        config.agent.env = HashMap::from([("BEAM_AGENT_TOKEN".to_string(), "x".to_string())]);
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "agent.binary_path"));
+        assert!(has_error(&issues, "BEAM_AGENT_TOKEN"));
+
+        // Bare names are found in PATH when the agent starts
+        config.agent.binary_path = Some("beam-agent-wrapper".to_string());
+        config.agent.env = HashMap::from([("RUST_LOG".to_string(), "debug".to_string())]);
+        assert!(validate_issues(&config).is_empty());
+    }
+
     #[test]
     fn validate_display_start_zero_is_error() {
         let mut config = valid_config();
@@ -796,6 +2638,152 @@ idle_timeout = 7200
         assert!(config.validate().is_ok());
     }
 
+    #[test]
+    fn validate_idle_policy_stages() {
+        let mut config = valid_config();
+        config.session.idle.disconnect_after = 30;
+        config.session.idle_users.insert(
+            "alice".to_string(),
+            IdlePolicyOverride {
+                throttle_framerate: Some(0),
+                ..Default::default()
+            },
+        );
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "session.idle.disconnect_after"));
+        assert!(has_error(
+            &issues,
+            "session.idle_users.alice.throttle_framerate"
+        ));
+
+        let mut config = valid_config();
+        config.session.idle.disconnect_after = 600;
+        config.session.idle.destroy_after = 600;
+        let issues = validate_issues(&config);
+        assert!(has_warning(&issues, "session.idle.destroy_after"));
+    }
+
+    #[test]
+    fn validate_clipboard_history_limits() {
+        let mut config = valid_config();
+        config.session.clipboard_history.max_total_bytes = MAX_CLIPBOARD_HISTORY_BYTES + 1;
+        assert!(has_error(
+            &validate_issues(&config),
+            "session.clipboard_history.max_total_bytes"
+        ));
+
+        let mut config = valid_config();
+        config.session.clipboard_history.max_total_bytes = 4096;
+        config.session.clipboard_history.max_entry_bytes = 8192;
+        assert!(has_warning(
+            &validate_issues(&config),
+            "session.clipboard_history.max_entry_bytes"
+        ));
+        config.session.clipboard_history.entries = 0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_share_link_lifetime() {
+        let mut config = valid_config();
+        config.session.share_link_max_secs = 30;
+        assert!(config.validate().is_ok());
+        config.session.share_links = true;
+        assert!(has_error(
+            &validate_issues(&config),
+            "session.share_link_max_secs"
+        ));
+        config.session.share_link_max_secs = MIN_SHARE_LINK_SECS;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_bandwidth_caps() {
+        let mut config = valid_config();
+        config.session.max_bandwidth_kbps = 20_000;
+        config.session.host_bandwidth_kbps = 100_000;
+        assert!(config.validate().is_ok());
+
+        config.session.bandwidth_users.insert("alice".into(), 300);
+        assert!(has_warning(
+            &validate_issues(&config),
+            "session.bandwidth_users.alice"
+        ));
+
+        let mut config = valid_config();
+        config.session.max_bandwidth_kbps = 20_000;
+        config.session.host_bandwidth_kbps = 10_000;
+        assert!(has_warning(
+            &validate_issues(&config),
+            "session.host_bandwidth_kbps"
+        ));
+    }
+
+    #[test]
+    fn validate_stats_history_length() {
+        let mut config = valid_config();
+        assert_eq!(config.session.stats_history_secs, 600);
+        config.session.stats_history_secs = 0;
+        assert!(config.validate().is_ok());
+        config.session.stats_history_secs = MAX_STATS_HISTORY_SECS + 1;
+        assert!(has_error(
+            &validate_issues(&config),
+            "session.stats_history_secs"
+        ));
+    }
+
+    #[test]
+    fn validate_thumbnail_interval() {
+        let mut config = valid_config();
+        assert_eq!(config.session.thumbnail_secs, 0);
+        config.session.thumbnail_secs = 1;
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "session.thumbnail_secs"));
+        assert!(has_warning(&issues, "admin_users"));
+
+        config.session.thumbnail_secs = 30;
+        config.server.admin_users = vec!["root".into()];
+        assert!(config.validate().is_ok());
+
+        config.session.privacy = true;
+        assert!(has_warning(&validate_issues(&config), "session.privacy"));
+        // ...unless someone opted out
+        config.session.privacy_users.insert("bob".into(), false);
+        assert!(config.validate().is_ok());
+        assert!(config.session.privacy_for("alice"));
+        assert!(!config.session.privacy_for("bob"));
+    }
+
+    #[test]
+    fn validate_display_modes() {
+        let mut config = valid_config();
+        config.session.display_modes = vec![DisplayMode::parse("3840x2160@60").unwrap()];
+        assert!(config.validate().is_ok());
+        config.video.max_width = 2560;
+        assert!(has_warning(
+            &validate_issues(&config),
+            "session.display_modes"
+        ));
+
+        let mut config = valid_config();
+        let modes = vec![DisplayMode::parse("1920x1080").unwrap(); MAX_DISPLAY_MODES + 1];
+        config
+            .session
+            .display_modes_users
+            .insert("alice".into(), modes);
+        assert!(has_error(
+            &validate_issues(&config),
+            "session.display_modes_users.alice"
+        ));
+
+        let bad = toml::from_str::<BeamConfig>("[session]\ndisplay_modes = [\"1920x1080@1000\"]");
+        assert!(
+            bad.unwrap_err()
+                .to_string()
+                .contains("invalid display mode")
+        );
+    }
+
     #[test]
     fn validate_multiple_errors_collected() {
         let mut config = valid_config();
@@ -845,6 +2833,34 @@ idle_timeout = 7200
         );
     }
 
+    #[test]
+    fn validate_login_audit_settings() {
+        let mut config = valid_config();
+        config.server.trusted_proxies = vec!["10.0.0.0/8".into(), "::1".into()];
+        assert!(config.validate().is_ok());
+
+        config.server.trusted_proxies = vec!["10.0.0.0/33".into(), "proxy".into()];
+        config.server.geoip_db = Some("/nonexistent/GeoLite2-Country.mmdb".into());
+        let issues = validate_issues(&config);
+        assert!(has_error(&issues, "'10.0.0.0/33'"));
+        assert!(has_error(&issues, "'proxy'"));
+        assert!(has_error(&issues, "geoip_db"));
+    }
+
+    #[test]
+    fn ip_prefixes_parse() {
+        use std::net::IpAddr;
+        let v4: IpAddr = "10.0.0.0".parse().unwrap();
+        assert_eq!(parse_ip_prefix("10.0.0.0/8"), Some((v4, 8)));
+        assert_eq!(parse_ip_prefix(" 10.0.0.0 "), Some((v4, 32)));
+        assert_eq!(
+            parse_ip_prefix("2001:db8::/32"),
+            Some(("2001:db8::".parse().unwrap(), 32))
+        );
+        assert_eq!(parse_ip_prefix("2001:db8::/129"), None);
+        assert_eq!(parse_ip_prefix("10.0.0.0/"), None);
+    }
+
     #[test]
     fn validate_admin_users_valid_names_ok() {
         let mut config = valid_config();
@@ -870,4 +2886,85 @@ idle_timeout = 7200
             "empty admin username should produce warning"
         );
     }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn env_overrides_win_over_the_file_and_defaults() {
+        let file = "[server]\nport = 8444\nbind = \"127.0.0.1\"\n";
+        let (config, overrides) = BeamConfig::from_toml_with_env(
+            file,
+            env(&[
+                ("BEAM_SERVER__PORT", "9000"),
+                ("BEAM_VIDEO__BITRATE", "5000"),
+                ("BEAM_SERVER__ADMIN_USERS", "[\"alice\", \"bob\"]"),
+                ("BEAM_SERVER__METRICS_REQUIRE_AUTH", "false"),
+                ("BEAM_VIDEO__TUNING__PRESET", "p1"),
+                ("BEAM_SESSION__CLIPBOARD_USERS__ALICE", "to-remote"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 9000);
+        // Untouched keys keep the file's value, then the default
+        assert_eq!(config.server.bind, "127.0.0.1");
+        assert_eq!(config.server.ws_ping_interval, default_ws_ping_interval());
+        assert_eq!(config.video.bitrate, 5000);
+        assert_eq!(config.server.admin_users, ["alice", "bob"]);
+        assert!(!config.server.metrics_require_auth);
+        assert_eq!(config.video.tuning.preset.as_deref(), Some("p1"));
+        assert_eq!(
+            config.session.clipboard_policy_for("alice"),
+            ClipboardPolicy::ToRemote
+        );
+        assert_eq!(overrides.applied.len(), 6);
+        assert!(overrides.unknown.is_empty());
+    }
+
+    #[test]
+    fn env_values_fall_back_to_strings() {
+        let (config, _) = BeamConfig::from_toml_with_env(
+            "",
+            env(&[
+                ("BEAM_SERVER__BIND", "0.0.0.0"),
+                ("BEAM_SERVER__JWT_SECRET", "\"0123\""),
+                ("BEAM_SERVER__WEB_ROOT", "/srv/beam web"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.server.bind, "0.0.0.0");
+        assert_eq!(config.server.jwt_secret.as_deref(), Some("0123"));
+        assert_eq!(config.server.web_root, "/srv/beam web");
+    }
+
+    #[test]
+    fn only_sectioned_beam_variables_override() {
+        assert!(is_env_override("BEAM_SERVER__PORT"));
+        assert!(!is_env_override("BEAM_SERVER"));
+        assert!(!is_env_override("BEAM_TOKEN"));
+        assert!(!is_env_override("BEAM_SERVER____PORT"));
+        assert!(!is_env_override("HOME"));
+
+        let (config, overrides) = BeamConfig::from_toml_with_env(
+            "",
+            env(&[
+                ("BEAM_SERVER", "https://beam.example.com"),
+                ("BEAM_SERVER__PROT", "9000"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.server.port, default_port());
+        assert_eq!(overrides.unknown, ["BEAM_SERVER__PROT"]);
+        assert!(overrides.applied.is_empty());
+    }
+
+    #[test]
+    fn bad_env_values_are_errors() {
+        let err =
+            BeamConfig::from_toml_with_env("", env(&[("BEAM_SERVER__PORT", "high")])).unwrap_err();
+        assert!(err.to_string().contains("port"), "{err}");
+    }
 }
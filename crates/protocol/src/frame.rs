@@ -4,13 +4,15 @@
 //! ```text
 //! [0..4]   magic: 0x42454156 ("BEAV")
 //! [4]      version: 1
-//! [5]      flags: bit 0 = keyframe, bit 1 = audio
+//! [5]      flags: bit 0 = keyframe, bit 1 = audio, bit 2 = 4:4:4 chroma,
+//!                 bit 3 = webcam (browser → agent), bit 4 = second screen
 //! [6..8]   width (u16)
 //! [8..10]  height (u16)
 //! [10..12] reserved (u16, must be 0)
 //! [12..20] timestamp_us (u64) — microseconds since capture start
 //! [20..24] payload_length (u32)
-//! [24..]   payload (H.264 Annex B for video, Opus for audio)
+//! [24..]   payload (H.264 Annex B for video, Opus for audio, the codec
+//!          announced by `InputEvent::WebcamStart` for webcam)
 //! ```
 
 pub const FRAME_HEADER_SIZE: usize = 24;
@@ -19,6 +21,15 @@ pub const FRAME_VERSION: u8 = 1;
 
 pub const FLAG_KEYFRAME: u8 = 0x01;
 pub const FLAG_AUDIO: u8 = 0x02;
+/// Video payload is High 4:4:4 Predictive; the browser must configure its
+/// decoder with the matching codec string.
+pub const FLAG_CHROMA_444: u8 = 0x04;
+/// Camera frame sent by the browser for the agent's virtual webcam. The
+/// only binary frame type that travels browser → agent.
+pub const FLAG_WEBCAM: u8 = 0x08;
+/// Video of the second virtual monitor (`InputEvent::ScreenAdd`), a
+/// stream of its own next to the first monitor's.
+pub const FLAG_SECOND_SCREEN: u8 = 0x10;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VideoFrameHeader {
@@ -58,6 +69,19 @@ impl VideoFrameHeader {
         }
     }
 
+    /// Create a header for a browser webcam frame.
+    pub fn webcam(
+        width: u16,
+        height: u16,
+        timestamp_us: u64,
+        payload_length: u32,
+        keyframe: bool,
+    ) -> Self {
+        let mut header = Self::video(width, height, timestamp_us, payload_length, keyframe);
+        header.flags |= FLAG_WEBCAM;
+        header
+    }
+
     pub fn is_keyframe(&self) -> bool {
         self.flags & FLAG_KEYFRAME != 0
     }
@@ -66,6 +90,34 @@ impl VideoFrameHeader {
         self.flags & FLAG_AUDIO != 0
     }
 
+    /// Mark a video frame as 4:4:4 chroma.
+    pub fn with_chroma_444(mut self, chroma_444: bool) -> Self {
+        if chroma_444 {
+            self.flags |= FLAG_CHROMA_444;
+        } else {
+            self.flags &= !FLAG_CHROMA_444;
+        }
+        self
+    }
+
+    pub fn is_chroma_444(&self) -> bool {
+        self.flags & FLAG_CHROMA_444 != 0
+    }
+
+    pub fn is_webcam(&self) -> bool {
+        self.flags & FLAG_WEBCAM != 0
+    }
+
+    /// Mark a video frame as the second monitor's.
+    pub fn on_second_screen(mut self) -> Self {
+        self.flags |= FLAG_SECOND_SCREEN;
+        self
+    }
+
+    pub fn is_second_screen(&self) -> bool {
+        self.flags & FLAG_SECOND_SCREEN != 0
+    }
+
     /// Serialize header to 24-byte little-endian buffer.
     pub fn serialize(&self, buf: &mut [u8; FRAME_HEADER_SIZE]) {
         buf[0..4].copy_from_slice(&FRAME_MAGIC.to_le_bytes());
@@ -167,6 +219,39 @@ mod tests {
         assert!(parsed.is_audio());
     }
 
+    #[test]
+    fn chroma_444_flag_roundtrip() {
+        let header = VideoFrameHeader::video(1920, 1080, 0, 1024, true).with_chroma_444(true);
+        let mut buf = [0u8; FRAME_HEADER_SIZE];
+        header.serialize(&mut buf);
+        let parsed = VideoFrameHeader::deserialize(&buf).unwrap();
+        assert!(parsed.is_chroma_444());
+        assert!(parsed.is_keyframe());
+        assert!(!parsed.with_chroma_444(false).is_chroma_444());
+    }
+
+    #[test]
+    fn webcam_header_roundtrip() {
+        let header = VideoFrameHeader::webcam(640, 480, 33_000, 2048, true);
+        let buf = header.serialize_with_payload(&[0u8; 2048]);
+        VideoFrameHeader::validate_complete(&buf).unwrap();
+        let parsed = VideoFrameHeader::deserialize(&buf).unwrap();
+        assert!(parsed.is_webcam());
+        assert!(parsed.is_keyframe());
+        assert!(!parsed.is_audio());
+        assert!(!VideoFrameHeader::video(640, 480, 0, 0, true).is_webcam());
+    }
+
+    #[test]
+    fn second_screen_header_roundtrip() {
+        let header = VideoFrameHeader::video(1280, 1024, 16_000, 512, false).on_second_screen();
+        let buf = header.serialize_with_payload(&[0u8; 512]);
+        let parsed = VideoFrameHeader::deserialize(&buf).unwrap();
+        assert!(parsed.is_second_screen());
+        assert!(!parsed.is_keyframe());
+        assert!(!VideoFrameHeader::video(1280, 1024, 0, 0, true).is_second_screen());
+    }
+
     #[test]
     fn p_frame_no_keyframe_flag() {
         let header = VideoFrameHeader::video(1920, 1080, 0, 1024, false);
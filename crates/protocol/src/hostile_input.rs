@@ -0,0 +1,191 @@
+//! Property tests for everything the server and agent parse from the
+//! network: browsers and share-link viewers are untrusted, so signaling
+//! messages, CBOR framing and media frame headers must turn any input into
+//! a value or an error, never a panic, and never trust a length they were
+//! sent. The same parsers are fuzzed by the targets in `crates/protocol/fuzz`.
+
+use proptest::prelude::*;
+use serde_json::{Map, Number, Value, json};
+
+use crate::{
+    AgentCommand, FRAME_HEADER_SIZE, InputEvent, MAX_INPUT_BATCH, MAX_OPEN_URL_LEN,
+    SignalingMessage, StampedInput, TimedInput, VideoFrameHeader, WireFormat, cbor_to_value,
+    is_forwardable_url, is_valid_input_batch,
+};
+
+/// Tags of every `InputEvent`, so generated objects reach each variant's
+/// field handling instead of stopping at an unknown tag
+const INPUT_TAGS: &[&str] = &[
+    "k", "m", "rm", "b", "s", "c", "cp", "chl", "chp", "chc", "r", "l", "ds", "q", "vs", "cm",
+    "pl", "kg", "kc", "ou", "fa", "wcs", "wce", "ae", "am", "ao", "ar", "asl", "as", "fs", "fc",
+    "fd", "fdr", "ib",
+];
+
+fn json_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        any::<i64>().prop_map(Value::from),
+        any::<u64>().prop_map(Value::from),
+        any::<f64>().prop_filter_map("finite", |f| Number::from_f64(f).map(Value::Number)),
+        ".{0,16}".prop_map(Value::String),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+            prop::collection::btree_map("[a-z]{1,4}", inner, 0..8)
+                .prop_map(|map| Value::Object(map.into_iter().collect())),
+        ]
+    })
+}
+
+/// An object carrying a real `InputEvent` tag and arbitrary fields, some
+/// with the names the variants use.
+fn tagged_input() -> impl Strategy<Value = Value> {
+    let field = prop_oneof!["[a-z]{1,4}", Just("e".to_string())];
+    (
+        prop::sample::select(INPUT_TAGS),
+        prop::collection::btree_map(field, json_value(), 0..6),
+    )
+        .prop_map(|(tag, fields)| {
+            let mut object: Map<String, Value> = fields.into_iter().collect();
+            object.insert("t".into(), tag.into());
+            Value::Object(object)
+        })
+}
+
+/// Every way the server or agent reads a signaling message.
+fn decode_all(format: WireFormat, data: &[u8]) {
+    let _ = format.decode::<InputEvent>(data);
+    let _ = format.decode::<StampedInput>(data);
+    let _ = format.decode::<TimedInput>(data);
+    let _ = format.decode::<AgentCommand>(data);
+    let _ = format.decode::<SignalingMessage>(data);
+}
+
+proptest! {
+    #[test]
+    fn any_bytes_decode_or_fail_cleanly(data in prop::collection::vec(any::<u8>(), 0..256)) {
+        decode_all(WireFormat::Json, &data);
+        decode_all(WireFormat::Cbor, &data);
+        let _ = cbor_to_value(&data);
+    }
+
+    #[test]
+    fn tagged_objects_decode_or_fail_cleanly(value in tagged_input()) {
+        let json = serde_json::to_vec(&value).unwrap();
+        decode_all(WireFormat::Json, &json);
+        let cbor = WireFormat::Cbor.encode(&value).unwrap();
+        decode_all(WireFormat::Cbor, &cbor);
+    }
+
+    #[test]
+    fn batches_are_bounded(len in 0..MAX_INPUT_BATCH * 2) {
+        let batch = json!({
+            "t": "ib",
+            "e": vec![json!({ "ts": 1.0, "ev": { "t": "m", "x": 0.5, "y": 0.5 } }); len],
+        });
+        let InputEvent::InputBatch { e } = serde_json::from_value(batch).unwrap() else {
+            panic!("expected a batch");
+        };
+        prop_assert_eq!(is_valid_input_batch(&e), len <= MAX_INPUT_BATCH);
+    }
+
+    #[test]
+    fn cbor_roundtrips_any_json_value(value in json_value()) {
+        let cbor = WireFormat::Cbor.encode(&value).unwrap();
+        prop_assert_eq!(cbor_to_value(&cbor).unwrap(), value);
+    }
+
+    #[test]
+    fn truncated_cbor_is_rejected(value in json_value(), cut in any::<prop::sample::Index>()) {
+        let cbor = WireFormat::Cbor.encode(&value).unwrap();
+        let cut = cut.index(cbor.len());
+        prop_assert!(cbor_to_value(&cbor[..cut]).is_err());
+    }
+
+    #[test]
+    fn cbor_lengths_are_bounded_by_the_message(
+        major in prop::sample::select(vec![3u8, 4, 5]),
+        claimed in 24u64..,
+        rest in prop::collection::vec(any::<u8>(), 0..16),
+    ) {
+        // A head claiming more items or bytes than follow must fail before
+        // anything is allocated for them
+        let mut data = vec![(major << 5) | 27];
+        data.extend_from_slice(&claimed.max(rest.len() as u64 + 1).to_be_bytes());
+        data.extend_from_slice(&rest);
+        prop_assert!(cbor_to_value(&data).is_err());
+    }
+
+    #[test]
+    fn frame_headers_roundtrip(
+        flags: u8,
+        width: u16,
+        height: u16,
+        timestamp_us: u64,
+        payload_length: u32,
+    ) {
+        let header = VideoFrameHeader { flags, width, height, timestamp_us, payload_length };
+        let mut buf = [0u8; FRAME_HEADER_SIZE];
+        header.serialize(&mut buf);
+        prop_assert_eq!(VideoFrameHeader::deserialize(&buf).unwrap(), header);
+    }
+
+    #[test]
+    fn any_bytes_parse_as_a_frame_or_fail_cleanly(
+        data in prop::collection::vec(any::<u8>(), 0..64),
+    ) {
+        match VideoFrameHeader::deserialize(&data) {
+            Ok(_) => prop_assert!(data.len() >= FRAME_HEADER_SIZE),
+            Err(_) => prop_assert!(VideoFrameHeader::validate_complete(&data).is_err()),
+        }
+    }
+
+    #[test]
+    fn claimed_payload_length_is_checked(
+        payload_length in prop_oneof![0u32..1024, Just(u32::MAX)],
+        actual in 0usize..1024,
+    ) {
+        let header = VideoFrameHeader::video(1920, 1080, 0, payload_length, true);
+        let mut data = vec![0u8; FRAME_HEADER_SIZE + actual];
+        header.serialize((&mut data[..FRAME_HEADER_SIZE]).try_into().unwrap());
+        prop_assert_eq!(
+            VideoFrameHeader::validate_complete(&data).is_ok(),
+            actual >= payload_length as usize
+        );
+    }
+
+    #[test]
+    fn forwarded_urls_stay_within_limits(url in "(?s)(https?://|HTTP://|file:|javascript:)?.{0,64}") {
+        if is_forwardable_url(&url) {
+            let lower = url.to_ascii_lowercase();
+            prop_assert!(lower.starts_with("http://") || lower.starts_with("https://"));
+            prop_assert!(url.len() <= MAX_OPEN_URL_LEN);
+            prop_assert!(!url.chars().any(|c| c.is_whitespace() || c.is_control()));
+        }
+    }
+}
+
+#[test]
+fn oversized_urls_are_never_forwarded() {
+    let url = format!("https://{}", "a".repeat(MAX_OPEN_URL_LEN));
+    assert!(!is_forwardable_url(&url));
+    // Non-ASCII right at the scheme boundary mustn't split a character
+    assert!(!is_forwardable_url("https:/é"));
+}
+
+#[test]
+fn nested_batches_fail_cleanly() {
+    // Past serde_json's recursion limit and CBOR's MAX_DEPTH
+    for depth in [1, 16, 40, 127, 200] {
+        let mut value = json!({ "t": "k", "c": 30, "d": true });
+        for _ in 0..depth {
+            value = json!({ "t": "ib", "e": [{ "ts": 0.0, "ev": value }] });
+        }
+        let json = serde_json::to_string(&value).unwrap();
+        decode_all(WireFormat::Json, json.as_bytes());
+        let cbor = WireFormat::Cbor.encode(&value).unwrap();
+        decode_all(WireFormat::Cbor, &cbor);
+    }
+}
@@ -1,7 +1,13 @@
+pub mod codec;
 pub mod config;
 pub mod frame;
 pub mod messages;
+pub mod sd_notify;
 
+#[cfg(test)]
+mod hostile_input;
+
+pub use codec::*;
 pub use config::*;
 pub use frame::*;
 pub use messages::*;
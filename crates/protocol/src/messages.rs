@@ -9,6 +9,13 @@ pub enum SignalingMessage {
     SessionReady { session_id: Uuid },
     /// Error
     Error { message: String },
+    /// Message from an admin (`from`), shown to the user
+    Notice { message: String, from: String },
+    /// An admin (`from`) scheduled the session to end in `ends_in_secs`.
+    /// Sent when scheduled, as the end draws near and on reconnect.
+    TerminationScheduled { ends_in_secs: u64, from: String },
+    /// The scheduled end was called off by `from`
+    TerminationCancelled { from: String },
 }
 
 /// Input events sent over WebSocket (compact format).
@@ -43,21 +50,167 @@ pub enum InputEvent {
     /// Clipboard text (CLIPBOARD selection)
     #[serde(rename = "c")]
     Clipboard { text: String },
-    /// Clipboard text for X11 PRIMARY selection (middle-click paste)
+    /// Clipboard text for X11 PRIMARY selection (middle-click paste).
+    /// With `session.primary_selection` the agent sends it the other way too.
     #[serde(rename = "cp")]
     ClipboardPrimary { text: String },
-    /// Resolution change request
+    /// Ask the agent for its clipboard history. Answered with a
+    /// `ClipboardHistory` message.
+    #[serde(rename = "chl")]
+    ClipboardHistoryRequest,
+    /// Put history entry `id` back on the session's clipboard, ready to
+    /// paste there. Answered with the updated `ClipboardHistory`.
+    #[serde(rename = "chp")]
+    ClipboardHistoryPaste { id: u64 },
+    /// Forget the clipboard history. Answered with the (empty) history.
+    #[serde(rename = "chc")]
+    ClipboardHistoryClear,
+    /// Resolution change request in CSS pixels. `s` is the browser's
+    /// devicePixelRatio; the agent renders at `w*s` x `h*s` and raises the
+    /// desktop DPI to match. Absent for old clients (treated as 1.0).
     #[serde(rename = "r")]
-    Resize { w: u32, h: u32 },
-    /// Keyboard layout hint (XKB layout name, e.g. "no", "us", "de")
+    Resize {
+        w: u32,
+        h: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        s: Option<f64>,
+    },
+    /// Keyboard layout hint, in XKB names: a layout ("no", "us", "de"),
+    /// optionally one of its variants ("nodeadkeys") and XKB options
+    /// ("ctrl:nocaps"). Older browsers send the layout alone.
     #[serde(rename = "l")]
-    Layout { layout: String },
-    /// Quality mode: "high" (LAN) or "low" (WAN)
+    Layout {
+        layout: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        variant: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        options: Vec<String>,
+    },
+    /// Virtual monitor settings besides its size: `rotation` turns it for
+    /// portrait use, `refresh` (Hz) overrides the rate of the modes it is
+    /// set to (0 goes back to the preferred modes' rates), and `modes` replaces the preferred mode list for the rest of
+    /// the session. Unset fields are left alone, so an empty message just
+    /// asks for the current state; every one is answered with
+    /// `AgentMessage::DisplayState`.
+    #[serde(rename = "ds")]
+    DisplaySettings {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rotation: Option<Rotation>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        refresh: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        modes: Option<Vec<DisplayMode>>,
+    },
+    /// Attach a second virtual monitor of `w` x `h` pixels right of the
+    /// first, for a browser on a client with two monitors. Its picture
+    /// comes as video frames flagged `FLAG_SECOND_SCREEN`. Sent again, it
+    /// resizes the monitor, or at the same size asks for a keyframe.
+    /// Answered with `AgentMessage::SecondScreen`.
+    #[serde(rename = "sa")]
+    ScreenAdd { w: u32, h: u32 },
+    /// Detach the second monitor. Answered with `AgentMessage::SecondScreen`.
+    #[serde(rename = "sd")]
+    ScreenRemove,
+    /// Mouse move on the second monitor: normalized coordinates (0.0 - 1.0)
+    #[serde(rename = "m2")]
+    ScreenMouseMove { x: f64, y: f64 },
+    /// Video quality preset. The web client sets it through
+    /// `POST /api/sessions/{id}/quality`, which remembers it for the user.
     #[serde(rename = "q")]
-    Quality { mode: String },
+    Quality { mode: QualityPreset },
     /// Browser tab visibility state (true = visible, false = hidden/backgrounded)
     #[serde(rename = "vs")]
     VisibilityState { visible: bool },
+    /// Cursor compositing: true = agent draws the cursor into the video
+    /// stream (for clients that can't render a local cursor), false = the
+    /// browser renders its own cursor from `cur` messages (default).
+    #[serde(rename = "cm")]
+    CursorMode { composite: bool },
+    /// Pointer lock: true = browser holds pointer lock and sends only
+    /// relative motion; the agent disables acceleration and ignores
+    /// absolute moves until unlocked. The agent echoes the resulting
+    /// state back as `{"t":"pl","locked":..}`.
+    #[serde(rename = "pl")]
+    PointerLock { locked: bool },
+    /// Keyboard grab: true = browser holds the Keyboard Lock, so system
+    /// keys (Alt+Tab, Super, Escape) arrive as ordinary key events. On
+    /// release the agent lifts any keys still held, since their key-ups
+    /// may have been swallowed by the local OS.
+    #[serde(rename = "kg")]
+    KeyboardGrab { grab: bool },
+    /// Synthesize a system key combo the browser can never capture
+    /// (e.g. "ctrl-alt-del"). Only forwarded when the session allows it.
+    #[serde(rename = "kc")]
+    KeyCombo { combo: String },
+    /// Open an http(s) URL on the other side: browser → agent opens it in
+    /// the session's web browser; agent → browser opens a local tab (sent
+    /// when the session forwards `xdg-open` links). Both ends check
+    /// `is_forwardable_url` first.
+    #[serde(rename = "ou")]
+    OpenUrl { url: String },
+    /// Frame display acknowledgement for latency telemetry. `ts` is the
+    /// frame header's `timestamp_us`; `dd` is the milliseconds the browser
+    /// spent between receiving the frame and presenting it.
+    #[serde(rename = "fa")]
+    FrameAck { ts: u64, dd: f64 },
+    /// Browser camera is about to stream `codec` ("h264" or "vp8") frames
+    /// at `w`x`h` as binary `FLAG_WEBCAM` frames, for the agent to expose as
+    /// a v4l2loopback camera. Answered with a `WebcamStatus`.
+    #[serde(rename = "wcs")]
+    WebcamStart { codec: String, w: u32, h: u32 },
+    /// Browser camera stopped; the agent tears down the loopback feed.
+    #[serde(rename = "wce")]
+    WebcamStop,
+    /// Pause (false) or resume (true) audio capture entirely. The agent
+    /// echoes the applied state back with the same message.
+    #[serde(rename = "ae")]
+    AudioEnabled { enabled: bool },
+    /// Opus encoding profile: "voice" (32 kbps, DTX, wideband), "music"
+    /// (192 kbps, fullband) or "standard" (configured `audio.bitrate`).
+    #[serde(rename = "am")]
+    AudioMode { mode: String },
+    /// Trade audio latency for robustness: Opus frame length (one of
+    /// `OPUS_FRAME_MS`), inband FEC and DTX. Fields left out keep their
+    /// setting; the voice profile turns DTX on whatever this says.
+    #[serde(rename = "ao")]
+    AudioTuning {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        frame_ms: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fec: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dtx: Option<bool>,
+    },
+    /// Audio the browser played since its last report and how much of that
+    /// it filled with silence because frames came late (ms), like an RTCP
+    /// receiver report's loss figures. Sent every few seconds while audio
+    /// plays.
+    #[serde(rename = "ar")]
+    AudioReport { played_ms: u32, concealed_ms: u32 },
+    /// Ask the agent for its capturable audio sinks and application
+    /// streams. Answered with an `AudioSources` message.
+    #[serde(rename = "asl")]
+    AudioSourcesRequest,
+    /// Choose what audio is captured: a sink's monitor by sink name, or a
+    /// single application by sink-input index. Neither = default monitor.
+    #[serde(rename = "as")]
+    AudioSource {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sink: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        app: Option<u32>,
+    },
+    /// Set an application stream's volume (percent of normal, up to
+    /// `MAX_APP_VOLUME`) and/or mute it, by sink-input index. Answered
+    /// with a fresh `AudioSources` message.
+    #[serde(rename = "av")]
+    AudioAppVolume {
+        app: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        volume: Option<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        muted: Option<bool>,
+    },
     /// File transfer start: initiates a new file upload
     #[serde(rename = "fs")]
     FileStart { id: String, name: String, size: u64 },
@@ -70,6 +223,179 @@ pub enum InputEvent {
     /// File download request: browser asks agent to send a file
     #[serde(rename = "fdr")]
     FileDownloadRequest { path: String },
+    /// Pointer events of one animation frame in a single message, in order.
+    /// Only `is_batchable` events are allowed, at most `MAX_INPUT_BATCH`.
+    #[serde(rename = "ib")]
+    InputBatch { e: Vec<TimedInput> },
+}
+
+/// An `InputBatch` entry: the event plus the browser's event timestamp
+/// (`Event.timeStamp`, milliseconds).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedInput {
+    pub ts: f64,
+    pub ev: InputEvent,
+}
+
+/// A browser input message as sent on the wire: the event plus an optional
+/// `at` field (`Event.timeStamp`, milliseconds) next to its own fields.
+/// Not `ts`, which `FrameAck` already uses. Servers that predate it ignore
+/// the field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StampedInput {
+    #[serde(rename = "at", default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<f64>,
+    #[serde(flatten)]
+    pub ev: InputEvent,
+}
+
+/// Most events one `InputBatch` may carry.
+pub const MAX_INPUT_BATCH: usize = 256;
+
+impl InputEvent {
+    /// Pointer events, the only ones an `InputBatch` may carry. Keys and
+    /// everything else go out on their own so nothing waits for a frame.
+    pub fn is_batchable(&self) -> bool {
+        matches!(
+            self,
+            Self::MouseMove { .. }
+                | Self::ScreenMouseMove { .. }
+                | Self::RelativeMouseMove { .. }
+                | Self::Button { .. }
+                | Self::Scroll { .. }
+        )
+    }
+}
+
+/// Whether an `InputBatch` is within limits and carries only batchable events.
+pub fn is_valid_input_batch(events: &[TimedInput]) -> bool {
+    events.len() <= MAX_INPUT_BATCH && events.iter().all(|t| t.ev.is_batchable())
+}
+
+/// Longest URL `OpenUrl` carries; matches what browsers reliably accept.
+pub const MAX_OPEN_URL_LEN: usize = 2048;
+
+/// Largest clipboard or PRIMARY selection text sent either way.
+pub const MAX_CLIPBOARD_BYTES: usize = 1_048_576;
+
+/// File data per upload (`fc`) or download (`fdc`) chunk, before base64.
+pub const FILE_CHUNK_BYTES: usize = 16 * 1024;
+
+/// Longest base64 `data` of a file chunk.
+pub const MAX_FILE_CHUNK_LEN: usize = FILE_CHUNK_BYTES.div_ceil(3) * 4;
+
+/// Longest of the short strings in input events: names, ids, modes,
+/// layouts, key combos and paths (Linux's `PATH_MAX`).
+pub const MAX_FIELD_LEN: usize = 4096;
+
+/// Most XKB options one `Layout` message may set.
+pub const MAX_XKB_OPTIONS: usize = 16;
+
+/// A message field over its protocol limit.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{field} is {len} bytes, over the limit of {max}")]
+pub struct TooLarge {
+    pub field: &'static str,
+    pub len: usize,
+    pub max: usize,
+}
+
+fn limit(field: &'static str, value: &str, max: usize) -> Result<(), TooLarge> {
+    if value.len() > max {
+        return Err(TooLarge {
+            field,
+            len: value.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+impl InputEvent {
+    /// Check string and batch sizes against the protocol limits. Fields an
+    /// event doesn't know are ignored when it is parsed, so newer browsers
+    /// can add some; sizes are what's left to hold untrusted input to.
+    pub fn check_limits(&self) -> Result<(), TooLarge> {
+        match self {
+            Self::Clipboard { text } | Self::ClipboardPrimary { text } => {
+                limit("text", text, MAX_CLIPBOARD_BYTES)
+            }
+            Self::OpenUrl { url } => limit("url", url, MAX_OPEN_URL_LEN),
+            Self::Layout {
+                layout,
+                variant,
+                options,
+            } => {
+                limit("layout", layout, MAX_FIELD_LEN)?;
+                limit(
+                    "variant",
+                    variant.as_deref().unwrap_or_default(),
+                    MAX_FIELD_LEN,
+                )?;
+                if options.len() > MAX_XKB_OPTIONS {
+                    return Err(TooLarge {
+                        field: "options",
+                        len: options.len(),
+                        max: MAX_XKB_OPTIONS,
+                    });
+                }
+                options
+                    .iter()
+                    .try_for_each(|o| limit("options", o, MAX_FIELD_LEN))
+            }
+            Self::DisplaySettings {
+                modes: Some(modes), ..
+            } if modes.len() > MAX_DISPLAY_MODES => Err(TooLarge {
+                field: "modes",
+                len: modes.len(),
+                max: MAX_DISPLAY_MODES,
+            }),
+            Self::AudioMode { mode } => limit("mode", mode, MAX_FIELD_LEN),
+            Self::KeyCombo { combo } => limit("combo", combo, MAX_FIELD_LEN),
+            Self::WebcamStart { codec, .. } => limit("codec", codec, MAX_FIELD_LEN),
+            Self::AudioSource { sink, .. } => {
+                limit("sink", sink.as_deref().unwrap_or_default(), MAX_FIELD_LEN)
+            }
+            Self::FileStart { id, name, .. } => {
+                limit("id", id, MAX_FIELD_LEN)?;
+                limit("name", name, MAX_FIELD_LEN)
+            }
+            Self::FileChunk { id, data } => {
+                limit("id", id, MAX_FIELD_LEN)?;
+                limit("data", data, MAX_FILE_CHUNK_LEN)
+            }
+            Self::FileDone { id } => limit("id", id, MAX_FIELD_LEN),
+            Self::FileDownloadRequest { path } => limit("path", path, MAX_FIELD_LEN),
+            Self::InputBatch { e } => {
+                if e.len() > MAX_INPUT_BATCH {
+                    return Err(TooLarge {
+                        field: "e",
+                        len: e.len(),
+                        max: MAX_INPUT_BATCH,
+                    });
+                }
+                e.iter().try_for_each(|t| t.ev.check_limits())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `url` may be opened across the session boundary: http(s) only
+/// (no `file:`, `javascript:` or custom schemes), with no whitespace or
+/// control characters, at most `MAX_OPEN_URL_LEN` bytes.
+pub fn is_forwardable_url(url: &str) -> bool {
+    let lower = url.get(..8).unwrap_or(url).to_ascii_lowercase();
+    let rest = if lower.starts_with("https://") {
+        &url[8..]
+    } else if lower.starts_with("http://") {
+        &url[7..]
+    } else {
+        return false;
+    };
+    !rest.is_empty()
+        && url.len() <= MAX_OPEN_URL_LEN
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
 }
 
 /// Authentication request.
@@ -86,6 +412,43 @@ pub struct AuthRequest {
     /// Must be in range 60..=86400 (1 minute to 24 hours).
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub idle_timeout: Option<u64>,
+    /// Turn off synthesizing system key combos (Ctrl+Alt+Del etc.) for
+    /// this session. Only narrows `session.system_combos`; None = as
+    /// configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_combos: Option<bool>,
+    /// Bandwidth probe result, used to pick the initial bitrate/framerate
+    /// instead of the configured maximums. The probe needs a token, so the
+    /// web client reports it after login to `POST /api/sessions/{id}/nettest`
+    /// instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nettest: Option<NetTestResult>,
+    /// Only issue a token; don't start or reuse a desktop session. Used by
+    /// the `beam` command-line client.
+    #[serde(default)]
+    pub token_only: bool,
+    /// Disconnect a browser already showing the user's session. Without
+    /// this, such a login is answered 409 "session in use".
+    #[serde(default)]
+    pub takeover: bool,
+    /// Attach to the host's physical display (`[assist]`) instead of the
+    /// user's own virtual desktop. Admins only.
+    #[serde(default)]
+    pub assist: bool,
+    /// Desktop environment for a new session, one of `Desktop::NAMED`.
+    /// None = `session.desktop`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desktop: Option<Desktop>,
+}
+
+/// Throughput and round-trip time measured by the browser against
+/// `/api/nettest` once it has logged in.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NetTestResult {
+    /// Achieved download throughput in kbps
+    pub kbps: u32,
+    /// Best-of-several round-trip time in milliseconds
+    pub rtt_ms: u32,
 }
 
 impl std::fmt::Debug for AuthRequest {
@@ -111,6 +474,43 @@ pub struct AuthResponse {
     /// Returned so the client can show accurate idle warnings.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub idle_timeout: Option<u64>,
+    /// Clipboard directions the agent allows for this session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clipboard: Option<ClipboardPolicy>,
+    /// Whether the agent bridges the PRIMARY selection as well
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_selection: Option<bool>,
+    /// Whether the owner may mint view-only share links
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_links: Option<bool>,
+    /// Set for remote assistance sessions: nothing streams until the
+    /// person at the display accepts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assist: Option<bool>,
+    /// The session's quality preset: the user's remembered choice for a
+    /// new session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityPreset>,
+    /// Whether the browser may send system key combos (`KeyCombo`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_combos: Option<bool>,
+}
+
+/// `POST /api/agent/handshake` body. The agent proves itself with its
+/// long-lived token (`BEAM_AGENT_TOKEN`) in the Authorization header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentHandshake {
+    pub session_id: Uuid,
+}
+
+/// Answer to an agent handshake: a signed token good for a single
+/// `/ws/agent/{id}?token=` connection, so the long-lived token never
+/// appears in a URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentTicket {
+    pub token: String,
+    /// Seconds the agent has to connect with it
+    pub expires_in: u64,
 }
 
 /// Session information
@@ -122,8 +522,777 @@ pub struct SessionInfo {
     pub width: u32,
     pub height: u32,
     pub created_at: u64,
+    /// Where the latest login to this session came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<LoginClient>,
+    /// For remote assistance, the local account whose physical display
+    /// this session shows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assist: Option<String>,
+}
+
+/// Origin of a login, kept for the audit log and admin session list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginClient {
+    /// Client address, taken from `X-Forwarded-For` behind trusted proxies
+    pub ip: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// ISO 3166 country code from the GeoIP database, if one is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+/// Glass-to-glass latency aggregate, reported periodically by the agent
+/// as `{"t":"lat",...}` and cached by the server for the stats API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "lat")]
+pub struct LatencyStats {
+    /// Acknowledged frames in this reporting window
+    pub samples: u32,
+    /// Estimated capture-to-display latency percentiles (ms)
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    /// Median agent-side capture + encode + send time (ms)
+    pub pipeline_p50_ms: f64,
+    /// Median one-way network estimate (ms), half the ack round trip
+    pub network_p50_ms: f64,
+}
+
+/// Duration percentiles of one frame pipeline stage over a reporting window.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Where the agent's per-frame time goes, reported periodically as
+/// `{"t":"pst",...}` and cached by the server for the stats API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "pst")]
+pub struct PipelineStats {
+    /// Frames captured in this reporting window
+    pub frames: u32,
+    /// Frames whose capture and hand-off to the encoder overran the frame
+    /// interval
+    #[serde(default)]
+    pub deadline_missed: u32,
+    /// Grabbing the screen (XShm)
+    pub capture: StageTiming,
+    /// Handing the frame to the encoder
+    pub encode: StageTiming,
+    /// Handed to the encoder until its output arrives
+    pub drain: StageTiming,
+    /// Encoder output to the WebSocket outbox
+    pub send: StageTiming,
+    /// P-frames dropped because the send loop fell behind the encoder
+    #[serde(default)]
+    pub queue_dropped: u32,
+    /// Frames dropped because the WebSocket outbox was full
+    #[serde(default)]
+    pub outbox_dropped: u32,
+}
+
+/// Agent self-monitoring, reported every few seconds by its watchdog as
+/// `{"t":"ah",...}` and cached by the server for the stats API and metrics.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "ah")]
+pub struct AgentHealth {
+    /// Resident memory (MiB)
+    pub rss_mb: u32,
+    /// CPU time used in this reporting window, in percent of one core
+    pub cpu_pct: f64,
+    /// Frames captured per second
+    pub capture_fps: f64,
+    /// Frames out of the encoder per second
+    pub encode_fps: f64,
+    /// Framerate the capture thread paces for (lower while idle or
+    /// backgrounded)
+    pub target_fps: u32,
+    /// Encoder recreates and capture re-inits the watchdog triggered since
+    /// the agent started
+    pub recoveries: u32,
+    /// Browser input dropped for going over the agent's rate caps since it
+    /// started (absent from older agents)
+    #[serde(default)]
+    pub input_dropped: InputDrops,
+    /// How far audio timestamps, counted in samples, have run from the
+    /// media clock video is stamped from (ms): the audio device's clock
+    /// running fast or slow. Positive when audio runs ahead.
+    #[serde(default)]
+    pub av_drift_ms: f64,
+    /// Audio browsers played since the agent started, and the part of it
+    /// they had to fill with silence (ms), from their `AudioReport`s
+    #[serde(default)]
+    pub audio_played_ms: u64,
+    #[serde(default)]
+    pub audio_concealed_ms: u64,
+}
+
+/// Input events dropped per rate-capped category.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputDrops {
+    /// Key events and key combos
+    pub key: u64,
+    /// Pointer moves, buttons, scrolls and input batches
+    pub pointer: u64,
+    /// Clipboard sets and clipboard history requests
+    pub clipboard: u64,
+    /// Keyboard layout changes (each one has the X server compile a keymap)
+    pub layout: u64,
+    /// Resizes, mode switches and other session controls
+    pub control: u64,
+}
+
+/// Coarse connection quality shown as a badge in the web client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityLevel {
+    Good,
+    Fair,
+    Poor,
+}
+
+/// Connection quality snapshot, pushed by the agent every second as
+/// `{"t":"nq",...}` while frames are flowing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "nq")]
+pub struct NetworkQuality {
+    pub level: QualityLevel,
+    /// Smoothed network round trip from frame acks; None until the first ack
+    pub rtt_ms: Option<f64>,
+    /// Share of encoded frames dropped for backpressure in the last second
+    pub loss_pct: f64,
+    /// Video bitrate actually sent in the last second
+    pub kbps: u32,
+    /// Configured encoder bitrate
+    pub target_kbps: u32,
+}
+
+/// What kept a session's video from the browser
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamAlertKind {
+    /// Frames were encoded and sent, but the browser acked none of them
+    NotDelivered,
+    /// Screen capture produced no frames
+    CaptureStalled,
+}
+
+impl StreamAlertKind {
+    pub const ALL: [Self; 2] = [Self::NotDelivered, Self::CaptureStalled];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NotDelivered => "not_delivered",
+            Self::CaptureStalled => "capture_stalled",
+        }
+    }
+}
+
+/// The agent's watchdog noticing the browser's picture froze, as
+/// `{"t":"va",...}`: once when it starts (`active`) and once when video
+/// flows again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "va")]
+pub struct StreamAlert {
+    pub kind: StreamAlertKind,
+    pub active: bool,
+    /// How long it had lasted
+    pub secs: u32,
+    /// The browser should restart its decoder; a keyframe is on its way
+    #[serde(default)]
+    pub recover: bool,
+}
+
+/// The agent asking the browser to reconnect, as `{"t":"rc",...}`: video
+/// still isn't reaching it after a fresh keyframe and a new encoder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "rc")]
+pub struct ReconnectRequest {
+    /// How many times the agent has asked during this freeze
+    pub attempt: u32,
+}
+
+/// The agent dropping a preset whose output stayed over the quality
+/// ceiling (`video.quality_ceiling`), as `{"t":"qf",...}`. The user's
+/// choice is kept; the session runs at `effective` until they pick again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "qf")]
+pub struct QualityFallback {
+    pub requested: QualityPreset,
+    pub effective: QualityPreset,
+    /// Video bitrate measured over the last second
+    pub kbps: u32,
+    pub ceiling_kbps: u32,
+}
+
+/// PulseAudio sink whose monitor can be captured.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioSinkInfo {
+    pub name: String,
+    pub description: String,
+}
+
+/// Application playback stream (PulseAudio sink-input).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioAppInfo {
+    pub index: u32,
+    pub name: String,
+    /// Loudest channel, in percent of normal volume
+    pub volume: u16,
+    pub muted: bool,
+}
+
+/// Agent's reply to `InputEvent::WebcamStart`/`WebcamStop`: whether the
+/// virtual camera is live, which device remote apps should open, or why it
+/// could not start.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "wcst")]
+pub struct WebcamStatus {
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Agent's reply to `InputEvent::AudioSourcesRequest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "asl")]
+pub struct AudioSources {
+    pub sinks: Vec<AudioSinkInfo>,
+    pub apps: Vec<AudioAppInfo>,
+}
+
+/// Agent's reply to the `ClipboardHistory*` requests, newest entry first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "chl")]
+pub struct ClipboardHistory {
+    pub entries: Vec<ClipboardHistoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipboardHistoryEntry {
+    /// Stable for the life of the agent; used by `ClipboardHistoryPaste`
+    pub id: u64,
+    pub text: String,
+    /// When the text was copied (Unix milliseconds)
+    pub at: u64,
+}
+
+/// Encoder the agent is actually running, sent as `{"t":"enc",...}` when
+/// the pipeline starts and whenever it fails over to another encoder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "enc")]
+pub struct EncoderStatus {
+    /// GStreamer element name, e.g. "nvh264enc"
+    pub name: String,
+    /// True if this encoder replaced a failing one
+    pub fallback: bool,
+}
+
+/// What the agent runs and streams, sent as `{"t":"ai",...}` on every
+/// signaling connect and whenever it changes (new encoder, resize). The
+/// server keeps the latest for the admin session list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "ai")]
+pub struct AgentInfo {
+    /// Agent build, e.g. "0.4.2"
+    pub version: String,
+    /// GStreamer element name of the running encoder, e.g. "nvh264enc"
+    pub encoder: String,
+    /// True once the agent fell back from the encoder it started with
+    pub fallback: bool,
+    /// How the screen is captured, e.g. "x11-shm"
+    pub capture: String,
+    /// Size of the encoded frames
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second the encoder is set up for
+    pub framerate: u32,
+}
+
+/// Downscaled JPEG of the session's screen, sent by the agent as
+/// `{"t":"thumb",...}` every `--thumbnail-secs`. The server keeps the
+/// latest for the admin panel and doesn't relay it to the browser.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "thumb")]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    /// Base64 JPEG
+    pub jpeg: String,
+}
+
+/// Messages the agent sends the browser as JSON text, relayed by the
+/// server untouched. Reports the server reads itself (`AgentHealth`,
+/// `Thumbnail` and the like) have types of their own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t")]
+pub enum AgentMessage {
+    /// Text copied in the session (at most `MAX_CLIPBOARD_BYTES`)
+    #[serde(rename = "c")]
+    Clipboard { text: String },
+    /// New PRIMARY selection in the session
+    #[serde(rename = "cp")]
+    ClipboardPrimary { text: String },
+    /// Cursor shape: a CSS cursor name, plus the bitmap for custom ones
+    #[serde(rename = "cur")]
+    Cursor {
+        css: String,
+        /// Base64 PNG
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        png: Option<String>,
+        /// Hotspot within the bitmap
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hx: Option<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hy: Option<u16>,
+    },
+    /// Pointer lock state the agent applied
+    #[serde(rename = "pl")]
+    PointerLock { locked: bool },
+    /// A file download begins
+    #[serde(rename = "fds")]
+    DownloadStart { id: String, name: String, size: u64 },
+    /// Up to `FILE_CHUNK_BYTES` of the file, base64
+    #[serde(rename = "fdc")]
+    DownloadChunk { id: String, data: String },
+    #[serde(rename = "fdd")]
+    DownloadDone { id: String },
+    #[serde(rename = "fde")]
+    DownloadError { id: String, error: String },
+    /// The virtual monitor as the agent left it after a `Resize` or
+    /// `DisplaySettings` request: screen size, the refresh rate and
+    /// rotation of its mode, and the preferred mode list. Every resize is
+    /// answered, including ones that were refused or letterboxed, so the
+    /// browser knows when the picture has settled.
+    #[serde(rename = "ds")]
+    DisplayState {
+        w: u32,
+        h: u32,
+        refresh: u32,
+        rotation: Rotation,
+        modes: Vec<DisplayMode>,
+    },
+    /// The second monitor as the agent left it after a `ScreenAdd` or
+    /// `ScreenRemove`: its size, or 0 x 0 when there is none, as when
+    /// attaching it failed
+    #[serde(rename = "s2")]
+    SecondScreen { w: u32, h: u32 },
+    /// Pairs an audio frame's timestamp with the media clock time it was
+    /// captured at, as an RTCP sender report pairs RTP and NTP time. Sent
+    /// every few seconds of audio, so the browser can line audio up with
+    /// video frames, whose timestamps are media clock time.
+    #[serde(rename = "sr")]
+    SenderReport { clock_us: u64, audio_us: u64 },
+    /// The X server died mid-session. The agent starts the display and
+    /// desktop again, or leaves that to the server by exiting.
+    #[serde(rename = "dl")]
+    DisplayLost,
+    /// A display that was lost is back, with a fresh desktop
+    #[serde(rename = "dr")]
+    DisplayRestored,
+}
+
+impl AgentMessage {
+    pub fn to_json(&self) -> String {
+        // Strings, integers and bools only: serializing can't fail
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Trade-off between smoothness and sharpness the agent's encoder is set
+/// up for. Each user's last choice is kept by the server and applied to
+/// their next session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityPreset {
+    /// Fewer bits and no quantizer ceiling: blurrier, but keeps up on a
+    /// weak link
+    Smooth,
+    /// Constant bitrate at the configured `video.bitrate`. Older clients
+    /// call it "standard" ("high" and "low" before that).
+    #[default]
+    #[serde(alias = "standard", alias = "high", alias = "low")]
+    Balanced,
+    /// A low quantizer ceiling so text stays crisp; bursts over the bitrate
+    Sharp,
+    /// Near-lossless constant quantizer for text-heavy work; unbounded
+    /// bitrate, so the agent falls back to `Balanced` when it stays over
+    Lossless,
+}
+
+impl QualityPreset {
+    pub const ALL: [Self; 4] = [Self::Smooth, Self::Balanced, Self::Sharp, Self::Lossless];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Smooth => "smooth",
+            Self::Balanced => "balanced",
+            Self::Sharp => "sharp",
+            Self::Lossless => "lossless",
+        }
+    }
+}
+
+/// Which way clipboard text may cross between the browser ("local") and
+/// the remote desktop. Enforced by the agent; the client is told so it
+/// can stop offering the blocked direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClipboardPolicy {
+    #[default]
+    Bidirectional,
+    /// Paste into the session only; nothing copied there leaves it
+    ToRemote,
+    /// Copy out of the session only
+    ToLocal,
+    Off,
+}
+
+impl ClipboardPolicy {
+    pub const NAMES: [&str; 4] = ["bidirectional", "to-remote", "to-local", "off"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bidirectional => "bidirectional",
+            Self::ToRemote => "to-remote",
+            Self::ToLocal => "to-local",
+            Self::Off => "off",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bidirectional" => Some(Self::Bidirectional),
+            "to-remote" => Some(Self::ToRemote),
+            "to-local" => Some(Self::ToLocal),
+            "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+
+    /// Browser clipboard may be pasted into the session
+    pub fn allows_to_remote(self) -> bool {
+        matches!(self, Self::Bidirectional | Self::ToRemote)
+    }
+
+    /// Session clipboard may be sent to the browser
+    pub fn allows_to_local(self) -> bool {
+        matches!(self, Self::Bidirectional | Self::ToLocal)
+    }
+}
+
+/// Orientation of the virtual monitor, in xrandr's names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    #[default]
+    Normal,
+    /// Turned a quarter counter-clockwise
+    Left,
+    Inverted,
+    /// Turned a quarter clockwise
+    Right,
+}
+
+impl Rotation {
+    /// Whether the monitor stands on its side, making the screen its mode
+    /// with width and height swapped
+    pub fn is_portrait(self) -> bool {
+        matches!(self, Self::Left | Self::Right)
+    }
+}
+
+/// Refresh rate of modes that don't name one, in Hz
+pub const DEFAULT_REFRESH_HZ: u32 = 60;
+
+/// Refresh rates a display mode may have, in Hz
+pub const DISPLAY_REFRESH_HZ: std::ops::RangeInclusive<u32> = 24..=240;
+
+/// Most modes a preferred mode list may hold.
+pub const MAX_DISPLAY_MODES: usize = 16;
+
+/// Loudest an `AudioAppVolume` may set a stream, in percent: the boost
+/// PulseAudio's own mixers allow
+pub const MAX_APP_VOLUME: u16 = 150;
+
+/// Opus frame lengths the agent can encode, in ms
+pub const OPUS_FRAME_MS: [u32; 3] = [10, 20, 40];
+
+/// Opus frame length unless `audio.frame_ms` says otherwise
+pub const DEFAULT_OPUS_FRAME_MS: u32 = 20;
+
+/// A mode of the virtual monitor, written `2560x1440` or `2560x1440@75`
+/// in config files, agent arguments and messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    /// Hz, `DEFAULT_REFRESH_HZ` unless the mode names one
+    pub refresh: u32,
+}
+
+impl DisplayMode {
+    /// Parse `WIDTHxHEIGHT[@HZ]`. None unless the size is one the agent can
+    /// resize to (even, 640x480 to 7680x4320) and the rate is within
+    /// `DISPLAY_REFRESH_HZ`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (size, refresh) = match s.split_once('@') {
+            Some((size, hz)) => (size, hz.parse().ok()?),
+            None => (s, DEFAULT_REFRESH_HZ),
+        };
+        let (width, height) = size.split_once('x')?;
+        let mode = Self {
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+            refresh,
+        };
+        let valid = (640..=7680).contains(&mode.width)
+            && (480..=4320).contains(&mode.height)
+            && mode.width.is_multiple_of(2)
+            && mode.height.is_multiple_of(2)
+            && DISPLAY_REFRESH_HZ.contains(&mode.refresh);
+        valid.then_some(mode)
+    }
+}
+
+impl std::fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}@{}", self.width, self.height, self.refresh)
+    }
+}
+
+impl TryFrom<String> for DisplayMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::parse(&s).ok_or_else(|| {
+            format!(
+                "invalid display mode \"{s}\": expected an even WIDTHxHEIGHT from 640x480 \
+                 to 7680x4320, optionally followed by @HZ from {} to {}",
+                DISPLAY_REFRESH_HZ.start(),
+                DISPLAY_REFRESH_HZ.end()
+            )
+        })
+    }
+}
+
+impl From<DisplayMode> for String {
+    fn from(mode: DisplayMode) -> Self {
+        mode.to_string()
+    }
+}
+
+/// Desktop environment a session runs, written `"auto"`, `"xfce"`,
+/// `"kde"`, `"gnome"`, `"i3"` or `"openbox"`. Any other string in the
+/// config is a command line the agent runs with `sh -c` instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Desktop {
+    /// XFCE if installed, else openbox
+    #[default]
+    Auto,
+    Xfce,
+    Kde,
+    Gnome,
+    I3,
+    Openbox,
+    Custom(String),
+}
+
+impl Desktop {
+    /// Names a login may ask for; custom commands only come from the config
+    pub const NAMED: [&str; 6] = ["auto", "xfce", "kde", "gnome", "i3", "openbox"];
+
+    pub fn parse(s: &str) -> Self {
+        match s.trim() {
+            "auto" => Self::Auto,
+            "xfce" => Self::Xfce,
+            "kde" => Self::Kde,
+            "gnome" => Self::Gnome,
+            "i3" => Self::I3,
+            "openbox" => Self::Openbox,
+            command => Self::Custom(command.to_string()),
+        }
+    }
+
+    /// The program that has to be installed for this desktop to start:
+    /// its session binary, or the first word of a custom command. None
+    /// for `Auto`, which falls back to whatever is there.
+    pub fn program(&self) -> Option<&str> {
+        match self {
+            Self::Auto => None,
+            Self::Xfce => Some("xfce4-session"),
+            Self::Kde => Some("startplasma-x11"),
+            Self::Gnome => Some("gnome-session"),
+            Self::I3 => Some("i3"),
+            Self::Openbox => Some("openbox"),
+            Self::Custom(command) => command.split_whitespace().next(),
+        }
+    }
+}
+
+impl std::fmt::Display for Desktop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Xfce => "xfce",
+            Self::Kde => "kde",
+            Self::Gnome => "gnome",
+            Self::I3 => "i3",
+            Self::Openbox => "openbox",
+            Self::Custom(command) => command,
+        })
+    }
+}
+
+impl From<String> for Desktop {
+    fn from(s: String) -> Self {
+        Self::parse(&s)
+    }
+}
+
+impl From<Desktop> for String {
+    fn from(desktop: Desktop) -> Self {
+        desktop.to_string()
+    }
+}
+
+/// Idle policy stage the agent just reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Screen blanked and locked inside the session
+    Lock,
+    /// Browser should be disconnected; the desktop keeps running
+    Disconnect,
+    /// Session should be ended
+    Destroy,
+}
+
+/// Sent by the agent as `{"t":"idle",...}` when a stage of the session's
+/// idle policy fires. The server acts on `Disconnect` and `Destroy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "idle")]
+pub struct IdleNotice {
+    pub action: IdleAction,
+}
+
+/// Sent by an assisting agent as `{"t":"assist",...}` when the person at
+/// the display pauses or resumes the operator's keyboard and mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "assist")]
+pub struct AssistNotice {
+    pub control: bool,
+}
+
+/// Exit status of an assisting agent whose consent prompt was declined or
+/// went unanswered. The server ends the session instead of restarting it.
+pub const ASSIST_DECLINED_EXIT: i32 = 75;
+
+/// Exit status of an assisting agent after the person at the display ended
+/// the session from its banner.
+pub const ASSIST_ENDED_EXIT: i32 = 76;
+
+/// Signaling protocol version of this build. Bump when a change to the
+/// browser or agent WebSocket messages would confuse an older peer.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest peer protocol version the server still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Release of this build, e.g. "0.2.9". Announced in `Hello` next to the
+/// protocol version, which only changes with the messages.
+pub const RELEASE: &str = env!("CARGO_PKG_VERSION");
+
+/// How far apart two releases are, by their semantic versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionSkew {
+    Same,
+    /// Within the allowed number of minor releases of each other
+    Within,
+    /// Same major version, but more minor releases apart than allowed
+    Beyond,
+    /// Different major versions, which make no promises to each other
+    Incompatible,
+}
+
+impl VersionSkew {
+    /// Compare releases `a` and `b`, allowing `window` minor releases
+    /// between them. None if either isn't a semantic version.
+    pub fn between(a: &str, b: &str, window: u32) -> Option<Self> {
+        let (a, b) = (semver(a)?, semver(b)?);
+        Some(if a == b {
+            Self::Same
+        } else if a.0 != b.0 {
+            Self::Incompatible
+        } else if a.1.abs_diff(b.1) > u64::from(window) {
+            Self::Beyond
+        } else {
+            Self::Within
+        })
+    }
+}
+
+/// Major, minor and patch of a semantic version; pre-release and build
+/// suffixes are ignored.
+fn semver(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+    let parsed = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(parsed)
+}
+
+/// First text message a browser or agent sends on its WebSocket, answered
+/// by the server with its own. Peers that predate it send nothing and are
+/// treated as speaking the current version with every capability.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "t", rename = "hello")]
+pub struct Hello {
+    pub version: u32,
+    /// Optional features this peer implements (`AgentCommand` variants an
+    /// agent can act on, for instance)
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// The peer's `RELEASE`; None from builds that predate it, and from
+    /// browsers, whose client the server itself serves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+}
+
+impl Hello {
+    pub fn new(capabilities: &[&str]) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+            release: Some(RELEASE.to_string()),
+        }
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
 }
 
+/// Agent capability: acts on `AgentCommand::LockScreen`/`UnlockScreen`.
+pub const CAP_LOCK_SCREEN: &str = "lock_screen";
+
+/// Agent capability: acts on `AgentCommand::TimedInput`.
+pub const CAP_INPUT_TIMESTAMPS: &str = "input_timestamps";
+
+/// Agent capability: acts on `AgentCommand::Notify`.
+pub const CAP_NOTIFY: &str = "notify";
+
+/// Agent capability: acts on `AgentCommand::Upgrade`.
+pub const CAP_UPGRADE: &str = "upgrade";
+
 /// Internal message from server to agent process.
 /// Uses adjacently tagged representation to avoid tag collision with nested types.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -131,6 +1300,24 @@ pub struct SessionInfo {
 pub enum AgentCommand {
     /// Forward an input event to the agent
     Input(InputEvent),
+    /// Forward an input event with the browser's timestamp, so the agent
+    /// can space out events that arrive in a burst
+    TimedInput(TimedInput),
+    /// Pause or resume audio capture (server API, replayed on agent reconnect)
+    SetAudio { enabled: bool },
+    /// Ceiling on the video bitrate in kbps from the bandwidth caps,
+    /// replayed on agent reconnect; 0 lifts it
+    SetBitrateCap { kbps: u32 },
+    /// Lock the desktop (`lock_on_disconnect`: the browser went away)
+    LockScreen,
+    /// End a lock without a password (`unlock_on_login`: the user just
+    /// signed in through the server)
+    UnlockScreen,
+    /// Show a desktop notification in the session (admin messages)
+    Notify { summary: String, body: String },
+    /// Execute the agent binary at `path` in place of this one, keeping the
+    /// display, the desktop and the session
+    Upgrade { path: String },
     /// Shut down the agent
     Shutdown,
 }
@@ -169,6 +1356,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn notices_and_notify_commands_serialize() {
+        let notice = SignalingMessage::Notice {
+            message: "Restarting at 18:00".to_string(),
+            from: "admin".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&notice).unwrap(),
+            r#"{"type":"notice","message":"Restarting at 18:00","from":"admin"}"#
+        );
+
+        let notify = AgentCommand::Notify {
+            summary: "Message from admin".to_string(),
+            body: "Restarting at 18:00".to_string(),
+        };
+        let json = serde_json::to_string(&notify).unwrap();
+        assert!(json.starts_with(r#"{"cmd":"notify","data":{"#));
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            AgentCommand::Notify { body, .. } if body == "Restarting at 18:00"
+        ));
+
+        let upgrade = AgentCommand::Upgrade {
+            path: "/usr/bin/beam-agent".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&upgrade).unwrap(),
+            r#"{"cmd":"upgrade","data":{"path":"/usr/bin/beam-agent"}}"#
+        );
+    }
+
+    #[test]
+    fn termination_notices_serialize() {
+        let scheduled = SignalingMessage::TerminationScheduled {
+            ends_in_secs: 300,
+            from: "admin".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&scheduled).unwrap(),
+            r#"{"type":"termination_scheduled","ends_in_secs":300,"from":"admin"}"#
+        );
+        let cancelled = SignalingMessage::TerminationCancelled {
+            from: "admin".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&cancelled).unwrap(),
+            r#"{"type":"termination_cancelled","from":"admin"}"#
+        );
+    }
+
     #[test]
     fn input_event_compact_format() {
         let key = InputEvent::Key { c: 30, d: true };
@@ -198,16 +1435,31 @@ mod tests {
         assert!(json.contains(r#""t":"cp""#));
         assert!(json.contains(r#""text":"primary""#));
 
-        let resize = InputEvent::Resize { w: 1920, h: 1080 };
+        let resize = InputEvent::Resize {
+            w: 1920,
+            h: 1080,
+            s: None,
+        };
         let json = serde_json::to_string(&resize).unwrap();
         assert!(json.contains(r#""t":"r""#));
+        assert!(!json.contains(r#""s""#));
 
         let layout = InputEvent::Layout {
             layout: "no".to_string(),
+            variant: None,
+            options: vec![],
         };
         let json = serde_json::to_string(&layout).unwrap();
-        assert!(json.contains(r#""t":"l""#));
-        assert!(json.contains(r#""layout":"no""#));
+        assert_eq!(json, r#"{"t":"l","layout":"no"}"#);
+        let layout: InputEvent = serde_json::from_str(
+            r#"{"t":"l","layout":"de","variant":"nodeadkeys","options":["ctrl:nocaps"]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            layout,
+            InputEvent::Layout { variant: Some(v), options, .. }
+                if v == "nodeadkeys" && options == ["ctrl:nocaps"]
+        ));
 
         let rel_mouse = InputEvent::RelativeMouseMove { dx: -3.5, dy: 1.2 };
         let json = serde_json::to_string(&rel_mouse).unwrap();
@@ -215,6 +1467,32 @@ mod tests {
         assert!(json.contains(r#""dx""#));
         assert!(json.contains(r#""dy""#));
 
+        let cursor_mode = InputEvent::CursorMode { composite: true };
+        let json = serde_json::to_string(&cursor_mode).unwrap();
+        assert!(json.contains(r#""t":"cm""#));
+        assert!(json.contains(r#""composite":true"#));
+
+        let lock = InputEvent::PointerLock { locked: true };
+        let json = serde_json::to_string(&lock).unwrap();
+        assert_eq!(json, r#"{"t":"pl","locked":true}"#);
+
+        let grab = InputEvent::KeyboardGrab { grab: false };
+        let json = serde_json::to_string(&grab).unwrap();
+        assert_eq!(json, r#"{"t":"kg","grab":false}"#);
+
+        let combo = InputEvent::KeyCombo {
+            combo: "ctrl-alt-del".into(),
+        };
+        let json = serde_json::to_string(&combo).unwrap();
+        assert_eq!(json, r#"{"t":"kc","combo":"ctrl-alt-del"}"#);
+
+        let ack = InputEvent::FrameAck {
+            ts: 16_667,
+            dd: 4.5,
+        };
+        let json = serde_json::to_string(&ack).unwrap();
+        assert_eq!(json, r#"{"t":"fa","ts":16667,"dd":4.5}"#);
+
         let visibility = InputEvent::VisibilityState { visible: false };
         let json = serde_json::to_string(&visibility).unwrap();
         assert!(json.contains(r#""t":"vs""#));
@@ -301,6 +1579,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn resize_carries_optional_device_pixel_ratio() {
+        let event: InputEvent =
+            serde_json::from_str(r#"{"t":"r","w":1280,"h":720,"s":1.5}"#).unwrap();
+        match event {
+            InputEvent::Resize { w, h, s } => {
+                assert_eq!((w, h), (1280, 720));
+                assert_eq!(s, Some(1.5));
+            }
+            _ => panic!("Expected Resize"),
+        }
+
+        // Old clients omit the scale entirely
+        let event: InputEvent = serde_json::from_str(r#"{"t":"r","w":1280,"h":720}"#).unwrap();
+        assert!(matches!(event, InputEvent::Resize { s: None, .. }));
+    }
+
     #[test]
     fn agent_command_wraps_input() {
         let event = InputEvent::Key { c: 30, d: true };
@@ -329,6 +1624,454 @@ mod tests {
         assert!(matches!(parsed, AgentCommand::Shutdown));
     }
 
+    #[test]
+    fn agent_command_set_audio() {
+        let cmd = AgentCommand::SetAudio { enabled: false };
+        let json = serde_json::to_string(&cmd).unwrap();
+        assert_eq!(json, r#"{"cmd":"set_audio","data":{"enabled":false}}"#);
+        let parsed: AgentCommand = serde_json::from_str(&json).unwrap();
+        assert!(matches!(parsed, AgentCommand::SetAudio { enabled: false }));
+    }
+
+    #[test]
+    fn agent_command_set_bitrate_cap() {
+        let cmd = AgentCommand::SetBitrateCap { kbps: 8000 };
+        let json = serde_json::to_string(&cmd).unwrap();
+        assert_eq!(json, r#"{"cmd":"set_bitrate_cap","data":{"kbps":8000}}"#);
+        let parsed: AgentCommand = serde_json::from_str(&json).unwrap();
+        assert!(matches!(parsed, AgentCommand::SetBitrateCap { kbps: 8000 }));
+    }
+
+    #[test]
+    fn agent_command_lock_screen() {
+        let json = serde_json::to_string(&AgentCommand::LockScreen).unwrap();
+        assert_eq!(json, r#"{"cmd":"lock_screen"}"#);
+        let parsed: AgentCommand = serde_json::from_str(r#"{"cmd":"unlock_screen"}"#).unwrap();
+        assert!(matches!(parsed, AgentCommand::UnlockScreen));
+    }
+
+    #[test]
+    fn latency_stats_tagged_roundtrip() {
+        let stats = LatencyStats {
+            samples: 40,
+            p50_ms: 18.0,
+            p95_ms: 31.5,
+            p99_ms: 44.0,
+            pipeline_p50_ms: 6.0,
+            network_p50_ms: 7.5,
+        };
+        let json = serde_json::to_string(&stats).unwrap();
+        assert!(json.starts_with(r#"{"t":"lat","#));
+        let parsed: LatencyStats = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, stats);
+
+        // Other agent messages must not parse as stats
+        assert!(serde_json::from_str::<LatencyStats>(r#"{"t":"cur","css":"text"}"#).is_err());
+    }
+
+    #[test]
+    fn pipeline_stats_tagged_roundtrip() {
+        let stage = |p50_ms| StageTiming {
+            p50_ms,
+            p95_ms: p50_ms * 2.0,
+            max_ms: p50_ms * 4.0,
+        };
+        let stats = PipelineStats {
+            frames: 600,
+            deadline_missed: 4,
+            capture: stage(1.5),
+            encode: stage(0.2),
+            drain: stage(3.0),
+            send: stage(0.1),
+            queue_dropped: 3,
+            outbox_dropped: 0,
+        };
+        let json = serde_json::to_string(&stats).unwrap();
+        assert!(json.starts_with(r#"{"t":"pst","#));
+        let parsed: PipelineStats = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, stats);
+        assert!(serde_json::from_str::<PipelineStats>(r#"{"t":"lat","samples":1}"#).is_err());
+
+        // Agents from before the drop counters
+        let mut older = serde_json::to_value(&stats).unwrap();
+        older.as_object_mut().unwrap().remove("queue_dropped");
+        let parsed: PipelineStats = serde_json::from_value(older).unwrap();
+        assert_eq!(parsed.queue_dropped, 0);
+    }
+
+    #[test]
+    fn network_quality_wire_format() {
+        let nq = NetworkQuality {
+            level: QualityLevel::Fair,
+            rtt_ms: None,
+            loss_pct: 1.5,
+            kbps: 8000,
+            target_kbps: 10000,
+        };
+        let json = serde_json::to_string(&nq).unwrap();
+        assert!(json.starts_with(r#"{"t":"nq","level":"fair","#));
+        assert_eq!(serde_json::from_str::<NetworkQuality>(&json).unwrap(), nq);
+    }
+
+    #[test]
+    fn stream_alert_wire_format() {
+        let alert = StreamAlert {
+            kind: StreamAlertKind::NotDelivered,
+            active: true,
+            secs: 5,
+            recover: true,
+        };
+        let json = serde_json::to_string(&alert).unwrap();
+        assert_eq!(
+            json,
+            r#"{"t":"va","kind":"not_delivered","active":true,"secs":5,"recover":true}"#
+        );
+        assert_eq!(serde_json::from_str::<StreamAlert>(&json).unwrap(), alert);
+        for kind in StreamAlertKind::ALL {
+            assert_eq!(
+                serde_json::to_value(kind).unwrap(),
+                serde_json::json!(kind.name())
+            );
+        }
+        assert!(serde_json::from_str::<StreamAlert>(r#"{"t":"nq","level":"good"}"#).is_err());
+
+        let json = serde_json::to_string(&ReconnectRequest { attempt: 2 }).unwrap();
+        assert_eq!(json, r#"{"t":"rc","attempt":2}"#);
+
+        let fallback = QualityFallback {
+            requested: QualityPreset::Lossless,
+            effective: QualityPreset::Balanced,
+            kbps: 120_000,
+            ceiling_kbps: 100_000,
+        };
+        let json = serde_json::to_string(&fallback).unwrap();
+        assert_eq!(
+            json,
+            r#"{"t":"qf","requested":"lossless","effective":"balanced","kbps":120000,"ceiling_kbps":100000}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<QualityFallback>(&json).unwrap(),
+            fallback
+        );
+    }
+
+    #[test]
+    fn webcam_events_and_status() {
+        let start: InputEvent =
+            serde_json::from_str(r#"{"t":"wcs","codec":"vp8","w":640,"h":480}"#).unwrap();
+        assert!(matches!(
+            start,
+            InputEvent::WebcamStart { ref codec, w: 640, h: 480 } if codec == "vp8"
+        ));
+        let stop: InputEvent = serde_json::from_str(r#"{"t":"wce"}"#).unwrap();
+        assert!(matches!(stop, InputEvent::WebcamStop));
+
+        let status = WebcamStatus {
+            active: true,
+            device: Some("/dev/video10".into()),
+            error: None,
+        };
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(
+            json,
+            r#"{"t":"wcst","active":true,"device":"/dev/video10"}"#
+        );
+    }
+
+    #[test]
+    fn open_url_event_and_validation() {
+        let event: InputEvent =
+            serde_json::from_str(r#"{"t":"ou","url":"https://example.com/a?b=c"}"#).unwrap();
+        assert!(
+            matches!(event, InputEvent::OpenUrl { ref url } if url == "https://example.com/a?b=c")
+        );
+
+        assert!(is_forwardable_url("https://example.com"));
+        assert!(is_forwardable_url("HTTP://example.com/path#frag"));
+        assert!(!is_forwardable_url("https://"));
+        assert!(!is_forwardable_url("file:///etc/passwd"));
+        assert!(!is_forwardable_url("javascript:alert(1)"));
+        assert!(!is_forwardable_url("https://example.com/a b"));
+        assert!(!is_forwardable_url("https://example.com/\n"));
+        assert!(!is_forwardable_url("https://example.com/\u{7}"));
+        assert!(!is_forwardable_url(&format!(
+            "https://example.com/{}",
+            "a".repeat(MAX_OPEN_URL_LEN)
+        )));
+        assert!(!is_forwardable_url("ftp://example.com"));
+        assert!(!is_forwardable_url("http"));
+    }
+
+    #[test]
+    fn audio_mode_event_parses() {
+        let event: InputEvent = serde_json::from_str(r#"{"t":"am","mode":"music"}"#).unwrap();
+        assert!(matches!(event, InputEvent::AudioMode { ref mode } if mode == "music"));
+    }
+
+    #[test]
+    fn audio_tuning_and_reports_parse() {
+        let event: InputEvent =
+            serde_json::from_str(r#"{"t":"ao","frame_ms":40,"fec":true}"#).unwrap();
+        assert!(matches!(
+            event,
+            InputEvent::AudioTuning {
+                frame_ms: Some(40),
+                fec: Some(true),
+                dtx: None
+            }
+        ));
+        let event: InputEvent =
+            serde_json::from_str(r#"{"t":"ar","played_ms":5000,"concealed_ms":120}"#).unwrap();
+        assert!(matches!(
+            event,
+            InputEvent::AudioReport {
+                played_ms: 5000,
+                concealed_ms: 120
+            }
+        ));
+    }
+
+    #[test]
+    fn audio_source_events_parse() {
+        let req: InputEvent = serde_json::from_str(r#"{"t":"asl"}"#).unwrap();
+        assert!(matches!(req, InputEvent::AudioSourcesRequest));
+
+        let app: InputEvent = serde_json::from_str(r#"{"t":"as","app":42}"#).unwrap();
+        assert!(matches!(
+            app,
+            InputEvent::AudioSource {
+                sink: None,
+                app: Some(42)
+            }
+        ));
+
+        let default: InputEvent = serde_json::from_str(r#"{"t":"as"}"#).unwrap();
+        assert!(matches!(
+            default,
+            InputEvent::AudioSource {
+                sink: None,
+                app: None
+            }
+        ));
+
+        let mute: InputEvent = serde_json::from_str(r#"{"t":"av","app":7,"muted":true}"#).unwrap();
+        assert!(matches!(
+            mute,
+            InputEvent::AudioAppVolume {
+                app: 7,
+                volume: None,
+                muted: Some(true)
+            }
+        ));
+    }
+
+    #[test]
+    fn clipboard_history_wire_format() {
+        let paste: InputEvent = serde_json::from_str(r#"{"t":"chp","id":7}"#).unwrap();
+        assert!(matches!(paste, InputEvent::ClipboardHistoryPaste { id: 7 }));
+        let clear: InputEvent = serde_json::from_str(r#"{"t":"chc"}"#).unwrap();
+        assert!(matches!(clear, InputEvent::ClipboardHistoryClear));
+
+        let history = ClipboardHistory {
+            entries: vec![ClipboardHistoryEntry {
+                id: 7,
+                text: "hello".into(),
+                at: 1_700_000_000_000,
+            }],
+        };
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(
+            json,
+            r#"{"t":"chl","entries":[{"id":7,"text":"hello","at":1700000000000}]}"#
+        );
+    }
+
+    #[test]
+    fn audio_sources_wire_format() {
+        let sources = AudioSources {
+            sinks: vec![AudioSinkInfo {
+                name: "auto_null".into(),
+                description: "Dummy Output".into(),
+            }],
+            apps: vec![AudioAppInfo {
+                index: 3,
+                name: "Firefox".into(),
+                volume: 80,
+                muted: false,
+            }],
+        };
+        let json = serde_json::to_string(&sources).unwrap();
+        assert!(json.starts_with(r#"{"t":"asl","sinks":"#));
+        assert_eq!(
+            serde_json::from_str::<AudioSources>(&json).unwrap(),
+            sources
+        );
+    }
+
+    #[test]
+    fn encoder_status_wire_format() {
+        let status = EncoderStatus {
+            name: "x264enc".into(),
+            fallback: true,
+        };
+        let json = serde_json::to_string(&status).unwrap();
+        assert_eq!(json, r#"{"t":"enc","name":"x264enc","fallback":true}"#);
+        assert_eq!(
+            serde_json::from_str::<EncoderStatus>(&json).unwrap(),
+            status
+        );
+    }
+
+    #[test]
+    fn agent_info_wire_format() {
+        let info = AgentInfo {
+            version: "0.4.2".into(),
+            encoder: "x264enc".into(),
+            fallback: true,
+            capture: "x11-shm".into(),
+            width: 1920,
+            height: 1080,
+            framerate: 60,
+        };
+        let json = serde_json::to_string(&info).unwrap();
+        assert_eq!(
+            json,
+            r#"{"t":"ai","version":"0.4.2","encoder":"x264enc","fallback":true,"capture":"x11-shm","width":1920,"height":1080,"framerate":60}"#
+        );
+        assert_eq!(serde_json::from_str::<AgentInfo>(&json).unwrap(), info);
+    }
+
+    #[test]
+    fn thumbnail_wire_format() {
+        let thumb = Thumbnail {
+            width: 320,
+            height: 180,
+            jpeg: "/9j/".into(),
+        };
+        let json = serde_json::to_string(&thumb).unwrap();
+        assert!(json.starts_with(r#"{"t":"thumb","#), "{json}");
+        assert_eq!(serde_json::from_str::<Thumbnail>(&json).unwrap(), thumb);
+    }
+
+    #[test]
+    fn idle_notice_wire_format() {
+        let notice = IdleNotice {
+            action: IdleAction::Disconnect,
+        };
+        let json = serde_json::to_string(&notice).unwrap();
+        assert_eq!(json, r#"{"t":"idle","action":"disconnect"}"#);
+        assert_eq!(serde_json::from_str::<IdleNotice>(&json).unwrap(), notice);
+    }
+
+    #[test]
+    fn assist_notice_wire_format() {
+        let notice = AssistNotice { control: false };
+        let json = serde_json::to_string(&notice).unwrap();
+        assert_eq!(json, r#"{"t":"assist","control":false}"#);
+        assert_eq!(serde_json::from_str::<AssistNotice>(&json).unwrap(), notice);
+    }
+
+    #[test]
+    fn input_batch_wire_format() {
+        let json = r#"{"t":"ib","e":[{"ts":10.5,"ev":{"t":"m","x":0.1,"y":0.2}},{"ts":11.5,"ev":{"t":"rm","dx":3.0,"dy":-1.0}}]}"#;
+        let event: InputEvent = serde_json::from_str(json).unwrap();
+        let InputEvent::InputBatch { e } = event else {
+            panic!("expected InputBatch");
+        };
+        assert_eq!(e.len(), 2);
+        assert_eq!(e[0].ts, 10.5);
+        assert!(matches!(e[1].ev, InputEvent::RelativeMouseMove { dx, .. } if dx == 3.0));
+        assert!(is_valid_input_batch(&e));
+    }
+
+    #[test]
+    fn input_batch_limits() {
+        let timed = |ev| TimedInput { ts: 0.0, ev };
+        let key = timed(InputEvent::Key { c: 30, d: true });
+        assert!(!is_valid_input_batch(&[key]));
+        let combo = timed(InputEvent::KeyCombo {
+            combo: "ctrl+alt+del".to_string(),
+        });
+        assert!(!is_valid_input_batch(&[combo]));
+        let nested = timed(InputEvent::InputBatch { e: vec![] });
+        assert!(!is_valid_input_batch(&[nested]));
+
+        let moves: Vec<_> = (0..=MAX_INPUT_BATCH)
+            .map(|_| timed(InputEvent::MouseMove { x: 0.5, y: 0.5 }))
+            .collect();
+        assert!(!is_valid_input_batch(&moves));
+        assert!(is_valid_input_batch(&moves[1..]));
+    }
+
+    #[test]
+    fn stamped_input_wire_format() {
+        let stamped: StampedInput =
+            serde_json::from_str(r#"{"t":"b","b":0,"d":true,"at":1234.5}"#).unwrap();
+        assert_eq!(stamped.ts, Some(1234.5));
+        assert!(matches!(stamped.ev, InputEvent::Button { b: 0, d: true }));
+
+        // Unstamped messages and unit variants still parse
+        let plain: StampedInput = serde_json::from_str(r#"{"t":"asl"}"#).unwrap();
+        assert_eq!(plain.ts, None);
+        assert!(matches!(plain.ev, InputEvent::AudioSourcesRequest));
+        let ack: StampedInput = serde_json::from_str(r#"{"t":"fa","ts":5,"dd":1.0}"#).unwrap();
+        assert_eq!(ack.ts, None);
+        assert!(matches!(ack.ev, InputEvent::FrameAck { ts: 5, .. }));
+
+        // Older peers see a plain event with an extra field
+        let event: InputEvent =
+            serde_json::from_str(r#"{"t":"k","c":30,"d":false,"at":7.0}"#).unwrap();
+        assert!(matches!(event, InputEvent::Key { c: 30, d: false }));
+
+        let cmd = AgentCommand::TimedInput(TimedInput {
+            ts: 7.0,
+            ev: InputEvent::Key { c: 30, d: false },
+        });
+        let json = serde_json::to_string(&cmd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"cmd":"timed_input","data":{"ts":7.0,"ev":{"t":"k","c":30,"d":false}}}"#
+        );
+    }
+
+    #[test]
+    fn hello_wire_format() {
+        let hello = Hello::new(&[CAP_LOCK_SCREEN]);
+        let json = serde_json::to_string(&hello).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"t":"hello","version":{PROTOCOL_VERSION},"capabilities":["lock_screen"],"release":"{RELEASE}"}}"#
+            )
+        );
+        assert!(hello.supports(CAP_LOCK_SCREEN));
+
+        // Capabilities and release are optional on the wire
+        let bare: Hello = serde_json::from_str(r#"{"t":"hello","version":7}"#).unwrap();
+        assert_eq!(bare.version, 7);
+        assert!(!bare.supports(CAP_LOCK_SCREEN));
+        assert_eq!(bare.release, None);
+    }
+
+    #[test]
+    fn version_skew_counts_minor_releases() {
+        let skew = |a, b| VersionSkew::between(a, b, 2);
+        assert_eq!(skew("0.2.9", "0.2.9"), Some(VersionSkew::Same));
+        assert_eq!(skew("0.2.9", "0.2.1"), Some(VersionSkew::Within));
+        assert_eq!(skew("0.2.9", "0.4.0"), Some(VersionSkew::Within));
+        assert_eq!(skew("0.5.0", "0.2.9"), Some(VersionSkew::Beyond));
+        assert_eq!(skew("1.0.0", "0.9.9"), Some(VersionSkew::Incompatible));
+        // Pre-release and build suffixes don't count
+        assert_eq!(skew("0.3.0-rc.1", "0.3.0+abc"), Some(VersionSkew::Same));
+        assert_eq!(skew("0.3", "0.3.0"), None);
+        assert_eq!(skew("0.3.0.1", "0.3.0"), None);
+        assert_eq!(skew("dev", "0.3.0"), None);
+        assert_eq!(
+            VersionSkew::between("0.2.0", "0.3.0", 0),
+            Some(VersionSkew::Beyond)
+        );
+    }
+
     #[test]
     fn auth_request_password_redacted_in_debug() {
         let req = AuthRequest {
@@ -337,6 +2080,12 @@ mod tests {
             viewport_width: None,
             viewport_height: None,
             idle_timeout: None,
+            system_combos: None,
+            nettest: None,
+            token_only: false,
+            takeover: false,
+            assist: false,
+            desktop: None,
         };
         let debug_str = format!("{:?}", req);
         assert!(debug_str.contains("admin"));
@@ -359,6 +2108,42 @@ mod tests {
         assert_eq!(req.idle_timeout, Some(7200));
     }
 
+    #[test]
+    fn auth_request_with_system_combos() {
+        let json = r#"{"username":"user","password":"pass","system_combos":true}"#;
+        let req: AuthRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(req.system_combos, Some(true));
+
+        let json = r#"{"username":"user","password":"pass"}"#;
+        let req: AuthRequest = serde_json::from_str(json).unwrap();
+        assert!(req.system_combos.is_none());
+    }
+
+    #[test]
+    fn auth_request_with_nettest() {
+        let json = r#"{"username":"user","password":"pass","nettest":{"kbps":12000,"rtt_ms":45}}"#;
+        let req: AuthRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            req.nettest,
+            Some(NetTestResult {
+                kbps: 12000,
+                rtt_ms: 45
+            })
+        );
+    }
+
+    #[test]
+    fn auth_request_token_only() {
+        let json = r#"{"username":"user","password":"pass","token_only":true}"#;
+        let req: AuthRequest = serde_json::from_str(json).unwrap();
+        assert!(req.token_only);
+
+        let json = r#"{"username":"user","password":"pass"}"#;
+        let req: AuthRequest = serde_json::from_str(json).unwrap();
+        assert!(!req.token_only);
+        assert!(!req.takeover);
+    }
+
     #[test]
     fn auth_request_idle_timeout_skipped_when_none() {
         let req = AuthRequest {
@@ -367,6 +2152,12 @@ mod tests {
             viewport_width: None,
             viewport_height: None,
             idle_timeout: None,
+            system_combos: None,
+            nettest: None,
+            token_only: false,
+            takeover: false,
+            assist: false,
+            desktop: None,
         };
         let json = serde_json::to_string(&req).unwrap();
         assert!(!json.contains("idle_timeout"));
@@ -379,6 +2170,12 @@ mod tests {
             session_id: Uuid::nil(),
             release_token: None,
             idle_timeout: Some(3600),
+            clipboard: None,
+            primary_selection: None,
+            share_links: None,
+            assist: None,
+            quality: None,
+            system_combos: None,
         };
         let json = serde_json::to_string(&resp).unwrap();
         assert!(json.contains(r#""idle_timeout":3600"#));
@@ -391,9 +2188,267 @@ mod tests {
             session_id: Uuid::nil(),
             release_token: None,
             idle_timeout: None,
+            clipboard: None,
+            primary_selection: None,
+            share_links: None,
+            assist: None,
+            quality: None,
+            system_combos: None,
         };
         let json = serde_json::to_string(&resp).unwrap();
         assert!(!json.contains("idle_timeout"));
+        assert!(!json.contains("clipboard"));
+        assert!(!json.contains("primary_selection"));
+        assert!(!json.contains("share_links"));
+    }
+
+    #[test]
+    fn clipboard_policy_directions() {
+        let resp = AuthResponse {
+            token: "tok".to_string(),
+            session_id: Uuid::nil(),
+            release_token: None,
+            idle_timeout: None,
+            clipboard: Some(ClipboardPolicy::ToRemote),
+            primary_selection: Some(true),
+            share_links: None,
+            assist: None,
+            quality: None,
+            system_combos: None,
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains(r#""clipboard":"to-remote""#));
+        assert!(json.contains(r#""primary_selection":true"#));
+
+        for name in ClipboardPolicy::NAMES {
+            let policy = ClipboardPolicy::from_name(name).unwrap();
+            assert_eq!(policy.name(), name);
+        }
+        assert_eq!(ClipboardPolicy::from_name("both"), None);
+        assert!(ClipboardPolicy::ToRemote.allows_to_remote());
+        assert!(!ClipboardPolicy::ToRemote.allows_to_local());
+        assert!(!ClipboardPolicy::ToLocal.allows_to_remote());
+        assert!(ClipboardPolicy::Bidirectional.allows_to_local());
+        assert!(!ClipboardPolicy::Off.allows_to_local());
+        assert!(!ClipboardPolicy::Off.allows_to_remote());
+    }
+
+    #[test]
+    fn quality_presets_accept_legacy_modes() {
+        for preset in QualityPreset::ALL {
+            let json = format!(r#"{{"t":"q","mode":"{}"}}"#, preset.name());
+            let event: InputEvent = serde_json::from_str(&json).unwrap();
+            assert!(matches!(event, InputEvent::Quality { mode } if mode == preset));
+            assert_eq!(serde_json::to_string(&event).unwrap(), json);
+        }
+        for legacy in ["standard", "high", "low"] {
+            let json = format!(r#"{{"t":"q","mode":"{legacy}"}}"#);
+            assert!(matches!(
+                serde_json::from_str::<InputEvent>(&json).unwrap(),
+                InputEvent::Quality {
+                    mode: QualityPreset::Balanced
+                }
+            ));
+        }
+        assert!(serde_json::from_str::<InputEvent>(r#"{"t":"q","mode":"ultra"}"#).is_err());
+    }
+
+    #[test]
+    fn input_limits() {
+        let ok = |json: &str| {
+            serde_json::from_str::<InputEvent>(json)
+                .unwrap()
+                .check_limits()
+        };
+        assert_eq!(ok(r#"{"t":"c","text":"hi"}"#), Ok(()));
+        let big = "a".repeat(MAX_CLIPBOARD_BYTES + 1);
+        let err = ok(&format!(r#"{{"t":"c","text":"{big}"}}"#)).unwrap_err();
+        assert_eq!(err.field, "text");
+        assert_eq!(err.max, MAX_CLIPBOARD_BYTES);
+
+        let chunk = "A".repeat(MAX_FILE_CHUNK_LEN);
+        assert_eq!(
+            ok(&format!(r#"{{"t":"fc","id":"1","data":"{chunk}"}}"#)),
+            Ok(())
+        );
+        let err = ok(&format!(r#"{{"t":"fc","id":"1","data":"{chunk}A"}}"#)).unwrap_err();
+        assert_eq!(err.field, "data");
+        let path = "/".repeat(MAX_FIELD_LEN + 1);
+        assert!(ok(&format!(r#"{{"t":"fdr","path":"{path}"}}"#)).is_err());
+
+        // Each element of a batch is held to the limits too
+        let layout = "x".repeat(MAX_FIELD_LEN + 1);
+        let err = ok(&format!(
+            r#"{{"t":"ib","e":[{{"ts":1,"ev":{{"t":"l","layout":"{layout}"}}}}]}}"#
+        ))
+        .unwrap_err();
+        assert_eq!(err.field, "layout");
+
+        let modes = vec![r#""1920x1080""#; MAX_DISPLAY_MODES + 1].join(",");
+        let err = ok(&format!(r#"{{"t":"ds","modes":[{modes}]}}"#)).unwrap_err();
+        assert_eq!(err.field, "modes");
+
+        // Unknown fields are ignored rather than rejected
+        assert_eq!(ok(r#"{"t":"k","c":30,"d":true,"future":[1,2]}"#), Ok(()));
+    }
+
+    #[test]
+    fn display_modes_parse() {
+        let mode = |s: &str| DisplayMode::parse(s);
+        assert_eq!(
+            mode("2560x1440@75"),
+            Some(DisplayMode {
+                width: 2560,
+                height: 1440,
+                refresh: 75
+            })
+        );
+        assert_eq!(mode("1920x1080").unwrap().refresh, DEFAULT_REFRESH_HZ);
+        assert_eq!(mode("1080x1920@144").unwrap().to_string(), "1080x1920@144");
+        for bad in [
+            "",
+            "1920",
+            "1920x",
+            "x1080",
+            "1921x1080",
+            "320x240",
+            "8000x4320",
+            "1920x1080@",
+            "1920x1080@10",
+            "1920x1080@500",
+            "1920 x 1080",
+            "-1920x1080",
+        ] {
+            assert_eq!(mode(bad), None, "{bad}");
+        }
+        assert!(!Rotation::Inverted.is_portrait());
+        assert!(Rotation::Right.is_portrait());
+    }
+
+    #[test]
+    fn desktops_parse() {
+        for name in Desktop::NAMED {
+            let desktop = Desktop::parse(name);
+            assert!(!matches!(desktop, Desktop::Custom(_)), "{name}");
+            assert_eq!(desktop.to_string(), name);
+        }
+        assert_eq!(Desktop::Kde.program(), Some("startplasma-x11"));
+        assert_eq!(Desktop::Auto.program(), None);
+
+        let custom = Desktop::parse(" dbus-run-session  lxqt-session ");
+        assert_eq!(
+            custom,
+            Desktop::Custom("dbus-run-session  lxqt-session".to_string())
+        );
+        assert_eq!(custom.program(), Some("dbus-run-session"));
+        assert_eq!(Desktop::parse("").program(), None);
+
+        let req: AuthRequest =
+            serde_json::from_str(r#"{"username":"u","password":"p","desktop":"i3"}"#).unwrap();
+        assert_eq!(req.desktop, Some(Desktop::I3));
+    }
+
+    #[test]
+    fn display_settings_roundtrip() {
+        let event: InputEvent =
+            serde_json::from_str(r#"{"t":"ds","rotation":"left","modes":["1280x720@50"]}"#)
+                .unwrap();
+        let InputEvent::DisplaySettings {
+            rotation,
+            refresh,
+            modes,
+        } = &event
+        else {
+            panic!("Expected DisplaySettings");
+        };
+        assert_eq!(*rotation, Some(Rotation::Left));
+        assert_eq!(*refresh, None);
+        assert_eq!(
+            *modes,
+            Some(vec![DisplayMode::parse("1280x720@50").unwrap()])
+        );
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"t":"ds","rotation":"left","modes":["1280x720@50"]}"#
+        );
+
+        // An empty request is a query
+        let event: InputEvent = serde_json::from_str(r#"{"t":"ds"}"#).unwrap();
+        assert!(matches!(
+            event,
+            InputEvent::DisplaySettings {
+                rotation: None,
+                refresh: None,
+                modes: None
+            }
+        ));
+        assert!(serde_json::from_str::<InputEvent>(r#"{"t":"ds","rotation":"sideways"}"#).is_err());
+        assert!(serde_json::from_str::<InputEvent>(r#"{"t":"ds","modes":["big"]}"#).is_err());
+
+        let state = AgentMessage::DisplayState {
+            w: 1080,
+            h: 1920,
+            refresh: 60,
+            rotation: Rotation::Right,
+            modes: vec![],
+        };
+        assert_eq!(
+            state.to_json(),
+            r#"{"t":"ds","w":1080,"h":1920,"refresh":60,"rotation":"right","modes":[]}"#
+        );
+    }
+
+    #[test]
+    fn second_screen_events_parse() {
+        let event: InputEvent = serde_json::from_str(r#"{"t":"sa","w":1920,"h":1200}"#).unwrap();
+        assert!(matches!(event, InputEvent::ScreenAdd { w: 1920, h: 1200 }));
+        let event: InputEvent = serde_json::from_str(r#"{"t":"sd"}"#).unwrap();
+        assert!(matches!(event, InputEvent::ScreenRemove));
+        let event: InputEvent = serde_json::from_str(r#"{"t":"m2","x":0.5,"y":1.0}"#).unwrap();
+        assert!(event.is_batchable());
+        assert_eq!(
+            AgentMessage::SecondScreen { w: 0, h: 0 }.to_json(),
+            r#"{"t":"s2","w":0,"h":0}"#
+        );
+    }
+
+    #[test]
+    fn agent_message_format() {
+        let msg = AgentMessage::Cursor {
+            css: "default".into(),
+            png: None,
+            hx: None,
+            hy: None,
+        };
+        assert_eq!(msg.to_json(), r#"{"t":"cur","css":"default"}"#);
+        let msg = AgentMessage::DownloadStart {
+            id: "d1".into(),
+            name: "a.txt".into(),
+            size: 3,
+        };
+        assert_eq!(
+            msg.to_json(),
+            r#"{"t":"fds","id":"d1","name":"a.txt","size":3}"#
+        );
+        assert_eq!(
+            AgentMessage::PointerLock { locked: true }.to_json(),
+            r#"{"t":"pl","locked":true}"#
+        );
+        assert_eq!(
+            AgentMessage::SenderReport {
+                clock_us: 5_000_000,
+                audio_us: 4_999_000,
+            }
+            .to_json(),
+            r#"{"t":"sr","clock_us":5000000,"audio_us":4999000}"#
+        );
+        assert_eq!(AgentMessage::DisplayLost.to_json(), r#"{"t":"dl"}"#);
+        let parsed: AgentMessage =
+            serde_json::from_str(r#"{"t":"cp","text":"sel","extra":1}"#).unwrap();
+        assert_eq!(
+            parsed,
+            AgentMessage::ClipboardPrimary { text: "sel".into() }
+        );
     }
 
     #[test]
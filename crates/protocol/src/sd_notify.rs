@@ -0,0 +1,113 @@
+//! Client side of systemd's service notification protocol (sd_notify(3)),
+//! shared by the server and the agent: readiness and watchdog keepalives
+//! sent as datagrams to `$NOTIFY_SOCKET`. Outside a `Type=notify` unit the
+//! variable is unset and every call does nothing.
+
+use std::time::Duration;
+
+/// Environment variables a notify-enabled process must not pass on to the
+/// processes it spawns, or their notifications land on its unit.
+pub const NOTIFY_ENV: [&str; 3] = ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"];
+
+/// Tell systemd start-up is complete.
+pub fn ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={status}"));
+}
+
+/// Tell systemd shutdown has begun.
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+/// Watchdog keepalive; must be sent at least every `watchdog_interval()`.
+pub fn watchdog() {
+    notify("WATCHDOG=1");
+}
+
+/// How often to send `watchdog()`: half the unit's `WatchdogSec`, or None
+/// if the watchdog is off or meant for another process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?;
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    keepalive_interval(&usec, pid.as_deref(), std::process::id())
+}
+
+fn keepalive_interval(usec: &str, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid
+        && pid.parse::<u32>().ok()? != own_pid
+    {
+        return None;
+    }
+    let usec: u64 = usec.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Send one notification. Failures are logged by nobody: systemd treats a
+/// missing READY=1 or keepalive as the failure, which is what matters.
+fn notify(state: &str) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return;
+        };
+        let path = path.as_encoded_bytes();
+        // A leading '@' names a socket in the abstract namespace
+        let addr = match path.strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(std::ffi::OsStr::from_bytes(path)),
+        };
+        if let (Ok(addr), Ok(socket)) = (addr, UnixDatagram::unbound()) {
+            let _ = socket.send_to_addr(state.as_bytes(), &addr);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = state;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keepalive_is_half_the_watchdog_timeout() {
+        assert_eq!(
+            keepalive_interval("30000000", None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            keepalive_interval("30000000", Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        // Meant for the process that spawned us
+        assert_eq!(keepalive_interval("30000000", Some("7"), 42), None);
+        assert_eq!(keepalive_interval("0", None, 42), None);
+        assert_eq!(keepalive_interval("soon", None, 42), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn notifications_reach_the_socket() {
+        let dir = std::env::temp_dir().join(format!("beam-sd-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        // SAFETY: no other test reads or writes NOTIFY_SOCKET
+        unsafe { std::env::set_var("NOTIFY_SOCKET", &path) };
+        ready("Listening");
+        watchdog();
+        unsafe { std::env::remove_var("NOTIFY_SOCKET") };
+
+        let mut buf = [0u8; 64];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=Listening");
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"WATCHDOG=1");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,223 @@
+//! Per-session agent log files.
+//!
+//! Each agent writes its stdout and stderr to
+//! `<logging.agent_dir>/<session id>/agent.log`, owned by the session user
+//! and readable by nobody else. Agents keep their descriptor across server
+//! restarts, so a log past `logging.agent_max_bytes` is copied aside and
+//! truncated in place. It is opened for appending, which puts the agent's
+//! next write at the new end of the file instead of past a hole.
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use beam_protocol::LoggingConfig;
+use uuid::Uuid;
+
+/// How often logs are checked for rotation and expiry
+pub const MAINTAIN_INTERVAL: Duration = Duration::from_secs(60);
+
+const LOG_NAME: &str = "agent.log";
+
+fn session_dir(config: &LoggingConfig, session_id: Uuid) -> PathBuf {
+    Path::new(&config.agent_dir).join(session_id.to_string())
+}
+
+/// Open a session's log for the agent to append to, creating its directory
+/// (0700) and file (0600) for `owner` (uid, gid) if given.
+pub fn open(
+    config: &LoggingConfig,
+    session_id: Uuid,
+    owner: Option<(u32, u32)>,
+) -> Result<(File, PathBuf)> {
+    let dir = session_dir(config, session_id);
+    std::fs::create_dir_all(&config.agent_dir)
+        .with_context(|| format!("Failed to create agent log directory {}", config.agent_dir))?;
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .recursive(true)
+        .create(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(LOG_NAME);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to create agent log at {}", path.display()))?;
+    if let Some((uid, gid)) = owner {
+        std::os::unix::fs::chown(&dir, Some(uid), Some(gid))
+            .and_then(|()| std::os::unix::fs::chown(&path, Some(uid), Some(gid)))
+            .with_context(|| format!("Failed to hand {} to uid {uid}", path.display()))?;
+    }
+    Ok((file, path))
+}
+
+/// Rotate the logs of `active` sessions that outgrew the limit and remove
+/// those of sessions that ended more than the retention period ago.
+pub fn maintain(config: &LoggingConfig, active: &HashSet<Uuid>) {
+    for &session_id in active {
+        let dir = session_dir(config, session_id);
+        match rotate(config, &dir) {
+            Ok(true) => tracing::info!(%session_id, "Rotated agent log"),
+            Ok(false) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(%session_id, "Failed to rotate agent log: {e}"),
+        }
+    }
+    cleanup(config, active, SystemTime::now());
+}
+
+/// Shift `agent.log.N` up by one, copy `agent.log` to `agent.log.1` and
+/// truncate it, if it is past `agent_max_bytes`.
+fn rotate(config: &LoggingConfig, dir: &Path) -> std::io::Result<bool> {
+    let log = dir.join(LOG_NAME);
+    let meta = std::fs::metadata(&log)?;
+    if config.agent_max_bytes == 0 || meta.len() <= config.agent_max_bytes {
+        return Ok(false);
+    }
+    if config.agent_keep > 0 {
+        let rotated = |n: u32| dir.join(format!("{LOG_NAME}.{n}"));
+        for n in (1..config.agent_keep).rev() {
+            match std::fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        // Copies keep the mode, and get the owner back
+        std::fs::copy(&log, rotated(1))?;
+        std::os::unix::fs::chown(rotated(1), Some(meta.uid()), Some(meta.gid()))?;
+    }
+    OpenOptions::new().write(true).open(&log)?.set_len(0)?;
+    Ok(true)
+}
+
+/// Remove session directories, and the flat `agent-<id>.log` files that
+/// preceded them, not written to within `agent_retention_secs`.
+fn cleanup(config: &LoggingConfig, active: &HashSet<Uuid>, now: SystemTime) {
+    if config.agent_retention_secs == 0 {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(&config.agent_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let id = name
+            .strip_prefix("agent-")
+            .and_then(|n| n.strip_suffix(".log"))
+            .unwrap_or(name);
+        let Ok(session_id) = id.parse::<Uuid>() else {
+            continue;
+        };
+        if active.contains(&session_id) {
+            continue;
+        }
+        let path = entry.path();
+        let Some(written) = last_written(&path) else {
+            continue;
+        };
+        let age = now.duration_since(written).unwrap_or_default();
+        if age.as_secs() > config.agent_retention_secs {
+            let removed = if path.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            };
+            if let Err(e) = removed {
+                tracing::warn!(path = %path.display(), "Failed to remove old agent log: {e}");
+            }
+        }
+    }
+}
+
+/// Latest modification of a file, or of any file in a directory (writes to
+/// a file leave its directory's own mtime alone)
+fn last_written(path: &Path) -> Option<SystemTime> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    if !path.is_dir() {
+        return Some(modified);
+    }
+    let files = std::fs::read_dir(path).ok()?.flatten();
+    files
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .chain([modified])
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn test_config(name: &str) -> LoggingConfig {
+        let dir = std::env::temp_dir().join(format!("beam-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        LoggingConfig {
+            agent_dir: dir.to_string_lossy().into_owned(),
+            agent_max_bytes: 16,
+            agent_keep: 2,
+            ..LoggingConfig::default()
+        }
+    }
+
+    #[test]
+    fn logs_are_private_and_rotate_past_the_limit() {
+        let config = test_config("agent-logs-rotate");
+        let id = Uuid::new_v4();
+        let (mut file, path) = open(&config, id, None).unwrap();
+        let dir = session_dir(&config, id);
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&dir), 0o700);
+
+        file.write_all(b"short\n").unwrap();
+        assert!(!rotate(&config, &dir).unwrap());
+
+        for round in ["first", "second", "third"] {
+            writeln!(file, "{round} round, long enough to rotate").unwrap();
+            assert!(rotate(&config, &dir).unwrap());
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("agent.log"), "");
+        assert_eq!(read("agent.log.1"), "third round, long enough to rotate\n");
+        assert!(read("agent.log.2").starts_with("second"));
+        assert!(!dir.join("agent.log.3").exists());
+        assert_eq!(mode(&dir.join("agent.log.1")), 0o600);
+
+        // Appends continue at the start of the truncated file
+        file.write_all(b"after\n").unwrap();
+        assert_eq!(read("agent.log"), "after\n");
+        std::fs::remove_dir_all(&config.agent_dir).unwrap();
+    }
+
+    #[test]
+    fn only_ended_sessions_past_retention_are_removed() {
+        let config = test_config("agent-logs-cleanup");
+        let (active, ended) = (Uuid::new_v4(), Uuid::new_v4());
+        open(&config, active, None).unwrap();
+        open(&config, ended, None).unwrap();
+        let legacy = Path::new(&config.agent_dir).join(format!("agent-{}.log", Uuid::new_v4()));
+        std::fs::write(&legacy, "old layout\n").unwrap();
+        let unrelated = Path::new(&config.agent_dir).join("beam.log");
+        std::fs::write(&unrelated, "").unwrap();
+        let active_set = HashSet::from([active]);
+
+        cleanup(&config, &active_set, SystemTime::now());
+        assert!(session_dir(&config, ended).exists());
+
+        let later = SystemTime::now() + Duration::from_secs(config.agent_retention_secs + 1);
+        cleanup(&config, &active_set, later);
+        assert!(session_dir(&config, active).exists());
+        assert!(!session_dir(&config, ended).exists());
+        assert!(!legacy.exists());
+        assert!(unrelated.exists());
+        std::fs::remove_dir_all(&config.agent_dir).unwrap();
+    }
+}
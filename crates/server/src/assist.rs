@@ -0,0 +1,128 @@
+//! Remote assistance: sessions on the host's physical display.
+//!
+//! An admin who logs in with `assist` gets a session whose agent runs as
+//! the account logged in at the console and attaches to its X display
+//! instead of starting a virtual one. The agent asks the person there to
+//! accept before anything is streamed and keeps a banner on their screen
+//! for as long as the session lasts.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail, ensure};
+use beam_protocol::{ASSIST_DECLINED_EXIT, ASSIST_ENDED_EXIT, AssistConfig};
+use tokio::process::Command;
+
+/// The account logged in on the assisted display: `assist.user`, or the
+/// owner of seat0's active session according to logind.
+pub async fn local_user(config: &AssistConfig) -> Result<String> {
+    if let Some(user) = &config.user {
+        return Ok(user.clone());
+    }
+    let session = loginctl(&["show-seat", "seat0", "--property=ActiveSession", "--value"]).await?;
+    let session = session.trim();
+    ensure!(!session.is_empty(), "Nobody is logged in at this computer");
+    let properties = loginctl(&[
+        "show-session",
+        session,
+        "--property=Name",
+        "--property=Type",
+        "--property=Display",
+    ])
+    .await?;
+    console_user(&parse_properties(&properties), &config.display)
+}
+
+async fn loginctl(args: &[&str]) -> Result<String> {
+    let output = Command::new("loginctl")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run loginctl")?;
+    ensure!(
+        output.status.success(),
+        "loginctl {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `Key=Value` lines, as `loginctl show-*` prints them
+fn parse_properties(text: &str) -> HashMap<&str, &str> {
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect()
+}
+
+/// User of a logind session, provided it is an X11 session on `display`.
+fn console_user(properties: &HashMap<&str, &str>, display: &str) -> Result<String> {
+    let kind = properties.get("Type").copied().unwrap_or_default();
+    if kind != "x11" {
+        bail!("The session at this computer is '{kind}'; remote assistance needs an X11 session");
+    }
+    if let Some(&shown) = properties.get("Display")
+        && !shown.is_empty()
+        && shown != display
+    {
+        bail!("The session at this computer is on display {shown}, not {display}");
+    }
+    match properties.get("Name") {
+        Some(&name) if !name.is_empty() => Ok(name.to_string()),
+        _ => bail!("Could not tell who is logged in at this computer"),
+    }
+}
+
+/// The X authority file display managers leave for `uid`'s session, if any.
+pub fn xauthority(uid: u32, home: &str) -> Option<String> {
+    [
+        format!("/run/user/{uid}/gdm/Xauthority"),
+        format!("{home}/.Xauthority"),
+    ]
+    .into_iter()
+    .find(|path| Path::new(path).exists())
+}
+
+/// What to tell the browser when an assisting agent exits on purpose;
+/// None for a crash, which is restarted as usual.
+pub fn exit_message(code: Option<i32>) -> Option<&'static str> {
+    match code? {
+        ASSIST_DECLINED_EXIT => Some("assist_declined"),
+        ASSIST_ENDED_EXIT => Some("assist_ended"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console_user_needs_an_x11_session_on_the_display() {
+        let text = "Name=alice\nType=x11\nDisplay=:0\n";
+        let properties = parse_properties(text);
+        assert_eq!(console_user(&properties, ":0").unwrap(), "alice");
+        assert!(console_user(&properties, ":1").is_err());
+
+        let wayland = parse_properties("Name=alice\nType=wayland\nDisplay=\n");
+        let err = console_user(&wayland, ":0").unwrap_err().to_string();
+        assert!(err.contains("wayland"), "{err}");
+
+        // Some display managers leave Display empty
+        let unnamed = parse_properties("Name=bob\nType=x11\nDisplay=\n");
+        assert_eq!(console_user(&unnamed, ":0").unwrap(), "bob");
+        assert!(console_user(&parse_properties("Type=x11\n"), ":0").is_err());
+    }
+
+    #[test]
+    fn assist_exit_codes_end_the_session() {
+        assert_eq!(
+            exit_message(Some(ASSIST_DECLINED_EXIT)),
+            Some("assist_declined")
+        );
+        assert_eq!(exit_message(Some(ASSIST_ENDED_EXIT)), Some("assist_ended"));
+        assert_eq!(exit_message(Some(1)), None);
+        assert_eq!(exit_message(None), None);
+    }
+}
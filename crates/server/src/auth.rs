@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
 
 /// JWT claims for authenticated sessions.
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,6 +17,19 @@ pub struct Claims {
 
 const TOKEN_EXPIRY_SECS: u64 = 24 * 60 * 60; // 24 hours
 
+/// Authenticate a user against `auth.users_file` if set, PAM otherwise.
+/// Blocking, like `authenticate_pam`.
+pub fn authenticate(
+    config: &beam_protocol::AuthConfig,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    match &config.users_file {
+        Some(path) => crate::users::verify(std::path::Path::new(path), username, password),
+        None => authenticate_pam(username, password),
+    }
+}
+
 /// Authenticate a user via Linux PAM.
 ///
 /// Returns `Ok(())` if credentials are valid, or an error describing the failure.
@@ -106,6 +120,56 @@ pub fn validate_jwt_for_refresh(token: &str, secret: &str) -> Result<Claims> {
     Ok(token_data.claims)
 }
 
+/// How long an agent has to open its WebSocket with a fresh ticket.
+pub const AGENT_TICKET_SECS: u64 = 30;
+
+/// Claims of an agent's single-use WebSocket ticket. They share no fields
+/// with `Claims`, so a browser token never passes for a ticket or the
+/// other way round.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentTicketClaims {
+    /// Session the ticket is for
+    pub sid: Uuid,
+    /// Matched against the session's latest handshake, so each ticket
+    /// works once and a new handshake voids the previous one
+    pub nonce: String,
+    /// Expiration time (Unix timestamp)
+    pub exp: u64,
+}
+
+/// Sign a ticket for `session_id`'s agent.
+pub fn generate_agent_ticket(session_id: Uuid, nonce: &str, secret: &str) -> Result<String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("System clock error")?
+        .as_secs();
+    let claims = AgentTicketClaims {
+        sid: session_id,
+        nonce: nonce.to_string(),
+        exp: now + AGENT_TICKET_SECS,
+    };
+    jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .context("Failed to encode agent ticket")
+}
+
+/// Check an agent ticket's signature and expiry (with no leeway: it is
+/// meant to be used right away).
+pub fn validate_agent_ticket(token: &str, secret: &str) -> Result<AgentTicketClaims> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.leeway = 0;
+    let token_data: TokenData<AgentTicketClaims> = jsonwebtoken::decode(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .context("Invalid or expired agent ticket")?;
+    Ok(token_data.claims)
+}
+
 /// Generate a cryptographically secure random JWT secret.
 ///
 /// Uses `/dev/urandom` for CSPRNG on Linux.
@@ -196,4 +260,19 @@ mod tests {
         let s2 = generate_secret();
         assert_ne!(s1, s2);
     }
+
+    #[test]
+    fn agent_tickets_and_browser_tokens_do_not_mix() {
+        let secret = "test-secret-for-jwt";
+        let id = Uuid::new_v4();
+        let ticket = generate_agent_ticket(id, "nonce", secret).unwrap();
+        let claims = validate_agent_ticket(&ticket, secret).unwrap();
+        assert_eq!(claims.sid, id);
+        assert_eq!(claims.nonce, "nonce");
+        assert!(validate_agent_ticket(&ticket, "wrong-secret").is_err());
+
+        assert!(validate_jwt(&ticket, secret).is_err());
+        let browser = generate_jwt("testuser", secret).unwrap();
+        assert!(validate_agent_ticket(&browser, secret).is_err());
+    }
 }
@@ -0,0 +1,100 @@
+//! Video bandwidth caps.
+//!
+//! A session's bitrate ceiling is its user's `max_bandwidth_kbps`. With
+//! `host_bandwidth_kbps` set, sessions without a cap count as wanting
+//! `video.bitrate`, and when the ceilings add up to more than the host cap
+//! every session is lowered by the same proportion. Agents get their
+//! ceiling on the command line and, as sessions come and go, through
+//! `AgentCommand::SetBitrateCap`.
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use beam_protocol::AgentCommand;
+
+use crate::session::SessionManager;
+use crate::signaling::ChannelRegistry;
+use crate::timeline::SessionEvent;
+
+/// No session is squeezed below this share of the host cap (kbps)
+pub const MIN_SHARE_KBPS: u32 = 500;
+
+/// How often the host cap is shared out again
+pub const REBALANCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Each session's ceiling from its own cap (0 = none) under `host_kbps`
+/// (0 = none). Uncapped sessions count as `uncapped_kbps` once a host cap
+/// applies and stay uncapped otherwise.
+pub fn share_out(caps: &[u32], uncapped_kbps: u32, host_kbps: u32) -> Vec<u32> {
+    if host_kbps == 0 {
+        return caps.to_vec();
+    }
+    let wants: Vec<u32> = caps
+        .iter()
+        .map(|&cap| if cap == 0 { uncapped_kbps } else { cap })
+        .collect();
+    let total: u64 = wants.iter().map(|&kbps| u64::from(kbps)).sum();
+    if total <= u64::from(host_kbps) {
+        return wants;
+    }
+    wants
+        .into_iter()
+        .map(|kbps| {
+            let share = u64::from(kbps) * u64::from(host_kbps) / total;
+            (share as u32).max(MIN_SHARE_KBPS.min(kbps))
+        })
+        .collect()
+}
+
+/// Tell every agent whose ceiling moved since it was last told.
+pub async fn rebalance(sessions: &SessionManager, channels: &ChannelRegistry) {
+    for (session_id, kbps) in sessions.bitrate_ceilings().await {
+        let Some(channel) = channels.read().await.get(&session_id).cloned() else {
+            continue;
+        };
+        let previous = channel.bitrate_cap.swap(kbps, Ordering::Relaxed);
+        if previous == kbps {
+            continue;
+        }
+        tracing::info!(%session_id, kbps, previous, "Video bitrate ceiling changed");
+        channel.timeline.record(SessionEvent::BitrateCap { kbps });
+        // No agent connected is fine: it gets the ceiling when it connects
+        let _ = channel.to_agent.send(AgentCommand::SetBitrateCap { kbps });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_a_host_cap_sessions_keep_their_own() {
+        assert_eq!(share_out(&[0, 8000], 50_000, 0), vec![0, 8000]);
+    }
+
+    #[test]
+    fn host_cap_lowers_everyone_by_the_same_proportion() {
+        // Within the host cap: uncapped sessions are held to video.bitrate
+        assert_eq!(
+            share_out(&[0, 20_000], 50_000, 100_000),
+            vec![50_000, 20_000]
+        );
+        // 150 Mbps wanted of 100: two thirds each
+        assert_eq!(
+            share_out(&[0, 0, 50_000], 50_000, 100_000),
+            vec![33_333, 33_333, 33_333]
+        );
+        assert_eq!(
+            share_out(&[40_000, 10_000], 50_000, 25_000),
+            vec![20_000, 5000]
+        );
+    }
+
+    #[test]
+    fn shares_have_a_floor() {
+        let shares = share_out(&[0; 100], 50_000, 10_000);
+        assert!(shares.iter().all(|&kbps| kbps == MIN_SHARE_KBPS));
+        // ...but never above what the session asked for
+        assert_eq!(share_out(&[300, 0], 50_000, 10_000)[0], 300);
+    }
+}
@@ -0,0 +1,179 @@
+//! Temporary bans for addresses that keep failing logins.
+//!
+//! The per-IP rate limiter only slows password spraying down to its window;
+//! an address that exhausts it again and again gets banned, for
+//! `server.ip_ban_secs` at first and twice as long on every repeat, up to
+//! `server.ip_ban_max_secs`. Admins list and lift bans via `/api/admin/bans`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Addresses tracked at once; the ones whose bans ended longest ago go first
+const MAX_KEYS: usize = 10_000;
+
+struct Ban {
+    until: Instant,
+    /// Bans so far; the next one lasts `base * 2^strikes`
+    strikes: u32,
+}
+
+pub struct IpBans {
+    bans: Mutex<HashMap<String, Ban>>,
+    /// 0 = bans disabled
+    base: Duration,
+    max: Duration,
+}
+
+impl IpBans {
+    pub fn new(base_secs: u64, max_secs: u64) -> Self {
+        Self {
+            bans: Mutex::new(HashMap::new()),
+            base: Duration::from_secs(base_secs),
+            max: Duration::from_secs(max_secs.max(base_secs)),
+        }
+    }
+
+    /// Ban `key`, longer than last time. Returns the ban's length and how
+    /// many bans the key has had, or None when bans are disabled.
+    pub fn ban(&self, key: &str) -> Option<(Duration, u32)> {
+        if self.base.is_zero() {
+            return None;
+        }
+        let mut bans = self.bans.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        self.forget_reformed(&mut bans, now);
+        if bans.len() >= MAX_KEYS
+            && !bans.contains_key(key)
+            && let Some(oldest) = bans
+                .iter()
+                .min_by_key(|(_, ban)| ban.until)
+                .map(|(k, _)| k.clone())
+        {
+            bans.remove(&oldest);
+        }
+
+        let ban = bans.entry(key.to_string()).or_insert(Ban {
+            until: now,
+            strikes: 0,
+        });
+        let length = self
+            .base
+            .saturating_mul(1u32.checked_shl(ban.strikes).unwrap_or(u32::MAX))
+            .min(self.max);
+        ban.until = now + length;
+        ban.strikes = ban.strikes.saturating_add(1);
+        Some((length, ban.strikes))
+    }
+
+    /// Time left on `key`'s ban, if it is banned.
+    pub fn remaining(&self, key: &str) -> Option<Duration> {
+        let bans = self.bans.lock().unwrap_or_else(|e| e.into_inner());
+        let left = bans
+            .get(key)?
+            .until
+            .saturating_duration_since(Instant::now());
+        (!left.is_zero()).then_some(left)
+    }
+
+    /// End `key`'s ban and forget its strikes. Returns false if it wasn't banned.
+    pub fn lift(&self, key: &str) -> bool {
+        let mut bans = self.bans.lock().unwrap_or_else(|e| e.into_inner());
+        bans.remove(key)
+            .is_some_and(|ban| ban.until > Instant::now())
+    }
+
+    /// Current bans for the admin API, longest remaining first.
+    pub fn list_json(&self) -> Vec<serde_json::Value> {
+        let bans = self.bans.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let unix_now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut active: Vec<(&String, Duration, u32)> = bans
+            .iter()
+            .map(|(key, ban)| (key, ban.until.saturating_duration_since(now), ban.strikes))
+            .filter(|(_, left, _)| !left.is_zero())
+            .collect();
+        active.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        active
+            .into_iter()
+            .map(|(key, left, strikes)| {
+                serde_json::json!({
+                    "ip": key,
+                    "strikes": strikes,
+                    "remaining_secs": left.as_secs().max(1),
+                    "banned_until": (unix_now + left).as_secs(),
+                })
+            })
+            .collect()
+    }
+
+    /// Drop keys that have stayed clean for as long as the longest ban, so
+    /// an address's next ban starts from the base length again.
+    fn forget_reformed(&self, bans: &mut HashMap<String, Ban>, now: Instant) {
+        bans.retain(|_, ban| now.saturating_duration_since(ban.until) < self.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_double_up_to_the_ceiling() {
+        let bans = IpBans::new(60, 200);
+        assert_eq!(bans.ban("192.0.2.1"), Some((Duration::from_secs(60), 1)));
+        assert_eq!(bans.ban("192.0.2.1"), Some((Duration::from_secs(120), 2)));
+        assert_eq!(bans.ban("192.0.2.1"), Some((Duration::from_secs(200), 3)));
+        // Other addresses start over
+        assert_eq!(bans.ban("192.0.2.2"), Some((Duration::from_secs(60), 1)));
+        for _ in 0..40 {
+            bans.ban("192.0.2.1");
+        }
+        assert_eq!(bans.ban("192.0.2.1").unwrap().0, Duration::from_secs(200));
+    }
+
+    #[test]
+    fn banned_until_lifted() {
+        let bans = IpBans::new(60, 3600);
+        assert_eq!(bans.remaining("192.0.2.1"), None);
+        bans.ban("192.0.2.1");
+        let left = bans.remaining("192.0.2.1").unwrap();
+        assert!(left > Duration::from_secs(59) && left <= Duration::from_secs(60));
+
+        let listed = bans.list_json();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["ip"], "192.0.2.1");
+        assert_eq!(listed[0]["strikes"], 1);
+
+        assert!(bans.lift("192.0.2.1"));
+        assert!(!bans.lift("192.0.2.1"));
+        assert_eq!(bans.remaining("192.0.2.1"), None);
+        // Lifting forgets the strikes
+        assert_eq!(bans.ban("192.0.2.1").unwrap().1, 1);
+    }
+
+    #[test]
+    fn zero_base_disables_bans() {
+        let bans = IpBans::new(0, 3600);
+        assert_eq!(bans.ban("192.0.2.1"), None);
+        assert_eq!(bans.remaining("192.0.2.1"), None);
+        assert!(bans.list_json().is_empty());
+    }
+
+    #[test]
+    fn expired_bans_are_not_listed() {
+        let bans = IpBans::new(60, 3600);
+        bans.ban("192.0.2.1");
+        bans.bans
+            .lock()
+            .unwrap()
+            .get_mut("192.0.2.1")
+            .unwrap()
+            .until = Instant::now();
+        assert_eq!(bans.remaining("192.0.2.1"), None);
+        assert!(bans.list_json().is_empty());
+        assert!(!bans.lift("192.0.2.1"));
+    }
+}
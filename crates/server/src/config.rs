@@ -3,30 +3,33 @@ use std::path::Path;
 use anyhow::{Context, Result};
 use beam_protocol::BeamConfig;
 
-pub use beam_protocol::{AudioConfig, ServerConfig, SessionConfig, VideoConfig};
-
-/// Load configuration from a TOML file at the given path.
-/// If the file doesn't exist, returns default configuration.
+/// Load configuration from a TOML file at the given path, with
+/// `BEAM_<SECTION>__<KEY>` environment overrides on top. If the file
+/// doesn't exist, the overrides apply to the defaults.
 pub fn load_config(path: &Path) -> Result<BeamConfig> {
-    if !path.exists() {
+    let contents = if path.exists() {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        tracing::info!("Loaded config from {}", path.display());
+        contents
+    } else {
         tracing::warn!(
             "Config file not found at {}, using defaults",
             path.display()
         );
-        return Ok(BeamConfig {
-            server: ServerConfig::default(),
-            video: VideoConfig::default(),
-            audio: AudioConfig::default(),
-            session: SessionConfig::default(),
-        });
-    }
-
-    let contents = std::fs::read_to_string(path)
-        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
-
-    let config: BeamConfig =
-        toml::from_str(&contents).with_context(|| "Failed to parse config TOML")?;
+        String::new()
+    };
 
-    tracing::info!("Loaded config from {}", path.display());
+    let (config, overrides) =
+        BeamConfig::from_toml_and_env(&contents).with_context(|| "Failed to parse config TOML")?;
+    if !overrides.applied.is_empty() {
+        tracing::info!(
+            "Config overridden from the environment: {}",
+            overrides.applied.join(", ")
+        );
+    }
+    for name in &overrides.unknown {
+        tracing::warn!("{name} matches no config key and was ignored");
+    }
     Ok(config)
 }
@@ -0,0 +1,128 @@
+//! Drain mode for rolling upgrades.
+//!
+//! Once draining (`POST /api/admin/drain` or SIGUSR1), logins that would
+//! create a new session are refused while existing sessions keep working.
+//! The server shuts down when the last session ends or `server.drain_timeout`
+//! passes; sessions still running then are persisted like on SIGTERM.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::session::SessionManager;
+
+/// How often a draining server checks whether its sessions are gone.
+const DRAIN_POLL: Duration = Duration::from_secs(5);
+
+pub struct DrainState {
+    started: Mutex<Option<Instant>>,
+    started_notify: Notify,
+    /// 0 = wait for the last session however long it takes
+    timeout: Duration,
+}
+
+impl DrainState {
+    pub fn new(timeout_secs: u64) -> Self {
+        Self {
+            started: Mutex::new(None),
+            started_notify: Notify::new(),
+            timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+
+    /// Enter drain mode. Returns false if already draining.
+    pub fn start(&self) -> bool {
+        let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+        if started.is_some() {
+            return false;
+        }
+        *started = Some(Instant::now());
+        self.started_notify.notify_waiters();
+        true
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.started_at().is_some()
+    }
+
+    fn started_at(&self) -> Option<Instant> {
+        *self.started.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Drain progress for the health and admin APIs.
+    pub fn status_json(&self, sessions: usize) -> serde_json::Value {
+        match self.started_at() {
+            None => serde_json::json!({ "draining": false }),
+            Some(started) => serde_json::json!({
+                "draining": true,
+                "elapsed_secs": started.elapsed().as_secs(),
+                "timeout_secs": (!self.timeout.is_zero()).then_some(self.timeout.as_secs()),
+                "sessions_remaining": sessions,
+            }),
+        }
+    }
+
+    /// Whether a drain that started at `started` is finished at `now`.
+    fn is_complete(&self, started: Instant, now: Instant, sessions: usize) -> bool {
+        sessions == 0 || (!self.timeout.is_zero() && now.duration_since(started) >= self.timeout)
+    }
+
+    /// Resolve once draining has started and then completed.
+    pub async fn wait_complete(&self, session_manager: &SessionManager) {
+        let started = loop {
+            let notified = self.started_notify.notified();
+            if let Some(started) = self.started_at() {
+                break started;
+            }
+            notified.await;
+        };
+        loop {
+            let sessions = session_manager.list_sessions().await.len();
+            if self.is_complete(started, Instant::now(), sessions) {
+                if sessions > 0 {
+                    tracing::warn!(
+                        sessions,
+                        "Drain timeout reached with sessions still running"
+                    );
+                } else {
+                    tracing::info!("Drain complete: no sessions left");
+                }
+                return;
+            }
+            tokio::time::sleep(DRAIN_POLL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_is_idempotent_and_reported() {
+        let drain = DrainState::new(600);
+        assert!(!drain.is_draining());
+        assert_eq!(drain.status_json(3)["draining"], false);
+
+        assert!(drain.start());
+        assert!(!drain.start(), "second start is a no-op");
+        let status = drain.status_json(2);
+        assert_eq!(status["draining"], true);
+        assert_eq!(status["timeout_secs"], 600);
+        assert_eq!(status["sessions_remaining"], 2);
+    }
+
+    #[test]
+    fn completes_on_last_session_or_timeout() {
+        let start = Instant::now();
+        let drain = DrainState::new(600);
+        assert!(!drain.is_complete(start, start, 1));
+        assert!(drain.is_complete(start, start, 0));
+        assert!(drain.is_complete(start, start + Duration::from_secs(600), 1));
+
+        let unbounded = DrainState::new(0);
+        assert!(!unbounded.is_complete(start, start + Duration::from_secs(86_400), 1));
+        assert_eq!(unbounded.status_json(0)["draining"], false);
+    }
+}
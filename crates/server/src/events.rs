@@ -0,0 +1,169 @@
+//! Live feed of what happens on the server, for `GET /api/admin/events/ws`.
+//!
+//! Every session timeline entry is published here as it is recorded, along
+//! with server-wide alerts, so dashboards and the admin panel can follow
+//! along instead of polling `GET /api/admin/sessions`. Nothing is kept:
+//! a subscriber sees what happens after it subscribes.
+
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::ws::{Message, WebSocket};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::time::{Instant, interval};
+use uuid::Uuid;
+
+use crate::signaling::Keepalive;
+use crate::timeline::SessionEvent;
+
+/// Events a slow subscriber can fall behind by before it misses some
+const FEED_CAPACITY: usize = 1024;
+
+static FEED: LazyLock<broadcast::Sender<FeedEvent>> =
+    LazyLock::new(|| broadcast::channel(FEED_CAPACITY).0);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedEvent {
+    /// Unix time in milliseconds
+    pub at_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub kind: FeedKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum FeedKind {
+    Session(SessionEvent),
+    Alert(Alert),
+}
+
+/// Something an admin should look at, serialized as `{"event":"alert",...}`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename = "alert")]
+pub struct Alert {
+    /// Stable identifier, e.g. "ip_banned"
+    pub kind: &'static str,
+    pub message: String,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub fn subscribe() -> broadcast::Receiver<FeedEvent> {
+    FEED.subscribe()
+}
+
+/// Publish a session's timeline entry.
+pub fn session(session_id: Uuid, at_ms: u64, event: SessionEvent) {
+    let _ = FEED.send(FeedEvent {
+        at_ms,
+        session_id: Some(session_id),
+        kind: FeedKind::Session(event),
+    });
+}
+
+/// Publish an alert, about one session or the whole server.
+pub fn alert(kind: &'static str, session_id: Option<Uuid>, message: impl Into<String>) {
+    let _ = FEED.send(FeedEvent {
+        at_ms: now_ms(),
+        session_id,
+        kind: FeedKind::Alert(Alert {
+            kind,
+            message: message.into(),
+        }),
+    });
+}
+
+/// Send the feed to an admin's WebSocket as JSON text frames until it
+/// closes. A subscriber too slow to keep up gets `{"event":"lagged",...}`
+/// in place of what it missed, and should fetch the session list again.
+pub async fn stream(mut socket: WebSocket, keepalive: Keepalive) {
+    let mut feed = subscribe();
+    let mut ping_interval = interval(keepalive.ping_interval);
+    ping_interval.tick().await;
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(last_seen + keepalive.timeout) => break,
+            _ = ping_interval.tick() => {
+                if socket.send(Message::Ping(vec![].into())).await.is_err() {
+                    break;
+                }
+            }
+            result = feed.recv() => {
+                let json = match result {
+                    Ok(event) => serde_json::to_string(&event),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => serde_json::to_string(
+                        &serde_json::json!({ "at_ms": now_ms(), "event": "lagged", "missed": missed }),
+                    ),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let Ok(json) = json else {
+                    continue;
+                };
+                if socket.send(Message::Text(json.into())).await.is_err() {
+                    break;
+                }
+            }
+            // Admins only listen; anything they send just shows they're there
+            result = socket.recv() => match result {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => last_seen = Instant::now(),
+            },
+        }
+    }
+    tracing::debug!("Admin event stream closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeline::Peer;
+
+    #[test]
+    fn feed_events_serialize_flat() {
+        let id = Uuid::new_v4();
+        let connected = FeedEvent {
+            at_ms: 1000,
+            session_id: Some(id),
+            kind: FeedKind::Session(SessionEvent::Connected {
+                peer: Peer::Browser,
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(&connected).unwrap(),
+            serde_json::json!({
+                "at_ms": 1000,
+                "session_id": id,
+                "event": "connected",
+                "peer": "browser",
+            })
+        );
+
+        let banned = FeedEvent {
+            at_ms: 1000,
+            session_id: None,
+            kind: FeedKind::Alert(Alert {
+                kind: "ip_banned",
+                message: "192.0.2.1 banned for 5 minutes".to_string(),
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(&banned).unwrap(),
+            serde_json::json!({
+                "at_ms": 1000,
+                "event": "alert",
+                "kind": "ip_banned",
+                "message": "192.0.2.1 banned for 5 minutes",
+            })
+        );
+    }
+}
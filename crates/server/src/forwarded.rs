@@ -0,0 +1,196 @@
+//! Who a request came from: the client address behind trusted reverse
+//! proxies, and the user agent, for the login audit trail and rate limits.
+
+use std::net::IpAddr;
+
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use beam_protocol::{LoginClient, parse_ip_prefix};
+
+use crate::geoip::GeoIp;
+
+/// User agents are cut to this many characters
+const MAX_USER_AGENT_CHARS: usize = 256;
+
+/// Proxies allowed to name the client in `X-Forwarded-For`
+/// (`server.trusted_proxies`).
+#[derive(Default)]
+pub struct TrustedProxies(Vec<(IpAddr, u8)>);
+
+impl TrustedProxies {
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        entries
+            .iter()
+            .map(|entry| {
+                parse_ip_prefix(entry)
+                    .with_context(|| format!("Invalid trusted_proxies entry: {entry}"))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|&(net, len)| in_prefix(ip, net, len))
+    }
+}
+
+fn in_prefix(ip: IpAddr, net: IpAddr, len: u8) -> bool {
+    match (ip.to_canonical(), net.to_canonical()) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// The client's address: the TCP peer, or when that is a trusted proxy,
+/// the last `X-Forwarded-For` hop not added by a trusted proxy. Hops
+/// further left were written by the client itself and can't be believed.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &TrustedProxies) -> IpAddr {
+    let mut client = peer.to_canonical();
+    if !trusted.contains(client) {
+        return client;
+    }
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    for hop in hops.iter().rev() {
+        let Some(ip) = parse_hop(hop.trim()) else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !trusted.contains(client) {
+            break;
+        }
+    }
+    client
+}
+
+/// An `X-Forwarded-For` entry: an address, optionally with a port
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse()
+        .ok()
+        .or_else(|| hop.parse::<std::net::SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// The `User-Agent` header, shortened and stripped of control characters
+/// so it can't forge lines in a log.
+pub fn user_agent(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(axum::http::header::USER_AGENT)?;
+    let agent: String = String::from_utf8_lossy(value.as_bytes())
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_USER_AGENT_CHARS)
+        .collect();
+    (!agent.trim().is_empty()).then_some(agent)
+}
+
+/// Everything recorded about where a login came from.
+pub fn login_client(ip: IpAddr, headers: &HeaderMap, geoip: Option<&GeoIp>) -> LoginClient {
+    LoginClient {
+        ip: ip.to_string(),
+        user_agent: user_agent(headers),
+        country: geoip.and_then(|db| db.country(ip)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn trusted(entries: &[&str]) -> TrustedProxies {
+        TrustedProxies::parse(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let headers = forwarded(&["203.0.113.9"]);
+        assert_eq!(
+            client_ip(ip("198.51.100.1"), &headers, &TrustedProxies::default()),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            client_ip(ip("198.51.100.1"), &headers, &trusted(&["10.0.0.0/8"])),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_name_the_client() {
+        let proxies = trusted(&["10.0.0.0/8", "::1"]);
+        // A client-supplied spoof on the left is ignored
+        let headers = forwarded(&["1.2.3.4, 203.0.113.9", "10.0.0.2"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies),
+            ip("203.0.113.9")
+        );
+        let headers = forwarded(&["[2001:db8::7]:4711"]);
+        assert_eq!(client_ip(ip("::1"), &headers, &proxies), ip("2001:db8::7"));
+        // Mapped IPv4 peers match IPv4 ranges
+        let headers = forwarded(&["203.0.113.9:55000"]);
+        assert_eq!(
+            client_ip(ip("::ffff:10.1.1.1"), &headers, &proxies),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn trusted_proxy_without_usable_header_is_the_client() {
+        let proxies = trusted(&["10.0.0.1"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &HeaderMap::new(), &proxies),
+            ip("10.0.0.1")
+        );
+        let headers = forwarded(&["unknown"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn prefixes_match_by_length() {
+        assert!(in_prefix(ip("192.168.7.1"), ip("192.168.0.0"), 16));
+        assert!(!in_prefix(ip("192.169.0.1"), ip("192.168.0.0"), 16));
+        assert!(in_prefix(ip("8.8.8.8"), ip("0.0.0.0"), 0));
+        assert!(in_prefix(ip("2001:db8::1"), ip("2001:db8::"), 32));
+        assert!(!in_prefix(ip("2001:db9::1"), ip("2001:db8::"), 32));
+        assert!(!in_prefix(ip("10.0.0.1"), ip("::"), 0));
+    }
+
+    #[test]
+    fn user_agent_is_sanitized() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(user_agent(&headers), None);
+        headers.insert(
+            axum::http::header::USER_AGENT,
+            "Mozilla/5.0\tFake".parse().unwrap(),
+        );
+        assert_eq!(user_agent(&headers).as_deref(), Some("Mozilla/5.0Fake"));
+        headers.insert(
+            axum::http::header::USER_AGENT,
+            "x".repeat(1000).parse().unwrap(),
+        );
+        assert_eq!(user_agent(&headers).unwrap().len(), MAX_USER_AGENT_CHARS);
+    }
+}
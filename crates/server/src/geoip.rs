@@ -0,0 +1,415 @@
+//! Country lookups in a MaxMind DB file (GeoLite2 Country or City), for
+//! the login audit trail. Only the parts of the format a country lookup
+//! touches are implemented: the search tree and the data section decoder.
+//! See https://maxmind.github.io/MaxMind-DB/ for the layout.
+
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail, ensure};
+
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// Zero bytes between the search tree and the data section
+const DATA_SEPARATOR: usize = 16;
+/// Nesting allowed in a record; real databases use three or four levels
+const MAX_DEPTH: usize = 16;
+
+pub struct GeoIp {
+    data: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u16,
+    /// Node reached after the 96 zero bits of `::a.b.c.d` in an IPv6 tree
+    ipv4_start: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    String(String),
+    Uint(u128),
+    Int(i32),
+    Double(f64),
+    Bool(bool),
+    Bytes,
+    Map(Vec<(String, Value)>),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_usize(&self) -> Option<usize> {
+        match self {
+            Value::Uint(n) => usize::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl GeoIp {
+    pub fn open(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_bytes(data).with_context(|| format!("Invalid MaxMind DB {}", path.display()))
+    }
+
+    fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let marker = data
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .context("No metadata section")?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let (metadata, _) = Decoder {
+            section: &data[metadata_start..],
+        }
+        .decode(0, 0)?;
+        let field = |key| {
+            metadata
+                .get(key)
+                .and_then(Value::as_usize)
+                .with_context(|| format!("Metadata lacks {key}"))
+        };
+        let node_count = field("node_count")?;
+        let record_size = field("record_size")?;
+        let ip_version = field("ip_version")? as u16;
+        ensure!(
+            matches!(record_size, 24 | 28 | 32),
+            "Unsupported record size {record_size}"
+        );
+        ensure!(
+            matches!(ip_version, 4 | 6),
+            "Unsupported IP version {ip_version}"
+        );
+        let tree_size = node_count * record_size / 4;
+        ensure!(
+            tree_size + DATA_SEPARATOR <= marker,
+            "Search tree overruns the file"
+        );
+
+        let mut db = Self {
+            data,
+            node_count,
+            record_size,
+            ip_version,
+            ipv4_start: 0,
+        };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = db.record(node, false);
+            }
+            db.ipv4_start = node;
+        }
+        Ok(db)
+    }
+
+    /// ISO 3166 code of the country `ip` is in, falling back to the
+    /// country it is registered to (e.g. for anycast or satellite ranges).
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let record = self.lookup(ip).ok()??;
+        ["country", "registered_country"]
+            .iter()
+            .find_map(|key| record.get(key)?.get("iso_code")?.as_str())
+            .map(str::to_owned)
+    }
+
+    fn lookup(&self, ip: IpAddr) -> Result<Option<Value>> {
+        let (bits, start) = match ip.to_canonical() {
+            IpAddr::V4(v4) => (u128::from(u32::from(v4)) << 96, 32),
+            IpAddr::V6(_) if self.ip_version == 4 => return Ok(None),
+            IpAddr::V6(v6) => (u128::from(v6), 128),
+        };
+        let mut node = if start == 32 { self.ipv4_start } else { 0 };
+        for i in 0..start {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bits >> (127 - i)) & 1 == 1;
+            node = self.record(node, bit);
+        }
+        if node <= self.node_count {
+            // Equal means no data for this address
+            return Ok(None);
+        }
+        let tree_size = self.node_count * self.record_size / 4;
+        let offset = node - self.node_count - DATA_SEPARATOR;
+        let decoder = Decoder {
+            section: &self.data[tree_size + DATA_SEPARATOR..],
+        };
+        Ok(Some(decoder.decode(offset, 0)?.0))
+    }
+
+    fn record(&self, node: usize, right: bool) -> usize {
+        let bytes = self.record_size / 4;
+        let n = &self.data[node * bytes..(node + 1) * bytes];
+        let be = |b: &[u8]| b.iter().fold(0usize, |acc, &x| acc << 8 | usize::from(x));
+        match (self.record_size, right) {
+            (24, false) => be(&n[..3]),
+            (24, true) => be(&n[3..]),
+            (28, false) => usize::from(n[3] >> 4) << 24 | be(&n[..3]),
+            (28, true) => usize::from(n[3] & 0x0f) << 24 | be(&n[4..]),
+            (_, false) => be(&n[..4]),
+            (_, true) => be(&n[4..]),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    section: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.section
+            .get(offset..offset + len)
+            .context("Record runs past the data section")
+    }
+
+    /// Decode the value at `offset`; returns it and the offset after it.
+    fn decode(&self, offset: usize, depth: usize) -> Result<(Value, usize)> {
+        ensure!(depth <= MAX_DEPTH, "Record nested too deeply");
+        let ctrl = self.bytes(offset, 1)?[0];
+        let mut pos = offset + 1;
+        let mut kind = ctrl >> 5;
+        if kind == 1 {
+            let target = self.pointer(ctrl, &mut pos)?;
+            let (value, _) = self.decode(target, depth + 1)?;
+            return Ok((value, pos));
+        }
+        if kind == 0 {
+            kind = 7 + self.bytes(pos, 1)?[0];
+            pos += 1;
+        }
+        let size = match ctrl & 0x1f {
+            n @ 0..29 => usize::from(n),
+            29 => 29 + usize::from(self.bytes(pos, 1)?[0]),
+            30 => 285 + be_usize(self.bytes(pos, 2)?),
+            _ => 65_821 + be_usize(self.bytes(pos, 3)?),
+        };
+        pos += match ctrl & 0x1f {
+            29 => 1,
+            30 => 2,
+            31 => 3,
+            _ => 0,
+        };
+
+        let value = match kind {
+            2 => {
+                let text =
+                    std::str::from_utf8(self.bytes(pos, size)?).context("String is not UTF-8")?;
+                pos += size;
+                Value::String(text.to_owned())
+            }
+            3 => {
+                ensure!(size == 8, "Double of size {size}");
+                let b: [u8; 8] = self.bytes(pos, 8)?.try_into()?;
+                pos += 8;
+                Value::Double(f64::from_be_bytes(b))
+            }
+            15 => {
+                ensure!(size == 4, "Float of size {size}");
+                let b: [u8; 4] = self.bytes(pos, 4)?.try_into()?;
+                pos += 4;
+                Value::Double(f64::from(f32::from_be_bytes(b)))
+            }
+            4 => {
+                self.bytes(pos, size)?;
+                pos += size;
+                Value::Bytes
+            }
+            5 | 6 | 9 | 10 => {
+                ensure!(size <= 16, "Integer of size {size}");
+                let n = self
+                    .bytes(pos, size)?
+                    .iter()
+                    .fold(0u128, |acc, &x| acc << 8 | u128::from(x));
+                pos += size;
+                Value::Uint(n)
+            }
+            8 => {
+                ensure!(size <= 4, "int32 of size {size}");
+                let n = self
+                    .bytes(pos, size)?
+                    .iter()
+                    .fold(0u32, |acc, &x| acc << 8 | u32::from(x));
+                pos += size;
+                Value::Int(n as i32)
+            }
+            7 => {
+                let mut entries = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (key, next) = self.decode(pos, depth + 1)?;
+                    let Value::String(key) = key else {
+                        bail!("Map key is not a string");
+                    };
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    entries.push((key, value));
+                    pos = next;
+                }
+                Value::Map(entries)
+            }
+            11 => {
+                let mut items = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (item, next) = self.decode(pos, depth + 1)?;
+                    items.push(item);
+                    pos = next;
+                }
+                Value::Array(items)
+            }
+            14 => Value::Bool(size != 0),
+            other => bail!("Unexpected data type {other}"),
+        };
+        Ok((value, pos))
+    }
+
+    fn pointer(&self, ctrl: u8, pos: &mut usize) -> Result<usize> {
+        let high = usize::from(ctrl & 0x07);
+        let (len, base) = match (ctrl >> 3) & 0x03 {
+            0 => (1, 0),
+            1 => (2, 2048),
+            2 => (3, 526_336),
+            _ => (4, 0),
+        };
+        let low = be_usize(self.bytes(*pos, len)?);
+        *pos += len;
+        Ok(if len == 4 {
+            low
+        } else {
+            (high << (8 * len) | low) + base
+        })
+    }
+}
+
+fn be_usize(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |acc, &x| acc << 8 | usize::from(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut out = vec![0x40 | s.len() as u8];
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    /// A 24-bit tree with one prefix leading to `data[record]`, plus
+    /// metadata, laid out as a MaxMind DB writer would.
+    fn build(ip_version: u16, prefix: &[bool], data: &[u8], record: usize) -> Vec<u8> {
+        let node_count = prefix.len();
+        let mut out = Vec::new();
+        for (i, &bit) in prefix.iter().enumerate() {
+            let next = if i + 1 == node_count {
+                node_count + DATA_SEPARATOR + record
+            } else {
+                i + 1
+            };
+            let (left, right) = if bit {
+                (node_count, next)
+            } else {
+                (next, node_count)
+            };
+            out.extend_from_slice(&(left as u32).to_be_bytes()[1..]);
+            out.extend_from_slice(&(right as u32).to_be_bytes()[1..]);
+        }
+        out.extend_from_slice(&[0; DATA_SEPARATOR]);
+        out.extend_from_slice(data);
+        out.extend_from_slice(METADATA_MARKER);
+        out.push(0xe3);
+        out.extend(string("node_count"));
+        out.extend([0xc2, (node_count >> 8) as u8, node_count as u8]);
+        out.extend(string("record_size"));
+        out.extend([0xa1, 24]);
+        out.extend(string("ip_version"));
+        out.extend([0xa1, ip_version as u8]);
+        out
+    }
+
+    fn bits(value: u128, len: usize) -> Vec<bool> {
+        (0..len).map(|i| (value >> (127 - i)) & 1 == 1).collect()
+    }
+
+    /// `{"country": {"iso_code": "NO"}}`, the code reached via a pointer
+    fn norway() -> (Vec<u8>, usize) {
+        let mut data = string("NO");
+        let record = data.len();
+        data.push(0xe1);
+        data.extend(string("country"));
+        data.push(0xe1);
+        data.extend(string("iso_code"));
+        data.extend([0x20, 0x00]);
+        (data, record)
+    }
+
+    #[test]
+    fn ipv4_database_lookup() {
+        let (data, record) = norway();
+        // 10.0.0.0/8
+        let db = GeoIp::from_bytes(build(4, &bits(10 << 120, 8), &data, record)).unwrap();
+        assert_eq!(
+            db.country("10.1.2.3".parse().unwrap()).as_deref(),
+            Some("NO")
+        );
+        assert_eq!(db.country("11.1.2.3".parse().unwrap()), None);
+        assert_eq!(
+            db.country("::ffff:10.0.0.1".parse().unwrap()).as_deref(),
+            Some("NO")
+        );
+        assert_eq!(db.country("2001:db8::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn ipv6_database_maps_ipv4_into_its_tree() {
+        let (data, record) = norway();
+        // ::10.0.0.0/104, where IPv4 lives in an IPv6 tree
+        let db = GeoIp::from_bytes(build(6, &bits(10 << 24, 104), &data, record)).unwrap();
+        assert_eq!(
+            db.country("10.9.9.9".parse().unwrap()).as_deref(),
+            Some("NO")
+        );
+        assert_eq!(db.country("192.0.2.1".parse().unwrap()), None);
+        assert_eq!(db.country("2001:db8::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn registered_country_is_the_fallback() {
+        let mut data = vec![0xe1];
+        data.extend(string("registered_country"));
+        data.push(0xe2);
+        data.extend(string("geoname_id"));
+        data.extend([0xc2, 0x0b, 0xb8]);
+        data.extend(string("iso_code"));
+        data.extend(string("SE"));
+        let db = GeoIp::from_bytes(build(4, &bits(10 << 120, 8), &data, 0)).unwrap();
+        assert_eq!(
+            db.country("10.0.0.1".parse().unwrap()).as_deref(),
+            Some("SE")
+        );
+    }
+
+    #[test]
+    fn rejects_files_without_metadata() {
+        assert!(GeoIp::from_bytes(vec![0; 64]).is_err());
+        let (data, record) = norway();
+        let mut truncated = build(4, &bits(10 << 120, 8), &data, record);
+        truncated.drain(..40);
+        assert!(GeoIp::from_bytes(truncated).is_err());
+    }
+}
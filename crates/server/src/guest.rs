@@ -0,0 +1,391 @@
+//! Guest sessions on throwaway accounts (`[guest]`), for workshops and
+//! demo kiosks.
+//!
+//! `POST /api/guest` creates a system user named `user_prefix` plus random
+//! hex digits, starts a session for it and schedules the session to end
+//! after `lifetime_secs`, as an admin's `DELETE ?after=N` would, but with
+//! no way to call it off. Every guest is written to `GUESTS_FILE` before
+//! its account exists, so none is forgotten across a crash or restart:
+//! `collect` deletes the account and home of every guest whose session is
+//! gone, whether it ran out, was ended early or never started. Only
+//! accounts carrying the comment useradd gave them are deleted, so a name
+//! that was already taken never costs its owner their home.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::signaling;
+use crate::termination;
+use crate::web::AppState;
+
+pub const GUESTS_FILE: &str = "/var/lib/beam/guests.json";
+
+/// How often ended guests are looked for
+pub const COLLECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Who a guest's countdown is from, as the browser and desktop show it
+pub const SCHEDULED_BY: &str = "Guest access";
+
+/// Comment of guest accounts; no other account is ever deleted
+const ACCOUNT_COMMENT: &str = "Beam guest";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Guest {
+    username: String,
+    /// None until the session is started
+    #[serde(default)]
+    session_id: Option<Uuid>,
+    /// Unix time the session ends
+    ends_at: u64,
+}
+
+/// What a pass of `collect` does with the guests
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Sweep {
+    /// Accounts to delete: their session is gone
+    ended: Vec<String>,
+    /// Live sessions and the unix time each ends
+    running: Vec<(Uuid, u64)>,
+}
+
+pub struct Guests {
+    path: PathBuf,
+    guests: Mutex<Vec<Guest>>,
+    /// Guests whose account or session this process is still creating;
+    /// any other without a session was left half made
+    creating: Mutex<HashSet<String>>,
+}
+
+impl Guests {
+    /// The guests saved at `path`; none if there is no file or it can't
+    /// be read.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let guests = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), "Ignoring unreadable guest list: {e}");
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), "Failed to read guest list: {e}");
+                Vec::new()
+            }
+        };
+        Self {
+            path,
+            guests: Mutex::new(guests),
+            creating: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Pick a name for a new guest ending `lifetime` from now and record
+    /// it. None if `max` guests already exist.
+    pub fn reserve(&self, prefix: &str, max: u32, lifetime: Duration) -> Result<Option<String>> {
+        let mut guests = self.guests.lock().unwrap_or_else(|e| e.into_inner());
+        if guests.len() >= max as usize {
+            return Ok(None);
+        }
+        let username = loop {
+            let name = new_username(prefix);
+            if !guests.iter().any(|g| g.username == name) {
+                break name;
+            }
+        };
+        guests.push(Guest {
+            username: username.clone(),
+            session_id: None,
+            ends_at: unix_now() + lifetime.as_secs(),
+        });
+        self.creating
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(username.clone());
+        if let Err(e) = self.save(&guests) {
+            guests.pop();
+            self.finish(&username);
+            return Err(e);
+        }
+        Ok(Some(username))
+    }
+
+    /// Record the session started for `username`.
+    pub fn started(&self, username: &str, session_id: Uuid) -> Result<()> {
+        let mut guests = self.guests.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(guest) = guests.iter_mut().find(|g| g.username == username) {
+            guest.session_id = Some(session_id);
+        }
+        self.finish(username);
+        self.save(&guests)
+    }
+
+    /// Give up creating `username`; the next `collect` deletes whatever
+    /// of the account was made.
+    pub fn abandon(&self, username: &str) {
+        self.finish(username);
+    }
+
+    /// Drop `username` after useradd failed, leaving alone any account of
+    /// that name, which is someone else's.
+    pub fn withdraw(&self, username: &str) -> Result<()> {
+        self.finish(username);
+        self.forget(username)
+    }
+
+    /// Whether `session_id` is a guest's session
+    pub fn is_guest_session(&self, session_id: Uuid) -> bool {
+        self.guests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .any(|g| g.session_id == Some(session_id))
+    }
+
+    fn finish(&self, username: &str) {
+        self.creating
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(username);
+    }
+
+    fn sweep(&self, live: &HashSet<Uuid>) -> Sweep {
+        let guests = self.guests.lock().unwrap_or_else(|e| e.into_inner());
+        let creating = self.creating.lock().unwrap_or_else(|e| e.into_inner());
+        sweep(&guests, &creating, live)
+    }
+
+    /// Drop `username` once its account is deleted.
+    fn forget(&self, username: &str) -> Result<()> {
+        let mut guests = self.guests.lock().unwrap_or_else(|e| e.into_inner());
+        guests.retain(|g| g.username != username);
+        self.save(&guests)
+    }
+
+    fn save(&self, guests: &[Guest]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(guests)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+fn sweep(guests: &[Guest], creating: &HashSet<String>, live: &HashSet<Uuid>) -> Sweep {
+    let mut sweep = Sweep::default();
+    for guest in guests {
+        match guest.session_id {
+            Some(id) if live.contains(&id) => sweep.running.push((id, guest.ends_at)),
+            None if creating.contains(&guest.username) => {}
+            _ => sweep.ended.push(guest.username.clone()),
+        }
+    }
+    sweep
+}
+
+fn new_username(prefix: &str) -> String {
+    let random = Uuid::new_v4().simple().to_string();
+    format!(
+        "{prefix}{}",
+        &random[..beam_protocol::GuestConfig::SUFFIX_LEN]
+    )
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Create the system user `username`, with a home from /etc/skel.
+pub fn add_user(username: &str) -> Result<()> {
+    let output = Command::new("useradd")
+        .args([
+            "--create-home",
+            "--user-group",
+            "--shell",
+            "/bin/bash",
+            "--comment",
+            ACCOUNT_COMMENT,
+            username,
+        ])
+        .output()
+        .context("Failed to run useradd")?;
+    if !output.status.success() {
+        bail!(
+            "useradd {username} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Delete the guest account `username` with its home, first killing
+/// whatever it still runs, which would keep userdel from removing it.
+/// False if there was no guest account to delete: none of that name, or
+/// one useradd didn't make for a guest.
+fn remove_user(username: &str) -> Result<bool> {
+    let user = nix::unistd::User::from_name(username)
+        .with_context(|| format!("Failed to look up {username}"))?;
+    match user {
+        None => return Ok(false),
+        Some(user) if user.gecos.to_str() != Ok(ACCOUNT_COMMENT) => {
+            tracing::warn!(%username, "Not deleting an account that isn't a guest's");
+            return Ok(false);
+        }
+        Some(_) => {}
+    }
+    let _ = Command::new("pkill")
+        .args(["-KILL", "-u", username])
+        .status();
+    let output = Command::new("userdel")
+        .args(["--remove", username])
+        .output()
+        .context("Failed to run userdel")?;
+    // 6: no such user, deleted since the lookup
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(6) => Ok(false),
+        _ => bail!(
+            "userdel {username} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+/// End the sessions of guests whose time is up and delete the accounts of
+/// guests whose session is gone. A session that outlived a server restart
+/// gets its countdown back.
+pub async fn collect(state: &Arc<AppState>) {
+    let live: HashSet<Uuid> = state
+        .session_manager
+        .list_sessions()
+        .await
+        .into_iter()
+        .map(|s| s.id)
+        .collect();
+    let Sweep { ended, running } = state.guests.sweep(&live);
+
+    let now = unix_now();
+    for (session_id, ends_at) in running {
+        let counting_down = match state.channels.read().await.get(&session_id) {
+            Some(channel) => channel
+                .termination
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_some(),
+            None => false,
+        };
+        if ends_at > now {
+            if !counting_down {
+                termination::schedule(
+                    state,
+                    session_id,
+                    Duration::from_secs(ends_at - now),
+                    SCHEDULED_BY,
+                )
+                .await;
+            }
+            continue;
+        }
+        // Also when an admin pushed the end back
+        tracing::info!(%session_id, "Guest session ran out");
+        if let Err(e) = state.session_manager.destroy_session(session_id).await {
+            tracing::warn!(%session_id, "Failed to end guest session: {e:#}");
+        }
+        signaling::remove_channel(&state.channels, session_id).await;
+    }
+
+    for username in ended {
+        let name = username.clone();
+        match tokio::task::spawn_blocking(move || remove_user(&name)).await {
+            Ok(Ok(removed)) => {
+                if removed {
+                    tracing::info!(target: "audit", event = "guest_removed", %username, "Guest account deleted");
+                }
+                if let Err(e) = state.guests.forget(&username) {
+                    tracing::warn!(%username, "Failed to update the guest list: {e:#}");
+                }
+            }
+            Ok(Err(e)) => tracing::warn!(%username, "Failed to delete guest account: {e:#}"),
+            Err(e) => tracing::error!("Guest removal task panicked: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guest_names_are_valid_and_distinct() {
+        let a = new_username("beam-guest-");
+        let b = new_username("beam-guest-");
+        assert_ne!(a, b);
+        assert_eq!(a.len(), "beam-guest-".len() + 8);
+        assert!(a.starts_with("beam-guest-"));
+        assert!(crate::web::is_valid_username(&a));
+    }
+
+    #[test]
+    fn guests_are_kept_until_their_account_is_deleted() {
+        let path =
+            std::env::temp_dir().join(format!("beam-guest-list-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let guests = Guests::load(&path);
+        let lifetime = Duration::from_secs(600);
+        let alice = guests.reserve("g-", 2, lifetime).unwrap().unwrap();
+        let bob = guests.reserve("g-", 2, lifetime).unwrap().unwrap();
+        assert_eq!(guests.reserve("g-", 2, lifetime).unwrap(), None);
+
+        // Still being created: left alone
+        assert_eq!(guests.sweep(&HashSet::new()), Sweep::default());
+
+        let carol = guests.reserve("g-", 3, lifetime).unwrap().unwrap();
+        // useradd failed: never swept, so its account is never deleted
+        guests.withdraw(&carol).unwrap();
+
+        let session = Uuid::new_v4();
+        guests.started(&alice, session).unwrap();
+        guests.abandon(&bob);
+        assert!(guests.is_guest_session(session));
+        let sweep = guests.sweep(&HashSet::from([session]));
+        assert_eq!(sweep.ended, vec![bob.clone()]);
+        assert_eq!(sweep.running.len(), 1);
+        assert_eq!(sweep.running[0].0, session);
+
+        // After a restart nothing is being created, and alice's session
+        // is gone too
+        let reloaded = Guests::load(&path);
+        assert!(reloaded.is_guest_session(session));
+        assert_eq!(
+            reloaded.sweep(&HashSet::new()).ended,
+            vec![alice.clone(), bob.clone()]
+        );
+        reloaded.forget(&bob).unwrap();
+        assert_eq!(
+            Guests::load(&path).sweep(&HashSet::new()).ended,
+            vec![alice]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_guest_accounts_are_deleted() {
+        assert!(!remove_user("root").unwrap());
+        assert!(!remove_user("beam-guest-00000000").unwrap());
+    }
+}
@@ -0,0 +1,140 @@
+//! Home directories for users whose accounts come from elsewhere (LDAP on
+//! a stateless host) and have none yet (`session.create_home`).
+//!
+//! pam_mkhomedir does this when the PAM session stack has it; this covers
+//! hosts without it, and users-file logins of system users. The new home
+//! is a copy of /etc/skel owned by the user. Beam's own desktop settings
+//! are seeded by the agent, which keeps them under `~/.config/beam`.
+
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+const SKEL: &str = "/etc/skel";
+
+/// Homes that mean "none", as system accounts have
+const NO_HOME: [&str; 3] = ["/", "/nonexistent", "/dev/null"];
+
+/// Create `home` from /etc/skel, owned by `uid`:`gid`, if it doesn't
+/// exist. True if it was created.
+pub fn provision(home: &Path, uid: u32, gid: u32) -> Result<bool> {
+    provision_from(Path::new(SKEL), home, uid, gid)
+}
+
+fn provision_from(skel: &Path, home: &Path, uid: u32, gid: u32) -> Result<bool> {
+    if !home.is_absolute()
+        || NO_HOME.iter().any(|none| home == Path::new(none))
+        || home.symlink_metadata().is_ok()
+    {
+        return Ok(false);
+    }
+    if let Some(parent) = home.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(home)
+        .with_context(|| format!("Failed to create home directory {}", home.display()))?;
+    // Explicitly, since the server's umask may be stricter
+    std::fs::set_permissions(home, std::fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("Failed to set the mode of {}", home.display()))?;
+    if skel.is_dir() {
+        copy_tree(skel, home, uid, gid)?;
+    }
+    chown(home, uid, gid)?;
+    Ok(true)
+}
+
+/// Copy the contents of `from` into `to`, keeping modes and symlinks and
+/// giving everything to `uid`:`gid`.
+fn copy_tree(from: &Path, to: &Path, uid: u32, gid: u32) -> Result<()> {
+    let entries =
+        std::fs::read_dir(from).with_context(|| format!("Failed to read {}", from.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", from.display()))?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let meta = entry
+            .metadata()
+            .with_context(|| format!("Failed to stat {}", source.display()))?;
+        if meta.is_dir() {
+            std::fs::create_dir(&target)
+                .and_then(|()| std::fs::set_permissions(&target, meta.permissions()))
+                .with_context(|| format!("Failed to create {}", target.display()))?;
+            copy_tree(&source, &target, uid, gid)?;
+        } else if meta.is_symlink() {
+            let link = std::fs::read_link(&source)
+                .with_context(|| format!("Failed to read link {}", source.display()))?;
+            std::os::unix::fs::symlink(&link, &target)
+                .with_context(|| format!("Failed to create {}", target.display()))?;
+        } else if meta.is_file() {
+            // Copies the mode too
+            std::fs::copy(&source, &target)
+                .with_context(|| format!("Failed to copy {}", source.display()))?;
+        } else {
+            // Sockets and devices have no place in a skeleton
+            continue;
+        }
+        chown(&target, uid, gid)?;
+    }
+    Ok(())
+}
+
+fn chown(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+        .with_context(|| format!("Failed to give {} to uid {uid}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn homes_are_copied_from_the_skeleton_once() {
+        let dir = std::env::temp_dir().join(format!("beam-home-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let skel = dir.join("skel");
+        std::fs::create_dir_all(skel.join(".config/autostart")).unwrap();
+        std::fs::write(skel.join(".bashrc"), "alias ll='ls -l'\n").unwrap();
+        std::fs::write(
+            skel.join(".config/autostart/x.desktop"),
+            "[Desktop Entry]\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(skel.join(".bashrc"), std::fs::Permissions::from_mode(0o640))
+            .unwrap();
+        std::os::unix::fs::symlink(".bashrc", skel.join(".profile")).unwrap();
+
+        let uid = nix::unistd::getuid().as_raw();
+        let gid = nix::unistd::getgid().as_raw();
+        let home = dir.join("home/alice");
+        assert!(provision_from(&skel, &home, uid, gid).unwrap());
+
+        let meta = std::fs::metadata(&home).unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o700);
+        assert_eq!(meta.uid(), uid);
+        let bashrc = home.join(".bashrc");
+        assert_eq!(
+            std::fs::read_to_string(&bashrc).unwrap(),
+            "alias ll='ls -l'\n"
+        );
+        assert_eq!(std::fs::metadata(&bashrc).unwrap().mode() & 0o777, 0o640);
+        assert!(home.join(".config/autostart/x.desktop").is_file());
+        assert_eq!(
+            std::fs::read_link(home.join(".profile")).unwrap(),
+            Path::new(".bashrc")
+        );
+
+        assert!(!provision_from(&skel, Path::new("/nonexistent"), uid, gid).unwrap());
+        assert!(!Path::new("/nonexistent").exists());
+
+        // An existing home is left as it is
+        std::fs::remove_file(&bashrc).unwrap();
+        assert!(!provision_from(&skel, &home, uid, gid).unwrap());
+        assert!(!bashrc.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,28 +1,70 @@
+mod agent_logs;
+mod assist;
 mod auth;
+mod bandwidth;
+mod bans;
 mod config;
+mod drain;
+mod events;
+mod forwarded;
+mod geoip;
+mod guest;
+mod home;
+mod orphans;
+mod pam_session;
+mod preferences;
 mod session;
+mod setup;
+mod share;
 mod signaling;
+mod stats_history;
+mod termination;
+mod timeline;
 mod tls;
+mod users;
 mod web;
 
+use std::collections::HashSet;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
+use beam_doctor::{Report, Status};
+use beam_protocol::sd_notify;
 use tokio::net::TcpListener;
 use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 use tracing::Level;
 use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
 
 use crate::session::SessionManager;
+use crate::timeline::SessionEvent;
 use crate::web::AppState;
 
-fn parse_args() -> (PathBuf, Option<u16>) {
+struct Args {
+    config_path: PathBuf,
+    port_override: Option<u16>,
+    /// `check-config`: validate and exit instead of serving
+    check_config: bool,
+    /// `users ...`: manage `auth.users_file` and exit
+    users: Option<Vec<String>>,
+    password_stdin: bool,
+    /// `pam-session <USER>`: internal, holds an agent's PAM session open
+    pam_session: Option<String>,
+}
+
+fn parse_args() -> Args {
     let args: Vec<String> = std::env::args().collect();
-    let mut config_path = PathBuf::from("./config/beam.toml");
-    let mut port_override = None;
+    let mut parsed = Args {
+        config_path: PathBuf::from("./config/beam.toml"),
+        port_override: None,
+        check_config: false,
+        users: None,
+        password_stdin: false,
+        pam_session: None,
+    };
 
     let mut i = 1;
     while i < args.len() {
@@ -36,26 +78,48 @@ fn parse_args() -> (PathBuf, Option<u16>) {
                 println!();
                 println!("USAGE:");
                 println!("    beam-server [OPTIONS]");
+                println!("    beam-server check-config [-c <PATH>]");
+                println!("    beam-server users [list | add <NAME> | remove <NAME>] [-c <PATH>]");
+                println!();
+                println!("COMMANDS:");
+                println!(
+                    "    check-config           Validate the config, TLS files, PAM service and"
+                );
+                println!("                           web root, print the results as JSON and exit");
+                println!("                           non-zero on errors");
+                println!(
+                    "    users                  List, add (or change the password of) and remove"
+                );
+                println!("                           users in auth.users_file");
                 println!();
                 println!("OPTIONS:");
                 println!(
                     "    -c, --config <PATH>    Configuration file [default: ./config/beam.toml]"
                 );
                 println!("    -p, --port <PORT>      Override server port");
+                println!("    --password-stdin       Read the new user's password from stdin");
                 println!("    -V, --version          Print version and exit");
                 println!("    -h, --help             Print this help and exit");
                 std::process::exit(0);
             }
-            "--config" | "-c" => {
-                if i + 1 < args.len() {
-                    config_path = PathBuf::from(&args[i + 1]);
-                    i += 1;
-                }
+            "--config" | "-c" if i + 1 < args.len() => {
+                parsed.config_path = PathBuf::from(&args[i + 1]);
+                i += 1;
             }
-            "--port" | "-p" => {
-                if i + 1 < args.len() {
-                    port_override = args[i + 1].parse().ok();
-                    i += 1;
+            "--port" | "-p" if i + 1 < args.len() => {
+                parsed.port_override = args[i + 1].parse().ok();
+                i += 1;
+            }
+            "check-config" => parsed.check_config = true,
+            pam_session::HELPER_ARG if i + 1 < args.len() => {
+                parsed.pam_session = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "users" if parsed.users.is_none() => parsed.users = Some(Vec::new()),
+            "--password-stdin" => parsed.password_stdin = true,
+            other if !other.starts_with('-') => {
+                if let Some(users) = &mut parsed.users {
+                    users.push(other.to_string());
                 }
             }
             _ => {}
@@ -63,11 +127,60 @@ fn parse_args() -> (PathBuf, Option<u16>) {
         i += 1;
     }
 
-    (config_path, port_override)
+    parsed
+}
+
+/// `beam-server check-config`: the config checks the server makes at
+/// startup plus the files it will need, as a JSON report, so a config
+/// change can be gated before the restart that would trip over it. Exits
+/// 1 if any check failed.
+fn check_config(config_path: &Path) -> ! {
+    let mut report = Report::default();
+    let config = beam_doctor::check_config_file(&mut report, config_path);
+    report
+        .checks
+        .extend(beam_doctor::run_config(&config).checks);
+    println!("{:#}", report.to_json());
+    std::process::exit(i32::from(report.status() == Status::Fail));
+}
+
+/// `beam-server users`: edit the users file the config names.
+fn users_command(config_path: &Path, args: &[String], password_stdin: bool) -> ! {
+    let text = std::fs::read_to_string(config_path).unwrap_or_default();
+    let users_file = match beam_protocol::BeamConfig::from_toml_and_env(&text) {
+        Ok((config, _)) => config.auth.users_file,
+        Err(e) => {
+            eprintln!("beam-server: {}: {e}", config_path.display());
+            std::process::exit(1);
+        }
+    };
+    let Some(users_file) = users_file else {
+        eprintln!(
+            "beam-server: set users_file under [auth] in {} first",
+            config_path.display()
+        );
+        std::process::exit(1);
+    };
+    if let Err(e) = users::command(Path::new(&users_file), args, password_stdin) {
+        eprintln!("beam-server: {e:#}");
+        std::process::exit(1);
+    }
+    std::process::exit(0);
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = parse_args();
+    if let Some(username) = &args.pam_session {
+        pam_session::helper(username);
+    }
+    if args.check_config {
+        check_config(&args.config_path);
+    }
+    if let Some(users) = &args.users {
+        users_command(&args.config_path, users, args.password_stdin);
+    }
+
     // Install rustls crypto provider
     rustls::crypto::ring::default_provider()
         .install_default()
@@ -80,11 +193,20 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    let (config_path, port_override) = parse_args();
+    // Without a config, one is written through the setup API first, unless
+    // the environment configures the server (as in a container)
+    let env_configured = std::env::vars_os()
+        .any(|(name, _)| name.to_str().is_some_and(beam_protocol::is_env_override));
+    if !args.config_path.exists()
+        && !env_configured
+        && !setup::run(&args.config_path, args.port_override).await?
+    {
+        return Ok(());
+    }
 
     // Load configuration
-    let mut config = config::load_config(&config_path)?;
-    if let Some(p) = port_override {
+    let mut config = config::load_config(&args.config_path)?;
+    if let Some(p) = args.port_override {
         config.server.port = p;
     }
     // Validate configuration semantics
@@ -172,9 +294,28 @@ async fn main() -> Result<()> {
         config.session.default_height,
         Some(tls_cert_path),
         config.video.clone(),
-    );
+        config.audio.clone(),
+        config.session.clone(),
+    )
+    .with_agent_config(config.agent.clone())
+    .with_assist_config(config.assist.clone())
+    .with_logging_config(config.logging.clone())
+    .with_auth_config(config.auth.clone());
+
+    // Login audit: client address behind proxies, country lookups
+    let trusted_proxies = forwarded::TrustedProxies::parse(&config.server.trusted_proxies)?;
+    let geoip = match &config.server.geoip_db {
+        Some(path) => {
+            let db = geoip::GeoIp::open(std::path::Path::new(path))?;
+            tracing::info!(path, "Loaded GeoIP database");
+            Some(db)
+        }
+        None => None,
+    };
 
     // Build app state and router
+    let drain = drain::DrainState::new(config.server.drain_timeout);
+    let ip_bans = bans::IpBans::new(config.server.ip_ban_secs, config.server.ip_ban_max_secs);
     let state = Arc::new(AppState {
         config,
         session_manager,
@@ -183,16 +324,28 @@ async fn main() -> Result<()> {
         login_limiter: web::LoginRateLimiter::new(5, 60), // 5 attempts per username per 60s
         ip_limiter: web::LoginRateLimiter::new(20, 60),   // 20 attempts per IP per 60s
         release_limiter: web::LoginRateLimiter::new(10, 60), // 10 release attempts per IP per 60s
+        nettest_limiter: web::LoginRateLimiter::new(12, 60), // 3 bandwidth probes per user per 60s
+        ip_bans,
         started_at: std::time::Instant::now(),
+        drain,
         metrics_logins_attempted: std::sync::atomic::AtomicU64::new(0),
         metrics_logins_failed: std::sync::atomic::AtomicU64::new(0),
         metrics_agent_restarts: std::sync::atomic::AtomicU64::new(0),
+        trusted_proxies,
+        geoip,
+        preferences: preferences::Preferences::new(preferences::PREFERENCES_DIR),
+        guests: guest::Guests::load(guest::GUESTS_FILE),
     });
 
     // Restore sessions from previous graceful shutdown
     let restored = state.session_manager.restore_sessions().await;
     for (session_id, pid) in &restored {
-        signaling::get_or_create_channel(&state.channels, *session_id).await;
+        let channel = signaling::get_or_create_channel(&state.channels, *session_id).await;
+        channel.timeline.record(SessionEvent::Restored);
+        channel.private.store(
+            state.session_manager.is_private(*session_id).await,
+            std::sync::atomic::Ordering::Relaxed,
+        );
         web::spawn_orphan_agent_monitor(Arc::clone(&state), *session_id, *pid).await;
     }
     if !restored.is_empty() {
@@ -202,6 +355,16 @@ async fn main() -> Result<()> {
         );
     }
 
+    // Agents a crash left behind: adopt what can be, stop the rest
+    let adopted = state.session_manager.reconcile_agents().await;
+    for (session_id, pid) in &adopted {
+        signaling::get_or_create_channel(&state.channels, *session_id)
+            .await
+            .timeline
+            .record(SessionEvent::Adopted);
+        web::spawn_orphan_agent_monitor(Arc::clone(&state), *session_id, *pid).await;
+    }
+
     if state.config.server.admin_users.is_empty() {
         tracing::info!("Admin panel disabled (no admin_users configured in beam.toml)");
     }
@@ -241,9 +404,6 @@ async fn main() -> Result<()> {
         )
         .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
 
-    // Clean up old agent logs on startup (keep last 20, remove >24h old)
-    cleanup_old_agent_logs(24 * 3600, 20);
-
     // Print startup banner
     tracing::info!("===========================================");
     tracing::info!(
@@ -259,6 +419,7 @@ async fn main() -> Result<()> {
         .with_context(|| format!("Failed to bind to {bind_addr}"))?;
 
     tracing::info!("Server ready, accepting connections");
+    sd_notify::ready(&format!("Accepting connections on {bind_addr}"));
 
     // Background task: reap stale sessions (configurable idle timeout)
     let idle_timeout = state.config.session.idle_timeout;
@@ -288,9 +449,67 @@ async fn main() -> Result<()> {
         tracing::info!("Session idle timeout disabled (idle_timeout = 0)");
     }
 
+    // Background task: return display numbers the pool lost track of
+    let display_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            display_state.session_manager.reconcile_displays().await;
+        }
+    });
+
+    // Background task: rotate agent logs and remove those of long-ended sessions
+    let log_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        loop {
+            let active: HashSet<Uuid> = log_state
+                .session_manager
+                .list_sessions()
+                .await
+                .into_iter()
+                .map(|session| session.id)
+                .collect();
+            let logging = log_state.config.logging.clone();
+            let _ =
+                tokio::task::spawn_blocking(move || agent_logs::maintain(&logging, &active)).await;
+            tokio::time::sleep(agent_logs::MAINTAIN_INTERVAL).await;
+        }
+    });
+
+    // Background task: end guest sessions that ran out and delete the
+    // accounts of ended ones, also those left from before [guest] was
+    // turned off
+    let guest_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        loop {
+            guest::collect(&guest_state).await;
+            tokio::time::sleep(guest::COLLECT_INTERVAL).await;
+        }
+    });
+
+    // Background task: share host_bandwidth_kbps out as sessions come and go
+    if state.config.session.host_bandwidth_kbps > 0 {
+        let bandwidth_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            loop {
+                bandwidth::rebalance(&bandwidth_state.session_manager, &bandwidth_state.channels)
+                    .await;
+                tokio::time::sleep(bandwidth::REBALANCE_INTERVAL).await;
+            }
+        });
+    }
+
     // Set up graceful shutdown
     let shutdown_state = Arc::clone(&state);
     let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    let mut sigusr1 =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())?;
+    let drain_complete = shutdown_state
+        .drain
+        .wait_complete(&shutdown_state.session_manager);
+    tokio::pin!(drain_complete);
+    // Keepalives come from the accept loop, so a wedged loop gets restarted
+    let mut sd_watchdog = sd_notify::watchdog_interval().map(tokio::time::interval);
 
     // Accept TLS connections and serve with axum
     loop {
@@ -304,39 +523,7 @@ async fn main() -> Result<()> {
                     }
                 };
 
-                let acceptor = tls_acceptor.clone();
-                let app = app.clone();
-
-                tokio::spawn(async move {
-                    // TLS handshake timeout (10 seconds)
-                    let tls_stream = match tokio::time::timeout(
-                        std::time::Duration::from_secs(10),
-                        acceptor.accept(stream),
-                    ).await {
-                        Ok(Ok(s)) => s,
-                        Ok(Err(e)) => {
-                            tracing::debug!(%peer_addr, "TLS handshake failed: {e}");
-                            return;
-                        }
-                        Err(_) => {
-                            tracing::debug!(%peer_addr, "TLS handshake timed out");
-                            return;
-                        }
-                    };
-
-                    // Inject peer address so handlers can extract client IP
-                    let app_with_peer = app.layer(axum::Extension(peer_addr));
-
-                    let io = hyper_util::rt::TokioIo::new(tls_stream);
-                    let hyper_service = hyper_util::service::TowerToHyperService::new(app_with_peer);
-                    let builder = hyper_util::server::conn::auto::Builder::new(
-                        hyper_util::rt::TokioExecutor::new(),
-                    );
-
-                    if let Err(e) = builder.serve_connection_with_upgrades(io, hyper_service).await {
-                        tracing::debug!(%peer_addr, "Connection error: {e}");
-                    }
-                });
+                serve_connection(tls_acceptor.clone(), app.clone(), stream, peer_addr);
             }
             _ = tokio::signal::ctrl_c() => {
                 tracing::info!("Received SIGINT, initiating graceful shutdown");
@@ -346,8 +533,21 @@ async fn main() -> Result<()> {
                 tracing::info!("Received SIGTERM, initiating graceful shutdown");
                 break;
             }
+            _ = sigusr1.recv() => {
+                if shutdown_state.drain.start() {
+                    tracing::info!(target: "audit", event = "drain_started", "Received SIGUSR1, draining sessions");
+                }
+            }
+            _ = &mut drain_complete => {
+                tracing::info!("Drain finished, shutting down");
+                break;
+            }
+            _ = async { sd_watchdog.as_mut().unwrap().tick().await }, if sd_watchdog.is_some() => {
+                sd_notify::watchdog();
+            }
         }
     }
+    sd_notify::stopping();
 
     // Graceful shutdown: persist sessions so agents survive the restart
     tracing::info!("Persisting sessions for graceful restart...");
@@ -369,36 +569,44 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-/// Remove old agent logs from /var/log/beam/, keeping at most `max_count`
-/// and removing any older than `max_age_secs`.
-fn cleanup_old_agent_logs(max_age_secs: u64, max_count: usize) {
-    let dir = std::path::Path::new("/var/log/beam");
-    let _ = std::fs::create_dir_all(dir);
-    let Ok(entries) = std::fs::read_dir(dir) else {
-        return;
-    };
+/// Finish the TLS handshake with a new client and serve it `app`, in a
+/// task of its own.
+fn serve_connection(
+    acceptor: tokio_rustls::TlsAcceptor,
+    app: axum::Router,
+    stream: tokio::net::TcpStream,
+    peer_addr: SocketAddr,
+) {
+    tokio::spawn(async move {
+        // TLS handshake timeout (10 seconds)
+        let tls_stream =
+            match tokio::time::timeout(std::time::Duration::from_secs(10), acceptor.accept(stream))
+                .await
+            {
+                Ok(Ok(s)) => s,
+                Ok(Err(e)) => {
+                    tracing::debug!(%peer_addr, "TLS handshake failed: {e}");
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!(%peer_addr, "TLS handshake timed out");
+                    return;
+                }
+            };
 
-    let now = std::time::SystemTime::now();
-    let mut logs: Vec<(std::path::PathBuf, std::time::SystemTime)> = entries
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            e.file_name()
-                .to_str()
-                .is_some_and(|n| n.starts_with("agent-") && n.ends_with(".log"))
-        })
-        .filter_map(|e| {
-            let mtime = e.metadata().ok()?.modified().ok()?;
-            Some((e.path(), mtime))
-        })
-        .collect();
-
-    // Sort newest first
-    logs.sort_by(|a, b| b.1.cmp(&a.1));
-
-    for (i, (path, mtime)) in logs.iter().enumerate() {
-        let age = now.duration_since(*mtime).unwrap_or_default().as_secs();
-        if i >= max_count || age > max_age_secs {
-            let _ = std::fs::remove_file(path);
+        // Inject peer address so handlers can extract client IP
+        let app_with_peer = app.layer(axum::Extension(peer_addr));
+
+        let io = hyper_util::rt::TokioIo::new(tls_stream);
+        let hyper_service = hyper_util::service::TowerToHyperService::new(app_with_peer);
+        let builder =
+            hyper_util::server::conn::auto::Builder::new(hyper_util::rt::TokioExecutor::new());
+
+        if let Err(e) = builder
+            .serve_connection_with_upgrades(io, hyper_service)
+            .await
+        {
+            tracing::debug!(%peer_addr, "Connection error: {e}");
         }
-    }
+    });
 }
@@ -0,0 +1,197 @@
+//! Agents and X servers a crashed server left behind.
+//!
+//! `restore_sessions` only knows the agents a graceful shutdown wrote down.
+//! Any other agent still running is adopted when its session can be rebuilt
+//! from its command line, environment and owner, and terminated otherwise.
+//! X servers on pool displays that no session uses are terminated after
+//! that, so their display numbers can be handed out again.
+
+use std::collections::HashSet;
+use std::os::unix::fs::MetadataExt;
+use std::time::{Duration, UNIX_EPOCH};
+
+use beam_protocol::SessionInfo;
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+use uuid::Uuid;
+
+/// An agent process no session accounts for
+pub struct RunningAgent {
+    pub pid: u32,
+    /// None when the session can't be rebuilt
+    pub session: Option<RecoveredSession>,
+}
+
+pub struct RecoveredSession {
+    pub info: SessionInfo,
+    pub agent_token: String,
+}
+
+/// Processes `is_agent` recognizes, other than the `known` ones.
+pub fn running_agents(is_agent: impl Fn(u32) -> bool, known: &HashSet<u32>) -> Vec<RunningAgent> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let own_uid = nix::unistd::geteuid().as_raw();
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| !known.contains(pid) && is_agent(*pid))
+        .map(|pid| {
+            let proc_dir = format!("/proc/{pid}");
+            let args: Vec<String> = std::fs::read(format!("{proc_dir}/cmdline"))
+                .unwrap_or_default()
+                .split(|&b| b == 0)
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            let environ = std::fs::read(format!("{proc_dir}/environ")).unwrap_or_default();
+            let meta = std::fs::metadata(&proc_dir).ok();
+            // An agent running as the server itself had no user to run as
+            let owner = meta
+                .as_ref()
+                .map(|meta| meta.uid())
+                .filter(|&uid| uid != own_uid)
+                .and_then(|uid| nix::unistd::User::from_uid(uid.into()).ok().flatten())
+                .map(|user| user.name);
+            // /proc/<pid> appears when the process starts
+            let started = meta
+                .and_then(|meta| meta.modified().ok())
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_secs());
+            RunningAgent {
+                pid,
+                session: recover(&args, &environ, owner.as_deref(), started),
+            }
+        })
+        .collect()
+}
+
+/// Rebuild a session from the arguments the server started its agent with,
+/// the token in its environment and the user it runs as.
+fn recover(
+    args: &[String],
+    environ: &[u8],
+    owner: Option<&str>,
+    created_at: u64,
+) -> Option<RecoveredSession> {
+    let flag = |name: &str| {
+        let at = args.iter().position(|arg| arg == name)?;
+        args.get(at + 1).map(String::as_str)
+    };
+    let agent_token = environ
+        .split(|&b| b == 0)
+        .find_map(|var| var.strip_prefix(b"BEAM_AGENT_TOKEN="))
+        .and_then(|token| std::str::from_utf8(token).ok())
+        .filter(|token| !token.is_empty())?;
+    let owner = owner?;
+    // Assistance runs the agent as the console user on the operator's behalf
+    let (username, assist) = match flag("--assist-operator") {
+        Some(operator) => (operator.to_string(), Some(owner.to_string())),
+        None => (owner.to_string(), None),
+    };
+    let info = SessionInfo {
+        id: flag("--session-id")?.parse::<Uuid>().ok()?,
+        username,
+        display: flag("--display")?.strip_prefix(':')?.parse().ok()?,
+        width: flag("--width")?.parse().ok()?,
+        height: flag("--height")?.parse().ok()?,
+        created_at,
+        client: None,
+        assist,
+    };
+    Some(RecoveredSession {
+        info,
+        agent_token: agent_token.to_string(),
+    })
+}
+
+/// Live X servers on displays numbered `start` and up, per their lock
+/// files: (display, pid)
+pub fn x_servers(start: u32) -> Vec<(u32, u32)> {
+    let Ok(entries) = std::fs::read_dir("/tmp") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let display = lock_display(name.to_str()?)?;
+            let pid = std::fs::read_to_string(entry.path())
+                .ok()?
+                .trim()
+                .parse::<u32>()
+                .ok()?;
+            let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+            (display >= start && comm.trim() == "Xorg").then_some((display, pid))
+        })
+        .collect()
+}
+
+/// Display number of an X lock file name, `.X<n>-lock`
+fn lock_display(name: &str) -> Option<u32> {
+    name.strip_prefix(".X")?.strip_suffix("-lock")?.parse().ok()
+}
+
+/// SIGTERM `pid`, and SIGKILL it if it is still there 5 seconds later.
+pub async fn terminate(pid: u32) {
+    let pid = Pid::from_raw(pid as i32);
+    if kill(pid, Signal::SIGTERM).is_err() {
+        return;
+    }
+    for _ in 0..50 {
+        if kill(pid, None).is_err() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let _ = kill(pid, Signal::SIGKILL);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn sessions_are_rebuilt_from_agent_arguments() {
+        let id = Uuid::new_v4();
+        let agent = args(&format!(
+            "/usr/bin/beam-agent --display :12 --session-id {id} --server-url wss://x \
+             --width 1280 --height 800 --framerate 60"
+        ));
+        let environ = b"HOME=/home/alice\0BEAM_AGENT_TOKEN=secret\0RUST_LOG=info\0";
+
+        let recovered = recover(&agent, environ, Some("alice"), 1700000000).unwrap();
+        assert_eq!(recovered.agent_token, "secret");
+        assert_eq!(recovered.info.id, id);
+        assert_eq!(recovered.info.username, "alice");
+        assert_eq!(recovered.info.display, 12);
+        assert_eq!((recovered.info.width, recovered.info.height), (1280, 800));
+        assert_eq!(recovered.info.created_at, 1700000000);
+        assert!(recovered.info.assist.is_none());
+
+        // Assistance: the operator owns the session, the agent runs as the console user
+        let mut assisting = agent.clone();
+        assisting.extend(args("--assist-operator bob"));
+        let recovered = recover(&assisting, environ, Some("alice"), 0).unwrap();
+        assert_eq!(recovered.info.username, "bob");
+        assert_eq!(recovered.info.assist.as_deref(), Some("alice"));
+
+        // Without the token, the user or the session id, there is nothing to adopt
+        assert!(recover(&agent, b"HOME=/root\0", Some("alice"), 0).is_none());
+        assert!(recover(&agent, environ, None, 0).is_none());
+        let anonymous = args("beam-agent --display :12 --width 1280 --height 800");
+        assert!(recover(&anonymous, environ, Some("alice"), 0).is_none());
+    }
+
+    #[test]
+    fn x_lock_file_names() {
+        assert_eq!(lock_display(".X10-lock"), Some(10));
+        assert_eq!(lock_display(".X0-lock"), Some(0));
+        assert_eq!(lock_display(".X11-unix"), None);
+        assert_eq!(lock_display(".Xfoo-lock"), None);
+    }
+}
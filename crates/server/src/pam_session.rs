@@ -0,0 +1,423 @@
+//! PAM account and session phases for agents (`auth.pam_session`).
+//!
+//! A login only runs PAM's auth phase. Before an agent starts, the hidden
+//! `beam-server pam-session <USER>` helper runs the account phase, which
+//! refuses expired or locked accounts, and opens a session, which runs
+//! modules like pam_mkhomedir, pam_limits and pam_env. Those modules act on
+//! the process that opens the session, so the helper opens it rather than
+//! the server. It reports the limits and environment the session set up,
+//! the agent starts with them, and the helper closes the session once that
+//! agent has exited.
+
+use std::ffi::CStr;
+use std::io::{BufRead, Write};
+use std::process::Stdio;
+
+use anyhow::{Context, Result, anyhow, bail};
+use libc::{c_int, c_void};
+use nix::sys::resource::{Resource, getrlimit, setrlimit};
+use pam::ffi::{pam_conv, pam_message, pam_response};
+use pam::{PamFlag, PamHandle, PamReturnCode};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+
+/// Argument that runs `beam-server` as the helper
+pub const HELPER_ARG: &str = "pam-session";
+
+/// How long session modules get, e.g. for pam_mkhomedir to copy /etc/skel
+const OPEN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+const RESOURCES: [(&str, Resource); 16] = [
+    ("as", Resource::RLIMIT_AS),
+    ("core", Resource::RLIMIT_CORE),
+    ("cpu", Resource::RLIMIT_CPU),
+    ("data", Resource::RLIMIT_DATA),
+    ("fsize", Resource::RLIMIT_FSIZE),
+    ("locks", Resource::RLIMIT_LOCKS),
+    ("memlock", Resource::RLIMIT_MEMLOCK),
+    ("msgqueue", Resource::RLIMIT_MSGQUEUE),
+    ("nice", Resource::RLIMIT_NICE),
+    ("nofile", Resource::RLIMIT_NOFILE),
+    ("nproc", Resource::RLIMIT_NPROC),
+    ("rss", Resource::RLIMIT_RSS),
+    ("rtprio", Resource::RLIMIT_RTPRIO),
+    ("rttime", Resource::RLIMIT_RTTIME),
+    ("sigpending", Resource::RLIMIT_SIGPENDING),
+    ("stack", Resource::RLIMIT_STACK),
+];
+
+/// The account phase turned the user away (expired, locked, not allowed
+/// on this host), as opposed to PAM failing.
+#[derive(Debug)]
+pub struct AccountRefused(pub String);
+
+impl std::fmt::Display for AccountRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Account refused by PAM: {}", self.0)
+    }
+}
+
+impl std::error::Error for AccountRefused {}
+
+/// A resource limit, by its limits.conf name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limit {
+    pub resource: String,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// What opening the session set up, for the agent to start with
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Opened {
+    /// PAM environment (pam_env, pam_systemd, ...)
+    pub env: Vec<(String, String)>,
+    /// Limits the session changed (pam_limits)
+    pub limits: Vec<Limit>,
+    /// Scheduling priority, if the session changed it
+    pub nice: Option<i32>,
+}
+
+/// The helper's one line of output
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Reply {
+    Refused { refused: String },
+    Failed { error: String },
+    Opened(Opened),
+}
+
+/// A user's PAM session, held open by a helper process.
+pub struct Session {
+    helper: Child,
+    pub opened: Opened,
+}
+
+/// Run the account phase for `username` and open a session in a helper.
+pub async fn open(username: &str) -> Result<Session> {
+    let exe = std::env::current_exe().context("Failed to find the beam-server executable")?;
+    let mut cmd = Command::new(exe);
+    cmd.arg(HELPER_ARG)
+        .arg(username)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    crate::session::hide_server_env(&mut cmd);
+    let mut helper = cmd
+        .spawn()
+        .context("Failed to start the PAM session helper")?;
+    let stdout = helper
+        .stdout
+        .take()
+        .context("PAM session helper has no stdout")?;
+
+    let mut line = String::new();
+    tokio::time::timeout(OPEN_TIMEOUT, BufReader::new(stdout).read_line(&mut line))
+        .await
+        .map_err(|_| anyhow!("PAM session for {username} did not open in time"))?
+        .context("Failed to read from the PAM session helper")?;
+    match serde_json::from_str(&line) {
+        Ok(Reply::Opened(opened)) => Ok(Session { helper, opened }),
+        Ok(Reply::Refused { refused }) => Err(AccountRefused(refused).into()),
+        Ok(Reply::Failed { error }) => bail!("PAM session for {username} failed: {error}"),
+        Err(_) => bail!("PAM session helper for {username} exited without opening a session"),
+    }
+}
+
+impl Session {
+    /// Limits to apply to the agent, ahead of `pre_exec`
+    pub fn rlimits(&self) -> Vec<(Resource, u64, u64)> {
+        self.opened
+            .limits
+            .iter()
+            .filter_map(|limit| Some((resource(&limit.resource)?, limit.soft, limit.hard)))
+            .collect()
+    }
+
+    /// Keep the session open for as long as `agent_pid` runs; with None
+    /// (the agent never started) it is closed now.
+    pub async fn hold_for(mut self, agent_pid: Option<u32>) {
+        // Dropping stdin after the PID lets the helper read it to the end
+        if let (Some(pid), Some(mut stdin)) = (agent_pid, self.helper.stdin.take()) {
+            let _ = stdin.write_all(format!("{pid}\n").as_bytes()).await;
+        }
+        tokio::spawn(async move {
+            let _ = self.helper.wait().await;
+        });
+    }
+}
+
+/// Apply `limits` and `nice` to the calling process. Runs in `pre_exec`,
+/// while it may still raise hard limits.
+pub fn apply(limits: &[(Resource, u64, u64)], nice: Option<i32>) -> std::io::Result<()> {
+    for &(resource, soft, hard) in limits {
+        setrlimit(resource, soft, hard)?;
+    }
+    if let Some(nice) = nice
+        && unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn resource(name: &str) -> Option<Resource> {
+    RESOURCES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, resource)| *resource)
+}
+
+fn current_limits() -> Vec<Limit> {
+    RESOURCES
+        .iter()
+        .filter_map(|(name, resource)| {
+            let (soft, hard) = getrlimit(*resource).ok()?;
+            Some(Limit {
+                resource: name.to_string(),
+                soft,
+                hard,
+            })
+        })
+        .collect()
+}
+
+/// Limits in `after` that differ from `before`
+fn changed(before: &[Limit], after: Vec<Limit>) -> Vec<Limit> {
+    after
+        .into_iter()
+        .filter(|limit| !before.contains(limit))
+        .collect()
+}
+
+fn current_nice() -> Option<i32> {
+    nix::errno::Errno::clear();
+    let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+    (nice != -1 || nix::errno::Errno::last_raw() == 0).then_some(nice)
+}
+
+/// `beam-server pam-session <USER>`: open the session, print the `Reply`,
+/// then read the agent's PID from stdin and close the session when it
+/// exits (at once on EOF).
+pub fn helper(username: &str) -> ! {
+    let conv = pam_conv {
+        conv: Some(converse),
+        appdata_ptr: std::ptr::null_mut(),
+    };
+    let handle = match pam::start("beam", Some(username), &conv) {
+        Ok(handle) => handle,
+        Err(e) => reply_and_exit(&Reply::Failed {
+            error: format!("PAM start failed: {e}"),
+        }),
+    };
+
+    let limits = current_limits();
+    let nice = current_nice();
+    let opened = match open_in_helper(handle) {
+        Ok(env) => Opened {
+            env,
+            limits: changed(&limits, current_limits()),
+            nice: current_nice().filter(|now| Some(*now) != nice),
+        },
+        Err(reply) => {
+            pam::end(handle, PamReturnCode::Success);
+            reply_and_exit(&reply);
+        }
+    };
+    println!(
+        "{}",
+        serde_json::to_string(&Reply::Opened(opened)).unwrap_or_default()
+    );
+    let _ = std::io::stdout().flush();
+
+    let mut line = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut line);
+    if let Ok(pid) = line.trim().parse() {
+        wait_for_exit(pid);
+    }
+
+    let code = pam::close_session(handle, true);
+    if code != PamReturnCode::Success {
+        eprintln!(
+            "beam-server: closing the PAM session for {username} failed: {}",
+            pam::strerror(handle, code)
+        );
+    }
+    pam::setcred(handle, PamFlag::Delete_Cred);
+    pam::end(handle, code);
+    std::process::exit(0);
+}
+
+/// Account phase, credentials and session; the PAM environment after.
+fn open_in_helper(handle: &mut PamHandle) -> std::result::Result<Vec<(String, String)>, Reply> {
+    let code = pam::acct_mgmt(handle, PamFlag::None);
+    if code != PamReturnCode::Success {
+        return Err(Reply::Refused {
+            refused: pam::strerror(handle, code).to_string(),
+        });
+    }
+    let code = pam::setcred(handle, PamFlag::Establish_Cred);
+    if code != PamReturnCode::Success {
+        return Err(Reply::Failed {
+            error: format!("setting credentials: {}", pam::strerror(handle, code)),
+        });
+    }
+    let code = pam::open_session(handle, true);
+    if code != PamReturnCode::Success {
+        let error = format!("opening the session: {}", pam::strerror(handle, code));
+        pam::setcred(handle, PamFlag::Delete_Cred);
+        return Err(Reply::Failed { error });
+    }
+    Ok(env_list(handle))
+}
+
+fn reply_and_exit(reply: &Reply) -> ! {
+    println!("{}", serde_json::to_string(reply).unwrap_or_default());
+    std::process::exit(1);
+}
+
+/// The PAM environment. Not `pam::getenvlist`, which splits entries one
+/// byte off.
+fn env_list(handle: &mut PamHandle) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    // SAFETY: pam_getenvlist returns a NULL-terminated array of malloc'd
+    // "NAME=value" strings, all of which the caller frees
+    unsafe {
+        let list = pam::ffi::pam_getenvlist(handle);
+        if list.is_null() {
+            return env;
+        }
+        let mut entry = list;
+        while !(*entry).is_null() {
+            let text = CStr::from_ptr(*entry).to_string_lossy();
+            if let Some((name, value)) = text.split_once('=') {
+                env.push((name.to_string(), value.to_string()));
+            }
+            libc::free((*entry).cast());
+            entry = entry.add(1);
+        }
+        libc::free(list.cast());
+    }
+    env
+}
+
+/// Nobody answers prompts during the account and session phases; messages
+/// (pam_mkhomedir saying it created a home) go to the server's log.
+unsafe extern "C" fn converse(
+    num_msg: c_int,
+    msg: *mut *const pam_message,
+    resp: *mut *mut pam_response,
+    _appdata: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 || msg.is_null() || resp.is_null() {
+        return pam::ffi::PAM_CONV_ERR;
+    }
+    // SAFETY: PAM passes num_msg valid messages and owns the responses
+    // allocated here, which it frees
+    unsafe {
+        for i in 0..num_msg as usize {
+            let message = *msg.add(i);
+            if message.is_null() {
+                return pam::ffi::PAM_CONV_ERR;
+            }
+            match (*message).msg_style {
+                pam::ffi::PAM_TEXT_INFO | pam::ffi::PAM_ERROR_MSG => {
+                    if !(*message).msg.is_null() {
+                        let text = CStr::from_ptr((*message).msg).to_string_lossy();
+                        eprintln!("beam-server: PAM: {text}");
+                    }
+                }
+                _ => return pam::ffi::PAM_CONV_ERR,
+            }
+        }
+        let replies = libc::calloc(num_msg as usize, std::mem::size_of::<pam_response>());
+        if replies.is_null() {
+            return pam::ffi::PAM_BUF_ERR;
+        }
+        *resp = replies.cast();
+    }
+    pam::ffi::PAM_SUCCESS
+}
+
+/// Wait for `pid` to exit, or become a zombie, or be replaced by another
+/// process with the same PID.
+fn wait_for_exit(pid: u32) {
+    let stat = || {
+        std::fs::read_to_string(format!("/proc/{pid}/stat"))
+            .ok()
+            .and_then(|text| parse_stat(&text))
+    };
+    let Some((_, started)) = stat() else {
+        return;
+    };
+    while let Some((state, start)) = stat()
+        && start == started
+        && !matches!(state, 'Z' | 'X')
+    {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+/// (state, start time) from /proc/PID/stat
+fn parse_stat(text: &str) -> Option<(char, u64)> {
+    // The command name may hold spaces and parentheses; fields resume
+    // after its last ')'
+    let (_, rest) = text.rsplit_once(')')?;
+    let mut fields = rest.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    // Field 22, counting the state as field 3
+    let start = fields.nth(18)?.parse().ok()?;
+    Some((state, start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helper_replies_parse() {
+        let opened: Reply = serde_json::from_str(
+            r#"{"env":[["LANG","C.UTF-8"]],"limits":[{"resource":"nofile","soft":1024,"hard":4096}],"nice":5}"#,
+        )
+        .unwrap();
+        let Reply::Opened(opened) = opened else {
+            panic!("expected an opened session, got {opened:?}");
+        };
+        assert_eq!(
+            opened.env,
+            vec![("LANG".to_string(), "C.UTF-8".to_string())]
+        );
+        assert_eq!(opened.nice, Some(5));
+        let session_limit = (
+            resource(&opened.limits[0].resource),
+            opened.limits[0].soft,
+            opened.limits[0].hard,
+        );
+        assert_eq!(session_limit, (Some(Resource::RLIMIT_NOFILE), 1024, 4096));
+
+        let refused = serde_json::from_str(r#"{"refused":"User account has expired"}"#).unwrap();
+        assert!(matches!(refused, Reply::Refused { refused } if refused.contains("expired")));
+        let failed = serde_json::from_str(r#"{"error":"opening the session: stub"}"#).unwrap();
+        assert!(matches!(failed, Reply::Failed { .. }));
+    }
+
+    #[test]
+    fn only_changed_limits_are_reported() {
+        let before = current_limits();
+        assert!(changed(&before, current_limits()).is_empty());
+        let mut after = before.clone();
+        after[0].soft = after[0].soft.saturating_sub(1);
+        assert_eq!(changed(&before, after.clone()), vec![after[0].clone()]);
+    }
+
+    #[test]
+    fn proc_stat_fields() {
+        let stat = "4242 (beam agent) (x)) S 1 4242 4242 0 -1 4194560 1000 0 0 0 \
+                    10 5 0 0 20 0 8 0 123456 1000000 500";
+        assert_eq!(parse_stat(stat), Some(('S', 123456)));
+        assert_eq!(parse_stat("4242 (beam-agent"), None);
+        let own = std::fs::read_to_string("/proc/self/stat").unwrap();
+        assert!(parse_stat(&own).is_some());
+    }
+}
@@ -0,0 +1,109 @@
+//! Settings each user picked in the web client, kept across sessions.
+//!
+//! One small JSON file per user under `/var/lib/beam/preferences`, read
+//! when a session is created and rewritten when the user changes one.
+//! Unknown or missing fields read as their defaults, so the file can grow.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use beam_protocol::QualityPreset;
+use serde::{Deserialize, Serialize};
+
+pub const PREFERENCES_DIR: &str = "/var/lib/beam/preferences";
+
+/// What is remembered for a user
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserPreferences {
+    /// Quality preset of their last session, applied to the next
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityPreset>,
+}
+
+pub struct Preferences {
+    dir: PathBuf,
+    /// Serializes read-modify-write of a user's file
+    write_lock: Mutex<()>,
+}
+
+impl Preferences {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// A user's preferences; the defaults if none were saved or the file
+    /// can't be read.
+    pub fn get(&self, username: &str) -> UserPreferences {
+        match std::fs::read_to_string(self.path(username)) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                tracing::warn!(%username, "Ignoring unreadable preferences: {e}");
+                UserPreferences::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => UserPreferences::default(),
+            Err(e) => {
+                tracing::warn!(%username, "Failed to read preferences: {e}");
+                UserPreferences::default()
+            }
+        }
+    }
+
+    /// Remember `preset` as the user's quality preset.
+    pub fn set_quality(&self, username: &str, preset: QualityPreset) -> Result<()> {
+        self.update(username, |prefs| prefs.quality = Some(preset))
+    }
+
+    fn update(&self, username: &str, change: impl FnOnce(&mut UserPreferences)) -> Result<()> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut prefs = self.get(username);
+        change(&mut prefs);
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.path(username);
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&prefs)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Usernames passed `is_valid_username`, so they hold no `/`; with the
+    /// suffix even "." and ".." name plain files.
+    fn path(&self, username: &str) -> PathBuf {
+        self.dir.join(format!("{username}.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_is_remembered_per_user() {
+        let dir = std::env::temp_dir().join(format!("beam-preferences-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let prefs = Preferences::new(&dir);
+        assert_eq!(prefs.get("alice"), UserPreferences::default());
+
+        prefs.set_quality("alice", QualityPreset::Sharp).unwrap();
+        prefs.set_quality("bob", QualityPreset::Smooth).unwrap();
+        prefs.set_quality("alice", QualityPreset::Lossless).unwrap();
+        assert_eq!(prefs.get("alice").quality, Some(QualityPreset::Lossless));
+        assert_eq!(prefs.get("bob").quality, Some(QualityPreset::Smooth));
+
+        // A file from a newer server, or a damaged one, reads as defaults
+        std::fs::write(
+            dir.join("carol.json"),
+            r#"{"quality":"sharp","theme":"dark"}"#,
+        )
+        .unwrap();
+        assert_eq!(prefs.get("carol").quality, Some(QualityPreset::Sharp));
+        std::fs::write(dir.join("dave.json"), "{").unwrap();
+        assert_eq!(prefs.get("dave"), UserPreferences::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
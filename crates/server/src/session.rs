@@ -1,19 +1,29 @@
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
-use beam_protocol::SessionInfo;
+use beam_protocol::{
+    AudioBackend, AuthRequest, Desktop, LoginClient, NetTestResult, ResizePolicy, SessionInfo,
+    StreamParams,
+};
 use serde::{Deserialize, Serialize};
 use tokio::process::{Child, Command};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+use crate::orphans;
+
 const SESSION_DIR: &str = "/var/lib/beam/sessions";
 
+/// Format of the session files written by `persist_sessions`: a
+/// `{"version": N, "session": {...}}` envelope. Version 1 files predate the
+/// envelope and hold the session object alone.
+const SESSION_FORMAT_VERSION: u64 = 2;
+
 #[derive(Serialize, Deserialize)]
 struct PersistedSession {
     session_id: Uuid,
@@ -26,12 +36,105 @@ struct PersistedSession {
     agent_token: String,
     #[serde(default)]
     release_token: String,
+    #[serde(default)]
+    client: Option<LoginClient>,
+    #[serde(default)]
+    assist: Option<String>,
+    #[serde(default)]
+    private: bool,
+    /// The agent predates tickets and connects with its long-lived token
+    #[serde(default)]
+    legacy_agent_token: bool,
+}
+
+#[derive(Serialize)]
+struct SessionFile<'a> {
+    version: u64,
+    session: &'a PersistedSession,
+}
+
+impl PersistedSession {
+    fn to_file(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&SessionFile {
+            version: SESSION_FORMAT_VERSION,
+            session: self,
+        })
+    }
+
+    /// Parse a session file of any version so far. Fields a newer server
+    /// added are ignored, so a downgrade still adopts its agents.
+    fn from_file(data: &str) -> Result<Self> {
+        let mut value: serde_json::Value = serde_json::from_str(data)?;
+        let Some(version) = value.get("version").map(serde_json::Value::as_u64) else {
+            return Self::migrate_v1(value);
+        };
+        match version {
+            Some(version) if version >= 2 => {
+                if version > SESSION_FORMAT_VERSION {
+                    tracing::warn!(
+                        version,
+                        "Session file is from a newer server, reading the fields it shares"
+                    );
+                }
+                let session = value
+                    .get_mut("session")
+                    .map(serde_json::Value::take)
+                    .context("Session file has no session")?;
+                Ok(serde_json::from_value(session)?)
+            }
+            _ => anyhow::bail!("Unsupported session file version {}", value["version"]),
+        }
+    }
+
+    /// Version 1: the bare session, which before release tokens existed
+    /// came without one. Servers writing it came before agent tickets too,
+    /// so its agent may only know its long-lived token.
+    fn migrate_v1(value: serde_json::Value) -> Result<Self> {
+        let mut session: Self = serde_json::from_value(value)?;
+        if session.release_token.is_empty() {
+            session.release_token = generate_release_token();
+        }
+        session.legacy_agent_token = true;
+        Ok(session)
+    }
+}
+
+/// The session's user has no account an agent may run as: it would run
+/// as root, or a users-file login has no system account of its own.
+#[derive(Debug)]
+pub struct UnusableAccount(pub String);
+
+impl std::fmt::Display for UnusableAccount {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UnusableAccount {}
+
+/// Keep what only the server may see out of a child's environment: the
+/// `BEAM_*__*` config overrides, which can hold `jwt_secret`, and systemd's
+/// notify socket, since only the server reports readiness and keepalives.
+/// The agent runs as the session user, who can read its environment.
+pub(crate) fn hide_server_env(cmd: &mut Command) {
+    hide_env(cmd, std::env::vars_os().map(|(name, _)| name));
+}
+
+fn hide_env(cmd: &mut Command, inherited: impl IntoIterator<Item = std::ffi::OsString>) {
+    for name in inherited {
+        if name.to_str().is_some_and(beam_protocol::is_env_override) {
+            cmd.env_remove(name);
+        }
+    }
+    for var in beam_protocol::sd_notify::NOTIFY_ENV {
+        cmd.env_remove(var);
+    }
 }
 
 /// Constant-time byte comparison to prevent timing side-channel attacks.
 /// Always iterates over the full max(a.len(), b.len()) range so that
 /// differing lengths cannot be detected via timing.
-fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
     let mut diff = if a.len() != b.len() { 1u8 } else { 0u8 };
     for i in 0..a.len().max(b.len()) {
         let x = a.get(i).copied().unwrap_or(0);
@@ -84,19 +187,39 @@ pub struct SessionManager {
     tls_cert_path: Option<String>,
     /// Video/audio config to pass to agents
     video_config: beam_protocol::VideoConfig,
+    audio_config: beam_protocol::AudioConfig,
+    /// URL forwarding and idle policy settings passed to agents
+    session_config: beam_protocol::SessionConfig,
+    /// Thread scheduling and watchdog limits passed to agents
+    agent_config: beam_protocol::AgentConfig,
+    /// Physical display and consent settings for remote assistance
+    assist_config: beam_protocol::AssistConfig,
+    /// Where agent logs go
+    logging_config: beam_protocol::LoggingConfig,
+    /// Whether agents get a PAM session
+    auth_config: beam_protocol::AuthConfig,
 }
 
 struct DisplayPool {
+    /// First display number of the pool
+    start: u32,
     next: u32,
     /// Display numbers freed by destroyed sessions
     free: HashSet<u32>,
+    /// Numbers the last reconciliation found neither in use nor free
+    unaccounted: HashSet<u32>,
+    /// (display, pid) of X servers it found on displays without a session
+    stray_x: HashSet<(u32, u32)>,
 }
 
 impl DisplayPool {
     fn new(start: u32) -> Self {
         Self {
+            start,
             next: start,
             free: HashSet::new(),
+            unaccounted: HashSet::new(),
+            stray_x: HashSet::new(),
         }
     }
 
@@ -114,6 +237,15 @@ impl DisplayPool {
     fn release(&mut self, num: u32) {
         self.free.insert(num);
     }
+
+    /// Take a display already in use; the numbers skipped over stay free
+    fn reserve(&mut self, num: u32) {
+        self.free.remove(&num);
+        if num >= self.next {
+            self.free.extend(self.next.max(self.start)..num);
+            self.next = num + 1;
+        }
+    }
 }
 
 struct ManagedSession {
@@ -136,6 +268,20 @@ struct ManagedSession {
     pub restart_count: u32,
     /// Per-session idle timeout override in seconds. None = use global default.
     pub idle_timeout_override: Option<u64>,
+    /// Per-session system key combo permission. None = use global default.
+    pub system_combos_override: Option<bool>,
+    /// Bitrate/framerate fitted to the client's latest bandwidth probe.
+    /// None = configured video defaults. Reused when the agent is respawned,
+    /// and its bitrate is a ceiling for the running agent.
+    pub initial_stream: Option<StreamParams>,
+    /// Desktop environment picked at login. None = `session.desktop`.
+    pub desktop: Option<Desktop>,
+    /// The owner kept admins from observing the session (the signaling
+    /// channel's `private`), kept here so it survives a restart
+    pub private: bool,
+    /// Restored from a session file written before agent tickets: its
+    /// agent may connect with the long-lived token in the URL
+    pub legacy_agent_token: bool,
 }
 
 impl SessionManager {
@@ -145,6 +291,8 @@ impl SessionManager {
         default_height: u32,
         tls_cert_path: Option<String>,
         video_config: beam_protocol::VideoConfig,
+        audio_config: beam_protocol::AudioConfig,
+        session_config: beam_protocol::SessionConfig,
     ) -> Self {
         Self {
             sessions: RwLock::new(HashMap::new()),
@@ -153,31 +301,159 @@ impl SessionManager {
             display_pool: RwLock::new(DisplayPool::new(display_start)),
             tls_cert_path,
             video_config,
+            audio_config,
+            session_config,
+            agent_config: beam_protocol::AgentConfig::default(),
+            assist_config: beam_protocol::AssistConfig::default(),
+            logging_config: beam_protocol::LoggingConfig::default(),
+            auth_config: beam_protocol::AuthConfig::default(),
         }
     }
 
+    /// Configure agents per `[agent]` instead of the defaults.
+    pub fn with_agent_config(mut self, agent_config: beam_protocol::AgentConfig) -> Self {
+        self.agent_config = agent_config;
+        self
+    }
+
+    /// Attach remote assistance sessions per `[assist]`.
+    pub fn with_assist_config(mut self, assist_config: beam_protocol::AssistConfig) -> Self {
+        self.assist_config = assist_config;
+        self
+    }
+
+    /// Write agent logs per `[logging]`.
+    pub fn with_logging_config(mut self, logging_config: beam_protocol::LoggingConfig) -> Self {
+        self.logging_config = logging_config;
+        self
+    }
+
+    /// Open PAM sessions for agents per `[auth]`.
+    pub fn with_auth_config(mut self, auth_config: beam_protocol::AuthConfig) -> Self {
+        self.auth_config = auth_config;
+        self
+    }
+
     /// Create a new session for a user.
     ///
     /// Allocates a display number and spawns the beam-agent process.
-    /// Returns an error if max_sessions would be exceeded.
+    /// Viewport size, idle timeout and bandwidth probe come from the
+    /// login request. Returns an error if max_sessions would be exceeded.
     pub async fn create_session(
         &self,
-        username: &str,
+        req: &AuthRequest,
         server_url: &str,
         max_sessions: usize,
-        initial_width: Option<u32>,
-        initial_height: Option<u32>,
-        idle_timeout_override: Option<u64>,
     ) -> Result<SessionInfo> {
+        let initial_stream = req
+            .nettest
+            .map(|probe| self.video_config.fit_to_link(probe.kbps, probe.rtt_ms));
+        let (info, agent_token) = self
+            .reserve_session(req, initial_stream, max_sessions)
+            .await?;
+        let session_id = info.id;
+        let username = info.username.as_str();
+        let display_num = info.display;
+
+        // Clean up stale temp files from previous sessions on this display number.
+        // These may be owned by a different user if the previous agent was killed
+        // without running its Drop handler (e.g., SIGKILL during deployment).
+        // The physical display an assist session shows is not ours to clean.
+        if info.assist.is_none() {
+            let _ = std::fs::remove_file(format!("/tmp/beam-xorg-{display_num}.conf"));
+            let _ = std::fs::remove_file(format!("/tmp/beam-pulse-{display_num}.pa"));
+            let _ = std::fs::remove_dir_all(format!("/tmp/beam-pulse-{display_num}"));
+            // Remove stale X lock file if Xorg didn't clean up
+            let _ = std::fs::remove_file(format!("/tmp/.X{display_num}-lock"));
+            // Keyring dir may be owned by a different user (mode 700); server runs as root
+            let _ = std::fs::remove_dir_all(format!("/tmp/beam-keyring-{display_num}"));
+        }
+
+        if let Some(stream) = initial_stream {
+            tracing::info!(
+                %session_id,
+                bitrate = stream.bitrate,
+                framerate = stream.framerate,
+                "Initial stream fitted to client bandwidth probe"
+            );
+        }
+
+        // Spawn the agent process (outside the write lock to avoid holding it during spawn)
+        let agent_process = match self
+            .spawn_agent(
+                &info,
+                server_url,
+                &agent_token,
+                initial_stream,
+                req.desktop.as_ref(),
+            )
+            .await
+        {
+            Ok(child) => child,
+            Err(e) => {
+                // Clean up the reserved slot on spawn failure
+                self.sessions.write().await.remove(&session_id);
+                if info.assist.is_none() {
+                    self.display_pool.write().await.release(display_num);
+                }
+                return Err(e).context("Failed to spawn agent");
+            }
+        };
+
+        // Update the reserved slot with the agent process
+        let agent_pid = agent_process.id();
+        {
+            let mut sessions = self.sessions.write().await;
+            if let Some(session) = sessions.get_mut(&session_id) {
+                session.agent_process = Some(agent_process);
+                session.agent_pid = agent_pid;
+            }
+        }
+
+        tracing::info!(
+            %session_id,
+            %username,
+            display_num,
+            "Session created"
+        );
+
+        Ok(info)
+    }
+
+    /// Register a session and allocate its display without starting an
+    /// agent. Returns the session and the token its agent must present.
+    /// `create_session` spawns the agent next; tests attach a mock agent.
+    ///
+    /// An `assist` request takes the physical display instead of one from
+    /// the pool; only one such session can exist at a time.
+    pub async fn reserve_session(
+        &self,
+        req: &AuthRequest,
+        initial_stream: Option<StreamParams>,
+        max_sessions: usize,
+    ) -> Result<(SessionInfo, String)> {
         // Use client viewport dimensions if provided, clamped to sane bounds.
         // Fall back to config defaults for old clients or missing values.
-        let width = initial_width
+        let width = req
+            .viewport_width
             .filter(|&w| (320..=3840).contains(&w))
             .unwrap_or(self.default_width);
-        let height = initial_height
+        let height = req
+            .viewport_height
             .filter(|&h| (240..=2160).contains(&h))
             .unwrap_or(self.default_height);
-
+        let assist = if req.assist {
+            let display = self
+                .assist_config
+                .display_number()
+                .context("assist.display is not a local X display")?;
+            Some((
+                crate::assist::local_user(&self.assist_config).await?,
+                display,
+            ))
+        } else {
+            None
+        };
         // Atomically check max sessions and reserve a slot under the write lock
         // to prevent TOCTOU race (two concurrent logins both passing the check).
         // Both locks are acquired in a single scope to avoid deadlock from
@@ -197,15 +473,23 @@ impl SessionManager {
                 anyhow::bail!("Maximum number of sessions reached ({max_sessions})");
             }
 
-            display_num = self.display_pool.write().await.allocate();
+            display_num = match &assist {
+                Some(_) if sessions.values().any(|s| s.info.assist.is_some()) => {
+                    anyhow::bail!("Someone is already assisting at this computer");
+                }
+                Some((_, display)) => *display,
+                None => self.display_pool.write().await.allocate(),
+            };
 
             let info = SessionInfo {
                 id: session_id,
-                username: username.to_string(),
+                username: req.username.clone(),
                 display: display_num,
                 width,
                 height,
                 created_at: now,
+                client: None,
+                assist: assist.as_ref().map(|(user, _)| user.clone()),
             };
 
             // Reserve the slot immediately so concurrent requests see it
@@ -218,60 +502,29 @@ impl SessionManager {
                 release_token: release_token.clone(),
                 grace_generation: Arc::new(AtomicU64::new(0)),
                 restart_count: 0,
-                idle_timeout_override,
+                idle_timeout_override: req.idle_timeout,
+                system_combos_override: None,
+                initial_stream,
+                desktop: req.desktop.clone(),
+                private: false,
+                legacy_agent_token: false,
             };
             sessions.insert(session_id, managed);
         }
 
-        let info = SessionInfo {
-            id: session_id,
-            username: username.to_string(),
-            display: display_num,
-            width,
-            height,
-            created_at: now,
-        };
-
-        // Clean up stale temp files from previous sessions on this display number.
-        // These may be owned by a different user if the previous agent was killed
-        // without running its Drop handler (e.g., SIGKILL during deployment).
-        let _ = std::fs::remove_file(format!("/tmp/beam-xorg-{display_num}.conf"));
-        let _ = std::fs::remove_file(format!("/tmp/beam-pulse-{display_num}.pa"));
-        let _ = std::fs::remove_dir_all(format!("/tmp/beam-pulse-{display_num}"));
-        // Remove stale X lock file if Xorg didn't clean up
-        let _ = std::fs::remove_file(format!("/tmp/.X{display_num}-lock"));
-        // Keyring dir may be owned by a different user (mode 700); server runs as root
-        let _ = std::fs::remove_dir_all(format!("/tmp/beam-keyring-{display_num}"));
-
-        // Spawn the agent process (outside the write lock to avoid holding it during spawn)
-        let agent_process = match self.spawn_agent(&info, server_url, &agent_token).await {
-            Ok(child) => child,
-            Err(e) => {
-                // Clean up the reserved slot on spawn failure
-                self.sessions.write().await.remove(&session_id);
-                self.display_pool.write().await.release(display_num);
-                return Err(e).context("Failed to spawn agent");
-            }
-        };
-
-        // Update the reserved slot with the agent process
-        let agent_pid = agent_process.id();
-        {
-            let mut sessions = self.sessions.write().await;
-            if let Some(session) = sessions.get_mut(&session_id) {
-                session.agent_process = Some(agent_process);
-                session.agent_pid = agent_pid;
-            }
-        }
-
-        tracing::info!(
-            %session_id,
-            %username,
-            display_num,
-            "Session created"
-        );
-
-        Ok(info)
+        Ok((
+            SessionInfo {
+                id: session_id,
+                username: req.username.clone(),
+                display: display_num,
+                width,
+                height,
+                created_at: now,
+                client: None,
+                assist: assist.map(|(user, _)| user),
+            },
+            agent_token,
+        ))
     }
 
     /// Destroy a session, gracefully stopping the agent process.
@@ -322,18 +575,22 @@ impl SessionManager {
                 }
             }
 
-            // Wait for Xorg lock file cleanup before recycling the display number.
-            // The agent exits before Xorg, so the lock file may linger briefly.
-            let lock_path = format!("/tmp/.X{display_num}-lock");
-            for _ in 0..20 {
-                if !std::path::Path::new(&lock_path).exists() {
-                    break;
+            // The physical display an assist session showed stays up and
+            // never came from the pool
+            if session.info.assist.is_none() {
+                // Wait for Xorg lock file cleanup before recycling the display number.
+                // The agent exits before Xorg, so the lock file may linger briefly.
+                let lock_path = format!("/tmp/.X{display_num}-lock");
+                for _ in 0..20 {
+                    if !std::path::Path::new(&lock_path).exists() {
+                        break;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
                 }
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-            }
 
-            // Now that the agent has exited, recycle the display number
-            self.display_pool.write().await.release(display_num);
+                // Now that the agent has exited, recycle the display number
+                self.display_pool.write().await.release(display_num);
+            }
             tracing::info!(%session_id, "Session destroyed");
         }
         Ok(())
@@ -345,6 +602,47 @@ impl SessionManager {
         sessions.values().map(|s| s.info.clone()).collect()
     }
 
+    /// Each session's video bitrate ceiling in kbps under the bandwidth
+    /// caps (0 = uncapped).
+    pub async fn bitrate_ceilings(&self) -> Vec<(Uuid, u32)> {
+        let sessions = self.sessions.read().await;
+        let (ids, caps): (Vec<Uuid>, Vec<u32>) = sessions
+            .values()
+            .map(|s| {
+                let mut cap = self.session_config.bandwidth_cap_for(&s.info.username);
+                // What the client's link can take is a ceiling as well
+                if let Some(stream) = s.initial_stream {
+                    cap = match cap {
+                        0 => stream.bitrate,
+                        cap => cap.min(stream.bitrate),
+                    };
+                }
+                (s.info.id, cap)
+            })
+            .unzip();
+        let shares = crate::bandwidth::share_out(
+            &caps,
+            self.video_config.bitrate,
+            self.session_config.host_bandwidth_kbps,
+        );
+        ids.into_iter().zip(shares).collect()
+    }
+
+    /// Fit the session's stream to a bandwidth probe the client ran after
+    /// logging in. The bitrate applies as a ceiling from the next
+    /// `bandwidth::rebalance`; the framerate when the agent next starts.
+    /// Returns `None` if the session does not exist.
+    pub async fn fit_to_link(
+        &self,
+        session_id: Uuid,
+        probe: NetTestResult,
+    ) -> Option<StreamParams> {
+        let stream = self.video_config.fit_to_link(probe.kbps, probe.rtt_ms);
+        let mut sessions = self.sessions.write().await;
+        sessions.get_mut(&session_id)?.initial_stream = Some(stream);
+        Some(stream)
+    }
+
     /// List all active sessions with their last activity timestamps.
     pub async fn list_sessions_with_activity(&self) -> Vec<(SessionInfo, u64)> {
         let sessions = self.sessions.read().await;
@@ -401,6 +699,25 @@ impl SessionManager {
             .unwrap_or(global_default)
     }
 
+    /// Record whether a session's login wants system key combos.
+    pub async fn set_system_combos(&self, session_id: Uuid, allowed: bool) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.get_mut(&session_id) {
+            s.system_combos_override = Some(allowed);
+        }
+    }
+
+    /// Whether a session may synthesize system key combos: only where the
+    /// config allows them, and not if its login turned them off.
+    pub async fn allows_system_combos(&self, session_id: Uuid, configured: bool) -> bool {
+        let sessions = self.sessions.read().await;
+        configured
+            && sessions
+                .get(&session_id)
+                .and_then(|s| s.system_combos_override)
+                .unwrap_or(true)
+    }
+
     /// Verify the agent token for a session. Returns true if valid.
     /// Uses constant-time comparison to prevent timing side-channel attacks.
     pub async fn verify_agent_token(&self, session_id: Uuid, token: &str) -> bool {
@@ -411,6 +728,16 @@ impl SessionManager {
             .unwrap_or(false)
     }
 
+    /// Verify a long-lived agent token presented where a ticket belongs:
+    /// only accepted from agents of sessions restored from a session file
+    /// that predates tickets.
+    pub async fn verify_legacy_agent_token(&self, session_id: Uuid, token: &str) -> bool {
+        let sessions = self.sessions.read().await;
+        sessions.get(&session_id).is_some_and(|s| {
+            s.legacy_agent_token && constant_time_eq(s.agent_token.as_bytes(), token.as_bytes())
+        })
+    }
+
     /// Take the agent child process for external monitoring.
     /// Returns the Child if it hasn't been taken already.
     pub async fn take_agent_child(&self, session_id: Uuid) -> Option<Child> {
@@ -429,6 +756,38 @@ impl SessionManager {
             .map(|s| s.info.clone())
     }
 
+    /// The session a login should reuse: the user's own desktop, or with
+    /// `assist` the remote assistance session they are running.
+    pub async fn find_for_login(&self, username: &str, assist: bool) -> Option<SessionInfo> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .values()
+            .find(|s| s.info.username == username && s.info.assist.is_some() == assist)
+            .map(|s| s.info.clone())
+    }
+
+    /// Record where the latest login to a session came from.
+    pub async fn set_login_client(&self, session_id: Uuid, client: Option<LoginClient>) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(session) = sessions.get_mut(&session_id) {
+            session.info.client = client;
+        }
+    }
+
+    /// Record whether the owner made the session private.
+    pub async fn set_private(&self, session_id: Uuid, private: bool) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(session) = sessions.get_mut(&session_id) {
+            session.private = private;
+        }
+    }
+
+    /// Whether the owner made the session private, as last recorded.
+    pub async fn is_private(&self, session_id: Uuid) -> bool {
+        let sessions = self.sessions.read().await;
+        sessions.get(&session_id).is_some_and(|s| s.private)
+    }
+
     /// Get the release token for a session.
     pub async fn get_release_token(&self, session_id: Uuid) -> Option<String> {
         let sessions = self.sessions.read().await;
@@ -470,6 +829,15 @@ impl SessionManager {
         }
     }
 
+    /// Current grace-period generation of a session.
+    #[cfg(test)]
+    pub async fn grace_generation(&self, session_id: Uuid) -> Option<u64> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(&session_id)
+            .map(|s| s.grace_generation.load(Ordering::SeqCst))
+    }
+
     /// Increment the restart count for a session and return the new count.
     /// Returns `None` if the session does not exist.
     pub async fn increment_restart_count(&self, session_id: Uuid) -> Option<u32> {
@@ -497,10 +865,10 @@ impl SessionManager {
     /// Returns `None` if the session does not exist.
     pub async fn respawn_agent(&self, session_id: Uuid, server_url: &str) -> Result<Option<()>> {
         // Read session info under a read lock first
-        let info = {
+        let (info, initial_stream, desktop) = {
             let sessions = self.sessions.read().await;
             match sessions.get(&session_id) {
-                Some(s) => s.info.clone(),
+                Some(s) => (s.info.clone(), s.initial_stream, s.desktop.clone()),
                 None => return Ok(None),
             }
         };
@@ -509,13 +877,23 @@ impl SessionManager {
 
         // Clean up stale temp files before respawn
         let display_num = info.display;
-        let _ = std::fs::remove_file(format!("/tmp/beam-xorg-{display_num}.conf"));
-        let _ = std::fs::remove_file(format!("/tmp/beam-pulse-{display_num}.pa"));
-        let _ = std::fs::remove_dir_all(format!("/tmp/beam-pulse-{display_num}"));
-        let _ = std::fs::remove_file(format!("/tmp/.X{display_num}-lock"));
-        let _ = std::fs::remove_dir_all(format!("/tmp/beam-keyring-{display_num}"));
+        if info.assist.is_none() {
+            let _ = std::fs::remove_file(format!("/tmp/beam-xorg-{display_num}.conf"));
+            let _ = std::fs::remove_file(format!("/tmp/beam-pulse-{display_num}.pa"));
+            let _ = std::fs::remove_dir_all(format!("/tmp/beam-pulse-{display_num}"));
+            let _ = std::fs::remove_file(format!("/tmp/.X{display_num}-lock"));
+            let _ = std::fs::remove_dir_all(format!("/tmp/beam-keyring-{display_num}"));
+        }
 
-        let child = self.spawn_agent(&info, server_url, &new_token).await?;
+        let child = self
+            .spawn_agent(
+                &info,
+                server_url,
+                &new_token,
+                initial_stream,
+                desktop.as_ref(),
+            )
+            .await?;
         let new_pid = child.id();
 
         // Update the session with the new agent process and token
@@ -536,18 +914,22 @@ impl SessionManager {
         info: &SessionInfo,
         server_url: &str,
         agent_token: &str,
+        initial_stream: Option<StreamParams>,
+        desktop: Option<&Desktop>,
     ) -> Result<Child> {
         let display_str = format!(":{}", info.display);
-
-        // Try to find the agent binary in the same directory as the server,
-        // or fall back to "beam-agent" in PATH.
-        let agent_path = std::env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|parent| parent.join("beam-agent")))
-            .filter(|p| p.exists())
-            .unwrap_or_else(|| "beam-agent".into());
-
-        let mut cmd = Command::new(agent_path);
+        let stream = initial_stream.unwrap_or(StreamParams {
+            bitrate: self.video_config.bitrate,
+            framerate: self.video_config.framerate,
+        });
+        let max_bitrate = self
+            .bitrate_ceilings()
+            .await
+            .into_iter()
+            .find_map(|(id, kbps)| (id == info.id).then_some(kbps))
+            .unwrap_or_default();
+
+        let mut cmd = Command::new(self.agent_binary());
         cmd.arg("--display")
             .arg(&display_str)
             .arg("--session-id")
@@ -559,38 +941,215 @@ impl SessionManager {
             .arg("--height")
             .arg(info.height.to_string())
             .arg("--framerate")
-            .arg(self.video_config.framerate.to_string())
+            .arg(stream.framerate.to_string())
             .arg("--bitrate")
-            .arg(self.video_config.bitrate.to_string())
+            .arg(stream.bitrate.to_string())
             .arg("--max-width")
             .arg(self.video_config.max_width.to_string())
             .arg("--max-height")
-            .arg(self.video_config.max_height.to_string());
+            .arg(self.video_config.max_height.to_string())
+            .arg("--chroma")
+            .arg(&self.video_config.chroma);
 
         // Pass encoder preference if configured
         if let Some(ref encoder) = self.video_config.encoder {
             cmd.arg("--encoder").arg(encoder);
         }
 
-        // Pass agent authentication token via environment variable
-        // (CLI args are visible to all users via /proc/<pid>/cmdline)
-        cmd.env("BEAM_AGENT_TOKEN", agent_token);
+        // Encoder tuning overrides; unset knobs keep the agent's defaults
+        let tuning = &self.video_config.tuning;
+        if let Some(ref preset) = tuning.preset {
+            cmd.arg("--preset").arg(preset);
+        }
+        if let Some(ref tune) = tuning.tune {
+            cmd.arg("--tune").arg(tune);
+        }
+        if let Some(ref rc_mode) = tuning.rc_mode {
+            cmd.arg("--rc-mode").arg(rc_mode);
+        }
+        if let Some(gop_size) = tuning.gop_size {
+            cmd.arg("--gop-size").arg(gop_size.to_string());
+        }
+        if let Some(lookahead) = tuning.lookahead {
+            cmd.arg("--lookahead").arg(lookahead.to_string());
+        }
+        if tuning.intra_refresh {
+            cmd.arg("--intra-refresh");
+        }
+
+        if let Some(ref dir) = self.video_config.stage_metrics_dir {
+            cmd.arg("--stage-metrics-file")
+                .arg(format!("{dir}/beam-agent-{}.prom", info.id));
+        }
+
+        if max_bitrate > 0 {
+            cmd.arg("--max-bitrate").arg(max_bitrate.to_string());
+        }
+        if self.video_config.quality_ceiling > 0 {
+            cmd.arg("--quality-ceiling")
+                .arg(self.video_config.quality_ceiling.to_string());
+        }
+
+        cmd.arg("--audio-bitrate")
+            .arg(self.audio_config.bitrate.to_string())
+            .arg("--audio-channels")
+            .arg(self.audio_config.channels.to_string())
+            .arg("--audio-frame-ms")
+            .arg(self.audio_config.frame_ms.to_string());
+        if self.audio_config.fec {
+            cmd.arg("--audio-fec");
+        }
+        if self.audio_config.dtx {
+            cmd.arg("--audio-dtx");
+        }
+        if self.audio_config.backend != AudioBackend::Auto {
+            cmd.arg("--audio-backend")
+                .arg(self.audio_config.backend.name());
+        }
+
+        if self.session_config.resize_policy == ResizePolicy::Letterbox {
+            cmd.arg("--letterbox");
+        }
+        if self.session_config.forward_urls && info.assist.is_none() {
+            cmd.arg("--forward-urls");
+        }
+        if info.assist.is_none() {
+            let desktop = desktop.unwrap_or(&self.session_config.desktop);
+            cmd.arg("--desktop").arg(desktop.to_string());
+        }
+        // Whoever is at an assisted display agreed to one operator watching
+        if self.session_config.thumbnail_secs > 0
+            && info.assist.is_none()
+            && !self.session_config.privacy_for(&info.username)
+        {
+            cmd.arg("--thumbnail-secs")
+                .arg(self.session_config.thumbnail_secs.to_string());
+        }
+        if info.assist.is_none() {
+            for mode in self.session_config.display_modes_for(&info.username) {
+                cmd.arg("--display-mode").arg(mode.to_string());
+            }
+        }
+        if info.assist.is_some() {
+            cmd.arg("--assist-operator")
+                .arg(&info.username)
+                .arg("--assist-consent-secs")
+                .arg(self.assist_config.consent_secs.to_string());
+            if !self.assist_config.control {
+                cmd.arg("--assist-view-only");
+            }
+        }
+        if self.session_config.pace_input {
+            cmd.arg("--pace-input");
+        }
+
+        let idle = self.session_config.idle_policy_for(&info.username);
+        cmd.arg("--idle-throttle-after")
+            .arg(idle.throttle_after.to_string())
+            .arg("--idle-throttle-framerate")
+            .arg(idle.throttle_framerate.to_string())
+            .arg("--idle-lock-after")
+            .arg(idle.lock_after.to_string())
+            .arg("--idle-disconnect-after")
+            .arg(idle.disconnect_after.to_string())
+            .arg("--idle-destroy-after")
+            .arg(idle.destroy_after.to_string());
+        cmd.arg("--clipboard").arg(
+            self.session_config
+                .clipboard_policy_for(&info.username)
+                .name(),
+        );
+        if self.session_config.primary_selection {
+            cmd.arg("--primary-selection");
+        }
+        let history = &self.session_config.clipboard_history;
+        cmd.arg("--clipboard-history")
+            .arg(history.entries.to_string())
+            .arg("--clipboard-history-entry-bytes")
+            .arg(history.max_entry_bytes.to_string())
+            .arg("--clipboard-history-total-bytes")
+            .arg(history.max_total_bytes.to_string());
+
+        let realtime = &self.agent_config.realtime;
+        cmd.arg("--realtime-policy")
+            .arg(&realtime.policy)
+            .arg("--capture-priority")
+            .arg(realtime.capture.to_string())
+            .arg("--audio-priority")
+            .arg(realtime.audio.to_string())
+            .arg("--worker-priority")
+            .arg(realtime.workers.to_string());
+        cmd.arg("--max-rss-mb")
+            .arg(self.agent_config.max_rss_mb.to_string());
+        if self.agent_config.freeze_recovery {
+            cmd.arg("--freeze-recovery");
+        }
+        if self.agent_config.sandbox {
+            cmd.arg("--sandbox");
+        }
+        let affinity = &self.agent_config.cpu_affinity;
+        for (flag, cpus) in [
+            ("--capture-cpus", &affinity.capture),
+            ("--audio-cpus", &affinity.audio),
+            ("--worker-cpus", &affinity.workers),
+        ] {
+            if !cpus.is_empty() {
+                let list: Vec<String> = cpus.iter().map(|cpu| cpu.to_string()).collect();
+                cmd.arg(flag).arg(list.join(","));
+            }
+        }
 
         // Pass TLS cert path for certificate pinning
         if let Some(ref cert_path) = self.tls_cert_path {
             cmd.arg("--tls-cert").arg(cert_path);
         }
+        cmd.args(&self.agent_config.extra_args);
+
+        // A PAM session for the user, as a login would open; its environment
+        // goes first so that everything set below takes precedence
+        let run_as = info.assist.as_deref().unwrap_or(&info.username);
+        let user = lookup_user(run_as);
+        if let Some(reason) = unusable_account(
+            user.as_ref(),
+            self.auth_config.users_file.is_some(),
+            nix::unistd::geteuid().is_root(),
+        ) {
+            tracing::warn!(username = %run_as, "Not starting an agent: {reason}");
+            return Err(UnusableAccount(reason.to_string()).into());
+        }
+        let pam_session = if self.auth_config.pam_session
+            && self.auth_config.users_file.is_none()
+            && info.assist.is_none()
+            && user.is_some()
+            && nix::unistd::geteuid().is_root()
+        {
+            let session = crate::pam_session::open(run_as).await?;
+            cmd.envs(session.opened.env.iter().map(|(name, value)| (name, value)));
+            Some(session)
+        } else {
+            None
+        };
 
         // Set agent log level to info (avoid inheriting server's debug level)
+        // unless the config asks for another
         cmd.env("RUST_LOG", "info");
+        cmd.envs(&self.agent_config.env);
+
+        // Pass agent authentication token via environment variable
+        // (CLI args are visible to all users via /proc/<pid>/cmdline)
+        cmd.env("BEAM_AGENT_TOKEN", agent_token);
+
+        hide_server_env(&mut cmd);
 
-        // Run agent as the authenticated user for security isolation.
+        // Run agent as the authenticated user for security isolation, or
+        // for remote assistance as the user whose display it attaches to.
         // Look up the user's UID/GID and set HOME/USER/LOGNAME environment.
         // If the user doesn't exist on the system, run as current user with a warning.
-        match lookup_user(&info.username) {
+        let mut log_owner = None;
+        match user {
             Some(user_info) => {
                 tracing::info!(
-                    username = %info.username,
+                    username = %run_as,
                     uid = user_info.uid,
                     gid = user_info.gid,
                     home = %user_info.home,
@@ -598,13 +1157,22 @@ impl SessionManager {
                 );
                 let uid = user_info.uid;
                 let gid = user_info.gid;
-                let username_c = std::ffi::CString::new(info.username.as_str())
+                log_owner = Some((uid, gid));
+                let username_c = std::ffi::CString::new(run_as)
                     .unwrap_or_else(|_| std::ffi::CString::new("nobody").unwrap());
+                let limits = pam_session
+                    .as_ref()
+                    .map(|session| session.rlimits())
+                    .unwrap_or_default();
+                let nice = pam_session.as_ref().and_then(|session| session.opened.nice);
 
                 // SAFETY: pre_exec runs between fork and exec. initgroups sets
                 // supplementary groups (e.g. input, video, render) needed by the agent.
                 unsafe {
                     cmd.pre_exec(move || {
+                        // The PAM session's limits, while still root and
+                        // able to raise hard limits
+                        crate::pam_session::apply(&limits, nice)?;
                         // Set supplementary groups from /etc/group
                         if libc::initgroups(username_c.as_ptr(), gid) != 0 {
                             return Err(std::io::Error::last_os_error());
@@ -620,10 +1188,38 @@ impl SessionManager {
                     });
                 }
 
+                if info.assist.is_none() {
+                    let home = Path::new(&user_info.home);
+                    if self.session_config.create_home && nix::unistd::geteuid().is_root() {
+                        match crate::home::provision(home, uid, gid) {
+                            Ok(true) => tracing::info!(
+                                username = %run_as,
+                                home = %user_info.home,
+                                "Created home directory"
+                            ),
+                            Ok(false) => {}
+                            Err(e) => tracing::warn!(
+                                username = %run_as,
+                                "Failed to create home directory: {e:#}"
+                            ),
+                        }
+                    }
+                    // Desktop settings persist in the home, rather than
+                    // starting over in /tmp each session
+                    if home.is_dir() {
+                        cmd.arg("--persist-desktop");
+                    }
+                }
+
                 cmd.env("HOME", &user_info.home);
-                cmd.env("USER", &info.username);
-                cmd.env("LOGNAME", &info.username);
+                cmd.env("USER", run_as);
+                cmd.env("LOGNAME", run_as);
                 cmd.env("DISPLAY", &display_str);
+                if info.assist.is_some()
+                    && let Some(xauthority) = crate::assist::xauthority(uid, &user_info.home)
+                {
+                    cmd.env("XAUTHORITY", xauthority);
+                }
 
                 // PulseAudio needs XDG_RUNTIME_DIR
                 let runtime_dir = format!("/run/user/{}", user_info.uid);
@@ -637,7 +1233,11 @@ impl SessionManager {
                 }
                 cmd.env("XDG_RUNTIME_DIR", &runtime_dir);
             }
+            None if info.assist.is_some() => {
+                anyhow::bail!("Console user '{run_as}' not found");
+            }
             None => {
+                // Only a server that isn't root gets here (`unusable_account`)
                 tracing::warn!(
                     username = %info.username,
                     "User not found in system, running agent as current user"
@@ -649,20 +1249,23 @@ impl SessionManager {
         // Write agent logs to a dedicated file per session.
         // IMPORTANT: Never use Stdio::piped() without reading the pipe -
         // the 64KB pipe buffer fills up and blocks the agent.
-        let log_dir = "/var/log/beam";
-        let _ = std::fs::create_dir_all(log_dir);
-        let log_path = format!("{log_dir}/agent-{}.log", info.id);
-        let log_file = std::fs::File::create(&log_path)
-            .with_context(|| format!("Failed to create agent log at {log_path}"))?;
+        let (log_file, log_path) =
+            crate::agent_logs::open(&self.logging_config, info.id, log_owner)?;
         let log_file_clone = log_file
             .try_clone()
             .context("Failed to clone agent log file")?;
-        tracing::info!(%log_path, "Agent log file created");
+        tracing::info!(log_path = %log_path.display(), "Agent log file opened");
 
-        let child = cmd
+        let spawned = cmd
             .stdout(Stdio::from(log_file))
             .stderr(Stdio::from(log_file_clone))
-            .spawn()
+            .spawn();
+        if let Some(session) = pam_session {
+            session
+                .hold_for(spawned.as_ref().ok().and_then(Child::id))
+                .await;
+        }
+        let child = spawned
             .with_context(|| format!("Failed to spawn beam-agent for display {}", display_str))?;
 
         tracing::info!(
@@ -675,10 +1278,32 @@ impl SessionManager {
         Ok(child)
     }
 
+    /// The configured agent executable; otherwise `beam-agent` in the
+    /// server's own directory, falling back to the one in PATH.
+    pub fn agent_binary(&self) -> PathBuf {
+        if let Some(path) = &self.agent_config.binary_path {
+            return path.into();
+        }
+        std::env::current_exe()
+            .ok()
+            .and_then(|p| p.parent().map(|parent| parent.join("beam-agent")))
+            .filter(|p| p.exists())
+            .unwrap_or_else(|| "beam-agent".into())
+    }
+
+    /// Whether `pid` is still an agent, as opposed to a recycled PID.
+    pub fn is_agent_process(&self, pid: u32) -> bool {
+        std::fs::read_to_string(format!("/proc/{pid}/comm"))
+            .is_ok_and(|comm| is_agent_comm(comm.trim(), &self.agent_binary()))
+    }
+
     /// Save all active sessions to disk for graceful restart.
     /// Agents are left running — the new server process re-adopts them.
     pub async fn persist_sessions(&self) -> Result<()> {
-        let dir = Path::new(SESSION_DIR);
+        self.persist_sessions_to(Path::new(SESSION_DIR)).await
+    }
+
+    async fn persist_sessions_to(&self, dir: &Path) -> Result<()> {
         std::fs::create_dir_all(dir).context("Failed to create session persistence directory")?;
 
         // Clean old files
@@ -704,10 +1329,14 @@ impl SessionManager {
                 agent_pid: pid,
                 agent_token: managed.agent_token.clone(),
                 release_token: managed.release_token.clone(),
+                client: managed.info.client.clone(),
+                assist: managed.info.assist.clone(),
+                private: managed.private,
+                legacy_agent_token: managed.legacy_agent_token,
             };
             let path = dir.join(format!("{id}.json"));
             let tmp_path = dir.join(format!("{id}.json.tmp"));
-            let data = serde_json::to_string_pretty(&persisted)?;
+            let data = persisted.to_file()?;
 
             // Write with restricted permissions (contains agent token)
             use std::io::Write;
@@ -731,17 +1360,15 @@ impl SessionManager {
     /// Restore sessions from a previous graceful shutdown.
     /// Verifies each agent is still alive. Returns (session_id, agent_pid) pairs.
     pub async fn restore_sessions(&self) -> Vec<(Uuid, u32)> {
-        let dir = Path::new(SESSION_DIR);
+        self.restore_sessions_from(Path::new(SESSION_DIR)).await
+    }
+
+    async fn restore_sessions_from(&self, dir: &Path) -> Vec<(Uuid, u32)> {
         let entries = match std::fs::read_dir(dir) {
             Ok(e) => e,
             Err(_) => return Vec::new(),
         };
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-
         let mut restored = Vec::new();
 
         for entry in entries.flatten() {
@@ -759,10 +1386,10 @@ impl SessionManager {
                 }
             };
 
-            let persisted: PersistedSession = match serde_json::from_str(&data) {
+            let persisted = match PersistedSession::from_file(&data) {
                 Ok(p) => p,
                 Err(e) => {
-                    tracing::warn!(path = %path.display(), "Failed to parse session file: {e}");
+                    tracing::warn!(path = %path.display(), "Failed to parse session file: {e:#}");
                     let _ = std::fs::remove_file(&path);
                     continue;
                 }
@@ -780,15 +1407,6 @@ impl SessionManager {
                 continue;
             }
 
-            // Reserve the display number to avoid double-allocation
-            {
-                let mut pool = self.display_pool.write().await;
-                pool.free.remove(&persisted.display);
-                if persisted.display >= pool.next {
-                    pool.next = persisted.display + 1;
-                }
-            }
-
             let info = SessionInfo {
                 id: persisted.session_id,
                 username: persisted.username.clone(),
@@ -796,29 +1414,21 @@ impl SessionManager {
                 width: persisted.width,
                 height: persisted.height,
                 created_at: persisted.created_at,
+                client: persisted.client.clone(),
+                assist: persisted.assist.clone(),
             };
 
-            // If restoring an old session file without release_token, generate one
-            let release_token = if persisted.release_token.is_empty() {
-                generate_release_token()
-            } else {
-                persisted.release_token
-            };
-
-            let managed = ManagedSession {
+            self.adopt(
                 info,
-                agent_process: None, // orphaned — no Child handle
-                agent_pid: Some(persisted.agent_pid),
-                last_activity: now,
-                agent_token: persisted.agent_token,
-                release_token,
-                grace_generation: Arc::new(AtomicU64::new(0)),
-                restart_count: 0,
-                idle_timeout_override: None, // restored sessions use global default
-            };
-
-            let mut sessions = self.sessions.write().await;
-            sessions.insert(persisted.session_id, managed);
+                persisted.agent_pid,
+                persisted.agent_token,
+                persisted.release_token,
+            )
+            .await;
+            if let Some(session) = self.sessions.write().await.get_mut(&persisted.session_id) {
+                session.private = persisted.private;
+                session.legacy_agent_token = persisted.legacy_agent_token;
+            }
             restored.push((persisted.session_id, persisted.agent_pid));
 
             tracing::info!(
@@ -834,6 +1444,209 @@ impl SessionManager {
 
         restored
     }
+
+    /// Adopt agents a crashed server left running, so their users can
+    /// reconnect, and stop those whose session can't be rebuilt along with
+    /// X servers no session uses. Returns (session_id, agent_pid) pairs of
+    /// the adopted agents.
+    pub async fn reconcile_agents(&self) -> Vec<(Uuid, u32)> {
+        let known: HashSet<u32> = {
+            let sessions = self.sessions.read().await;
+            sessions.values().filter_map(|s| s.agent_pid).collect()
+        };
+        let found = orphans::running_agents(|pid| self.is_agent_process(pid), &known);
+
+        let mut adopted = Vec::new();
+        for agent in found {
+            let pid = agent.pid;
+            let taken = match &agent.session {
+                Some(recovered) => {
+                    let sessions = self.sessions.read().await;
+                    sessions.values().any(|s| {
+                        s.info.id == recovered.info.id
+                            || (s.info.display == recovered.info.display
+                                && s.info.assist.is_none()
+                                && recovered.info.assist.is_none())
+                    })
+                }
+                None => true,
+            };
+            match agent.session {
+                Some(recovered) if !taken => {
+                    let session_id = recovered.info.id;
+                    tracing::info!(
+                        %session_id,
+                        username = %recovered.info.username,
+                        display = recovered.info.display,
+                        pid,
+                        "Adopted agent left running by a previous server"
+                    );
+                    self.adopt(
+                        recovered.info,
+                        pid,
+                        recovered.agent_token,
+                        generate_release_token(),
+                    )
+                    .await;
+                    adopted.push((session_id, pid));
+                }
+                _ => {
+                    tracing::warn!(pid, "Stopping agent that belongs to no session");
+                    orphans::terminate(pid).await;
+                }
+            }
+        }
+
+        for (display_num, pid) in self.stray_x_servers().await {
+            tracing::warn!(
+                display = display_num,
+                pid,
+                "Stopping X server that belongs to no session"
+            );
+            orphans::terminate(pid).await;
+        }
+
+        adopted
+    }
+
+    /// Return display numbers the pool lost track of, such as those of
+    /// agents killed from outside whose sessions never got torn down, and
+    /// stop X servers no session uses. Only what two passes in a row found
+    /// is acted on, so sessions being created or torn down meanwhile are
+    /// left alone. Returns the reclaimed numbers.
+    pub async fn reconcile_displays(&self) -> Vec<u32> {
+        let stray_x: HashSet<(u32, u32)> = self.stray_x_servers().await.into_iter().collect();
+        let in_use = self.displays_in_use().await;
+        let mut pool = self.display_pool.write().await;
+
+        let unaccounted: HashSet<u32> = (pool.start..pool.next)
+            .filter(|n| !in_use.contains(n) && !pool.free.contains(n))
+            .collect();
+        // A display still held by an X server comes back once it is stopped
+        let reclaimed: Vec<u32> = unaccounted
+            .intersection(&pool.unaccounted)
+            .filter(|&&n| !stray_x.iter().any(|&(display, _)| display == n))
+            .copied()
+            .collect();
+        pool.free.extend(&reclaimed);
+        pool.unaccounted = unaccounted
+            .into_iter()
+            .filter(|n| !reclaimed.contains(n))
+            .collect();
+        let stop: Vec<(u32, u32)> = stray_x.intersection(&pool.stray_x).copied().collect();
+        pool.stray_x = stray_x;
+        drop(pool);
+
+        if !reclaimed.is_empty() {
+            tracing::warn!(?reclaimed, "Reclaimed display numbers no session was using");
+        }
+        for (display_num, pid) in stop {
+            tracing::warn!(
+                display = display_num,
+                pid,
+                "Stopping X server that belongs to no session"
+            );
+            orphans::terminate(pid).await;
+        }
+        reclaimed
+    }
+
+    /// The pool, the sessions holding displays and the X servers running,
+    /// for `/api/admin/displays`.
+    pub async fn displays_json(&self) -> serde_json::Value {
+        let owners: HashMap<u32, (Uuid, String)> = {
+            let sessions = self.sessions.read().await;
+            sessions
+                .values()
+                .map(|s| (s.info.display, (s.info.id, s.info.username.clone())))
+                .collect()
+        };
+        let pool = self.display_pool.read().await;
+        let sorted = |set: &HashSet<u32>| {
+            let mut list: Vec<u32> = set.iter().copied().collect();
+            list.sort_unstable();
+            list
+        };
+        let mut sessions: Vec<_> = owners.iter().collect();
+        sessions.sort_unstable_by_key(|(display, _)| **display);
+        let mut x_servers = orphans::x_servers(pool.start);
+        x_servers.sort_unstable();
+        serde_json::json!({
+            "start": pool.start,
+            "next": pool.next,
+            "free": sorted(&pool.free),
+            "unaccounted": sorted(&pool.unaccounted),
+            "sessions": sessions
+                .into_iter()
+                .map(|(display, (id, username))| serde_json::json!({
+                    "display": display,
+                    "session_id": id,
+                    "username": username,
+                }))
+                .collect::<Vec<_>>(),
+            "x_servers": x_servers
+                .into_iter()
+                .map(|(display, pid)| serde_json::json!({
+                    "display": display,
+                    "pid": pid,
+                    "session_id": owners.get(&display).map(|(id, _)| id),
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    async fn displays_in_use(&self) -> HashSet<u32> {
+        let sessions = self.sessions.read().await;
+        sessions.values().map(|s| s.info.display).collect()
+    }
+
+    /// X servers on pool displays that no session uses; the console an
+    /// assist session attaches to is never one of them
+    async fn stray_x_servers(&self) -> Vec<(u32, u32)> {
+        let start = self.display_pool.read().await.start;
+        let in_use = self.displays_in_use().await;
+        let console = self.assist_config.display_number();
+        orphans::x_servers(start)
+            .into_iter()
+            .filter(|&(display, _)| !in_use.contains(&display) && Some(display) != console)
+            .collect()
+    }
+
+    /// Take over a running agent's session, keeping its display number
+    /// from being handed out again.
+    async fn adopt(
+        &self,
+        info: SessionInfo,
+        agent_pid: u32,
+        agent_token: String,
+        release_token: String,
+    ) {
+        if info.assist.is_none() {
+            self.display_pool.write().await.reserve(info.display);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let managed = ManagedSession {
+            info,
+            agent_process: None, // orphaned — no Child handle
+            agent_pid: Some(agent_pid),
+            last_activity: now,
+            agent_token,
+            release_token,
+            grace_generation: Arc::new(AtomicU64::new(0)),
+            restart_count: 0,
+            idle_timeout_override: None, // adopted sessions use global default
+            system_combos_override: None,
+            initial_stream: None,
+            desktop: None,
+            private: false,
+            legacy_agent_token: false,
+        };
+        let mut sessions = self.sessions.write().await;
+        sessions.insert(managed.info.id, managed);
+    }
 }
 
 struct UserInfo {
@@ -842,6 +1655,36 @@ struct UserInfo {
     home: String,
 }
 
+/// Why an agent can't run as `user`, the session user's account (None if
+/// there is none). Agents never run as root: not for an account of uid 0,
+/// nor as a root server's own user for a name without an account. A
+/// users-file login, which the file alone vouches for, needs an account.
+fn unusable_account(
+    user: Option<&UserInfo>,
+    users_file: bool,
+    server_is_root: bool,
+) -> Option<&'static str> {
+    match user {
+        Some(user) if user.uid == 0 => Some("sessions never run as root"),
+        Some(_) => None,
+        None if users_file => Some("users-file users need a system account of the same name"),
+        None if server_is_root => Some("no system account of that name"),
+        None => None,
+    }
+}
+
+/// A wrapper that execs `beam-agent` ends up named after it, one that
+/// runs it as a child keeps its own name. The kernel keeps the first 15
+/// bytes of either.
+fn is_agent_comm(comm: &str, binary: &Path) -> bool {
+    const COMM_LEN: usize = 15;
+    comm == "beam-agent"
+        || binary.file_name().is_some_and(|name| {
+            let name = name.as_encoded_bytes();
+            name[..name.len().min(COMM_LEN)] == *comm.as_bytes()
+        })
+}
+
 /// Look up a Unix user by name, returning UID, GID, and home directory.
 /// Uses getpwnam via nix, which supports NSS (LDAP, SSSD, etc.).
 fn lookup_user(username: &str) -> Option<UserInfo> {
@@ -857,6 +1700,18 @@ fn lookup_user(username: &str) -> Option<UserInfo> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn agent_process_names() {
+        let default = Path::new("beam-agent");
+        assert!(is_agent_comm("beam-agent", default));
+        assert!(!is_agent_comm("bash", default));
+
+        let wrapper = Path::new("/opt/beam/bin/beam-agent-wrapper.sh");
+        assert!(is_agent_comm("beam-agent-wrap", wrapper));
+        assert!(is_agent_comm("beam-agent", wrapper));
+        assert!(!is_agent_comm("beam-agent-wrapper.sh", wrapper));
+    }
+
     #[test]
     fn lookup_root_user() {
         // root always exists on Linux
@@ -868,6 +1723,88 @@ mod tests {
         assert_eq!(user.home, "/root");
     }
 
+    #[tokio::test]
+    async fn children_never_see_config_overrides() {
+        let mut cmd = Command::new("env");
+        // As if the server had been started with them
+        cmd.env("BEAM_SERVER__JWT_SECRET", "secret")
+            .env("BEAM_SESSION__IDLE_TIMEOUT", "60")
+            .env("BEAM_SERVER", "wss://127.0.0.1:8444")
+            .env("NOTIFY_SOCKET", "/run/systemd/notify");
+        hide_env(
+            &mut cmd,
+            [
+                "BEAM_SERVER__JWT_SECRET".into(),
+                "BEAM_SESSION__IDLE_TIMEOUT".into(),
+                "BEAM_SERVER".into(),
+            ],
+        );
+        hide_server_env(&mut cmd);
+        let output = cmd.output().await.unwrap();
+        let env = String::from_utf8(output.stdout).unwrap();
+        let names: Vec<&str> = env
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .map(|(n, _)| n)
+            .collect();
+        assert!(
+            !names.iter().any(|n| beam_protocol::is_env_override(n)),
+            "{env}"
+        );
+        assert!(!names.contains(&"NOTIFY_SOCKET"));
+        // Other BEAM_ variables are no config override
+        assert!(names.contains(&"BEAM_SERVER"));
+    }
+
+    #[test]
+    fn agents_never_run_as_root() {
+        let root = lookup_user("root");
+        assert!(unusable_account(root.as_ref(), false, true).is_some());
+        assert!(unusable_account(root.as_ref(), true, false).is_some());
+
+        let alice = UserInfo {
+            uid: 1000,
+            gid: 1000,
+            home: "/home/alice".to_string(),
+        };
+        assert_eq!(unusable_account(Some(&alice), true, true), None);
+        // Without an account only a non-root server for a PAM login, as
+        // in development, runs the agent as itself
+        assert!(unusable_account(None, true, true).is_some());
+        assert!(unusable_account(None, true, false).is_some());
+        assert!(unusable_account(None, false, true).is_some());
+        assert_eq!(unusable_account(None, false, false), None);
+    }
+
+    #[tokio::test]
+    async fn users_file_logins_without_an_account_are_refused() {
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        )
+        .with_auth_config(beam_protocol::AuthConfig {
+            users_file: Some("/etc/beam/users".to_string()),
+            ..Default::default()
+        });
+        let req: AuthRequest = serde_json::from_value(serde_json::json!({
+            "username": "beam_nonexistent_user_12345",
+            "password": "",
+        }))
+        .unwrap();
+        let err = manager
+            .create_session(&req, "wss://127.0.0.1:8444", 10)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<UnusableAccount>().is_some(), "{err:#}");
+        // Nothing is left reserved
+        assert!(manager.list_sessions().await.is_empty());
+    }
+
     #[test]
     fn lookup_nonexistent_user() {
         let user = lookup_user("beam_nonexistent_user_12345");
@@ -882,6 +1819,67 @@ mod tests {
         assert_eq!(pool.allocate(), 12);
     }
 
+    #[test]
+    fn display_pool_reserves_displays_in_use() {
+        let mut pool = DisplayPool::new(10);
+        pool.reserve(12);
+        let mut next: Vec<u32> = (0..3).map(|_| pool.allocate()).collect();
+        next.sort();
+        assert_eq!(next, [10, 11, 13]);
+        pool.reserve(11);
+        pool.release(11);
+        assert_eq!(pool.allocate(), 11);
+    }
+
+    #[tokio::test]
+    async fn lost_displays_are_reclaimed_on_the_second_pass() {
+        let manager = test_manager();
+        let (lost, held) = {
+            let mut pool = manager.display_pool.write().await;
+            (pool.allocate(), pool.allocate())
+        };
+        manager.sessions.write().await.insert(
+            Uuid::new_v4(),
+            ManagedSession {
+                info: SessionInfo {
+                    id: Uuid::new_v4(),
+                    username: "test".to_string(),
+                    display: held,
+                    width: 1920,
+                    height: 1080,
+                    created_at: 0,
+                    client: None,
+                    assist: None,
+                },
+                agent_process: None,
+                agent_pid: None,
+                last_activity: 0,
+                agent_token: "token".to_string(),
+                release_token: "release".to_string(),
+                grace_generation: Arc::new(AtomicU64::new(0)),
+                restart_count: 0,
+                idle_timeout_override: None,
+                system_combos_override: None,
+                initial_stream: None,
+                desktop: None,
+                private: false,
+                legacy_agent_token: false,
+            },
+        );
+
+        // The first pass only notes the number; a teardown may be under way
+        assert!(manager.reconcile_displays().await.is_empty());
+        let displays = manager.displays_json().await;
+        assert_eq!(displays["unaccounted"], serde_json::json!([lost]));
+        assert_eq!(displays["sessions"][0]["display"], held);
+
+        assert_eq!(manager.reconcile_displays().await, [lost]);
+        let displays = manager.displays_json().await;
+        assert_eq!(displays["free"], serde_json::json!([lost]));
+        assert_eq!(displays["unaccounted"], serde_json::json!([]));
+        assert_eq!(manager.display_pool.write().await.allocate(), lost);
+    }
+
     #[test]
     fn display_pool_recycles() {
         let mut pool = DisplayPool::new(10);
@@ -909,8 +1907,15 @@ mod tests {
 
     #[tokio::test]
     async fn verify_agent_token_rejects_wrong_token() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
         // Non-existent session should reject
         assert!(!manager.verify_agent_token(id, "fake-token").await);
@@ -941,8 +1946,15 @@ mod tests {
 
     #[tokio::test]
     async fn verify_release_token_rejects_wrong_token() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
         // Non-existent session should reject
         assert!(!manager.verify_release_token(id, "fake-token").await);
@@ -1021,8 +2033,15 @@ mod tests {
 
     #[tokio::test]
     async fn increment_restart_count_returns_new_count() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
 
         // Insert a session manually
@@ -1038,6 +2057,8 @@ mod tests {
                         width: 1920,
                         height: 1080,
                         created_at: 0,
+                        client: None,
+                        assist: None,
                     },
                     agent_process: None,
                     agent_pid: None,
@@ -1047,6 +2068,11 @@ mod tests {
                     grace_generation: Arc::new(AtomicU64::new(0)),
                     restart_count: 0,
                     idle_timeout_override: None,
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
                 },
             );
         }
@@ -1061,26 +2087,143 @@ mod tests {
         assert_eq!(manager.get_restart_count(id).await, Some(3));
     }
 
+    #[tokio::test]
+    async fn reserve_session_enforces_max_sessions() {
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
+        let req: AuthRequest = serde_json::from_value(serde_json::json!({
+            "username": "test",
+            "password": "",
+            "viewport_width": 1280,
+            "viewport_height": 100,
+        }))
+        .unwrap();
+
+        let (first, token) = manager.reserve_session(&req, None, 2).await.unwrap();
+        assert_eq!((first.width, first.height), (1280, 1080));
+        assert!(manager.verify_agent_token(first.id, &token).await);
+        let (second, _) = manager.reserve_session(&req, None, 2).await.unwrap();
+        assert_eq!(second.display, first.display + 1);
+        assert!(manager.reserve_session(&req, None, 2).await.is_err());
+        assert_eq!(manager.list_sessions().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn link_probe_caps_the_session_bitrate() {
+        let manager = test_manager();
+        let req: AuthRequest =
+            serde_json::from_value(serde_json::json!({ "username": "test", "password": "" }))
+                .unwrap();
+        let (session, _) = manager.reserve_session(&req, None, 2).await.unwrap();
+        assert_eq!(manager.bitrate_ceilings().await, [(session.id, 0)]);
+
+        let probe = NetTestResult {
+            kbps: 10_000,
+            rtt_ms: 40,
+        };
+        let stream = manager.fit_to_link(session.id, probe).await.unwrap();
+        assert_eq!(stream.bitrate, 7_000);
+        assert_eq!(manager.bitrate_ceilings().await, [(session.id, 7_000)]);
+        assert!(manager.fit_to_link(Uuid::new_v4(), probe).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn assist_session_takes_the_console_display() {
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        )
+        .with_assist_config(beam_protocol::AssistConfig {
+            enabled: true,
+            user: Some("alice".to_string()),
+            ..Default::default()
+        });
+        let assist: AuthRequest = serde_json::from_value(serde_json::json!({
+            "username": "admin",
+            "password": "",
+            "assist": true,
+        }))
+        .unwrap();
+        let desktop: AuthRequest =
+            serde_json::from_value(serde_json::json!({ "username": "admin", "password": "" }))
+                .unwrap();
+
+        let (session, _) = manager.reserve_session(&assist, None, 8).await.unwrap();
+        assert_eq!(session.display, 0);
+        assert_eq!(session.assist.as_deref(), Some("alice"));
+        // One console, one helper at a time
+        assert!(manager.reserve_session(&assist, None, 8).await.is_err());
+
+        assert!(manager.find_for_login("admin", false).await.is_none());
+        let (own, _) = manager.reserve_session(&desktop, None, 8).await.unwrap();
+        assert_eq!(own.display, 100);
+        assert_eq!(
+            manager.find_for_login("admin", false).await.unwrap().id,
+            own.id
+        );
+        assert_eq!(
+            manager.find_for_login("admin", true).await.unwrap().id,
+            session.id
+        );
+
+        // The console display never joins the pool
+        manager.destroy_session(session.id).await.unwrap();
+        assert_eq!(manager.display_pool.write().await.allocate(), 101);
+    }
+
     #[tokio::test]
     async fn increment_restart_count_nonexistent_session() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
         assert_eq!(manager.increment_restart_count(id).await, None);
     }
 
     #[tokio::test]
     async fn get_restart_count_nonexistent_session() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
         assert_eq!(manager.get_restart_count(id).await, None);
     }
 
     #[tokio::test]
     async fn restart_count_starts_at_zero() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
 
         // Insert a session
@@ -1096,6 +2239,8 @@ mod tests {
                         width: 1920,
                         height: 1080,
                         created_at: 0,
+                        client: None,
+                        assist: None,
                     },
                     agent_process: None,
                     agent_pid: None,
@@ -1105,6 +2250,11 @@ mod tests {
                     grace_generation: Arc::new(AtomicU64::new(0)),
                     restart_count: 0,
                     idle_timeout_override: None,
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
                 },
             );
         }
@@ -1114,8 +2264,15 @@ mod tests {
 
     #[tokio::test]
     async fn restart_count_independent_per_session() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id1 = Uuid::new_v4();
         let id2 = Uuid::new_v4();
 
@@ -1133,6 +2290,8 @@ mod tests {
                             width: 1920,
                             height: 1080,
                             created_at: 0,
+                            client: None,
+                            assist: None,
                         },
                         agent_process: None,
                         agent_pid: None,
@@ -1142,6 +2301,11 @@ mod tests {
                         grace_generation: Arc::new(AtomicU64::new(0)),
                         restart_count: 0,
                         idle_timeout_override: None,
+                        system_combos_override: None,
+                        initial_stream: None,
+                        desktop: None,
+                        private: false,
+                        legacy_agent_token: false,
                     },
                 );
             }
@@ -1160,8 +2324,15 @@ mod tests {
 
     #[tokio::test]
     async fn stale_sessions_uses_per_session_timeout() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
 
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -1186,6 +2357,8 @@ mod tests {
                         width: 1920,
                         height: 1080,
                         created_at: 0,
+                        client: None,
+                        assist: None,
                     },
                     agent_process: None,
                     agent_pid: None,
@@ -1195,6 +2368,11 @@ mod tests {
                     grace_generation: Arc::new(AtomicU64::new(0)),
                     restart_count: 0,
                     idle_timeout_override: Some(60),
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
                 },
             );
 
@@ -1209,6 +2387,8 @@ mod tests {
                         width: 1920,
                         height: 1080,
                         created_at: 0,
+                        client: None,
+                        assist: None,
                     },
                     agent_process: None,
                     agent_pid: None,
@@ -1218,6 +2398,11 @@ mod tests {
                     grace_generation: Arc::new(AtomicU64::new(0)),
                     restart_count: 0,
                     idle_timeout_override: Some(86400),
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
                 },
             );
 
@@ -1232,6 +2417,8 @@ mod tests {
                         width: 1920,
                         height: 1080,
                         created_at: 0,
+                        client: None,
+                        assist: None,
                     },
                     agent_process: None,
                     agent_pid: None,
@@ -1241,6 +2428,11 @@ mod tests {
                     grace_generation: Arc::new(AtomicU64::new(0)),
                     restart_count: 0,
                     idle_timeout_override: None,
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
                 },
             );
         }
@@ -1262,8 +2454,15 @@ mod tests {
 
     #[tokio::test]
     async fn get_idle_timeout_returns_override_when_set() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
 
         {
@@ -1278,6 +2477,8 @@ mod tests {
                         width: 1920,
                         height: 1080,
                         created_at: 0,
+                        client: None,
+                        assist: None,
                     },
                     agent_process: None,
                     agent_pid: None,
@@ -1287,6 +2488,11 @@ mod tests {
                     grace_generation: Arc::new(AtomicU64::new(0)),
                     restart_count: 0,
                     idle_timeout_override: Some(7200),
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
                 },
             );
         }
@@ -1296,8 +2502,15 @@ mod tests {
 
     #[tokio::test]
     async fn get_idle_timeout_returns_global_when_no_override() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
 
         {
@@ -1312,6 +2525,8 @@ mod tests {
                         width: 1920,
                         height: 1080,
                         created_at: 0,
+                        client: None,
+                        assist: None,
                     },
                     agent_process: None,
                     agent_pid: None,
@@ -1321,6 +2536,11 @@ mod tests {
                     grace_generation: Arc::new(AtomicU64::new(0)),
                     restart_count: 0,
                     idle_timeout_override: None,
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
                 },
             );
         }
@@ -1330,9 +2550,227 @@ mod tests {
 
     #[tokio::test]
     async fn get_idle_timeout_nonexistent_returns_global() {
-        let manager =
-            SessionManager::new(100, 1920, 1080, None, beam_protocol::VideoConfig::default());
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
         let id = Uuid::new_v4();
         assert_eq!(manager.get_idle_timeout(id, 3600).await, 3600);
     }
+
+    #[tokio::test]
+    async fn system_combos_login_only_narrows_the_config() {
+        let manager = SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
+        let id = Uuid::new_v4();
+
+        {
+            let mut sessions = manager.sessions.write().await;
+            sessions.insert(
+                id,
+                ManagedSession {
+                    info: SessionInfo {
+                        id,
+                        username: "test".to_string(),
+                        display: 100,
+                        width: 1920,
+                        height: 1080,
+                        created_at: 0,
+                        client: None,
+                        assist: None,
+                    },
+                    agent_process: None,
+                    agent_pid: None,
+                    last_activity: 0,
+                    agent_token: "token".to_string(),
+                    release_token: "release".to_string(),
+                    grace_generation: Arc::new(AtomicU64::new(0)),
+                    restart_count: 0,
+                    idle_timeout_override: None,
+                    system_combos_override: None,
+                    initial_stream: None,
+                    desktop: None,
+                    private: false,
+                    legacy_agent_token: false,
+                },
+            );
+        }
+
+        assert!(!manager.allows_system_combos(id, false).await);
+        assert!(manager.allows_system_combos(id, true).await);
+
+        // A login can't turn on what the operator turned off
+        manager.set_system_combos(id, true).await;
+        assert!(!manager.allows_system_combos(id, false).await);
+        assert!(manager.allows_system_combos(id, true).await);
+        manager.set_system_combos(id, false).await;
+        assert!(!manager.allows_system_combos(id, true).await);
+
+        // Unknown sessions get the config
+        assert!(!manager.allows_system_combos(Uuid::new_v4(), false).await);
+        assert!(manager.allows_system_combos(Uuid::new_v4(), true).await);
+    }
+
+    fn restore_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("beam-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn test_manager() -> SessionManager {
+        SessionManager::new(
+            100,
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn restores_version_1_session_files() {
+        let dir = restore_dir("restore-v1");
+        let id = Uuid::new_v4();
+        // The test process stands in for a live agent
+        let pid = std::process::id();
+        let v1 = serde_json::json!({
+            "session_id": id,
+            "username": "alice",
+            "display": 105,
+            "width": 1280,
+            "height": 720,
+            "created_at": 1700000000,
+            "agent_pid": pid,
+            "agent_token": "agent-token",
+        });
+        std::fs::write(dir.join(format!("{id}.json")), v1.to_string()).unwrap();
+
+        let manager = test_manager();
+        assert_eq!(manager.restore_sessions_from(&dir).await, [(id, pid)]);
+        let session = manager.get_session(id).await.unwrap();
+        assert_eq!(session.username, "alice");
+        assert_eq!(session.display, 105);
+        assert!(manager.verify_agent_token(id, "agent-token").await);
+        // Its agent came before tickets
+        assert!(manager.verify_legacy_agent_token(id, "agent-token").await);
+        assert!(!manager.verify_legacy_agent_token(id, "not-a-token").await);
+        // Migrated files get the release token they never had
+        assert_eq!(manager.get_release_token(id).await.unwrap().len(), 16);
+        // Displays of adopted agents are not handed out again
+        assert!(manager.display_pool.read().await.next > 105);
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+
+        // ...and still does after the next restart
+        manager.persist_sessions_to(&dir).await.unwrap();
+        let restarted = test_manager();
+        restarted.restore_sessions_from(&dir).await;
+        assert!(restarted.verify_legacy_agent_token(id, "agent-token").await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn persisted_sessions_round_trip_and_newer_fields_are_ignored() {
+        let dir = restore_dir("restore-v2");
+        let manager = test_manager();
+        let id = Uuid::new_v4();
+        let pid = std::process::id();
+        manager.sessions.write().await.insert(
+            id,
+            ManagedSession {
+                info: SessionInfo {
+                    id,
+                    username: "bob".to_string(),
+                    display: 101,
+                    width: 1920,
+                    height: 1080,
+                    created_at: 0,
+                    client: None,
+                    assist: None,
+                },
+                agent_process: None,
+                agent_pid: Some(pid),
+                last_activity: 0,
+                agent_token: "token".to_string(),
+                release_token: "release".to_string(),
+                grace_generation: Arc::new(AtomicU64::new(0)),
+                restart_count: 0,
+                idle_timeout_override: None,
+                system_combos_override: None,
+                initial_stream: None,
+                desktop: None,
+                private: false,
+                legacy_agent_token: false,
+            },
+        );
+        manager.persist_sessions_to(&dir).await.unwrap();
+        let path = dir.join(format!("{id}.json"));
+        let mut file: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(file["version"], SESSION_FORMAT_VERSION);
+
+        // As a future server might write it
+        file["version"] = serde_json::json!(SESSION_FORMAT_VERSION + 1);
+        file["session"]["pinned_gpu"] = serde_json::json!(1);
+        std::fs::write(&path, file.to_string()).unwrap();
+
+        let restored = test_manager();
+        assert_eq!(restored.restore_sessions_from(&dir).await, [(id, pid)]);
+        assert!(restored.verify_release_token(id, "release").await);
+        assert!(!restored.is_private(id).await);
+        assert!(!restored.verify_legacy_agent_token(id, "token").await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn privacy_survives_a_restart() {
+        let dir = restore_dir("restore-private");
+        let manager = test_manager();
+        let id = Uuid::new_v4();
+        manager
+            .adopt(
+                SessionInfo {
+                    id,
+                    username: "bob".to_string(),
+                    display: 101,
+                    width: 1920,
+                    height: 1080,
+                    created_at: 0,
+                    client: None,
+                    assist: None,
+                },
+                std::process::id(),
+                "token".to_string(),
+                "release".to_string(),
+            )
+            .await;
+        manager.set_private(id, true).await;
+        manager.persist_sessions_to(&dir).await.unwrap();
+
+        let restored = test_manager();
+        restored.restore_sessions_from(&dir).await;
+        assert!(restored.is_private(id).await);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unsupported_session_files_are_rejected() {
+        assert!(PersistedSession::from_file(r#"{"version": 1, "session": {}}"#).is_err());
+        assert!(PersistedSession::from_file(r#"{"version": "2"}"#).is_err());
+        assert!(PersistedSession::from_file(r#"{"version": 2}"#).is_err());
+    }
 }
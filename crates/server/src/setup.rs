@@ -0,0 +1,414 @@
+//! First-run setup mode.
+//!
+//! Without a config file the server doesn't start on defaults but in setup
+//! mode: on the default address, with a self-signed certificate, serving
+//! nothing but `/api/setup`. A token logged at startup guards it, so
+//! whoever finds the port first can't configure the server. Posting a setup
+//! writes the config file, and the server starts up normally from it.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use beam_protocol::{BeamConfig, ServerConfig, sd_notify};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::{Mutex, Notify};
+
+use crate::session::constant_time_eq;
+
+const HEADER: &str = "\
+# Written by beam-server first-run setup. Every other setting has its
+# default; see config/beam.toml in the Beam sources for all of them.
+
+";
+
+/// What `POST /api/setup` takes. Anything left out keeps its default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetupRequest {
+    pub bind: Option<String>,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: TlsChoice,
+    /// Users allowed into the admin panel
+    #[serde(default)]
+    pub admin_users: Vec<String>,
+    pub web_root: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum TlsChoice {
+    /// Generated on first start and kept in /var/lib/beam
+    #[default]
+    SelfSigned,
+    /// A certificate and key already on the server
+    Files { cert: String, key: String },
+}
+
+/// The config file `req` describes, and the problems `validate` finds
+/// with it ("ERROR:" ones mean it must not be written).
+pub fn render_config(req: &SetupRequest) -> (String, Vec<String>) {
+    let mut issues = Vec::new();
+    for user in &req.admin_users {
+        if !crate::web::is_valid_username(user) {
+            issues.push(format!(
+                "ERROR: admin user '{user}' is not a valid username."
+            ));
+        }
+    }
+
+    let mut server = toml::Table::new();
+    let mut set = |key: &str, value: toml::Value| {
+        server.insert(key.to_string(), value);
+    };
+    if let Some(bind) = &req.bind {
+        set("bind", bind.clone().into());
+    }
+    if let Some(port) = req.port {
+        set("port", i64::from(port).into());
+    }
+    if let TlsChoice::Files { cert, key } = &req.tls {
+        set("tls_cert", cert.clone().into());
+        set("tls_key", key.clone().into());
+    }
+    if let Some(web_root) = &req.web_root {
+        set("web_root", web_root.clone().into());
+    }
+    if !req.admin_users.is_empty() {
+        set("admin_users", req.admin_users.clone().into());
+    }
+    let mut table = toml::Table::new();
+    table.insert("server".into(), server.into());
+    let text = format!("{HEADER}{table}");
+
+    // Checked the way startup will read it
+    match toml::from_str::<BeamConfig>(&text) {
+        Ok(config) => {
+            let server = &config.server;
+            if format!("{}:{}", server.bind, server.port)
+                .parse::<SocketAddr>()
+                .is_err()
+            {
+                issues.push(format!(
+                    "ERROR: bind '{}' is not an IP address.",
+                    server.bind
+                ));
+            }
+            if let Err(found) = config.validate() {
+                issues.extend(found);
+            }
+        }
+        Err(e) => issues.push(format!("ERROR: {e}")),
+    }
+    (text, issues)
+}
+
+/// Write `text` to `path` through a temporary file in the same directory,
+/// so a crash never leaves a half-written config behind.
+fn write_config(path: &Path, text: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("toml.setup");
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(&tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    file.write_all(text.as_bytes())
+        .and_then(|()| file.set_permissions(std::fs::Permissions::from_mode(0o644)))
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path)
+        .with_context(|| format!("Failed to move the config into {}", path.display()))
+}
+
+struct Setup {
+    config_path: PathBuf,
+    token: String,
+    /// `--port`, which wins over the port in the config
+    port_override: Option<u16>,
+    /// Whether the config has been written; held while writing it
+    written: Mutex<bool>,
+    done: Notify,
+}
+
+impl Setup {
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+    }
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({ "error": "Setup token required (see the server log)" })),
+    )
+        .into_response()
+}
+
+fn router(setup: Arc<Setup>) -> Router {
+    Router::new()
+        .route("/api/setup", get(setup_defaults).post(apply_setup))
+        .with_state(setup)
+}
+
+/// What setup would write if nothing were changed.
+async fn setup_defaults(State(setup): State<Arc<Setup>>, headers: HeaderMap) -> Response {
+    if !setup.authorized(&headers) {
+        return unauthorized();
+    }
+    let defaults = ServerConfig::default();
+    Json(json!({
+        "config_path": setup.config_path,
+        "defaults": {
+            "bind": defaults.bind,
+            "port": defaults.port,
+            "tls": { "mode": "self_signed" },
+            "admin_users": defaults.admin_users,
+            "web_root": defaults.web_root,
+        },
+    }))
+    .into_response()
+}
+
+async fn apply_setup(State(setup): State<Arc<Setup>>, headers: HeaderMap, body: Bytes) -> Response {
+    if !setup.authorized(&headers) {
+        return unauthorized();
+    }
+    let req: SetupRequest = match serde_json::from_slice(&body) {
+        Ok(req) => req,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": format!("Invalid request: {e}") })),
+            )
+                .into_response();
+        }
+    };
+    let (text, issues) = render_config(&req);
+    if issues.iter().any(|i| i.starts_with("ERROR:")) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "Invalid setup", "issues": issues })),
+        )
+            .into_response();
+    }
+
+    let mut written = setup.written.lock().await;
+    // Someone may have put a config in place by hand meanwhile
+    if *written || setup.config_path.exists() {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "The config file already exists" })),
+        )
+            .into_response();
+    }
+    let path = setup.config_path.clone();
+    let result = tokio::task::spawn_blocking(move || write_config(&path, &text)).await;
+    if let Err(e) = result
+        .map_err(anyhow::Error::from)
+        .and_then(std::convert::identity)
+    {
+        tracing::error!("First-run setup failed to write the config: {e:#}");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": format!("{e:#}") })),
+        )
+            .into_response();
+    }
+    *written = true;
+    tracing::info!(target: "audit", event = "setup_completed", path = %setup.config_path.display(), "First-run setup wrote the config");
+    setup.done.notify_one();
+
+    let port = setup
+        .port_override
+        .or(req.port)
+        .unwrap_or_else(|| ServerConfig::default().port);
+    (
+        StatusCode::CREATED,
+        Json(json!({
+            "config_path": setup.config_path,
+            "port": port,
+            "warnings": issues,
+        })),
+    )
+        .into_response()
+}
+
+/// Serve the setup API until a config has been written to `config_path`.
+/// Returns false if the server was told to stop first.
+pub async fn run(config_path: &Path, port_override: Option<u16>) -> Result<bool> {
+    let defaults = ServerConfig::default();
+    let port = port_override.unwrap_or(defaults.port);
+    let bind_addr: SocketAddr = format!("{}:{port}", defaults.bind)
+        .parse()
+        .context("Invalid bind address")?;
+    let tls = crate::tls::build_tls_config(None, None)?;
+    let acceptor = crate::tls::make_acceptor(tls.config);
+
+    let setup = Arc::new(Setup {
+        config_path: config_path.to_path_buf(),
+        token: crate::auth::generate_secret(),
+        port_override,
+        written: Mutex::new(false),
+        done: Notify::new(),
+    });
+    let app = router(Arc::clone(&setup));
+
+    let listener = TcpListener::bind(bind_addr)
+        .await
+        .with_context(|| format!("Failed to bind to {bind_addr}"))?;
+    tracing::warn!(
+        "No config file at {}, starting first-run setup",
+        config_path.display()
+    );
+    tracing::info!("===========================================");
+    tracing::info!("  Beam first-run setup on https://{bind_addr}/api/setup");
+    tracing::info!("  Setup token: {}", setup.token);
+    tracing::info!("===========================================");
+    sd_notify::ready("Waiting for first-run setup");
+
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    let mut sd_watchdog = sd_notify::watchdog_interval().map(tokio::time::interval);
+    loop {
+        tokio::select! {
+            result = listener.accept() => match result {
+                Ok((stream, peer_addr)) => {
+                    crate::serve_connection(acceptor.clone(), app.clone(), stream, peer_addr);
+                }
+                Err(e) => tracing::warn!("Failed to accept TCP connection: {e}"),
+            },
+            _ = setup.done.notified() => {
+                tracing::info!("Setup complete, starting with {}", config_path.display());
+                return Ok(true);
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(false),
+            _ = sigterm.recv() => return Ok(false),
+            _ = async { sd_watchdog.as_mut().unwrap().tick().await }, if sd_watchdog.is_some() => {
+                sd_notify::watchdog();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn test_setup(dir: &Path) -> Arc<Setup> {
+        Arc::new(Setup {
+            config_path: dir.join("beam.toml"),
+            token: "t0ken".into(),
+            port_override: None,
+            written: Mutex::new(false),
+            done: Notify::new(),
+        })
+    }
+
+    fn post(body: serde_json::Value, token: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/api/setup")
+            .header("authorization", format!("Bearer {token}"))
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[test]
+    fn renders_only_what_was_chosen() {
+        let req = SetupRequest {
+            port: Some(9443),
+            tls: TlsChoice::Files {
+                cert: "/etc/beam/cert.pem".into(),
+                key: "/etc/beam/key.pem".into(),
+            },
+            admin_users: vec!["alice".into()],
+            ..Default::default()
+        };
+        let (text, _) = render_config(&req);
+        let config: BeamConfig = toml::from_str(&text).unwrap();
+        assert_eq!(config.server.port, 9443);
+        assert_eq!(config.server.tls_key.as_deref(), Some("/etc/beam/key.pem"));
+        assert_eq!(config.server.admin_users, ["alice"]);
+        assert!(!text.contains("bind"));
+        assert!(!text.contains("[video]"));
+    }
+
+    #[test]
+    fn rejects_what_startup_would() {
+        let req = SetupRequest {
+            bind: Some("example.com".into()),
+            port: Some(0),
+            admin_users: vec!["bad user".into()],
+            ..Default::default()
+        };
+        let (_, issues) = render_config(&req);
+        let errors: Vec<_> = issues.iter().filter(|i| i.starts_with("ERROR:")).collect();
+        assert_eq!(errors.len(), 3, "{issues:?}");
+    }
+
+    #[tokio::test]
+    async fn writes_the_config_once() {
+        let dir = std::env::temp_dir().join(format!("beam-setup-{}", std::process::id()));
+        let setup = test_setup(&dir);
+        let app = router(Arc::clone(&setup));
+        let body = json!({ "port": 9443, "admin_users": ["alice"] });
+
+        let response = app
+            .clone()
+            .oneshot(post(body.clone(), "wrong"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .clone()
+            .oneshot(post(json!({ "port": 0 }), "t0ken"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!setup.config_path.exists());
+
+        let response = app
+            .clone()
+            .oneshot(post(body.clone(), "t0ken"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let text = std::fs::read_to_string(&setup.config_path).unwrap();
+        let config: BeamConfig = toml::from_str(&text).unwrap();
+        assert_eq!(config.server.admin_users, ["alice"]);
+        // The server is waiting for this to start up
+        tokio::time::timeout(std::time::Duration::from_secs(1), setup.done.notified())
+            .await
+            .unwrap();
+
+        let response = app.oneshot(post(body, "t0ken")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! View-only share links.
+//!
+//! A session's owner mints a time-limited token (`POST /api/sessions/{id}/share`)
+//! that lets someone without an account watch the desktop through
+//! `/api/share/{token}/ws`. Viewers get the video and audio stream only;
+//! nothing they send reaches the agent.
+//!
+//! Links are kept in memory with the session's signaling channel, so they
+//! end with the session and don't survive a server restart.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, ensure};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::session::constant_time_eq;
+
+/// Live links per session
+const MAX_LINKS: usize = 8;
+/// Viewers watching one session at once, across all its links
+const MAX_VIEWERS: usize = 8;
+
+struct ShareLink {
+    id: Uuid,
+    token: String,
+    created_at: u64,
+    expires_at: u64,
+    expires: Instant,
+}
+
+#[derive(Default)]
+pub struct ShareLinks {
+    links: Mutex<Vec<ShareLink>>,
+    /// Woken whenever links are revoked, so viewers can check theirs
+    revoked: Notify,
+    viewers: AtomicUsize,
+}
+
+/// Holds a viewer slot; released on drop.
+pub struct ViewerSlot<'a>(&'a ShareLinks);
+
+impl Drop for ViewerSlot<'_> {
+    fn drop(&mut self) {
+        self.0.viewers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ShareLinks {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ShareLink>> {
+        self.links.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Mint a link valid for `ttl`. Returns its id, the secret token for
+    /// the URL, and the Unix time it expires.
+    pub fn create(&self, ttl: Duration) -> Result<(Uuid, String, u64)> {
+        let mut links = self.lock();
+        let now = Instant::now();
+        links.retain(|link| link.expires > now);
+        ensure!(
+            links.len() < MAX_LINKS,
+            "This session already has {MAX_LINKS} share links; revoke one first"
+        );
+        let created_at = unix_now();
+        let link = ShareLink {
+            id: Uuid::new_v4(),
+            token: crate::auth::generate_secret(),
+            created_at,
+            expires_at: created_at + ttl.as_secs(),
+            expires: now + ttl,
+        };
+        let minted = (link.id, link.token.clone(), link.expires_at);
+        links.push(link);
+        Ok(minted)
+    }
+
+    /// When `token`'s link expires, if it is live.
+    pub fn find(&self, token: &str) -> Option<Instant> {
+        let now = Instant::now();
+        self.lock()
+            .iter()
+            .find(|link| constant_time_eq(link.token.as_bytes(), token.as_bytes()))
+            .map(|link| link.expires)
+            .filter(|&expires| expires > now)
+    }
+
+    /// Live links for the owner, without their tokens.
+    pub fn list_json(&self) -> Vec<serde_json::Value> {
+        let now = Instant::now();
+        self.lock()
+            .iter()
+            .filter(|link| link.expires > now)
+            .map(|link| {
+                serde_json::json!({
+                    "id": link.id,
+                    "created_at": link.created_at,
+                    "expires_at": link.expires_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Revoke one link. Returns false if there was no such link.
+    pub fn revoke(&self, id: Uuid) -> bool {
+        let mut links = self.lock();
+        let before = links.len();
+        links.retain(|link| link.id != id);
+        let revoked = links.len() < before;
+        drop(links);
+        if revoked {
+            self.revoked.notify_waiters();
+        }
+        revoked
+    }
+
+    /// Revoke every link, returning how many were live.
+    pub fn revoke_all(&self) -> usize {
+        let now = Instant::now();
+        let live = std::mem::take(&mut *self.lock())
+            .iter()
+            .filter(|link| link.expires > now)
+            .count();
+        self.revoked.notify_waiters();
+        live
+    }
+
+    /// Take a viewer slot, or None when the session has all the viewers
+    /// it may have.
+    pub fn add_viewer(&self) -> Option<ViewerSlot<'_>> {
+        self.viewers
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < MAX_VIEWERS).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| ViewerSlot(self))
+    }
+
+    /// Resolves once `token`'s link has been revoked or has expired.
+    pub async fn ended(&self, token: &str) {
+        loop {
+            // Registered before the check, so a revocation in between isn't missed
+            let revoked = self.revoked.notified();
+            let Some(expires) = self.find(token) else {
+                return;
+            };
+            tokio::select! {
+                _ = revoked => {}
+                _ = tokio::time::sleep_until(expires.into()) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_resolve_until_revoked() {
+        let links = ShareLinks::default();
+        let (id, token, expires_at) = links.create(Duration::from_secs(600)).unwrap();
+        assert_eq!(token.len(), 64);
+        assert!(expires_at >= unix_now() + 599);
+        assert!(links.find(&token).is_some());
+        assert!(links.find("not-a-token").is_none());
+
+        let listed = links.list_json();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["id"], id.to_string());
+        assert!(listed[0].get("token").is_none());
+
+        assert!(links.revoke(id));
+        assert!(!links.revoke(id));
+        assert!(links.find(&token).is_none());
+    }
+
+    #[test]
+    fn expired_links_are_gone() {
+        let links = ShareLinks::default();
+        let (_, token, _) = links.create(Duration::ZERO).unwrap();
+        assert!(links.find(&token).is_none());
+        assert!(links.list_json().is_empty());
+        assert_eq!(links.revoke_all(), 0);
+    }
+
+    #[test]
+    fn links_and_viewers_are_capped() {
+        let links = ShareLinks::default();
+        for _ in 0..MAX_LINKS {
+            links.create(Duration::from_secs(60)).unwrap();
+        }
+        assert!(links.create(Duration::from_secs(60)).is_err());
+        assert_eq!(links.revoke_all(), MAX_LINKS);
+        assert!(links.create(Duration::from_secs(60)).is_ok());
+
+        let slots: Vec<_> = (0..MAX_VIEWERS).map(|_| links.add_viewer()).collect();
+        assert!(slots.iter().all(Option::is_some));
+        assert!(links.add_viewer().is_none());
+        drop(slots);
+        assert!(links.add_viewer().is_some());
+    }
+
+    #[tokio::test]
+    async fn ended_wakes_on_revoke() {
+        let links = std::sync::Arc::new(ShareLinks::default());
+        let (_, token, _) = links.create(Duration::from_secs(600)).unwrap();
+        let watcher = {
+            let links = std::sync::Arc::clone(&links);
+            tokio::spawn(async move { links.ended(&token).await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!watcher.is_finished());
+        links.revoke_all();
+        tokio::time::timeout(Duration::from_secs(5), watcher)
+            .await
+            .expect("viewer should see the revocation")
+            .unwrap();
+    }
+}
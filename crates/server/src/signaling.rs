@@ -1,19 +1,160 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 
 use axum::extract::ws::{Message, WebSocket};
-use beam_protocol::{AgentCommand, FRAME_MAGIC, InputEvent, SignalingMessage};
+use beam_protocol::{
+    AgentCommand, AgentHealth, AgentInfo, CAP_INPUT_TIMESTAMPS, CAP_LOCK_SCREEN, EncoderStatus,
+    FRAME_MAGIC, Hello, IdleAction, IdleNotice, InputEvent, LatencyStats, MIN_PROTOCOL_VERSION,
+    NetworkQuality, PROTOCOL_VERSION, PipelineStats, QualityFallback, QualityLevel, QualityPreset,
+    RELEASE, ReconnectRequest, SignalingMessage, StampedInput, StreamAlert, StreamAlertKind,
+    Thumbnail, TimedInput, VersionSkew, VideoFrameHeader, WireFormat, cbor_to_value,
+    is_cbor_message, is_valid_input_batch, json_to_cbor,
+};
 use bytes::Bytes;
-use tokio::sync::{Notify, RwLock, broadcast};
+use tokio::sync::{Notify, RwLock, broadcast, watch};
 use tokio::time::{Duration, Instant, interval};
 use uuid::Uuid;
 
-/// Interval between WebSocket ping frames.
-const WS_PING_INTERVAL: Duration = Duration::from_secs(30);
+use crate::share::ShareLinks;
+use crate::stats_history::{StatsHistory, StatsSample};
+use crate::timeline::{Peer, SessionEvent, Timeline};
 
-/// Maximum time to wait for a pong response before considering the connection dead.
-/// This allows 3 missed pings (3 * 30s = 90s).
-const WS_PONG_TIMEOUT: Duration = Duration::from_secs(90);
+/// WebSocket keepalive timing for browser and agent connections.
+///
+/// The server pings every `ping_interval`; a connection that sends nothing
+/// at all (not even a pong) for `timeout` is considered half-open and closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    pub ping_interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Keepalive {
+    pub fn from_config(server: &beam_protocol::ServerConfig) -> Self {
+        Self {
+            ping_interval: Duration::from_secs(server.ws_ping_interval),
+            timeout: Duration::from_secs(server.ws_timeout),
+        }
+    }
+}
+
+/// Connection state of one side (browser or agent) of a signaling channel,
+/// as reported by the admin API.
+#[derive(Default)]
+pub struct PeerLink {
+    state: std::sync::Mutex<PeerLinkState>,
+}
+
+#[derive(Default)]
+struct PeerLinkState {
+    /// Bumped on every connect so a replaced socket can't mark its
+    /// successor as disconnected
+    generation: u64,
+    connected_at: Option<Instant>,
+    last_seen: Option<Instant>,
+    disconnected_at: Option<Instant>,
+    disconnect_reason: Option<&'static str>,
+    /// What the peer announced on this connection (None = not yet, or a
+    /// peer that predates `Hello`)
+    hello: Option<Hello>,
+}
+
+impl PeerLink {
+    fn lock(&self) -> std::sync::MutexGuard<'_, PeerLinkState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a new connection, returning its generation.
+    fn connect(&self) -> u64 {
+        let now = Instant::now();
+        let mut state = self.lock();
+        state.generation += 1;
+        state.connected_at = Some(now);
+        state.last_seen = Some(now);
+        state.disconnected_at = None;
+        state.disconnect_reason = None;
+        state.hello = None;
+        state.generation
+    }
+
+    fn set_hello(&self, generation: u64, hello: Hello) {
+        let mut state = self.lock();
+        if state.generation == generation {
+            state.hello = Some(hello);
+        }
+    }
+
+    /// Protocol version the peer announced, if any.
+    pub fn peer_version(&self) -> Option<u32> {
+        self.lock().hello.as_ref().map(|h| h.version)
+    }
+
+    /// Release the peer announced, if any.
+    pub fn peer_release(&self) -> Option<String> {
+        self.lock().hello.as_ref().and_then(|h| h.release.clone())
+    }
+
+    /// Whether the peer can handle `capability`. Peers that haven't sent a
+    /// `Hello` get the benefit of the doubt, as before versioning existed.
+    pub fn supports(&self, capability: &str) -> bool {
+        self.lock()
+            .hello
+            .as_ref()
+            .is_none_or(|h| h.supports(capability))
+    }
+
+    /// Whether the peer's `Hello` lists `capability`. For features newer
+    /// than versioning, so a silent peer never qualifies.
+    pub fn announces(&self, capability: &str) -> bool {
+        self.lock()
+            .hello
+            .as_ref()
+            .is_some_and(|h| h.supports(capability))
+    }
+
+    /// How long the current connection has been up, if there is one.
+    pub fn connected_secs(&self) -> Option<u64> {
+        self.lock().connected_at.map(|t| t.elapsed().as_secs())
+    }
+
+    /// Record that the connection delivered a frame.
+    fn seen(&self, generation: u64, at: Instant) {
+        let mut state = self.lock();
+        if state.generation == generation {
+            state.last_seen = Some(at);
+        }
+    }
+
+    /// Record the end of a connection, unless a newer one has taken over.
+    fn disconnect(&self, generation: u64, reason: &'static str) {
+        let mut state = self.lock();
+        if state.generation == generation && state.connected_at.is_some() {
+            state.connected_at = None;
+            state.disconnected_at = Some(Instant::now());
+            state.disconnect_reason = Some(reason);
+        }
+    }
+
+    pub fn status_json(&self) -> serde_json::Value {
+        let state = self.lock();
+        match state.connected_at {
+            Some(connected_at) => serde_json::json!({
+                "connected": true,
+                "connected_secs": connected_at.elapsed().as_secs(),
+                "last_seen_secs": state.last_seen.map(|t| t.elapsed().as_secs()),
+                "protocol_version": state.hello.as_ref().map(|h| h.version),
+                "release": state.hello.as_ref().and_then(|h| h.release.as_ref()),
+                "capabilities": state.hello.as_ref().map(|h| &h.capabilities),
+            }),
+            None => serde_json::json!({
+                "connected": false,
+                "disconnected_secs": state.disconnected_at.map(|t| t.elapsed().as_secs()),
+                "disconnect_reason": state.disconnect_reason,
+            }),
+        }
+    }
+}
 
 /// Per-session signaling channel with separate paths for browser→agent and agent→browser.
 /// Binary video/audio frames from the agent are relayed via a separate broadcast channel.
@@ -24,21 +165,181 @@ pub struct SignalingChannel {
     pub to_browser: broadcast::Sender<String>,
     /// Binary video/audio frames from agent, relayed to browser
     pub video_frames: broadcast::Sender<Bytes>,
+    /// Binary webcam frames from browser, relayed to agent
+    pub webcam_frames: broadcast::Sender<Bytes>,
     /// Notified when a new browser connects, kicking the previous one.
     /// Only one browser WebSocket per session is supported at a time.
     pub browser_kick: Notify,
+    /// Set by the agent's idle policy to disconnect the browser; the
+    /// browser is told whether the session survives
+    pub idle_kick: watch::Sender<Option<IdleAction>>,
+    /// Most recent latency report from the agent (for the stats API and metrics)
+    pub latency: std::sync::RwLock<Option<LatencyStats>>,
+    /// Most recent per-stage frame pipeline timings from the agent
+    pub pipeline: std::sync::RwLock<Option<PipelineStats>>,
+    /// Most recent watchdog report from the agent (memory, CPU, frame rates)
+    pub health: std::sync::RwLock<Option<AgentHealth>>,
+    /// Encoder the agent last reported (changes on fallback)
+    pub encoder: std::sync::RwLock<Option<EncoderStatus>>,
+    /// Encoder, capture and stream the agent last reported, for the admin
+    /// session list
+    pub agent_info: std::sync::RwLock<Option<AgentInfo>>,
+    /// Whether audio capture should run. Survives agent restarts: a newly
+    /// connected agent is told to pause if this is false.
+    pub audio_enabled: AtomicBool,
+    /// Video bitrate ceiling the agent was last sent (kbps, 0 = none);
+    /// replayed to a newly connected agent
+    pub bitrate_cap: AtomicU32,
+    /// Quality preset the session runs at: the owner's remembered one at
+    /// first, and what the agent fell back to after one stayed over the
+    /// quality ceiling. Replayed to a newly connected agent.
+    pub quality: std::sync::RwLock<QualityPreset>,
+    /// Browser WebSocket connection state
+    pub browser_link: PeerLink,
+    /// Agent WebSocket connection state
+    pub agent_link: PeerLink,
+    /// View-only links the owner has handed out
+    pub shares: ShareLinks,
+    /// Recent per-second stats, for looking back at a bad stretch
+    pub stats_history: StatsHistory,
+    /// What happened to the session so far, for troubleshooting
+    pub timeline: Timeline,
+    /// Latest screenshot from the agent, for the admin panel
+    pub thumbnail: std::sync::RwLock<Option<SessionThumbnail>>,
+    /// Set by the owner to keep admins from observing the session (the
+    /// owner's `privacy` policy can also require it)
+    pub private: AtomicBool,
+    /// Browser messages dropped for breaking the protocol
+    pub rejected: RejectedMessages,
+    /// Frozen pictures the agent reported while a browser was attached
+    pub stream_alerts: StreamAlerts,
+    /// End an admin scheduled, counting down
+    pub termination: std::sync::Mutex<Option<crate::termination::Scheduled>>,
+    /// Nonce of the agent's latest handshake ticket, until it is used
+    agent_ticket: std::sync::Mutex<Option<String>>,
+}
+
+/// Counts of browser messages the server refused to forward, by reason.
+#[derive(Default)]
+pub struct RejectedMessages {
+    /// Not a message this server knows (bad JSON or CBOR, unknown tag,
+    /// missing or mistyped fields, invalid batch)
+    pub malformed: AtomicU64,
+    /// Well-formed but over a size limit (`InputEvent::check_limits`)
+    pub too_large: AtomicU64,
+}
+
+impl RejectedMessages {
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "malformed": self.malformed.load(Ordering::Relaxed),
+            "too_large": self.too_large.load(Ordering::Relaxed),
+        })
+    }
 }
 
+/// Counts of the agent's `StreamAlert`s that started, by kind.
+#[derive(Default)]
+pub struct StreamAlerts {
+    not_delivered: AtomicU64,
+    capture_stalled: AtomicU64,
+}
+
+impl StreamAlerts {
+    pub fn count(&self, kind: StreamAlertKind) -> &AtomicU64 {
+        match kind {
+            StreamAlertKind::NotDelivered => &self.not_delivered,
+            StreamAlertKind::CaptureStalled => &self.capture_stalled,
+        }
+    }
+}
+
+/// A decoded agent thumbnail and when it arrived.
+#[derive(Clone)]
+pub struct SessionThumbnail {
+    /// Unix time (seconds)
+    pub at: u64,
+    pub jpeg: Bytes,
+}
+
+/// Largest thumbnail kept; a 320-pixel-wide JPEG is a few tens of KB
+const MAX_THUMBNAIL_BYTES: usize = 256 * 1024;
+
 impl SignalingChannel {
     pub fn new() -> Self {
         let (to_agent, _) = broadcast::channel(64);
         let (to_browser, _) = broadcast::channel(64);
         let (video_frames, _) = broadcast::channel(64);
+        // Camera runs at 30fps or less; a short queue keeps it near real time
+        let (webcam_frames, _) = broadcast::channel(16);
         Self {
             to_agent,
             to_browser,
             video_frames,
+            webcam_frames,
             browser_kick: Notify::new(),
+            idle_kick: watch::Sender::new(None),
+            latency: std::sync::RwLock::new(None),
+            pipeline: std::sync::RwLock::new(None),
+            health: std::sync::RwLock::new(None),
+            encoder: std::sync::RwLock::new(None),
+            agent_info: std::sync::RwLock::new(None),
+            audio_enabled: AtomicBool::new(true),
+            bitrate_cap: AtomicU32::new(0),
+            quality: std::sync::RwLock::new(QualityPreset::default()),
+            browser_link: PeerLink::default(),
+            agent_link: PeerLink::default(),
+            shares: ShareLinks::default(),
+            stats_history: StatsHistory::default(),
+            timeline: Timeline::default(),
+            thumbnail: std::sync::RwLock::new(None),
+            private: AtomicBool::new(false),
+            rejected: RejectedMessages::default(),
+            stream_alerts: StreamAlerts::default(),
+            termination: std::sync::Mutex::new(None),
+            agent_ticket: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// A channel whose timeline is published to the admin event feed.
+    pub fn for_session(session_id: Uuid) -> Self {
+        Self {
+            timeline: Timeline::for_session(session_id),
+            ..Self::new()
+        }
+    }
+
+    /// The agent fell back from a preset over the quality ceiling: the
+    /// session runs at the effective one from now on. The owner's stored
+    /// preference keeps what they picked.
+    pub fn fall_back_quality(&self, fallback: &QualityFallback) {
+        *self.quality.write().unwrap_or_else(|e| e.into_inner()) = fallback.effective;
+        self.timeline.record(SessionEvent::PresetFallback {
+            requested: fallback.requested,
+            effective: fallback.effective,
+            kbps: fallback.kbps,
+            ceiling_kbps: fallback.ceiling_kbps,
+        });
+    }
+
+    /// Remember the nonce of a freshly issued agent ticket, voiding any
+    /// earlier one.
+    pub fn issue_agent_ticket(&self, nonce: String) {
+        *self.agent_ticket.lock().unwrap_or_else(|e| e.into_inner()) = Some(nonce);
+    }
+
+    /// Use up the agent ticket carrying `nonce`. False if it isn't the
+    /// latest one issued or was used already.
+    pub fn redeem_agent_ticket(&self, nonce: &str) -> bool {
+        let mut ticket = self.agent_ticket.lock().unwrap_or_else(|e| e.into_inner());
+        match ticket.as_deref() {
+            Some(latest)
+                if crate::session::constant_time_eq(latest.as_bytes(), nonce.as_bytes()) =>
+            {
+                *ticket = None;
+                true
+            }
+            _ => false,
         }
     }
 }
@@ -66,17 +367,48 @@ pub async fn get_or_create_channel(
     let mut channels = registry.write().await;
     channels
         .entry(session_id)
-        .or_insert_with(|| Arc::new(SignalingChannel::new()))
+        .or_insert_with(|| Arc::new(SignalingChannel::for_session(session_id)))
         .clone()
 }
 
+/// Add to a session's timeline, if it still has a channel.
+pub async fn record_event(registry: &ChannelRegistry, session_id: Uuid, event: SessionEvent) {
+    if let Some(channel) = registry.read().await.get(&session_id) {
+        channel.timeline.record(event);
+    }
+}
+
 /// Remove a signaling channel when a session is destroyed.
 pub async fn remove_channel(registry: &ChannelRegistry, session_id: Uuid) {
     let mut channels = registry.write().await;
-    channels.remove(&session_id);
+    if let Some(channel) = channels.remove(&session_id) {
+        // Ends any viewers still watching
+        channel.shares.revoke_all();
+        channel.timeline.record(SessionEvent::Ended);
+        let scheduled = channel
+            .termination
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(scheduled) = scheduled {
+            scheduled.abort();
+        }
+    }
     tracing::debug!(%session_id, "Signaling channel removed");
 }
 
+/// The session and channel a share link token belongs to.
+pub async fn find_share(
+    registry: &ChannelRegistry,
+    token: &str,
+) -> Option<(Uuid, Arc<SignalingChannel>)> {
+    let channels = registry.read().await;
+    channels
+        .iter()
+        .find(|(_, channel)| channel.shares.find(token).is_some())
+        .map(|(id, channel)| (*id, Arc::clone(channel)))
+}
+
 /// Handle a WebSocket connection from a **browser** client.
 ///
 /// Browser sends text → parsed as InputEvent, wrapped in AgentCommand::Input, sent to agent.
@@ -84,8 +416,22 @@ pub async fn remove_channel(registry: &ChannelRegistry, session_id: Uuid) {
 ///
 /// Only one browser per session at a time. Connecting a new browser
 /// kicks the previous one with close code 4001 ("replaced").
-pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry: ChannelRegistry) {
-    tracing::info!(%session_id, "Browser WebSocket upgrade request");
+///
+/// `system_combos` gates `KeyCombo` events (Ctrl+Alt+Del and friends);
+/// when false they are rejected here and never reach the agent.
+///
+/// `lock_on_disconnect` locks the desktop when this browser goes away for
+/// any reason other than being replaced by a newer connection.
+pub async fn handle_browser_ws(
+    mut socket: WebSocket,
+    session_id: Uuid,
+    registry: ChannelRegistry,
+    system_combos: bool,
+    lock_on_disconnect: bool,
+    keepalive: Keepalive,
+) {
+    let format = WireFormat::from_subprotocol(socket.protocol().and_then(|p| p.to_str().ok()));
+    tracing::info!(%session_id, ?format, "Browser WebSocket upgrade request");
     let channel = get_or_create_channel(&registry, session_id).await;
 
     // Kick any existing browser for this session
@@ -105,14 +451,28 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
     // Register our own kick listener AFTER kicking the old browser
     let kicked = channel.browser_kick.notified();
     tokio::pin!(kicked);
+    let mut idle_kick = channel.idle_kick.subscribe();
+
+    // A browser arriving mid-countdown learns when the session ends
+    if let Some(json) =
+        crate::termination::pending(&channel).and_then(|n| serde_json::to_string(&n).ok())
+    {
+        let _ = socket.send(text_frame(format, json)).await;
+    }
 
     // Ping/pong keepalive state
-    let mut ping_interval = interval(WS_PING_INTERVAL);
+    let mut ping_interval = interval(keepalive.ping_interval);
     ping_interval.tick().await; // consume the immediate first tick
-    let mut last_pong = Instant::now();
+    let mut last_seen = Instant::now();
+    let link = channel.browser_link.connect();
+    channel.timeline.record(SessionEvent::Connected {
+        peer: Peer::Browser,
+    });
 
     tracing::info!(%session_id, "Browser WebSocket connected");
     let mut video_frames_relayed: u64 = 0;
+    let mut replaced = false;
+    let mut reason = "closed";
 
     loop {
         tokio::select! {
@@ -124,18 +484,40 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
                     message: "replaced".to_string(),
                 };
                 if let Ok(json) = serde_json::to_string(&msg) {
-                    let _ = socket.send(Message::Text(json.into())).await;
+                    let _ = socket.send(text_frame(format, json)).await;
+                }
+                replaced = true;
+                reason = "replaced";
+                break;
+            }
+            // Disconnected by the session's idle policy
+            Ok(()) = idle_kick.changed() => {
+                let message = match *idle_kick.borrow_and_update() {
+                    Some(IdleAction::Disconnect) => "idle_disconnect",
+                    Some(IdleAction::Destroy) => "idle_destroy",
+                    Some(IdleAction::Lock) | None => continue,
+                };
+                tracing::info!(%session_id, message, "Disconnecting idle browser");
+                let msg = SignalingMessage::Error {
+                    message: message.to_string(),
+                };
+                if let Ok(json) = serde_json::to_string(&msg) {
+                    let _ = socket.send(text_frame(format, json)).await;
                 }
+                reason = "idle";
+                break;
+            }
+            // Nothing received for too long: half-open connection
+            _ = tokio::time::sleep_until(last_seen + keepalive.timeout) => {
+                tracing::info!(%session_id, timeout_secs = keepalive.timeout.as_secs(), "Browser WebSocket keepalive timeout, closing");
+                reason = "timeout";
                 break;
             }
             // Send periodic WebSocket ping frames
             _ = ping_interval.tick() => {
-                if last_pong.elapsed() > WS_PONG_TIMEOUT {
-                    tracing::debug!(%session_id, "Browser WebSocket ping timeout, closing");
-                    break;
-                }
                 if socket.send(Message::Ping(vec![].into())).await.is_err() {
                     tracing::debug!(%session_id, "Browser WebSocket ping send failed");
+                    reason = "error";
                     break;
                 }
             }
@@ -149,7 +531,7 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
                     }
                     Err(broadcast::error::RecvError::Closed) => break,
                 };
-                if socket.send(Message::Text(text.into())).await.is_err() {
+                if socket.send(text_frame(format, text)).await.is_err() {
                     tracing::debug!(%session_id, "Browser WebSocket send failed");
                     break;
                 }
@@ -159,6 +541,9 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
                 match result {
                     Ok(frame) => {
                         video_frames_relayed += 1;
+                        if video_frames_relayed == 1 {
+                            channel.timeline.record(SessionEvent::FirstFrame);
+                        }
                         if video_frames_relayed <= 3 {
                             tracing::info!(%session_id, size = frame.len(), frame = video_frames_relayed, "Relaying binary frame to browser");
                         }
@@ -179,28 +564,101 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
             }
             // Receive messages from browser and forward to agent
             Some(result) = socket.recv() => {
+                if result.is_ok() {
+                    last_seen = Instant::now();
+                    channel.browser_link.seen(link, last_seen);
+                }
+                // CBOR messages take the same path as their JSON form
+                let result = match result {
+                    Ok(Message::Binary(data)) if format == WireFormat::Cbor && is_cbor_message(&data) => {
+                        match cbor_to_value(&data) {
+                            Ok(value) => Ok(Message::Text(value.to_string().into())),
+                            Err(e) => {
+                                tracing::warn!(%session_id, "Invalid CBOR from browser: {e}");
+                                channel.rejected.malformed.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                        }
+                    }
+                    other => other,
+                };
                 match result {
                     Ok(Message::Text(text)) => {
+                        if let Some(hello) = parse_hello(&text) {
+                            if !answer_hello(&mut socket, format, session_id, "Browser", &channel.browser_link, link, hello).await {
+                                reason = "protocol";
+                                break;
+                            }
+                            continue;
+                        }
                         // Try parsing as InputEvent first (most common)
-                        match serde_json::from_str::<InputEvent>(&text) {
-                            Ok(event) => {
-                                let cmd = AgentCommand::Input(event);
+                        match serde_json::from_str::<StampedInput>(&text) {
+                            Ok(StampedInput { ev: InputEvent::KeyCombo { combo }, .. }) if !system_combos => {
+                                tracing::warn!(%session_id, %combo, "System key combo rejected by session policy");
+                                let err = SignalingMessage::Error {
+                                    message: "System key combos are disabled for this session".to_string(),
+                                };
+                                let json = serde_json::to_string(&err).unwrap_or_default();
+                                let _ = socket.send(text_frame(format, json)).await;
+                            }
+                            // Batches are for pointer events only, which also
+                            // keeps key combos from slipping past the check above
+                            Ok(StampedInput { ev: InputEvent::InputBatch { e }, .. }) if !is_valid_input_batch(&e) => {
+                                tracing::warn!(%session_id, len = e.len(), "Invalid input batch rejected");
+                                channel.rejected.malformed.fetch_add(1, Ordering::Relaxed);
+                                let err = SignalingMessage::Error {
+                                    message: "Invalid input batch".to_string(),
+                                };
+                                let json = serde_json::to_string(&err).unwrap_or_default();
+                                let _ = socket.send(text_frame(format, json)).await;
+                            }
+                            Ok(StampedInput { ts, ev }) => {
+                                if let Err(e) = ev.check_limits() {
+                                    tracing::warn!(%session_id, "Oversized browser message rejected: {e}");
+                                    channel.rejected.too_large.fetch_add(1, Ordering::Relaxed);
+                                    let err = SignalingMessage::Error {
+                                        message: format!("Message too large: {e}"),
+                                    };
+                                    let json = serde_json::to_string(&err).unwrap_or_default();
+                                    let _ = socket.send(text_frame(format, json)).await;
+                                    continue;
+                                }
+                                if let InputEvent::Resize { w, h, .. } = ev {
+                                    channel.timeline.record(SessionEvent::Resize { width: w, height: h });
+                                }
+                                let cmd = match ts {
+                                    Some(ts) if channel.agent_link.announces(CAP_INPUT_TIMESTAMPS) => {
+                                        AgentCommand::TimedInput(TimedInput { ts, ev })
+                                    }
+                                    _ => AgentCommand::Input(ev),
+                                };
                                 if let Err(e) = channel.to_agent.send(cmd) {
                                     tracing::warn!(%session_id, "No agent listening for input: {e}");
                                 }
                             }
+                            // A newer browser may send events this server
+                            // doesn't know yet; drop them quietly
+                            Err(e) if channel.browser_link.peer_version().is_some_and(|v| v > PROTOCOL_VERSION) => {
+                                tracing::debug!(%session_id, "Ignoring unknown message from newer browser: {e}");
+                            }
                             Err(e) => {
                                 tracing::warn!(%session_id, "Invalid browser message: {e}");
+                                channel.rejected.malformed.fetch_add(1, Ordering::Relaxed);
                                 let err = SignalingMessage::Error {
                                     message: format!("Invalid message format: {e}"),
                                 };
                                 let json = serde_json::to_string(&err).unwrap_or_default();
-                                let _ = socket.send(Message::Text(json.into())).await;
+                                let _ = socket.send(text_frame(format, json)).await;
                             }
                         }
                     }
-                    Ok(Message::Pong(_)) => {
-                        last_pong = Instant::now();
+                    Ok(Message::Binary(data)) => {
+                        // The only binary the browser sends is webcam video
+                        if is_webcam_frame(&data) {
+                            let _ = channel.webcam_frames.send(Bytes::from(data.to_vec()));
+                        } else {
+                            tracing::warn!(%session_id, len = data.len(), "Browser sent binary that is not a webcam frame");
+                        }
                     }
                     Ok(Message::Close(_)) => {
                         tracing::info!(%session_id, "Browser WebSocket closed");
@@ -208,6 +666,7 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
                     }
                     Err(e) => {
                         tracing::debug!(%session_id, "Browser WebSocket error: {e}");
+                        reason = "error";
                         break;
                     }
                     _ => {}
@@ -217,7 +676,164 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
         }
     }
 
-    tracing::info!(%session_id, "Browser WebSocket disconnected");
+    channel.browser_link.disconnect(link, reason);
+    channel.timeline.record(SessionEvent::Disconnected {
+        peer: Peer::Browser,
+        reason,
+    });
+    tracing::info!(%session_id, reason, "Browser WebSocket disconnected");
+
+    // A pointer lock belongs to the browser that took it; left set, the
+    // agent would drop the absolute moves of whoever connects next
+    let _ = channel
+        .to_agent
+        .send(AgentCommand::Input(InputEvent::PointerLock {
+            locked: false,
+        }));
+
+    if lock_on_disconnect && !replaced {
+        if !channel.agent_link.supports(CAP_LOCK_SCREEN) {
+            tracing::warn!(%session_id, "Agent cannot lock the desktop, leaving it unlocked");
+            return;
+        }
+        tracing::info!(%session_id, "Locking desktop after browser disconnect");
+        if let Err(e) = channel.to_agent.send(AgentCommand::LockScreen) {
+            tracing::warn!(%session_id, "No agent listening to lock the desktop: {e}");
+        }
+    }
+}
+
+/// Handle a WebSocket connection from a **view-only** share link.
+///
+/// The viewer gets the session's video and audio frames plus server error
+/// messages. Agent text (clipboard, file transfers, audio sources) is not
+/// relayed, and apart from `Hello` everything the viewer sends is dropped.
+/// Unlike a browser, a viewer doesn't replace anyone, and it is closed when
+/// its link is revoked or expires.
+pub async fn handle_viewer_ws(
+    mut socket: WebSocket,
+    session_id: Uuid,
+    channel: Arc<SignalingChannel>,
+    token: String,
+    keepalive: Keepalive,
+) {
+    let format = WireFormat::Json;
+    let Some(_slot) = channel.shares.add_viewer() else {
+        tracing::info!(%session_id, "Share viewer refused, too many viewers");
+        let msg = SignalingMessage::Error {
+            message: "too_many_viewers".to_string(),
+        };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            let _ = socket.send(text_frame(format, json)).await;
+        }
+        return;
+    };
+
+    let mut from_agent = channel.to_browser.subscribe();
+    let mut from_agent_video = channel.video_frames.subscribe();
+    // A keyframe for the newcomer
+    let _ = channel
+        .to_agent
+        .send(AgentCommand::Input(InputEvent::VisibilityState {
+            visible: true,
+        }));
+    let ended = channel.shares.ended(&token);
+    tokio::pin!(ended);
+
+    let mut ping_interval = interval(keepalive.ping_interval);
+    ping_interval.tick().await;
+    let mut last_seen = Instant::now();
+    // Only for answering `Hello`; viewers don't show up in the admin API
+    let link = PeerLink::default();
+    let generation = link.connect();
+
+    tracing::info!(%session_id, "Share viewer connected");
+    let mut reason = "closed";
+
+    loop {
+        tokio::select! {
+            () = &mut ended => {
+                let msg = SignalingMessage::Error {
+                    message: "share_ended".to_string(),
+                };
+                if let Ok(json) = serde_json::to_string(&msg) {
+                    let _ = socket.send(text_frame(format, json)).await;
+                }
+                reason = "share_ended";
+                break;
+            }
+            _ = tokio::time::sleep_until(last_seen + keepalive.timeout) => {
+                reason = "timeout";
+                break;
+            }
+            _ = ping_interval.tick() => {
+                if socket.send(Message::Ping(vec![].into())).await.is_err() {
+                    reason = "error";
+                    break;
+                }
+            }
+            // Of the agent's text, only server errors (agent_exited) pass
+            result = from_agent.recv() => {
+                let text = match result {
+                    Ok(t) => t,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if serde_json::from_str::<SignalingMessage>(&text).is_ok()
+                    && socket.send(text_frame(format, text)).await.is_err()
+                {
+                    reason = "error";
+                    break;
+                }
+            }
+            result = from_agent_video.recv() => {
+                match result {
+                    Ok(frame) => {
+                        if socket.send(Message::Binary(frame.to_vec().into())).await.is_err() {
+                            reason = "error";
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::debug!(%session_id, skipped = n, "Share viewer lagged — requesting keyframe");
+                        let _ = channel.to_agent.send(AgentCommand::Input(InputEvent::VisibilityState { visible: true }));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            Some(result) = socket.recv() => {
+                match result {
+                    Ok(Message::Text(text)) => {
+                        last_seen = Instant::now();
+                        if let Some(hello) = parse_hello(&text)
+                            && !answer_hello(&mut socket, format, session_id, "Viewer", &link, generation, hello).await
+                        {
+                            reason = "protocol";
+                            break;
+                        }
+                    }
+                    Ok(Message::Close(_)) => break,
+                    Ok(_) => last_seen = Instant::now(),
+                    Err(_) => {
+                        reason = "error";
+                        break;
+                    }
+                }
+            }
+            else => break,
+        }
+    }
+
+    tracing::info!(%session_id, reason, "Share viewer disconnected");
+}
+
+/// Why an agent WebSocket ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentWsExit {
+    /// Connection closed or failed; the agent may reconnect
+    Closed,
+    /// The agent's idle policy asked for the session to be destroyed
+    IdleDestroy,
 }
 
 /// Handle a WebSocket connection from a **beam-agent**.
@@ -225,28 +841,100 @@ pub async fn handle_browser_ws(mut socket: WebSocket, session_id: Uuid, registry
 /// Agent sends text → forwarded to browser as-is (raw JSON relay).
 /// Agent sends binary → validated and relayed to browser as video/audio frames.
 /// Agent receives ← AgentCommand (input events + shutdown).
-pub async fn handle_agent_ws(mut socket: WebSocket, session_id: Uuid, registry: ChannelRegistry) {
+///
+/// Idle disconnects are applied here; an idle destroy is returned to the
+/// caller, which owns the session manager. Up to `stats_history_secs`
+/// connection quality reports are kept in the channel's stats history.
+pub async fn handle_agent_ws(
+    mut socket: WebSocket,
+    session_id: Uuid,
+    registry: ChannelRegistry,
+    keepalive: Keepalive,
+    stats_history_secs: u32,
+    version_window: u32,
+) -> AgentWsExit {
     tracing::info!(%session_id, "Agent WebSocket upgrade request");
     let channel = get_or_create_channel(&registry, session_id).await;
     let mut from_browser = channel.to_agent.subscribe();
+    let mut from_browser_webcam = channel.webcam_frames.subscribe();
 
     // Ping/pong keepalive state
-    let mut ping_interval = interval(WS_PING_INTERVAL);
+    let mut ping_interval = interval(keepalive.ping_interval);
     ping_interval.tick().await; // consume the immediate first tick
-    let mut last_pong = Instant::now();
+    let mut last_seen = Instant::now();
+    let link = channel.agent_link.connect();
+    channel
+        .timeline
+        .record(SessionEvent::Connected { peer: Peer::Agent });
+
+    // Last reported connection quality, so only transitions are logged
+    let mut quality_level: Option<QualityLevel> = None;
+    // Stream alerts this agent raised that were counted and haven't ended
+    let mut frozen: Vec<StreamAlertKind> = Vec::new();
 
     tracing::info!(%session_id, "Agent WebSocket connected");
 
+    // Agents start with audio on; re-apply a pause from before a restart
+    if !channel.audio_enabled.load(Ordering::Relaxed)
+        && let Ok(json) = serde_json::to_string(&AgentCommand::SetAudio { enabled: false })
+        && socket.send(Message::Text(json.into())).await.is_err()
+    {
+        tracing::debug!(%session_id, "Agent WebSocket send failed");
+        channel.agent_link.disconnect(link, "error");
+        channel.timeline.record(SessionEvent::Disconnected {
+            peer: Peer::Agent,
+            reason: "error",
+        });
+        return AgentWsExit::Closed;
+    }
+
+    // ...and the bandwidth ceiling it was last given
+    let kbps = channel.bitrate_cap.load(Ordering::Relaxed);
+    if kbps > 0
+        && let Ok(json) = serde_json::to_string(&AgentCommand::SetBitrateCap { kbps })
+        && socket.send(Message::Text(json.into())).await.is_err()
+    {
+        tracing::debug!(%session_id, "Agent WebSocket send failed");
+        channel.agent_link.disconnect(link, "error");
+        channel.timeline.record(SessionEvent::Disconnected {
+            peer: Peer::Agent,
+            reason: "error",
+        });
+        return AgentWsExit::Closed;
+    }
+
+    // ...and a quality preset other than the one agents start with
+    let quality = *channel.quality.read().unwrap_or_else(|e| e.into_inner());
+    if quality != QualityPreset::default()
+        && let Ok(json) =
+            serde_json::to_string(&AgentCommand::Input(InputEvent::Quality { mode: quality }))
+        && socket.send(Message::Text(json.into())).await.is_err()
+    {
+        tracing::debug!(%session_id, "Agent WebSocket send failed");
+        channel.agent_link.disconnect(link, "error");
+        channel.timeline.record(SessionEvent::Disconnected {
+            peer: Peer::Agent,
+            reason: "error",
+        });
+        return AgentWsExit::Closed;
+    }
+
+    let mut exit = AgentWsExit::Closed;
+    let mut reason = "closed";
+
     loop {
         tokio::select! {
+            // Nothing received for too long: half-open connection
+            _ = tokio::time::sleep_until(last_seen + keepalive.timeout) => {
+                tracing::warn!(%session_id, timeout_secs = keepalive.timeout.as_secs(), "Agent WebSocket keepalive timeout, closing");
+                reason = "timeout";
+                break;
+            }
             // Send periodic WebSocket ping frames
             _ = ping_interval.tick() => {
-                if last_pong.elapsed() > WS_PONG_TIMEOUT {
-                    tracing::debug!(%session_id, "Agent WebSocket ping timeout, closing");
-                    break;
-                }
                 if socket.send(Message::Ping(vec![].into())).await.is_err() {
                     tracing::debug!(%session_id, "Agent WebSocket ping send failed");
+                    reason = "error";
                     break;
                 }
             }
@@ -272,14 +960,179 @@ pub async fn handle_agent_ws(mut socket: WebSocket, session_id: Uuid, registry:
                     break;
                 }
             }
+            // Forward browser webcam frames to agent
+            result = from_browser_webcam.recv() => {
+                match result {
+                    Ok(frame) => {
+                        if socket.send(Message::Binary(frame.to_vec().into())).await.is_err() {
+                            tracing::debug!(%session_id, "Agent WebSocket binary send failed");
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        // The browser encoder sends a keyframe every couple of
+                        // seconds, so the agent's decoder recovers on its own
+                        tracing::debug!(%session_id, skipped = n, "Agent webcam consumer lagged");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
             // Receive messages from agent
             Some(result) = socket.recv() => {
+                if result.is_ok() {
+                    last_seen = Instant::now();
+                    channel.agent_link.seen(link, last_seen);
+                }
                 match result {
                     Ok(Message::Text(text)) => {
                         // Relay agent text messages to browser as-is (raw JSON).
                         // This carries signaling (SessionReady, Error) plus data
                         // messages (clipboard, cursor shape, file transfer).
+                        if let Some(hello) = parse_hello(&text) {
+                            if !check_agent_release(&mut socket, session_id, &hello, version_window).await {
+                                reason = "version";
+                                break;
+                            }
+                            if !answer_hello(&mut socket, WireFormat::Json, session_id, "Agent", &channel.agent_link, link, hello).await {
+                                reason = "protocol";
+                                break;
+                            }
+                            continue;
+                        }
+                        // Thumbnails are for the admin panel, not the browser
+                        if text.starts_with(r#"{"t":"thumb""#) {
+                            match parse_thumbnail(&text) {
+                                Some(_) if channel.private.load(Ordering::Relaxed) => {}
+                                Some(jpeg) => {
+                                    let at = std::time::SystemTime::now()
+                                        .duration_since(std::time::UNIX_EPOCH)
+                                        .map(|d| d.as_secs())
+                                        .unwrap_or_default();
+                                    *channel.thumbnail.write().unwrap_or_else(|e| e.into_inner()) =
+                                        Some(SessionThumbnail { at, jpeg });
+                                }
+                                None => tracing::debug!(%session_id, "Ignoring malformed agent thumbnail"),
+                            }
+                            continue;
+                        }
+                        // So is what the agent runs and streams
+                        if let Some(info) = parse_agent_info(&text) {
+                            tracing::debug!(
+                                %session_id,
+                                version = %info.version,
+                                encoder = %info.encoder,
+                                width = info.width,
+                                height = info.height,
+                                framerate = info.framerate,
+                                "Agent reported its pipeline"
+                            );
+                            *channel.agent_info.write().unwrap_or_else(|e| e.into_inner()) = Some(info);
+                            continue;
+                        }
                         tracing::debug!(%session_id, "Agent → Browser text relay");
+                        if let Some(stats) = parse_latency_report(&text) {
+                            *channel.latency.write().unwrap_or_else(|e| e.into_inner()) = Some(stats);
+                        }
+                        if let Some(stats) = parse_pipeline_stats(&text) {
+                            *channel.pipeline.write().unwrap_or_else(|e| e.into_inner()) = Some(stats);
+                        }
+                        if let Some(health) = parse_agent_health(&text) {
+                            *channel.health.write().unwrap_or_else(|e| e.into_inner()) = Some(health);
+                        }
+                        if let Some(enabled) = parse_audio_enabled(&text) {
+                            tracing::info!(%session_id, enabled, "Agent audio capture toggled");
+                            channel.audio_enabled.store(enabled, Ordering::Relaxed);
+                        }
+                        if let Some(status) = parse_encoder_status(&text) {
+                            if status.fallback {
+                                tracing::warn!(%session_id, encoder = %status.name, "Agent fell back to another encoder");
+                                crate::events::alert(
+                                    "encoder_fallback",
+                                    Some(session_id),
+                                    format!("Agent fell back to the {} encoder", status.name),
+                                );
+                            } else {
+                                tracing::info!(%session_id, encoder = %status.name, "Agent encoder started");
+                            }
+                            channel.timeline.record(SessionEvent::EncoderStarted {
+                                name: status.name.clone(),
+                                fallback: status.fallback,
+                            });
+                            *channel.encoder.write().unwrap_or_else(|e| e.into_inner()) = Some(status);
+                        }
+                        if let Some(nq) = parse_network_quality(&text) {
+                            if quality_level != Some(nq.level) {
+                                tracing::info!(
+                                    %session_id,
+                                    from = ?quality_level,
+                                    to = ?nq.level,
+                                    rtt_ms = ?nq.rtt_ms,
+                                    loss_pct = nq.loss_pct,
+                                    kbps = nq.kbps,
+                                    "Connection quality changed"
+                                );
+                                channel.timeline.record(SessionEvent::QualityChanged { from: quality_level, to: nq.level });
+                                quality_level = Some(nq.level);
+                            }
+                            let sample = StatsSample::new(
+                                &nq,
+                                channel.health.read().unwrap_or_else(|e| e.into_inner()).as_ref(),
+                                channel.latency.read().unwrap_or_else(|e| e.into_inner()).as_ref(),
+                            );
+                            channel.stats_history.record(sample, stats_history_secs as usize);
+                        }
+                        if let Some(alert) = parse_stream_alert(&text) {
+                            let kind = alert.kind;
+                            if !alert.active {
+                                if let Some(i) = frozen.iter().position(|&k| k == kind) {
+                                    frozen.swap_remove(i);
+                                    tracing::info!(%session_id, kind = kind.name(), secs = alert.secs, "Video flows again");
+                                    channel.timeline.record(SessionEvent::StreamResumed { kind, secs: alert.secs });
+                                }
+                            } else if channel.browser_link.connected_secs().is_some() && !frozen.contains(&kind) {
+                                // Without a browser nothing acks the video, so only count these with one attached
+                                frozen.push(kind);
+                                tracing::warn!(%session_id, kind = kind.name(), recover = alert.recover, "Browser's video froze");
+                                channel.stream_alerts.count(kind).fetch_add(1, Ordering::Relaxed);
+                                channel.timeline.record(SessionEvent::StreamStalled { kind });
+                                let message = match kind {
+                                    StreamAlertKind::NotDelivered => "Video is not reaching the browser",
+                                    StreamAlertKind::CaptureStalled => "Screen capture stalled",
+                                };
+                                crate::events::alert("stream_stalled", Some(session_id), message);
+                            }
+                        }
+                        if let Some(fallback) = parse_quality_fallback(&text) {
+                            tracing::warn!(
+                                %session_id,
+                                requested = fallback.requested.name(),
+                                effective = fallback.effective.name(),
+                                kbps = fallback.kbps,
+                                ceiling_kbps = fallback.ceiling_kbps,
+                                "Agent fell back from a quality preset over the ceiling"
+                            );
+                            channel.fall_back_quality(&fallback);
+                        }
+                        if let Some(request) = parse_reconnect_request(&text) {
+                            tracing::warn!(%session_id, attempt = request.attempt, "Agent asked the browser to reconnect");
+                            channel.timeline.record(SessionEvent::ReconnectRequested { attempt: request.attempt });
+                        }
+                        if let Some(notice) = parse_idle_notice(&text) {
+                            tracing::info!(%session_id, action = ?notice.action, "Agent idle policy stage reached");
+                            channel.timeline.record(SessionEvent::Idle { action: notice.action });
+                            match notice.action {
+                                IdleAction::Lock => {}
+                                IdleAction::Disconnect => {
+                                    channel.idle_kick.send_replace(Some(IdleAction::Disconnect));
+                                }
+                                IdleAction::Destroy => {
+                                    channel.idle_kick.send_replace(Some(IdleAction::Destroy));
+                                    exit = AgentWsExit::IdleDestroy;
+                                    reason = "idle";
+                                    break;
+                                }
+                            }
+                        }
                         if let Err(e) = channel.to_browser.send(text.to_string()) {
                             tracing::warn!(%session_id, "No browser listening: {e}");
                         }
@@ -304,15 +1157,13 @@ pub async fn handle_agent_ws(mut socket: WebSocket, session_id: Uuid, registry:
                             }
                         }
                     }
-                    Ok(Message::Pong(_)) => {
-                        last_pong = Instant::now();
-                    }
                     Ok(Message::Close(_)) => {
                         tracing::info!(%session_id, "Agent WebSocket closed");
                         break;
                     }
                     Err(e) => {
                         tracing::debug!(%session_id, "Agent WebSocket error: {e}");
+                        reason = "error";
                         break;
                     }
                     _ => {}
@@ -322,12 +1173,256 @@ pub async fn handle_agent_ws(mut socket: WebSocket, session_id: Uuid, registry:
         }
     }
 
-    tracing::info!(%session_id, "Agent WebSocket disconnected");
+    channel.agent_link.disconnect(link, reason);
+    channel.timeline.record(SessionEvent::Disconnected {
+        peer: Peer::Agent,
+        reason,
+    });
+    tracing::info!(%session_id, reason, "Agent WebSocket disconnected");
+    exit
+}
+
+/// Recognize a peer's `Hello`.
+fn parse_hello(text: &str) -> Option<Hello> {
+    if !text.starts_with(r#"{"t":"hello""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Record a peer's `Hello` and answer with the server's. A peer older than
+/// `MIN_PROTOCOL_VERSION` is told so and the connection should be closed
+/// (returns false). A newer peer is accepted; it has to fall back to the
+/// server's version, which the reply announces.
+async fn answer_hello(
+    socket: &mut WebSocket,
+    format: WireFormat,
+    session_id: Uuid,
+    peer: &'static str,
+    link: &PeerLink,
+    generation: u64,
+    hello: Hello,
+) -> bool {
+    if hello.version < MIN_PROTOCOL_VERSION {
+        tracing::warn!(
+            %session_id,
+            peer,
+            version = hello.version,
+            min = MIN_PROTOCOL_VERSION,
+            "Refusing connection with unsupported protocol version"
+        );
+        let msg = SignalingMessage::Error {
+            message: "protocol_unsupported".to_string(),
+        };
+        if let Ok(json) = serde_json::to_string(&msg) {
+            let _ = socket.send(text_frame(format, json)).await;
+        }
+        return false;
+    }
+    if hello.version == PROTOCOL_VERSION {
+        tracing::debug!(%session_id, peer, capabilities = ?hello.capabilities, "Peer hello");
+    } else {
+        tracing::info!(
+            %session_id,
+            peer,
+            version = hello.version,
+            server_version = PROTOCOL_VERSION,
+            capabilities = ?hello.capabilities,
+            "Peer speaks a different protocol version"
+        );
+    }
+    link.set_hello(generation, hello);
+    let Ok(json) = serde_json::to_string(&Hello::new(&[])) else {
+        return true;
+    };
+    socket.send(text_frame(format, json)).await.is_ok()
+}
+
+/// Compare an agent's release with the server's. One more than `window`
+/// minor releases away is logged and raised as an admin alert; one of
+/// another major release is told so, and the connection should be closed
+/// (returns false).
+async fn check_agent_release(
+    socket: &mut WebSocket,
+    session_id: Uuid,
+    hello: &Hello,
+    window: u32,
+) -> bool {
+    // Agents from before releases were announced
+    let Some(release) = hello.release.as_deref() else {
+        return true;
+    };
+    let problem = match VersionSkew::between(RELEASE, release, window) {
+        Some(VersionSkew::Same | VersionSkew::Within) => return true,
+        Some(VersionSkew::Incompatible) => {
+            tracing::warn!(
+                %session_id,
+                agent = release,
+                server = RELEASE,
+                "Refusing agent of an incompatible release"
+            );
+            let msg = SignalingMessage::Error {
+                message: format!(
+                    "Agent {release} is incompatible with server {RELEASE}; install matching releases"
+                ),
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                let _ = socket.send(text_frame(WireFormat::Json, json)).await;
+            }
+            return false;
+        }
+        Some(VersionSkew::Beyond) => {
+            format!("Agent {release} is more than {window} minor releases from server {RELEASE}")
+        }
+        None => format!("Agent release {release:?} is not a semantic version"),
+    };
+    tracing::warn!(%session_id, agent = release, server = RELEASE, window, "{problem}");
+    crate::events::alert("version_skew", Some(session_id), problem);
+    true
+}
+
+/// Frame a JSON message for a socket's wire format. Falls back to text if
+/// the JSON can't be transcoded; browsers always accept text frames.
+fn text_frame(format: WireFormat, json: String) -> Message {
+    if format == WireFormat::Cbor {
+        match json_to_cbor(&json) {
+            Ok(cbor) => return Message::Binary(cbor.into()),
+            Err(e) => tracing::warn!("Sending message as JSON, CBOR transcoding failed: {e}"),
+        }
+    }
+    Message::Text(json.into())
+}
+
+/// Recognize an agent latency report among relayed text messages.
+/// Cheap prefix check first so clipboard/file payloads aren't parsed twice.
+fn parse_latency_report(text: &str) -> Option<LatencyStats> {
+    if !text.starts_with(r#"{"t":"lat""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize an agent pipeline stage report among relayed text messages.
+fn parse_pipeline_stats(text: &str) -> Option<PipelineStats> {
+    if !text.starts_with(r#"{"t":"pst""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize an agent watchdog report among relayed text messages.
+fn parse_agent_health(text: &str) -> Option<AgentHealth> {
+    if !text.starts_with(r#"{"t":"ah""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize an agent encoder status report among relayed text messages.
+fn parse_encoder_status(text: &str) -> Option<EncoderStatus> {
+    if !text.starts_with(r#"{"t":"enc""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize an agent's report of its encoder, capture and stream.
+fn parse_agent_info(text: &str) -> Option<AgentInfo> {
+    if !text.starts_with(r#"{"t":"ai""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Decode an agent thumbnail's JPEG, if it is one and not oversized.
+fn parse_thumbnail(text: &str) -> Option<Bytes> {
+    use base64::Engine;
+    let thumb: Thumbnail = serde_json::from_str(text).ok()?;
+    let jpeg = base64::engine::general_purpose::STANDARD
+        .decode(thumb.jpeg)
+        .ok()?;
+    (jpeg.starts_with(&[0xFF, 0xD8]) && jpeg.len() <= MAX_THUMBNAIL_BYTES).then(|| jpeg.into())
+}
+
+/// A complete `FLAG_WEBCAM` frame (header plus the announced payload).
+fn is_webcam_frame(data: &[u8]) -> bool {
+    VideoFrameHeader::validate_complete(data).is_ok()
+        && VideoFrameHeader::deserialize(data).is_ok_and(|h| h.is_webcam())
+}
+
+/// Recognize the agent's audio pause/resume acknowledgement.
+fn parse_audio_enabled(text: &str) -> Option<bool> {
+    if !text.starts_with(r#"{"t":"ae""#) {
+        return None;
+    }
+    match serde_json::from_str(text).ok()? {
+        InputEvent::AudioEnabled { enabled } => Some(enabled),
+        _ => None,
+    }
+}
+
+/// Recognize an agent idle policy notice among relayed text messages.
+fn parse_idle_notice(text: &str) -> Option<IdleNotice> {
+    if !text.starts_with(r#"{"t":"idle""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize an agent connection quality snapshot among relayed text messages.
+fn parse_network_quality(text: &str) -> Option<NetworkQuality> {
+    if !text.starts_with(r#"{"t":"nq""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize an agent stream alert among relayed text messages.
+fn parse_stream_alert(text: &str) -> Option<StreamAlert> {
+    if !text.starts_with(r#"{"t":"va""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize the agent falling back from a quality preset.
+fn parse_quality_fallback(text: &str) -> Option<QualityFallback> {
+    if !text.starts_with(r#"{"t":"qf""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
+}
+
+/// Recognize an agent's request for the browser to reconnect.
+fn parse_reconnect_request(text: &str) -> Option<ReconnectRequest> {
+    if !text.starts_with(r#"{"t":"rc""#) {
+        return None;
+    }
+    serde_json::from_str(text).ok()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use beam_protocol::StageTiming;
+
+    #[test]
+    fn peer_link_ignores_disconnect_of_replaced_socket() {
+        let link = PeerLink::default();
+        assert_eq!(link.status_json()["connected"], false);
+
+        let old = link.connect();
+        let new = link.connect();
+        link.disconnect(old, "replaced");
+        assert_eq!(link.status_json()["connected"], true);
+        assert_eq!(link.connected_secs(), Some(0));
+
+        link.disconnect(new, "timeout");
+        let status = link.status_json();
+        assert_eq!(status["connected"], false);
+        assert_eq!(status["disconnect_reason"], "timeout");
+        assert_eq!(link.connected_secs(), None);
+    }
 
     #[tokio::test]
     async fn get_or_create_channel_creates_new() {
@@ -418,6 +1513,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn quality_fallback_changes_the_effective_preset() {
+        let channel = SignalingChannel::new();
+        *channel.quality.write().unwrap() = QualityPreset::Lossless;
+        channel.fall_back_quality(&QualityFallback {
+            requested: QualityPreset::Lossless,
+            effective: QualityPreset::Balanced,
+            kbps: 120_000,
+            ceiling_kbps: 100_000,
+        });
+        assert_eq!(*channel.quality.read().unwrap(), QualityPreset::Balanced);
+        assert!(matches!(
+            channel.timeline.entries()[0].event,
+            SessionEvent::PresetFallback {
+                requested: QualityPreset::Lossless,
+                effective: QualityPreset::Balanced,
+                ..
+            }
+        ));
+    }
+
     #[tokio::test]
     async fn browser_kick_notification() {
         let channel = SignalingChannel::new();
@@ -455,4 +1571,243 @@ mod tests {
         let received = rx.recv().await.unwrap();
         assert_eq!(received, msg);
     }
+
+    #[test]
+    fn parse_latency_report_only_matches_stats() {
+        let stats = LatencyStats {
+            samples: 12,
+            p50_ms: 20.0,
+            p95_ms: 35.0,
+            p99_ms: 41.0,
+            pipeline_p50_ms: 5.0,
+            network_p50_ms: 8.0,
+        };
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(parse_latency_report(&json), Some(stats));
+        assert_eq!(parse_latency_report(r#"{"t":"cur","css":"text"}"#), None);
+        assert_eq!(parse_latency_report(r#"{"t":"lat","samples":"bad"}"#), None);
+    }
+
+    #[test]
+    fn parse_pipeline_stats_only_matches_reports() {
+        let stats = PipelineStats {
+            frames: 300,
+            deadline_missed: 0,
+            capture: StageTiming {
+                p50_ms: 1.5,
+                p95_ms: 3.0,
+                max_ms: 9.0,
+            },
+            encode: StageTiming::default(),
+            drain: StageTiming::default(),
+            send: StageTiming::default(),
+            queue_dropped: 2,
+            outbox_dropped: 1,
+        };
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(parse_pipeline_stats(&json), Some(stats));
+        assert_eq!(parse_pipeline_stats(r#"{"t":"lat","samples":1}"#), None);
+        assert_eq!(parse_pipeline_stats(r#"{"t":"pst","frames":-1}"#), None);
+    }
+
+    #[test]
+    fn parse_agent_health_only_matches_reports() {
+        let health = AgentHealth {
+            rss_mb: 310,
+            cpu_pct: 42.5,
+            capture_fps: 59.8,
+            encode_fps: 59.6,
+            target_fps: 60,
+            recoveries: 1,
+            input_dropped: Default::default(),
+            av_drift_ms: -1.5,
+            audio_played_ms: 60_000,
+            audio_concealed_ms: 40,
+        };
+        let json = serde_json::to_string(&health).unwrap();
+        assert_eq!(parse_agent_health(&json), Some(health));
+        assert_eq!(parse_agent_health(r#"{"t":"pst","frames":1}"#), None);
+        assert_eq!(parse_agent_health(r#"{"t":"ah","rss_mb":"big"}"#), None);
+    }
+
+    #[test]
+    fn parse_encoder_status_only_matches_reports() {
+        let status =
+            parse_encoder_status(r#"{"t":"enc","name":"vah264enc","fallback":true}"#).unwrap();
+        assert_eq!(status.name, "vah264enc");
+        assert!(status.fallback);
+        assert_eq!(parse_encoder_status(r#"{"t":"nq","level":"good"}"#), None);
+        assert_eq!(parse_encoder_status(r#"{"t":"enc"}"#), None);
+    }
+
+    #[test]
+    fn parse_agent_info_only_matches_reports() {
+        let info = parse_agent_info(
+            r#"{"t":"ai","version":"0.4.2","encoder":"x264enc","fallback":true,"capture":"x11-shm","width":1920,"height":1080,"framerate":60}"#,
+        )
+        .unwrap();
+        assert_eq!(info.encoder, "x264enc");
+        assert!(info.fallback);
+        assert_eq!((info.width, info.height, info.framerate), (1920, 1080, 60));
+        assert_eq!(
+            parse_agent_info(r#"{"t":"enc","name":"x264enc","fallback":true}"#),
+            None
+        );
+        assert_eq!(parse_agent_info(r#"{"t":"ai","version":"0.4.2"}"#), None);
+    }
+
+    #[test]
+    fn parse_thumbnail_only_keeps_jpegs() {
+        use base64::Engine;
+        let encode = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+        let json = |jpeg: String| {
+            serde_json::to_string(&Thumbnail {
+                width: 320,
+                height: 180,
+                jpeg,
+            })
+            .unwrap()
+        };
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10];
+        assert_eq!(
+            parse_thumbnail(&json(encode(&jpeg))).as_deref(),
+            Some(&jpeg[..])
+        );
+        assert_eq!(parse_thumbnail(&json(encode(b"\x89PNG"))), None);
+        assert_eq!(parse_thumbnail(&json("not base64!".into())), None);
+        let huge = [&[0xFF, 0xD8][..], &vec![0; MAX_THUMBNAIL_BYTES]].concat();
+        assert_eq!(parse_thumbnail(&json(encode(&huge))), None);
+    }
+
+    #[test]
+    fn webcam_frames_are_validated() {
+        let frame =
+            VideoFrameHeader::webcam(640, 480, 0, 3, true).serialize_with_payload(&[1, 2, 3]);
+        assert!(is_webcam_frame(&frame));
+        // Truncated payload
+        assert!(!is_webcam_frame(&frame[..frame.len() - 1]));
+        // Screen video from a misbehaving browser is not relayed upstream
+        let video =
+            VideoFrameHeader::video(640, 480, 0, 3, true).serialize_with_payload(&[1, 2, 3]);
+        assert!(!is_webcam_frame(&video));
+        assert!(!is_webcam_frame(b"junk"));
+    }
+
+    #[test]
+    fn parse_audio_enabled_only_matches_acks() {
+        assert_eq!(
+            parse_audio_enabled(r#"{"t":"ae","enabled":false}"#),
+            Some(false)
+        );
+        assert_eq!(
+            parse_audio_enabled(r#"{"t":"ae","enabled":true}"#),
+            Some(true)
+        );
+        assert_eq!(parse_audio_enabled(r#"{"t":"am","mode":"voice"}"#), None);
+        assert_eq!(parse_audio_enabled(r#"{"t":"ae"}"#), None);
+    }
+
+    #[test]
+    fn parse_network_quality_only_matches_snapshots() {
+        let nq = parse_network_quality(
+            r#"{"t":"nq","level":"poor","rtt_ms":180.0,"loss_pct":0.0,"kbps":900,"target_kbps":10000}"#,
+        )
+        .unwrap();
+        assert_eq!(nq.level, QualityLevel::Poor);
+        assert_eq!(nq.rtt_ms, Some(180.0));
+        assert_eq!(parse_network_quality(r#"{"t":"lat","samples":1}"#), None);
+        assert_eq!(parse_network_quality(r#"{"t":"nq","level":"great"}"#), None);
+    }
+
+    #[test]
+    fn parse_stream_alert_only_matches_alerts() {
+        let alert = parse_stream_alert(
+            r#"{"t":"va","kind":"capture_stalled","active":false,"secs":12,"recover":false}"#,
+        )
+        .unwrap();
+        assert_eq!(alert.kind, StreamAlertKind::CaptureStalled);
+        assert!(!alert.active);
+        assert_eq!(alert.secs, 12);
+        // `recover` is optional
+        assert!(
+            parse_stream_alert(r#"{"t":"va","kind":"not_delivered","active":true,"secs":5}"#)
+                .is_some()
+        );
+        assert_eq!(parse_stream_alert(r#"{"t":"nq","level":"good"}"#), None);
+        assert_eq!(parse_stream_alert(r#"{"t":"va","kind":"black"}"#), None);
+    }
+
+    #[test]
+    fn parse_reconnect_request_only_matches_requests() {
+        assert_eq!(
+            parse_reconnect_request(r#"{"t":"rc","attempt":2}"#),
+            Some(ReconnectRequest { attempt: 2 })
+        );
+        assert_eq!(parse_reconnect_request(r#"{"t":"rc"}"#), None);
+    }
+
+    #[test]
+    fn parse_quality_fallback_only_matches_fallbacks() {
+        let fallback = parse_quality_fallback(
+            r#"{"t":"qf","requested":"lossless","effective":"balanced","kbps":120000,"ceiling_kbps":100000}"#,
+        )
+        .unwrap();
+        assert_eq!(fallback.requested, QualityPreset::Lossless);
+        assert_eq!(fallback.effective, QualityPreset::Balanced);
+        assert_eq!(
+            parse_quality_fallback(r#"{"t":"qf","requested":"sharp"}"#),
+            None
+        );
+        assert_eq!(parse_quality_fallback(r#"{"t":"rc","attempt":2}"#), None);
+    }
+
+    #[test]
+    fn parse_idle_notice_only_matches_notices() {
+        assert_eq!(
+            parse_idle_notice(r#"{"t":"idle","action":"destroy"}"#).map(|n| n.action),
+            Some(IdleAction::Destroy)
+        );
+        assert_eq!(parse_idle_notice(r#"{"t":"idle","action":"nap"}"#), None);
+        assert_eq!(parse_idle_notice(r#"{"t":"ae","enabled":true}"#), None);
+    }
+
+    #[test]
+    fn text_frame_follows_wire_format() {
+        let json = r#"{"type":"error","message":"replaced"}"#.to_string();
+        assert!(matches!(
+            text_frame(WireFormat::Json, json.clone()),
+            Message::Text(_)
+        ));
+        let Message::Binary(cbor) = text_frame(WireFormat::Cbor, json) else {
+            panic!("expected a binary frame");
+        };
+        assert!(is_cbor_message(&cbor));
+        assert_eq!(cbor_to_value(&cbor).unwrap()["message"], "replaced");
+        // Not JSON: sent through untouched rather than dropped
+        assert!(matches!(
+            text_frame(WireFormat::Cbor, "not json".to_string()),
+            Message::Text(_)
+        ));
+    }
+
+    #[test]
+    fn hello_capabilities_default_to_legacy_behaviour() {
+        let hello = parse_hello(r#"{"t":"hello","version":1,"capabilities":[]}"#).unwrap();
+        assert_eq!(hello.version, 1);
+        assert!(parse_hello(r#"{"t":"k","c":30,"d":true}"#).is_none());
+
+        let link = PeerLink::default();
+        let generation = link.connect();
+        // No hello yet: an agent from before versioning
+        assert!(link.supports(CAP_LOCK_SCREEN));
+        assert!(!link.announces(CAP_INPUT_TIMESTAMPS));
+        link.set_hello(generation, hello);
+        assert!(!link.supports(CAP_LOCK_SCREEN));
+        assert_eq!(link.peer_version(), Some(1));
+        assert_eq!(link.status_json()["protocol_version"], 1);
+
+        let generation = link.connect();
+        link.set_hello(generation, Hello::new(&[CAP_INPUT_TIMESTAMPS]));
+        assert!(link.announces(CAP_INPUT_TIMESTAMPS));
+    }
 }
@@ -0,0 +1,143 @@
+//! Recent per-second stats of a session.
+//!
+//! The stats API only has the agent's latest reports; by the time someone
+//! looks, the stall a user complained about is gone. Each one-second
+//! connection quality report is recorded here with the latest frame rate
+//! and latency, keeping the last `session.stats_history_secs`, and served
+//! by `GET /api/sessions/{id}/stats/history`. Kept in memory with the
+//! session's signaling channel, so it ends with the session.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use beam_protocol::{AgentHealth, LatencyStats, NetworkQuality, QualityLevel};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsSample {
+    /// Unix time of the report (seconds)
+    pub at: u64,
+    pub level: QualityLevel,
+    /// Video bitrate sent in that second, and what the encoder aimed for
+    pub kbps: u32,
+    pub target_kbps: u32,
+    /// Frames out of the encoder per second, from the latest health report
+    pub fps: Option<f64>,
+    pub rtt_ms: Option<f64>,
+    pub loss_pct: f64,
+    /// Median glass-to-glass latency from the latest latency report
+    pub latency_p50_ms: Option<f64>,
+}
+
+impl StatsSample {
+    pub fn new(
+        quality: &NetworkQuality,
+        health: Option<&AgentHealth>,
+        latency: Option<&LatencyStats>,
+    ) -> Self {
+        Self {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            level: quality.level,
+            kbps: quality.kbps,
+            target_kbps: quality.target_kbps,
+            fps: health.map(|h| h.encode_fps),
+            rtt_ms: quality.rtt_ms,
+            loss_pct: quality.loss_pct,
+            latency_p50_ms: latency.map(|l| l.p50_ms),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StatsHistory {
+    samples: Mutex<VecDeque<StatsSample>>,
+}
+
+impl StatsHistory {
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<StatsSample>> {
+        self.samples.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a sample, keeping at most `keep` (0 = record nothing).
+    pub fn record(&self, sample: StatsSample, keep: usize) {
+        let mut samples = self.lock();
+        while samples.len() >= keep.max(1) {
+            samples.pop_front();
+        }
+        if keep > 0 {
+            samples.push_back(sample);
+        }
+    }
+
+    /// Samples at or after `since` (Unix seconds), oldest first.
+    pub fn since(&self, since: u64) -> Vec<StatsSample> {
+        self.lock()
+            .iter()
+            .filter(|sample| sample.at >= since)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(at: u64, kbps: u32) -> StatsSample {
+        StatsSample {
+            at,
+            level: QualityLevel::Good,
+            kbps,
+            target_kbps: 20_000,
+            fps: Some(60.0),
+            rtt_ms: Some(12.0),
+            loss_pct: 0.0,
+            latency_p50_ms: None,
+        }
+    }
+
+    #[test]
+    fn keeps_the_most_recent_samples() {
+        let history = StatsHistory::default();
+        for at in 0..10 {
+            history.record(sample(at, at as u32), 4);
+        }
+        let kept = history.since(0);
+        assert_eq!(
+            kept.iter().map(|s| s.at).collect::<Vec<_>>(),
+            vec![6, 7, 8, 9]
+        );
+        assert_eq!(history.since(8).len(), 2);
+        assert!(history.since(10).is_empty());
+    }
+
+    #[test]
+    fn zero_keeps_nothing() {
+        let history = StatsHistory::default();
+        history.record(sample(1, 1), 0);
+        assert!(history.since(0).is_empty());
+    }
+
+    #[test]
+    fn sample_takes_fps_and_latency_from_the_latest_reports() {
+        let quality = NetworkQuality {
+            level: QualityLevel::Fair,
+            rtt_ms: None,
+            loss_pct: 2.5,
+            kbps: 8000,
+            target_kbps: 10_000,
+        };
+        let bare = StatsSample::new(&quality, None, None);
+        assert_eq!(bare.fps, None);
+        assert_eq!(bare.latency_p50_ms, None);
+        assert_eq!(bare.kbps, 8000);
+
+        let json = serde_json::to_value(&bare).unwrap();
+        assert_eq!(json["level"], "fair");
+        assert_eq!(json["loss_pct"], 2.5);
+    }
+}
@@ -0,0 +1,193 @@
+//! Sessions an admin scheduled to end.
+//!
+//! `DELETE /api/admin/sessions/{id}?after=N` ends the session N seconds
+//! later instead of at once. The user's browser is told when it is
+//! scheduled, at each of `REMINDERS` and whenever it reconnects, and the
+//! desktop shows a notification, so there is time to save work. Until then
+//! an admin can call it off.
+
+use std::sync::Arc;
+
+use beam_protocol::{AgentCommand, CAP_NOTIFY, SignalingMessage};
+use tokio::task::AbortHandle;
+use tokio::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::signaling::{self, SignalingChannel};
+use crate::web::AppState;
+
+/// Longest delay an admin can schedule
+pub const MAX_DELAY_SECS: u64 = 86_400;
+
+/// Seconds before the end at which the user is reminded
+const REMINDERS: [u64; 4] = [300, 60, 30, 10];
+
+/// A pending end, kept on the session's channel
+pub struct Scheduled {
+    pub ends_at: Instant,
+    /// Admin who scheduled it
+    pub by: String,
+    task: AbortHandle,
+}
+
+impl Scheduled {
+    /// Seconds left, rounded up
+    pub fn remaining_secs(&self) -> u64 {
+        let left = self.ends_at.saturating_duration_since(Instant::now());
+        left.as_secs() + u64::from(left.subsec_nanos() > 0)
+    }
+
+    /// Stop the countdown; the session stays.
+    pub fn abort(self) {
+        self.task.abort();
+    }
+}
+
+/// The notice for a browser connecting while the session is set to end.
+pub fn pending(channel: &SignalingChannel) -> Option<SignalingMessage> {
+    let scheduled = channel
+        .termination
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    scheduled
+        .as_ref()
+        .map(|s| SignalingMessage::TerminationScheduled {
+            ends_in_secs: s.remaining_secs(),
+            from: s.by.clone(),
+        })
+}
+
+/// End the session `delay` from now, replacing any earlier schedule.
+pub async fn schedule(state: &Arc<AppState>, session_id: Uuid, delay: Duration, by: &str) {
+    let channel = signaling::get_or_create_channel(&state.channels, session_id).await;
+    let ends_at = Instant::now() + delay;
+    let task = tokio::spawn(countdown(
+        Arc::clone(state),
+        session_id,
+        ends_at,
+        reminders(delay.as_secs()),
+        by.to_string(),
+    ));
+    let previous = channel
+        .termination
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace(Scheduled {
+            ends_at,
+            by: by.to_string(),
+            task: task.abort_handle(),
+        });
+    if let Some(previous) = previous {
+        previous.abort();
+    }
+    announce(&channel, delay.as_secs(), by, true);
+}
+
+/// Call off a scheduled end. False if none was pending.
+pub async fn cancel(state: &AppState, session_id: Uuid, by: &str) -> bool {
+    let Some(channel) = state.channels.read().await.get(&session_id).cloned() else {
+        return false;
+    };
+    let Some(scheduled) = channel
+        .termination
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+    else {
+        return false;
+    };
+    scheduled.abort();
+    let notice = SignalingMessage::TerminationCancelled {
+        from: by.to_string(),
+    };
+    if let Ok(json) = serde_json::to_string(&notice) {
+        let _ = channel.to_browser.send(json);
+    }
+    if channel.agent_link.announces(CAP_NOTIFY) {
+        let _ = channel.to_agent.send(AgentCommand::Notify {
+            summary: "Session will stay open".to_string(),
+            body: format!("{by} called off the scheduled end of this session."),
+        });
+    }
+    true
+}
+
+/// Reminders due for an end `delay_secs` away, earliest first
+fn reminders(delay_secs: u64) -> Vec<u64> {
+    REMINDERS
+        .into_iter()
+        .filter(|&secs| secs < delay_secs)
+        .collect()
+}
+
+/// Tell the browser, and for a minute or more the desktop too, how long
+/// the session has left.
+fn announce(channel: &SignalingChannel, secs: u64, by: &str, desktop: bool) {
+    let notice = SignalingMessage::TerminationScheduled {
+        ends_in_secs: secs,
+        from: by.to_string(),
+    };
+    if let Ok(json) = serde_json::to_string(&notice) {
+        let _ = channel.to_browser.send(json);
+    }
+    if desktop && channel.agent_link.announces(CAP_NOTIFY) {
+        let _ = channel.to_agent.send(AgentCommand::Notify {
+            summary: format!("Session ends in {}", crate::web::format_wait(secs)),
+            body: format!("{by} scheduled this session to end. Save your work."),
+        });
+    }
+}
+
+async fn countdown(
+    state: Arc<AppState>,
+    session_id: Uuid,
+    ends_at: Instant,
+    reminders: Vec<u64>,
+    by: String,
+) {
+    let channel = || async { state.channels.read().await.get(&session_id).cloned() };
+    for secs in reminders {
+        tokio::time::sleep_until(ends_at - Duration::from_secs(secs)).await;
+        let Some(channel) = channel().await else {
+            return;
+        };
+        announce(&channel, secs, &by, secs >= 60);
+    }
+    tokio::time::sleep_until(ends_at).await;
+
+    // Past this point a cancel finds nothing to call off
+    if let Some(channel) = channel().await {
+        channel
+            .termination
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+    if state
+        .session_manager
+        .get_session(session_id)
+        .await
+        .is_none()
+    {
+        return;
+    }
+    if let Err(e) = state.session_manager.destroy_session(session_id).await {
+        tracing::error!(%session_id, "Failed to end scheduled session: {e:#}");
+        return;
+    }
+    signaling::remove_channel(&state.channels, session_id).await;
+    tracing::info!(target: "audit", event = "admin_session_destroyed", %session_id, admin = %by, scheduled = true, "Session destroyed by admin");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reminders_fall_inside_the_delay() {
+        assert_eq!(reminders(600), vec![300, 60, 30, 10]);
+        assert_eq!(reminders(300), vec![60, 30, 10]);
+        assert_eq!(reminders(45), vec![30, 10]);
+        assert!(reminders(10).is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+//! Per-session event timeline.
+//!
+//! Milestones of a session's life (agent starts, crashes and upgrades,
+//! browsers coming and going, resizes, quality and encoder changes, frozen video)
+//! recorded as they happen, so `GET /api/admin/sessions/{id}/events` can
+//! show one session's story without picking it out of interleaved logs.
+//! Kept in memory with the session's signaling channel, and published to
+//! the admin event feed.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use beam_protocol::{IdleAction, QualityLevel, QualityPreset, StreamAlertKind};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Events kept per session; the oldest go first
+const MAX_EVENTS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Peer {
+    Browser,
+    Agent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    Created {
+        username: String,
+    },
+    /// Picked up again after a server restart
+    Restored,
+    /// Found running after the server crashed, and taken over
+    Adopted,
+    AgentSpawned,
+    /// The agent process ended (None = killed by a signal, or unknown)
+    AgentExited {
+        code: Option<i32>,
+    },
+    AgentRestarted {
+        attempt: u32,
+    },
+    /// An admin had the agent execute a new binary in its place
+    AgentUpgrading {
+        by: String,
+    },
+    Connected {
+        peer: Peer,
+    },
+    Disconnected {
+        peer: Peer,
+        reason: &'static str,
+    },
+    /// First video frame relayed to a newly connected browser
+    FirstFrame,
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    QualityChanged {
+        from: Option<QualityLevel>,
+        to: QualityLevel,
+    },
+    EncoderStarted {
+        name: String,
+        fallback: bool,
+    },
+    BitrateCap {
+        kbps: u32,
+    },
+    /// The agent dropped `requested` for staying over the quality ceiling
+    PresetFallback {
+        requested: QualityPreset,
+        effective: QualityPreset,
+        kbps: u32,
+        ceiling_kbps: u32,
+    },
+    Idle {
+        action: IdleAction,
+    },
+    /// The agent found the browser's picture frozen
+    StreamStalled {
+        kind: StreamAlertKind,
+    },
+    /// Video flows again after `secs` frozen
+    StreamResumed {
+        kind: StreamAlertKind,
+        secs: u32,
+    },
+    /// The agent asked the browser to reconnect to get video through
+    ReconnectRequested {
+        attempt: u32,
+    },
+    /// The session was destroyed
+    Ended,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimelineEntry {
+    /// Unix time in milliseconds
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub event: SessionEvent,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    entries: Mutex<VecDeque<TimelineEntry>>,
+    /// Set to publish entries to the admin event feed
+    session_id: Option<Uuid>,
+}
+
+impl Timeline {
+    pub fn for_session(session_id: Uuid) -> Self {
+        Self {
+            session_id: Some(session_id),
+            ..Self::default()
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<TimelineEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(&self, event: SessionEvent) {
+        let at_ms = crate::events::now_ms();
+        if let Some(session_id) = self.session_id {
+            crate::events::session(session_id, at_ms, event.clone());
+        }
+        let mut entries = self.lock();
+        if entries.len() >= MAX_EVENTS {
+            entries.pop_front();
+        }
+        entries.push_back(TimelineEntry { at_ms, event });
+    }
+
+    /// Every kept event, oldest first.
+    pub fn entries(&self) -> Vec<TimelineEntry> {
+        self.lock().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_events_make_room() {
+        let timeline = Timeline::default();
+        timeline.record(SessionEvent::AgentSpawned);
+        for width in 0..MAX_EVENTS as u32 {
+            timeline.record(SessionEvent::Resize { width, height: 720 });
+        }
+        let entries = timeline.entries();
+        assert_eq!(entries.len(), MAX_EVENTS);
+        assert_eq!(
+            entries[0].event,
+            SessionEvent::Resize {
+                width: 0,
+                height: 720
+            }
+        );
+        assert!(entries.windows(2).all(|w| w[0].at_ms <= w[1].at_ms));
+    }
+
+    #[test]
+    fn events_serialize_flat() {
+        let entry = TimelineEntry {
+            at_ms: 1_700_000_000_000,
+            event: SessionEvent::Disconnected {
+                peer: Peer::Browser,
+                reason: "timeout",
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            serde_json::json!({
+                "at_ms": 1_700_000_000_000u64,
+                "event": "disconnected",
+                "peer": "browser",
+                "reason": "timeout",
+            })
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Logins from a users file (`auth.users_file`) instead of PAM.
+//!
+//! The file holds one `name:hash` line per user, as `htpasswd -B` (bcrypt)
+//! or `beam-server users add` (argon2id) write them; `#` starts a comment.
+//! It is read on every login, so edits apply without a restart. The file
+//! only vouches for the password: sessions run as the system account of
+//! the same name, which must exist and not be root.
+
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::Path;
+use std::sync::LazyLock;
+
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+
+/// Checked against when the user doesn't exist, so an unknown name takes
+/// as long to refuse as a wrong password
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| hash("not a password").expect("hashing a constant"));
+
+/// Check a login against the users file.
+pub fn verify(path: &Path, username: &str, password: &str) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read users file {}", path.display()))?;
+    let Some(hash) = find(&text, username) else {
+        let _ = verify_hash(&DUMMY_HASH, password);
+        bail!("No such user");
+    };
+    if !verify_hash(hash, password)? {
+        bail!("Wrong password");
+    }
+    Ok(())
+}
+
+/// Hash a password for the users file, with argon2id.
+pub fn hash(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut salt))
+        .context("Failed to read random bytes")?;
+    let salt = SaltString::encode_b64(&salt).map_err(|e| anyhow!("Bad salt: {e}"))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("Failed to hash password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+fn verify_hash(hash: &str, password: &str) -> Result<bool> {
+    if hash.starts_with("$argon2") {
+        let parsed = PasswordHash::new(hash).map_err(|e| anyhow!("Bad argon2 hash: {e}"))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok())
+    } else if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).context("Bad bcrypt hash")
+    } else {
+        bail!("Unsupported password hash; use bcrypt (htpasswd -B) or argon2")
+    }
+}
+
+/// (name, hash) of each user line
+fn entries(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+}
+
+fn find<'a>(text: &'a str, username: &str) -> Option<&'a str> {
+    entries(text)
+        .find(|(name, _)| *name == username)
+        .map(|(_, hash)| hash)
+}
+
+/// `text` with `username`'s line replaced by `line`, or appended if there
+/// was none (`line` None removes it). Other lines are kept as they are.
+fn replace(text: &str, username: &str, line: Option<&str>) -> (String, bool) {
+    let mut out = String::with_capacity(text.len());
+    let mut found = false;
+    for existing in text.lines() {
+        let is_user = !existing.trim_start().starts_with('#')
+            && existing.trim().split_once(':').map(|(name, _)| name) == Some(username);
+        if is_user {
+            if !found && let Some(line) = line {
+                out.push_str(line);
+                out.push('\n');
+            }
+            found = true;
+        } else {
+            out.push_str(existing);
+            out.push('\n');
+        }
+    }
+    if !found && let Some(line) = line {
+        out.push_str(line);
+        out.push('\n');
+    }
+    (out, found)
+}
+
+/// Replace the file through a temporary one, keeping its owner and mode
+/// (0600 for a new file).
+fn write(path: &Path, text: &str) -> Result<()> {
+    let existing = std::fs::metadata(path).ok();
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp = Path::new(&tmp_name);
+    let mode = existing.as_ref().map_or(0o600, |meta| meta.mode() & 0o7777);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    file.write_all(text.as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    if let Some(meta) = existing {
+        std::os::unix::fs::chown(tmp, Some(meta.uid()), Some(meta.gid()))
+            .with_context(|| format!("Failed to keep the owner of {}", path.display()))?;
+    }
+    std::fs::rename(tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+fn read_or_empty(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        read => read.with_context(|| format!("Failed to read users file {}", path.display())),
+    }
+}
+
+/// Ask for a new password twice on the terminal, or read one line from
+/// stdin with `from_stdin`.
+fn read_new_password(from_stdin: bool) -> Result<String> {
+    let stdin = std::io::stdin();
+    let read_line = |prompt: &str| -> Result<String> {
+        let mut line = String::new();
+        if !prompt.is_empty() {
+            eprint!("{prompt}");
+        }
+        stdin
+            .lock()
+            .read_line(&mut line)
+            .context("Failed to read password")?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let password = if from_stdin {
+        read_line("")?
+    } else {
+        if !stdin.is_terminal() {
+            bail!("No terminal to prompt for a password on; use --password-stdin");
+        }
+        let stty = |arg: &str| {
+            std::process::Command::new("stty")
+                .arg(arg)
+                .stdin(std::process::Stdio::inherit())
+                .status()
+        };
+        let hidden = stty("-echo").is_ok_and(|s| s.success());
+        let entered = read_line("Password: ").and_then(|first| {
+            if hidden {
+                eprintln!();
+            }
+            let again = read_line("Again: ")?;
+            Ok((first, again))
+        });
+        if hidden {
+            let _ = stty("echo");
+            eprintln!();
+        }
+        let (first, again) = entered?;
+        if first != again {
+            bail!("The passwords don't match");
+        }
+        first
+    };
+    if password.is_empty() {
+        bail!("The password must not be empty");
+    }
+    Ok(password)
+}
+
+/// `beam-server users <list | add NAME | remove NAME>` on `path`.
+pub fn command(path: &Path, args: &[String], password_stdin: bool) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] | ["list"] => {
+            for (name, _) in entries(&read_or_empty(path)?) {
+                println!("{name}");
+            }
+        }
+        ["add", name] => {
+            if !crate::web::is_valid_username(name) {
+                bail!("Invalid user name: {name} (letters, digits, '.', '_' and '-')");
+            }
+            let password = read_new_password(password_stdin)?;
+            let line = format!("{name}:{}", hash(&password)?);
+            let (text, updated) = replace(&read_or_empty(path)?, name, Some(&line));
+            write(path, &text)?;
+            let done = if updated { "Updated" } else { "Added" };
+            println!("{done} {name} in {}", path.display());
+        }
+        ["remove", name] => {
+            let (text, found) = replace(&read_or_empty(path)?, name, None);
+            if !found {
+                bail!("No user {name} in {}", path.display());
+            }
+            write(path, &text)?;
+            println!("Removed {name} from {}", path.display());
+        }
+        _ => bail!("Usage: beam-server users [list | add <NAME> | remove <NAME>]"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("beam-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("users")
+    }
+
+    #[test]
+    fn bcrypt_and_argon2_hashes_verify() {
+        let path = test_path("users-verify");
+        let bcrypt = bcrypt::hash("hunter2", 4).unwrap();
+        let argon = hash("correct horse").unwrap();
+        assert!(argon.starts_with("$argon2id$"));
+        std::fs::write(
+            &path,
+            format!("# homelab\nalice:{bcrypt}\n\n  bob:{argon}\nplain:secret\n"),
+        )
+        .unwrap();
+
+        assert!(verify(&path, "alice", "hunter2").is_ok());
+        assert!(verify(&path, "alice", "hunter3").is_err());
+        assert!(verify(&path, "bob", "correct horse").is_ok());
+        assert!(verify(&path, "bob", "hunter2").is_err());
+        // Unknown users, and plain text passwords, never log in
+        assert!(verify(&path, "carol", "").is_err());
+        assert!(verify(&path, "plain", "secret").is_err());
+        assert!(verify(&path, "# homelab", "").is_err());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn users_are_added_updated_and_removed_in_place() {
+        let text = "# admins\nalice:old\nbob:hash\n";
+        let (added, found) = replace(text, "carol", Some("carol:new"));
+        assert!(!found);
+        assert_eq!(added, "# admins\nalice:old\nbob:hash\ncarol:new\n");
+        let (updated, found) = replace(text, "alice", Some("alice:new"));
+        assert!(found);
+        assert_eq!(updated, "# admins\nalice:new\nbob:hash\n");
+        let (removed, found) = replace(text, "bob", None);
+        assert!(found);
+        assert_eq!(removed, "# admins\nalice:old\n");
+        assert!(!replace(text, "admins", None).1);
+    }
+
+    #[test]
+    fn new_users_files_are_private() {
+        let path = test_path("users-write");
+        write(&path, "alice:hash\n").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+        // Kept on rewrite
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o640))
+            .unwrap();
+        write(&path, "alice:other\n").unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o777, 0o640);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "alice:other\n");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use axum::extract::ws::WebSocketUpgrade;
@@ -6,7 +7,10 @@ use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use beam_protocol::{AuthRequest, AuthResponse, BeamConfig, SignalingMessage};
+use beam_protocol::{
+    AuthRequest, AuthResponse, BeamConfig, Desktop, PROTOCOL_VERSION, QualityPreset, RELEASE,
+    SignalingMessage, VersionSkew,
+};
 use serde::Deserialize;
 use serde_json::json;
 use tower_http::limit::RequestBodyLimitLayer;
@@ -14,8 +18,14 @@ use tower_http::services::ServeDir;
 use uuid::Uuid;
 
 use crate::auth;
+use crate::bans::IpBans;
+use crate::drain::DrainState;
+use crate::forwarded;
+use crate::guest;
 use crate::session::SessionManager;
 use crate::signaling::{self, ChannelRegistry};
+use crate::termination;
+use crate::timeline::SessionEvent;
 
 /// Shared application state.
 pub struct AppState {
@@ -26,11 +36,25 @@ pub struct AppState {
     pub login_limiter: LoginRateLimiter,
     pub ip_limiter: LoginRateLimiter,
     pub release_limiter: LoginRateLimiter,
+    /// Requests to `/api/nettest` per user (each probe makes four)
+    pub nettest_limiter: LoginRateLimiter,
+    /// Escalating bans for addresses that exhaust `ip_limiter`
+    pub ip_bans: IpBans,
     pub started_at: std::time::Instant,
+    /// Drain mode: no new sessions, shut down once existing ones end
+    pub drain: DrainState,
     /// Metrics counters (atomic for lock-free thread safety)
     pub metrics_logins_attempted: std::sync::atomic::AtomicU64,
     pub metrics_logins_failed: std::sync::atomic::AtomicU64,
     pub metrics_agent_restarts: std::sync::atomic::AtomicU64,
+    /// Proxies whose `X-Forwarded-For` is believed (`server.trusted_proxies`)
+    pub trusted_proxies: crate::forwarded::TrustedProxies,
+    /// Country lookups for the login audit trail (`server.geoip_db`)
+    pub geoip: Option<crate::geoip::GeoIp>,
+    /// What each user chose last time, such as their quality preset
+    pub preferences: crate::preferences::Preferences,
+    /// Throwaway accounts of guest sessions, until they are deleted
+    pub guests: crate::guest::Guests,
 }
 
 /// Simple per-key rate limiter for login attempts.
@@ -199,16 +223,60 @@ pub fn build_router(state: Arc<AppState>) -> Router {
     let api = Router::new()
         .route("/api/auth/login", post(login))
         .route("/api/auth/refresh", post(refresh_token))
+        .route("/api/guest", post(create_guest))
         .route("/api/sessions", get(list_sessions))
         .route("/api/sessions/{id}", delete(delete_session))
         .route("/api/sessions/{id}/release", post(release_session))
         .route("/api/sessions/{id}/heartbeat", post(session_heartbeat))
+        .route("/api/sessions/{id}/audio", post(set_session_audio))
+        .route("/api/sessions/{id}/quality", post(set_session_quality))
+        .route("/api/sessions/{id}/nettest", post(report_session_nettest))
         .route("/api/sessions/{id}/ws", get(browser_ws_upgrade))
+        .route(
+            "/api/sessions/{id}/share",
+            post(create_share).get(list_shares).delete(revoke_shares),
+        )
+        .route("/api/sessions/{id}/share/{share_id}", delete(revoke_share))
+        .route("/api/share/{token}/ws", get(viewer_ws_upgrade))
+        .route(
+            "/api/sessions/{id}/privacy",
+            get(get_privacy).put(set_privacy),
+        )
         .route("/api/admin/sessions", get(admin_list_sessions))
+        .route("/api/admin/events/ws", get(admin_events_ws))
         .route("/api/admin/sessions/{id}", delete(admin_delete_session))
+        .route(
+            "/api/admin/sessions/{id}/termination",
+            delete(admin_cancel_termination),
+        )
+        .route("/api/admin/sessions/{id}/stats", get(admin_session_stats))
+        .route("/api/admin/sessions/{id}/events", get(admin_session_events))
+        .route(
+            "/api/admin/sessions/{id}/notify",
+            post(admin_notify_session),
+        )
+        .route(
+            "/api/admin/sessions/{id}/thumbnail",
+            get(admin_session_thumbnail),
+        )
+        .route(
+            "/api/admin/sessions/{id}/upgrade",
+            post(admin_upgrade_session),
+        )
+        .route("/api/admin/agents/upgrade", post(admin_upgrade_agents))
+        .route(
+            "/api/sessions/{id}/stats/history",
+            get(session_stats_history),
+        )
+        .route("/api/admin/drain", post(admin_drain))
+        .route("/api/admin/bans", get(admin_list_bans))
+        .route("/api/admin/displays", get(admin_list_displays))
+        .route("/api/admin/bans/{ip}", delete(admin_lift_ban))
+        .route("/api/nettest", get(nettest))
         .route("/api/health", get(health_check))
         .route("/api/health/detailed", get(health_check_detailed))
         .route("/metrics", get(metrics))
+        .route("/api/agent/handshake", post(agent_handshake))
         .route("/ws/agent/{id}", get(agent_ws_upgrade))
         .layer(RequestBodyLimitLayer::new(65_536)) // 64KB max request body
         .with_state(Arc::clone(&state));
@@ -286,7 +354,7 @@ fn extract_claims_from_headers(
 
 /// Validate that a username is non-empty, at most 64 chars, and contains only
 /// alphanumeric ASCII characters plus `_`, `-`, and `.`.
-fn is_valid_username(username: &str) -> bool {
+pub(crate) fn is_valid_username(username: &str) -> bool {
     !username.is_empty()
         && username.len() <= 64
         && username
@@ -316,20 +384,90 @@ fn normalize_ip_for_rate_limit(ip: std::net::IpAddr) -> String {
     }
 }
 
+/// Count a failed login against an address, banning it once it has used
+/// up the per-IP limit. The limiter starts over for the ban's end, so each
+/// repeat offence takes a full window of failures and earns a longer ban.
+fn record_ip_failure(state: &AppState, peer_ip: &str, audit_ip: &str) {
+    state.ip_limiter.record_failure(peer_ip);
+    if state.ip_limiter.is_allowed(peer_ip) {
+        return;
+    }
+    if let Some((length, strikes)) = state.ip_bans.ban(peer_ip) {
+        state.ip_limiter.clear(peer_ip);
+        tracing::warn!(target: "audit", event = "ip_banned", key = peer_ip, client_ip = audit_ip, secs = length.as_secs(), strikes, "Address banned after repeated login failures");
+        crate::events::alert(
+            "ip_banned",
+            None,
+            format!(
+                "{audit_ip} banned for {} after repeated login failures",
+                format_wait(length.as_secs())
+            ),
+        );
+    }
+}
+
+/// A ban's remaining time for the login error, rounded up: "5 minutes", "2 hours"
+pub(crate) fn format_wait(secs: u64) -> String {
+    let (n, unit) = match secs {
+        0..120 => (secs, "second"),
+        120..7200 => (secs.div_ceil(60), "minute"),
+        7200..172_800 => (secs.div_ceil(3600), "hour"),
+        _ => (secs.div_ceil(86_400), "day"),
+    };
+    format!("{n} {unit}{}", if n == 1 { "" } else { "s" })
+}
+
+/// Check a login against the browser already on its session. Returns the
+/// 409 body when one is connected and the user hasn't asked to take over;
+/// with `takeover`, that browser is told it was replaced and dropped now
+/// rather than when the new one connects.
+fn take_over_browser(
+    channel: &signaling::SignalingChannel,
+    existing: &beam_protocol::SessionInfo,
+    takeover: bool,
+) -> Option<serde_json::Value> {
+    let connected_secs = channel.browser_link.connected_secs()?;
+    if takeover {
+        channel.browser_kick.notify_waiters();
+        return None;
+    }
+    Some(json!({
+        "error": "This session is open in another browser",
+        "in_use": true,
+        "connected_secs": connected_secs,
+        "client": existing.client,
+    }))
+}
+
 /// POST /api/auth/login
 ///
 /// Authenticate via PAM and return a JWT + session.
 async fn login(
     State(state): State<Arc<AppState>>,
     peer: Option<axum::extract::Extension<std::net::SocketAddr>>,
+    headers: HeaderMap,
     Json(req): Json<AuthRequest>,
 ) -> impl IntoResponse {
-    let peer_ip = peer
-        .map(|axum::extract::Extension(addr)| normalize_ip_for_rate_limit(addr.ip()))
+    let client_ip = peer.map(|axum::extract::Extension(addr)| {
+        forwarded::client_ip(addr.ip(), &headers, &state.trusted_proxies)
+    });
+    let peer_ip = client_ip
+        .map(normalize_ip_for_rate_limit)
         .unwrap_or_else(|| {
             tracing::warn!("Could not extract peer address from connection");
             "unknown".to_string()
         });
+    let client = client_ip.map(|ip| forwarded::login_client(ip, &headers, state.geoip.as_ref()));
+    // Audit trail fields
+    let audit_ip = client.as_ref().map_or("unknown", |c| c.ip.as_str());
+    let audit_agent = client
+        .as_ref()
+        .and_then(|c| c.user_agent.as_deref())
+        .unwrap_or("-");
+    let audit_country = client
+        .as_ref()
+        .and_then(|c| c.country.as_deref())
+        .unwrap_or("-");
     tracing::info!(username = %req.username, peer_ip = %peer_ip, "Login request");
 
     // Validate username before anything else (before rate limiter to avoid
@@ -354,11 +492,46 @@ async fn login(
             .into_response();
     }
 
+    // Only the config may run a command of its own as the desktop
+    if let Some(Desktop::Custom(_)) = &req.desktop {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": format!("desktop must be one of {}", Desktop::NAMED.join(", "))
+            })),
+        )
+            .into_response();
+    }
+
+    // Remote assistance is off unless the server opts in
+    if req.assist && !state.config.assist.enabled {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Remote assistance is disabled on this server" })),
+        )
+            .into_response();
+    }
+
     // Count every valid login attempt for metrics
     state
         .metrics_logins_attempted
         .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
+    // Banned addresses are turned away until the ban runs out
+    if let Some(left) = state.ip_bans.remaining(&peer_ip) {
+        let secs = left.as_secs().max(1);
+        tracing::warn!(target: "audit", event = "rate_limited", limiter = "ban", username = %req.username, client_ip = audit_ip, user_agent = audit_agent, country = audit_country, "Rate limit exceeded");
+        state
+            .metrics_logins_failed
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(axum::http::header::RETRY_AFTER, secs.to_string())],
+            Json(json!({ "error": format!("Too many failed logins from your address. Try again in {}.", format_wait(secs)) })),
+        )
+            .into_response();
+    }
+
     // Rate limit: check BEFORE auth to avoid wasting PAM calls.
     // Only failures are recorded (after auth), so legitimate users can't be
     // locked out by an attacker sending requests with their username.
@@ -367,7 +540,7 @@ async fn login(
     if !username_allowed || !ip_allowed {
         let reason = if !username_allowed { "username" } else { "ip" };
         tracing::warn!(username = %req.username, peer_ip = %peer_ip, limiter = reason, "Login rate limited");
-        tracing::warn!(target: "audit", event = "rate_limited", limiter = reason, "Rate limit exceeded");
+        tracing::warn!(target: "audit", event = "rate_limited", limiter = reason, username = %req.username, client_ip = audit_ip, user_agent = audit_agent, country = audit_country, "Rate limit exceeded");
         state
             .metrics_logins_failed
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
@@ -383,9 +556,10 @@ async fn login(
     // on misconfigured LDAP/SSSD backends
     let username = req.username.clone();
     let password = req.password.clone();
+    let auth_config = state.config.auth.clone();
     let pam_result = tokio::time::timeout(
         std::time::Duration::from_secs(30),
-        tokio::task::spawn_blocking(move || auth::authenticate_pam(&username, &password)),
+        tokio::task::spawn_blocking(move || auth::authenticate(&auth_config, &username, &password)),
     )
     .await;
 
@@ -394,7 +568,7 @@ async fn login(
             // PAM timeout counts as a failure (may indicate LDAP being hammered)
             tracing::warn!(username = %req.username, "PAM authentication timed out (30s)");
             state.login_limiter.record_failure(&req.username);
-            state.ip_limiter.record_failure(&peer_ip);
+            record_ip_failure(&state, &peer_ip, audit_ip);
             state
                 .metrics_logins_failed
                 .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
@@ -410,14 +584,14 @@ async fn login(
             // Don't clear IP limiter — one success shouldn't unlock the IP for
             // other usernames being brute-forced from the same source.
             state.login_limiter.clear(&req.username);
-            tracing::info!(target: "audit", event = "login_success", username = %req.username, "User logged in");
+            tracing::info!(target: "audit", event = "login_success", username = %req.username, client_ip = audit_ip, user_agent = audit_agent, country = audit_country, "User logged in");
         }
         Ok(Ok(Err(e))) => {
             // Bad credentials — record failure against both username and IP
             tracing::warn!(username = %req.username, "Authentication failed: {e}");
-            tracing::info!(target: "audit", event = "login_failure", username = %req.username, "Login failed");
+            tracing::info!(target: "audit", event = "login_failure", username = %req.username, client_ip = audit_ip, user_agent = audit_agent, country = audit_country, "Login failed");
             state.login_limiter.record_failure(&req.username);
-            state.ip_limiter.record_failure(&peer_ip);
+            record_ip_failure(&state, &peer_ip, audit_ip);
             state
                 .metrics_logins_failed
                 .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
@@ -455,25 +629,73 @@ async fn login(
         }
     };
 
+    // Command-line clients only want the token, not a desktop
+    if req.token_only {
+        return (StatusCode::OK, Json(json!({ "token": token }))).into_response();
+    }
+
+    // Attaching to someone's physical display is for admins only
+    if req.assist && !is_admin(&state, &req.username) {
+        tracing::warn!(target: "audit", event = "assist_denied", username = %req.username, client_ip = audit_ip, country = audit_country, "Non-admin attempted remote assistance");
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Only administrators may use remote assistance" })),
+        )
+            .into_response();
+    }
+
     // Reuse existing session if the user already has one running.
     // The desktop state (windows, files, etc.) is preserved across reconnects.
-    if let Some(existing) = state.session_manager.find_by_username(&req.username).await {
+    if let Some(existing) = state
+        .session_manager
+        .find_for_login(&req.username, req.assist)
+        .await
+    {
         tracing::info!(
             session_id = %existing.id,
             username = %req.username,
             "Reusing existing session"
         );
         // Ensure signaling channel exists (may have been cleaned up)
-        signaling::get_or_create_channel(&state.channels, existing.id).await;
+        let channel = signaling::get_or_create_channel(&state.channels, existing.id).await;
+
+        if let Some(in_use) = take_over_browser(&channel, &existing, req.takeover) {
+            tracing::info!(session_id = %existing.id, username = %req.username, "Session open in another browser");
+            return (StatusCode::CONFLICT, Json(in_use)).into_response();
+        }
+        if req.takeover {
+            tracing::info!(target: "audit", event = "session_takeover", session_id = %existing.id, username = %req.username, client_ip = audit_ip, country = audit_country, "Session taken over from another browser");
+        }
+
+        // The password was just checked, which stands in for the in-session
+        // unlock prompt
+        if state.config.session.lock_on_disconnect
+            && state.config.session.unlock_on_login
+            && channel.agent_link.supports(beam_protocol::CAP_LOCK_SCREEN)
+            && channel
+                .to_agent
+                .send(beam_protocol::AgentCommand::UnlockScreen)
+                .is_err()
+        {
+            tracing::warn!(session_id = %existing.id, "No agent listening to unlock the desktop");
+        }
 
         // Cancel any pending grace-period cleanup since the user is reconnecting
         state.session_manager.cancel_grace_period(existing.id).await;
+        state
+            .session_manager
+            .set_login_client(existing.id, client)
+            .await;
 
         let release_token = state.session_manager.get_release_token(existing.id).await;
         let effective_timeout = state
             .session_manager
             .get_idle_timeout(existing.id, state.config.session.idle_timeout)
             .await;
+        let system_combos = state
+            .session_manager
+            .allows_system_combos(existing.id, state.config.session.system_combos)
+            .await;
 
         return (
             StatusCode::OK,
@@ -482,30 +704,58 @@ async fn login(
                 session_id: existing.id,
                 release_token,
                 idle_timeout: Some(effective_timeout),
+                clipboard: Some(state.config.session.clipboard_policy_for(&req.username)),
+                primary_selection: Some(state.config.session.primary_selection),
+                share_links: Some(state.config.session.share_links),
+                assist: existing.assist.is_some().then_some(true),
+                quality: Some(*channel.quality.read().unwrap_or_else(|e| e.into_inner())),
+                system_combos: Some(system_combos),
             })),
         )
             .into_response();
     }
 
-    // No existing session — create a new one
+    // No existing session — create a new one, unless shutting down
+    if state.drain.is_draining() {
+        tracing::info!(username = %req.username, "Refusing new session while draining");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "Server is restarting for maintenance. Try again shortly." })),
+        )
+            .into_response();
+    }
     let server_url = format!("wss://127.0.0.1:{}", state.config.server.port);
     let max_sessions = state.config.session.max_sessions as usize;
 
     let session = match state
         .session_manager
-        .create_session(
-            &req.username,
-            &server_url,
-            max_sessions,
-            req.viewport_width,
-            req.viewport_height,
-            req.idle_timeout,
-        )
+        .create_session(&req, &server_url, max_sessions)
         .await
     {
         Ok(s) => s,
         Err(e) => {
             let msg = e.to_string();
+            if req.assist && !msg.starts_with("Failed to spawn") {
+                // Nobody at the console, a Wayland session, someone else assisting
+                tracing::warn!(username = %req.username, "Remote assistance unavailable: {e:#}");
+                return (StatusCode::CONFLICT, Json(json!({ "error": msg }))).into_response();
+            }
+            if let Some(refused) = e.downcast_ref::<crate::pam_session::AccountRefused>() {
+                tracing::warn!(target: "audit", event = "login_account_refused", username = %req.username, client_ip = audit_ip, reason = %refused.0, "PAM account check refused login");
+                return (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({ "error": format!("This account can't log in: {}", refused.0) })),
+                )
+                    .into_response();
+            }
+            if let Some(unusable) = e.downcast_ref::<crate::session::UnusableAccount>() {
+                tracing::warn!(target: "audit", event = "login_account_unusable", username = %req.username, client_ip = audit_ip, reason = %unusable.0, "No account to run the session as");
+                return (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({ "error": format!("This account can't log in: {}", unusable.0) })),
+                )
+                    .into_response();
+            }
             if msg.contains("Maximum number of sessions") {
                 tracing::warn!(username = %req.username, "Max sessions reached");
                 return (
@@ -523,8 +773,26 @@ async fn login(
         }
     };
 
+    if let Some(allowed) = req.system_combos {
+        state
+            .session_manager
+            .set_system_combos(session.id, allowed)
+            .await;
+    }
+
     // Create signaling channel
-    signaling::get_or_create_channel(&state.channels, session.id).await;
+    let channel = signaling::get_or_create_channel(&state.channels, session.id).await;
+    channel.timeline.record(SessionEvent::Created {
+        username: req.username.clone(),
+    });
+    // The agent is told when it connects
+    let quality = state
+        .preferences
+        .get(&req.username)
+        .quality
+        .unwrap_or_default();
+    *channel.quality.write().unwrap_or_else(|e| e.into_inner()) = quality;
+    channel.timeline.record(SessionEvent::AgentSpawned);
 
     // Monitor agent process in the background
     spawn_agent_monitor(Arc::clone(&state), session.id).await;
@@ -534,6 +802,10 @@ async fn login(
         .session_manager
         .get_idle_timeout(session.id, state.config.session.idle_timeout)
         .await;
+    let system_combos = state
+        .session_manager
+        .allows_system_combos(session.id, state.config.session.system_combos)
+        .await;
 
     tracing::info!(
         session_id = %session.id,
@@ -541,7 +813,14 @@ async fn login(
         display = session.display,
         "Session created"
     );
-    tracing::info!(target: "audit", event = "session_created", session_id = %session.id, username = %req.username, "Session created");
+    tracing::info!(target: "audit", event = "session_created", session_id = %session.id, username = %req.username, client_ip = audit_ip, country = audit_country, "Session created");
+    if let Some(local_user) = &session.assist {
+        tracing::info!(target: "audit", event = "assist_requested", session_id = %session.id, username = %req.username, local_user = %local_user, display = session.display, "Remote assistance requested; waiting for consent");
+    }
+    state
+        .session_manager
+        .set_login_client(session.id, client)
+        .await;
 
     (
         StatusCode::OK,
@@ -550,11 +829,190 @@ async fn login(
             session_id: session.id,
             release_token,
             idle_timeout: Some(effective_timeout),
+            clipboard: Some(state.config.session.clipboard_policy_for(&req.username)),
+            primary_selection: Some(state.config.session.primary_selection),
+            share_links: Some(state.config.session.share_links),
+            assist: session.assist.is_some().then_some(true),
+            quality: Some(quality),
+            system_combos: Some(system_combos),
         })),
     )
         .into_response()
 }
 
+/// POST /api/guest
+///
+/// Start a session on a new throwaway account (see `guest`), for admins or
+/// with `guest.token` as the Bearer token. The session ends after
+/// `guest.lifetime_secs`, when its account is deleted.
+async fn create_guest(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let config = &state.config.guest;
+    if !config.enabled {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Guest sessions are disabled on this server" })),
+        )
+            .into_response();
+    }
+
+    // Kiosks hold the guest token; admins use their own login
+    let bearer = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let by = match (&config.token, bearer) {
+        (Some(expected), Some(token))
+            if crate::session::constant_time_eq(expected.as_bytes(), token.as_bytes()) =>
+        {
+            "guest token".to_string()
+        }
+        _ => match require_admin(&state, &headers, &query, "to start a guest session").await {
+            Ok(claims) => claims.sub,
+            Err(response) => return response,
+        },
+    };
+
+    if state.drain.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "Server is restarting for maintenance. Try again shortly." })),
+        )
+            .into_response();
+    }
+
+    let lifetime = std::time::Duration::from_secs(config.lifetime_secs);
+    let username = match state
+        .guests
+        .reserve(&config.user_prefix, config.max_guests, lifetime)
+    {
+        Ok(Some(username)) => username,
+        Ok(None) => {
+            tracing::warn!(max_guests = config.max_guests, "Guest limit reached");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "error": "All guest places are taken. Try again later." })),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            tracing::error!("Failed to record guest: {e:#}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "Failed to create guest session" })),
+            )
+                .into_response();
+        }
+    };
+    let failed = |state: &AppState, username: &str| {
+        state.guests.abandon(username);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": "Failed to create guest session" })),
+        )
+            .into_response()
+    };
+
+    let token = match auth::generate_jwt(&username, &state.jwt_secret) {
+        Ok(t) => t,
+        Err(e) => {
+            tracing::error!("Failed to generate JWT: {e}");
+            return failed(&state, &username);
+        }
+    };
+    let name = username.clone();
+    let created = match tokio::task::spawn_blocking(move || guest::add_user(&name)).await {
+        Ok(result) => result,
+        Err(e) => Err(anyhow::anyhow!("Guest account task panicked: {e}")),
+    };
+    if let Err(e) = created {
+        // Perhaps the name was taken: whatever account has it isn't ours
+        tracing::error!(%username, "Failed to create guest account: {e:#}");
+        if let Err(e) = state.guests.withdraw(&username) {
+            tracing::warn!(%username, "Failed to update the guest list: {e:#}");
+        }
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": "Failed to create guest session" })),
+        )
+            .into_response();
+    }
+
+    let req = AuthRequest {
+        username: username.clone(),
+        password: String::new(),
+        viewport_width: None,
+        viewport_height: None,
+        idle_timeout: None,
+        system_combos: None,
+        nettest: None,
+        token_only: false,
+        takeover: false,
+        assist: false,
+        desktop: None,
+    };
+    let server_url = format!("wss://127.0.0.1:{}", state.config.server.port);
+    let max_sessions = state.config.session.max_sessions as usize;
+    let session = match state
+        .session_manager
+        .create_session(&req, &server_url, max_sessions)
+        .await
+    {
+        Ok(s) => s,
+        Err(e) => {
+            let msg = e.to_string();
+            if msg.contains("Maximum number of sessions") {
+                tracing::warn!(%username, "Max sessions reached");
+                state.guests.abandon(&username);
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Json(json!({ "error": msg })),
+                )
+                    .into_response();
+            }
+            tracing::error!(%username, "Failed to create guest session: {e:#}");
+            return failed(&state, &username);
+        }
+    };
+    if let Err(e) = state.guests.started(&username, session.id) {
+        tracing::warn!(%username, "Failed to update the guest list: {e:#}");
+    }
+
+    let channel = signaling::get_or_create_channel(&state.channels, session.id).await;
+    channel.timeline.record(SessionEvent::Created {
+        username: username.clone(),
+    });
+    channel.timeline.record(SessionEvent::AgentSpawned);
+    spawn_agent_monitor(Arc::clone(&state), session.id).await;
+    termination::schedule(&state, session.id, lifetime, guest::SCHEDULED_BY).await;
+
+    let release_token = state.session_manager.get_release_token(session.id).await;
+    let effective_timeout = state
+        .session_manager
+        .get_idle_timeout(session.id, state.config.session.idle_timeout)
+        .await;
+    tracing::info!(target: "audit", event = "guest_created", session_id = %session.id, %username, by = %by, lifetime_secs = config.lifetime_secs, "Guest session created");
+
+    let mut response = json!(AuthResponse {
+        token,
+        session_id: session.id,
+        release_token,
+        idle_timeout: Some(effective_timeout),
+        clipboard: Some(state.config.session.clipboard_policy_for(&username)),
+        primary_selection: Some(state.config.session.primary_selection),
+        share_links: Some(state.config.session.share_links),
+        assist: None,
+        quality: Some(*channel.quality.read().unwrap_or_else(|e| e.into_inner())),
+        system_combos: Some(state.config.session.system_combos),
+    });
+    response["username"] = json!(username);
+    response["expires_in"] = json!(config.lifetime_secs);
+    (StatusCode::OK, Json(response)).into_response()
+}
+
 /// POST /api/auth/refresh
 ///
 /// Accept a valid or recently-expired JWT and return a fresh one.
@@ -633,8 +1091,27 @@ const MAX_AGENT_RESTARTS: u32 = 3;
 async fn spawn_agent_monitor(state: Arc<AppState>, session_id: Uuid) {
     if let Some(mut child) = state.session_manager.take_agent_child(session_id).await {
         tokio::spawn(async move {
+            let mut exit_message = "agent_exited";
             loop {
                 let status = child.wait().await;
+                signaling::record_event(
+                    &state.channels,
+                    session_id,
+                    SessionEvent::AgentExited {
+                        code: status.as_ref().ok().and_then(|s| s.code()),
+                    },
+                )
+                .await;
+                // Declined or ended at the console: not a crash, don't restart
+                if let Some(message) = status
+                    .as_ref()
+                    .ok()
+                    .and_then(|s| crate::assist::exit_message(s.code()))
+                {
+                    tracing::info!(target: "audit", event = "assist_ended", %session_id, reason = message, "Remote assistance ended at the console");
+                    exit_message = message;
+                    break;
+                }
                 let should_restart = match &status {
                     Ok(exit_status) if exit_status.success() => {
                         tracing::info!(%session_id, "Agent exited cleanly");
@@ -682,6 +1159,11 @@ async fn spawn_agent_monitor(state: Arc<AppState>, session_id: Uuid) {
                         restart_count,
                         "Agent restart limit reached ({MAX_AGENT_RESTARTS}), giving up"
                     );
+                    crate::events::alert(
+                        "agent_restart_limit",
+                        Some(session_id),
+                        format!("Agent crashed {restart_count} times, ending the session"),
+                    );
                     break;
                 }
 
@@ -724,6 +1206,14 @@ async fn spawn_agent_monitor(state: Arc<AppState>, session_id: Uuid) {
                             restart_count,
                             "Agent restarted successfully"
                         );
+                        signaling::record_event(
+                            &state.channels,
+                            session_id,
+                            SessionEvent::AgentRestarted {
+                                attempt: restart_count,
+                            },
+                        )
+                        .await;
                         // Take the new child handle and continue monitoring
                         match state.session_manager.take_agent_child(session_id).await {
                             Some(new_child) => {
@@ -761,7 +1251,7 @@ async fn spawn_agent_monitor(state: Arc<AppState>, session_id: Uuid) {
                 let channels = state.channels.read().await;
                 if let Some(channel) = channels.get(&session_id) {
                     let msg = SignalingMessage::Error {
-                        message: "agent_exited".to_string(),
+                        message: exit_message.to_string(),
                     };
                     if let Ok(json) = serde_json::to_string(&msg) {
                         let _ = channel.to_browser.send(json);
@@ -787,11 +1277,14 @@ pub async fn spawn_orphan_agent_monitor(state: Arc<AppState>, session_id: Uuid,
         loop {
             tokio::time::sleep(std::time::Duration::from_secs(5)).await;
             // Check process is alive AND is actually beam-agent (guards against PID recycling)
-            let is_agent = std::fs::read_to_string(format!("/proc/{pid}/comm"))
-                .map(|name| name.trim() == "beam-agent")
-                .unwrap_or(false);
-            if !is_agent {
+            if !state.session_manager.is_agent_process(pid) {
                 tracing::warn!(%session_id, pid, "Restored agent process exited (or PID recycled)");
+                signaling::record_event(
+                    &state.channels,
+                    session_id,
+                    SessionEvent::AgentExited { code: None },
+                )
+                .await;
                 break;
             }
         }
@@ -852,6 +1345,14 @@ pub async fn spawn_orphan_agent_monitor(state: Arc<AppState>, session_id: Uuid,
                         restart_count,
                         "Orphan agent restarted successfully, switching to child monitor"
                     );
+                    signaling::record_event(
+                        &state.channels,
+                        session_id,
+                        SessionEvent::AgentRestarted {
+                            attempt: restart_count,
+                        },
+                    )
+                    .await;
                     // Now we have a Child handle — delegate to the normal monitor
                     spawn_agent_monitor(Arc::clone(&state), session_id).await;
                     return;
@@ -877,6 +1378,11 @@ pub async fn spawn_orphan_agent_monitor(state: Arc<AppState>, session_id: Uuid,
                 restart_count,
                 "Orphan agent restart limit reached ({MAX_AGENT_RESTARTS}), giving up"
             );
+            crate::events::alert(
+                "agent_restart_limit",
+                Some(session_id),
+                format!("Agent crashed {restart_count} times, ending the session"),
+            );
         }
 
         // Notify browser and clean up
@@ -952,50 +1458,477 @@ async fn browser_ws_upgrade(
     // Cancel any pending grace-period cleanup since a browser is reconnecting
     state.session_manager.cancel_grace_period(id).await;
 
+    let system_combos = state
+        .session_manager
+        .allows_system_combos(id, state.config.session.system_combos)
+        .await;
+
     tracing::info!(%id, "Browser WebSocket upgrade");
     let channels = state.channels.clone();
+    let lock_on_disconnect = state.config.session.lock_on_disconnect;
+    let keepalive = signaling::Keepalive::from_config(&state.config.server);
     ws.max_message_size(2 * 1024 * 1024) // 2MB max (binary video frames + text input)
-        .on_upgrade(move |socket| signaling::handle_browser_ws(socket, id, channels))
+        // Browsers may opt in to CBOR-framed signaling
+        .protocols([beam_protocol::CBOR_SUBPROTOCOL])
+        .on_upgrade(move |socket| {
+            signaling::handle_browser_ws(
+                socket,
+                id,
+                channels,
+                system_combos,
+                lock_on_disconnect,
+                keepalive,
+            )
+        })
         .into_response()
 }
 
-/// POST /api/sessions/:id/heartbeat - update session activity (requires JWT + session ownership)
-async fn session_heartbeat(
+/// Whether `user` is one of the configured `admin_users`.
+fn is_admin(state: &AppState, user: &str) -> bool {
+    state.config.server.admin_users.iter().any(|u| u == user)
+}
+
+/// The JWT's user, if they are an admin; otherwise the error response.
+/// Refusals are audit-logged as "Non-admin attempted {what}".
+async fn require_admin(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: &WsQuery,
+    what: &str,
+) -> Result<auth::Claims, axum::response::Response> {
+    let claims = extract_claims_from_headers(headers, query, &state.jwt_secret)
+        .map_err(|(status, msg)| (status, Json(json!({ "error": msg }))).into_response())?;
+    if !is_admin(state, &claims.sub) {
+        tracing::warn!(target: "audit", user = %claims.sub, "Non-admin attempted {what}");
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "You do not have permission to access this resource" })),
+        )
+            .into_response());
+    }
+    Ok(claims)
+}
+
+/// The JWT's user, if they own session `id`; otherwise the error response.
+async fn session_owner(
+    state: &AppState,
+    id: Uuid,
+    headers: &HeaderMap,
+    query: &WsQuery,
+) -> Result<auth::Claims, axum::response::Response> {
+    let claims = extract_claims_from_headers(headers, query, &state.jwt_secret)
+        .map_err(|(status, msg)| (status, Json(json!({ "error": msg }))).into_response())?;
+    match state.session_manager.get_session(id).await {
+        Some(session) if session.username == claims.sub => Ok(claims),
+        Some(_) => Err((StatusCode::FORBIDDEN, "Access denied").into_response()),
+        None => Err((StatusCode::NOT_FOUND, "Session not found").into_response()),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct ShareRequest {
+    /// Link lifetime; default an hour, capped at `session.share_link_max_secs`
+    ttl_secs: Option<u64>,
+}
+
+/// POST /api/sessions/:id/share - mint a view-only link (requires JWT + session ownership)
+async fn create_share(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
     headers: HeaderMap,
     Query(query): Query<WsQuery>,
+    body: axum::body::Bytes,
 ) -> impl IntoResponse {
-    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+    let claims = match session_owner(&state, id, &headers, &query).await {
         Ok(c) => c,
-        Err((status, msg)) => return (status, msg).into_response(),
+        Err(response) => return response,
     };
-
-    // Verify session ownership
-    match state.session_manager.get_session(id).await {
-        Some(session) if session.username == claims.sub => {}
-        Some(_) => {
-            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    if !state.config.session.share_links {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Share links are disabled on this server" })),
+        )
+            .into_response();
+    }
+    let req: ShareRequest = if body.is_empty() {
+        ShareRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(req) => req,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": format!("Invalid request: {e}") })),
+                )
+                    .into_response();
+            }
         }
-        None => {
-            return (StatusCode::NOT_FOUND, "Session not found").into_response();
+    };
+    let max_secs = state.config.session.share_link_max_secs;
+    let ttl_secs = req.ttl_secs.unwrap_or(max_secs.min(3600));
+    if !(beam_protocol::MIN_SHARE_LINK_SECS..=max_secs).contains(&ttl_secs) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": format!(
+                "ttl_secs must be between {} and {max_secs} seconds",
+                beam_protocol::MIN_SHARE_LINK_SECS
+            ) })),
+        )
+            .into_response();
+    }
+
+    let channel = signaling::get_or_create_channel(&state.channels, id).await;
+    match channel
+        .shares
+        .create(std::time::Duration::from_secs(ttl_secs))
+    {
+        Ok((share_id, token, expires_at)) => {
+            tracing::info!(target: "audit", event = "share_created", session_id = %id, share_id = %share_id, username = %claims.sub, ttl_secs, "View-only link created");
+            Json(json!({ "id": share_id, "token": token, "expires_at": expires_at }))
+                .into_response()
         }
+        Err(e) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
     }
+}
 
-    state.session_manager.heartbeat(id).await;
-    (StatusCode::OK, "OK").into_response()
+/// GET /api/sessions/:id/share - the session's live view-only links (requires JWT + session ownership)
+async fn list_shares(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = session_owner(&state, id, &headers, &query).await {
+        return response;
+    }
+    let channels = state.channels.read().await;
+    let links = channels
+        .get(&id)
+        .map(|channel| channel.shares.list_json())
+        .unwrap_or_default();
+    Json(links).into_response()
 }
 
-/// DELETE /api/sessions/:id - destroy a session (requires JWT + session ownership)
-async fn delete_session(
+/// DELETE /api/sessions/:id/share - revoke all of the session's view-only
+/// links and disconnect their viewers (requires JWT + session ownership)
+async fn revoke_shares(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
     headers: HeaderMap,
     Query(query): Query<WsQuery>,
 ) -> impl IntoResponse {
-    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+    let claims = match session_owner(&state, id, &headers, &query).await {
         Ok(c) => c,
-        Err((status, msg)) => return (status, msg).into_response(),
+        Err(response) => return response,
+    };
+    let channels = state.channels.read().await;
+    let revoked = channels
+        .get(&id)
+        .map_or(0, |channel| channel.shares.revoke_all());
+    tracing::info!(target: "audit", event = "share_revoked", session_id = %id, revoked, username = %claims.sub, "View-only links revoked");
+    Json(json!({ "revoked": revoked })).into_response()
+}
+
+/// DELETE /api/sessions/:id/share/:share_id - revoke one view-only link
+/// (requires JWT + session ownership)
+async fn revoke_share(
+    State(state): State<Arc<AppState>>,
+    Path((id, share_id)): Path<(Uuid, Uuid)>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match session_owner(&state, id, &headers, &query).await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+    let channels = state.channels.read().await;
+    if !channels
+        .get(&id)
+        .is_some_and(|channel| channel.shares.revoke(share_id))
+    {
+        return (StatusCode::NOT_FOUND, "Share link not found").into_response();
+    }
+    tracing::info!(target: "audit", event = "share_revoked", session_id = %id, share_id = %share_id, username = %claims.sub, "View-only link revoked");
+    (StatusCode::OK, "Share link revoked").into_response()
+}
+
+/// Whether admins are kept from observing session `id`, either by its
+/// owner's `privacy` policy or because the owner turned it on. None if
+/// there is no such session.
+async fn session_private(state: &AppState, id: Uuid) -> Option<bool> {
+    let session = state.session_manager.get_session(id).await?;
+    let required = state.config.session.privacy_for(&session.username);
+    let chosen = state
+        .channels
+        .read()
+        .await
+        .get(&id)
+        .is_some_and(|channel| channel.private.load(std::sync::atomic::Ordering::Relaxed));
+    Some(required || chosen)
+}
+
+/// GET /api/sessions/:id/privacy - whether the session is private
+/// (requires JWT + session ownership)
+async fn get_privacy(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match session_owner(&state, id, &headers, &query).await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+    let private = session_private(&state, id).await.unwrap_or_default();
+    Json(json!({
+        "private": private,
+        "required": state.config.session.privacy_for(&claims.sub),
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct PrivacyRequest {
+    private: bool,
+}
+
+/// PUT /api/sessions/:id/privacy - keep admins from observing the session,
+/// or allow it again (requires JWT + session ownership)
+async fn set_privacy(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    Json(req): Json<PrivacyRequest>,
+) -> impl IntoResponse {
+    let claims = match session_owner(&state, id, &headers, &query).await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+    if !req.private && state.config.session.privacy_for(&claims.sub) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Privacy is required for your sessions by the server policy" })),
+        )
+            .into_response();
+    }
+    let channel = signaling::get_or_create_channel(&state.channels, id).await;
+    channel
+        .private
+        .store(req.private, std::sync::atomic::Ordering::Relaxed);
+    // Persisted with the session, so a restart doesn't reopen it to admins
+    state.session_manager.set_private(id, req.private).await;
+    if req.private {
+        // What was captured before doesn't stay behind for admins either
+        *channel.thumbnail.write().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+    tracing::info!(target: "audit", event = "privacy_changed", session_id = %id, private = req.private, username = %claims.sub, "Session privacy changed");
+    Json(json!({ "private": req.private })).into_response()
+}
+
+/// GET /api/share/:token/ws - WebSocket for a view-only viewer (the link
+/// token is the only credential)
+async fn viewer_ws_upgrade(
+    State(state): State<Arc<AppState>>,
+    peer: Option<axum::extract::Extension<std::net::SocketAddr>>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let found = if state.config.session.share_links {
+        signaling::find_share(&state.channels, &token).await
+    } else {
+        None
+    };
+    let Some((id, channel)) = found else {
+        return (StatusCode::NOT_FOUND, "Share link not found or expired").into_response();
+    };
+    let client_ip = peer.map(|axum::extract::Extension(addr)| {
+        forwarded::client_ip(addr.ip(), &headers, &state.trusted_proxies).to_string()
+    });
+    tracing::info!(target: "audit", event = "share_viewer", session_id = %id, client_ip = client_ip.as_deref().unwrap_or("unknown"), "View-only viewer connecting");
+
+    let keepalive = signaling::Keepalive::from_config(&state.config.server);
+    ws.max_message_size(65_536) // viewers only send hello and control frames
+        .on_upgrade(move |socket| {
+            signaling::handle_viewer_ws(socket, id, channel, token, keepalive)
+        })
+        .into_response()
+}
+
+/// POST /api/sessions/:id/heartbeat - update session activity (requires JWT + session ownership)
+async fn session_heartbeat(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+
+    // Verify session ownership
+    match state.session_manager.get_session(id).await {
+        Some(session) if session.username == claims.sub => {}
+        Some(_) => {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+        None => {
+            return (StatusCode::NOT_FOUND, "Session not found").into_response();
+        }
+    }
+
+    state.session_manager.heartbeat(id).await;
+    (StatusCode::OK, "OK").into_response()
+}
+
+#[derive(Deserialize)]
+struct SetAudioRequest {
+    enabled: bool,
+}
+
+/// POST /api/sessions/:id/audio - pause or resume audio capture without
+/// restarting the session (requires JWT + session ownership).
+///
+/// The setting is kept on the signaling channel, so it also applies to an
+/// agent that connects later (respawn after a crash or resize failure).
+async fn set_session_audio(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    Json(req): Json<SetAudioRequest>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+
+    match state.session_manager.get_session(id).await {
+        Some(session) if session.username == claims.sub => {}
+        Some(_) => {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+        None => {
+            return (StatusCode::NOT_FOUND, "Session not found").into_response();
+        }
+    }
+
+    let channel = signaling::get_or_create_channel(&state.channels, id).await;
+    channel
+        .audio_enabled
+        .store(req.enabled, std::sync::atomic::Ordering::Relaxed);
+    // No receiver just means the agent isn't connected yet; it picks the
+    // setting up from the channel when it does
+    let _ = channel
+        .to_agent
+        .send(beam_protocol::AgentCommand::SetAudio {
+            enabled: req.enabled,
+        });
+    tracing::info!(%id, enabled = req.enabled, "Session audio toggled via API");
+
+    Json(json!({ "audio_enabled": req.enabled })).into_response()
+}
+
+#[derive(Deserialize)]
+struct SetQualityRequest {
+    preset: QualityPreset,
+}
+
+/// POST /api/sessions/:id/quality - switch the session's quality preset and
+/// remember it for the user's next session (requires JWT + session ownership).
+async fn set_session_quality(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    Json(req): Json<SetQualityRequest>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+
+    match state.session_manager.get_session(id).await {
+        Some(session) if session.username == claims.sub => {}
+        Some(_) => {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+        None => {
+            return (StatusCode::NOT_FOUND, "Session not found").into_response();
+        }
+    }
+
+    let channel = signaling::get_or_create_channel(&state.channels, id).await;
+    *channel.quality.write().unwrap_or_else(|e| e.into_inner()) = req.preset;
+    // An agent that isn't connected yet is sent the preset when it is
+    let _ = channel.to_agent.send(beam_protocol::AgentCommand::Input(
+        beam_protocol::InputEvent::Quality { mode: req.preset },
+    ));
+    if let Err(e) = state.preferences.set_quality(&claims.sub, req.preset) {
+        tracing::warn!(user = %claims.sub, "Failed to remember quality preset: {e:#}");
+    }
+    tracing::info!(%id, preset = req.preset.name(), "Session quality preset changed via API");
+
+    Json(json!({ "quality": req.preset })).into_response()
+}
+
+/// POST /api/sessions/:id/nettest - fit the session's stream to the client's
+/// bandwidth probe (requires JWT + session ownership). The fitted bitrate
+/// becomes the session's ceiling.
+async fn report_session_nettest(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    Json(probe): Json<beam_protocol::NetTestResult>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+
+    match state.session_manager.get_session(id).await {
+        Some(session) if session.username == claims.sub => {}
+        Some(_) => {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+        None => {
+            return (StatusCode::NOT_FOUND, "Session not found").into_response();
+        }
+    }
+
+    let Some(stream) = state.session_manager.fit_to_link(id, probe).await else {
+        return (StatusCode::NOT_FOUND, "Session not found").into_response();
+    };
+    tracing::info!(
+        %id,
+        kbps = probe.kbps,
+        rtt_ms = probe.rtt_ms,
+        bitrate = stream.bitrate,
+        framerate = stream.framerate,
+        "Stream fitted to client bandwidth probe"
+    );
+    crate::bandwidth::rebalance(&state.session_manager, &state.channels).await;
+
+    Json(json!({ "bitrate": stream.bitrate, "framerate": stream.framerate })).into_response()
+}
+
+/// DELETE /api/sessions/:id - destroy a session (requires JWT + session ownership)
+async fn delete_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
     };
 
     // Verify session ownership
@@ -1033,75 +1966,134 @@ async fn admin_list_sessions(
     headers: HeaderMap,
     Query(query): Query<WsQuery>,
 ) -> impl IntoResponse {
-    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
-        Ok(c) => c,
-        Err((status, msg)) => return (status, Json(json!({ "error": msg }))).into_response(),
-    };
-
-    if !state
-        .config
-        .server
-        .admin_users
-        .iter()
-        .any(|u| u == &claims.sub)
-    {
-        tracing::warn!(target: "audit", user = %claims.sub, "Non-admin attempted admin session list");
-        return (
-            StatusCode::FORBIDDEN,
-            Json(json!({ "error": "You do not have permission to access this resource" })),
-        )
-            .into_response();
+    if let Err(response) = require_admin(&state, &headers, &query, "admin session list").await {
+        return response;
     }
 
-    let sessions: Vec<_> = state
-        .session_manager
-        .list_sessions_with_activity()
-        .await
+    let sessions = state.session_manager.list_sessions_with_activity().await;
+    let channels = state.channels.read().await;
+    let sessions: Vec<_> = sessions
         .into_iter()
         .map(|(info, last_activity)| {
+            let mut private = state.config.session.privacy_for(&info.username);
+            let (browser, agent, thumbnail_at, termination) = match channels.get(&info.id) {
+                Some(ch) => {
+                    private |= ch.private.load(std::sync::atomic::Ordering::Relaxed);
+                    let termination = ch
+                        .termination
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .as_ref()
+                        .map(|t| json!({ "ends_in_secs": t.remaining_secs(), "by": t.by }));
+                    (
+                        ch.browser_link.status_json(),
+                        ch.agent_link.status_json(),
+                        ch.thumbnail
+                            .read()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .as_ref()
+                            .map(|t| t.at)
+                            .filter(|_| !private),
+                        termination,
+                    )
+                }
+                None => (
+                    json!({ "connected": false }),
+                    json!({ "connected": false }),
+                    None,
+                    None,
+                ),
+            };
+            let agent_info = channels.get(&info.id).and_then(|ch| {
+                ch.agent_info
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone()
+            });
+            // How far the agent's release is from the server's
+            let version_skew = channels
+                .get(&info.id)
+                .and_then(|ch| ch.agent_link.peer_release())
+                .and_then(|release| {
+                    VersionSkew::between(
+                        RELEASE,
+                        &release,
+                        state.config.server.agent_version_window,
+                    )
+                });
             json!({
                 "id": info.id,
                 "username": info.username,
                 "display": info.display,
                 "created_at": info.created_at,
                 "last_activity": last_activity,
+                "client": info.client,
+                "browser": browser,
+                "agent": agent,
+                "thumbnail_at": thumbnail_at,
+                "private": private,
+                "termination": termination,
+                "agent_info": agent_info,
+                "version_skew": version_skew,
             })
         })
         .collect();
     Json(sessions).into_response()
 }
 
-/// DELETE /api/admin/sessions/:id - destroy any session (requires JWT + admin)
+#[derive(Deserialize)]
+struct DeleteQuery {
+    /// Seconds to give the user before the session ends
+    after: Option<u64>,
+}
+
+/// DELETE /api/admin/sessions/:id - destroy any session (requires JWT + admin).
+/// With `?after=N`, the session ends N seconds later and the user is
+/// counted down to it.
 async fn admin_delete_session(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
     headers: HeaderMap,
     Query(query): Query<WsQuery>,
+    Query(schedule): Query<DeleteQuery>,
 ) -> impl IntoResponse {
-    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+    let claims = match require_admin(&state, &headers, &query, "admin session delete").await {
         Ok(c) => c,
-        Err((status, msg)) => return (status, Json(json!({ "error": msg }))).into_response(),
+        Err(response) => return response,
     };
 
-    if !state
-        .config
-        .server
-        .admin_users
-        .iter()
-        .any(|u| u == &claims.sub)
-    {
-        tracing::warn!(target: "audit", user = %claims.sub, "Non-admin attempted admin session delete");
-        return (
-            StatusCode::FORBIDDEN,
-            Json(json!({ "error": "You do not have permission to access this resource" })),
-        )
-            .into_response();
-    }
-
     if state.session_manager.get_session(id).await.is_none() {
         return (StatusCode::NOT_FOUND, "Session not found").into_response();
     }
 
+    match schedule.after.filter(|&secs| secs > 0) {
+        Some(secs) if secs > termination::MAX_DELAY_SECS => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": format!("after must be at most {} seconds", termination::MAX_DELAY_SECS)
+                })),
+            )
+                .into_response();
+        }
+        Some(secs) => {
+            termination::schedule(
+                &state,
+                id,
+                std::time::Duration::from_secs(secs),
+                &claims.sub,
+            )
+            .await;
+            tracing::info!(target: "audit", event = "admin_session_termination_scheduled", session_id = %id, admin = %claims.sub, after = secs, "Session end scheduled by admin");
+            return (
+                StatusCode::ACCEPTED,
+                Json(json!({ "ends_in_secs": secs, "scheduled_by": claims.sub })),
+            )
+                .into_response();
+        }
+        None => {}
+    }
+
     if let Err(e) = state.session_manager.destroy_session(id).await {
         tracing::error!(%id, "Failed to destroy session: {e:#}");
         return (
@@ -1116,962 +2108,3468 @@ async fn admin_delete_session(
     (StatusCode::OK, "Session destroyed").into_response()
 }
 
-/// POST /api/sessions/:id/release - graceful session release on browser tab close.
-///
-/// Called via `navigator.sendBeacon()` which cannot set Authorization headers,
-/// so this endpoint uses a separate release token in the request body instead
-/// of JWT auth. The release token is returned alongside the JWT at login time.
-///
-/// Starts a 60-second grace period. If no browser WebSocket reconnects within
-/// that window, the session is destroyed. This handles the common case of
-/// closing a tab without clicking "End Session".
-async fn release_session(
+/// GET /api/admin/events/ws - live session events and alerts as JSON text
+/// frames (requires JWT + admin; browsers pass it as `?token=`)
+async fn admin_events_ws(
     State(state): State<Arc<AppState>>,
-    peer: Option<axum::extract::Extension<std::net::SocketAddr>>,
-    Path(id): Path<Uuid>,
-    body: String,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    let peer_ip = peer
-        .map(|axum::extract::Extension(addr)| normalize_ip_for_rate_limit(addr.ip()))
-        .unwrap_or_else(|| "unknown".to_string());
+    let claims = match require_admin(&state, &headers, &query, "admin event stream").await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
 
-    // Rate limit release attempts per IP (separate from login limiter)
-    if !state.release_limiter.is_allowed(&peer_ip) {
-        return (StatusCode::TOO_MANY_REQUESTS, "Rate limited").into_response();
-    }
+    tracing::info!(target: "audit", event = "admin_event_stream", admin = %claims.sub, "Admin event stream opened");
+    let keepalive = signaling::Keepalive::from_config(&state.config.server);
+    ws.max_message_size(4096) // admins only send control frames
+        .on_upgrade(move |socket| crate::events::stream(socket, keepalive))
+        .into_response()
+}
 
-    // sendBeacon sends as text/plain — the body IS the release token.
-    // Trim whitespace/newlines that browsers or proxies might add.
-    let token = body.trim();
+/// DELETE /api/admin/sessions/:id/termination - call off a scheduled end
+/// (requires JWT + admin)
+async fn admin_cancel_termination(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims =
+        match require_admin(&state, &headers, &query, "to cancel a session termination").await {
+            Ok(c) => c,
+            Err(response) => return response,
+        };
 
-    if token.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Missing release token").into_response();
+    if state.guests.is_guest_session(id) {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "Guest sessions end when their time is up" })),
+        )
+            .into_response();
     }
-
-    if !state.session_manager.verify_release_token(id, token).await {
-        state.release_limiter.record_failure(&peer_ip);
-        // Don't reveal whether the session exists or the token is wrong
-        return (StatusCode::UNAUTHORIZED, "Invalid release token").into_response();
+    if !termination::cancel(&state, id, &claims.sub).await {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "No end is scheduled for this session" })),
+        )
+            .into_response();
     }
+    tracing::info!(target: "audit", event = "admin_session_termination_cancelled", session_id = %id, admin = %claims.sub, "Scheduled session end cancelled by admin");
+    Json(json!({ "cancelled": true })).into_response()
+}
 
-    tracing::info!(%id, "Session release requested, starting 60s grace period");
+/// Longest admin message, in characters
+const MAX_NOTICE_CHARS: usize = 1000;
 
-    // Get the generation counter and spawn the grace-period cleanup task.
-    // Each new grace period bumps the generation, so overlapping timers
-    // from rapid disconnect/reconnect cycles don't race with each other.
-    let (gen_counter, my_gen) = match state.session_manager.start_grace_period(id).await {
-        Some(pair) => pair,
-        None => {
-            return (StatusCode::NOT_FOUND, "Session not found").into_response();
-        }
+#[derive(Deserialize)]
+struct NotifyRequest {
+    message: String,
+    /// Show it as a toast in the user's browser
+    #[serde(default = "default_true")]
+    browser: bool,
+    /// Pop it up as a desktop notification in the session
+    #[serde(default = "default_true")]
+    desktop: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// POST /api/admin/sessions/:id/notify - show the user a message, such as
+/// a warning ahead of maintenance (requires JWT + admin). Answers whether
+/// a browser and an agent were there to take it.
+async fn admin_notify_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    Json(req): Json<NotifyRequest>,
+) -> impl IntoResponse {
+    let claims = match require_admin(&state, &headers, &query, "admin session notify").await {
+        Ok(c) => c,
+        Err(response) => return response,
     };
 
-    let state_clone = Arc::clone(&state);
-    tokio::spawn(async move {
-        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+    let message = req.message.trim();
+    if message.is_empty() || message.chars().count() > MAX_NOTICE_CHARS {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": format!("The message must have 1 to {MAX_NOTICE_CHARS} characters")
+            })),
+        )
+            .into_response();
+    }
 
-        // Check if the grace period was cancelled (browser reconnected or
-        // a newer grace period superseded this one)
-        if gen_counter.load(std::sync::atomic::Ordering::SeqCst) != my_gen {
-            tracing::info!(%id, "Grace period cancelled — browser reconnected");
-            return;
-        }
+    if state.session_manager.get_session(id).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Session not found" })),
+        )
+            .into_response();
+    }
 
-        tracing::info!(%id, "Grace period expired — destroying session");
-        if let Err(e) = state_clone.session_manager.destroy_session(id).await {
-            tracing::error!(%id, "Failed to destroy session after grace period: {e:#}");
-        } else {
-            tracing::info!(target: "audit", event = "session_destroyed", session_id = %id, "Session destroyed");
-        }
-        signaling::remove_channel(&state_clone.channels, id).await;
-    });
+    let channel = signaling::get_or_create_channel(&state.channels, id).await;
+    let browser = req.browser && {
+        let notice = SignalingMessage::Notice {
+            message: message.to_string(),
+            from: claims.sub.clone(),
+        };
+        serde_json::to_string(&notice).is_ok_and(|json| channel.to_browser.send(json).is_ok())
+    };
+    let desktop = req.desktop
+        && channel.agent_link.announces(beam_protocol::CAP_NOTIFY)
+        && channel
+            .to_agent
+            .send(beam_protocol::AgentCommand::Notify {
+                summary: format!("Message from {}", claims.sub),
+                body: message.to_string(),
+            })
+            .is_ok();
 
-    (StatusCode::OK, "Release accepted").into_response()
+    tracing::info!(target: "audit", event = "admin_session_notified", session_id = %id, admin = %claims.sub, browser, desktop, "Message sent to session by admin");
+    Json(json!({ "browser": browser, "desktop": desktop })).into_response()
 }
 
-/// GET /api/health - server health check (no auth required, minimal info for load balancers)
-async fn health_check() -> impl IntoResponse {
-    Json(json!({ "status": "ok", "version": env!("CARGO_PKG_VERSION") }))
+/// Have a session's agent execute `path` in place of itself, if it is
+/// connected and announced it can. Returns whether the command went out.
+fn request_agent_upgrade(channel: &signaling::SignalingChannel, path: &str, by: &str) -> bool {
+    let sent = channel.agent_link.announces(beam_protocol::CAP_UPGRADE)
+        && channel
+            .to_agent
+            .send(beam_protocol::AgentCommand::Upgrade {
+                path: path.to_string(),
+            })
+            .is_ok();
+    if sent {
+        channel
+            .timeline
+            .record(SessionEvent::AgentUpgrading { by: by.to_string() });
+    }
+    sent
 }
 
-/// GET /api/health/detailed - full health info (requires JWT auth)
-async fn health_check_detailed(
+/// POST /api/admin/sessions/:id/upgrade - have the session's agent execute
+/// the agent binary new sessions start, keeping its display and its
+/// session; the browser reconnects as after a network blip. Only that
+/// binary: it is the one the server recognizes its agents by.
+/// (requires JWT + admin)
+async fn admin_upgrade_session(
     State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
     headers: HeaderMap,
     Query(query): Query<WsQuery>,
 ) -> impl IntoResponse {
-    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+    let claims = match require_admin(&state, &headers, &query, "to upgrade an agent").await {
         Ok(c) => c,
-        Err((status, msg)) => {
-            return (status, Json(json!({ "error": msg }))).into_response();
-        }
+        Err(response) => return response,
     };
 
-    let _ = claims; // authenticated — no further authorization needed
+    if state.session_manager.get_session(id).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Session not found" })),
+        )
+            .into_response();
+    }
 
-    let sessions = state.session_manager.list_sessions().await;
-    Json(json!({
-        "status": "ok",
-        "version": env!("CARGO_PKG_VERSION"),
-        "uptime_secs": state.started_at.elapsed().as_secs(),
-        "sessions": sessions.len(),
-    }))
-    .into_response()
+    let path = state.session_manager.agent_binary();
+    let path = path.to_string_lossy();
+    let channel = state.channels.read().await.get(&id).cloned();
+    if !channel.is_some_and(|ch| request_agent_upgrade(&ch, &path, &claims.sub)) {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "error": "The session's agent is not connected or can't upgrade in place" })),
+        )
+            .into_response();
+    }
+
+    tracing::info!(target: "audit", event = "agent_upgrade_requested", session_id = %id, admin = %claims.sub, %path, "Agent upgrade requested by admin");
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "id": id, "path": path })),
+    )
+        .into_response()
 }
 
-/// GET /metrics - Prometheus-compatible metrics endpoint (auth configurable)
-async fn metrics(
+/// POST /api/admin/agents/upgrade - upgrade the agents of all sessions as
+/// `admin_upgrade_session` does, listing those upgrading and those skipped
+/// because their agent is away or predates upgrades (requires JWT + admin)
+async fn admin_upgrade_agents(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     Query(query): Query<WsQuery>,
 ) -> impl IntoResponse {
-    if state.config.server.metrics_require_auth
-        && let Err((status, msg)) = extract_claims_from_headers(&headers, &query, &state.jwt_secret)
-    {
-        return (status, msg).into_response();
-    }
-
-    let active_sessions = state.session_manager.list_sessions().await.len();
-    let uptime_secs = state.started_at.elapsed().as_secs();
-    let logins_attempted = state
-        .metrics_logins_attempted
-        .load(std::sync::atomic::Ordering::Relaxed);
-    let logins_failed = state
-        .metrics_logins_failed
-        .load(std::sync::atomic::Ordering::Relaxed);
-    let agent_restarts = state
-        .metrics_agent_restarts
-        .load(std::sync::atomic::Ordering::Relaxed);
+    let claims = match require_admin(&state, &headers, &query, "to upgrade agents").await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
 
-    let body = format!(
-        "# HELP beam_active_sessions Number of active sessions\n\
-         # TYPE beam_active_sessions gauge\n\
-         beam_active_sessions {active_sessions}\n\
-         \n\
-         # HELP beam_uptime_seconds Server uptime in seconds\n\
-         # TYPE beam_uptime_seconds gauge\n\
-         beam_uptime_seconds {uptime_secs}\n\
-         \n\
-         # HELP beam_total_logins_attempted Total login attempts\n\
-         # TYPE beam_total_logins_attempted counter\n\
-         beam_total_logins_attempted {logins_attempted}\n\
-         \n\
-         # HELP beam_total_logins_failed Total failed login attempts\n\
-         # TYPE beam_total_logins_failed counter\n\
-         beam_total_logins_failed {logins_failed}\n\
-         \n\
-         # HELP beam_agent_restarts_total Total agent restart attempts\n\
-         # TYPE beam_agent_restarts_total counter\n\
-         beam_agent_restarts_total {agent_restarts}\n"
-    );
+    let path = state.session_manager.agent_binary();
+    let path = path.to_string_lossy();
+    let sessions = state.session_manager.list_sessions().await;
+    let channels = state.channels.read().await;
+    let (mut upgrading, mut skipped) = (Vec::new(), Vec::new());
+    for info in sessions {
+        let sent = channels
+            .get(&info.id)
+            .is_some_and(|ch| request_agent_upgrade(ch, &path, &claims.sub));
+        if sent {
+            upgrading.push(info.id);
+        } else {
+            skipped.push(info.id);
+        }
+    }
+    drop(channels);
 
+    tracing::info!(target: "audit", event = "agents_upgrade_requested", admin = %claims.sub, %path, upgrading = upgrading.len(), skipped = skipped.len(), "Agent upgrades requested by admin");
     (
-        [(
-            axum::http::header::CONTENT_TYPE,
-            "text/plain; version=0.0.4; charset=utf-8",
-        )],
-        body,
+        StatusCode::ACCEPTED,
+        Json(json!({ "path": path, "upgrading": upgrading, "skipped": skipped })),
     )
         .into_response()
 }
 
-/// GET /ws/agent/:id - WebSocket upgrade for agent signaling (requires agent token)
-async fn agent_ws_upgrade(
+/// GET /api/admin/sessions/:id/stats - latest latency telemetry for a session (requires JWT + admin)
+async fn admin_session_stats(
     State(state): State<Arc<AppState>>,
     Path(id): Path<Uuid>,
+    headers: HeaderMap,
     Query(query): Query<WsQuery>,
-    ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    // Validate agent token
-    let token = match &query.token {
-        Some(t) => t,
-        None => {
-            return (StatusCode::UNAUTHORIZED, "Missing agent token").into_response();
-        }
-    };
+    if let Err(response) = require_admin(&state, &headers, &query, "admin session stats").await {
+        return response;
+    }
 
-    if !state.session_manager.verify_agent_token(id, token).await {
-        tracing::warn!(%id, "Invalid agent token on WebSocket upgrade");
-        return (StatusCode::UNAUTHORIZED, "Invalid agent token").into_response();
+    if state.session_manager.get_session(id).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Session not found" })),
+        )
+            .into_response();
     }
 
-    tracing::info!(%id, "Agent WebSocket upgrade (authenticated)");
-    let channels = state.channels.clone();
-    ws.max_message_size(2 * 1024 * 1024) // 2MB max (binary video frames + text signaling)
-        .on_upgrade(move |socket| signaling::handle_agent_ws(socket, id, channels))
-        .into_response()
-}
+    // No report yet (browser not connected, or first window still filling)
+    let (latency, pipeline, health, encoder, audio_enabled, rejected) =
+        match state.channels.read().await.get(&id) {
+            Some(ch) => (
+                ch.latency.read().unwrap_or_else(|e| e.into_inner()).clone(),
+                ch.pipeline
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone(),
+                ch.health.read().unwrap_or_else(|e| e.into_inner()).clone(),
+                ch.encoder.read().unwrap_or_else(|e| e.into_inner()).clone(),
+                ch.audio_enabled.load(std::sync::atomic::Ordering::Relaxed),
+                Some(ch.rejected.snapshot()),
+            ),
+            None => (None, None, None, None, true, None),
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    Json(json!({
+        "session_id": id,
+        "latency": latency,
+        "pipeline": pipeline,
+        "health": health,
+        "encoder": encoder,
+        "audio_enabled": audio_enabled,
+        "rejected_messages": rejected,
+    }))
+    .into_response()
+}
 
-    #[test]
-    fn rate_limiter_allows_under_limit() {
-        let limiter = LoginRateLimiter::new(3, 60);
-        limiter.record_failure("user1");
-        limiter.record_failure("user1");
-        // 2 failures recorded, limit is 3 — should still be allowed
-        assert!(limiter.is_allowed("user1"));
+/// GET /api/admin/sessions/:id/events - the session's event timeline (requires JWT + admin)
+async fn admin_session_events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers, &query, "admin session events").await {
+        return response;
     }
 
-    #[test]
-    fn rate_limiter_blocks_over_limit() {
-        let limiter = LoginRateLimiter::new(3, 60);
-        limiter.record_failure("user1");
-        limiter.record_failure("user1");
-        limiter.record_failure("user1");
-        // 3 failures recorded, limit is 3 — should be blocked
-        assert!(!limiter.is_allowed("user1"));
+    if state.session_manager.get_session(id).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Session not found" })),
+        )
+            .into_response();
     }
 
-    #[test]
-    fn rate_limiter_independent_per_key() {
-        let limiter = LoginRateLimiter::new(2, 60);
-        limiter.record_failure("user1");
-        limiter.record_failure("user1");
-        assert!(!limiter.is_allowed("user1")); // blocked
+    let events = match state.channels.read().await.get(&id) {
+        Some(ch) => ch.timeline.entries(),
+        None => Vec::new(),
+    };
+    Json(json!({
+        "session_id": id,
+        "events": events,
+    }))
+    .into_response()
+}
 
-        // user2 should still be allowed
-        assert!(limiter.is_allowed("user2"));
-        limiter.record_failure("user2");
-        assert!(limiter.is_allowed("user2"));
-    }
+/// GET /api/admin/sessions/:id/thumbnail - latest screenshot of a session as JPEG (requires JWT + admin)
+async fn admin_session_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match require_admin(&state, &headers, &query, "session thumbnail").await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
 
-    #[test]
-    fn rate_limiter_resets_after_window() {
-        let limiter = LoginRateLimiter::new(2, 0); // 0-second window = immediately expires
-        limiter.record_failure("user1");
-        limiter.record_failure("user1");
-        // With a 0-second window, previous attempts expire immediately
-        std::thread::sleep(std::time::Duration::from_millis(10));
-        assert!(limiter.is_allowed("user1"));
+    if state.config.session.thumbnail_secs == 0 {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "Session thumbnails are disabled on this server" })),
+        )
+            .into_response();
     }
 
-    #[test]
-    fn rate_limiter_is_allowed_does_not_record() {
-        let limiter = LoginRateLimiter::new(2, 60);
-        // Calling is_allowed many times should never block — it's read-only
-        for _ in 0..100 {
-            assert!(limiter.is_allowed("user1"));
+    match session_private(&state, id).await {
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "error": "Session not found" })),
+            )
+                .into_response();
+        }
+        Some(true) => {
+            tracing::warn!(target: "audit", event = "observation_denied", admin = %claims.sub, session_id = %id, feature = "thumbnail", "Admin attempted to observe a private session");
+            return (
+                StatusCode::FORBIDDEN,
+                Json(json!({ "error": "This session is private" })),
+            )
+                .into_response();
         }
+        Some(false) => {}
     }
 
-    #[test]
+    let thumbnail = state.channels.read().await.get(&id).and_then(|ch| {
+        ch.thumbnail
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    });
+    // Also covers sessions whose agent hasn't sent one yet
+    let Some(thumbnail) = thumbnail else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "No thumbnail for this session" })),
+        )
+            .into_response();
+    };
+    (
+        [
+            (axum::http::header::CONTENT_TYPE, "image/jpeg".to_string()),
+            (axum::http::header::CACHE_CONTROL, "no-store".to_string()),
+            (
+                axum::http::header::HeaderName::from_static("x-thumbnail-at"),
+                thumbnail.at.to_string(),
+            ),
+        ],
+        thumbnail.jpeg,
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct StatsHistoryQuery {
+    /// Only samples at or after this Unix time (seconds)
+    since: Option<u64>,
+}
+
+/// GET /api/sessions/:id/stats/history - recent per-second stats (requires JWT + session ownership or admin)
+async fn session_stats_history(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    Query(history): Query<StatsHistoryQuery>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, Json(json!({ "error": msg }))).into_response(),
+    };
+    let Some(session) = state.session_manager.get_session(id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Session not found" })),
+        )
+            .into_response();
+    };
+    if session.username != claims.sub && !is_admin(&state, &claims.sub) {
+        tracing::warn!(target: "audit", user = %claims.sub, session_id = %id, "Denied stats history of another user's session");
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "You do not have permission to access this resource" })),
+        )
+            .into_response();
+    }
+
+    let samples = match state.channels.read().await.get(&id) {
+        Some(ch) => ch.stats_history.since(history.since.unwrap_or(0)),
+        None => Vec::new(),
+    };
+    Json(json!({
+        "session_id": id,
+        "username": session.username,
+        "keep_secs": state.config.session.stats_history_secs,
+        "samples": samples,
+    }))
+    .into_response()
+}
+
+/// POST /api/sessions/:id/release - graceful session release on browser tab close.
+///
+/// Called via `navigator.sendBeacon()` which cannot set Authorization headers,
+/// so this endpoint uses a separate release token in the request body instead
+/// of JWT auth. The release token is returned alongside the JWT at login time.
+///
+/// Starts a 60-second grace period. If no browser WebSocket reconnects within
+/// that window, the session is destroyed. This handles the common case of
+/// closing a tab without clicking "End Session".
+async fn release_session(
+    State(state): State<Arc<AppState>>,
+    peer: Option<axum::extract::Extension<std::net::SocketAddr>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    body: String,
+) -> impl IntoResponse {
+    let peer_ip = peer
+        .map(|axum::extract::Extension(addr)| {
+            let ip = forwarded::client_ip(addr.ip(), &headers, &state.trusted_proxies);
+            normalize_ip_for_rate_limit(ip)
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // Rate limit release attempts per IP (separate from login limiter)
+    if !state.release_limiter.is_allowed(&peer_ip) {
+        return (StatusCode::TOO_MANY_REQUESTS, "Rate limited").into_response();
+    }
+
+    // sendBeacon sends as text/plain — the body IS the release token.
+    // Trim whitespace/newlines that browsers or proxies might add.
+    let token = body.trim();
+
+    if token.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Missing release token").into_response();
+    }
+
+    if !state.session_manager.verify_release_token(id, token).await {
+        state.release_limiter.record_failure(&peer_ip);
+        // Don't reveal whether the session exists or the token is wrong
+        return (StatusCode::UNAUTHORIZED, "Invalid release token").into_response();
+    }
+
+    tracing::info!(%id, "Session release requested, starting 60s grace period");
+
+    // Get the generation counter and spawn the grace-period cleanup task.
+    // Each new grace period bumps the generation, so overlapping timers
+    // from rapid disconnect/reconnect cycles don't race with each other.
+    let (gen_counter, my_gen) = match state.session_manager.start_grace_period(id).await {
+        Some(pair) => pair,
+        None => {
+            return (StatusCode::NOT_FOUND, "Session not found").into_response();
+        }
+    };
+
+    let state_clone = Arc::clone(&state);
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+
+        // Check if the grace period was cancelled (browser reconnected or
+        // a newer grace period superseded this one)
+        if gen_counter.load(std::sync::atomic::Ordering::SeqCst) != my_gen {
+            tracing::info!(%id, "Grace period cancelled — browser reconnected");
+            return;
+        }
+
+        tracing::info!(%id, "Grace period expired — destroying session");
+        if let Err(e) = state_clone.session_manager.destroy_session(id).await {
+            tracing::error!(%id, "Failed to destroy session after grace period: {e:#}");
+        } else {
+            tracing::info!(target: "audit", event = "session_destroyed", session_id = %id, "Session destroyed");
+        }
+        signaling::remove_channel(&state_clone.channels, id).await;
+    });
+
+    (StatusCode::OK, "Release accepted").into_response()
+}
+
+/// Largest padding body served by `/api/nettest` (~0.7s at 100 Mbps).
+const NETTEST_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// Query parameters for the bandwidth probe
+#[derive(Deserialize)]
+struct NetTestQuery {
+    #[serde(default)]
+    bytes: usize,
+}
+
+/// GET /api/nettest?bytes=N - padding for the bandwidth probe the client
+/// runs after logging in (requires JWT, rate-limited per user).
+///
+/// `bytes=0` measures RTT. The payload is pseudo-random so compressing
+/// proxies can't inflate the result, and capped at `NETTEST_MAX_BYTES`.
+async fn nettest(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(token_query): Query<WsQuery>,
+    Query(query): Query<NetTestQuery>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &token_query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    if !state.nettest_limiter.is_allowed(&claims.sub) {
+        return (StatusCode::TOO_MANY_REQUESTS, "Rate limited").into_response();
+    }
+    state.nettest_limiter.record_failure(&claims.sub);
+
+    static PADDING: std::sync::LazyLock<bytes::Bytes> = std::sync::LazyLock::new(|| {
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut buf = Vec::with_capacity(NETTEST_MAX_BYTES);
+        while buf.len() < NETTEST_MAX_BYTES {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            buf.extend_from_slice(&state.to_le_bytes());
+        }
+        buf.into()
+    });
+
+    let len = query.bytes.min(NETTEST_MAX_BYTES);
+    (
+        [
+            (axum::http::header::CONTENT_TYPE, "application/octet-stream"),
+            (axum::http::header::CACHE_CONTROL, "no-store"),
+        ],
+        PADDING.slice(..len),
+    )
+        .into_response()
+}
+
+/// POST /api/admin/drain - stop accepting new sessions and shut down once
+/// the existing ones end (requires JWT + admin)
+async fn admin_drain(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match require_admin(&state, &headers, &query, "to drain the server").await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    if state.drain.start() {
+        tracing::info!(target: "audit", event = "drain_started", admin = %claims.sub, "Server draining");
+    }
+    let sessions = state.session_manager.list_sessions().await.len();
+    Json(state.drain.status_json(sessions)).into_response()
+}
+
+/// GET /api/admin/bans - addresses currently banned for failed logins (requires JWT + admin)
+async fn admin_list_bans(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers, &query, "admin ban list").await {
+        return response;
+    }
+
+    Json(state.ip_bans.list_json()).into_response()
+}
+
+/// GET /api/admin/displays - the display pool, which sessions hold which
+/// displays and the X servers running on them (requires JWT + admin)
+async fn admin_list_displays(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&state, &headers, &query, "admin display list").await {
+        return response;
+    }
+
+    Json(state.session_manager.displays_json().await).into_response()
+}
+
+/// DELETE /api/admin/bans/:ip - lift a ban and reset the address's failed
+/// logins (requires JWT + admin). Takes a plain address or a listed key,
+/// URL-encoded for IPv6 `/64` prefixes.
+async fn admin_lift_ban(
+    State(state): State<Arc<AppState>>,
+    Path(ip): Path<String>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match require_admin(&state, &headers, &query, "to lift a ban").await {
+        Ok(c) => c,
+        Err(response) => return response,
+    };
+
+    // Bans are keyed like the rate limiter: IPv6 by /64
+    let key = match ip.parse() {
+        Ok(addr) => normalize_ip_for_rate_limit(addr),
+        Err(_) => ip,
+    };
+    state.ip_limiter.clear(&key);
+    if !state.ip_bans.lift(&key) {
+        return (StatusCode::NOT_FOUND, "Address not banned").into_response();
+    }
+    tracing::info!(target: "audit", event = "ip_ban_lifted", key = %key, admin = %claims.sub, "Ban lifted by admin");
+    (StatusCode::OK, "Ban lifted").into_response()
+}
+
+/// GET /api/health - server health check (no auth required, minimal info for load balancers)
+async fn health_check() -> impl IntoResponse {
+    Json(json!({ "status": "ok", "version": env!("CARGO_PKG_VERSION") }))
+}
+
+/// GET /api/health/detailed - full health info (requires JWT auth)
+async fn health_check_detailed(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    let claims = match extract_claims_from_headers(&headers, &query, &state.jwt_secret) {
+        Ok(c) => c,
+        Err((status, msg)) => {
+            return (status, Json(json!({ "error": msg }))).into_response();
+        }
+    };
+
+    let _ = claims; // authenticated — no further authorization needed
+
+    // Same checks as `beam-doctor`; they spawn gst-inspect and friends
+    let config = state.config.clone();
+    let system = tokio::task::spawn_blocking(move || beam_doctor::run(&config).to_json())
+        .await
+        .unwrap_or_default();
+
+    // Releases of the connected agents, to spot any an upgrade left behind
+    let window = state.config.server.agent_version_window;
+    let mut releases: BTreeMap<Option<String>, usize> = BTreeMap::new();
+    for channel in state.channels.read().await.values() {
+        if channel.agent_link.connected_secs().is_some() {
+            *releases
+                .entry(channel.agent_link.peer_release())
+                .or_default() += 1;
+        }
+    }
+    let agents: Vec<_> = releases
+        .into_iter()
+        .map(|(release, sessions)| {
+            let skew = release
+                .as_deref()
+                .and_then(|r| VersionSkew::between(RELEASE, r, window));
+            json!({ "release": release, "sessions": sessions, "version_skew": skew })
+        })
+        .collect();
+
+    let sessions = state.session_manager.list_sessions().await;
+    Json(json!({
+        "status": if state.drain.is_draining() { "draining" } else { "ok" },
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol_version": PROTOCOL_VERSION,
+        "agents": agents,
+        "uptime_secs": state.started_at.elapsed().as_secs(),
+        "sessions": sessions.len(),
+        "drain": state.drain.status_json(sessions.len()),
+        "system": system,
+    }))
+    .into_response()
+}
+
+/// GET /metrics - Prometheus-compatible metrics endpoint (auth configurable)
+async fn metrics(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+) -> impl IntoResponse {
+    if state.config.server.metrics_require_auth
+        && let Err((status, msg)) = extract_claims_from_headers(&headers, &query, &state.jwt_secret)
+    {
+        return (status, msg).into_response();
+    }
+
+    let active_sessions = state.session_manager.list_sessions().await.len();
+    let uptime_secs = state.started_at.elapsed().as_secs();
+    let logins_attempted = state
+        .metrics_logins_attempted
+        .load(std::sync::atomic::Ordering::Relaxed);
+    let logins_failed = state
+        .metrics_logins_failed
+        .load(std::sync::atomic::Ordering::Relaxed);
+    let agent_restarts = state
+        .metrics_agent_restarts
+        .load(std::sync::atomic::Ordering::Relaxed);
+
+    let latencies: Vec<(Uuid, beam_protocol::LatencyStats)> = state
+        .channels
+        .read()
+        .await
+        .iter()
+        .filter_map(|(id, ch)| {
+            let stats = ch.latency.read().unwrap_or_else(|e| e.into_inner()).clone();
+            stats.map(|s| (*id, s))
+        })
+        .collect();
+
+    let health: Vec<(Uuid, beam_protocol::AgentHealth)> = state
+        .channels
+        .read()
+        .await
+        .iter()
+        .filter_map(|(id, ch)| {
+            let health = ch.health.read().unwrap_or_else(|e| e.into_inner()).clone();
+            health.map(|h| (*id, h))
+        })
+        .collect();
+
+    let rejected: Vec<(Uuid, u64, u64)> = state
+        .channels
+        .read()
+        .await
+        .iter()
+        .map(|(id, ch)| {
+            let malformed = ch
+                .rejected
+                .malformed
+                .load(std::sync::atomic::Ordering::Relaxed);
+            let too_large = ch
+                .rejected
+                .too_large
+                .load(std::sync::atomic::Ordering::Relaxed);
+            (*id, malformed, too_large)
+        })
+        .collect();
+
+    let stream_alerts: Vec<(Uuid, beam_protocol::StreamAlertKind, u64)> = state
+        .channels
+        .read()
+        .await
+        .iter()
+        .flat_map(|(id, ch)| {
+            beam_protocol::StreamAlertKind::ALL.map(|kind| {
+                let count = ch
+                    .stream_alerts
+                    .count(kind)
+                    .load(std::sync::atomic::Ordering::Relaxed);
+                (*id, kind, count)
+            })
+        })
+        .collect();
+
+    let mut body = format!(
+        "# HELP beam_active_sessions Number of active sessions\n\
+         # TYPE beam_active_sessions gauge\n\
+         beam_active_sessions {active_sessions}\n\
+         \n\
+         # HELP beam_uptime_seconds Server uptime in seconds\n\
+         # TYPE beam_uptime_seconds gauge\n\
+         beam_uptime_seconds {uptime_secs}\n\
+         \n\
+         # HELP beam_total_logins_attempted Total login attempts\n\
+         # TYPE beam_total_logins_attempted counter\n\
+         beam_total_logins_attempted {logins_attempted}\n\
+         \n\
+         # HELP beam_total_logins_failed Total failed login attempts\n\
+         # TYPE beam_total_logins_failed counter\n\
+         beam_total_logins_failed {logins_failed}\n\
+         \n\
+         # HELP beam_agent_restarts_total Total agent restart attempts\n\
+         # TYPE beam_agent_restarts_total counter\n\
+         beam_agent_restarts_total {agent_restarts}\n"
+    );
+    body.push_str(&latency_metrics(&latencies));
+    body.push_str(&agent_health_metrics(&health));
+    body.push_str(&rejected_message_metrics(&rejected));
+    body.push_str(&stream_alert_metrics(&stream_alerts));
+
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    )
+        .into_response()
+}
+
+/// Render per-session glass-to-glass latency as a Prometheus summary-style gauge.
+fn latency_metrics(latencies: &[(Uuid, beam_protocol::LatencyStats)]) -> String {
+    use std::fmt::Write;
+    let mut out = String::from(
+        "\n# HELP beam_session_latency_ms Estimated glass-to-glass latency per session\n\
+         # TYPE beam_session_latency_ms gauge\n",
+    );
+    for (id, stats) in latencies {
+        for (quantile, value) in [
+            ("0.5", stats.p50_ms),
+            ("0.95", stats.p95_ms),
+            ("0.99", stats.p99_ms),
+        ] {
+            let _ = writeln!(
+                out,
+                "beam_session_latency_ms{{session_id=\"{id}\",quantile=\"{quantile}\"}} {value}"
+            );
+        }
+    }
+    out
+}
+
+/// Render per-session agent memory, CPU, input drops and audio drift and
+/// concealment from the agents' watchdog reports.
+fn agent_health_metrics(health: &[(Uuid, beam_protocol::AgentHealth)]) -> String {
+    use std::fmt::Write;
+    let mut out = String::from(
+        "\n# HELP beam_agent_rss_bytes Agent resident memory per session\n\
+         # TYPE beam_agent_rss_bytes gauge\n",
+    );
+    for (id, h) in health {
+        let bytes = u64::from(h.rss_mb) << 20;
+        let _ = writeln!(out, "beam_agent_rss_bytes{{session_id=\"{id}\"}} {bytes}");
+    }
+    out.push_str(
+        "\n# HELP beam_agent_cpu_percent Agent CPU use per session, in percent of one core\n\
+         # TYPE beam_agent_cpu_percent gauge\n",
+    );
+    for (id, h) in health {
+        let _ = writeln!(
+            out,
+            "beam_agent_cpu_percent{{session_id=\"{id}\"}} {}",
+            h.cpu_pct
+        );
+    }
+    out.push_str(
+        "\n# HELP beam_agent_input_dropped_total Browser input the agent dropped for going over its rate caps\n\
+         # TYPE beam_agent_input_dropped_total counter\n",
+    );
+    for (id, h) in health {
+        let d = &h.input_dropped;
+        for (category, count) in [
+            ("key", d.key),
+            ("pointer", d.pointer),
+            ("clipboard", d.clipboard),
+            ("layout", d.layout),
+            ("control", d.control),
+        ] {
+            let _ = writeln!(
+                out,
+                "beam_agent_input_dropped_total{{session_id=\"{id}\",category=\"{category}\"}} {count}"
+            );
+        }
+    }
+    out.push_str(
+        "\n# HELP beam_agent_av_drift_ms How far audio timestamps have drifted from the media clock per session\n\
+         # TYPE beam_agent_av_drift_ms gauge\n",
+    );
+    for (id, h) in health {
+        let _ = writeln!(
+            out,
+            "beam_agent_av_drift_ms{{session_id=\"{id}\"}} {}",
+            h.av_drift_ms
+        );
+    }
+    out.push_str(
+        "\n# HELP beam_agent_audio_seconds_total Audio browsers played per session, and the part they filled with silence\n\
+         # TYPE beam_agent_audio_seconds_total counter\n",
+    );
+    for (id, h) in health {
+        for (kind, ms) in [
+            ("played", h.audio_played_ms),
+            ("concealed", h.audio_concealed_ms),
+        ] {
+            let _ = writeln!(
+                out,
+                "beam_agent_audio_seconds_total{{session_id=\"{id}\",kind=\"{kind}\"}} {}",
+                ms as f64 / 1000.0
+            );
+        }
+    }
+    out
+}
+
+/// Render per-session counts of browser messages the server refused.
+fn rejected_message_metrics(rejected: &[(Uuid, u64, u64)]) -> String {
+    use std::fmt::Write;
+    let mut out = String::from(
+        "\n# HELP beam_rejected_messages_total Browser messages dropped as malformed or oversized\n\
+         # TYPE beam_rejected_messages_total counter\n",
+    );
+    for (id, malformed, too_large) in rejected {
+        for (reason, count) in [("malformed", malformed), ("too_large", too_large)] {
+            let _ = writeln!(
+                out,
+                "beam_rejected_messages_total{{session_id=\"{id}\",reason=\"{reason}\"}} {count}"
+            );
+        }
+    }
+    out
+}
+
+/// Render per-session counts of frozen pictures the agents reported.
+fn stream_alert_metrics(alerts: &[(Uuid, beam_protocol::StreamAlertKind, u64)]) -> String {
+    use std::fmt::Write;
+    let mut out = String::from(
+        "\n# HELP beam_stream_alerts_total Times a session's video stopped reaching its browser\n\
+         # TYPE beam_stream_alerts_total counter\n",
+    );
+    for (id, kind, count) in alerts {
+        let _ = writeln!(
+            out,
+            "beam_stream_alerts_total{{session_id=\"{id}\",kind=\"{}\"}} {count}",
+            kind.name()
+        );
+    }
+    out
+}
+
+/// POST /api/agent/handshake - trade the agent's long-lived token (Bearer)
+/// for a short-lived, single-use WebSocket ticket
+async fn agent_handshake(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<beam_protocol::AgentHandshake>,
+) -> impl IntoResponse {
+    let id = req.session_id;
+    let token = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let Some(token) = token else {
+        return (StatusCode::UNAUTHORIZED, "Missing agent token").into_response();
+    };
+    if !state.session_manager.verify_agent_token(id, token).await {
+        tracing::warn!(%id, "Invalid agent token on handshake");
+        return (StatusCode::UNAUTHORIZED, "Invalid agent token").into_response();
+    }
+
+    // A new ticket on every (re)connect; an older one that was never used
+    // stops working
+    let nonce = auth::generate_secret();
+    let ticket = match auth::generate_agent_ticket(id, &nonce, &state.jwt_secret) {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            tracing::error!(%id, "Failed to sign agent ticket: {e:#}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue ticket").into_response();
+        }
+    };
+    signaling::get_or_create_channel(&state.channels, id)
+        .await
+        .issue_agent_ticket(nonce);
+    tracing::debug!(%id, "Agent ticket issued");
+    Json(beam_protocol::AgentTicket {
+        token: ticket,
+        expires_in: auth::AGENT_TICKET_SECS,
+    })
+    .into_response()
+}
+
+/// GET /ws/agent/:id - WebSocket upgrade for agent signaling (requires a
+/// ticket from `/api/agent/handshake`, or for now the legacy agent token)
+async fn agent_ws_upgrade(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<WsQuery>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let Some(token) = &query.token else {
+        return (StatusCode::UNAUTHORIZED, "Missing agent ticket").into_response();
+    };
+    match auth::validate_agent_ticket(token, &state.jwt_secret) {
+        Ok(claims) if claims.sid == id => {
+            // Whoever replays a URL from a proxy log finds its ticket used up
+            let redeemed = state
+                .channels
+                .read()
+                .await
+                .get(&id)
+                .is_some_and(|channel| channel.redeem_agent_ticket(&claims.nonce));
+            if !redeemed || state.session_manager.get_session(id).await.is_none() {
+                tracing::warn!(target: "audit", event = "agent_ticket_rejected", session_id = %id, "Agent ticket already used or superseded");
+                return (StatusCode::UNAUTHORIZED, "Invalid agent ticket").into_response();
+            }
+        }
+        // Agents outlive server restarts, so one started before tickets
+        // existed reconnects with its long-lived token. Accepted for one
+        // release, and only for sessions restored from a session file that
+        // old; such agents pick up tickets when their session restarts.
+        _ if state
+            .session_manager
+            .verify_legacy_agent_token(id, token)
+            .await =>
+        {
+            tracing::warn!(target: "audit", event = "agent_legacy_token", session_id = %id, "Agent connected with its long-lived token in the URL; deprecated, and refused from the next release");
+        }
+        _ => {
+            tracing::warn!(%id, "Invalid agent ticket on WebSocket upgrade");
+            return (StatusCode::UNAUTHORIZED, "Invalid agent ticket").into_response();
+        }
+    }
+
+    tracing::info!(%id, "Agent WebSocket upgrade (authenticated)");
+    let channels = state.channels.clone();
+    let keepalive = signaling::Keepalive::from_config(&state.config.server);
+    let stats_history_secs = state.config.session.stats_history_secs;
+    let version_window = state.config.server.agent_version_window;
+    ws.max_message_size(2 * 1024 * 1024) // 2MB max (binary video frames + text signaling)
+        .on_upgrade(move |socket| async move {
+            if signaling::handle_agent_ws(
+                socket,
+                id,
+                channels,
+                keepalive,
+                stats_history_secs,
+                version_window,
+            )
+            .await
+                != signaling::AgentWsExit::IdleDestroy
+            {
+                return;
+            }
+            if let Err(e) = state.session_manager.destroy_session(id).await {
+                tracing::error!(%id, "Failed to destroy idle session: {e:#}");
+            }
+            signaling::remove_channel(&state.channels, id).await;
+            tracing::info!(target: "audit", event = "session_destroyed", session_id = %id, reason = "idle", "Session destroyed");
+        })
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_allows_under_limit() {
+        let limiter = LoginRateLimiter::new(3, 60);
+        limiter.record_failure("user1");
+        limiter.record_failure("user1");
+        // 2 failures recorded, limit is 3 — should still be allowed
+        assert!(limiter.is_allowed("user1"));
+    }
+
+    #[test]
+    fn rate_limiter_blocks_over_limit() {
+        let limiter = LoginRateLimiter::new(3, 60);
+        limiter.record_failure("user1");
+        limiter.record_failure("user1");
+        limiter.record_failure("user1");
+        // 3 failures recorded, limit is 3 — should be blocked
+        assert!(!limiter.is_allowed("user1"));
+    }
+
+    #[test]
+    fn rate_limiter_independent_per_key() {
+        let limiter = LoginRateLimiter::new(2, 60);
+        limiter.record_failure("user1");
+        limiter.record_failure("user1");
+        assert!(!limiter.is_allowed("user1")); // blocked
+
+        // user2 should still be allowed
+        assert!(limiter.is_allowed("user2"));
+        limiter.record_failure("user2");
+        assert!(limiter.is_allowed("user2"));
+    }
+
+    #[test]
+    fn rate_limiter_resets_after_window() {
+        let limiter = LoginRateLimiter::new(2, 0); // 0-second window = immediately expires
+        limiter.record_failure("user1");
+        limiter.record_failure("user1");
+        // With a 0-second window, previous attempts expire immediately
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(limiter.is_allowed("user1"));
+    }
+
+    #[test]
+    fn rate_limiter_is_allowed_does_not_record() {
+        let limiter = LoginRateLimiter::new(2, 60);
+        // Calling is_allowed many times should never block — it's read-only
+        for _ in 0..100 {
+            assert!(limiter.is_allowed("user1"));
+        }
+    }
+
+    #[test]
     fn rate_limiter_clear_resets_failures() {
         let limiter = LoginRateLimiter::new(2, 60);
-        limiter.record_failure("user1");
-        limiter.record_failure("user1");
-        assert!(!limiter.is_allowed("user1")); // blocked
-        limiter.clear("user1");
-        assert!(limiter.is_allowed("user1")); // unblocked after clear
+        limiter.record_failure("user1");
+        limiter.record_failure("user1");
+        assert!(!limiter.is_allowed("user1")); // blocked
+        limiter.clear("user1");
+        assert!(limiter.is_allowed("user1")); // unblocked after clear
+    }
+
+    #[test]
+    fn rate_limiter_ttl_cleanup_removes_expired_entries() {
+        // window=0s means entries expire immediately; cleanup_interval=1
+        // means every call to is_allowed() triggers a full TTL sweep.
+        let limiter = LoginRateLimiter::new(5, 0).with_cleanup_interval(1);
+
+        // Simulate an enumeration attack: 50 unique keys
+        for i in 0..50 {
+            limiter.record_failure(&format!("attacker-{i}"));
+        }
+
+        // Wait for all entries to expire (window=0s, so any delay suffices)
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        // Next is_allowed() triggers TTL cleanup, pruning all 50 expired keys.
+        // is_allowed is read-only and doesn't insert entries for unknown keys.
+        limiter.is_allowed("trigger-cleanup");
+
+        assert_eq!(
+            limiter.key_count(),
+            0,
+            "Expired entries should be pruned by TTL cleanup"
+        );
+    }
+
+    #[test]
+    fn rate_limiter_ttl_cleanup_preserves_active_entries() {
+        // Use a 60-second window with cleanup on every call
+        let limiter = LoginRateLimiter::new(5, 60).with_cleanup_interval(1);
+
+        limiter.record_failure("active-user-1");
+        limiter.record_failure("active-user-2");
+        limiter.record_failure("active-user-3");
+
+        // Trigger another cleanup — all entries are within window, none should be pruned
+        limiter.record_failure("active-user-4");
+
+        assert_eq!(
+            limiter.key_count(),
+            4,
+            "Active entries should not be pruned"
+        );
+    }
+
+    #[test]
+    fn rate_limiter_boundary_exact_limit() {
+        // With max_attempts=5, the 5th failure should block but 4 should not
+        let limiter = LoginRateLimiter::new(5, 60);
+        for _ in 0..4 {
+            limiter.record_failure("user");
+        }
+        assert!(
+            limiter.is_allowed("user"),
+            "4 failures out of 5 should still be allowed"
+        );
+
+        limiter.record_failure("user");
+        assert!(
+            !limiter.is_allowed("user"),
+            "5th failure should trigger block"
+        );
+    }
+
+    #[test]
+    fn rate_limiter_max_keys_rejects_new_keys() {
+        // With max_keys=3, once 3 keys are tracked, new unknown keys are rejected
+        let limiter = LoginRateLimiter::new(5, 60).with_max_keys(3);
+        limiter.record_failure("user1");
+        limiter.record_failure("user2");
+        limiter.record_failure("user3");
+
+        // Existing keys still work
+        assert!(limiter.is_allowed("user1"));
+        // New key is rejected because map is at capacity
+        assert!(
+            !limiter.is_allowed("new-user"),
+            "new keys should be rejected when at max_keys capacity"
+        );
+    }
+
+    #[test]
+    fn rate_limiter_max_keys_allows_existing_at_capacity() {
+        let limiter = LoginRateLimiter::new(5, 60).with_max_keys(2);
+        limiter.record_failure("alice");
+        limiter.record_failure("bob");
+
+        // Both existing keys should still work even at max_keys
+        assert!(limiter.is_allowed("alice"));
+        assert!(limiter.is_allowed("bob"));
+    }
+
+    #[test]
+    fn rate_limiter_remaining_attempts() {
+        let limiter = LoginRateLimiter::new(5, 60);
+        // No failures — remaining_attempts returns None (don't reveal state)
+        assert_eq!(limiter.remaining_attempts("user", 3), None);
+
+        // 2 failures (below threshold of 3) — still None
+        limiter.record_failure("user");
+        limiter.record_failure("user");
+        assert_eq!(limiter.remaining_attempts("user", 3), None);
+
+        // 3 failures (at threshold) — returns Some(2)
+        limiter.record_failure("user");
+        assert_eq!(limiter.remaining_attempts("user", 3), Some(2));
+
+        // 4 failures — returns Some(1)
+        limiter.record_failure("user");
+        assert_eq!(limiter.remaining_attempts("user", 3), Some(1));
+
+        // 5 failures (at limit) — returns Some(0)
+        limiter.record_failure("user");
+        assert_eq!(limiter.remaining_attempts("user", 3), Some(0));
+    }
+
+    #[test]
+    fn normalize_ipv6_to_64_prefix() {
+        use std::net::IpAddr;
+        // IPv4 — unchanged
+        let v4: IpAddr = "192.168.1.100".parse().unwrap();
+        assert_eq!(normalize_ip_for_rate_limit(v4), "192.168.1.100");
+
+        // IPv6 — truncated to /64
+        let v6: IpAddr = "2001:db8:85a3:1234:5678:abcd:ef01:2345".parse().unwrap();
+        assert_eq!(normalize_ip_for_rate_limit(v6), "2001:db8:85a3:1234::/64");
+
+        // Two IPs in same /64 should produce same key
+        let v6a: IpAddr = "2001:db8::1".parse().unwrap();
+        let v6b: IpAddr = "2001:db8::ffff".parse().unwrap();
+        assert_eq!(
+            normalize_ip_for_rate_limit(v6a),
+            normalize_ip_for_rate_limit(v6b),
+            "IPs in same /64 should map to same rate limit key"
+        );
+
+        // Different /64 should produce different keys
+        let v6c: IpAddr = "2001:db8:0:1::1".parse().unwrap();
+        assert_ne!(
+            normalize_ip_for_rate_limit(v6a),
+            normalize_ip_for_rate_limit(v6c),
+            "IPs in different /64 should have different rate limit keys"
+        );
+
+        // IPv4-mapped IPv6 (::ffff:x.x.x.x) should rate-limit as inner IPv4
+        let mapped: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+        assert_eq!(
+            normalize_ip_for_rate_limit(mapped),
+            "10.0.0.1",
+            "IPv4-mapped IPv6 should be treated as IPv4"
+        );
+    }
+
+    #[test]
+    fn rate_limiter_empty_key() {
+        let limiter = LoginRateLimiter::new(2, 60);
+        // Empty string is a valid key — should behave like any other
+        assert!(limiter.is_allowed(""));
+        limiter.record_failure("");
+        limiter.record_failure("");
+        assert!(!limiter.is_allowed(""));
+    }
+
+    #[test]
+    fn extract_claims_from_bearer_header() {
+        let secret = "test-secret";
+        let token = crate::auth::generate_jwt("alice", secret).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        let query = WsQuery { token: None };
+
+        let claims = extract_claims_from_headers(&headers, &query, secret).unwrap();
+        assert_eq!(claims.sub, "alice");
+    }
+
+    #[test]
+    fn extract_claims_from_query_fallback() {
+        let secret = "test-secret";
+        let token = crate::auth::generate_jwt("bob", secret).unwrap();
+
+        let headers = HeaderMap::new();
+        let query = WsQuery { token: Some(token) };
+
+        let claims = extract_claims_from_headers(&headers, &query, secret).unwrap();
+        assert_eq!(claims.sub, "bob");
+    }
+
+    #[test]
+    fn extract_claims_prefers_header_over_query() {
+        let secret = "test-secret";
+        let header_token = crate::auth::generate_jwt("alice", secret).unwrap();
+        let query_token = crate::auth::generate_jwt("bob", secret).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            format!("Bearer {header_token}").parse().unwrap(),
+        );
+        let query = WsQuery {
+            token: Some(query_token),
+        };
+
+        // Header should take precedence
+        let claims = extract_claims_from_headers(&headers, &query, secret).unwrap();
+        assert_eq!(claims.sub, "alice");
+    }
+
+    #[test]
+    fn extract_claims_rejects_missing_token() {
+        let headers = HeaderMap::new();
+        let query = WsQuery { token: None };
+        let result = extract_claims_from_headers(&headers, &query, "secret");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn extract_claims_rejects_invalid_token() {
+        let headers = HeaderMap::new();
+        let query = WsQuery {
+            token: Some("invalid.token.here".to_string()),
+        };
+        let result = extract_claims_from_headers(&headers, &query, "secret");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn username_validation_rejects_empty() {
+        assert!(!is_valid_username(""));
+    }
+
+    #[test]
+    fn username_validation_rejects_too_long() {
+        let long = "a".repeat(65);
+        assert!(!is_valid_username(&long));
+    }
+
+    #[test]
+    fn username_validation_rejects_invalid_chars() {
+        assert!(!is_valid_username("user name")); // space
+        assert!(!is_valid_username("user@host")); // @
+        assert!(!is_valid_username("user/root")); // path traversal
+        assert!(!is_valid_username("user\x00")); // null byte
+        assert!(!is_valid_username("user;id")); // shell injection
+    }
+
+    #[test]
+    fn username_validation_accepts_valid() {
+        assert!(is_valid_username("alice"));
+        assert!(is_valid_username("bob_smith"));
+        assert!(is_valid_username("user-123"));
+        assert!(is_valid_username("test.user"));
+        assert!(is_valid_username("A"));
+        assert!(is_valid_username(&"a".repeat(64))); // exactly 64 chars
+    }
+
+    // --- HTTP-level integration tests ---
+    //
+    // These use `tower::ServiceExt::oneshot` to send requests through the axum
+    // router without starting a real HTTP server or TLS listener.
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    const TEST_JWT_SECRET: &str = "test-secret-for-integration-tests";
+
+    /// Build a test `AppState` with defaults suitable for unit/integration tests.
+    fn test_app_state() -> Arc<AppState> {
+        test_app_state_with(toml::from_str("").expect("default config"))
+    }
+
+    /// A temp path no other test in this run uses, so tests writing
+    /// state files can run in parallel.
+    fn unique_temp_path(name: &str) -> std::path::PathBuf {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::env::temp_dir().join(format!("beam-{name}-{}-{n}", std::process::id()))
+    }
+
+    fn test_app_state_with(config: BeamConfig) -> Arc<AppState> {
+        let session_manager = crate::session::SessionManager::new(
+            100, // display_start (high to avoid conflicts)
+            1920,
+            1080,
+            None,
+            beam_protocol::VideoConfig::default(),
+            beam_protocol::AudioConfig::default(),
+            beam_protocol::SessionConfig::default(),
+        );
+        Arc::new(AppState {
+            config,
+            session_manager,
+            channels: crate::signaling::new_channel_registry(),
+            jwt_secret: TEST_JWT_SECRET.to_string(),
+            login_limiter: LoginRateLimiter::new(5, 60),
+            ip_limiter: LoginRateLimiter::new(20, 60),
+            release_limiter: LoginRateLimiter::new(10, 60),
+            nettest_limiter: LoginRateLimiter::new(12, 60),
+            ip_bans: crate::bans::IpBans::new(300, 86400),
+            started_at: std::time::Instant::now(),
+            drain: crate::drain::DrainState::new(3600),
+            metrics_logins_attempted: std::sync::atomic::AtomicU64::new(0),
+            metrics_logins_failed: std::sync::atomic::AtomicU64::new(0),
+            metrics_agent_restarts: std::sync::atomic::AtomicU64::new(0),
+            trusted_proxies: Default::default(),
+            geoip: None,
+            preferences: crate::preferences::Preferences::new(unique_temp_path("preferences")),
+            guests: crate::guest::Guests::load(unique_temp_path("guests")),
+        })
+    }
+
+    /// Helper: parse a response body as `serde_json::Value`.
+    async fn body_json(response: axum::response::Response<Body>) -> serde_json::Value {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("failed to read response body")
+            .to_bytes();
+        serde_json::from_slice(&bytes).expect("response body is not valid JSON")
+    }
+
+    #[tokio::test]
+    async fn health_returns_ok_unauthenticated() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let request = Request::builder()
+            .uri("/api/health")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let json = body_json(response).await;
+        assert_eq!(json["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn nettest_serves_capped_uncached_padding() {
+        let state = test_app_state();
+        let app = build_router(state);
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let probe = |bytes: usize| {
+            Request::builder()
+                .uri(format!("/api/nettest?bytes={bytes}"))
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let request = Request::builder()
+            .uri("/api/nettest?bytes=4096")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.clone().oneshot(probe(4096)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["cache-control"], "no-store");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.len(), 4096);
+        assert!(body.iter().any(|&b| b != 0));
+
+        let response = app
+            .clone()
+            .oneshot(probe(NETTEST_MAX_BYTES * 4))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.len(), NETTEST_MAX_BYTES);
+
+        // Three probes a minute, then the user is turned away
+        for _ in 2..12 {
+            let response = app.clone().oneshot(probe(0)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.oneshot(probe(0)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn session_nettest_checks_auth_and_session() {
+        let state = test_app_state();
+        let uri = format!("/api/sessions/{}/nettest", Uuid::new_v4());
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let request = |auth: bool| {
+            let mut builder = Request::builder()
+                .method("POST")
+                .uri(&uri)
+                .header("content-type", "application/json");
+            if auth {
+                builder = builder.header("authorization", format!("Bearer {token}"));
+            }
+            builder
+                .body(Body::from(r#"{"kbps":10000,"rtt_ms":40}"#))
+                .unwrap()
+        };
+
+        for (auth, status) in [
+            (false, StatusCode::UNAUTHORIZED),
+            (true, StatusCode::NOT_FOUND),
+        ] {
+            let response = build_router(Arc::clone(&state))
+                .oneshot(request(auth))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn health_detailed_requires_auth() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let request = Request::builder()
+            .uri("/api/health/detailed")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn health_detailed_with_valid_jwt() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+
+        let request = Request::builder()
+            .uri("/api/health/detailed")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let json = body_json(response).await;
+        assert_eq!(json["status"], "ok");
+        assert!(json["version"].is_string(), "expected version string");
+        assert_eq!(json["protocol_version"], beam_protocol::PROTOCOL_VERSION);
+        assert_eq!(json["agents"], json!([]));
+        assert!(json["uptime_secs"].is_number(), "expected uptime number");
+        assert!(json["sessions"].is_number(), "expected sessions count");
+        assert_eq!(json["drain"]["draining"], false);
+        assert!(
+            json["system"]["checks"].is_array(),
+            "expected doctor checks"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_sessions_requires_auth() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let request = Request::builder()
+            .uri("/api/sessions")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn list_sessions_with_valid_jwt() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+
+        let request = Request::builder()
+            .uri("/api/sessions")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let json = body_json(response).await;
+        assert!(json.is_array(), "expected JSON array of sessions");
+        // No sessions created, so the array should be empty
+        assert_eq!(json.as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn login_returns_401_for_invalid_creds() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let body = serde_json::json!({
+            "username": "nonexistent",
+            "password": "wrongpassword"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body).unwrap()))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        // PAM auth will fail (no such user) — expect 401 or 504 (timeout)
+        // depending on PAM backend speed. Either way, NOT 200.
+        let status = response.status();
+        assert!(
+            status == StatusCode::UNAUTHORIZED
+                || status == StatusCode::GATEWAY_TIMEOUT
+                || status == StatusCode::INTERNAL_SERVER_ERROR,
+            "expected auth failure status, got {status}"
+        );
+
+        let json = body_json(response).await;
+        assert!(json["error"].is_string(), "expected error message in body");
+    }
+
+    #[tokio::test]
+    async fn invalid_jwt_rejected() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        // Generate a JWT signed with a different secret
+        let wrong_token =
+            crate::auth::generate_jwt("testuser", "completely-different-secret").unwrap();
+
+        let request = Request::builder()
+            .uri("/api/sessions")
+            .header("authorization", format!("Bearer {wrong_token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn username_validation_rejects_bad_input() {
+        let bad_usernames = vec![
+            "../etc/passwd", // path traversal
+            "user\x00admin", // null byte
+            "user name",     // space
+            "user;id",       // shell injection
+            "",              // empty
+        ];
+
+        for bad_username in bad_usernames {
+            let state = test_app_state();
+            let app = build_router(state);
+
+            let body = serde_json::json!({
+                "username": bad_username,
+                "password": "anything"
+            });
+
+            let request = Request::builder()
+                .method("POST")
+                .uri("/api/auth/login")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&body).unwrap()))
+                .unwrap();
+
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::BAD_REQUEST,
+                "username {bad_username:?} should be rejected with 400"
+            );
+
+            let json = body_json(response).await;
+            assert_eq!(json["error"], "Invalid username");
+        }
+    }
+
+    #[tokio::test]
+    async fn login_rejects_idle_timeout_too_low() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let body = serde_json::json!({
+            "username": "testuser",
+            "password": "password",
+            "idle_timeout": 59
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body).unwrap()))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let json = body_json(response).await;
+        assert!(json["error"].as_str().unwrap().contains("idle_timeout"));
+    }
+
+    #[tokio::test]
+    async fn login_rejects_custom_desktop() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let body = serde_json::json!({
+            "username": "testuser",
+            "password": "password",
+            "desktop": "xterm -e sh"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body).unwrap()))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let json = body_json(response).await;
+        assert!(json["error"].as_str().unwrap().contains("kde"));
+    }
+
+    #[tokio::test]
+    async fn login_rejects_idle_timeout_too_high() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let body = serde_json::json!({
+            "username": "testuser",
+            "password": "password",
+            "idle_timeout": 86401
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body).unwrap()))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let json = body_json(response).await;
+        assert!(json["error"].as_str().unwrap().contains("idle_timeout"));
+    }
+
+    #[tokio::test]
+    async fn security_headers_present_on_responses() {
+        let state = test_app_state();
+        let app = build_router(state);
+
+        let request = Request::builder()
+            .uri("/api/health")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+
+        assert_eq!(
+            headers
+                .get("strict-transport-security")
+                .map(|v| v.as_bytes()),
+            Some(b"max-age=63072000; includeSubDomains".as_slice()),
+            "missing or wrong Strict-Transport-Security"
+        );
+        assert_eq!(
+            headers.get("x-content-type-options").map(|v| v.as_bytes()),
+            Some(b"nosniff".as_slice()),
+            "missing or wrong X-Content-Type-Options"
+        );
+        assert_eq!(
+            headers.get("x-frame-options").map(|v| v.as_bytes()),
+            Some(b"DENY".as_slice()),
+            "missing or wrong X-Frame-Options"
+        );
+        assert_eq!(
+            headers.get("referrer-policy").map(|v| v.as_bytes()),
+            Some(b"strict-origin-when-cross-origin".as_slice()),
+            "missing or wrong Referrer-Policy"
+        );
+        assert_eq!(
+            headers.get("x-xss-protection").map(|v| v.as_bytes()),
+            Some(b"0".as_slice()),
+            "missing or wrong X-XSS-Protection"
+        );
+        assert_eq!(
+            headers.get("content-security-policy").map(|v| v.as_bytes()),
+            Some(
+                b"default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; \
+                  connect-src 'self' wss:; img-src 'self' data:; media-src 'self' blob:"
+                    .as_slice()
+            ),
+            "missing or wrong Content-Security-Policy"
+        );
+        assert_eq!(
+            headers.get("permissions-policy").map(|v| v.as_bytes()),
+            Some(b"camera=(), microphone=(), geolocation=()".as_slice()),
+            "missing or wrong Permissions-Policy"
+        );
     }
 
-    #[test]
-    fn rate_limiter_ttl_cleanup_removes_expired_entries() {
-        // window=0s means entries expire immediately; cleanup_interval=1
-        // means every call to is_allowed() triggers a full TTL sweep.
-        let limiter = LoginRateLimiter::new(5, 0).with_cleanup_interval(1);
+    #[tokio::test]
+    async fn metrics_endpoint_returns_prometheus_format() {
+        let state = test_app_state();
 
-        // Simulate an enumeration attack: 50 unique keys
-        for i in 0..50 {
-            limiter.record_failure(&format!("attacker-{i}"));
+        // Simulate some metrics
+        state
+            .metrics_logins_attempted
+            .store(42, std::sync::atomic::Ordering::Relaxed);
+        state
+            .metrics_logins_failed
+            .store(5, std::sync::atomic::Ordering::Relaxed);
+        state
+            .metrics_agent_restarts
+            .store(2, std::sync::atomic::Ordering::Relaxed);
+
+        let app = build_router(state);
+
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+
+        let request = Request::builder()
+            .uri("/metrics")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Check Content-Type header
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .expect("missing content-type")
+            .to_str()
+            .unwrap();
+        assert_eq!(content_type, "text/plain; version=0.0.4; charset=utf-8");
+
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&bytes).unwrap();
+
+        // Verify Prometheus text format: each metric has HELP, TYPE, and value lines
+        assert!(body.contains("# HELP beam_active_sessions"));
+        assert!(body.contains("# TYPE beam_active_sessions gauge"));
+        assert!(body.contains("beam_active_sessions 0"));
+
+        assert!(body.contains("# HELP beam_uptime_seconds"));
+        assert!(body.contains("# TYPE beam_uptime_seconds gauge"));
+
+        assert!(body.contains("# HELP beam_total_logins_attempted"));
+        assert!(body.contains("# TYPE beam_total_logins_attempted counter"));
+        assert!(body.contains("beam_total_logins_attempted 42"));
+
+        assert!(body.contains("# HELP beam_total_logins_failed"));
+        assert!(body.contains("# TYPE beam_total_logins_failed counter"));
+        assert!(body.contains("beam_total_logins_failed 5"));
+
+        assert!(body.contains("# HELP beam_agent_restarts_total"));
+        assert!(body.contains("# TYPE beam_agent_restarts_total counter"));
+        assert!(body.contains("beam_agent_restarts_total 2"));
+    }
+
+    #[tokio::test]
+    async fn metrics_include_session_latency() {
+        let state = test_app_state();
+        let id = Uuid::new_v4();
+        let channel = crate::signaling::get_or_create_channel(&state.channels, id).await;
+        *channel.latency.write().unwrap() = Some(beam_protocol::LatencyStats {
+            samples: 10,
+            p50_ms: 21.5,
+            p95_ms: 30.0,
+            p99_ms: 45.0,
+            pipeline_p50_ms: 6.0,
+            network_p50_ms: 7.0,
+        });
+
+        let app = build_router(state);
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let request = Request::builder()
+            .uri("/metrics")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&bytes).unwrap();
+
+        assert!(body.contains("# TYPE beam_session_latency_ms gauge"));
+        assert!(body.contains(&format!(
+            "beam_session_latency_ms{{session_id=\"{id}\",quantile=\"0.5\"}} 21.5"
+        )));
+        assert!(body.contains(&format!(
+            "beam_session_latency_ms{{session_id=\"{id}\",quantile=\"0.99\"}} 45"
+        )));
+    }
+
+    #[tokio::test]
+    async fn metrics_include_agent_health() {
+        let state = test_app_state();
+        let id = Uuid::new_v4();
+        let channel = crate::signaling::get_or_create_channel(&state.channels, id).await;
+        *channel.health.write().unwrap() = Some(beam_protocol::AgentHealth {
+            rss_mb: 300,
+            cpu_pct: 37.5,
+            capture_fps: 60.0,
+            encode_fps: 60.0,
+            target_fps: 60,
+            recoveries: 0,
+            input_dropped: beam_protocol::InputDrops {
+                clipboard: 7,
+                ..Default::default()
+            },
+            av_drift_ms: 2.5,
+            audio_played_ms: 10_000,
+            audio_concealed_ms: 250,
+        });
+
+        let app = build_router(state);
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let request = Request::builder()
+            .uri("/metrics")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&bytes).unwrap();
+
+        assert!(body.contains("# TYPE beam_agent_rss_bytes gauge"));
+        assert!(body.contains(&format!(
+            "beam_agent_rss_bytes{{session_id=\"{id}\"}} 314572800"
+        )));
+        assert!(body.contains(&format!(
+            "beam_agent_cpu_percent{{session_id=\"{id}\"}} 37.5"
+        )));
+        assert!(body.contains(&format!(
+            "beam_agent_input_dropped_total{{session_id=\"{id}\",category=\"clipboard\"}} 7"
+        )));
+        assert!(body.contains(&format!(
+            "beam_agent_av_drift_ms{{session_id=\"{id}\"}} 2.5"
+        )));
+        assert!(body.contains(&format!(
+            "beam_agent_audio_seconds_total{{session_id=\"{id}\",kind=\"concealed\"}} 0.25"
+        )));
+    }
+
+    #[tokio::test]
+    async fn session_audio_toggle_checks_auth_and_session() {
+        let state = test_app_state();
+        let uri = format!("/api/sessions/{}/audio", Uuid::new_v4());
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+
+        let unauthenticated = Request::builder()
+            .method("POST")
+            .uri(&uri)
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"enabled":false}"#))
+            .unwrap();
+        let response = build_router(Arc::clone(&state))
+            .oneshot(unauthenticated)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let unknown_session = Request::builder()
+            .method("POST")
+            .uri(&uri)
+            .header("authorization", format!("Bearer {token}"))
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"enabled":false}"#))
+            .unwrap();
+        let response = build_router(state).oneshot(unknown_session).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn session_quality_checks_auth_preset_and_session() {
+        let state = test_app_state();
+        let uri = format!("/api/sessions/{}/quality", Uuid::new_v4());
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let request = |auth: bool, body: &'static str| {
+            let mut builder = Request::builder()
+                .method("POST")
+                .uri(&uri)
+                .header("content-type", "application/json");
+            if auth {
+                builder = builder.header("authorization", format!("Bearer {token}"));
+            }
+            builder.body(Body::from(body)).unwrap()
+        };
+
+        for (auth, body, status) in [
+            (false, r#"{"preset":"sharp"}"#, StatusCode::UNAUTHORIZED),
+            (
+                true,
+                r#"{"preset":"ultra"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (true, r#"{"preset":"sharp"}"#, StatusCode::NOT_FOUND),
+        ] {
+            let response = build_router(Arc::clone(&state))
+                .oneshot(request(auth, body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{body}");
         }
+        assert_eq!(state.preferences.get("testuser").quality, None);
+    }
 
-        // Wait for all entries to expire (window=0s, so any delay suffices)
-        std::thread::sleep(std::time::Duration::from_millis(10));
+    #[tokio::test]
+    async fn admin_session_stats_requires_admin() {
+        let state = test_app_state();
+        let app = build_router(state);
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
 
-        // Next is_allowed() triggers TTL cleanup, pruning all 50 expired keys.
-        // is_allowed is read-only and doesn't insert entries for unknown keys.
-        limiter.is_allowed("trigger-cleanup");
+        let request = Request::builder()
+            .uri(format!("/api/admin/sessions/{}/stats", Uuid::new_v4()))
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
 
-        assert_eq!(
-            limiter.key_count(),
-            0,
-            "Expired entries should be pruned by TTL cleanup"
-        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
 
-    #[test]
-    fn rate_limiter_ttl_cleanup_preserves_active_entries() {
-        // Use a 60-second window with cleanup on every call
-        let limiter = LoginRateLimiter::new(5, 60).with_cleanup_interval(1);
+    #[tokio::test]
+    async fn admin_drain_requires_admin() {
+        let state = test_app_state();
+        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
 
-        limiter.record_failure("active-user-1");
-        limiter.record_failure("active-user-2");
-        limiter.record_failure("active-user-3");
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/admin/drain")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
 
-        // Trigger another cleanup — all entries are within window, none should be pruned
-        limiter.record_failure("active-user-4");
+        let response = build_router(Arc::clone(&state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!state.drain.is_draining());
+    }
+
+    fn login_from(ip: &str) -> Request<Body> {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/api/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"username":"nosuchuser","password":"wrong"}"#,
+            ))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(std::net::SocketAddr::new(ip.parse().unwrap(), 50000));
+        request
+    }
 
+    #[tokio::test]
+    async fn assist_login_is_refused_while_disabled() {
+        let state = test_app_state();
+        let mut request = login_from("192.0.2.9");
+        *request.body_mut() =
+            Body::from(r#"{"username":"admin","password":"wrong","assist":true}"#);
+        let response = build_router(Arc::clone(&state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = body_json(response).await;
+        assert!(body["error"].as_str().unwrap().contains("disabled"));
+        // Turned away before the password was tried
         assert_eq!(
-            limiter.key_count(),
-            4,
-            "Active entries should not be pruned"
+            state
+                .metrics_logins_attempted
+                .load(std::sync::atomic::Ordering::Relaxed),
+            0
         );
     }
 
+    #[tokio::test]
+    async fn banned_address_is_refused_before_auth() {
+        let state = test_app_state();
+        state.ip_bans.ban("192.0.2.7");
+
+        let response = build_router(Arc::clone(&state))
+            .oneshot(login_from("192.0.2.7"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((299..=300).contains(&retry_after));
+        // The ban is per address
+        assert!(state.ip_bans.remaining("192.0.2.8").is_none());
+    }
+
     #[test]
-    fn rate_limiter_boundary_exact_limit() {
-        // With max_attempts=5, the 5th failure should block but 4 should not
-        let limiter = LoginRateLimiter::new(5, 60);
-        for _ in 0..4 {
-            limiter.record_failure("user");
+    fn exhausting_the_ip_limit_bans_with_growing_length() {
+        let state = test_app_state();
+        for _ in 0..19 {
+            record_ip_failure(&state, "192.0.2.7", "192.0.2.7");
         }
-        assert!(
-            limiter.is_allowed("user"),
-            "4 failures out of 5 should still be allowed"
-        );
-
-        limiter.record_failure("user");
-        assert!(
-            !limiter.is_allowed("user"),
-            "5th failure should trigger block"
-        );
+        assert!(state.ip_bans.remaining("192.0.2.7").is_none());
+        record_ip_failure(&state, "192.0.2.7", "192.0.2.7");
+        let first = state.ip_bans.remaining("192.0.2.7").unwrap();
+        assert!(first <= std::time::Duration::from_secs(300));
+        // The limiter restarts, so a repeat ban takes another full window
+        assert!(state.ip_limiter.is_allowed("192.0.2.7"));
+        for _ in 0..20 {
+            record_ip_failure(&state, "192.0.2.7", "192.0.2.7");
+        }
+        assert!(state.ip_bans.remaining("192.0.2.7").unwrap() > first);
     }
 
     #[test]
-    fn rate_limiter_max_keys_rejects_new_keys() {
-        // With max_keys=3, once 3 keys are tracked, new unknown keys are rejected
-        let limiter = LoginRateLimiter::new(5, 60).with_max_keys(3);
-        limiter.record_failure("user1");
-        limiter.record_failure("user2");
-        limiter.record_failure("user3");
+    fn ban_waits_are_rounded_up() {
+        assert_eq!(format_wait(1), "1 second");
+        assert_eq!(format_wait(300), "5 minutes");
+        assert_eq!(format_wait(301), "6 minutes");
+        assert_eq!(format_wait(7200), "2 hours");
+        assert_eq!(format_wait(86400), "24 hours");
+        assert_eq!(format_wait(172_800), "2 days");
+    }
 
-        // Existing keys still work
-        assert!(limiter.is_allowed("user1"));
-        // New key is rejected because map is at capacity
-        assert!(
-            !limiter.is_allowed("new-user"),
-            "new keys should be rejected when at max_keys capacity"
+    #[tokio::test]
+    async fn admin_lists_and_lifts_bans() {
+        let state = test_app_state_with(
+            toml::from_str("[server]\nadmin_users = [\"admin\"]").expect("admin config"),
         );
-    }
+        state.ip_bans.ban("192.0.2.7");
+        state.ip_bans.ban("2001:db8:0:0::/64");
+        let admin = crate::auth::generate_jwt("admin", TEST_JWT_SECRET).unwrap();
+        let user = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let request = |method: &str, uri: &str, token: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let app = build_router(Arc::clone(&state));
 
-    #[test]
-    fn rate_limiter_max_keys_allows_existing_at_capacity() {
-        let limiter = LoginRateLimiter::new(5, 60).with_max_keys(2);
-        limiter.record_failure("alice");
-        limiter.record_failure("bob");
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/api/admin/bans", &user))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        // Both existing keys should still work even at max_keys
-        assert!(limiter.is_allowed("alice"));
-        assert!(limiter.is_allowed("bob"));
-    }
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/api/admin/bans", &admin))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await.as_array().unwrap().len(), 2);
 
-    #[test]
-    fn rate_limiter_remaining_attempts() {
-        let limiter = LoginRateLimiter::new(5, 60);
-        // No failures — remaining_attempts returns None (don't reveal state)
-        assert_eq!(limiter.remaining_attempts("user", 3), None);
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/api/admin/bans/192.0.2.7", &user))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(state.ip_bans.remaining("192.0.2.7").is_some());
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/api/admin/bans/192.0.2.7", &admin))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.ip_bans.remaining("192.0.2.7").is_none());
+
+        // A plain IPv6 address lifts its /64's ban
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/api/admin/bans/2001:db8::42", &admin))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        // 2 failures (below threshold of 3) — still None
-        limiter.record_failure("user");
-        limiter.record_failure("user");
-        assert_eq!(limiter.remaining_attempts("user", 3), None);
+        let response = app
+            .oneshot(request("DELETE", "/api/admin/bans/192.0.2.7", &admin))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 
-        // 3 failures (at threshold) — returns Some(2)
-        limiter.record_failure("user");
-        assert_eq!(limiter.remaining_attempts("user", 3), Some(2));
+    #[tokio::test]
+    async fn admin_notifies_a_session() {
+        let state = test_app_state_with(
+            toml::from_str("[server]\nadmin_users = [\"admin\"]").expect("admin config"),
+        );
+        let req: AuthRequest =
+            serde_json::from_value(json!({ "username": "alice", "password": "" })).unwrap();
+        let (session, _) = state
+            .session_manager
+            .reserve_session(&req, None, 10)
+            .await
+            .unwrap();
+        let channel = signaling::get_or_create_channel(&state.channels, session.id).await;
+        let mut browser = channel.to_browser.subscribe();
+        let app = build_router(Arc::clone(&state));
+        let request = |user: &str, body: &str| {
+            let token = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            Request::builder()
+                .method("POST")
+                .uri(format!("/api/admin/sessions/{}/notify", session.id))
+                .header("authorization", format!("Bearer {token}"))
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
 
-        // 4 failures — returns Some(1)
-        limiter.record_failure("user");
-        assert_eq!(limiter.remaining_attempts("user", 3), Some(1));
+        let response = app
+            .clone()
+            .oneshot(request("alice", r#"{"message":"hi"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        // 5 failures (at limit) — returns Some(0)
-        limiter.record_failure("user");
-        assert_eq!(limiter.remaining_attempts("user", 3), Some(0));
+        let response = app
+            .clone()
+            .oneshot(request("admin", r#"{"message":"  "}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .oneshot(request("admin", r#"{"message":"Restarting at 18:00"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // No agent has connected to announce desktop notifications
+        assert_eq!(
+            body_json(response).await,
+            json!({ "browser": true, "desktop": false })
+        );
+        let notice: serde_json::Value =
+            serde_json::from_str(&browser.recv().await.unwrap()).unwrap();
+        assert_eq!(
+            notice,
+            json!({ "type": "notice", "message": "Restarting at 18:00", "from": "admin" })
+        );
     }
 
-    #[test]
-    fn normalize_ipv6_to_64_prefix() {
-        use std::net::IpAddr;
-        // IPv4 — unchanged
-        let v4: IpAddr = "192.168.1.100".parse().unwrap();
-        assert_eq!(normalize_ip_for_rate_limit(v4), "192.168.1.100");
+    #[tokio::test]
+    async fn admin_schedules_and_cancels_a_session_end() {
+        let state = test_app_state_with(
+            toml::from_str("[server]\nadmin_users = [\"admin\"]").expect("admin config"),
+        );
+        let req: AuthRequest =
+            serde_json::from_value(json!({ "username": "alice", "password": "" })).unwrap();
+        let (session, _) = state
+            .session_manager
+            .reserve_session(&req, None, 10)
+            .await
+            .unwrap();
+        let channel = signaling::get_or_create_channel(&state.channels, session.id).await;
+        let mut browser = channel.to_browser.subscribe();
+        let app = build_router(Arc::clone(&state));
+        let request = |user: &str, path: String| {
+            let token = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            Request::builder()
+                .method("DELETE")
+                .uri(path)
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let end = |after: u64| format!("/api/admin/sessions/{}?after={after}", session.id);
+        let cancel = format!("/api/admin/sessions/{}/termination", session.id);
 
-        // IPv6 — truncated to /64
-        let v6: IpAddr = "2001:db8:85a3:1234:5678:abcd:ef01:2345".parse().unwrap();
-        assert_eq!(normalize_ip_for_rate_limit(v6), "2001:db8:85a3:1234::/64");
+        let response = app
+            .clone()
+            .oneshot(request("admin", end(termination::MAX_DELAY_SECS + 1)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
-        // Two IPs in same /64 should produce same key
-        let v6a: IpAddr = "2001:db8::1".parse().unwrap();
-        let v6b: IpAddr = "2001:db8::ffff".parse().unwrap();
+        let response = app
+            .clone()
+            .oneshot(request("admin", end(300)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let notice: serde_json::Value =
+            serde_json::from_str(&browser.recv().await.unwrap()).unwrap();
         assert_eq!(
-            normalize_ip_for_rate_limit(v6a),
-            normalize_ip_for_rate_limit(v6b),
-            "IPs in same /64 should map to same rate limit key"
+            notice,
+            json!({ "type": "termination_scheduled", "ends_in_secs": 300, "from": "admin" })
         );
+        assert!(termination::pending(&channel).is_some());
 
-        // Different /64 should produce different keys
-        let v6c: IpAddr = "2001:db8:0:1::1".parse().unwrap();
-        assert_ne!(
-            normalize_ip_for_rate_limit(v6a),
-            normalize_ip_for_rate_limit(v6c),
-            "IPs in different /64 should have different rate limit keys"
-        );
+        let response = app
+            .clone()
+            .oneshot(request("alice", cancel.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        // IPv4-mapped IPv6 (::ffff:x.x.x.x) should rate-limit as inner IPv4
-        let mapped: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+        let response = app
+            .clone()
+            .oneshot(request("admin", cancel.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let notice: serde_json::Value =
+            serde_json::from_str(&browser.recv().await.unwrap()).unwrap();
         assert_eq!(
-            normalize_ip_for_rate_limit(mapped),
-            "10.0.0.1",
-            "IPv4-mapped IPv6 should be treated as IPv4"
+            notice,
+            json!({ "type": "termination_cancelled", "from": "admin" })
+        );
+        assert!(
+            state
+                .session_manager
+                .get_session(session.id)
+                .await
+                .is_some()
         );
-    }
 
-    #[test]
-    fn rate_limiter_empty_key() {
-        let limiter = LoginRateLimiter::new(2, 60);
-        // Empty string is a valid key — should behave like any other
-        assert!(limiter.is_allowed(""));
-        limiter.record_failure("");
-        limiter.record_failure("");
-        assert!(!limiter.is_allowed(""));
+        // Nothing left to call off
+        let response = app.oneshot(request("admin", cancel)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
-    #[test]
-    fn extract_claims_from_bearer_header() {
-        let secret = "test-secret";
-        let token = crate::auth::generate_jwt("alice", secret).unwrap();
+    #[tokio::test]
+    async fn admin_lists_displays() {
+        let state = test_app_state_with(
+            toml::from_str("[server]\nadmin_users = [\"admin\"]").expect("admin config"),
+        );
+        let app = build_router(Arc::clone(&state));
+        let request = |user: &str| {
+            let token = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            Request::builder()
+                .uri("/api/admin/displays")
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
 
-        let mut headers = HeaderMap::new();
-        headers.insert("authorization", format!("Bearer {token}").parse().unwrap());
-        let query = WsQuery { token: None };
+        let response = app.clone().oneshot(request("testuser")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        let claims = extract_claims_from_headers(&headers, &query, secret).unwrap();
-        assert_eq!(claims.sub, "alice");
+        let response = app.oneshot(request("admin")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let displays = body_json(response).await;
+        assert_eq!(displays["start"], displays["next"]);
+        assert_eq!(displays["free"], json!([]));
+        assert_eq!(displays["sessions"], json!([]));
     }
 
-    #[test]
-    fn extract_claims_from_query_fallback() {
-        let secret = "test-secret";
-        let token = crate::auth::generate_jwt("bob", secret).unwrap();
+    #[tokio::test]
+    async fn metrics_requires_auth_when_configured() {
+        // Default config has metrics_require_auth=true
+        let state = test_app_state();
+        assert!(state.config.server.metrics_require_auth);
+        let app = build_router(state);
 
-        let headers = HeaderMap::new();
-        let query = WsQuery { token: Some(token) };
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
 
-        let claims = extract_claims_from_headers(&headers, &query, secret).unwrap();
-        assert_eq!(claims.sub, "bob");
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
-    #[test]
-    fn extract_claims_prefers_header_over_query() {
-        let secret = "test-secret";
-        let header_token = crate::auth::generate_jwt("alice", secret).unwrap();
-        let query_token = crate::auth::generate_jwt("bob", secret).unwrap();
+    #[tokio::test]
+    async fn metrics_accessible_without_auth_when_disabled() {
+        let mut config: BeamConfig = toml::from_str("").expect("default config");
+        config.server.metrics_require_auth = false;
+        let state = test_app_state_with(config);
 
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "authorization",
-            format!("Bearer {header_token}").parse().unwrap(),
-        );
-        let query = WsQuery {
-            token: Some(query_token),
-        };
+        let app = build_router(state);
 
-        // Header should take precedence
-        let claims = extract_claims_from_headers(&headers, &query, secret).unwrap();
-        assert_eq!(claims.sub, "alice");
-    }
+        let request = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
 
-    #[test]
-    fn extract_claims_rejects_missing_token() {
-        let headers = HeaderMap::new();
-        let query = WsQuery { token: None };
-        let result = extract_claims_from_headers(&headers, &query, "secret");
-        assert!(result.is_err());
-    }
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-    #[test]
-    fn extract_claims_rejects_invalid_token() {
-        let headers = HeaderMap::new();
-        let query = WsQuery {
-            token: Some("invalid.token.here".to_string()),
-        };
-        let result = extract_claims_from_headers(&headers, &query, "secret");
-        assert!(result.is_err());
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&bytes).unwrap();
+        assert!(body.contains("beam_active_sessions"));
     }
 
-    #[test]
-    fn username_validation_rejects_empty() {
-        assert!(!is_valid_username(""));
+    // --- Mock agent session tests ---
+    //
+    // These serve the router on a loopback port so a mock agent and a
+    // browser can hold real WebSockets, and drive a session end to end
+    // without X11 or GStreamer.
+
+    use beam_mockagent::MockAgent;
+    use beam_protocol::{AgentCommand, InputEvent, VideoFrameHeader};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    type BrowserSocket = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    const WAIT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    /// A registered session on a running server, with no agent yet.
+    struct LiveSession {
+        state: Arc<AppState>,
+        addr: std::net::SocketAddr,
+        id: Uuid,
+        agent_token: String,
     }
 
-    #[test]
-    fn username_validation_rejects_too_long() {
-        let long = "a".repeat(65);
-        assert!(!is_valid_username(&long));
-    }
+    impl LiveSession {
+        async fn start() -> Self {
+            Self::start_with(toml::from_str("").expect("default config")).await
+        }
 
-    #[test]
-    fn username_validation_rejects_invalid_chars() {
-        assert!(!is_valid_username("user name")); // space
-        assert!(!is_valid_username("user@host")); // @
-        assert!(!is_valid_username("user/root")); // path traversal
-        assert!(!is_valid_username("user\x00")); // null byte
-        assert!(!is_valid_username("user;id")); // shell injection
-    }
+        async fn start_with(config: BeamConfig) -> Self {
+            let state = test_app_state_with(config);
+            let req: AuthRequest =
+                serde_json::from_value(json!({ "username": "testuser", "password": "" })).unwrap();
+            let (info, agent_token) = state
+                .session_manager
+                .reserve_session(&req, None, 10)
+                .await
+                .unwrap();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let app = build_router(Arc::clone(&state));
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Self {
+                state,
+                addr,
+                id: info.id,
+                agent_token,
+            }
+        }
 
-    #[test]
-    fn username_validation_accepts_valid() {
-        assert!(is_valid_username("alice"));
-        assert!(is_valid_username("bob_smith"));
-        assert!(is_valid_username("user-123"));
-        assert!(is_valid_username("test.user"));
-        assert!(is_valid_username("A"));
-        assert!(is_valid_username(&"a".repeat(64))); // exactly 64 chars
-    }
+        async fn agent(&self) -> MockAgent {
+            MockAgent::connect(
+                &format!("ws://{}", self.addr),
+                self.id,
+                &self.agent_token,
+                Default::default(),
+            )
+            .await
+            .expect("mock agent should connect")
+        }
 
-    // --- HTTP-level integration tests ---
-    //
-    // These use `tower::ServiceExt::oneshot` to send requests through the axum
-    // router without starting a real HTTP server or TLS listener.
+        async fn browser(&self) -> BrowserSocket {
+            let jwt = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+            let url = format!("ws://{}/api/sessions/{}/ws?token={jwt}", self.addr, self.id);
+            let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            ws
+        }
 
-    use axum::body::Body;
-    use axum::http::Request;
-    use http_body_util::BodyExt;
-    use tower::ServiceExt;
+        async fn release(&self, token: &str) -> StatusCode {
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/api/sessions/{}/release", self.id))
+                .body(Body::from(token.to_string()))
+                .unwrap();
+            let response = build_router(Arc::clone(&self.state))
+                .oneshot(request)
+                .await
+                .unwrap();
+            response.status()
+        }
+    }
 
-    const TEST_JWT_SECRET: &str = "test-secret-for-integration-tests";
+    /// Read the browser socket until a keyframe arrives.
+    async fn next_keyframe(browser: &mut BrowserSocket) -> (VideoFrameHeader, Vec<u8>) {
+        loop {
+            let msg = tokio::time::timeout(WAIT, browser.next())
+                .await
+                .expect("no keyframe in time")
+                .expect("browser socket closed")
+                .unwrap();
+            if let WsMessage::Binary(data) = msg {
+                let header = VideoFrameHeader::deserialize(&data).unwrap();
+                if header.is_keyframe() {
+                    return (header, data[beam_protocol::FRAME_HEADER_SIZE..].to_vec());
+                }
+            }
+        }
+    }
 
-    /// Build a test `AppState` with defaults suitable for unit/integration tests.
-    fn test_app_state() -> Arc<AppState> {
-        let config: BeamConfig = toml::from_str("").expect("default config");
-        let session_manager = crate::session::SessionManager::new(
-            100, // display_start (high to avoid conflicts)
-            1920,
-            1080,
-            None,
-            beam_protocol::VideoConfig::default(),
-        );
-        Arc::new(AppState {
-            config,
-            session_manager,
-            channels: crate::signaling::new_channel_registry(),
-            jwt_secret: TEST_JWT_SECRET.to_string(),
-            login_limiter: LoginRateLimiter::new(5, 60),
-            ip_limiter: LoginRateLimiter::new(20, 60),
-            release_limiter: LoginRateLimiter::new(10, 60),
-            started_at: std::time::Instant::now(),
-            metrics_logins_attempted: std::sync::atomic::AtomicU64::new(0),
-            metrics_logins_failed: std::sync::atomic::AtomicU64::new(0),
-            metrics_agent_restarts: std::sync::atomic::AtomicU64::new(0),
-        })
+    /// Read the browser socket until the server sends an error message.
+    async fn next_error(browser: &mut BrowserSocket) -> String {
+        loop {
+            let msg = tokio::time::timeout(WAIT, browser.next())
+                .await
+                .expect("no error message in time")
+                .expect("browser socket closed")
+                .unwrap();
+            if let WsMessage::Text(text) = msg {
+                let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if json["type"] == "error" {
+                    return json["message"].as_str().unwrap().to_string();
+                }
+            }
+        }
     }
 
-    /// Helper: parse a response body as `serde_json::Value`.
-    async fn body_json(response: axum::response::Response<Body>) -> serde_json::Value {
-        let bytes = response
-            .into_body()
-            .collect()
+    #[tokio::test]
+    async fn mock_agent_streams_to_browser_and_receives_input() {
+        let session = LiveSession::start().await;
+        let mut agent = session.agent().await;
+        let mut browser = session.browser().await;
+
+        let (header, payload) = next_keyframe(&mut browser).await;
+        assert_eq!((header.width, header.height), (320, 240));
+        // Annex B, starting with the SPS
+        assert_eq!(payload[..5], [0, 0, 0, 1, 0x67]);
+
+        let input = serde_json::to_string(&InputEvent::MouseMove { x: 0.5, y: 0.25 }).unwrap();
+        browser.send(WsMessage::Text(input.into())).await.unwrap();
+        agent
+            .wait_for(WAIT, |cmd| {
+                matches!(cmd, AgentCommand::Input(InputEvent::MouseMove { x, y }) if *x == 0.5 && *y == 0.25)
+            })
             .await
-            .expect("failed to read response body")
-            .to_bytes();
-        serde_json::from_slice(&bytes).expect("response body is not valid JSON")
+            .unwrap();
     }
 
     #[tokio::test]
-    async fn health_returns_ok_unauthenticated() {
-        let state = test_app_state();
-        let app = build_router(state);
+    async fn agent_needs_the_session_token() {
+        let session = LiveSession::start().await;
+        let server = format!("ws://{}", session.addr);
+        let wrong_token =
+            MockAgent::connect(&server, session.id, "not-the-token", Default::default());
+        assert!(wrong_token.await.is_err());
+        let wrong_session = MockAgent::connect(
+            &server,
+            Uuid::new_v4(),
+            &session.agent_token,
+            Default::default(),
+        );
+        assert!(wrong_session.await.is_err());
+    }
 
-        let request = Request::builder()
-            .uri("/api/health")
-            .body(Body::empty())
-            .unwrap();
+    #[tokio::test]
+    async fn agent_tickets_work_once() {
+        let session = LiveSession::start().await;
+        let server = format!("ws://{}", session.addr);
+        let url = |token: &str| format!("{server}/ws/agent/{}?token={token}", session.id);
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        // Only agents restored from before tickets may still use the
+        // long-lived token; a session spawned by this server may not
+        assert!(
+            tokio_tungstenite::connect_async(url(&session.agent_token))
+                .await
+                .is_err()
+        );
+        assert!(
+            tokio_tungstenite::connect_async(url("not-a-token"))
+                .await
+                .is_err()
+        );
 
-        let json = body_json(response).await;
-        assert_eq!(json["status"], "ok");
+        let ticket = beam_mockagent::handshake(&server, session.id, &session.agent_token)
+            .await
+            .unwrap();
+        assert_eq!(ticket.expires_in, crate::auth::AGENT_TICKET_SECS);
+        let (_agent, _) = tokio_tungstenite::connect_async(url(&ticket.token))
+            .await
+            .expect("fresh ticket should connect");
+        // Replayed from a proxy log
+        assert!(
+            tokio_tungstenite::connect_async(url(&ticket.token))
+                .await
+                .is_err()
+        );
+
+        // Each handshake voids the ticket before it
+        let first = beam_mockagent::handshake(&server, session.id, &session.agent_token)
+            .await
+            .unwrap();
+        let second = beam_mockagent::handshake(&server, session.id, &session.agent_token)
+            .await
+            .unwrap();
+        assert!(
+            tokio_tungstenite::connect_async(url(&first.token))
+                .await
+                .is_err()
+        );
+        assert!(
+            tokio_tungstenite::connect_async(url(&second.token))
+                .await
+                .is_ok()
+        );
     }
 
     #[tokio::test]
-    async fn health_detailed_requires_auth() {
-        let state = test_app_state();
-        let app = build_router(state);
+    async fn second_browser_replaces_the_first() {
+        let session = LiveSession::start().await;
+        let _agent = session.agent().await;
+        let mut first = session.browser().await;
+        next_keyframe(&mut first).await;
+
+        let mut second = session.browser().await;
+        assert_eq!(next_error(&mut first).await, "replaced");
+        // The newcomer asks the agent for a fresh keyframe
+        next_keyframe(&mut second).await;
+    }
 
-        let request = Request::builder()
-            .uri("/api/health/detailed")
-            .body(Body::empty())
+    #[tokio::test]
+    async fn login_asks_before_taking_over_a_connected_browser() {
+        let session = LiveSession::start().await;
+        let _agent = session.agent().await;
+        let info = session
+            .state
+            .session_manager
+            .get_session(session.id)
+            .await
             .unwrap();
+        let channel =
+            crate::signaling::get_or_create_channel(&session.state.channels, session.id).await;
+        assert_eq!(take_over_browser(&channel, &info, false), None);
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let mut first = session.browser().await;
+        next_keyframe(&mut first).await;
+        let in_use = take_over_browser(&channel, &info, false).unwrap();
+        assert_eq!(in_use["in_use"], true);
+
+        assert_eq!(take_over_browser(&channel, &info, true), None);
+        assert_eq!(next_error(&mut first).await, "replaced");
     }
 
     #[tokio::test]
-    async fn health_detailed_with_valid_jwt() {
-        let state = test_app_state();
-        let app = build_router(state);
+    async fn share_link_viewer_watches_until_revoked() {
+        let session = LiveSession::start_with(
+            toml::from_str("[session]\nshare_links = true").expect("share config"),
+        )
+        .await;
+        let _agent = session.agent().await;
+        let mut owner = session.browser().await;
+        next_keyframe(&mut owner).await;
 
-        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let jwt = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/api/sessions/{}/share", session.id))
+            .header("authorization", format!("Bearer {jwt}"))
+            .body(Body::from(r#"{"ttl_secs":600}"#))
+            .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let share = body_json(response).await;
+        let token = share["token"].as_str().unwrap();
+
+        let url = format!("ws://{}/api/share/{token}/ws", session.addr);
+        let (mut viewer, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        next_keyframe(&mut viewer).await;
+        // Input from a viewer goes nowhere, and the owner stays connected
+        let input = serde_json::to_string(&InputEvent::MouseMove { x: 0.5, y: 0.5 }).unwrap();
+        viewer.send(WsMessage::Text(input.into())).await.unwrap();
+        assert!(
+            session.state.channels.read().await[&session.id]
+                .browser_link
+                .connected_secs()
+                .is_some()
+        );
 
         let request = Request::builder()
-            .uri("/api/health/detailed")
-            .header("authorization", format!("Bearer {token}"))
+            .method("DELETE")
+            .uri(format!("/api/sessions/{}/share", session.id))
+            .header("authorization", format!("Bearer {jwt}"))
             .body(Body::empty())
             .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(body_json(response).await["revoked"], 1);
+        assert_eq!(next_error(&mut viewer).await, "share_ended");
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-
-        let json = body_json(response).await;
-        assert_eq!(json["status"], "ok");
-        assert!(json["version"].is_string(), "expected version string");
-        assert!(json["uptime_secs"].is_number(), "expected uptime number");
-        assert!(json["sessions"].is_number(), "expected sessions count");
+        let url = format!("ws://{}/api/share/{token}/ws", session.addr);
+        assert!(tokio_tungstenite::connect_async(url).await.is_err());
     }
 
     #[tokio::test]
-    async fn list_sessions_requires_auth() {
-        let state = test_app_state();
-        let app = build_router(state);
-
-        let request = Request::builder()
-            .uri("/api/sessions")
-            .body(Body::empty())
+    async fn admin_event_stream_follows_sessions() {
+        let session = LiveSession::start_with(
+            toml::from_str("[server]\nadmin_users = [\"admin\"]").expect("admin config"),
+        )
+        .await;
+        let url = |user: &str| {
+            let jwt = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            format!("ws://{}/api/admin/events/ws?token={jwt}", session.addr)
+        };
+        assert!(
+            tokio_tungstenite::connect_async(url("testuser"))
+                .await
+                .is_err()
+        );
+        let (mut events, _) = tokio_tungstenite::connect_async(url("admin"))
+            .await
             .unwrap();
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let _browser = session.browser().await;
+        // Other tests' sessions share the feed
+        let connected = loop {
+            let msg = tokio::time::timeout(WAIT, events.next())
+                .await
+                .expect("no event in time")
+                .expect("event stream closed")
+                .unwrap();
+            if let WsMessage::Text(text) = msg {
+                let event: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if event["session_id"] == json!(session.id) {
+                    break event;
+                }
+            }
+        };
+        assert_eq!(connected["event"], "connected");
+        assert_eq!(connected["peer"], "browser");
+        assert!(connected["at_ms"].as_u64().unwrap() > 0);
     }
 
     #[tokio::test]
-    async fn list_sessions_with_valid_jwt() {
-        let state = test_app_state();
-        let app = build_router(state);
+    async fn bad_browser_messages_are_rejected_and_counted() {
+        let session = LiveSession::start().await;
+        let _agent = session.agent().await;
+        let mut browser = session.browser().await;
+        next_keyframe(&mut browser).await;
+
+        let text = "a".repeat(beam_protocol::MAX_CLIPBOARD_BYTES + 1);
+        let oversized = serde_json::to_string(&InputEvent::Clipboard { text }).unwrap();
+        browser
+            .send(WsMessage::Text(oversized.into()))
+            .await
+            .unwrap();
+        assert!(
+            next_error(&mut browser)
+                .await
+                .starts_with("Message too large")
+        );
+        browser
+            .send(WsMessage::Text(r#"{"t":"k","c":"thirty"}"#.into()))
+            .await
+            .unwrap();
+        assert!(
+            next_error(&mut browser)
+                .await
+                .starts_with("Invalid message format")
+        );
 
-        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let channel = &session.state.channels.read().await[&session.id];
+        assert_eq!(
+            channel.rejected.snapshot(),
+            json!({ "malformed": 1, "too_large": 1 })
+        );
+        let body = rejected_message_metrics(&[(session.id, 1, 1)]);
+        assert!(body.contains(&format!(
+            "beam_rejected_messages_total{{session_id=\"{}\",reason=\"too_large\"}} 1",
+            session.id
+        )));
+    }
 
-        let request = Request::builder()
-            .uri("/api/sessions")
-            .header("authorization", format!("Bearer {token}"))
-            .body(Body::empty())
+    #[tokio::test]
+    async fn stream_alerts_are_counted_while_a_browser_watches() {
+        use beam_protocol::{StreamAlert, StreamAlertKind};
+        let session = LiveSession::start().await;
+        let server = format!("ws://{}", session.addr);
+        let ticket = beam_mockagent::handshake(&server, session.id, &session.agent_token)
+            .await
             .unwrap();
+        let url = format!("{server}/ws/agent/{}?token={}", session.id, ticket.token);
+        let (mut agent, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let alert = |kind, active, secs| {
+            let alert = StreamAlert {
+                kind,
+                active,
+                secs,
+                recover: false,
+            };
+            WsMessage::Text(serde_json::to_string(&alert).unwrap().into())
+        };
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        // Nothing acks video with no browser attached; the hello's answer
+        // shows the server has handled the alert before it
+        agent
+            .send(alert(StreamAlertKind::NotDelivered, true, 5))
+            .await
+            .unwrap();
+        let hello = serde_json::to_string(&beam_protocol::Hello::new(&[])).unwrap();
+        agent.send(WsMessage::Text(hello.into())).await.unwrap();
+        let reply = tokio::time::timeout(WAIT, agent.next()).await.unwrap();
+        assert!(matches!(reply, Some(Ok(WsMessage::Text(_)))));
+
+        let channel = signaling::get_or_create_channel(&session.state.channels, session.id).await;
+        let mut browser = session.browser().await;
+        while channel.browser_link.connected_secs().is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        for msg in [
+            // Its end isn't reported either
+            alert(StreamAlertKind::NotDelivered, false, 9),
+            alert(StreamAlertKind::CaptureStalled, true, 5),
+            alert(StreamAlertKind::CaptureStalled, true, 5),
+            alert(StreamAlertKind::CaptureStalled, false, 7),
+        ] {
+            agent.send(msg).await.unwrap();
+        }
+        // Relayed to the browser once handled
+        loop {
+            let msg = tokio::time::timeout(WAIT, browser.next())
+                .await
+                .expect("alert relayed in time")
+                .unwrap()
+                .unwrap();
+            if let WsMessage::Text(text) = msg
+                && text.contains(r#""secs":7"#)
+            {
+                break;
+            }
+        }
 
-        let json = body_json(response).await;
-        assert!(json.is_array(), "expected JSON array of sessions");
-        // No sessions created, so the array should be empty
-        assert_eq!(json.as_array().unwrap().len(), 0);
+        let count = |kind| {
+            channel
+                .stream_alerts
+                .count(kind)
+                .load(std::sync::atomic::Ordering::Relaxed)
+        };
+        assert_eq!(count(StreamAlertKind::NotDelivered), 0);
+        assert_eq!(count(StreamAlertKind::CaptureStalled), 1);
+        let events: Vec<_> = channel
+            .timeline
+            .entries()
+            .into_iter()
+            .map(|e| e.event)
+            .filter(|e| {
+                matches!(
+                    e,
+                    SessionEvent::StreamStalled { .. } | SessionEvent::StreamResumed { .. }
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                SessionEvent::StreamStalled {
+                    kind: StreamAlertKind::CaptureStalled
+                },
+                SessionEvent::StreamResumed {
+                    kind: StreamAlertKind::CaptureStalled,
+                    secs: 7
+                },
+            ]
+        );
+        let body = stream_alert_metrics(&[(session.id, StreamAlertKind::CaptureStalled, 1)]);
+        assert!(body.contains(&format!(
+            "beam_stream_alerts_total{{session_id=\"{}\",kind=\"capture_stalled\"}} 1",
+            session.id
+        )));
     }
 
     #[tokio::test]
-    async fn login_returns_401_for_invalid_creds() {
-        let state = test_app_state();
-        let app = build_router(state);
-
-        let body = serde_json::json!({
-            "username": "nonexistent",
-            "password": "wrongpassword"
-        });
-
+    async fn share_links_are_off_by_default() {
+        let session = LiveSession::start().await;
+        let jwt = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
         let request = Request::builder()
             .method("POST")
-            .uri("/api/auth/login")
-            .header("content-type", "application/json")
-            .body(Body::from(serde_json::to_vec(&body).unwrap()))
+            .uri(format!("/api/sessions/{}/share", session.id))
+            .header("authorization", format!("Bearer {jwt}"))
+            .body(Body::empty())
             .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        let response = app.oneshot(request).await.unwrap();
-        // PAM auth will fail (no such user) — expect 401 or 504 (timeout)
-        // depending on PAM backend speed. Either way, NOT 200.
-        let status = response.status();
-        assert!(
-            status == StatusCode::UNAUTHORIZED
-                || status == StatusCode::GATEWAY_TIMEOUT
-                || status == StatusCode::INTERNAL_SERVER_ERROR,
-            "expected auth failure status, got {status}"
-        );
-
-        let json = body_json(response).await;
-        assert!(json["error"].is_string(), "expected error message in body");
+        let other = crate::auth::generate_jwt("mallory", TEST_JWT_SECRET).unwrap();
+        let request = Request::builder()
+            .method("GET")
+            .uri(format!("/api/sessions/{}/share", session.id))
+            .header("authorization", format!("Bearer {other}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
-    async fn invalid_jwt_rejected() {
-        let state = test_app_state();
-        let app = build_router(state);
+    async fn stats_history_is_for_the_owner_and_admins() {
+        let session = LiveSession::start_with(
+            toml::from_str("[server]\nadmin_users = [\"root\"]").expect("admin config"),
+        )
+        .await;
+        let channel = signaling::get_or_create_channel(&session.state.channels, session.id).await;
+        let quality = beam_protocol::NetworkQuality {
+            level: beam_protocol::QualityLevel::Poor,
+            rtt_ms: Some(250.0),
+            loss_pct: 8.0,
+            kbps: 900,
+            target_kbps: 4000,
+        };
+        channel.stats_history.record(
+            crate::stats_history::StatsSample::new(&quality, None, None),
+            600,
+        );
 
-        // Generate a JWT signed with a different secret
-        let wrong_token =
-            crate::auth::generate_jwt("testuser", "completely-different-secret").unwrap();
+        let get = |user: &str, query: &str| {
+            let jwt = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            let request = Request::builder()
+                .uri(format!("/api/sessions/{}/stats/history{query}", session.id))
+                .header("authorization", format!("Bearer {jwt}"))
+                .body(Body::empty())
+                .unwrap();
+            build_router(Arc::clone(&session.state)).oneshot(request)
+        };
 
-        let request = Request::builder()
-            .uri("/api/sessions")
-            .header("authorization", format!("Bearer {wrong_token}"))
-            .body(Body::empty())
-            .unwrap();
+        let response = get("testuser", "").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let history = body_json(response).await;
+        assert_eq!(history["keep_secs"], 600);
+        assert_eq!(history["samples"][0]["level"], "poor");
+        assert_eq!(history["samples"][0]["kbps"], 900);
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = get("root", "?since=99999999999").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["samples"], json!([]));
+
+        let response = get("mallory", "").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
-    async fn username_validation_rejects_bad_input() {
-        let bad_usernames = vec![
-            "../etc/passwd", // path traversal
-            "user\x00admin", // null byte
-            "user name",     // space
-            "user;id",       // shell injection
-            "",              // empty
-        ];
-
-        for bad_username in bad_usernames {
-            let state = test_app_state();
-            let app = build_router(state);
+    async fn admin_sees_the_session_timeline() {
+        let session = LiveSession::start_with(
+            toml::from_str("[server]\nadmin_users = [\"root\"]").expect("admin config"),
+        )
+        .await;
+        let agent = session.agent().await;
+        let mut browser = session.browser().await;
+        next_keyframe(&mut browser).await;
+        agent.kill();
 
-            let body = serde_json::json!({
-                "username": bad_username,
-                "password": "anything"
-            });
+        let get = |user: &str| {
+            let jwt = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            let request = Request::builder()
+                .uri(format!("/api/admin/sessions/{}/events", session.id))
+                .header("authorization", format!("Bearer {jwt}"))
+                .body(Body::empty())
+                .unwrap();
+            build_router(Arc::clone(&session.state)).oneshot(request)
+        };
+        let response = get("testuser").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // The agent's disconnect lands once the server notices the socket is gone
+        let mut events = Vec::new();
+        for _ in 0..50 {
+            let response = get("root").await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            events = body_json(response).await["events"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{} {}",
+                        e["event"].as_str().unwrap(),
+                        e["peer"].as_str().unwrap_or("")
+                    )
+                })
+                .collect::<Vec<_>>();
+            if events.len() >= 4 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(
+            events,
+            [
+                "connected agent",
+                "connected browser",
+                "first_frame ",
+                "disconnected agent"
+            ]
+        );
+    }
 
+    #[tokio::test]
+    async fn admin_thumbnail_needs_the_policy_and_a_frame() {
+        let config = "[server]\nadmin_users = [\"root\"]\n[session]\nthumbnail_secs = 30";
+        let session = LiveSession::start_with(toml::from_str(config).expect("config")).await;
+        let get = |live: &LiveSession, user: &str| {
+            let jwt = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
             let request = Request::builder()
-                .method("POST")
-                .uri("/api/auth/login")
-                .header("content-type", "application/json")
-                .body(Body::from(serde_json::to_vec(&body).unwrap()))
+                .uri(format!("/api/admin/sessions/{}/thumbnail", live.id))
+                .header("authorization", format!("Bearer {jwt}"))
+                .body(Body::empty())
                 .unwrap();
+            build_router(Arc::clone(&live.state)).oneshot(request)
+        };
 
-            let response = app.oneshot(request).await.unwrap();
-            assert_eq!(
-                response.status(),
-                StatusCode::BAD_REQUEST,
-                "username {bad_username:?} should be rejected with 400"
-            );
+        let response = get(&session, "root").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let jpeg = bytes::Bytes::from_static(&[0xFF, 0xD8, 0xFF, 0xD9]);
+        let channel = signaling::get_or_create_channel(&session.state.channels, session.id).await;
+        *channel.thumbnail.write().unwrap() = Some(signaling::SessionThumbnail {
+            at: 1_700_000_000,
+            jpeg: jpeg.clone(),
+        });
+        let response = get(&session, "testuser").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = get(&session, "root").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/jpeg");
+        assert_eq!(response.headers()["x-thumbnail-at"], "1700000000");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, jpeg);
+
+        // Off by default, whatever an agent sends
+        let disabled = LiveSession::start_with(
+            toml::from_str("[server]\nadmin_users = [\"root\"]").expect("admin config"),
+        )
+        .await;
+        let response = get(&disabled, "root").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 
-            let json = body_json(response).await;
-            assert_eq!(json["error"], "Invalid username");
+    #[tokio::test]
+    async fn admin_list_shows_what_the_agent_streams() {
+        let config = "[server]\nadmin_users = [\"root\"]";
+        let session = LiveSession::start_with(toml::from_str(config).expect("config")).await;
+        async fn list(state: &Arc<AppState>) -> serde_json::Value {
+            let jwt = crate::auth::generate_jwt("root", TEST_JWT_SECRET).unwrap();
+            let request = Request::builder()
+                .uri("/api/admin/sessions")
+                .header("authorization", format!("Bearer {jwt}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = build_router(Arc::clone(state))
+                .oneshot(request)
+                .await
+                .unwrap();
+            body_json(response).await
         }
+        assert!(list(&session.state).await[0]["agent_info"].is_null());
+
+        let server = format!("ws://{}", session.addr);
+        let ticket = beam_mockagent::handshake(&server, session.id, &session.agent_token)
+            .await
+            .unwrap();
+        let url = format!("{server}/ws/agent/{}?token={}", session.id, ticket.token);
+        let (mut agent, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let info = beam_protocol::AgentInfo {
+            version: "0.4.2".into(),
+            encoder: "x264enc".into(),
+            fallback: true,
+            capture: "x11-shm+damage".into(),
+            width: 1920,
+            height: 1080,
+            framerate: 60,
+        };
+        agent
+            .send(WsMessage::Text(
+                serde_json::to_string(&info).unwrap().into(),
+            ))
+            .await
+            .unwrap();
+        // The hello's answer shows the server has handled the report
+        let hello = serde_json::to_string(&beam_protocol::Hello::new(&[])).unwrap();
+        agent.send(WsMessage::Text(hello.into())).await.unwrap();
+        let reply = tokio::time::timeout(WAIT, agent.next()).await.unwrap();
+        assert!(matches!(reply, Some(Ok(WsMessage::Text(_)))));
+
+        let reported = &list(&session.state).await[0]["agent_info"];
+        assert_eq!(reported["encoder"], "x264enc");
+        assert_eq!(reported["fallback"], true);
+        assert_eq!(reported["capture"], "x11-shm+damage");
+        assert_eq!(reported["width"], 1920);
+        assert_eq!(reported["framerate"], 60);
+        assert_eq!(reported["version"], "0.4.2");
     }
 
     #[tokio::test]
-    async fn login_rejects_idle_timeout_too_low() {
-        let state = test_app_state();
-        let app = build_router(state);
-
-        let body = serde_json::json!({
-            "username": "testuser",
-            "password": "password",
-            "idle_timeout": 59
-        });
+    async fn agents_of_other_releases_are_flagged_or_refused() {
+        let config = "[server]\nadmin_users = [\"root\"]";
+        let session = LiveSession::start_with(toml::from_str(config).expect("config")).await;
+        let server = format!("ws://{}", session.addr);
+        let connect = |release: String| {
+            let server = server.clone();
+            let (id, token) = (session.id, session.agent_token.clone());
+            async move {
+                let ticket = beam_mockagent::handshake(&server, id, &token)
+                    .await
+                    .unwrap();
+                let url = format!("{server}/ws/agent/{id}?token={}", ticket.token);
+                let (mut agent, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+                let hello = beam_protocol::Hello {
+                    release: Some(release),
+                    ..beam_protocol::Hello::new(&[])
+                };
+                let hello = serde_json::to_string(&hello).unwrap();
+                agent.send(WsMessage::Text(hello.into())).await.unwrap();
+                let reply = tokio::time::timeout(WAIT, agent.next()).await.unwrap();
+                let Some(Ok(WsMessage::Text(reply))) = reply else {
+                    panic!("expected a text reply, got {reply:?}");
+                };
+                let reply = serde_json::from_str::<serde_json::Value>(&reply).unwrap();
+                (agent, reply)
+            }
+        };
+        let mut parts = beam_protocol::RELEASE
+            .split('.')
+            .map(|p| p.parse::<u64>().unwrap());
+        let (major, minor) = (parts.next().unwrap(), parts.next().unwrap());
+
+        // Another major release is told why and turned away
+        let (_, reply) = connect(format!("{}.0.0", major + 1)).await;
+        assert_eq!(reply["type"], "error");
+        assert!(
+            reply["message"]
+                .as_str()
+                .unwrap()
+                .contains("is incompatible with server"),
+            "{reply}"
+        );
 
+        // Too many minor releases away is only flagged
+        let (_agent, reply) = connect(format!("{major}.{}.0", minor + 5)).await;
+        assert_eq!(reply["t"], "hello");
+        assert_eq!(reply["release"], beam_protocol::RELEASE);
+        let jwt = crate::auth::generate_jwt("root", TEST_JWT_SECRET).unwrap();
         let request = Request::builder()
-            .method("POST")
-            .uri("/api/auth/login")
-            .header("content-type", "application/json")
-            .body(Body::from(serde_json::to_vec(&body).unwrap()))
+            .uri("/api/admin/sessions")
+            .header("authorization", format!("Bearer {jwt}"))
+            .body(Body::empty())
             .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        let list = body_json(response).await;
+        assert_eq!(list[0]["version_skew"], "beyond");
+        assert_eq!(
+            list[0]["agent"]["release"],
+            format!("{major}.{}.0", minor + 5)
+        );
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-
-        let json = body_json(response).await;
-        assert!(json["error"].as_str().unwrap().contains("idle_timeout"));
+        let request = Request::builder()
+            .uri("/api/health/detailed")
+            .header("authorization", format!("Bearer {jwt}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        let health = body_json(response).await;
+        assert_eq!(health["agents"][0]["sessions"], 1);
+        assert_eq!(health["agents"][0]["version_skew"], "beyond");
     }
 
     #[tokio::test]
-    async fn login_rejects_idle_timeout_too_high() {
-        let state = test_app_state();
-        let app = build_router(state);
-
-        let body = serde_json::json!({
-            "username": "testuser",
-            "password": "password",
-            "idle_timeout": 86401
+    async fn private_sessions_hide_from_admins() {
+        let config = "[server]\nadmin_users = [\"root\"]\n[session]\nthumbnail_secs = 30";
+        let session = LiveSession::start_with(toml::from_str(config).expect("config")).await;
+        let channel = signaling::get_or_create_channel(&session.state.channels, session.id).await;
+        *channel.thumbnail.write().unwrap() = Some(signaling::SessionThumbnail {
+            at: 1_700_000_000,
+            jpeg: bytes::Bytes::from_static(&[0xFF, 0xD8, 0xFF, 0xD9]),
         });
+        let send = |method: &str, uri: String, user: &str, body: &'static str| {
+            let jwt = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", format!("Bearer {jwt}"))
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            build_router(Arc::clone(&session.state)).oneshot(request)
+        };
+        let privacy = format!("/api/sessions/{}/privacy", session.id);
+        let thumbnail = format!("/api/admin/sessions/{}/thumbnail", session.id);
 
-        let request = Request::builder()
-            .method("POST")
-            .uri("/api/auth/login")
-            .header("content-type", "application/json")
-            .body(Body::from(serde_json::to_vec(&body).unwrap()))
+        let response = send("PUT", privacy.clone(), "root", r#"{"private":true}"#)
+            .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = send("PUT", privacy.clone(), "testuser", r#"{"private":true}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send("GET", privacy.clone(), "testuser", "").await.unwrap();
+        let body = body_json(response).await;
+        assert_eq!(body["private"], true);
+        assert_eq!(body["required"], false);
+        // Kept with the session for a restart
+        assert!(session.state.session_manager.is_private(session.id).await);
+
+        let response = send("GET", thumbnail.clone(), "root", "").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            body_json(response).await["error"],
+            "This session is private"
+        );
+        // The old screenshot is gone, not just hidden
+        assert!(channel.thumbnail.read().unwrap().is_none());
+        let response = send("GET", "/api/admin/sessions".into(), "root", "")
+            .await
+            .unwrap();
+        let list = body_json(response).await;
+        assert_eq!(list[0]["private"], true);
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-
-        let json = body_json(response).await;
-        assert!(json["error"].as_str().unwrap().contains("idle_timeout"));
+        let response = send("PUT", privacy, "testuser", r#"{"private":false}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!session.state.session_manager.is_private(session.id).await);
+        let response = send("GET", thumbnail, "root", "").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn security_headers_present_on_responses() {
-        let state = test_app_state();
-        let app = build_router(state);
+    async fn privacy_policy_cannot_be_turned_off_by_the_owner() {
+        let config = "[server]\nadmin_users = [\"root\"]\n[session]\nthumbnail_secs = 30\n\
+                      [session.privacy_users]\ntestuser = true";
+        let session = LiveSession::start_with(toml::from_str(config).expect("config")).await;
+        let jwt = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let request = Request::builder()
+            .method("PUT")
+            .uri(format!("/api/sessions/{}/privacy", session.id))
+            .header("authorization", format!("Bearer {jwt}"))
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"private":false}"#))
+            .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
+        let jwt = crate::auth::generate_jwt("root", TEST_JWT_SECRET).unwrap();
         let request = Request::builder()
-            .uri("/api/health")
+            .uri(format!("/api/admin/sessions/{}/thumbnail", session.id))
+            .header("authorization", format!("Bearer {jwt}"))
             .body(Body::empty())
             .unwrap();
+        let response = build_router(Arc::clone(&session.state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+    #[tokio::test]
+    async fn agent_reconnect_resumes_the_stream() {
+        let session = LiveSession::start().await;
+        let agent = session.agent().await;
+        let mut browser = session.browser().await;
+        next_keyframe(&mut browser).await;
+
+        agent.kill();
+        let _agent = session.agent().await;
+        next_keyframe(&mut browser).await;
+    }
 
-        let headers = response.headers();
+    #[tokio::test]
+    async fn browser_reconnect_cancels_grace_period() {
+        let session = LiveSession::start().await;
+        let manager = &session.state.session_manager;
+        let _agent = session.agent().await;
+        let mut browser = session.browser().await;
+        next_keyframe(&mut browser).await;
+        browser.close(None).await.unwrap();
+        let connected = manager.grace_generation(session.id).await.unwrap();
 
         assert_eq!(
-            headers
-                .get("strict-transport-security")
-                .map(|v| v.as_bytes()),
-            Some(b"max-age=63072000; includeSubDomains".as_slice()),
-            "missing or wrong Strict-Transport-Security"
+            session.release("not-the-token").await,
+            StatusCode::UNAUTHORIZED
         );
+        assert_eq!(manager.grace_generation(session.id).await, Some(connected));
+
+        let token = manager.get_release_token(session.id).await.unwrap();
+        assert_eq!(session.release(&token).await, StatusCode::OK);
+        let timer = manager.grace_generation(session.id).await.unwrap();
+        assert_eq!(timer, connected + 1);
+
+        // The pending cleanup sees a newer generation and leaves the session
+        let mut browser = session.browser().await;
+        assert!(manager.grace_generation(session.id).await.unwrap() > timer);
+        next_keyframe(&mut browser).await;
+        assert!(manager.get_session(session.id).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn guest_sessions_need_an_admin_or_the_guest_token() {
+        async fn post(state: &Arc<AppState>, uri: &str, bearer: Option<&str>) -> StatusCode {
+            let mut request = Request::builder().method("POST").uri(uri);
+            if let Some(bearer) = bearer {
+                request = request.header("authorization", format!("Bearer {bearer}"));
+            }
+            build_router(Arc::clone(state))
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+                .status()
+        }
+        let user = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
+        let admin = crate::auth::generate_jwt("root", TEST_JWT_SECRET).unwrap();
+
+        let config = "[server]\nadmin_users = [\"root\"]";
+        let state = test_app_state_with(toml::from_str(config).unwrap());
         assert_eq!(
-            headers.get("x-content-type-options").map(|v| v.as_bytes()),
-            Some(b"nosniff".as_slice()),
-            "missing or wrong X-Content-Type-Options"
+            post(&state, "/api/guest", Some(&admin)).await,
+            StatusCode::NOT_FOUND
         );
+
+        let config = "[server]\nadmin_users = [\"root\"]\n\
+                      [guest]\nenabled = true\ntoken = \"kiosk-0123456789abcdef\"";
+        let state = test_app_state_with(toml::from_str(config).unwrap());
         assert_eq!(
-            headers.get("x-frame-options").map(|v| v.as_bytes()),
-            Some(b"DENY".as_slice()),
-            "missing or wrong X-Frame-Options"
+            post(&state, "/api/guest", None).await,
+            StatusCode::UNAUTHORIZED
         );
         assert_eq!(
-            headers.get("referrer-policy").map(|v| v.as_bytes()),
-            Some(b"strict-origin-when-cross-origin".as_slice()),
-            "missing or wrong Referrer-Policy"
+            post(&state, "/api/guest", Some("kiosk-wrong")).await,
+            StatusCode::UNAUTHORIZED
         );
         assert_eq!(
-            headers.get("x-xss-protection").map(|v| v.as_bytes()),
-            Some(b"0".as_slice()),
-            "missing or wrong X-XSS-Protection"
+            post(&state, "/api/guest", Some(&user)).await,
+            StatusCode::FORBIDDEN
         );
+        // Past the checks nothing is created while draining
+        state.drain.start();
         assert_eq!(
-            headers.get("content-security-policy").map(|v| v.as_bytes()),
-            Some(
-                b"default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; \
-                  connect-src 'self' wss:; img-src 'self' data:; media-src 'self' blob:"
-                    .as_slice()
-            ),
-            "missing or wrong Content-Security-Policy"
+            post(&state, "/api/guest", Some("kiosk-0123456789abcdef")).await,
+            StatusCode::SERVICE_UNAVAILABLE
         );
         assert_eq!(
-            headers.get("permissions-policy").map(|v| v.as_bytes()),
-            Some(b"camera=(), microphone=(), geolocation=()".as_slice()),
-            "missing or wrong Permissions-Policy"
+            post(&state, "/api/guest", Some(&admin)).await,
+            StatusCode::SERVICE_UNAVAILABLE
         );
-    }
-
-    #[tokio::test]
-    async fn metrics_endpoint_returns_prometheus_format() {
-        let state = test_app_state();
-
-        // Simulate some metrics
-        state
-            .metrics_logins_attempted
-            .store(42, std::sync::atomic::Ordering::Relaxed);
-        state
-            .metrics_logins_failed
-            .store(5, std::sync::atomic::Ordering::Relaxed);
-        state
-            .metrics_agent_restarts
-            .store(2, std::sync::atomic::Ordering::Relaxed);
-
-        let app = build_router(state);
-
-        let token = crate::auth::generate_jwt("testuser", TEST_JWT_SECRET).unwrap();
 
+        // A guest's end can't be called off
+        let username = state
+            .guests
+            .reserve("beam-guest-", 1, std::time::Duration::from_secs(60))
+            .unwrap()
+            .unwrap();
+        let id = Uuid::new_v4();
+        state.guests.started(&username, id).unwrap();
+        let uri = format!("/api/admin/sessions/{id}/termination");
         let request = Request::builder()
-            .uri("/metrics")
-            .header("authorization", format!("Bearer {token}"))
+            .method("DELETE")
+            .uri(uri)
+            .header("authorization", format!("Bearer {admin}"))
             .body(Body::empty())
             .unwrap();
-
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-
-        // Check Content-Type header
-        let content_type = response
-            .headers()
-            .get("content-type")
-            .expect("missing content-type")
-            .to_str()
+        let response = build_router(Arc::clone(&state))
+            .oneshot(request)
+            .await
             .unwrap();
-        assert_eq!(content_type, "text/plain; version=0.0.4; charset=utf-8");
-
-        let bytes = response.into_body().collect().await.unwrap().to_bytes();
-        let body = std::str::from_utf8(&bytes).unwrap();
-
-        // Verify Prometheus text format: each metric has HELP, TYPE, and value lines
-        assert!(body.contains("# HELP beam_active_sessions"));
-        assert!(body.contains("# TYPE beam_active_sessions gauge"));
-        assert!(body.contains("beam_active_sessions 0"));
-
-        assert!(body.contains("# HELP beam_uptime_seconds"));
-        assert!(body.contains("# TYPE beam_uptime_seconds gauge"));
-
-        assert!(body.contains("# HELP beam_total_logins_attempted"));
-        assert!(body.contains("# TYPE beam_total_logins_attempted counter"));
-        assert!(body.contains("beam_total_logins_attempted 42"));
-
-        assert!(body.contains("# HELP beam_total_logins_failed"));
-        assert!(body.contains("# TYPE beam_total_logins_failed counter"));
-        assert!(body.contains("beam_total_logins_failed 5"));
-
-        assert!(body.contains("# HELP beam_agent_restarts_total"));
-        assert!(body.contains("# TYPE beam_agent_restarts_total counter"));
-        assert!(body.contains("beam_agent_restarts_total 2"));
+        assert_eq!(response.status(), StatusCode::CONFLICT);
     }
 
     #[tokio::test]
-    async fn metrics_requires_auth_when_configured() {
-        // Default config has metrics_require_auth=true
-        let state = test_app_state();
-        assert!(state.config.server.metrics_require_auth);
-        let app = build_router(state);
-
-        let request = Request::builder()
-            .uri("/metrics")
-            .body(Body::empty())
+    async fn admins_upgrade_agents_in_place() {
+        let config = "[server]\nadmin_users = [\"root\"]";
+        let session = LiveSession::start_with(toml::from_str(config).expect("config")).await;
+        async fn post(
+            state: &Arc<AppState>,
+            user: &str,
+            uri: &str,
+        ) -> (StatusCode, serde_json::Value) {
+            let jwt = crate::auth::generate_jwt(user, TEST_JWT_SECRET).unwrap();
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("authorization", format!("Bearer {jwt}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = build_router(Arc::clone(state))
+                .oneshot(request)
+                .await
+                .unwrap();
+            (response.status(), body_json(response).await)
+        }
+        let upgrade_one = format!("/api/admin/sessions/{}/upgrade", session.id);
+        // No agent to upgrade yet
+        let (status, _) = post(&session.state, "root", &upgrade_one).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let mut agent = session.agent().await;
+        let mut browser = session.browser().await;
+        // Streaming, so the server has the agent's hello
+        next_keyframe(&mut browser).await;
+
+        let (status, _) = post(&session.state, "testuser", &upgrade_one).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let unknown = format!("/api/admin/sessions/{}/upgrade", Uuid::new_v4());
+        let (status, _) = post(&session.state, "root", &unknown).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = post(&session.state, "root", &upgrade_one).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        // Always the binary new sessions start
+        let binary = session.state.session_manager.agent_binary();
+        assert_eq!(body["path"], binary.to_string_lossy().as_ref());
+        let command = agent
+            .wait_for(WAIT, |cmd| matches!(cmd, AgentCommand::Upgrade { .. }))
+            .await
             .unwrap();
-
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-    }
-
-    #[tokio::test]
-    async fn metrics_accessible_without_auth_when_disabled() {
-        let mut config: BeamConfig = toml::from_str("").expect("default config");
-        config.server.metrics_require_auth = false;
-
-        let session_manager = crate::session::SessionManager::new(
-            100,
-            1920,
-            1080,
-            None,
-            beam_protocol::VideoConfig::default(),
+        assert!(
+            matches!(command, AgentCommand::Upgrade { path } if binary == std::path::Path::new(&path))
         );
-        let state = Arc::new(AppState {
-            config,
-            session_manager,
-            channels: crate::signaling::new_channel_registry(),
-            jwt_secret: TEST_JWT_SECRET.to_string(),
-            login_limiter: LoginRateLimiter::new(5, 60),
-            ip_limiter: LoginRateLimiter::new(20, 60),
-            release_limiter: LoginRateLimiter::new(10, 60),
-            started_at: std::time::Instant::now(),
-            metrics_logins_attempted: std::sync::atomic::AtomicU64::new(0),
-            metrics_logins_failed: std::sync::atomic::AtomicU64::new(0),
-            metrics_agent_restarts: std::sync::atomic::AtomicU64::new(0),
-        });
-
-        let app = build_router(state);
-
-        let request = Request::builder()
-            .uri("/metrics")
-            .body(Body::empty())
-            .unwrap();
-
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
 
-        let bytes = response.into_body().collect().await.unwrap().to_bytes();
-        let body = std::str::from_utf8(&bytes).unwrap();
-        assert!(body.contains("beam_active_sessions"));
+        let (status, body) = post(&session.state, "root", "/api/admin/agents/upgrade").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["upgrading"], json!([session.id]));
+        assert_eq!(body["skipped"], json!([]));
+
+        let channel = session.state.channels.read().await[&session.id].clone();
+        let upgrades = channel
+            .timeline
+            .entries()
+            .into_iter()
+            .filter(|e| e.event == SessionEvent::AgentUpgrading { by: "root".into() })
+            .count();
+        assert_eq!(upgrades, 2);
     }
 }
@@ -0,0 +1,134 @@
+//! The browser side of a session's WebSocket: hello, frame acks and input
+//! out, media frames and agent messages in.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use beam_protocol::{Hello, InputEvent, VideoFrameHeader};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+/// Acknowledge one in this many video frames, like the web client
+const ACK_SAMPLE_INTERVAL: u64 = 30;
+
+pub enum Event {
+    Video {
+        header: VideoFrameHeader,
+        received_at: Instant,
+    },
+    /// Audio frame or text message
+    Other,
+}
+
+pub struct Browser {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    video_frames: u64,
+}
+
+impl Browser {
+    /// Open the session's browser socket and announce ourselves the way
+    /// the web client does. Asks the agent to draw the cursor into the
+    /// video, since there is no local cursor to render.
+    pub async fn connect(url: &str, tls: Arc<rustls::ClientConfig>) -> Result<Self> {
+        let (ws, _) = tokio_tungstenite::connect_async_tls_with_config(
+            url,
+            None,
+            false,
+            Some(Connector::Rustls(tls)),
+        )
+        .await
+        .context("Browser WebSocket connection failed")?;
+        let mut browser = Self {
+            ws,
+            video_frames: 0,
+        };
+        browser
+            .send_json(&serde_json::to_string(&Hello::new(&[]))?)
+            .await?;
+        browser
+            .send(&InputEvent::VisibilityState { visible: true })
+            .await?;
+        browser
+            .send(&InputEvent::CursorMode { composite: true })
+            .await?;
+        Ok(browser)
+    }
+
+    pub async fn send(&mut self, event: &InputEvent) -> Result<()> {
+        self.send_json(&serde_json::to_string(event)?).await
+    }
+
+    async fn send_json(&mut self, json: &str) -> Result<()> {
+        self.ws
+            .send(Message::Text(json.into()))
+            .await
+            .context("Failed to send on the browser WebSocket")
+    }
+
+    /// Next frame or message, or None if `deadline` passes first.
+    pub async fn next(&mut self, deadline: Instant) -> Result<Option<Event>> {
+        loop {
+            let msg = match tokio::time::timeout_at(deadline, self.ws.next()).await {
+                Err(_) => return Ok(None),
+                Ok(None) => bail!("Server closed the browser WebSocket"),
+                Ok(Some(msg)) => msg.context("Browser WebSocket failed")?,
+            };
+            match msg {
+                Message::Binary(data) => {
+                    let received_at = Instant::now();
+                    VideoFrameHeader::validate_complete(&data).context("Invalid media frame")?;
+                    let header = VideoFrameHeader::deserialize(&data)?;
+                    if header.is_audio() {
+                        return Ok(Some(Event::Other));
+                    }
+                    self.video_frames += 1;
+                    if self.video_frames % ACK_SAMPLE_INTERVAL == 1 {
+                        // Nothing is decoded, so there is no display delay
+                        self.send(&InputEvent::FrameAck {
+                            ts: header.timestamp_us,
+                            dd: 0.0,
+                        })
+                        .await?;
+                    }
+                    return Ok(Some(Event::Video {
+                        header,
+                        received_at,
+                    }));
+                }
+                Message::Text(text) => {
+                    // "replaced", "agent_exited" and friends end the run
+                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text)
+                        && json["type"] == "error"
+                    {
+                        bail!("Server ended the connection: {}", json["message"]);
+                    }
+                    return Ok(Some(Event::Other));
+                }
+                Message::Close(frame) => {
+                    let reason = frame.map(|f| f.reason.to_string()).unwrap_or_default();
+                    bail!("Server closed the browser WebSocket: {reason}");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for the first keyframe, which is what the web client needs to
+    /// show anything.
+    pub async fn first_keyframe(&mut self, deadline: Instant) -> Result<VideoFrameHeader> {
+        loop {
+            match self.next(deadline).await? {
+                Some(Event::Video { header, .. }) if header.is_keyframe() => return Ok(header),
+                Some(_) => {}
+                None => bail!("No keyframe before the deadline"),
+            }
+        }
+    }
+
+    pub async fn close(mut self) {
+        let _ = self.ws.close(None).await;
+    }
+}
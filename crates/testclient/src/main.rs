@@ -0,0 +1,416 @@
+mod browser;
+mod stats;
+
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use beam_cli::http::{self, Client, ServerUrl};
+use beam_protocol::{AuthRequest, AuthResponse, InputEvent};
+use serde_json::json;
+use tokio::time::Instant;
+
+use browser::{Browser, Event};
+use stats::EchoDetector;
+
+/// Covers agent startup for a fresh session
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// A probe with no visible effect within this long counts as missed
+const ECHO_TIMEOUT: Duration = Duration::from_secs(1);
+/// Quiet time before each probe, for the previous one's frames to pass
+const PROBE_SETTLE: Duration = Duration::from_millis(300);
+
+struct Args {
+    server: String,
+    ca: Option<PathBuf>,
+    insecure: bool,
+    username: Option<String>,
+    password_stdin: bool,
+    width: u32,
+    height: u32,
+    duration: Duration,
+    probes: usize,
+    reconnects: usize,
+    keep_session: bool,
+    max_first_frame_ms: Option<f64>,
+    max_echo_p95_ms: Option<f64>,
+}
+
+fn parse_args() -> Args {
+    let args: Vec<String> = std::env::args().collect();
+    let mut parsed = Args {
+        server: std::env::var("BEAM_SERVER")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "https://localhost:8444".to_string()),
+        ca: None,
+        insecure: false,
+        username: None,
+        password_stdin: false,
+        width: 1280,
+        height: 720,
+        duration: Duration::from_secs(5),
+        probes: 20,
+        reconnects: 1,
+        keep_session: false,
+        max_first_frame_ms: None,
+        max_echo_p95_ms: None,
+    };
+
+    let value = |i: usize| -> &str {
+        match args.get(i + 1) {
+            Some(v) => v,
+            None => {
+                eprintln!("beam-testclient: {} needs a value", args[i]);
+                std::process::exit(2);
+            }
+        }
+    };
+    let number = |i: usize| -> f64 {
+        match value(i).parse::<f64>() {
+            Ok(n) if n.is_finite() && n >= 0.0 => n,
+            _ => {
+                eprintln!("beam-testclient: invalid {}: {}", args[i], value(i));
+                std::process::exit(2);
+            }
+        }
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-V" | "--version" => {
+                println!("beam-testclient {}", env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            "-h" | "--help" => {
+                println!("beam-testclient - headless Beam client for end-to-end tests");
+                println!();
+                println!("Logs in, streams like a browser tab and prints timings as JSON.");
+                println!("The password comes from BEAM_PASSWORD or --password-stdin.");
+                println!();
+                println!("USAGE:");
+                println!("    beam-testclient [OPTIONS]");
+                println!();
+                println!("OPTIONS:");
+                println!(
+                    "    -s, --server <URL>            Server URL [env: BEAM_SERVER, default: https://localhost:8444]"
+                );
+                println!("        --ca <PATH>               Trust this PEM certificate");
+                println!("    -k, --insecure                Don't verify the server's certificate");
+                println!("    -u, --username <NAME>         User to log in as [default: $USER]");
+                println!("        --password-stdin          Read the password from stdin");
+                println!(
+                    "        --size <WxH>              Viewport to request [default: 1280x720]"
+                );
+                println!(
+                    "        --duration <SECS>         How long to measure the stream [default: 5]"
+                );
+                println!("        --probes <N>              Input echo probes [default: 20]");
+                println!("        --reconnects <N>          Reconnects to time [default: 1]");
+                println!("        --keep-session            Leave the session running afterwards");
+                println!("        --max-first-frame-ms <MS> Fail if the first keyframe is slower");
+                println!("        --max-echo-p95-ms <MS>    Fail if p95 input echo is slower");
+                println!("    -V, --version                 Print version and exit");
+                println!("    -h, --help                    Print this help and exit");
+                std::process::exit(0);
+            }
+            "--server" | "-s" => {
+                parsed.server = value(i).to_string();
+                i += 1;
+            }
+            "--ca" => {
+                parsed.ca = Some(PathBuf::from(value(i)));
+                i += 1;
+            }
+            "--insecure" | "-k" => parsed.insecure = true,
+            "--username" | "-u" => {
+                parsed.username = Some(value(i).to_string());
+                i += 1;
+            }
+            "--password-stdin" => parsed.password_stdin = true,
+            "--size" => {
+                let size = value(i)
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                match size {
+                    Some((w, h)) if w > 0 && h > 0 => (parsed.width, parsed.height) = (w, h),
+                    _ => {
+                        eprintln!("beam-testclient: invalid --size: {}", value(i));
+                        std::process::exit(2);
+                    }
+                }
+                i += 1;
+            }
+            "--duration" => {
+                parsed.duration = Duration::from_secs_f64(number(i));
+                i += 1;
+            }
+            "--probes" => {
+                parsed.probes = number(i) as usize;
+                i += 1;
+            }
+            "--reconnects" => {
+                parsed.reconnects = number(i) as usize;
+                i += 1;
+            }
+            "--keep-session" => parsed.keep_session = true,
+            "--max-first-frame-ms" => {
+                parsed.max_first_frame_ms = Some(number(i));
+                i += 1;
+            }
+            "--max-echo-p95-ms" => {
+                parsed.max_echo_p95_ms = Some(number(i));
+                i += 1;
+            }
+            other => {
+                eprintln!("beam-testclient: unknown argument: {other}");
+                std::process::exit(2);
+            }
+        }
+        i += 1;
+    }
+    parsed
+}
+
+fn read_password(from_stdin: bool) -> Result<String> {
+    if from_stdin {
+        let mut line = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .context("Failed to read password")?;
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+    std::env::var("BEAM_PASSWORD").context("Set BEAM_PASSWORD or use --password-stdin")
+}
+
+/// What one run measured over the browser socket.
+#[derive(Default)]
+struct Measurements {
+    /// Socket connect to first keyframe, agent startup included
+    first_frame_ms: f64,
+    width: u16,
+    height: u16,
+    frames: u64,
+    keyframes: u64,
+    bytes: u64,
+    elapsed: Duration,
+    echo_ms: Vec<f64>,
+    echo_missed: usize,
+    reconnect_ms: Vec<f64>,
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+async fn measure(url: &str, tls: Arc<rustls::ClientConfig>, args: &Args) -> Result<Measurements> {
+    let mut m = Measurements::default();
+
+    eprintln!("Waiting for the first keyframe...");
+    let start = Instant::now();
+    let mut browser = Browser::connect(url, Arc::clone(&tls)).await?;
+    let header = browser
+        .first_keyframe(start + FIRST_FRAME_TIMEOUT)
+        .await
+        .context("Waiting for the first frame")?;
+    m.first_frame_ms = ms(start.elapsed());
+    (m.width, m.height) = (header.width, header.height);
+
+    eprintln!(
+        "Measuring the stream for {:.1}s...",
+        args.duration.as_secs_f64()
+    );
+    let start = Instant::now();
+    let end = start + args.duration;
+    while let Some(event) = browser.next(end).await? {
+        if let Event::Video { header, .. } = event {
+            m.frames += 1;
+            m.keyframes += u64::from(header.is_keyframe());
+            m.bytes += u64::from(header.payload_length);
+        }
+    }
+    m.elapsed = start.elapsed();
+
+    if args.probes > 0 {
+        eprintln!("Sending {} input echo probes...", args.probes);
+    }
+    let mut detector = EchoDetector::default();
+    for probe in 0..args.probes {
+        let settled = Instant::now() + PROBE_SETTLE;
+        while let Some(event) = browser.next(settled).await? {
+            if let Event::Video { header, .. } = event
+                && !header.is_keyframe()
+            {
+                detector.observe_idle(header.payload_length);
+            }
+        }
+
+        // Alternate between two far-apart spots so every probe moves the
+        // cursor
+        let pos = if probe % 2 == 0 { 0.25 } else { 0.75 };
+        let sent = Instant::now();
+        browser
+            .send(&InputEvent::MouseMove { x: pos, y: pos })
+            .await?;
+        loop {
+            match browser.next(sent + ECHO_TIMEOUT).await? {
+                Some(Event::Video {
+                    header,
+                    received_at,
+                }) if !header.is_keyframe() && detector.is_echo(header.payload_length) => {
+                    m.echo_ms.push(ms(received_at - sent));
+                    break;
+                }
+                Some(_) => {}
+                None => {
+                    m.echo_missed += 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    for attempt in 1..=args.reconnects {
+        eprintln!("Reconnecting ({attempt}/{})...", args.reconnects);
+        browser.close().await;
+        let start = Instant::now();
+        browser = Browser::connect(url, Arc::clone(&tls)).await?;
+        browser
+            .first_keyframe(start + RECONNECT_TIMEOUT)
+            .await
+            .context("Waiting for a keyframe after reconnecting")?;
+        m.reconnect_ms.push(ms(start.elapsed()));
+    }
+    browser.close().await;
+    Ok(m)
+}
+
+fn login(api: &Client, args: &Args, password: String) -> Result<AuthResponse> {
+    let username = match args.username.clone() {
+        Some(name) => name,
+        None => std::env::var("USER").context("No username given; use --username")?,
+    };
+    let request = AuthRequest {
+        username,
+        password,
+        viewport_width: Some(args.width),
+        viewport_height: Some(args.height),
+        idle_timeout: None,
+        system_combos: None,
+        nettest: None,
+        token_only: false,
+        takeover: false,
+        assist: false,
+        desktop: None,
+    };
+    let response = api.post("/api/auth/login", Some(&serde_json::to_value(&request)?))?;
+    if !response.is_success() {
+        bail!(
+            "Login failed: {} (HTTP {})",
+            response.error_message(),
+            response.status
+        );
+    }
+    serde_json::from_slice(&response.body).context("Unexpected login response")
+}
+
+fn run(args: &Args) -> Result<bool> {
+    let server = ServerUrl::parse(&args.server)?;
+    let tls = http::tls_config(args.ca.as_deref(), args.insecure)?;
+    let password = read_password(args.password_stdin)?;
+
+    eprintln!("Logging in to {server}...");
+    let started = std::time::Instant::now();
+    let api = Client::new(server.clone(), Arc::clone(&tls), None);
+    let auth = login(&api, args, password)?;
+    let login_ms = ms(started.elapsed());
+
+    let url = format!(
+        "{}/api/sessions/{}/ws?token={}",
+        server.to_string().replacen("http", "ws", 1),
+        auth.session_id,
+        urlencoding::encode(&auth.token)
+    );
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start the async runtime")?;
+    let measured = runtime.block_on(measure(&url, Arc::clone(&tls), args));
+
+    // Clean up even when the run failed, so CI doesn't leak desktops
+    if !args.keep_session {
+        let api = Client::new(server.clone(), tls, Some(auth.token.clone()));
+        match api.delete(&format!("/api/sessions/{}", auth.session_id)) {
+            Ok(response) if response.is_success() => {}
+            Ok(response) => eprintln!(
+                "beam-testclient: failed to end session {}: {}",
+                auth.session_id,
+                response.error_message()
+            ),
+            Err(e) => eprintln!(
+                "beam-testclient: failed to end session {}: {e:#}",
+                auth.session_id
+            ),
+        }
+    }
+    let m = measured?;
+
+    let secs = m.elapsed.as_secs_f64().max(f64::EPSILON);
+    let echo = stats::summarize(&m.echo_ms);
+    let mut failures = Vec::new();
+    if let Some(max) = args.max_first_frame_ms
+        && m.first_frame_ms > max
+    {
+        failures.push(format!(
+            "first frame took {:.0} ms (limit {max} ms)",
+            m.first_frame_ms
+        ));
+    }
+    if let Some(max) = args.max_echo_p95_ms {
+        match echo["p95"].as_f64() {
+            Some(p95) if p95 <= max => {}
+            Some(p95) => failures.push(format!("input echo p95 {p95} ms (limit {max} ms)")),
+            None => failures.push("no input echo was observed".to_string()),
+        }
+    }
+
+    let passed = failures.is_empty();
+    let report = json!({
+        "server": server.to_string(),
+        "session_id": auth.session_id,
+        "login_ms": stats::round(login_ms),
+        "first_frame_ms": stats::round(m.first_frame_ms),
+        "video": {
+            "width": m.width,
+            "height": m.height,
+            "frames": m.frames,
+            "keyframes": m.keyframes,
+            "fps": stats::round(m.frames as f64 / secs),
+            "kbps": stats::round(m.bytes as f64 * 8.0 / 1000.0 / secs),
+        },
+        "input_echo_ms": echo,
+        "input_echo_missed": m.echo_missed,
+        "reconnect_first_frame_ms": stats::summarize(&m.reconnect_ms),
+        "passed": passed,
+        "failures": failures,
+    });
+    println!("{report:#}");
+    Ok(passed)
+}
+
+fn main() {
+    let args = parse_args();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("beam-testclient: {e:#}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Timing summaries and input echo detection.
+
+use std::collections::VecDeque;
+
+use serde_json::{Value, json};
+
+/// Sample count, min, median, p95 and max of millisecond samples.
+pub fn summarize(samples: &[f64]) -> Value {
+    let mut sorted: Vec<f64> = samples.iter().copied().filter(|s| s.is_finite()).collect();
+    sorted.sort_by(f64::total_cmp);
+    if sorted.is_empty() {
+        return json!({ "samples": 0 });
+    }
+    json!({
+        "samples": sorted.len(),
+        "min": round(sorted[0]),
+        "p50": round(percentile(&sorted, 50.0)),
+        "p95": round(percentile(&sorted, 95.0)),
+        "max": round(sorted[sorted.len() - 1]),
+    })
+}
+
+/// Nearest-rank percentile of ascending `sorted`, which must not be empty.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Milliseconds to one decimal, which is all the precision a network
+/// measurement has.
+pub fn round(ms: f64) -> f64 {
+    (ms * 10.0).round() / 10.0
+}
+
+/// Static frames kept as the baseline
+const BASELINE_FRAMES: usize = 30;
+/// Fewer static frames than this and any frame counts as an echo
+const MIN_BASELINE_FRAMES: usize = 5;
+/// Smallest size step over the baseline that counts as a change
+const MIN_ECHO_GROWTH: u32 = 256;
+
+/// Spots the first frame showing the effect of an input without decoding
+/// video. The agent keeps encoding while the screen is static, and those
+/// delta frames are tiny; a frame that carries a moved cursor is at least
+/// twice the median static frame and `MIN_ECHO_GROWTH` bytes over it.
+#[derive(Default)]
+pub struct EchoDetector {
+    idle: VecDeque<u32>,
+}
+
+impl EchoDetector {
+    /// Record a delta frame from a stretch with no input in flight.
+    pub fn observe_idle(&mut self, payload_len: u32) {
+        if self.idle.len() == BASELINE_FRAMES {
+            self.idle.pop_front();
+        }
+        self.idle.push_back(payload_len);
+    }
+
+    /// Smallest payload that counts as an echo, or None while there is
+    /// no baseline (an encoder that skips static frames entirely).
+    pub fn threshold(&self) -> Option<u32> {
+        if self.idle.len() < MIN_BASELINE_FRAMES {
+            return None;
+        }
+        let mut sorted: Vec<u32> = self.idle.iter().copied().collect();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2];
+        Some((median * 2).max(median + MIN_ECHO_GROWTH))
+    }
+
+    pub fn is_echo(&self, payload_len: u32) -> bool {
+        self.threshold().is_none_or(|t| payload_len >= t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_percentiles() {
+        let samples: Vec<f64> = (1..=20).map(f64::from).collect();
+        let summary = summarize(&samples);
+        assert_eq!(summary["samples"], 20);
+        assert_eq!(summary["min"], 1.0);
+        assert_eq!(summary["p50"], 10.0);
+        assert_eq!(summary["p95"], 19.0);
+        assert_eq!(summary["max"], 20.0);
+
+        assert_eq!(summarize(&[]), json!({ "samples": 0 }));
+        assert_eq!(summarize(&[f64::NAN, 2.345])["p50"], 2.3);
+    }
+
+    #[test]
+    fn echo_needs_a_clear_size_jump() {
+        let mut detector = EchoDetector::default();
+        // No baseline yet: the first frame after an input is the echo
+        assert!(detector.is_echo(10));
+
+        for size in [40, 60, 50, 45, 55, 50] {
+            detector.observe_idle(size);
+        }
+        assert_eq!(detector.threshold(), Some(50 + MIN_ECHO_GROWTH));
+        assert!(!detector.is_echo(120));
+        assert!(detector.is_echo(400));
+
+        // Busy screens have large delta frames; double the median then
+        let mut busy = EchoDetector::default();
+        for _ in 0..BASELINE_FRAMES * 2 {
+            busy.observe_idle(5000);
+        }
+        assert_eq!(busy.threshold(), Some(10_000));
+        assert_eq!(busy.idle.len(), BASELINE_FRAMES);
+    }
+}
@@ -27,6 +27,21 @@ needs_root_rights=yes
 XWRAP
 fi
 
+# Print redirection: a cups-pdf queue whose output (~/PDF) the agent sends
+# to the browser as a download. Only when cups-pdf is installed.
+if command -v lpadmin >/dev/null 2>&1 && [ -x /usr/lib/cups/backend/cups-pdf ]; then
+    if ! lpstat -p Beam_Printer >/dev/null 2>&1; then
+        lpadmin -p Beam_Printer -E -v cups-pdf:/ \
+            -m lsb/usr/cups-pdf/CUPS-PDF_opt.ppd \
+            -D "Beam Printer" -L "Downloads in your browser" \
+            -o printer-is-shared=false 2>/dev/null || true
+    fi
+    # Default only when nothing else is
+    if ! lpstat -d 2>/dev/null | grep -q "system default destination:"; then
+        lpadmin -d Beam_Printer 2>/dev/null || true
+    fi
+fi
+
 # Reload udev rules for uinput access
 udevadm control --reload-rules 2>/dev/null || true
 udevadm trigger 2>/dev/null || true
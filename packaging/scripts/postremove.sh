@@ -4,6 +4,11 @@ set -e
 # Reload systemd after service file removal
 systemctl daemon-reload 2>/dev/null || true
 
+# Remove the print redirection queue
+if command -v lpadmin >/dev/null 2>&1; then
+    lpadmin -x Beam_Printer 2>/dev/null || true
+fi
+
 # On purge: remove configuration and runtime data
 if [ "$1" = "purge" ]; then
     rm -rf /var/lib/beam
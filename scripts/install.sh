@@ -98,6 +98,8 @@ cargo build --release --workspace
 log "Installing binaries to $INSTALL_DIR..."
 install -m 755 target/release/beam-server "$INSTALL_DIR/"
 install -m 755 target/release/beam-agent "$INSTALL_DIR/"
+install -m 755 target/release/beam-doctor "$INSTALL_DIR/"
+install -m 755 target/release/beam "$INSTALL_DIR/"
 
 # Install web client
 log "Installing web client to $WEB_INSTALL_DIR..."
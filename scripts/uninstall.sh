@@ -18,6 +18,8 @@ systemctl disable beam 2>/dev/null || true
 log "Removing binaries..."
 rm -f /usr/local/bin/beam-server
 rm -f /usr/local/bin/beam-agent
+rm -f /usr/local/bin/beam-doctor
+rm -f /usr/local/bin/beam
 
 log "Removing systemd service..."
 rm -f /etc/systemd/system/beam.service
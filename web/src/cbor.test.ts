@@ -0,0 +1,61 @@
+import { describe, it, expect } from "vitest";
+import { decodeCbor, encodeCbor, isCborMessage } from "./cbor";
+import { FRAME_MAGIC } from "./connection";
+
+describe("CBOR codec", () => {
+  it("round-trips protocol messages", () => {
+    const messages = [
+      { t: "k", c: 30, d: true },
+      { t: "m", x: 0.25, y: 0.1 },
+      { t: "s", dx: 0, dy: -30 },
+      { t: "c", text: "héllo ✓" },
+      { t: "hello", version: 1, capabilities: ["lock_screen"] },
+      { type: "error", message: "replaced" },
+      { cmd: "set_audio", data: { enabled: false } },
+      { t: "lat", big: 2 ** 40, neg: -70000, nothing: null, list: [] },
+    ];
+    for (const msg of messages) {
+      expect(decodeCbor(encodeCbor(msg))).toEqual(msg);
+    }
+  });
+
+  it("decodes the Rust encoding of a key event", () => {
+    // {"t":"k","c":30,"d":true} as emitted by crates/protocol codec.rs
+    // (serde_json orders map keys)
+    const bytes = new Uint8Array([
+      0xa3, 0x61, 0x63, 0x18, 0x1e, 0x61, 0x64, 0xf5, 0x61, 0x74, 0x61, 0x6b,
+    ]);
+    expect(decodeCbor(bytes)).toEqual({ t: "k", c: 30, d: true });
+  });
+
+  it("is smaller than JSON for mouse moves", () => {
+    const msg = { t: "m", x: 0.5, y: 0.75 };
+    expect(encodeCbor(msg).length).toBeLessThan(JSON.stringify(msg).length);
+  });
+
+  it("decodes half-precision floats", () => {
+    expect(decodeCbor(new Uint8Array([0xf9, 0x3e, 0x00]))).toBe(1.5);
+  });
+
+  it("rejects malformed input", () => {
+    const bad = [
+      [],
+      [0xa1],
+      [0x7a, 0xff, 0xff, 0xff],
+      [0xa1, 0x01, 0x01],
+      [0x5f],
+      [0x01, 0x02],
+      new Array(40).fill(0x81),
+    ];
+    for (const b of bad) {
+      expect(() => decodeCbor(new Uint8Array(b))).toThrow();
+    }
+  });
+
+  it("tells messages from media frames", () => {
+    const frame = new ArrayBuffer(4);
+    new DataView(frame).setUint32(0, FRAME_MAGIC, true);
+    expect(isCborMessage(frame)).toBe(false);
+    expect(isCborMessage(encodeCbor({ t: "k" }).buffer as ArrayBuffer)).toBe(true);
+  });
+});
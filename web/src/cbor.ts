@@ -0,0 +1,277 @@
+/**
+ * Minimal CBOR (RFC 8949) codec for the `beam-cbor` signaling format.
+ *
+ * Covers what JSON can express: maps with text keys, arrays, text, numbers,
+ * booleans and null. Mirrors `crates/protocol/src/codec.rs`.
+ */
+
+/** Subprotocol offered to the server to select CBOR framing */
+export const CBOR_SUBPROTOCOL = "beam-cbor";
+
+const MAX_DEPTH = 32;
+const textEncoder = new TextEncoder();
+const textDecoder = new TextDecoder("utf-8", { fatal: true });
+
+/** Whether a binary frame is a CBOR message (always a map) rather than media */
+export function isCborMessage(data: ArrayBuffer): boolean {
+  if (data.byteLength === 0) return false;
+  const first = new Uint8Array(data, 0, 1)[0];
+  return first >= 0xa0 && first <= 0xbf;
+}
+
+class Writer {
+  private buf = new Uint8Array(256);
+  private view = new DataView(this.buf.buffer);
+  length = 0;
+
+  private reserve(n: number): void {
+    if (this.length + n <= this.buf.length) return;
+    let size = this.buf.length * 2;
+    while (size < this.length + n) size *= 2;
+    const next = new Uint8Array(size);
+    next.set(this.buf.subarray(0, this.length));
+    this.buf = next;
+    this.view = new DataView(next.buffer);
+  }
+
+  byte(b: number): void {
+    this.reserve(1);
+    this.buf[this.length++] = b;
+  }
+
+  bytes(data: Uint8Array): void {
+    this.reserve(data.length);
+    this.buf.set(data, this.length);
+    this.length += data.length;
+  }
+
+  head(major: number, n: number): void {
+    const m = major << 5;
+    if (n < 24) {
+      this.byte(m | n);
+    } else if (n <= 0xff) {
+      this.byte(m | 24);
+      this.byte(n);
+    } else if (n <= 0xffff) {
+      this.byte(m | 25);
+      this.reserve(2);
+      this.view.setUint16(this.length, n);
+      this.length += 2;
+    } else if (n <= 0xffffffff) {
+      this.byte(m | 26);
+      this.reserve(4);
+      this.view.setUint32(this.length, n);
+      this.length += 4;
+    } else {
+      this.byte(m | 27);
+      this.reserve(8);
+      this.view.setBigUint64(this.length, BigInt(n));
+      this.length += 8;
+    }
+  }
+
+  float(f: number): void {
+    if (Math.fround(f) === f) {
+      this.byte(0xfa);
+      this.reserve(4);
+      this.view.setFloat32(this.length, f);
+      this.length += 4;
+    } else {
+      this.byte(0xfb);
+      this.reserve(8);
+      this.view.setFloat64(this.length, f);
+      this.length += 8;
+    }
+  }
+
+  result(): Uint8Array {
+    return this.buf.slice(0, this.length);
+  }
+}
+
+function writeValue(w: Writer, value: unknown): void {
+  if (value === null || value === undefined) {
+    w.byte(0xf6);
+  } else if (value === false) {
+    w.byte(0xf4);
+  } else if (value === true) {
+    w.byte(0xf5);
+  } else if (typeof value === "number") {
+    if (Number.isSafeInteger(value)) {
+      if (value >= 0) w.head(0, value);
+      else w.head(1, -1 - value);
+    } else if (Number.isFinite(value)) {
+      w.float(value);
+    } else {
+      w.byte(0xf6); // JSON.stringify turns NaN/Infinity into null too
+    }
+  } else if (typeof value === "string") {
+    const bytes = textEncoder.encode(value);
+    w.head(3, bytes.length);
+    w.bytes(bytes);
+  } else if (Array.isArray(value)) {
+    w.head(4, value.length);
+    for (const item of value) writeValue(w, item);
+  } else if (typeof value === "object") {
+    const entries = Object.entries(value as Record<string, unknown>).filter(
+      ([, v]) => v !== undefined,
+    );
+    w.head(5, entries.length);
+    for (const [key, item] of entries) {
+      const bytes = textEncoder.encode(key);
+      w.head(3, bytes.length);
+      w.bytes(bytes);
+      writeValue(w, item);
+    }
+  } else {
+    throw new Error(`cannot encode ${typeof value} as CBOR`);
+  }
+}
+
+/** Encode a JSON-compatible value as CBOR */
+export function encodeCbor(value: unknown): Uint8Array {
+  const w = new Writer();
+  writeValue(w, value);
+  return w.result();
+}
+
+class Reader {
+  private view: DataView;
+  private bytes: Uint8Array;
+  pos = 0;
+
+  constructor(data: ArrayBuffer | Uint8Array) {
+    this.bytes = data instanceof Uint8Array ? data : new Uint8Array(data);
+    this.view = new DataView(this.bytes.buffer, this.bytes.byteOffset, this.bytes.byteLength);
+  }
+
+  get remaining(): number {
+    return this.bytes.length - this.pos;
+  }
+
+  private need(n: number): void {
+    if (n > this.remaining) throw new Error("invalid CBOR: truncated");
+  }
+
+  byte(): number {
+    this.need(1);
+    return this.bytes[this.pos++];
+  }
+
+  argument(info: number): number {
+    let n: number;
+    switch (info) {
+      case 24:
+        return this.byte();
+      case 25:
+        this.need(2);
+        n = this.view.getUint16(this.pos);
+        this.pos += 2;
+        return n;
+      case 26:
+        this.need(4);
+        n = this.view.getUint32(this.pos);
+        this.pos += 4;
+        return n;
+      case 27:
+        this.need(8);
+        n = Number(this.view.getBigUint64(this.pos));
+        this.pos += 8;
+        return n;
+      default:
+        if (info < 24) return info;
+        throw new Error("invalid CBOR: unsupported length encoding");
+    }
+  }
+
+  len(info: number): number {
+    const n = this.argument(info);
+    if (n > this.remaining) throw new Error("invalid CBOR: length exceeds message");
+    return n;
+  }
+
+  text(info: number): string {
+    const n = this.len(info);
+    const s = textDecoder.decode(this.bytes.subarray(this.pos, this.pos + n));
+    this.pos += n;
+    return s;
+  }
+
+  float(size: 2 | 4 | 8): number {
+    this.need(size);
+    let f: number;
+    if (size === 2) f = halfToFloat(this.view.getUint16(this.pos));
+    else if (size === 4) f = this.view.getFloat32(this.pos);
+    else f = this.view.getFloat64(this.pos);
+    this.pos += size;
+    if (!Number.isFinite(f)) throw new Error("invalid CBOR: non-finite float");
+    return f;
+  }
+
+  value(depth: number): unknown {
+    if (depth > MAX_DEPTH) throw new Error("invalid CBOR: nested too deeply");
+    const head = this.byte();
+    const info = head & 0x1f;
+    switch (head >> 5) {
+      case 0:
+        return this.argument(info);
+      case 1:
+        return -1 - this.argument(info);
+      case 3:
+        return this.text(info);
+      case 4: {
+        const n = this.len(info);
+        const items: unknown[] = [];
+        for (let i = 0; i < n; i++) items.push(this.value(depth + 1));
+        return items;
+      }
+      case 5: {
+        const n = this.len(info);
+        const map: Record<string, unknown> = {};
+        for (let i = 0; i < n; i++) {
+          const keyHead = this.byte();
+          if (keyHead >> 5 !== 3) throw new Error("invalid CBOR: map keys must be text");
+          const key = this.text(keyHead & 0x1f);
+          map[key] = this.value(depth + 1);
+        }
+        return map;
+      }
+      case 7:
+        switch (info) {
+          case 20:
+            return false;
+          case 21:
+            return true;
+          case 22:
+          case 23:
+            return null;
+          case 25:
+            return this.float(2);
+          case 26:
+            return this.float(4);
+          case 27:
+            return this.float(8);
+        }
+        throw new Error("invalid CBOR: unsupported simple value");
+      default:
+        throw new Error("invalid CBOR: unsupported major type");
+    }
+  }
+}
+
+function halfToFloat(bits: number): number {
+  const sign = bits & 0x8000 ? -1 : 1;
+  const exp = (bits >> 10) & 0x1f;
+  const mant = bits & 0x3ff;
+  if (exp === 0) return sign * mant * 2 ** -24;
+  if (exp === 31) return mant ? NaN : sign * Infinity;
+  return sign * (1 + mant / 1024) * 2 ** (exp - 15);
+}
+
+/** Decode one CBOR item into a JSON-compatible value */
+export function decodeCbor(data: ArrayBuffer | Uint8Array): unknown {
+  const reader = new Reader(data);
+  const value = reader.value(0);
+  if (reader.remaining !== 0) throw new Error("invalid CBOR: trailing bytes");
+  return value;
+}
@@ -1,4 +1,8 @@
 import type { InputEvent } from "./connection";
+import { hasPrimarySelection } from "./platform";
+
+/** Which way clipboard text may cross, as set by the server for the session */
+export type ClipboardPolicy = "bidirectional" | "to-remote" | "to-local" | "off";
 
 /** Direction of a clipboard sync event */
 export type ClipboardSyncDirection = "sent" | "received";
@@ -28,8 +32,24 @@ export class ClipboardBridge {
   private history: ClipboardHistoryEntry[] = [];
   private historyCallback: (() => void) | null = null;
 
-  constructor(sendClipboard: (event: InputEvent) => void) {
+  private allowsToRemote: boolean;
+  private allowsToLocal: boolean;
+
+  /** The agent sends its PRIMARY selection after selections in the session */
+  private primarySelection: boolean;
+  /** Local clipboard text the remote already has, or had when it last selected */
+  private lastLocalClipboard: string | null = null;
+  private primaryArea: HTMLTextAreaElement | null = null;
+
+  constructor(
+    sendClipboard: (event: InputEvent) => void,
+    policy: ClipboardPolicy = "bidirectional",
+    primarySelection = false,
+  ) {
     this.sendClipboard = sendClipboard;
+    this.allowsToRemote = policy === "bidirectional" || policy === "to-remote";
+    this.allowsToLocal = policy === "bidirectional" || policy === "to-local";
+    this.primarySelection = primarySelection;
   }
 
   /** Register a callback that fires when history changes */
@@ -82,19 +102,60 @@ export class ClipboardBridge {
 
   disable(): void {
     document.removeEventListener("paste", this.onPaste);
+    this.primaryArea?.remove();
+    this.primaryArea = null;
   }
 
   /** Called when the remote sends clipboard text */
   handleRemoteClipboard(text: string): void {
+    if (!this.allowsToLocal) return;
     if (text) {
       this.syncCallback?.("received", this.buildPreview(text));
       this.addHistory("received", text);
     }
+    this.lastLocalClipboard = text;
     navigator.clipboard.writeText(text).catch(() => {
       // Clipboard write permission denied — ignore silently
     });
   }
 
+  /**
+   * Called when the remote sends its PRIMARY selection. Not added to the
+   * history or announced: every selection in the session produces one.
+   */
+  handleRemotePrimary(text: string): void {
+    if (!this.allowsToLocal || !this.primarySelection) return;
+    if (hasPrimarySelection) this.exportPrimary(text);
+    // The remote selection is now newer than the local clipboard; remember
+    // the clipboard so a middle-click only replaces it once it changes
+    navigator.clipboard.readText().then(
+      (local) => { this.lastLocalClipboard = local; },
+      () => {},
+    );
+  }
+
+  /**
+   * Browsers copy the page selection to PRIMARY but can't write it
+   * directly, so select the text in an offscreen textarea, then hand focus
+   * back to whatever had it.
+   */
+  private exportPrimary(text: string): void {
+    if (!this.primaryArea) {
+      const area = document.createElement("textarea");
+      area.setAttribute("aria-hidden", "true");
+      area.tabIndex = -1;
+      area.readOnly = true;
+      area.style.cssText = "position:fixed;left:-10000px;top:0;width:1px;height:1px;opacity:0";
+      document.body.appendChild(area);
+      this.primaryArea = area;
+    }
+    const previous = document.activeElement;
+    this.primaryArea.value = text;
+    this.primaryArea.focus({ preventScroll: true });
+    this.primaryArea.select();
+    if (previous instanceof HTMLElement) previous.focus({ preventScroll: true });
+  }
+
   /**
    * Try to read the browser clipboard and send it as PRIMARY selection
    * to the remote agent. Called before middle-click so the remote X11
@@ -104,10 +165,15 @@ export class ClipboardBridge {
    * (or immediately if clipboard read fails — e.g. permissions denied).
    */
   async sendPrimaryClipboard(): Promise<void> {
+    if (!this.allowsToRemote) return;
     const MAX_CLIPBOARD_BYTES = 1_048_576; // 1 MB
     try {
       const text = await navigator.clipboard.readText();
       if (text && text.length <= MAX_CLIPBOARD_BYTES) {
+        // Keep a selection made in the session unless something new was
+        // copied locally since; this also stops remote text echoing back
+        if (this.primarySelection && text === this.lastLocalClipboard) return;
+        this.lastLocalClipboard = text;
         this.sendClipboard({ t: "cp", text });
         this.syncCallback?.("sent", this.buildPreview(text));
         this.addHistory("sent", text);
@@ -119,6 +185,7 @@ export class ClipboardBridge {
   }
 
   private handlePaste(e: ClipboardEvent): void {
+    if (!this.allowsToRemote) return;
     const MAX_CLIPBOARD_BYTES = 1_048_576; // 1 MB
     const text = e.clipboardData?.getData("text");
     if (text && text.length <= MAX_CLIPBOARD_BYTES) {
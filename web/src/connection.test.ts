@@ -3,6 +3,7 @@ import {
   parseFrameHeader,
   FRAME_HEADER_SIZE,
   FRAME_MAGIC,
+  PROTOCOL_VERSION,
   BeamConnection,
 } from "./connection";
 
@@ -131,7 +132,10 @@ class MockWebSocket {
   static CLOSING = 2;
   static CLOSED = 3;
 
-  constructor(_url: string) {
+  url: string;
+
+  constructor(url: string) {
+    this.url = url;
     mockWsInstances.push(this);
   }
 
@@ -139,7 +143,11 @@ class MockWebSocket {
     this.readyState = MockWebSocket.CLOSED;
   }
 
-  send(_data: any) {}
+  sent: any[] = [];
+
+  send(data: any) {
+    this.sent.push(data);
+  }
 
   /** Test helper: simulate the WS opening */
   simulateOpen() {
@@ -284,6 +292,114 @@ describe("BeamConnection reconnect logic", () => {
     expect(reconnectingCalled).toBe(false);
   });
 
+  it("share link viewer connects through its token and stops when the link ends", async () => {
+    const conn = BeamConnection.viewer("abc123");
+    let endedReason: string | null = null;
+    let reconnectingCalled = false;
+    conn.onShareEnded((reason) => { endedReason = reason; });
+    conn.onReconnecting(() => { reconnectingCalled = true; });
+
+    await conn.connect();
+    const ws = mockWsInstances[0];
+    expect(ws.url).toMatch(/\/api\/share\/abc123\/ws$/);
+    ws.simulateOpen();
+
+    ws.simulateMessage(JSON.stringify({ type: "error", message: "share_ended" }));
+
+    await vi.advanceTimersByTimeAsync(5000);
+    expect(endedReason).toBe("share_ended");
+    expect(reconnectingCalled).toBe(false);
+  });
+
+  it("admin notices reach the callback without disconnecting", async () => {
+    const conn = new BeamConnection("test-session", "test-token");
+    const notices: string[] = [];
+    conn.onNotice((message, from) => { notices.push(`${from}: ${message}`); });
+
+    await conn.connect();
+    const ws = mockWsInstances[0];
+    ws.simulateOpen();
+
+    ws.simulateMessage(JSON.stringify({ type: "notice", message: "Restarting at 18:00", from: "admin" }));
+
+    expect(notices).toEqual(["admin: Restarting at 18:00"]);
+    expect(ws.readyState).toBe(MockWebSocket.OPEN);
+  });
+
+  it("scheduled session ends reach the callback until called off", async () => {
+    const conn = new BeamConnection("test-session", "test-token");
+    const updates: (number | null)[] = [];
+    conn.onTermination((endsInSecs) => { updates.push(endsInSecs); });
+
+    await conn.connect();
+    const ws = mockWsInstances[0];
+    ws.simulateOpen();
+
+    ws.simulateMessage(JSON.stringify({ type: "termination_scheduled", ends_in_secs: 300, from: "admin" }));
+    ws.simulateMessage(JSON.stringify({ type: "termination_scheduled", ends_in_secs: 60, from: "admin" }));
+    ws.simulateMessage(JSON.stringify({ type: "termination_cancelled", from: "admin" }));
+
+    expect(updates).toEqual([300, 60, null]);
+    expect(ws.readyState).toBe(MockWebSocket.OPEN);
+  });
+
+  it("assist declined at the other end stops reconnection", async () => {
+    const conn = new BeamConnection("test-session", "test-token");
+    let endedReason: string | null = null;
+    let reconnectingCalled = false;
+    conn.onAssistEnded((reason) => { endedReason = reason; });
+    conn.onReconnecting(() => { reconnectingCalled = true; });
+
+    await conn.connect();
+    const ws = mockWsInstances[0];
+    ws.simulateOpen();
+
+    ws.simulateMessage(JSON.stringify({ type: "error", message: "assist_declined" }));
+
+    await vi.advanceTimersByTimeAsync(5000);
+    expect(endedReason).toBe("assist_declined");
+    expect(reconnectingCalled).toBe(false);
+  });
+
+  it("idle disconnect stops reconnection", async () => {
+    const conn = new BeamConnection("test-session", "test-token");
+    let destroyed: boolean | null = null;
+    let reconnectingCalled = false;
+    conn.onIdleTimeout((d) => { destroyed = d; });
+    conn.onReconnecting(() => { reconnectingCalled = true; });
+
+    await conn.connect();
+    const ws = mockWsInstances[0];
+    ws.simulateOpen();
+
+    ws.simulateMessage(JSON.stringify({ type: "error", message: "idle_destroy" }));
+
+    await vi.advanceTimersByTimeAsync(5000);
+    expect(destroyed).toBe(true);
+    expect(reconnectingCalled).toBe(false);
+  });
+
+  it("sends hello on open and stops on protocol_unsupported", async () => {
+    const conn = new BeamConnection("test-session", "test-token");
+    let unsupportedCalled = false;
+    let reconnectingCalled = false;
+    conn.onProtocolUnsupported(() => { unsupportedCalled = true; });
+    conn.onReconnecting(() => { reconnectingCalled = true; });
+
+    await conn.connect();
+    const ws = mockWsInstances[0];
+    ws.simulateOpen();
+    expect(JSON.parse(ws.sent[0])).toEqual({ t: "hello", version: PROTOCOL_VERSION, capabilities: [] });
+
+    ws.simulateMessage(JSON.stringify({ t: "hello", version: PROTOCOL_VERSION + 1, capabilities: [] }));
+    expect(conn.serverProtocolVersion).toBe(PROTOCOL_VERSION + 1);
+
+    ws.simulateMessage(JSON.stringify({ type: "error", message: "protocol_unsupported" }));
+    await vi.advanceTimersByTimeAsync(5000);
+    expect(unsupportedCalled).toBe(true);
+    expect(reconnectingCalled).toBe(false);
+  });
+
   it("reconnect counter resets on successful connection", async () => {
     const conn = new BeamConnection("test-session", "test-token");
     const reconnectAttempts: number[] = [];
@@ -5,10 +5,15 @@
  * messages with a 24-byte header. Input events and signaling messages are
  * sent/received as JSON text messages.
  *
+ * With the `beam-cbor` subprotocol, JSON messages travel as CBOR binary
+ * messages instead; they are told apart from media frames by their first
+ * byte (a CBOR map, 0xa0-0xbf).
+ *
  * Binary frame header format (24 bytes, little-endian):
  *   [0..4]   magic: 0x56414542 ("BEAV" in LE)
  *   [4]      version: 1
- *   [5]      flags: bit 0 = keyframe, bit 1 = audio
+ *   [5]      flags: bit 0 = keyframe, bit 1 = audio, bit 2 = 4:4:4 chroma,
+ *                   bit 3 = webcam (browser -> agent), bit 4 = second screen
  *   [6..8]   width (u16 LE)
  *   [8..10]  height (u16 LE)
  *   [10..12] reserved (u16, always 0)
@@ -17,9 +22,14 @@
  *   [24..]   payload
  */
 
+import { CBOR_SUBPROTOCOL, decodeCbor, encodeCbor, isCborMessage } from "./cbor";
+
 export const FRAME_HEADER_SIZE = 24;
 export const FRAME_MAGIC = 0x56414542; // "BEAV" in little-endian
 
+/** Signaling protocol version, matching PROTOCOL_VERSION in crates/protocol */
+export const PROTOCOL_VERSION = 1;
+
 /** Parsed binary frame header */
 export interface FrameHeader {
   flags: number;
@@ -64,33 +74,106 @@ export function parseFrameHeader(
 /**
  * Input events sent over the WebSocket as JSON text.
  * Compact wire format matching the Rust InputEvent enum (serde tag = "t").
+ * User input may carry `at`, its `Event.timeStamp`, for agent-side pacing.
  */
 export type InputEvent =
-  | { t: "k"; c: number; d: boolean }
-  | { t: "m"; x: number; y: number }
-  | { t: "rm"; dx: number; dy: number }
-  | { t: "b"; b: number; d: boolean }
-  | { t: "s"; dx: number; dy: number }
+  | { t: "k"; c: number; d: boolean; at?: number }
+  | { t: "m"; x: number; y: number; at?: number }
+  | { t: "rm"; dx: number; dy: number; at?: number }
+  | { t: "b"; b: number; d: boolean; at?: number }
+  | { t: "s"; dx: number; dy: number; at?: number }
   | { t: "c"; text: string }
   | { t: "cp"; text: string }
-  | { t: "r"; w: number; h: number }
-  | { t: "l"; layout: string }
-  | { t: "q"; mode: string }
+  | { t: "chl"; entries?: { id: number; text: string; at: number }[] }
+  | { t: "chp"; id: number }
+  | { t: "chc" }
+  | { t: "r"; w: number; h: number; s?: number }
+  | { t: "sa"; w: number; h: number }
+  | { t: "sd" }
+  | { t: "m2"; x: number; y: number; at?: number }
+  | { t: "l"; layout: string; variant?: string; options?: string[] }
+  | {
+      t: "ds";
+      rotation?: DisplayRotation;
+      refresh?: number;
+      modes?: string[];
+      /** Screen size, in the agent's reply */
+      w?: number;
+      h?: number;
+    }
+  | { t: "q"; mode: QualityPreset }
   | { t: "vs"; visible: boolean }
-  | { t: "cur"; css: string }
+  | { t: "cm"; composite: boolean }
+  | { t: "pl"; locked: boolean }
+  | { t: "kg"; grab: boolean }
+  | { t: "kc"; combo: string }
+  | { t: "ou"; url: string }
+  | { t: "fa"; ts: number; dd: number }
+  | { t: "ae"; enabled: boolean }
+  | { t: "am"; mode: string }
+  | { t: "ao"; frame_ms?: number; fec?: boolean; dtx?: boolean }
+  | { t: "ar"; played_ms: number; concealed_ms: number }
+  | { t: "wcs"; codec: string; w: number; h: number }
+  | { t: "wce" }
+  | { t: "wcst"; active: boolean; device?: string; error?: string }
+  | { t: "asl"; sinks?: { name: string; description: string }[]; apps?: AudioApp[] }
+  | { t: "as"; sink?: string; app?: number }
+  | { t: "av"; app: number; volume?: number; muted?: boolean }
+  | { t: "idle"; action: "lock" | "disconnect" | "destroy" }
+  | { t: "assist"; control: boolean }
+  | { t: "nq"; level: "good" | "fair" | "poor"; rtt_ms: number | null; loss_pct: number; kbps: number; target_kbps: number }
+  | { t: "va"; kind: "not_delivered" | "capture_stalled"; active: boolean; secs: number; recover?: boolean }
+  | { t: "rc"; attempt: number }
+  | { t: "qf"; requested: QualityPreset; effective: QualityPreset; kbps: number; ceiling_kbps: number }
+  | { t: "lat"; samples: number; p50_ms: number; p95_ms: number; p99_ms: number; pipeline_p50_ms: number; network_p50_ms: number }
+  | { t: "sr"; clock_us: number; audio_us: number }
+  | { t: "s2"; w: number; h: number }
+  | { t: "dl" }
+  | { t: "dr" }
+  | { t: "cur"; css: string; png?: string; hx?: number; hy?: number }
   | { t: "fs"; id: string; name: string; size: number }
   | { t: "fc"; id: string; data: string }
   | { t: "fd"; id: string }
   | { t: "fdr"; path: string }
+  | { t: "ib"; e: TimedInput[] }
   | { t: "fds"; id: string; name: string; size: number }
   | { t: "fdc"; id: string; data: string }
   | { t: "fdd"; id: string }
   | { t: "fde"; id: string; error: string };
 
+/** An application playing in the session; `volume` is in percent */
+export interface AudioApp {
+  index: number;
+  name: string;
+  volume: number;
+  muted: boolean;
+}
+
+/** Video quality preset, QualityPreset in crates/protocol */
+export type QualityPreset = "smooth" | "balanced" | "sharp" | "lossless";
+
+/** Orientation of the session's monitor, in xrandr's names */
+export type DisplayRotation = "normal" | "left" | "inverted" | "right";
+
+/** An input event with its `Event.timeStamp`, as carried by an input batch */
+export interface TimedInput {
+  ts: number;
+  ev: InputEvent;
+}
+
+/** Most events one input batch may carry (MAX_INPUT_BATCH in crates/protocol) */
+export const MAX_INPUT_BATCH = 256;
+
 /** Signaling/control messages received as JSON text from the server */
 type ServerMessage =
   | { type: "session_ready" }
-  | { type: "error"; message: string };
+  | { type: "error"; message: string }
+  | { type: "notice"; message: string; from: string }
+  | { type: "termination_scheduled"; ends_in_secs: number; from: string }
+  | { type: "termination_cancelled"; from: string };
+
+/** Framing for signaling messages; CBOR is used only if the server agrees */
+export type WireFormat = "json" | "cbor";
 
 type VoidCallback = () => void;
 type VideoFrameCallback = (
@@ -129,10 +212,33 @@ export class BeamConnection {
   private agentMessageCallback: ((msg: InputEvent) => void) | null = null;
   private replacedCallback: VoidCallback | null = null;
   private agentExitedCallback: VoidCallback | null = null;
-
-  constructor(sessionId: string, token: string) {
+  private idleTimeoutCallback: ((destroyed: boolean) => void) | null = null;
+  private protocolUnsupportedCallback: VoidCallback | null = null;
+  private shareEndedCallback: ((reason: string) => void) | null = null;
+  private assistEndedCallback: ((reason: string) => void) | null = null;
+  private noticeCallback: ((message: string, from: string) => void) | null = null;
+  private terminationCallback: ((endsInSecs: number | null, from: string) => void) | null = null;
+  /** Version from the server's hello, null until it arrives */
+  serverProtocolVersion: number | null = null;
+
+  private wireFormat: WireFormat;
+  /** Whether the current socket negotiated `beam-cbor` */
+  private cbor = false;
+
+  constructor(sessionId: string, token: string, wireFormat: WireFormat = "json") {
     this.sessionId = sessionId;
     this.token = token;
+    this.wireFormat = wireFormat;
+  }
+
+  /** Token of the view-only share link this connection watches through, if any */
+  private shareToken: string | null = null;
+
+  /** A watch-only connection through a share link: video and audio only */
+  static viewer(shareToken: string): BeamConnection {
+    const connection = new BeamConnection("", "");
+    connection.shareToken = shareToken;
+    return connection;
   }
 
   /** Register callback for decoded video frames */
@@ -180,6 +286,41 @@ export class BeamConnection {
     this.agentExitedCallback = callback;
   }
 
+  /** Register callback for when the session's idle policy disconnected this
+   *  browser. `destroyed` is true if the session was ended too. */
+  onIdleTimeout(callback: (destroyed: boolean) => void): void {
+    this.idleTimeoutCallback = callback;
+  }
+
+  /** Register callback for when the server no longer speaks this client's
+   *  protocol version (the page is older than the server) */
+  onProtocolUnsupported(callback: VoidCallback): void {
+    this.protocolUnsupportedCallback = callback;
+  }
+
+  /** Register callback for when a share link viewer is turned away or its
+   *  link was revoked or expired ("share_ended", "too_many_viewers") */
+  onShareEnded(callback: (reason: string) => void): void {
+    this.shareEndedCallback = callback;
+  }
+
+  /** Register callback for when the person at an assisted display declined
+   *  the request or ended the session ("assist_declined", "assist_ended") */
+  onAssistEnded(callback: (reason: string) => void): void {
+    this.assistEndedCallback = callback;
+  }
+
+  /** Register callback for messages an admin sends the session's user */
+  onNotice(callback: (message: string, from: string) => void): void {
+    this.noticeCallback = callback;
+  }
+
+  /** Register callback for an end an admin scheduled for the session
+   *  (seconds left) or called off (null) */
+  onTermination(callback: (endsInSecs: number | null, from: string) => void): void {
+    this.terminationCallback = callback;
+  }
+
   /** Update the token (after refresh) so reconnections use the new one */
   updateToken(token: string): void {
     this.token = token;
@@ -198,10 +339,26 @@ export class BeamConnection {
     this.cleanup();
   }
 
-  /** Send an input event as JSON text over WebSocket */
+  /** Drop the WebSocket and connect again, as after a network blip */
+  reconnect(): void {
+    this.ws?.close();
+  }
+
+  /** Send an input event over WebSocket (JSON text, or CBOR if negotiated) */
   sendInput(event: InputEvent): void {
+    this.sendMessage(event);
+  }
+
+  private sendMessage(msg: object): void {
     if (this.ws?.readyState === WebSocket.OPEN) {
-      this.ws.send(JSON.stringify(event));
+      this.ws.send(this.cbor ? encodeCbor(msg) : JSON.stringify(msg));
+    }
+  }
+
+  /** Send a binary frame (browser webcam) to the agent. */
+  sendBinary(data: ArrayBuffer): void {
+    if (this.ws?.readyState === WebSocket.OPEN) {
+      this.ws.send(data);
     }
   }
 
@@ -209,9 +366,11 @@ export class BeamConnection {
     this.cleanup();
 
     const wsProtocol = location.protocol === "https:" ? "wss:" : "ws:";
-    const wsUrl = `${wsProtocol}//${location.host}/api/sessions/${this.sessionId}/ws?token=${encodeURIComponent(this.token)}`;
+    const wsUrl = this.shareToken !== null
+      ? `${wsProtocol}//${location.host}/api/share/${encodeURIComponent(this.shareToken)}/ws`
+      : `${wsProtocol}//${location.host}/api/sessions/${this.sessionId}/ws?token=${encodeURIComponent(this.token)}`;
 
-    this.ws = new WebSocket(wsUrl);
+    this.ws = this.wireFormat === "cbor" ? new WebSocket(wsUrl, [CBOR_SUBPROTOCOL]) : new WebSocket(wsUrl);
     this.ws.binaryType = "arraybuffer";
 
     let wsOpened = false;
@@ -219,11 +378,17 @@ export class BeamConnection {
     this.ws.onopen = () => {
       wsOpened = true;
       this.reconnectAttempt = 0;
+      this.cbor = this.ws?.protocol === CBOR_SUBPROTOCOL;
+      this.sendMessage({ t: "hello", version: PROTOCOL_VERSION, capabilities: [] });
       this.connectedCallback?.();
     };
 
     this.ws.onmessage = (event: MessageEvent) => {
       if (event.data instanceof ArrayBuffer) {
+        if (this.cbor && isCborMessage(event.data)) {
+          this.handleCborMessage(event.data);
+          return;
+        }
         this.handleBinaryMessage(event.data);
       } else if (typeof event.data === "string") {
         this.handleTextMessage(event.data);
@@ -286,7 +451,22 @@ export class BeamConnection {
       console.warn("Failed to parse text message:", data);
       return;
     }
+    this.handleMessage(msg);
+  }
 
+  /** Handle a signaling message that arrived CBOR-encoded */
+  private handleCborMessage(data: ArrayBuffer): void {
+    let msg: any;
+    try {
+      msg = decodeCbor(data);
+    } catch (e) {
+      console.warn("Failed to decode CBOR message:", e);
+      return;
+    }
+    this.handleMessage(msg);
+  }
+
+  private handleMessage(msg: any): void {
     // Server signaling messages
     if (msg.type === "error") {
       const serverMsg = msg as ServerMessage & { type: "error" };
@@ -304,10 +484,63 @@ export class BeamConnection {
         this.agentExitedCallback?.();
         return;
       }
+      if (serverMsg.message === "protocol_unsupported") {
+        console.error(`Server does not support protocol version ${PROTOCOL_VERSION}`);
+        this.intentionalDisconnect = true;
+        this.cleanup();
+        this.protocolUnsupportedCallback?.();
+        return;
+      }
+      if (serverMsg.message === "idle_disconnect" || serverMsg.message === "idle_destroy") {
+        console.log(`Disconnected by idle policy (${serverMsg.message})`);
+        this.intentionalDisconnect = true;
+        this.cleanup();
+        this.idleTimeoutCallback?.(serverMsg.message === "idle_destroy");
+        return;
+      }
+      if (serverMsg.message === "share_ended" || serverMsg.message === "too_many_viewers") {
+        console.log(`Share link viewer disconnected (${serverMsg.message})`);
+        this.intentionalDisconnect = true;
+        this.cleanup();
+        this.shareEndedCallback?.(serverMsg.message);
+        return;
+      }
+      if (serverMsg.message === "assist_declined" || serverMsg.message === "assist_ended") {
+        console.log(`Remote assistance ended at the other end (${serverMsg.message})`);
+        this.intentionalDisconnect = true;
+        this.cleanup();
+        this.assistEndedCallback?.(serverMsg.message);
+        return;
+      }
       console.error("Server error:", serverMsg.message);
       return;
     }
 
+    if (msg.type === "notice") {
+      const notice = msg as ServerMessage & { type: "notice" };
+      this.noticeCallback?.(notice.message, notice.from);
+      return;
+    }
+
+    if (msg.type === "termination_scheduled") {
+      const scheduled = msg as ServerMessage & { type: "termination_scheduled" };
+      this.terminationCallback?.(scheduled.ends_in_secs, scheduled.from);
+      return;
+    }
+
+    if (msg.type === "termination_cancelled") {
+      this.terminationCallback?.(null, (msg as ServerMessage & { type: "termination_cancelled" }).from);
+      return;
+    }
+
+    if (msg.t === "hello") {
+      this.serverProtocolVersion = msg.version;
+      if (msg.version !== PROTOCOL_VERSION) {
+        console.log(`Server speaks protocol version ${msg.version}, client ${PROTOCOL_VERSION}`);
+      }
+      return;
+    }
+
     // Agent-to-browser messages (clipboard, cursor, file download events)
     // These have a "t" field matching the InputEvent discriminator
     if (msg.t) {
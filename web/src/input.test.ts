@@ -2,7 +2,7 @@ import { describe, it, expect, vi, beforeEach } from "vitest";
 import { roundToEven, isSignificantResize } from "./resize";
 import type { InputEvent } from "./connection";
 
-import { InputHandler } from "./input";
+import { InputHandler, layoutMessage } from "./input";
 
 describe("roundToEven", () => {
   it("rounds odd numbers down to even", () => {
@@ -195,4 +195,44 @@ describe("InputHandler scroll multiplier", () => {
     expect(sent).toHaveLength(1);
     expect(sent[0]).toEqual({ t: "s", dx: 600, dy: 600 });
   });
+
+  it("pointer moves within one frame go out as a single input batch", () => {
+    const frames: FrameRequestCallback[] = [];
+    vi.stubGlobal("requestAnimationFrame", (cb: FrameRequestCallback) => frames.push(cb));
+    (handler as any).pointerLocked = true;
+    const mouseMove = (handler as any).onMouseMove as (e: MouseEvent) => void;
+
+    mouseMove({ movementX: 1, movementY: 0, timeStamp: 10 } as MouseEvent);
+    mouseMove({ movementX: 2, movementY: -1, timeStamp: 11 } as MouseEvent);
+    expect(sent).toHaveLength(0);
+    expect(frames).toHaveLength(1);
+
+    frames[0](0);
+    expect(sent).toEqual([
+      {
+        t: "ib",
+        e: [
+          { ts: 10, ev: { t: "rm", dx: 1, dy: 0 } },
+          { ts: 11, ev: { t: "rm", dx: 2, dy: -1 } },
+        ],
+      },
+    ]);
+
+    // A lone move is sent as itself, stamped
+    mouseMove({ movementX: 5, movementY: 5, timeStamp: 30 } as MouseEvent);
+    frames[1](0);
+    expect(sent[1]).toEqual({ t: "rm", dx: 5, dy: 5, at: 30 });
+  });
+});
+
+describe("layoutMessage", () => {
+  it("splits off an XKB variant", () => {
+    expect(layoutMessage("no")).toEqual({ t: "l", layout: "no" });
+    expect(layoutMessage("us(intl)")).toEqual({ t: "l", layout: "us", variant: "intl" });
+    expect(layoutMessage("de(nodeadkeys)")).toEqual({
+      t: "l",
+      layout: "de",
+      variant: "nodeadkeys",
+    });
+  });
 });
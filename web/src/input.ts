@@ -1,4 +1,4 @@
-import type { InputEvent } from "./connection";
+import { MAX_INPUT_BATCH, type InputEvent, type TimedInput } from "./connection";
 import { keyCodeToEvdev } from "./keymap";
 import { isBrowserShortcut, isMac } from "./platform";
 import { roundToEven, isSignificantResize } from "./resize";
@@ -87,6 +87,15 @@ async function detectKeyboardLayout(): Promise<string> {
   return "";
 }
 
+/**
+ * Layout message for a layout choice written the way XKB writes one:
+ * "de" or, with a variant, "de(nodeadkeys)".
+ */
+export function layoutMessage(choice: string): InputEvent {
+  const match = /^([^()]+)\(([^()]+)\)$/.exec(choice);
+  return match ? { t: "l", layout: match[1], variant: match[2] } : { t: "l", layout: choice };
+}
+
 /**
  * Captures keyboard, mouse, and wheel events from the browser
  * and forwards them as compact InputEvents to the remote desktop.
@@ -104,6 +113,7 @@ export class InputHandler {
   private sendInput: (event: InputEvent) => void;
   private active = false;
   private pointerLocked = false;
+  private keyboardGrabbed = false;
   private scrollMultiplier = 1.0;
 
   /** When true, intercept browser shortcuts (Ctrl+W, Ctrl+T, etc.) and forward them to remote */
@@ -115,6 +125,7 @@ export class InputHandler {
   private lastSentW = 0;
   private lastSentH = 0;
   private resizeNeededCallback: (() => void) | null = null;
+  private primarySync: (() => Promise<void>) | null = null;
 
   // Touch input state
   private longPressTimer: ReturnType<typeof setTimeout> | null = null;
@@ -139,8 +150,8 @@ export class InputHandler {
   private onTouchEnd = this.handleTouchEnd.bind(this);
 
   // Coalescing state
-  private pendingMouseMove: { x: number; y: number } | null = null;
-  private pendingRelativeMouseMove: { dx: number; dy: number } | null = null;
+  /** Pointer moves since the last animation frame, sent together as one batch */
+  private pendingMoves: TimedInput[] = [];
   private animationFrameId: number | null = null;
 
   private resizeObserver: ResizeObserver | null = null;
@@ -166,7 +177,7 @@ export class InputHandler {
     this.lastSentW = w;
     this.lastSentH = h;
     if (w > 0 && h > 0) {
-      this.sendInput({ t: "r", w, h });
+      this.sendInput({ t: "r", w, h, s: window.devicePixelRatio });
     }
   }
 
@@ -183,6 +194,14 @@ export class InputHandler {
     this.resizeNeededCallback = callback;
   }
 
+  /**
+   * Use `sync` to set the remote PRIMARY selection before a middle-click
+   * instead of sending the browser clipboard unconditionally.
+   */
+  setPrimarySync(sync: () => Promise<void>): void {
+    this.primarySync = sync;
+  }
+
   /** Send keyboard layout to remote agent. Uses saved preference, auto-detection, or fallback. */
   async sendLayout(): Promise<void> {
     const saved = localStorage.getItem("beam_keyboard_layout");
@@ -197,7 +216,7 @@ export class InputHandler {
         console.log("Could not detect keyboard layout, defaulting to US");
       }
     }
-    this.sendInput({ t: "l", layout });
+    this.sendInput(layoutMessage(layout));
     // Update the selector if it exists
     const select = document.getElementById("layout-select") as HTMLSelectElement | null;
     if (select && select.value !== layout) {
@@ -207,7 +226,7 @@ export class InputHandler {
 
   /** Send a specific keyboard layout to remote agent */
   sendSpecificLayout(layout: string): void {
-    this.sendInput({ t: "l", layout });
+    this.sendInput(layoutMessage(layout));
   }
 
   /** Set the scroll speed multiplier (applied to wheel deltas before sending) */
@@ -268,8 +287,7 @@ export class InputHandler {
       cancelAnimationFrame(this.animationFrameId);
       this.animationFrameId = null;
     }
-    this.pendingMouseMove = null;
-    this.pendingRelativeMouseMove = null;
+    this.pendingMoves = [];
 
     // Hide local cursor
     this.localCursor?.classList.remove("visible");
@@ -278,6 +296,9 @@ export class InputHandler {
     if (this.pointerLocked && document.pointerLockElement) {
       document.exitPointerLock();
     }
+    if (this.pointerLocked) {
+      this.sendInput({ t: "pl", locked: false });
+    }
     this.pointerLocked = false;
     this.firstFrameReceived = false;
     this.lastSentW = 0;
@@ -347,7 +368,7 @@ export class InputHandler {
     if (evdev === undefined) return;
 
     e.preventDefault();
-    this.sendInput({ t: "k", c: evdev, d: true });
+    this.sendInput({ t: "k", c: evdev, d: true, at: e.timeStamp });
   }
 
   private handleKeyUp(e: KeyboardEvent): void {
@@ -373,7 +394,7 @@ export class InputHandler {
     if (evdev === undefined) return;
 
     e.preventDefault();
-    this.sendInput({ t: "k", c: evdev, d: false });
+    this.sendInput({ t: "k", c: evdev, d: false, at: e.timeStamp });
   }
 
   // --- Keyboard helpers ---
@@ -444,44 +465,59 @@ export class InputHandler {
 
   private handleMouseMove(e: MouseEvent): void {
     if (this.pointerLocked) {
-      // Pointer lock: aggregate pixel deltas
+      // Pointer lock: raw pixel deltas
       const dx = e.movementX;
       const dy = e.movementY;
       if (dx !== 0 || dy !== 0) {
-        if (!this.pendingRelativeMouseMove) {
-          this.pendingRelativeMouseMove = { dx, dy };
-        } else {
-          this.pendingRelativeMouseMove.dx += dx;
-          this.pendingRelativeMouseMove.dy += dy;
-        }
-        this.scheduleFrame();
+        this.queueMove(e.timeStamp, { t: "rm", dx, dy });
       }
     } else {
       // Normal: send absolute coordinates
       const coords = this.getVideoCoords(e);
       if (coords) {
-        this.pendingMouseMove = coords;
-        this.scheduleFrame();
+        this.queueMove(e.timeStamp, { t: "m", x: coords.x, y: coords.y });
       }
     }
   }
 
+  /** Queue a pointer move for the next animation frame. Every move is kept
+   *  with its timestamp; the agent merges them at its own rate. */
+  private queueMove(ts: number, ev: InputEvent): void {
+    if (this.pendingMoves.length >= MAX_INPUT_BATCH) {
+      this.pendingMoves.shift();
+    }
+    this.pendingMoves.push({ ts, ev });
+    this.scheduleFrame();
+  }
+
   private scheduleFrame(): void {
     if (this.animationFrameId !== null) return;
     this.animationFrameId = requestAnimationFrame(() => {
       this.animationFrameId = null;
-      if (this.pendingMouseMove) {
-        this.sendInput({ t: "m", x: this.pendingMouseMove.x, y: this.pendingMouseMove.y });
-        this.updateLocalCursor(this.pendingMouseMove.x, this.pendingMouseMove.y);
-        this.pendingMouseMove = null;
-      }
-      if (this.pendingRelativeMouseMove) {
-        this.sendInput({ t: "rm", dx: this.pendingRelativeMouseMove.dx, dy: this.pendingRelativeMouseMove.dy });
-        this.pendingRelativeMouseMove = null;
-      }
+      this.flushMoves();
     });
   }
 
+  /** Send queued moves: a lone move as itself, several as one input batch */
+  private flushMoves(): void {
+    const moves = this.pendingMoves;
+    this.pendingMoves = [];
+    if (moves.length === 0) return;
+    if (moves.length === 1) {
+      this.sendInput({ ...moves[0].ev, at: moves[0].ts } as InputEvent);
+    } else {
+      this.sendInput({ t: "ib", e: moves });
+    }
+
+    for (let i = moves.length - 1; i >= 0; i--) {
+      const ev = moves[i].ev;
+      if (ev.t === "m") {
+        this.updateLocalCursor(ev.x, ev.y);
+        break;
+      }
+    }
+  }
+
   /** Update local cursor visual position (0-1 normalized coordinates) */
   private updateLocalCursor(x: number, y: number): void {
     if (!this.localCursor || this.pointerLocked) return;
@@ -519,8 +555,8 @@ export class InputHandler {
     const coords = this.getVideoCoords(e);
     if (coords) {
       // Send coordinates immediately for clicks to ensure accuracy
-      this.sendInput({ t: "m", x: coords.x, y: coords.y });
-      this.pendingMouseMove = null;
+      this.sendInput({ t: "m", x: coords.x, y: coords.y, at: e.timeStamp });
+      this.pendingMoves = [];
     }
 
     // Middle-click (button 1): try to sync browser clipboard to the remote
@@ -531,7 +567,7 @@ export class InputHandler {
     if (e.button === 1) {
       this.sendPrimaryClipboardThenButton(e.button);
     } else {
-      this.sendInput({ t: "b", b: e.button, d: true });
+      this.sendInput({ t: "b", b: e.button, d: true, at: e.timeStamp });
     }
   }
 
@@ -542,6 +578,10 @@ export class InputHandler {
    * immediately without clipboard data.
    */
   private sendPrimaryClipboardThenButton(button: number): void {
+    if (this.primarySync) {
+      this.primarySync().finally(() => this.sendInput({ t: "b", b: button, d: true }));
+      return;
+    }
     const MAX_CLIPBOARD_BYTES = 1_048_576; // 1 MB
     navigator.clipboard
       .readText()
@@ -559,7 +599,9 @@ export class InputHandler {
 
   private handleMouseUp(e: MouseEvent): void {
     e.preventDefault();
-    this.sendInput({ t: "b", b: e.button, d: false });
+    // The end of a drag lands before the release, keeping timestamps in order
+    this.flushMoves();
+    this.sendInput({ t: "b", b: e.button, d: false, at: e.timeStamp });
   }
 
   private handleWheel(e: WheelEvent): void {
@@ -579,7 +621,7 @@ export class InputHandler {
     dx *= this.scrollMultiplier;
     dy *= this.scrollMultiplier;
 
-    this.sendInput({ t: "s", dx, dy });
+    this.sendInput({ t: "s", dx, dy, at: e.timeStamp });
   }
 
   private handleContextMenu(e: Event): void {
@@ -598,12 +640,58 @@ export class InputHandler {
   }
 
   private handlePointerLockChange(): void {
-    this.pointerLocked = document.pointerLockElement === this.target;
+    const locked = document.pointerLockElement === this.target;
+    if (locked !== this.pointerLocked && this.active) {
+      // Agent switches to pure relative injection while locked
+      this.sendInput({ t: "pl", locked });
+    }
+    this.pointerLocked = locked;
     if (this.pointerLocked && this.localCursor) {
       this.localCursor.classList.remove("visible");
     }
   }
 
+  /** Apply the pointer lock state reported by the agent. */
+  handleRemotePointerLock(locked: boolean): void {
+    // Agent couldn't enter relative mode: release the browser lock too
+    if (!locked && this.pointerLocked && document.pointerLockElement) {
+      document.exitPointerLock();
+    }
+  }
+
+  // --- Keyboard grab ---
+
+  /**
+   * While fullscreen with shortcut forwarding enabled, take the Keyboard
+   * Lock (Chromium only) so Alt+Tab, Super and Escape reach the remote.
+   */
+  private updateKeyboardGrab(): void {
+    const keyboard = (navigator as Navigator & {
+      keyboard?: { lock(keys?: string[]): Promise<void>; unlock(): void };
+    }).keyboard;
+    if (!keyboard) return;
+    const grab = !!document.fullscreenElement && this.forwardBrowserShortcuts && this.active;
+    if (grab === this.keyboardGrabbed) return;
+    if (grab) {
+      keyboard.lock().then(
+        () => {
+          this.keyboardGrabbed = true;
+          this.sendInput({ t: "kg", grab: true });
+        },
+        () => {},
+      );
+    } else {
+      keyboard.unlock();
+      this.keyboardGrabbed = false;
+      this.sendInput({ t: "kg", grab: false });
+    }
+  }
+
+  /** Ask the agent to synthesize a system combo (e.g. "ctrl-alt-del"). */
+  sendKeyCombo(combo: string): void {
+    this.sendInput({ t: "kc", combo });
+  }
+
   // --- Resize ---
 
   /**
@@ -612,6 +700,7 @@ export class InputHandler {
    * have settled yet), container dimensions when exiting.
    */
   private handleFullscreenChange(): void {
+    this.updateKeyboardGrab();
     if (!this.firstFrameReceived) return;
     // Give the browser time to settle the fullscreen layout
     setTimeout(() => {
@@ -641,7 +730,7 @@ export class InputHandler {
         const significant = isSignificantResize(this.lastSentW, this.lastSentH, w, h);
         this.lastSentW = w;
         this.lastSentH = h;
-        this.sendInput({ t: "r", w, h });
+        this.sendInput({ t: "r", w, h, s: window.devicePixelRatio });
         if (significant) {
           this.resizeNeededCallback?.();
         }
@@ -661,7 +750,7 @@ export class InputHandler {
       const significant = isSignificantResize(this.lastSentW, this.lastSentH, ew, eh);
       this.lastSentW = ew;
       this.lastSentH = eh;
-      this.sendInput({ t: "r", w: ew, h: eh });
+      this.sendInput({ t: "r", w: ew, h: eh, s: window.devicePixelRatio });
       if (significant) {
         this.resizeNeededCallback?.();
       }
@@ -723,8 +812,8 @@ export class InputHandler {
     const coords = this.getTouchVideoCoords(touch);
     if (coords) {
       // Send coordinates immediately for clicks to ensure accuracy
-      this.sendInput({ t: "m", x: coords.x, y: coords.y });
-      this.pendingMouseMove = null;
+      this.sendInput({ t: "m", x: coords.x, y: coords.y, at: e.timeStamp });
+      this.pendingMoves = [];
     }
 
     // Start long-press timer for right-click
@@ -743,7 +832,7 @@ export class InputHandler {
     }, InputHandler.LONG_PRESS_MS);
 
     // Send left button down
-    this.sendInput({ t: "b", b: 0, d: true });
+    this.sendInput({ t: "b", b: 0, d: true, at: e.timeStamp });
   }
 
   private handleTouchMove(e: TouchEvent): void {
@@ -765,8 +854,7 @@ export class InputHandler {
 
     const coords = this.getTouchVideoCoords(touch);
     if (coords) {
-      this.pendingMouseMove = coords;
-      this.scheduleFrame();
+      this.queueMove(e.timeStamp, { t: "m", x: coords.x, y: coords.y });
     }
   }
 
@@ -776,7 +864,8 @@ export class InputHandler {
 
     // Don't send button up if long press fired (it already sent right-click)
     if (!this.longPressTriggered) {
-      this.sendInput({ t: "b", b: 0, d: false });
+      this.flushMoves();
+      this.sendInput({ t: "b", b: 0, d: false, at: e.timeStamp });
     }
     this.longPressTriggered = false;
   }
@@ -61,6 +61,90 @@ export function startRateLimitCountdown(seconds: number): void {
   }, 1000);
 }
 
+/** Bandwidth probe result sent with the login request */
+interface NetTestResult {
+  kbps: number;
+  rtt_ms: number;
+}
+
+const NETTEST_PAYLOAD_BYTES = 4 * 1024 * 1024;
+const NETTEST_TIMEOUT_MS = 4000;
+
+/** Measure RTT (best of three empty requests) and download throughput
+ *  against /api/nettest with the session's token. Returns null if the probe
+ *  fails or times out; the session then keeps its configured defaults. */
+async function measureLink(token: string): Promise<NetTestResult | null> {
+  const controller = new AbortController();
+  const timer = setTimeout(() => controller.abort(), NETTEST_TIMEOUT_MS);
+  const probe = async (bytes: number): Promise<ArrayBuffer> => {
+    const response = await fetch(`/api/nettest?bytes=${bytes}`, {
+      cache: "no-store",
+      headers: { Authorization: `Bearer ${token}` },
+      signal: controller.signal,
+    });
+    if (!response.ok) throw new Error(`nettest: ${response.status}`);
+    return response.arrayBuffer();
+  };
+  try {
+    let rtt = Infinity;
+    for (let i = 0; i < 3; i++) {
+      const start = performance.now();
+      await probe(0);
+      rtt = Math.min(rtt, performance.now() - start);
+    }
+
+    const start = performance.now();
+    const body = await probe(NETTEST_PAYLOAD_BYTES);
+    // Subtract one RTT for the request itself so short transfers on fast
+    // links aren't dominated by latency
+    const elapsedMs = Math.max(performance.now() - start - rtt, 1);
+    return {
+      kbps: Math.round((body.byteLength * 8) / elapsedMs),
+      rtt_ms: Math.round(rtt),
+    };
+  } catch {
+    return null;
+  } finally {
+    clearTimeout(timer);
+  }
+}
+
+/** Probe the link and have the server fit the session's stream to it, so
+ *  a slow link isn't sent more than it can carry. Failures are ignored. */
+async function fitSessionToLink(sessionId: string, token: string): Promise<void> {
+  const nettest = await measureLink(token);
+  if (!nettest) return;
+  try {
+    await fetch(`/api/sessions/${sessionId}/nettest`, {
+      method: "POST",
+      headers: { "Content-Type": "application/json", Authorization: `Bearer ${token}` },
+      body: JSON.stringify(nettest),
+    });
+  } catch {
+    // The session keeps its configured defaults
+  }
+}
+
+/** A `#assist` link asks for remote assistance on the server's own display
+ *  instead of a desktop of one's own (admins only). */
+export function assistRequested(): boolean {
+  return location.hash === "#assist";
+}
+
+/** The takeover question for a 409 login, naming where the session is open. */
+function describeSessionInUse(body: string): string {
+  let where = "another browser";
+  try {
+    const info = JSON.parse(body) as { client?: { ip?: string; country?: string } | null };
+    if (info.client?.ip) {
+      where += ` at ${info.client.ip}${info.client.country ? ` (${info.client.country})` : ""}`;
+    }
+  } catch {
+    // Keep the generic wording
+  }
+  return `Your session is open in ${where}. Disconnect it and continue here?`;
+}
+
 /** Perform the login API call and handle all response scenarios.
  *  Returns the LoginResponse on success, or null on failure. */
 export async function performLogin(
@@ -84,6 +168,8 @@ export async function performLogin(
 
   const MAX_RETRIES = 3;
   const BASE_DELAY = 1000;
+  // Set once the user agrees to disconnect a browser already on the session
+  let takeover = false;
 
   for (let attempt = 0; attempt <= MAX_RETRIES; attempt++) {
     try {
@@ -98,15 +184,20 @@ export async function performLogin(
           // Round down to even numbers (H.264 encoders require even dimensions).
           viewport_width: Math.floor(window.innerWidth / 2) * 2,
           viewport_height: Math.floor((window.innerHeight - 28) / 2) * 2,
-        }, sessionTimeoutSelect.value ? { idle_timeout: parseInt(sessionTimeoutSelect.value, 10) } : {})),
+        },
+        sessionTimeoutSelect.value ? { idle_timeout: parseInt(sessionTimeoutSelect.value, 10) } : {},
+        takeover ? { takeover: true } : {},
+        assistRequested() ? { assist: true } : {})),
       });
 
       if (!response.ok) {
         const text = await response.text();
         let message = "Authentication failed.";
+        let inUse = false;
         try {
-          const body = JSON.parse(text) as { error?: string };
+          const body = JSON.parse(text) as { error?: string; in_use?: boolean };
           if (body.error) message = body.error;
+          inUse = body.in_use ?? false;
         } catch {
           // Use default message
         }
@@ -127,6 +218,29 @@ export async function performLogin(
           return null;
         }
 
+        // 409: the session is open in another browser -- ask before taking it over
+        if (response.status === 409 && inUse && !takeover) {
+          if (confirm(describeSessionInUse(text))) {
+            takeover = true;
+            updateLoadingStatus("Taking over session...");
+            attempt--;
+            continue;
+          }
+          hideLoading();
+          showLoginError("Your session is still open in the other browser.");
+          setStatus("error", "Session in use");
+          return null;
+        }
+
+        // Remote assistance refused or unavailable (nobody at the display,
+        // someone else already helping) -- retrying won't change that
+        if (assistRequested() && (response.status === 403 || response.status === 409)) {
+          hideLoading();
+          showLoginError(message);
+          setStatus("error", message);
+          return null;
+        }
+
         // Client-side progressive warning (no server-side oracle)
         if (response.status === 401) {
           loginFailureCount++;
@@ -161,6 +275,10 @@ export async function performLogin(
       // Save timeout selection for next login
       localStorage.setItem(SESSION_TIMEOUT_KEY, sessionTimeoutSelect.value);
 
+      if (!data.assist) {
+        updateLoadingStatus("Measuring connection...");
+        await fitSessionToLink(data.session_id, data.token);
+      }
       updateLoadingStatus("Starting session...");
       return data;
     } catch (err) {
@@ -1,21 +1,26 @@
-import { ClipboardBridge, type ClipboardHistoryEntry } from "./clipboard";
-import { BeamConnection } from "./connection";
+import { ClipboardBridge, type ClipboardHistoryEntry, type ClipboardPolicy } from "./clipboard";
+import { BeamConnection, type AudioApp, type DisplayRotation, type InputEvent, type QualityPreset } from "./connection";
 import { FileDownloader, FileUploader } from "./filetransfer";
 import type { DownloadMessage } from "./filetransfer";
-import { ICON_CAPTURE, ICON_MUTE, ICON_UNMUTE } from "./icons";
+import { ICON_CAMERA, ICON_CAPTURE, ICON_CURSOR, ICON_MUTE, ICON_UNMUTE } from "./icons";
 import { InputHandler } from "./input";
 import { performLogin, clearRateLimitTimer } from "./login";
 import { WebCodecsRenderer } from "./webcodecs-renderer";
+import { WebcamStreamer } from "./webcam";
+import { FLAG_SECOND_SCREEN, SecondScreen } from "./second-screen";
 import {
-  loadSession, clearSession, sendReleaseBeacon, TokenManager,
+  loadSession, saveSession, clearSession, sendReleaseBeacon, TokenManager,
+  type LoginResponse,
 } from "./session";
 import {
   initTheme, toggleTheme, updateThemeButton,
-  THEME_KEY, AUDIO_MUTED_KEY, SCROLL_SPEED_KEY,
-  FORWARD_KEYS_KEY, SESSION_TIMEOUT_KEY,
+  THEME_KEY, AUDIO_MUTED_KEY, AUDIO_MODE_KEY, AUDIO_TUNING_KEY, SCROLL_SPEED_KEY,
+  FORWARD_KEYS_KEY, CURSOR_IN_VIDEO_KEY, SESSION_TIMEOUT_KEY, WIRE_FORMAT_KEY,
   IDLE_WARNING_BEFORE_SECS, IDLE_CHECK_INTERVAL_MS,
   updatePerfOverlay,
   updateLatencyStatsFps,
+  updateLatencyStatsGlass,
+  updateConnectionQuality,
   showIdleWarning, hideIdleWarning,
   resetLatencyStats, resetNetworkIndicators,
 } from "./settings";
@@ -28,14 +33,16 @@ import {
   helpOverlay, perfOverlay, sessionInfoPanel, sipCloseBtn,
   reconnectBtn, reconnectDisconnectBtn, reconnectOverlay,
   clipboardHistoryPanel, chpList, chpClearBtn, chpCloseBtn,
+  btnMixer, mixerPanel, mixerList, mixerCloseBtn,
   adminPanelOverlay, adminSessionsTbody, adminSessionCount, adminPanelClose,
-  fileDropOverlay, btnUpload, fileUploadInput, btnDownload,
+  fileDropOverlay, btnUpload, fileUploadInput, btnDownload, btnOpenUrl,
   mobileFab, mobileFabToggle, mobileFabMenu,
   fabKeyboard, fabFullscreen, fabScreenshot, fabDisconnect,
   mobileKeyboardInput, sipCopyStatsBtn,
-  btnMute, btnForwardKeys, btnTheme,
+  btnMute, btnShare, btnForwardKeys, btnCursorInVideo, btnWebcam, btnSecondScreen, btnTheme, audioSourceSelect, audioModeSelect, qualitySelect, keyComboSelect,
+  audioTuningSelect, rotationSelect,
   setStatus as setStatusUI,
-  showLoading, hideLoading, showLoadingError,
+  showLoading, hideLoading, showLoadingError, updateLoadingStatus,
   showDesktop as showDesktopUI, showLogin as showLoginUI,
   showReconnectOverlay, hideReconnectOverlay,
   reconnectDesc,
@@ -48,14 +55,35 @@ const tokenManager = new TokenManager();
 // We warn 2 minutes before expiry.
 let effectiveIdleTimeoutSecs = 3600; // updated from login response
 
+// Clipboard directions the server allows for this session
+let clipboardPolicy: ClipboardPolicy = "bidirectional";
+let primarySelection = false;
+// Whether the server allows view-only share links, and whether any are out
+let shareLinks = false;
+let sharing = false;
+// Remote assistance on the server's own display: the person there accepts
+// first, and may pause our keyboard and mouse at any time
+let assistSession = false;
+
 let connection: BeamConnection | null = null;
 let renderer: WebCodecsRenderer | null = null;
 let inputHandler: InputHandler | null = null;
 let clipboardBridge: ClipboardBridge | null = null;
 let fileUploader: FileUploader | null = null;
 let fileDownloader: FileDownloader | null = null;
+let webcam: WebcamStreamer | null = null;
+let secondScreen: SecondScreen | null = null;
 let ui: BeamUI | null = null;
 let heartbeatInterval: ReturnType<typeof setInterval> | null = null;
+let audioReportInterval: ReturnType<typeof setInterval> | null = null;
+
+/** Opus frame length and FEC per audio packet size choice: shorter frames
+ *  for less delay, longer ones with FEC for networks that drop and bunch up */
+const AUDIO_TUNINGS: Record<string, InputEvent> = {
+  standard: { t: "ao", frame_ms: 20, fec: false },
+  "low-latency": { t: "ao", frame_ms: 10, fec: false },
+  jitter: { t: "ao", frame_ms: 40, fec: true },
+};
 let connectionTimeout: ReturnType<typeof setTimeout> | null = null;
 
 // Release token for graceful session cleanup on tab close
@@ -69,6 +97,14 @@ let isReturningToLogin = false;
 let perfFps = 0;
 
 
+// Admin messages stay up long enough to be read from across the room
+const ADMIN_NOTICE_MS = 30_000;
+
+// Resize blur: the old picture is stretched to the new window until the
+// agent reports the resize done; agents that never answer get this long
+const RESIZE_BLUR_MAX_MS = 3000;
+let resizeBlurTimer: ReturnType<typeof setTimeout> | null = null;
+
 // Idle timeout warning state
 let lastActivity = Date.now();
 let idleCheckInterval: ReturnType<typeof setInterval> | null = null;
@@ -87,10 +123,20 @@ window.matchMedia("(prefers-color-scheme: light)").addEventListener("change", ()
 
 // Clipboard history panel state
 let clipboardHistoryVisible = false;
+/** The agent's history of the session clipboard, newest first */
+let sessionClipboardHistory: { id: number; text: string; at: number }[] = [];
+
+// Volume mixer panel state
+let mixerVisible = false;
+/** The mixer slider being dragged, whose row a listing must not replace */
+let heldMixerSlider: HTMLInputElement | null = null;
+let lastMixerSend = 0;
 
 // Admin sessions panel state
 let adminPanelVisible = false;
 let adminRefreshInterval: ReturnType<typeof setInterval> | null = null;
+let adminEvents: WebSocket | null = null;
+let adminRefreshPending: ReturnType<typeof setTimeout> | null = null;
 
 // Session info panel state
 let sessionInfoVisible = false;
@@ -141,6 +187,7 @@ function toggleClipboardHistoryPanel(): void {
   clipboardHistoryVisible = !clipboardHistoryVisible;
   if (clipboardHistoryVisible) {
     clipboardHistoryPanel.classList.add("visible");
+    if (clipboardAllowsToLocal()) connection?.sendInput({ t: "chl" });
     renderClipboardHistory();
   } else {
     clipboardHistoryPanel.classList.remove("visible");
@@ -157,27 +204,56 @@ function formatTime(ts: number): string {
   return new Date(ts).toLocaleTimeString();
 }
 
-/** Render the clipboard history list from the ClipboardBridge */
+/** The agent only keeps a history when text may leave the session */
+function clipboardAllowsToLocal(): boolean {
+  return clipboardPolicy === "bidirectional" || clipboardPolicy === "to-local";
+}
+
+function escapeClipboardText(text: string): string {
+  // Escape HTML to prevent XSS from clipboard content
+  return ClipboardBridge.truncatePreview(text)
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;");
+}
+
+function copyToLocalClipboard(text: string): void {
+  navigator.clipboard.writeText(text).then(
+    () => ui?.showNotification("Copied to clipboard", "success", 1500),
+    () => ui?.showNotification("Failed to copy", "error"),
+  );
+}
+
+/** Render the session's history from the agent, then the browser's own */
 function renderClipboardHistory(): void {
   if (!clipboardHistoryVisible) return;
 
   const history: ClipboardHistoryEntry[] = clipboardBridge?.getHistory() ?? [];
-  if (history.length === 0) {
+  if (history.length === 0 && sessionClipboardHistory.length === 0) {
     chpList.innerHTML = '<div class="chp-empty">No clipboard activity yet</div>';
     return;
   }
 
+  // Pasting re-sets the session clipboard; the user then pastes as usual
+  const sessionHtml = sessionClipboardHistory.map((entry) => `<div class="chp-entry">
+      <div class="chp-entry-header">
+        <div class="chp-entry-meta">
+          <span>${formatTime(entry.at)}</span>
+        </div>
+        <div class="chp-entry-actions">
+          <button class="chp-copy chp-paste" data-chp-id="${entry.id}" aria-label="Set as session clipboard">Paste</button>
+          <button class="chp-copy chp-session-copy" data-chp-id="${entry.id}" aria-label="Copy to clipboard">Copy</button>
+        </div>
+      </div>
+      <div class="chp-text">${escapeClipboardText(entry.text)}</div>
+    </div>`).join("");
+
   // Render newest-first
   const html = history.slice().reverse().map((entry, idx) => {
     const arrow = entry.direction === "sent" ? "\u2192" : "\u2190";
     const dirClass = entry.direction;
-    const preview = ClipboardBridge.truncatePreview(entry.text);
-    // Escape HTML to prevent XSS from clipboard content
-    const escaped = preview
-      .replace(/&/g, "&amp;")
-      .replace(/</g, "&lt;")
-      .replace(/>/g, "&gt;")
-      .replace(/"/g, "&quot;");
+    const escaped = escapeClipboardText(entry.text);
     return `<div class="chp-entry">
       <div class="chp-entry-header">
         <div class="chp-entry-meta">
@@ -191,24 +267,115 @@ function renderClipboardHistory(): void {
     </div>`;
   }).join("");
 
-  chpList.innerHTML = html;
+  chpList.innerHTML =
+    (sessionHtml ? `<div class="chp-section">In session</div>${sessionHtml}` : "") +
+    (html && sessionHtml ? `<div class="chp-section">This browser</div>${html}` : html);
 
   // Wire copy buttons
-  chpList.querySelectorAll(".chp-copy").forEach((btn) => {
+  chpList.querySelectorAll("[data-chp-idx]").forEach((btn) => {
     btn.addEventListener("click", () => {
       const idx = parseInt((btn as HTMLElement).dataset.chpIdx || "0", 10);
       const reversedHistory = history.slice().reverse();
       const entry = reversedHistory[idx];
-      if (entry) {
-        navigator.clipboard.writeText(entry.text).then(
-          () => ui?.showNotification("Copied to clipboard", "success", 1500),
-          () => ui?.showNotification("Failed to copy", "error"),
-        );
+      if (entry) copyToLocalClipboard(entry.text);
+    });
+  });
+  chpList.querySelectorAll("[data-chp-id]").forEach((btn) => {
+    btn.addEventListener("click", () => {
+      const id = parseInt((btn as HTMLElement).dataset.chpId || "0", 10);
+      if (btn.classList.contains("chp-paste")) {
+        connection?.sendInput({ t: "chp", id });
+        ui?.showNotification("Ready to paste in the session", "success", 1500);
+        return;
       }
+      const entry = sessionClipboardHistory.find((e) => e.id === id);
+      if (entry) copyToLocalClipboard(entry.text);
     });
   });
 }
 
+// --- Volume mixer panel ---
+
+/** Loudest a stream may be set, in percent (MAX_APP_VOLUME in crates/protocol) */
+const MAX_APP_VOLUME = 150;
+/** Slider changes sent while dragging, at most one per this many ms */
+const MIXER_SEND_INTERVAL_MS = 100;
+
+function toggleMixerPanel(): void {
+  mixerVisible = !mixerVisible;
+  mixerPanel.classList.toggle("visible", mixerVisible);
+  // Streams come and go; the agent's answer renders the panel
+  if (mixerVisible) connection?.sendInput({ t: "asl" });
+}
+
+function hideMixerPanel(): void {
+  mixerVisible = false;
+  mixerPanel.classList.remove("visible");
+}
+
+/** Rebuild the mixer from the agent's listing. Waits while a slider is
+ *  held: every change is answered with a listing, which would otherwise
+ *  replace the slider under the pointer. */
+function renderMixer(apps: AudioApp[]): void {
+  if (heldMixerSlider) return;
+  if (apps.length === 0) {
+    mixerList.innerHTML = '<div class="chp-empty">No applications are playing sound</div>';
+    return;
+  }
+  mixerList.replaceChildren(...apps.map(mixerRow));
+}
+
+function mixerRow(app: AudioApp): HTMLDivElement {
+  const row = document.createElement("div");
+  row.className = "chp-entry";
+  const header = document.createElement("div");
+  header.className = "chp-entry-header";
+  const name = document.createElement("div");
+  name.className = "chp-entry-meta";
+  name.textContent = app.name;
+  const mute = document.createElement("button");
+  mute.className = "chp-copy";
+  mute.textContent = app.muted ? "Unmute" : "Mute";
+  mute.setAttribute("aria-pressed", String(app.muted));
+  mute.addEventListener("click", () => {
+    connection?.sendInput({ t: "av", app: app.index, muted: !app.muted });
+  });
+  header.append(name, mute);
+
+  const volume = document.createElement("div");
+  volume.className = "mixer-volume";
+  const slider = document.createElement("input");
+  slider.type = "range";
+  slider.min = "0";
+  slider.max = String(MAX_APP_VOLUME);
+  slider.value = String(app.volume);
+  slider.disabled = app.muted;
+  slider.setAttribute("aria-label", `Volume of ${app.name}`);
+  const percent = document.createElement("span");
+  percent.className = "mixer-percent";
+  percent.textContent = `${app.volume}%`;
+  const send = () => {
+    lastMixerSend = performance.now();
+    connection?.sendInput({ t: "av", app: app.index, volume: slider.valueAsNumber });
+  };
+  slider.addEventListener("pointerdown", () => {
+    heldMixerSlider = slider;
+  });
+  slider.addEventListener("input", () => {
+    percent.textContent = `${slider.value}%`;
+    if (performance.now() - lastMixerSend >= MIXER_SEND_INTERVAL_MS) send();
+  });
+  // Released (or a keyboard step): the final value always goes out
+  slider.addEventListener("change", () => {
+    heldMixerSlider = null;
+    send();
+  });
+  volume.append(slider, percent);
+
+  row.append(header, volume);
+  return row;
+}
+
 // --- Admin sessions panel ---
 
 /** Shape of the admin session list API response */
@@ -218,6 +385,41 @@ interface AdminSession {
   display: number;
   created_at: number;
   last_activity: number;
+  client?: { ip: string; user_agent?: string; country?: string };
+  /** When the agent last sent a screenshot (null until one arrives) */
+  thumbnail_at?: number | null;
+  /** Kept from admin observation by its owner or the privacy policy */
+  private?: boolean;
+  /** Encoder, capture and stream the agent last reported (null until it does) */
+  agent_info?: {
+    version: string;
+    encoder: string;
+    fallback: boolean;
+    capture: string;
+    width: number;
+    height: number;
+    framerate: number;
+  } | null;
+}
+
+/** Rebuild the audio source picker from the agent's listing, keeping the
+ *  current choice selected if it still exists. */
+function populateAudioSources(
+  sinks: { name: string; description: string }[],
+  apps: { index: number; name: string }[],
+): void {
+  const current = audioSourceSelect.value;
+  audioSourceSelect.replaceChildren(new Option("All audio", ""));
+  for (const sink of sinks) {
+    audioSourceSelect.add(new Option(sink.description, `sink:${sink.name}`));
+  }
+  for (const app of apps) {
+    audioSourceSelect.add(new Option(`App: ${app.name}`, `app:${app.index}`));
+  }
+  audioSourceSelect.value = current;
+  if (audioSourceSelect.selectedIndex < 0) {
+    audioSourceSelect.value = "";
+  }
 }
 
 /** Format a Unix epoch timestamp as a relative time string ("2m ago", "1h ago") */
@@ -234,7 +436,10 @@ function toggleAdminPanel(): void {
   if (adminPanelVisible) {
     adminPanelOverlay.classList.add("visible");
     fetchAdminSessions();
-    adminRefreshInterval = setInterval(fetchAdminSessions, 10_000);
+    openAdminEvents();
+    // The event stream covers sessions coming and going; this catches
+    // thumbnails and activity times
+    adminRefreshInterval = setInterval(fetchAdminSessions, 60_000);
   } else {
     hideAdminPanel();
   }
@@ -247,12 +452,57 @@ function hideAdminPanel(): void {
     clearInterval(adminRefreshInterval);
     adminRefreshInterval = null;
   }
+  if (adminRefreshPending) {
+    clearTimeout(adminRefreshPending);
+    adminRefreshPending = null;
+  }
+  adminEvents?.close();
+  adminEvents = null;
+}
+
+/** Admin feed events that change what the session list shows ("lagged"
+ *  means some were missed) */
+const ADMIN_LIST_EVENTS = new Set([
+  "created", "restored", "adopted", "ended", "connected", "disconnected",
+  "agent_exited", "agent_restarted", "lagged",
+]);
+
+/** Follow `/api/admin/events/ws` while the admin panel is open, refreshing
+ *  the list when sessions change and showing alerts */
+function openAdminEvents(): void {
+  const currentToken = tokenManager.getToken();
+  if (!currentToken) return;
+  const wsProtocol = location.protocol === "https:" ? "wss:" : "ws:";
+  const ws = new WebSocket(
+    `${wsProtocol}//${location.host}/api/admin/events/ws?token=${encodeURIComponent(currentToken)}`,
+  );
+  ws.onmessage = (e: MessageEvent) => {
+    let event: { event?: string; message?: string };
+    try {
+      event = JSON.parse(e.data as string);
+    } catch {
+      return;
+    }
+    if (event.event === "alert" && event.message) {
+      ui?.showNotification(event.message, "warning", ADMIN_NOTICE_MS);
+    } else if (event.event && ADMIN_LIST_EVENTS.has(event.event) && !adminRefreshPending) {
+      // One fetch for a burst of events
+      adminRefreshPending = setTimeout(() => {
+        adminRefreshPending = null;
+        fetchAdminSessions();
+      }, 500);
+    }
+  };
+  ws.onclose = () => {
+    if (adminEvents === ws) adminEvents = null;
+  };
+  adminEvents = ws;
 }
 
 async function fetchAdminSessions(): Promise<void> {
   const currentToken = tokenManager.getToken();
   if (!currentToken) {
-    adminSessionsTbody.innerHTML = '<tr><td colspan="6" class="admin-empty">Not authenticated</td></tr>';
+    adminSessionsTbody.innerHTML = '<tr><td colspan="8" class="admin-empty">Not authenticated</td></tr>';
     return;
   }
 
@@ -262,7 +512,7 @@ async function fetchAdminSessions(): Promise<void> {
     });
     if (!resp.ok) {
       if (resp.status === 401) {
-        adminSessionsTbody.innerHTML = '<tr><td colspan="6" class="admin-empty">Session expired</td></tr>';
+        adminSessionsTbody.innerHTML = '<tr><td colspan="8" class="admin-empty">Session expired</td></tr>';
         return;
       }
       throw new Error(`HTTP ${resp.status}`);
@@ -270,15 +520,37 @@ async function fetchAdminSessions(): Promise<void> {
     const sessions = (await resp.json()) as AdminSession[];
     renderAdminSessions(sessions);
   } catch {
-    adminSessionsTbody.innerHTML = '<tr><td colspan="6" class="admin-empty">Failed to load sessions</td></tr>';
+    adminSessionsTbody.innerHTML = '<tr><td colspan="8" class="admin-empty">Failed to load sessions</td></tr>';
+  }
+}
+
+/** Object URLs of the thumbnails on screen, released on each refresh */
+let adminThumbnailUrls: string[] = [];
+
+/** Thumbnails need the auth header, so they are fetched rather than linked */
+async function loadAdminThumbnail(img: HTMLImageElement, sessionId: string): Promise<void> {
+  const currentToken = tokenManager.getToken();
+  if (!currentToken) return;
+  try {
+    const resp = await fetch(`/api/admin/sessions/${sessionId}/thumbnail`, {
+      headers: { Authorization: `Bearer ${currentToken}` },
+    });
+    if (!resp.ok) return;
+    const url = URL.createObjectURL(await resp.blob());
+    adminThumbnailUrls.push(url);
+    img.src = url;
+  } catch {
+    // Leave the cell empty; the next refresh tries again
   }
 }
 
 function renderAdminSessions(sessions: AdminSession[]): void {
   adminSessionCount.textContent = String(sessions.length);
+  adminThumbnailUrls.forEach((url) => URL.revokeObjectURL(url));
+  adminThumbnailUrls = [];
 
   if (sessions.length === 0) {
-    adminSessionsTbody.innerHTML = '<tr><td colspan="6" class="admin-empty">No active sessions</td></tr>';
+    adminSessionsTbody.innerHTML = '<tr><td colspan="9" class="admin-empty">No active sessions</td></tr>';
     return;
   }
 
@@ -288,16 +560,41 @@ function renderAdminSessions(sessions: AdminSession[]): void {
     const idle = formatRelativeTime(s.last_activity);
     const isSelf = s.id === currentSessionId;
     const escapedId = s.id.replace(/"/g, "&quot;");
+    // Client fields come from request headers; escape before rendering
+    const esc = (v: string) =>
+      v.replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;").replace(/"/g, "&quot;");
+    const from = s.client
+      ? `<td title="${esc(s.client.user_agent ?? "")}">${esc(s.client.ip)}${s.client.country ? ` (${esc(s.client.country)})` : ""}</td>`
+      : "<td></td>";
+    const thumb = s.private
+      ? '<td class="admin-private">Private</td>'
+      : s.thumbnail_at
+      ? `<td><img class="admin-thumb" data-session-id="${escapedId}" alt="" title="Taken ${formatRelativeTime(s.thumbnail_at)}"></td>`
+      : "<td></td>";
+    const info = s.agent_info;
+    const encoder = info
+      ? `<td title="${info.width}x${info.height} at ${info.framerate} fps, ${esc(info.capture)} capture, agent ${esc(info.version)}">${esc(info.encoder)}${info.fallback ? " (fallback)" : ""}</td>`
+      : "<td></td>";
     return `<tr>
+      ${thumb}
       <td title="${escapedId}">${shortId}${isSelf ? " *" : ""}</td>
       <td>${s.username}</td>
       <td>:${s.display}</td>
+      ${from}
+      ${encoder}
       <td>${created}</td>
       <td>${idle}</td>
       <td><button class="admin-terminate-btn" data-session-id="${escapedId}"${isSelf ? ' title="This is your session"' : ""}>Terminate</button></td>
     </tr>`;
   }).join("");
 
+  adminSessionsTbody.querySelectorAll<HTMLImageElement>("img.admin-thumb").forEach((img) => {
+    const sessionId = img.dataset.sessionId;
+    if (sessionId) {
+      loadAdminThumbnail(img, sessionId);
+    }
+  });
+
   // Wire terminate buttons
   adminSessionsTbody.querySelectorAll(".admin-terminate-btn").forEach((btn) => {
     btn.addEventListener("click", () => {
@@ -423,6 +720,8 @@ function updateSessionInfoPanel(): void {
   if (sipAudioMuted && renderer) {
     sipAudioMuted.textContent = renderer.isMuted() ? "Yes" : "No";
   }
+  const avSync = renderer?.getAvSyncMs() ?? null;
+  setText("sip-av-sync", avSync === null ? "--" : `${Math.round(avSync)} ms`);
 }
 
 /** Collect all current stats into a formatted text block and copy to clipboard */
@@ -448,6 +747,7 @@ function copyStatsToClipboard(): void {
   // Audio
   const audioCodec = getText("sip-audio-codec");
   const audioMuted = getText("sip-audio-muted");
+  const avSync = getText("sip-av-sync");
 
   // Client info
   const userAgent = navigator.userAgent;
@@ -472,6 +772,7 @@ function copyStatsToClipboard(): void {
     "Audio:",
     `  Codec: ${audioCodec}`,
     `  Muted: ${audioMuted}`,
+    `  A/V sync: ${avSync}`,
     "",
     "Client:",
     `  User Agent: ${userAgent}`,
@@ -531,6 +832,26 @@ function stopHeartbeat(): void {
   }
 }
 
+// The agent reports how much audio played and how much was gap filled with
+// silence to the server, which graphs it next to the Opus settings in use
+const AUDIO_REPORT_INTERVAL_MS = 5000;
+
+function startAudioReports(): void {
+  stopAudioReports();
+  audioReportInterval = setInterval(() => {
+    const playout = renderer?.takeAudioPlayout();
+    if (!playout || playout.playedMs === 0) return;
+    connection?.sendInput({ t: "ar", played_ms: playout.playedMs, concealed_ms: playout.concealedMs });
+  }, AUDIO_REPORT_INTERVAL_MS);
+}
+
+function stopAudioReports(): void {
+  if (audioReportInterval) {
+    clearInterval(audioReportInterval);
+    audioReportInterval = null;
+  }
+}
+
 // --- Idle timeout warning ---
 
 /** Record user activity and hide the warning if visible */
@@ -579,6 +900,17 @@ function stopIdleCheck(): void {
   idleWarningVisible = hideIdleWarning(idleWarningVisible);
 }
 
+function setResizeBlur(active: boolean): void {
+  desktopView.classList.toggle("resizing", active);
+  if (resizeBlurTimer) {
+    clearTimeout(resizeBlurTimer);
+    resizeBlurTimer = null;
+  }
+  if (active) {
+    resizeBlurTimer = setTimeout(() => setResizeBlur(false), RESIZE_BLUR_MAX_MS);
+  }
+}
+
 function handleDisconnect(): void {
   connection?.disconnect();
   connection = null;
@@ -590,7 +922,13 @@ function handleDisconnect(): void {
   clipboardBridge = null;
   fileUploader = null;
   fileDownloader = null;
+  webcam?.stop();
+  webcam = null;
+  updateWebcamButton(false);
+  secondScreen?.close();
+  secondScreen = null;
   stopHeartbeat();
+  stopAudioReports();
   stopIdleCheck();
   tokenManager.clearToken();
   currentReleaseToken = null;
@@ -600,6 +938,7 @@ function handleDisconnect(): void {
   hideSessionInfoPanel();
   hideClipboardHistoryPanel();
   hideAdminPanel();
+  ui?.clearCountdown();
 
   // Reset indicators
   resetNetworkIndicators();
@@ -716,6 +1055,29 @@ function toggleForwardKeys(): void {
   updateForwardKeysButton(enabled);
 }
 
+/** Whether the agent draws the cursor into the video (remembered) */
+let cursorInVideo = localStorage.getItem(CURSOR_IN_VIDEO_KEY) === "true";
+/** The remote cursor from the last `cur` message, shown while the agent
+ *  doesn't draw it */
+let remoteCursor = "default";
+
+/** Show the cursor mode on its button and the canvas, and tell the agent */
+function applyCursorInVideo(): void {
+  btnCursorInVideo.innerHTML = `${ICON_CURSOR}<span class="btn-label">Cursor</span>`;
+  btnCursorInVideo.classList.toggle("active", cursorInVideo);
+  btnCursorInVideo.setAttribute("aria-pressed", String(cursorInVideo));
+  // One cursor on screen: the local one goes while the video has its own
+  remoteCanvas.style.cursor = cursorInVideo ? "none" : remoteCursor;
+  connection?.sendInput({ t: "cm", composite: cursorInVideo });
+}
+
+/** Toggle drawing the cursor into the video */
+function toggleCursorInVideo(): void {
+  cursorInVideo = !cursorInVideo;
+  localStorage.setItem(CURSOR_IN_VIDEO_KEY, cursorInVideo ? "true" : "false");
+  applyCursorInVideo();
+}
+
 /** Update the mute button to reflect current audio state */
 function updateMuteButton(muted: boolean): void {
   const label = muted ? "Unmute" : "Mute";
@@ -732,20 +1094,91 @@ function toggleMute(): void {
   updateMuteButton(muted);
 }
 
+/** Update the camera button to reflect whether the webcam is being sent */
+function updateWebcamButton(active: boolean): void {
+  btnWebcam.innerHTML = `${ICON_CAMERA}<span class="btn-label">${active ? "Camera on" : "Camera"}</span>`;
+  btnWebcam.classList.toggle("active", active);
+  btnWebcam.setAttribute("aria-pressed", active ? "true" : "false");
+}
+
+/** Start or stop sending the local camera to the remote session */
+async function toggleWebcam(): Promise<void> {
+  if (!webcam) return;
+  if (webcam.active) {
+    webcam.stop();
+    updateWebcamButton(false);
+    return;
+  }
+  try {
+    await webcam.start();
+    updateWebcamButton(true);
+  } catch (err) {
+    webcam.stop();
+    updateWebcamButton(false);
+    const reason = err instanceof Error ? err.message : String(err);
+    ui?.showNotification(`Camera unavailable: ${reason}`, "error");
+  }
+}
+
+/** Update the second screen button to reflect whether its window is open */
+function updateSecondScreenButton(open: boolean): void {
+  btnSecondScreen.classList.toggle("active", open);
+  btnSecondScreen.setAttribute("aria-pressed", open ? "true" : "false");
+}
+
+/** Open or close the window showing the session's second monitor */
+function toggleSecondScreen(): void {
+  if (!secondScreen) return;
+  if (secondScreen.isOpen()) {
+    secondScreen.close();
+    return;
+  }
+  if (secondScreen.open()) {
+    updateSecondScreenButton(true);
+  } else {
+    ui?.showNotification("Allow pop-ups for this site to open a second screen", "error");
+  }
+}
+
+/** Open a link the session forwarded (session.forward_urls) in a new tab.
+ *  It arrives without a user gesture, so a popup blocker may refuse it. */
+function openSessionUrl(url: string): void {
+  if (!/^https?:\/\//i.test(url)) return;
+  const win = window.open(url, "_blank");
+  if (win) {
+    win.opener = null;
+  } else {
+    ui?.showNotification(`Pop-up blocked. Allow pop-ups to open links from the session: ${url}`, "warning", 8000);
+  }
+}
+
 async function handleLogin(event: SubmitEvent): Promise<void> {
   event.preventDefault();
 
   const data = await performLogin(setStatus);
   if (!data) return;
+  await enterSession(data, usernameInput.value.trim());
+}
 
+/** Connect to the session a login or guest request returned. */
+async function enterSession(data: LoginResponse, username: string): Promise<void> {
   tokenManager.setToken(data.token);
   tokenManager.setConnection(connection);
   currentSessionId = data.session_id;
   currentReleaseToken = data.release_token ?? null;
-  sessionUsername = usernameInput.value.trim();
+  sessionUsername = username;
   if (data.idle_timeout !== undefined) {
     effectiveIdleTimeoutSecs = data.idle_timeout;
   }
+  clipboardPolicy = data.clipboard ?? "bidirectional";
+  primarySelection = data.primary_selection ?? false;
+  shareLinks = data.share_links ?? false;
+  assistSession = data.assist ?? false;
+  qualitySelect.value = data.quality ?? "balanced";
+  keyComboSelect.style.display = data.system_combos ? "" : "none";
+  btnShare.style.display = shareLinks ? "" : "none";
+  setSharing(false);
+  sessionClipboardHistory = [];
   tokenManager.scheduleTokenRefresh();
 
   try {
@@ -757,6 +1190,157 @@ async function handleLogin(event: SubmitEvent): Promise<void> {
   }
 }
 
+/** Start a session on a throwaway guest account, for a kiosk's
+ *  `#guest=<token>` link. The server ends it after its lifetime, with the
+ *  same countdown as an admin's scheduled end. */
+async function startGuest(guestToken: string): Promise<void> {
+  showLoading("Starting guest session...");
+  setStatus("connecting", "Starting guest session...");
+  let data: LoginResponse & { username: string };
+  try {
+    const resp = await fetch("/api/guest", {
+      method: "POST",
+      headers: { Authorization: `Bearer ${guestToken}` },
+    });
+    const body = await resp.json() as (LoginResponse & { username: string }) | { error?: string };
+    if (!resp.ok || !("session_id" in body)) {
+      throw new Error(("error" in body && body.error) || `HTTP ${resp.status}`);
+    }
+    data = body;
+  } catch (err) {
+    const message = err instanceof Error ? err.message : "Failed to start a guest session.";
+    showLoadingError(message);
+    setStatus("error", message);
+    return;
+  }
+  // A reload returns to this session until it ends
+  saveSession(data);
+  localStorage.setItem("beam_username", data.username);
+  await enterSession(data, data.username);
+}
+
+/** Watch a session through a view-only share link. There is no login, no
+ *  input and no heartbeat; the page just shows the stream until the link
+ *  is revoked or expires. */
+async function startViewer(shareToken: string): Promise<void> {
+  document.body.classList.add("view-only");
+  showLoading("Joining shared session...");
+  setStatus("connecting", "Connecting...");
+
+  connection = BeamConnection.viewer(shareToken);
+  renderer = new WebCodecsRenderer(remoteCanvas, desktopView);
+  renderer.onMuteChange((muted) => updateMuteButton(muted));
+  updateMuteButton(true);
+
+  ui = new BeamUI();
+  ui.setOnFullscreen(toggleFullscreen);
+
+  renderer.onFpsUpdate((fps, decodeMs) => updateLatencyStatsFps(fps, decodeMs));
+  connection.onVideoFrame((flags, width, height, timestampUs, payload) => {
+    renderer?.feedVideoFrame(flags, width, height, timestampUs, payload);
+  });
+  connection.onAudioFrame((timestampUs, payload) => {
+    renderer?.feedAudioFrame(timestampUs, payload);
+  });
+  renderer.onFirstFrame(() => {
+    showDesktop();
+    setStatus("connected", "View only");
+  });
+
+  const stopViewing = (message: string) => {
+    renderer?.destroy();
+    renderer = null;
+    connection = null;
+    setStatus("error", message);
+    showLoadingError(message);
+  };
+  connection.onShareEnded((reason) => {
+    stopViewing(reason === "too_many_viewers"
+      ? "Too many people are watching this session. Try again later."
+      : "This share link has ended.");
+  });
+  connection.onAgentExited(() => stopViewing("The shared session has ended."));
+  connection.onReconnectFailed(() => stopViewing("Lost the connection to the shared session."));
+
+  try {
+    await connection.connect();
+  } catch {
+    stopViewing("This share link is invalid or has ended.");
+  }
+}
+
+/** Switch the session's quality preset; the server remembers it for the
+ *  user's next session */
+/** The quality menu's tooltip, until a fallback explains the preset */
+const QUALITY_SELECT_TITLE = qualitySelect.title;
+
+async function setQualityPreset(preset: QualityPreset): Promise<void> {
+  if (!currentSessionId) return;
+  try {
+    const resp = await fetch(`/api/sessions/${encodeURIComponent(currentSessionId)}/quality`, {
+      method: "POST",
+      headers: { Authorization: `Bearer ${tokenManager.getToken()}`, "Content-Type": "application/json" },
+      body: JSON.stringify({ preset }),
+    });
+    if (!resp.ok) throw new Error(`HTTP ${resp.status}`);
+    const saved = loadSession();
+    if (saved) saveSession({ ...saved, quality: preset });
+  } catch {
+    ui?.showNotification("Failed to change video quality", "error");
+  }
+}
+
+/** Create a share link and copy it, or stop sharing if links are out */
+async function toggleShare(): Promise<void> {
+  if (!currentSessionId) return;
+  const url = `/api/sessions/${encodeURIComponent(currentSessionId)}/share`;
+  const headers = { Authorization: `Bearer ${tokenManager.getToken()}` };
+
+  if (sharing) {
+    if (!confirm("Stop sharing? Everyone watching through a link is disconnected.")) return;
+    try {
+      const resp = await fetch(url, { method: "DELETE", headers });
+      if (!resp.ok) throw new Error(`HTTP ${resp.status}`);
+      setSharing(false);
+      ui?.showNotification("Share links revoked", "success");
+    } catch {
+      ui?.showNotification("Failed to revoke share links", "error");
+    }
+    return;
+  }
+
+  try {
+    const resp = await fetch(url, {
+      method: "POST",
+      headers: { ...headers, "Content-Type": "application/json" },
+      body: "{}",
+    });
+    if (!resp.ok) {
+      const body = await resp.json().catch(() => ({})) as { error?: string };
+      throw new Error(body.error ?? `HTTP ${resp.status}`);
+    }
+    const data = await resp.json() as { token: string; expires_at: number };
+    const link = `${location.origin}${location.pathname}#share=${encodeURIComponent(data.token)}`;
+    setSharing(true);
+    const until = new Date(data.expires_at * 1000).toLocaleTimeString([], { hour: "2-digit", minute: "2-digit" });
+    try {
+      await navigator.clipboard.writeText(link);
+      ui?.showNotification(`View-only link copied, valid until ${until}`, "success", 4000);
+    } catch {
+      prompt(`View-only link, valid until ${until}:`, link);
+    }
+  } catch (err) {
+    const message = err instanceof Error ? err.message : "";
+    ui?.showNotification(`Failed to create share link${message ? `: ${message}` : ""}`, "error");
+  }
+}
+
+function setSharing(on: boolean): void {
+  sharing = on;
+  btnShare.classList.toggle("active", on);
+  btnShare.title = on ? "Stop sharing" : "Share view-only link";
+}
+
 async function startConnection(sessionId: string, token: string): Promise<void> {
   if (connection) {
     connection.disconnect();
@@ -764,10 +1348,14 @@ async function startConnection(sessionId: string, token: string): Promise<void>
   }
 
   setStatus("connecting", "Connecting...");
+  if (assistSession) {
+    updateLoadingStatus("Waiting for the person at the computer to accept...");
+  }
 
-  // Timeout: if no video frame arrives within 20 seconds, show error
+  // Timeout: if no video frame arrives within 20 seconds, show error. An
+  // assist request waits on a person instead; the server reports a decline.
   if (connectionTimeout) clearTimeout(connectionTimeout);
-  connectionTimeout = setTimeout(() => {
+  connectionTimeout = assistSession ? null : setTimeout(() => {
     if (!renderer?.hasStream()) {
       showLoadingError("Desktop is taking too long to start. Please try again.");
       connection?.disconnect();
@@ -777,7 +1365,8 @@ async function startConnection(sessionId: string, token: string): Promise<void>
     connectionTimeout = null;
   }, 20_000);
 
-  connection = new BeamConnection(sessionId, token);
+  const wireFormat = localStorage.getItem(WIRE_FORMAT_KEY) === "cbor" ? "cbor" : "json";
+  connection = new BeamConnection(sessionId, token, wireFormat);
   tokenManager.setConnection(connection);
   renderer = new WebCodecsRenderer(remoteCanvas, desktopView);
 
@@ -806,8 +1395,18 @@ async function startConnection(sessionId: string, token: string): Promise<void>
     updatePerfOverlay(decodeMs, perfFps, 0, 0);
   });
 
-  // Wire video frames from connection to renderer
+  // Echo sampled frame display times so the agent can estimate latency
+  renderer.onFrameDisplayed((ts, dd) => {
+    connection?.sendInput({ t: "fa", ts, dd });
+  });
+
+  // Wire video frames from connection to renderer, or to the second
+  // screen's window
   connection.onVideoFrame((flags, width, height, timestampUs, payload) => {
+    if (flags & FLAG_SECOND_SCREEN) {
+      secondScreen?.feedVideoFrame(flags, width, height, timestampUs, payload);
+      return;
+    }
     renderer?.feedVideoFrame(flags, width, height, timestampUs, payload);
   });
 
@@ -836,13 +1435,17 @@ async function startConnection(sessionId: string, token: string): Promise<void>
   connection.onConnected(() => {
     setStatus("connected", "Connected");
     const sendInput = connection!.sendInput.bind(connection!);
+    // Agents start without drawing the cursor; ask again on every connect
+    if (cursorInVideo) applyCursorInVideo();
 
     if (!inputHandler) {
       inputHandler = new InputHandler(desktopView, sendInput);
+      inputHandler.setPrimarySync(() => clipboardBridge?.sendPrimaryClipboard() ?? Promise.resolve());
       const savedForwardKeys = localStorage.getItem(FORWARD_KEYS_KEY) === "true";
       inputHandler.forwardBrowserShortcuts = savedForwardKeys;
       updateForwardKeysButton(savedForwardKeys);
       inputHandler.enable();
+      inputHandler.onResizeNeeded(() => setResizeBlur(true));
 
       // Wire up manual layout selector
       const layoutSelect = document.getElementById("layout-select") as HTMLSelectElement | null;
@@ -874,6 +1477,80 @@ async function startConnection(sessionId: string, token: string): Promise<void>
     inputHandler.sendLayout();
     inputHandler.sendCurrentDimensions();
 
+    // Audio source picker: option values are "" (default monitor),
+    // "sink:<name>" or "app:<sink-input index>". Re-list on focus since
+    // application streams come and go.
+    sendInput({ t: "asl" });
+    audioSourceSelect.onfocus = () => sendInput({ t: "asl" });
+    audioSourceSelect.onchange = () => {
+      const value = audioSourceSelect.value;
+      if (value.startsWith("sink:")) {
+        sendInput({ t: "as", sink: value.slice(5) });
+      } else if (value.startsWith("app:")) {
+        sendInput({ t: "as", app: parseInt(value.slice(4), 10) });
+      } else {
+        sendInput({ t: "as" });
+      }
+    };
+
+    // Monitor rotation lives on the agent; ask for its state, which the
+    // reply to every change updates
+    sendInput({ t: "ds" });
+    rotationSelect.onchange = () => {
+      sendInput({ t: "ds", rotation: rotationSelect.value as DisplayRotation });
+    };
+
+    // Opus profile survives reconnects (a respawned agent starts in standard)
+    // "Audio off" is left alone: the server keeps the pause for the session
+    const savedAudioMode = localStorage.getItem(AUDIO_MODE_KEY);
+    if (savedAudioMode && audioModeSelect.value !== "off") {
+      audioModeSelect.value = savedAudioMode;
+    }
+    if (audioModeSelect.value !== "standard" && audioModeSelect.value !== "off") {
+      sendInput({ t: "am", mode: audioModeSelect.value });
+    }
+    // "off" pauses capture on the agent (no PulseAudio reads, no audio
+    // frames). Any other choice resumes it; resuming a running agent is a no-op.
+    audioModeSelect.onchange = () => {
+      const mode = audioModeSelect.value;
+      if (mode === "off") {
+        sendInput({ t: "ae", enabled: false });
+        return;
+      }
+      sendInput({ t: "ae", enabled: true });
+      localStorage.setItem(AUDIO_MODE_KEY, mode);
+      sendInput({ t: "am", mode });
+    };
+
+    // Packet size survives reconnects too; "standard" leaves the server's
+    // [audio] settings alone until changed back to within a session
+    const savedAudioTuning = localStorage.getItem(AUDIO_TUNING_KEY);
+    if (savedAudioTuning && savedAudioTuning in AUDIO_TUNINGS) {
+      audioTuningSelect.value = savedAudioTuning;
+    }
+    if (audioTuningSelect.value !== "standard") {
+      sendInput(AUDIO_TUNINGS[audioTuningSelect.value]);
+    }
+    audioTuningSelect.onchange = () => {
+      localStorage.setItem(AUDIO_TUNING_KEY, audioTuningSelect.value);
+      sendInput(AUDIO_TUNINGS[audioTuningSelect.value]);
+    };
+    startAudioReports();
+
+    // Webcam keeps streaming across reconnects; re-announce so a
+    // respawned agent rebuilds its loopback pipeline
+    if (!webcam) {
+      webcam = new WebcamStreamer(sendInput, connection!.sendBinary.bind(connection!));
+    } else {
+      webcam.resume();
+    }
+    if (!secondScreen) {
+      secondScreen = new SecondScreen(sendInput);
+      secondScreen.onClose(() => updateSecondScreenButton(false));
+    } else {
+      secondScreen.resume();
+    }
+
     if (!fileUploader) {
       fileUploader = new FileUploader(sendInput);
       fileUploader.setProgressCallback((filename, percent) => {
@@ -894,7 +1571,7 @@ async function startConnection(sessionId: string, token: string): Promise<void>
     }
 
     if (!clipboardBridge) {
-      clipboardBridge = new ClipboardBridge(sendInput);
+      clipboardBridge = new ClipboardBridge(sendInput, clipboardPolicy, primarySelection);
       clipboardBridge.onClipboardSync((direction, preview) => {
         const label = direction === "sent" ? "Clipboard sent" : "Clipboard received";
         const message = preview ? `${label}: ${preview}` : label;
@@ -911,9 +1588,120 @@ async function startConnection(sessionId: string, token: string): Promise<void>
   connection.onAgentMessage((msg) => {
     if (msg.t === "c" && "text" in msg) {
       clipboardBridge?.handleRemoteClipboard(msg.text);
+      if (clipboardHistoryVisible) connection?.sendInput({ t: "chl" });
+    }
+    if (msg.t === "cp" && "text" in msg) {
+      clipboardBridge?.handleRemotePrimary(msg.text);
     }
     if (msg.t === "cur" && "css" in msg) {
-      remoteCanvas.style.cursor = msg.css;
+      // App-specific cursors arrive as a PNG bitmap; the CSS name is the fallback
+      remoteCursor = msg.png
+        ? `url(data:image/png;base64,${msg.png}) ${msg.hx ?? 0} ${msg.hy ?? 0}, ${msg.css}`
+        : msg.css;
+      if (!cursorInVideo) remoteCanvas.style.cursor = remoteCursor;
+    }
+    if (msg.t === "lat" && "p50_ms" in msg) {
+      updateLatencyStatsGlass(msg.p50_ms, msg.p95_ms);
+    }
+    if (msg.t === "sr" && "clock_us" in msg) {
+      renderer?.handleSenderReport(msg.clock_us, msg.audio_us);
+    }
+    if (msg.t === "nq" && "level" in msg) {
+      updateConnectionQuality(msg.level, msg.rtt_ms, msg.loss_pct, msg.kbps, msg.target_kbps);
+    }
+    if (msg.t === "va" && "kind" in msg && msg.active) {
+      if (msg.recover) {
+        // A keyframe to start from is on its way
+        renderer?.resetDecoder();
+        ui?.showNotification("Video stopped arriving, restarting it…", "warning");
+      } else if (msg.kind === "capture_stalled") {
+        ui?.showNotification("The remote screen stopped updating", "warning");
+      } else {
+        ui?.showNotification("Video stopped arriving; reload the page if the picture stays frozen", "warning");
+      }
+    }
+    if (msg.t === "qf" && "requested" in msg) {
+      // The preset stayed over the server's quality ceiling
+      const ceiling = (msg.ceiling_kbps / 1000).toFixed(0);
+      ui?.showNotification(
+        `Streaming at ${msg.effective} quality: ${msg.requested} went over the ${ceiling} Mbps ceiling`,
+        "warning",
+      );
+      // Show what the session runs at; the server keeps the user's choice
+      // for their next session
+      qualitySelect.value = msg.effective;
+      qualitySelect.title = `Video quality: ${msg.requested} went over the ${ceiling} Mbps ceiling, using ${msg.effective}`;
+      const saved = loadSession();
+      if (saved) saveSession({ ...saved, quality: msg.effective });
+    }
+    if (msg.t === "rc" && "attempt" in msg) {
+      // The agent's decoder restart and new encoder didn't get video through
+      console.warn(`[Beam] Agent asked for a reconnect (attempt ${msg.attempt})`);
+      renderer?.resetDecoder();
+      connection?.reconnect();
+    }
+    if (msg.t === "ae" && "enabled" in msg) {
+      // Reflect toggles made elsewhere (session audio API, another tab)
+      if (!msg.enabled) {
+        audioModeSelect.value = "off";
+      } else if (audioModeSelect.value === "off") {
+        audioModeSelect.value = localStorage.getItem(AUDIO_MODE_KEY) ?? "standard";
+      }
+    }
+    if (msg.t === "wcst" && "active" in msg) {
+      if (msg.error) {
+        webcam?.stop();
+        updateWebcamButton(false);
+        ui?.showNotification(`Camera passthrough failed: ${msg.error}`, "error");
+      } else if (msg.active && msg.device) {
+        ui?.showNotification(`Camera available in session as ${msg.device}`, "success");
+      }
+    }
+    if (msg.t === "s2" && "w" in msg) {
+      // 0x0 while the window is open: the agent couldn't add the monitor,
+      // or its stream stopped
+      if (msg.w === 0 && secondScreen?.isOpen()) {
+        secondScreen.close();
+        ui?.showNotification("The second screen is unavailable", "warning");
+      }
+    }
+    if (msg.t === "dl") {
+      ui?.showNotification("The remote display stopped, restarting it…", "warning");
+    }
+    if (msg.t === "dr") {
+      ui?.showNotification("The remote display was restarted; open applications were closed", "info", 8000);
+    }
+    if (msg.t === "idle" && msg.action === "lock") {
+      ui?.showNotification("Session locked after inactivity", "info");
+    }
+    if (msg.t === "assist" && "control" in msg) {
+      if (msg.control) {
+        setStatus("connected", "Connected");
+        ui?.showNotification("You can use the keyboard and mouse again", "info");
+      } else {
+        setStatus("connected", "View only");
+        ui?.showNotification("The person at the computer paused your keyboard and mouse", "warning");
+      }
+    }
+    if (msg.t === "ou" && "url" in msg) {
+      openSessionUrl(msg.url);
+    }
+    if (msg.t === "chl" && "entries" in msg) {
+      sessionClipboardHistory = msg.entries ?? [];
+      renderClipboardHistory();
+    }
+    if (msg.t === "asl" && "sinks" in msg) {
+      populateAudioSources(msg.sinks ?? [], msg.apps ?? []);
+      renderMixer(msg.apps ?? []);
+    }
+    if (msg.t === "ds") {
+      setResizeBlur(false);
+      if (msg.rotation) {
+        rotationSelect.value = msg.rotation;
+      }
+    }
+    if (msg.t === "pl" && "locked" in msg) {
+      inputHandler?.handleRemotePointerLock(msg.locked);
     }
     if (msg.t === "fds" || msg.t === "fdc" || msg.t === "fdd" || msg.t === "fde") {
       fileDownloader?.handleMessage(msg as DownloadMessage);
@@ -928,6 +1716,7 @@ async function startConnection(sessionId: string, token: string): Promise<void>
     clipboardBridge?.disable();
     clipboardBridge = null;
     stopHeartbeat();
+    stopAudioReports();
     stopIdleCheck();
   });
 
@@ -952,6 +1741,58 @@ async function startConnection(sessionId: string, token: string): Promise<void>
     handleDisconnect();
   });
 
+  connection.onNotice((message, from) => {
+    ui?.showNotification(`Message from ${from}: ${message}`, "warning", ADMIN_NOTICE_MS);
+  });
+
+  connection.onTermination((endsInSecs, from) => {
+    if (endsInSecs === null) {
+      ui?.clearCountdown();
+      ui?.showNotification(`${from} called off the scheduled end of this session.`, "info");
+    } else {
+      ui?.showCountdown(endsInSecs, from);
+    }
+  });
+
+  connection.onAssistEnded((reason) => {
+    const message = reason === "assist_declined"
+      ? "The person at the computer declined or did not answer."
+      : "The person at the computer ended the session.";
+    setStatus("error", "Assistance ended");
+    ui?.showNotification(message, "info");
+    clearSession();
+    handleDisconnect();
+  });
+
+  connection.onProtocolUnsupported(() => {
+    // This page predates the server; a reload picks up the new client and
+    // reconnects to the same session
+    setStatus("error", "Beam was updated");
+    ui?.showNotification("Beam was updated on the server. Reloading...", "info");
+    setTimeout(() => location.reload(), 2000);
+  });
+
+  connection.onIdleTimeout((destroyed) => {
+    if (destroyed) {
+      setStatus("error", "Session ended after inactivity");
+      ui?.showNotification("Your remote desktop session was ended after a period of inactivity.", "info");
+      clearSession();
+      handleDisconnect();
+      return;
+    }
+    setStatus("error", "Disconnected for inactivity");
+    renderer?.destroy();
+    renderer = null;
+    inputHandler?.disable();
+    inputHandler = null;
+    clipboardBridge?.disable();
+    clipboardBridge = null;
+    stopHeartbeat();
+    stopAudioReports();
+    connection = null;
+    showReconnectOverlay("idle");
+  });
+
   connection.onReplaced(() => {
     setStatus("error", "Connected from another tab");
     renderer?.destroy();
@@ -961,6 +1802,7 @@ async function startConnection(sessionId: string, token: string): Promise<void>
     clipboardBridge?.disable();
     clipboardBridge = null;
     stopHeartbeat();
+    stopAudioReports();
     connection = null;
     showReconnectOverlay("replaced");
   });
@@ -1041,9 +1883,19 @@ chpCloseBtn.addEventListener("click", () => {
 });
 chpClearBtn.addEventListener("click", () => {
   clipboardBridge?.clearHistory();
+  // The agent answers with its emptied history
+  if (clipboardAllowsToLocal()) connection?.sendInput({ t: "chc" });
   renderClipboardHistory();
 });
 
+// Volume mixer panel buttons
+btnMixer.addEventListener("click", () => {
+  toggleMixerPanel();
+});
+mixerCloseBtn.addEventListener("click", () => {
+  hideMixerPanel();
+});
+
 // Admin panel close button + click-outside-to-close
 adminPanelClose.addEventListener("click", () => {
   hideAdminPanel();
@@ -1064,11 +1916,43 @@ btnForwardKeys.addEventListener("click", () => {
   toggleForwardKeys();
 });
 
+// Cursor drawn into the video, for clients that can't show it themselves
+btnCursorInVideo.addEventListener("click", () => {
+  toggleCursorInVideo();
+});
+
+// System key combos the browser won't pass on, such as Ctrl+Alt+Del
+keyComboSelect.addEventListener("change", () => {
+  if (keyComboSelect.value) inputHandler?.sendKeyCombo(keyComboSelect.value);
+  keyComboSelect.value = "";
+});
+
 // Mute/unmute button
 btnMute.addEventListener("click", () => {
   toggleMute();
 });
 
+// View-only share link button
+btnShare.addEventListener("click", () => {
+  void toggleShare();
+});
+
+// Webcam passthrough button
+btnWebcam.addEventListener("click", () => {
+  void toggleWebcam();
+});
+
+// Video quality preset
+qualitySelect.addEventListener("change", () => {
+  qualitySelect.title = QUALITY_SELECT_TITLE;
+  void setQualityPreset(qualitySelect.value as QualityPreset);
+});
+
+// Second screen button
+btnSecondScreen.addEventListener("click", () => {
+  toggleSecondScreen();
+});
+
 // Theme toggle button
 btnTheme.addEventListener("click", () => {
   toggleTheme();
@@ -1192,6 +2076,13 @@ btnDownload.addEventListener("click", () => {
   }
 });
 
+btnOpenUrl.addEventListener("click", () => {
+  const input = window.prompt("Open link in the remote desktop's browser:")?.trim();
+  if (!input || !connection) return;
+  const url = /^https?:\/\//i.test(input) ? input : `https://${input}`;
+  connection.sendInput({ t: "ou", url });
+});
+
 // --- Mobile FAB and virtual keyboard ---
 
 const isTouchDevice = "ontouchstart" in window || navigator.maxTouchPoints > 0;
@@ -1310,8 +2201,20 @@ fetch("/api/health").then(r => r.json()).then((data: { version?: string }) => {
   if (el && data.version) el.textContent = `v${data.version}`;
 }).catch(() => { /* silently ignore */ });
 
+// A #share=<token> link opens the view-only viewer instead of the login
+const shareToken = new URLSearchParams(location.hash.slice(1)).get("share");
+if (shareToken) {
+  void startViewer(shareToken);
+}
+
+// A kiosk's #guest=<token> link starts a guest session instead of the login
+const guestToken = shareToken ? null : new URLSearchParams(location.hash.slice(1)).get("guest");
+
 // Attempt to resume previous session on page load
-const savedSession = loadSession();
+const savedSession = shareToken ? null : loadSession();
+if (guestToken && !savedSession) {
+  void startGuest(guestToken);
+}
 if (savedSession) {
   (async () => {
     try {
@@ -1335,13 +2238,26 @@ if (savedSession) {
       if (savedSession.idle_timeout !== undefined) {
         effectiveIdleTimeoutSecs = savedSession.idle_timeout;
       }
+      clipboardPolicy = savedSession.clipboard ?? "bidirectional";
+      primarySelection = savedSession.primary_selection ?? false;
+      shareLinks = savedSession.share_links ?? false;
+      assistSession = savedSession.assist ?? false;
+      qualitySelect.value = savedSession.quality ?? "balanced";
+      keyComboSelect.style.display = savedSession.system_combos ? "" : "none";
+      btnShare.style.display = shareLinks ? "" : "none";
+      setSharing(false);
+      sessionClipboardHistory = [];
       tokenManager.scheduleTokenRefresh();
       showLoading("Resuming session...");
       startConnection(savedSession.session_id, savedSession.token);
     } catch (err) {
       console.warn("Could not resume previous session:", err);
       clearSession();
-      showLogin();
+      if (guestToken) {
+        void startGuest(guestToken);
+      } else {
+        showLogin();
+      }
     }
   })();
 }
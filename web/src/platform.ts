@@ -13,6 +13,12 @@
 export const isMac: boolean =
   typeof navigator !== "undefined" && /Mac|iPhone|iPad/.test(navigator.platform);
 
+/** X11/Wayland desktops have a PRIMARY selection (select, then middle-click) */
+export const hasPrimarySelection: boolean =
+  typeof navigator !== "undefined" &&
+  /Linux|BSD/.test(navigator.platform) &&
+  !/Android/.test(navigator.userAgent);
+
 /** Browser shortcuts that should NOT be intercepted (let the browser handle them) */
 const BROWSER_SHORTCUTS = new Set([
   "l", "n", "q", "t", "w", "shift+t",
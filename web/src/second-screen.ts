@@ -0,0 +1,161 @@
+/**
+ * Second screen: a popup window showing the agent's second virtual monitor,
+ * for clients with two monitors of their own. The agent attaches a monitor
+ * the size of the popup right of the first (`sa`) and streams it as frames
+ * flagged FLAG_SECOND_SCREEN. Input in the popup goes to the same desktop,
+ * with mouse moves as `m2`, relative to the second monitor. Closing the
+ * popup detaches the monitor (`sd`).
+ */
+
+import type { InputEvent } from "./connection";
+import { keyCodeToEvdev } from "./keymap";
+import { WebCodecsRenderer } from "./webcodecs-renderer";
+
+/** Header flag of the second monitor's video frames */
+export const FLAG_SECOND_SCREEN = 0x10;
+
+/** The popup's size is sent once it has stopped changing for this long */
+const RESIZE_SETTLE_MS = 300;
+
+export class SecondScreen {
+  private popup: Window | null = null;
+  private renderer: WebCodecsRenderer | null = null;
+  private resizeTimer: ReturnType<typeof setTimeout> | null = null;
+  /** Latest pointer move, sent with the popup's next animation frame */
+  private pendingMove: InputEvent | null = null;
+  private closeCallback: (() => void) | null = null;
+
+  constructor(private sendInput: (event: InputEvent) => void) {}
+
+  /** Register callback for the popup closing, by the user or `close()` */
+  onClose(callback: () => void): void {
+    this.closeCallback = callback;
+  }
+
+  isOpen(): boolean {
+    return this.popup !== null;
+  }
+
+  /** Open the popup and attach the monitor. Call from a click handler, or
+   *  the browser blocks the popup; returns false if it did. */
+  open(): boolean {
+    if (this.popup) {
+      this.popup.focus();
+      return true;
+    }
+    const popup = window.open("", "beam-second-screen", "popup,width=1280,height=800");
+    if (!popup) return false;
+    this.popup = popup;
+
+    const doc = popup.document;
+    doc.title = `${document.title} (second screen)`;
+    doc.body.style.cssText =
+      "margin:0;height:100vh;background:#000;overflow:hidden;display:flex;align-items:center;justify-content:center";
+    const canvas = doc.createElement("canvas");
+    // The agent draws the cursor into this monitor's picture
+    canvas.style.cssText = "max-width:100%;max-height:100%;cursor:none";
+    doc.body.append(canvas);
+    this.renderer = new WebCodecsRenderer(canvas, doc.body);
+    this.listen(popup, canvas);
+    this.sendSize();
+    return true;
+  }
+
+  /** Attach the monitor again after a reconnect, to a possibly new agent. */
+  resume(): void {
+    this.sendSize();
+  }
+
+  /** Close the popup and detach the monitor. */
+  close(): void {
+    const popup = this.popup;
+    this.closed();
+    popup?.close();
+  }
+
+  /** Feed a video frame flagged FLAG_SECOND_SCREEN */
+  feedVideoFrame(flags: number, width: number, height: number, timestampUs: bigint, payload: Uint8Array): void {
+    this.renderer?.feedVideoFrame(flags, width, height, timestampUs, payload);
+  }
+
+  private sendSize(): void {
+    if (!this.popup) return;
+    const dpr = this.popup.devicePixelRatio || 1;
+    this.sendInput({
+      t: "sa",
+      w: Math.round(this.popup.innerWidth * dpr),
+      h: Math.round(this.popup.innerHeight * dpr),
+    });
+  }
+
+  private closed(): void {
+    if (!this.popup) return;
+    this.popup = null;
+    if (this.resizeTimer) clearTimeout(this.resizeTimer);
+    this.resizeTimer = null;
+    this.pendingMove = null;
+    this.renderer?.destroy();
+    this.renderer = null;
+    this.sendInput({ t: "sd" });
+    this.closeCallback?.();
+  }
+
+  private listen(popup: Window, canvas: HTMLCanvasElement): void {
+    popup.addEventListener("pagehide", () => this.closed());
+    popup.addEventListener("resize", () => {
+      if (this.resizeTimer) clearTimeout(this.resizeTimer);
+      this.resizeTimer = setTimeout(() => this.sendSize(), RESIZE_SETTLE_MS);
+    });
+
+    const position = (e: MouseEvent): InputEvent | null => {
+      const rect = canvas.getBoundingClientRect();
+      if (rect.width === 0 || rect.height === 0) return null;
+      return {
+        t: "m2",
+        x: Math.min(Math.max((e.clientX - rect.left) / rect.width, 0), 1),
+        y: Math.min(Math.max((e.clientY - rect.top) / rect.height, 0), 1),
+        at: e.timeStamp,
+      };
+    };
+    canvas.addEventListener("mousemove", (e) => {
+      const move = position(e);
+      if (!move) return;
+      if (!this.pendingMove) {
+        popup.requestAnimationFrame(() => {
+          if (this.pendingMove) this.sendInput(this.pendingMove);
+          this.pendingMove = null;
+        });
+      }
+      this.pendingMove = move;
+    });
+    const button = (e: MouseEvent, down: boolean) => {
+      e.preventDefault();
+      // Clicks land where the pointer is now, not where the last frame left it
+      const move = position(e);
+      if (move) this.sendInput(move);
+      this.pendingMove = null;
+      this.sendInput({ t: "b", b: e.button, d: down, at: e.timeStamp });
+    };
+    canvas.addEventListener("mousedown", (e) => button(e, true));
+    canvas.addEventListener("mouseup", (e) => button(e, false));
+    canvas.addEventListener("contextmenu", (e) => e.preventDefault());
+    canvas.addEventListener(
+      "wheel",
+      (e) => {
+        e.preventDefault();
+        const scale = e.deltaMode === 1 ? 30 : e.deltaMode === 2 ? 300 : 1;
+        this.sendInput({ t: "s", dx: e.deltaX * scale, dy: e.deltaY * scale, at: e.timeStamp });
+      },
+      { passive: false },
+    );
+
+    const key = (e: KeyboardEvent, down: boolean) => {
+      const code = keyCodeToEvdev(e.code);
+      if (code === undefined) return;
+      e.preventDefault();
+      this.sendInput({ t: "k", c: code, d: down, at: e.timeStamp });
+    };
+    popup.addEventListener("keydown", (e) => key(e, true));
+    popup.addEventListener("keyup", (e) => key(e, false));
+  }
+}
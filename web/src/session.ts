@@ -3,7 +3,8 @@
  * token refresh, login/logout API calls, and release beacon.
  */
 
-import type { BeamConnection } from "./connection";
+import type { ClipboardPolicy } from "./clipboard";
+import type { BeamConnection, QualityPreset } from "./connection";
 
 /** Shape of the login API response */
 export interface LoginResponse {
@@ -11,6 +12,15 @@ export interface LoginResponse {
   token: string;
   release_token?: string;
   idle_timeout?: number;
+  clipboard?: ClipboardPolicy;
+  primary_selection?: boolean;
+  share_links?: boolean;
+  /** True for a remote assistance session on the server's own display */
+  assist?: boolean;
+  /** The session's quality preset, the user's last choice for a new one */
+  quality?: QualityPreset;
+  /** Whether the session lets the browser send system key combos */
+  system_combos?: boolean;
 }
 
 /** Stored session with expiry timestamp */
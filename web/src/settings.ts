@@ -6,16 +6,22 @@ import { ICON_SUN, ICON_MOON } from "./icons";
 import type { ConnectionState } from "./ui-state";
 import {
   btnTheme, perfOverlay, remoteVideo,
-  lsRtt, lsFps, lsDecode, lsLoss, lsTooltip,
+  lsQuality, lsRtt, lsFps, lsDecode, lsLoss, lsTooltip,
   bandwidthIndicator, idleWarning,
 } from "./ui-state";
 
 // --- Storage keys ---
 export const AUDIO_MUTED_KEY = "beam_audio_muted";
 export const SCROLL_SPEED_KEY = "beam_scroll_speed";
+export const AUDIO_MODE_KEY = "beam_audio_mode";
+export const AUDIO_TUNING_KEY = "beam_audio_tuning";
 export const THEME_KEY = "beam_theme";
 export const FORWARD_KEYS_KEY = "beam_forward_keys";
+/** "true" has the agent draw the cursor into the video */
+export const CURSOR_IN_VIDEO_KEY = "beam_cursor_in_video";
 export const SESSION_TIMEOUT_KEY = "beam_session_timeout";
+/** "cbor" opts in to binary signaling frames; anything else means JSON */
+export const WIRE_FORMAT_KEY = "beam_wire_format";
 // --- Idle timeout ---
 export const IDLE_WARNING_BEFORE_SECS = 120; // Show warning 2 min before expiry
 export const IDLE_CHECK_INTERVAL_MS = 30_000; // Check every 30s
@@ -147,6 +153,31 @@ export function updateLatencyStatsFps(fps: number, decodeMs?: number): void {
   }
 }
 
+/** Show agent-reported glass-to-glass latency percentiles in the status bar */
+export function updateLatencyStatsGlass(p50Ms: number, p95Ms: number): void {
+  lsRtt.style.display = "";
+  lsRtt.textContent = `Lat: ${Math.round(p50Ms)}ms`;
+  lsRtt.title = `Glass-to-glass p50 ${p50Ms.toFixed(1)}ms, p95 ${p95Ms.toFixed(1)}ms`;
+  lsRtt.className = "ls-stat " + (p50Ms < 30 ? "ls-good" : p50Ms <= 60 ? "ls-warn" : "ls-bad");
+}
+
+/** Show the agent-computed connection quality badge in the status bar */
+export function updateConnectionQuality(
+  level: "good" | "fair" | "poor",
+  rttMs: number | null,
+  lossPct: number,
+  kbps: number,
+  targetKbps: number,
+): void {
+  lsQuality.style.display = "";
+  lsQuality.textContent = level === "good" ? "Good" : level === "fair" ? "Fair" : "Poor";
+  const rtt = rttMs === null ? "--" : `${Math.round(rttMs)}ms`;
+  lsQuality.title =
+    `Connection ${level}: RTT ${rtt}, dropped ${lossPct.toFixed(1)}%, ` +
+    `${(kbps / 1000).toFixed(1)} of ${(targetKbps / 1000).toFixed(1)} Mbps`;
+  lsQuality.className = "ls-stat " + (level === "good" ? "ls-good" : level === "fair" ? "ls-warn" : "ls-bad");
+}
+
 // --- Idle timeout warning ---
 
 export function showIdleWarning(idleWarningVisible: boolean): boolean {
@@ -164,6 +195,8 @@ export function hideIdleWarning(idleWarningVisible: boolean): boolean {
 
 /** Reset the latency stats display to defaults */
 export function resetLatencyStats(): void {
+  lsQuality.textContent = "";
+  lsQuality.style.display = "none";
   lsRtt.textContent = "";
   lsRtt.style.display = "none";
   lsFps.textContent = "FPS: --";
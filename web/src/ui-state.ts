@@ -29,6 +29,7 @@ export const statusText = document.getElementById("status-text") as HTMLSpanElem
 export const statusVersion = document.getElementById("status-version") as HTMLSpanElement;
 
 export const bandwidthIndicator = document.getElementById("bandwidth-indicator") as HTMLSpanElement;
+export const lsQuality = document.getElementById("ls-quality") as HTMLSpanElement;
 export const lsRtt = document.getElementById("ls-rtt") as HTMLSpanElement;
 export const lsFps = document.getElementById("ls-fps") as HTMLSpanElement;
 export const lsDecode = document.getElementById("ls-decode") as HTMLSpanElement;
@@ -37,7 +38,16 @@ export const lsTooltip = document.getElementById("ls-tooltip") as HTMLDivElement
 export const faviconLink = document.querySelector("link[rel='icon']") as HTMLLinkElement;
 
 export const btnMute = document.getElementById("btn-mute") as HTMLButtonElement;
+export const btnWebcam = document.getElementById("btn-webcam") as HTMLButtonElement;
+export const btnSecondScreen = document.getElementById("btn-second-screen") as HTMLButtonElement;
+export const audioSourceSelect = document.getElementById("audio-source-select") as HTMLSelectElement;
+export const audioModeSelect = document.getElementById("audio-mode-select") as HTMLSelectElement;
+export const audioTuningSelect = document.getElementById("audio-tuning-select") as HTMLSelectElement;
+export const qualitySelect = document.getElementById("quality-select") as HTMLSelectElement;
+export const keyComboSelect = document.getElementById("key-combo-select") as HTMLSelectElement;
+export const rotationSelect = document.getElementById("rotation-select") as HTMLSelectElement;
 export const btnForwardKeys = document.getElementById("btn-forward-keys") as HTMLButtonElement;
+export const btnCursorInVideo = document.getElementById("btn-cursor-in-video") as HTMLButtonElement;
 export const btnTheme = document.getElementById("btn-theme") as HTMLButtonElement;
 export const perfOverlay = document.getElementById("perf-overlay") as HTMLDivElement;
 export const helpOverlay = document.getElementById("help-overlay") as HTMLDivElement;
@@ -56,6 +66,12 @@ export const chpList = document.getElementById("chp-list") as HTMLDivElement;
 export const chpClearBtn = document.getElementById("chp-clear") as HTMLButtonElement;
 export const chpCloseBtn = document.getElementById("chp-close") as HTMLButtonElement;
 
+// Volume mixer panel
+export const btnMixer = document.getElementById("btn-mixer") as HTMLButtonElement;
+export const mixerPanel = document.getElementById("mixer-panel") as HTMLDivElement;
+export const mixerList = document.getElementById("mixer-list") as HTMLDivElement;
+export const mixerCloseBtn = document.getElementById("mixer-close") as HTMLButtonElement;
+
 // Admin sessions panel
 export const adminPanelOverlay = document.getElementById("admin-panel-overlay") as HTMLDivElement;
 export const adminSessionsTbody = document.getElementById("admin-sessions-tbody") as HTMLTableSectionElement;
@@ -67,6 +83,8 @@ export const fileDropOverlay = document.getElementById("file-drop-overlay") as H
 export const btnUpload = document.getElementById("btn-upload") as HTMLButtonElement;
 export const fileUploadInput = document.getElementById("file-upload-input") as HTMLInputElement;
 export const btnDownload = document.getElementById("btn-download") as HTMLButtonElement;
+export const btnOpenUrl = document.getElementById("btn-open-url") as HTMLButtonElement;
+export const btnShare = document.getElementById("btn-share") as HTMLButtonElement;
 
 // Mobile FAB
 export const mobileFab = document.getElementById("mobile-fab") as HTMLDivElement;
@@ -250,13 +268,19 @@ const ICON_TAB = `<svg width="32" height="32" viewBox="0 0 24 24" fill="none" st
 // so the reconnect button click can skip the countdown.
 export let isAutoReconnectCountdown = false;
 
-export function showReconnectOverlay(mode: "disconnected" | "replaced" | "auto-reconnecting" = "disconnected", countdownSeconds?: number): void {
+export function showReconnectOverlay(mode: "disconnected" | "replaced" | "idle" | "auto-reconnecting" = "disconnected", countdownSeconds?: number): void {
   if (mode === "replaced") {
     reconnectIcon.innerHTML = ICON_TAB;
     reconnectTitle.textContent = "Session in use";
-    reconnectDesc.textContent = "This session was opened in another tab.";
+    reconnectDesc.textContent = "This session was opened in another tab or browser.";
     reconnectBtn.textContent = "Take back";
     isAutoReconnectCountdown = false;
+  } else if (mode === "idle") {
+    reconnectIcon.innerHTML = ICON_WIFI_OFF;
+    reconnectTitle.textContent = "Disconnected for inactivity";
+    reconnectDesc.textContent = "Your session is still running on the server.";
+    reconnectBtn.textContent = "Reconnect";
+    isAutoReconnectCountdown = false;
   } else if (mode === "auto-reconnecting" && countdownSeconds !== undefined && countdownSeconds > 0) {
     reconnectIcon.innerHTML = ICON_WIFI_OFF;
     reconnectTitle.textContent = "Network change detected";
@@ -20,6 +20,7 @@ export class BeamUI {
   private onEndSession: (() => void) | null = null;
 
   private fsHideTimer: ReturnType<typeof setTimeout> | null = null;
+  private countdown: { el: HTMLElement; timer: ReturnType<typeof setInterval> } | null = null;
   private isFullscreen = false;
 
   constructor() {
@@ -75,6 +76,37 @@ export class BeamUI {
     }
   }
 
+  /** Keep a toast up counting down to the end an admin scheduled for the
+   *  session, replacing any earlier countdown. */
+  showCountdown(endsInSecs: number, from: string): void {
+    this.clearCountdown();
+    const endsAt = Date.now() + endsInSecs * 1000;
+
+    const el = document.createElement("div");
+    el.className = "toast toast-warning";
+    const msgSpan = document.createElement("span");
+    msgSpan.className = "toast-message";
+    el.appendChild(msgSpan);
+    this.toastContainer.appendChild(el);
+    void el.offsetWidth;
+    el.classList.add("visible");
+
+    const update = (): void => {
+      const left = Math.max(0, Math.ceil((endsAt - Date.now()) / 1000));
+      const clock = `${Math.floor(left / 60)}:${String(left % 60).padStart(2, "0")}`;
+      msgSpan.textContent = `${from} scheduled this session to end in ${clock}. Save your work.`;
+    };
+    update();
+    this.countdown = { el, timer: setInterval(update, 1000) };
+  }
+
+  clearCountdown(): void {
+    if (!this.countdown) return;
+    clearInterval(this.countdown.timer);
+    this.countdown.el.remove();
+    this.countdown = null;
+  }
+
   private setupButtons(): void {
     document.getElementById("btn-fullscreen")?.addEventListener("click", () => {
       this.onFullscreen?.();
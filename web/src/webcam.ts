@@ -0,0 +1,155 @@
+/**
+ * Webcam passthrough: captures the local camera, encodes it with WebCodecs
+ * and streams it to the agent as binary frames. The agent decodes them into
+ * a v4l2loopback device, so apps inside the remote desktop see a camera.
+ */
+
+import type { InputEvent } from "./connection";
+import { FRAME_HEADER_SIZE, FRAME_MAGIC } from "./connection";
+
+const FLAG_KEYFRAME = 0x01;
+const FLAG_WEBCAM = 0x08;
+
+const WIDTH = 640;
+const HEIGHT = 480;
+const FPS = 30;
+const BITRATE = 1_000_000;
+/** Keyframe every 2s: the server and agent drop frames under load, and the
+ *  agent's decoder needs a keyframe to recover. */
+const KEYFRAME_INTERVAL = FPS * 2;
+
+type Codec = { name: "h264" | "vp8"; config: VideoEncoderConfig };
+
+/** Prefer H.264 (hardware encode on most machines), fall back to VP8. */
+async function pickCodec(): Promise<Codec | null> {
+  const base = { width: WIDTH, height: HEIGHT, bitrate: BITRATE, framerate: FPS, latencyMode: "realtime" as const };
+  const candidates: Codec[] = [
+    { name: "h264", config: { ...base, codec: "avc1.42e01f", avc: { format: "annexb" } } },
+    { name: "vp8", config: { ...base, codec: "vp8" } },
+  ];
+  for (const candidate of candidates) {
+    const support = await VideoEncoder.isConfigSupported(candidate.config);
+    if (support.supported) return candidate;
+  }
+  return null;
+}
+
+function frameHeader(flags: number, timestampUs: number, payloadLength: number): ArrayBuffer {
+  const buf = new ArrayBuffer(FRAME_HEADER_SIZE + payloadLength);
+  const view = new DataView(buf);
+  view.setUint32(0, FRAME_MAGIC, true);
+  view.setUint8(4, 1); // version
+  view.setUint8(5, flags);
+  view.setUint16(6, WIDTH, true);
+  view.setUint16(8, HEIGHT, true);
+  view.setBigUint64(12, BigInt(Math.max(0, Math.round(timestampUs))), true);
+  view.setUint32(20, payloadLength, true);
+  return buf;
+}
+
+export class WebcamStreamer {
+  private sendInput: (event: InputEvent) => void;
+  private sendBinary: (data: ArrayBuffer) => void;
+  private stream: MediaStream | null = null;
+  private video: HTMLVideoElement | null = null;
+  private encoder: VideoEncoder | null = null;
+  private codec: Codec | null = null;
+  private timer: ReturnType<typeof setInterval> | null = null;
+  private frameCount = 0;
+  private startTime = 0;
+
+  constructor(sendInput: (event: InputEvent) => void, sendBinary: (data: ArrayBuffer) => void) {
+    this.sendInput = sendInput;
+    this.sendBinary = sendBinary;
+  }
+
+  get active(): boolean {
+    return this.stream !== null;
+  }
+
+  /** Open the camera and start streaming. Throws if the camera or a
+   *  suitable encoder is unavailable. */
+  async start(): Promise<void> {
+    if (this.stream) return;
+    if (typeof VideoEncoder === "undefined") {
+      throw new Error("This browser cannot encode video (WebCodecs missing)");
+    }
+    const codec = await pickCodec();
+    if (!codec) throw new Error("No supported camera encoder (H.264 or VP8)");
+
+    this.stream = await navigator.mediaDevices.getUserMedia({
+      video: { width: WIDTH, height: HEIGHT, frameRate: FPS },
+      audio: false,
+    });
+    this.video = document.createElement("video");
+    this.video.muted = true;
+    this.video.playsInline = true;
+    this.video.srcObject = this.stream;
+    await this.video.play();
+
+    this.codec = codec;
+    this.encoder = new VideoEncoder({
+      output: (chunk) => this.sendChunk(chunk),
+      error: (err) => console.error("Webcam encoder error:", err),
+    });
+    this.encoder.configure(codec.config);
+    this.startTime = performance.now();
+    this.announce();
+    this.timer = setInterval(() => this.encodeFrame(), 1000 / FPS);
+  }
+
+  /** Re-announce after a reconnect (possibly to a respawned agent) and
+   *  force a keyframe so its decoder can start. */
+  resume(): void {
+    if (!this.stream) return;
+    this.announce();
+    this.frameCount = 0;
+  }
+
+  stop(): void {
+    if (this.timer) {
+      clearInterval(this.timer);
+      this.timer = null;
+    }
+    if (this.encoder && this.encoder.state !== "closed") {
+      this.encoder.close();
+    }
+    this.encoder = null;
+    this.stream?.getTracks().forEach((track) => track.stop());
+    if (this.stream) {
+      this.sendInput({ t: "wce" });
+    }
+    this.stream = null;
+    this.video = null;
+    this.codec = null;
+  }
+
+  private announce(): void {
+    if (!this.codec) return;
+    this.sendInput({ t: "wcs", codec: this.codec.name, w: WIDTH, h: HEIGHT });
+  }
+
+  private encodeFrame(): void {
+    if (!this.encoder || !this.video || this.encoder.state !== "configured") return;
+    // Encoder falling behind (slow machine): skip rather than queue
+    if (this.encoder.encodeQueueSize > 2) return;
+    if (this.video.readyState < HTMLMediaElement.HAVE_CURRENT_DATA) return;
+
+    const timestamp = Math.round((performance.now() - this.startTime) * 1000);
+    const frame = new VideoFrame(this.video, { timestamp });
+    const keyFrame = this.frameCount % KEYFRAME_INTERVAL === 0;
+    this.frameCount++;
+    try {
+      this.encoder.encode(frame, { keyFrame });
+    } finally {
+      frame.close();
+    }
+  }
+
+  private sendChunk(chunk: EncodedVideoChunk): void {
+    const flags = FLAG_WEBCAM | (chunk.type === "key" ? FLAG_KEYFRAME : 0);
+    const buf = frameHeader(flags, chunk.timestamp, chunk.byteLength);
+    chunk.copyTo(new Uint8Array(buf, FRAME_HEADER_SIZE));
+    this.sendBinary(buf);
+  }
+}
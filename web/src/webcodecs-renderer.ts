@@ -15,6 +15,7 @@ export class WebCodecsRenderer {
   private containerElement: HTMLElement;
   private currentWidth = 0;
   private currentHeight = 0;
+  private currentChroma444 = false;
   private framesDecoded = 0;
   private prevFrameCount = 0;
   private currentFps = 0;
@@ -30,6 +31,19 @@ export class WebCodecsRenderer {
   private needsKeyframe = true;
   private videoFrameCount = 0;
   private audioFrameCount = 0;
+  /** Feed time (performance.now) of frames sampled for latency acks, keyed by timestamp */
+  private ackFeedTimes = new Map<number, number>();
+  private frameDisplayedCallback: ((timestampUs: number, displayDelayMs: number) => void) | null = null;
+  private static readonly ACK_SAMPLE_INTERVAL = 30;
+  /** Media clock time minus audio timestamp, from the agent's latest sender report (µs) */
+  private audioClockOffsetUs: number | null = null;
+  /** How long after its capture the latest video frame was drawn and audio buffer played (ms).
+   *  Both include the same agent-to-browser clock offset, which cancels out in their difference. */
+  private videoPlayoutMs: number | null = null;
+  private audioPlayoutMs: number | null = null;
+  /** Audio played, and silence played in gaps the stream left, since the last report (ms) */
+  private playedMs = 0;
+  private concealedMs = 0;
 
   constructor(canvas: HTMLCanvasElement, containerElement: HTMLElement) {
     this.canvas = canvas;
@@ -53,6 +67,11 @@ export class WebCodecsRenderer {
     }
   }
 
+  /** Register callback for sampled frames once drawn (for latency telemetry) */
+  onFrameDisplayed(callback: (timestampUs: number, displayDelayMs: number) => void): void {
+    this.frameDisplayedCallback = callback;
+  }
+
   /** Register callback for the first decoded video frame */
   onFirstFrame(callback: () => void): void {
     this.firstFrameCallback = callback;
@@ -90,6 +109,27 @@ export class WebCodecsRenderer {
     this.muteChangeCallback?.(muted);
   }
 
+  /** Apply an agent sender report, which maps audio timestamps onto the
+   *  media clock video timestamps are in */
+  handleSenderReport(clockUs: number, audioUs: number): void {
+    this.audioClockOffsetUs = clockUs - audioUs;
+  }
+
+  /** How far audio plays behind video (ms, negative when ahead), or null
+   *  until both have played since the first sender report */
+  getAvSyncMs(): number | null {
+    if (this.videoPlayoutMs === null || this.audioPlayoutMs === null) return null;
+    return this.audioPlayoutMs - this.videoPlayoutMs;
+  }
+
+  /** Audio played and gaps concealed since the last call (ms) */
+  takeAudioPlayout(): { playedMs: number; concealedMs: number } {
+    const playout = { playedMs: Math.round(this.playedMs), concealedMs: Math.round(this.concealedMs) };
+    this.playedMs = 0;
+    this.concealedMs = 0;
+    return playout;
+  }
+
   /** Returns true if we have received at least one frame */
   hasStream(): boolean {
     return this.firstFrameFired;
@@ -111,8 +151,8 @@ export class WebCodecsRenderer {
   }
 
   /** Configure or reconfigure the video decoder for the given resolution */
-  private configureDecoder(width: number, height: number): void {
-    console.log(`[Beam] configureDecoder: ${width}x${height}`);
+  private configureDecoder(width: number, height: number, chroma444: boolean): void {
+    console.log(`[Beam] configureDecoder: ${width}x${height}${chroma444 ? " 4:4:4" : ""}`);
     if (this.decoder) {
       this.decoder.close();
       this.decoder = null;
@@ -120,12 +160,19 @@ export class WebCodecsRenderer {
 
     this.currentWidth = width;
     this.currentHeight = height;
+    this.currentChroma444 = chroma444;
     this.canvas.width = width;
     this.canvas.height = height;
 
     this.decoder = new VideoDecoder({
       output: (frame: VideoFrame) => {
         this.ctx.drawImage(frame, 0, 0);
+        this.videoPlayoutMs = performance.now() - frame.timestamp / 1000;
+        const fedAt = this.ackFeedTimes.get(frame.timestamp);
+        if (fedAt !== undefined) {
+          this.ackFeedTimes.delete(frame.timestamp);
+          this.frameDisplayedCallback?.(frame.timestamp, performance.now() - fedAt);
+        }
         frame.close();
         this.framesDecoded++;
         this.decodeTimeMs = performance.now() - this.lastFeedTimeMs;
@@ -142,7 +189,8 @@ export class WebCodecsRenderer {
     });
 
     this.decoder.configure({
-      codec: "avc1.4d0033", // Main profile, Level 5.1
+      // Main profile, or High 4:4:4 Predictive when the agent streams full chroma (Level 5.1)
+      codec: chroma444 ? "avc1.f40033" : "avc1.4d0033",
       hardwareAcceleration: "prefer-hardware",
       optimizeForLatency: true,
     });
@@ -151,6 +199,13 @@ export class WebCodecsRenderer {
     this.startFpsCounter();
   }
 
+  /** Start the video decoder over, from the next keyframe. A decoder
+   *  that hit an error stays closed and freezes the picture. */
+  resetDecoder(): void {
+    if (this.currentWidth === 0) return;
+    this.configureDecoder(this.currentWidth, this.currentHeight, this.currentChroma444);
+  }
+
   /** Feed a video frame from the binary WebSocket message */
   feedVideoFrame(
     flags: number,
@@ -165,9 +220,10 @@ export class WebCodecsRenderer {
       console.log(`[Beam] feedVideoFrame #${this.videoFrameCount}: ${width}x${height} flags=0x${flags.toString(16)} keyframe=${isKf} payload=${payload.byteLength} decoderState=${this.decoder?.state ?? "null"}`);
     }
 
-    // Reconfigure decoder if resolution changed
-    if (width !== this.currentWidth || height !== this.currentHeight) {
-      this.configureDecoder(width, height);
+    // Reconfigure decoder if resolution or chroma format changed
+    const chroma444 = (flags & 0x04) !== 0;
+    if (width !== this.currentWidth || height !== this.currentHeight || chroma444 !== this.currentChroma444) {
+      this.configureDecoder(width, height, chroma444);
     }
 
     if (!this.decoder || this.decoder.state === "closed") return;
@@ -189,6 +245,11 @@ export class WebCodecsRenderer {
 
     try {
       this.lastFeedTimeMs = performance.now();
+      if (this.videoFrameCount % WebCodecsRenderer.ACK_SAMPLE_INTERVAL === 0) {
+        // Dropped frames never reach output; don't let their entries pile up
+        if (this.ackFeedTimes.size > 16) this.ackFeedTimes.clear();
+        this.ackFeedTimes.set(chunk.timestamp, this.lastFeedTimeMs);
+      }
       this.decoder.decode(chunk);
     } catch (err) {
       console.error("VideoDecoder.decode() error:", err);
@@ -239,10 +300,20 @@ export class WebCodecsRenderer {
             const now = this.audioContext.currentTime;
             // Snap forward if we've fallen behind (network stall, tab resume)
             if (this.nextAudioPlayTime < now) {
+              // Not a gap on the first buffer, or after a decoder reset
+              if (this.nextAudioPlayTime > 0) {
+                this.concealedMs += (now - this.nextAudioPlayTime) * 1000;
+              }
               this.nextAudioPlayTime = now;
             }
             source.start(this.nextAudioPlayTime);
+            if (this.audioClockOffsetUs !== null) {
+              const playsAtMs =
+                performance.now() + (this.nextAudioPlayTime - now + this.audioContext.outputLatency) * 1000;
+              this.audioPlayoutMs = playsAtMs - (audioData.timestamp + this.audioClockOffsetUs) / 1000;
+            }
             this.nextAudioPlayTime += buffer.duration;
+            this.playedMs += buffer.duration * 1000;
           }
           audioData.close();
         },
@@ -316,6 +387,7 @@ export class WebCodecsRenderer {
     this.firstFrameFired = false;
     this.currentWidth = 0;
     this.currentHeight = 0;
+    this.currentChroma444 = false;
   }
 
   private startFpsCounter(): void {